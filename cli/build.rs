@@ -0,0 +1,18 @@
+fn main() {
+    // gRPC stubs are only needed with the `grpc` feature, don't make everyone else pay for protoc
+    #[cfg(feature = "grpc")]
+    compile_protos();
+}
+
+#[cfg(feature = "grpc")]
+fn compile_protos() {
+    // the PLC's service definition, clients and server can't drift apart
+    println!("cargo:rerun-if-changed=../plc/proto/tags.proto");
+
+    // SAFETY: build scripts are single threaded
+    unsafe { std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path().expect("vendored protoc")) };
+    tonic_build::configure()
+        .build_server(false)
+        .compile_protos(&["../plc/proto/tags.proto"], &["../plc/proto"])
+        .expect("compile tags.proto");
+}
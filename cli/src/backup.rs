@@ -0,0 +1,290 @@
+// gipop backup <file> and gipop restore <file>: the PLC's runtime state in one file, to carry over to a replacement
+// controller or put back after a reinstall. What's in it is what the PLC keeps in memory only and a new one would
+// start without, and the EnOcean pairing:
+//
+// retained   every writable tag (setpoints and HMI commands), external ones aside, they come from another server
+// total      every tag in the Totals folder, the counters operators reset
+// running    whether the logic was running or stopped by an operator
+// enocean    the [enocean] senders in gipop.toml, paired by hand one device at a time, what a new gipop.toml lacks
+//
+// Forces aren't backed up, a replacement controller shouldn't come up with outputs held. The rest of the config isn't
+// either: register maps and users are in gipop.toml, keep it in a project bundle (gipop_shm::project) under version
+// control. The PLC has no calibration or recipe store yet, tag scaling is compiled into its tag list.
+//
+// The file is tab separated like the rest of gipop's output, one line each, with \t, \n, \r and \\ escaped in names:
+//
+// gipop-backup	2
+// created	2026-10-16T09:12:44.512Z
+// plc	0.1.0	3f2a9c1
+// project	plant-1	1.4.0
+// tags	9c0e1f3a
+// running	true
+// retained	area 1 lights hmi cmd	3
+// total	enocean telegrams	18211
+// enocean	0181A1B2	F6-02-01	Hall rocker
+//
+// Restoring takes the engineer role and goes by tag name, through the command ring like `gipop set`, each command
+// audited with the login restoring it, the run state last. A backup of another tag list (see
+// gipop_shm::project::fingerprint) is refused unless --force is given. Then, and whenever the running PLC no longer
+// has a tag as a writable one or a total, what doesn't match is skipped with a warning. Senders gipop.toml doesn't
+// have are appended to it as [[enocean.devices]], the OPC UA server takes them on its next start. Version 1 backups
+// (nothing escaped, no pairing) are still read.
+use std::fs;
+use std::io;
+
+use serde::Deserialize;
+
+use gipop_shm::project::fingerprint;
+use gipop_shm::tags::{iso_time, now_ms};
+use gipop_shm::ring::ITEM_RUN_MODE;
+use gipop_shm::{Action, RingItem, Subscriber, TagType, Users};
+
+use crate::local::{send, wait_acked};
+use crate::{bad_value, format_value, parse_value};
+
+const MAGIC: &str = "gipop-backup";
+const FORMAT: u32 = 2;
+const RUNNING_TAG: &str = "running"; // see plc/src/tags.rs
+
+#[derive(Deserialize, Default)]
+struct ConfigFile {
+    #[serde(default)]
+    enocean: EnoceanSection, // other sections belong to the binaries and are ignored here
+}
+
+#[derive(Deserialize, Default)]
+struct EnoceanSection {
+    #[serde(default)]
+    devices: Vec<EnoceanDevice>,
+}
+
+// As the OPC UA server reads it, see opcua/src/config.rs
+#[derive(Deserialize)]
+struct EnoceanDevice {
+    id: String,
+    eep: String,
+    #[serde(default)]
+    name: String,
+}
+
+/// Writes the PLC's runtime state and `config`'s EnOcean pairing to `path`
+pub fn backup(table: &Subscriber, config: &str, path: &str) -> Result<(), String> {
+    let devices = read_config(config)?.1.enocean.devices;
+    let mut text = format!("{}\t{}\ncreated\t{}\n", MAGIC, FORMAT, iso_time(now_ms()));
+    if let Some(info) = table.read_plc_info() {
+        text += &format!("plc\t{}\t{}\n", info.version(), info.commit());
+        if !info.project().is_empty() {
+            text += &format!("project\t{}\t{}\n", escape(&info.project()), escape(&info.project_version()));
+        }
+    }
+    text += &format!("tags\t{}\n", fingerprint(table.tags()));
+    let mut saved = 0;
+    if let Some(idx) = table.index_of(RUNNING_TAG) {
+        text += &format!("running\t{}\n", format_value(table.read_sample(idx).value));
+    }
+    for (idx, tag) in table.tags().iter().enumerate() {
+        let Some(kind) = tag.retained() else { continue };
+        text += &format!("{}\t{}\t{}\n", kind, escape(&tag.name), format_value(table.read_sample(idx).value));
+        saved += 1;
+    }
+    for device in &devices {
+        text += &format!("enocean\t{}\t{}\t{}\n", escape(&device.id), escape(&device.eep), escape(&device.name));
+    }
+    fs::write(path, text).map_err(|e| format!("Failed to write {}: {}", path, e))?;
+    println!("{}\t{} tags, {} EnOcean senders", path, saved, devices.len());
+    Ok(())
+}
+
+/// Puts the state backed up in `path` back into the PLC, `force` restores across tag lists
+pub fn restore(table: &Subscriber, config: &str, path: &str, force: bool) -> Result<(), String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let mut lines = text.lines().map(|line| line.split('\t').collect::<Vec<_>>());
+    let format = match lines.next().as_deref() {
+        Some([MAGIC, format]) if matches!(format.parse(), Ok(1..=FORMAT)) => format.parse::<u32>().unwrap_or(FORMAT),
+        Some([MAGIC, format]) => return Err(format!("{} is a version {} backup, this gipop reads up to {}", path, format, FORMAT)),
+        _ => return Err(format!("{} isn't a gipop backup", path)),
+    };
+    // version 1 didn't escape
+    let field = |text: &str| if format >= 2 { unescape(text) } else { Some(text.to_owned()) };
+    let users = Users::load(config)?;
+    let user = Users::local_user();
+    users.authorize(user.as_deref(), Action::RestoreState)?;
+    let user = user.unwrap_or_default();
+
+    let mut items = Vec::new();
+    let mut devices = Vec::new();
+    for (number, fields) in lines.enumerate() {
+        let at = || format!("{} line {}", path, number + 2);
+        match fields.as_slice() {
+            ["tags", tags] if !tags.eq_ignore_ascii_case(&fingerprint(table.tags())) => {
+                if !force {
+                    return Err(format!("{} is from tag list {}, the PLC runs {}, --force to restore what still matches", path, tags, fingerprint(table.tags())));
+                }
+                eprintln!("gipop: {} is from tag list {}, the PLC runs {}", path, tags, fingerprint(table.tags()));
+            }
+            ["running", text] => match parse_value(TagType::Bool, text) {
+                Some(value) => items.push((RingItem::run_mode(value.as_f64() != 0.0), RUNNING_TAG, *text)),
+                None => return Err(format!("{}: '{}' isn't true or false", at(), text)),
+            },
+            [kind @ ("retained" | "total"), name, text] => {
+                let name = field(name).ok_or_else(|| format!("{}: bad escape in {:?}", at(), name))?;
+                let Some(idx) = table.index_of(&name).filter(|&idx| table.tags()[idx].retained() == Some(*kind)) else {
+                    eprintln!("gipop: Skipping '{}', the PLC has no {} tag of that name", name, kind);
+                    continue;
+                };
+                let tag = &table.tags()[idx];
+                let Some(value) = parse_value(tag.ty, text).filter(|value| *kind == "total" || tag.accepts(*value)) else {
+                    eprintln!("gipop: Skipping '{}', {}", name, bad_value(tag, text));
+                    continue;
+                };
+                let item = if *kind == "total" { RingItem::restore_total(idx, value) } else { RingItem::tag_write(idx, value) };
+                items.push((item, tag.name.as_str(), *text));
+            }
+            ["enocean", id, eep, name] if format >= 2 => match (field(id), field(eep), field(name)) {
+                (Some(id), Some(eep), Some(name)) => devices.push(EnoceanDevice { id, eep, name }),
+                _ => return Err(format!("{}: bad escape in the EnOcean sender", at())),
+            },
+            _ => {} // what the header says about where it came from, and lines of later versions
+        }
+    }
+    if items.is_empty() && devices.is_empty() {
+        return Err(format!("Nothing in {} to restore", path));
+    }
+
+    // the state first, the logic started (or stopped) on it last
+    items.sort_by_key(|(item, _, _)| item.kind == ITEM_RUN_MODE);
+    let mut last = None;
+    for (item, name, text) in items {
+        last = Some(send(table, &user, item)?);
+        println!("{}\t{}", name, text);
+    }
+    if let Some(seq) = last {
+        wait_acked(table, seq, "the restore")?;
+    }
+    restore_pairing(config, &devices, path)
+}
+
+// The config file's text (empty if there's none) and what's in it for us
+fn read_config(config: &str) -> Result<(String, ConfigFile), String> {
+    match fs::read_to_string(config) {
+        Ok(text) => {
+            let file = toml::from_str(&text).map_err(|e| format!("Failed to parse {}: {}", config, e))?;
+            Ok((text, file))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok((String::new(), ConfigFile::default())),
+        Err(e) => Err(format!("Failed to read {}: {}", config, e)),
+    }
+}
+
+// Appends the senders in `devices` that `config` doesn't have, a sender it has with another profile is left as it is
+fn restore_pairing(config: &str, devices: &[EnoceanDevice], path: &str) -> Result<(), String> {
+    let (mut text, file) = read_config(config)?;
+    let mut added = 0;
+    for device in devices {
+        let paired = file.enocean.devices.iter().find(|paired| paired.id.eq_ignore_ascii_case(&device.id));
+        match paired {
+            Some(paired) if !paired.eep.eq_ignore_ascii_case(&device.eep) => {
+                eprintln!("gipop: Keeping EnOcean sender {} as {} in {}, the backup has it as {}", device.id, paired.eep, config, device.eep);
+            }
+            Some(_) => {}
+            None => {
+                if !text.is_empty() && !text.ends_with('\n') {
+                    text.push('\n');
+                }
+                let quote = |text: &str| toml::Value::String(text.to_owned()).to_string();
+                text += &format!("\n[[enocean.devices]] # restored from {}\nid = {}\neep = {}\nname = {}\n", path.replace('\n', " "), quote(&device.id), quote(&device.eep), quote(&device.name));
+                println!("enocean\t{}\t{}\t{}", device.id, device.eep, device.name);
+                added += 1;
+            }
+        }
+    }
+    if added == 0 {
+        return Ok(());
+    }
+    // [enocean] devices as an inline array can't be added to
+    toml::from_str::<ConfigFile>(&text).map_err(|e| format!("Can't add the EnOcean senders to {}, add them by hand: {}", config, e))?;
+    fs::write(config, text).map_err(|e| format!("Failed to write {}: {}", config, e))?;
+    eprintln!("gipop: Added {} EnOcean sender(s) to {}, the OPC UA server takes them on its next start", added, config);
+    Ok(())
+}
+
+// Tabs and line ends would split the line
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+// None for a backslash that doesn't start an escape
+fn unescape(text: &str) -> Option<String> {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        out.push(match c {
+            '\\' => match chars.next()? {
+                't' => '\t',
+                'n' => '\n',
+                'r' => '\r',
+                '\\' => '\\',
+                _ => return None,
+            },
+            c => c,
+        });
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_escaped() {
+        let cases = [
+            ("hall light", "hall light"),
+            ("tab\there", "tab\\there"),
+            ("two\nlines\r\n", "two\\nlines\\r\\n"),
+            ("C:\\temp\\t", "C:\\\\temp\\\\t"),
+            ("", ""),
+        ];
+        for (name, escaped) in cases {
+            assert_eq!(escape(name), escaped);
+            assert_eq!(unescape(escaped).as_deref(), Some(name));
+            assert!(!escape(name).contains(['\t', '\n', '\r']));
+        }
+        assert_eq!(unescape("trailing\\"), None);
+        assert_eq!(unescape("\\x"), None);
+    }
+
+    #[test]
+    fn pairing_is_added_to_the_config() {
+        let config = std::env::temp_dir().join(format!("gipop_backup_pairing_{}.toml", std::process::id()));
+        let config = config.to_str().unwrap();
+        let device = |id: &str, eep: &str, name: &str| EnoceanDevice { id: id.to_owned(), eep: eep.to_owned(), name: name.to_owned() };
+        fs::write(config, "[enocean]\n[[enocean.devices]]\nid = \"0181A1B2\"\neep = \"F6-02-01\"\n\n[users]\nroot = { role = \"engineer\" }").unwrap();
+
+        let devices = [device("0181a1b2", "F6-02-01", "known"), device("0181A1B3", "A5-02-05", "Room \"1\"\tsensor"), device("01", "D5-00-01", "")];
+        restore_pairing(config, &devices, "plant.backup").unwrap();
+        let paired: Vec<_> = read_config(config).unwrap().1.enocean.devices.into_iter().map(|d| (d.id, d.eep, d.name)).collect();
+        assert_eq!(paired, [
+            ("0181A1B2".to_owned(), "F6-02-01".to_owned(), String::new()),
+            ("0181A1B3".to_owned(), "A5-02-05".to_owned(), "Room \"1\"\tsensor".to_owned()),
+            ("01".to_owned(), "D5-00-01".to_owned(), String::new()),
+        ]);
+        assert!(gipop_shm::Users::load(config).is_ok());
+
+        // again, nothing to add
+        let text = fs::read_to_string(config).unwrap();
+        restore_pairing(config, &devices, "plant.backup").unwrap();
+        assert_eq!(fs::read_to_string(config).unwrap(), text);
+
+        // an inline array can't be added to, the file is left alone
+        fs::write(config, "[enocean]\ndevices = []\n").unwrap();
+        assert!(restore_pairing(config, &devices, "plant.backup").is_err());
+        assert_eq!(fs::read_to_string(config).unwrap(), "[enocean]\ndevices = []\n");
+
+        // no config yet
+        fs::remove_file(config).unwrap();
+        restore_pairing(config, &devices[1..2], "plant.backup").unwrap();
+        assert_eq!(read_config(config).unwrap().1.enocean.devices.len(), 1);
+        fs::remove_file(config).unwrap();
+    }
+}
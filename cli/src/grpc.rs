@@ -0,0 +1,103 @@
+// gipop over the PLC's gRPC tag service (--grpc, `grpc` feature, [grpc] on the PLC), for a PLC on another host or one
+// whose IPC this user can't attach to. The service knows neither quality nor timestamps nor units: quality prints as
+// "-", watch stamps changes with the time they arrived and list only has names, types and whether they're writable.
+// Writes need an operator's api_key in the x-api-key metadata, see plc/src/grpc.rs.
+use gipop_shm::tags::{iso_time, now_ms};
+use gipop_shm::TagValue;
+use tonic::transport::Channel;
+use tonic::{Request, Status};
+
+use crate::{format_value, parse_value, Command};
+
+#[allow(clippy::enum_variant_names)] // generated, oneof variants all end in `Value`
+mod pb {
+    tonic::include_proto!("gipop.tags.v1");
+}
+use pb::tag_service_client::TagServiceClient;
+use pb::tag_value::Value;
+
+const API_KEY_METADATA: &str = "x-api-key";
+
+pub fn run(command: &Command, url: &str, api_key: Option<&str>) -> Result<(), String> {
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().map_err(|e| e.to_string())?;
+    runtime.block_on(async {
+        let mut client = TagServiceClient::connect(url.to_owned()).await.map_err(|e| format!("Can't connect to {}: {}", url, e))?;
+        match command {
+            Command::List => {
+                for (tag, value) in read(&mut client, &[]).await? {
+                    println!("{}\t{:?}\t{}\t\t", tag.name, value.ty(), if tag.writable { "writable" } else { "read only" });
+                }
+            }
+            Command::Get(names) => {
+                for (tag, value) in read(&mut client, names).await? {
+                    println!("{}\t{}\t-\t-", tag.name, format_value(value));
+                }
+            }
+            Command::Set(name, text) => {
+                let (tag, current) = read(&mut client, std::slice::from_ref(name)).await?.remove(0);
+                if !tag.writable {
+                    return Err(format!("Tag '{}' is read only", name));
+                }
+                let value = parse_value(current.ty(), text).ok_or_else(|| format!("Tag '{}' takes a {:?}, not '{}'", name, current.ty(), text))?;
+                let mut request = Request::new(pb::WriteTagsRequest { tags: vec![pb::Tag { name: name.clone(), value: Some(to_pb(value)), writable: true }] });
+                if let Some(api_key) = api_key {
+                    let api_key = api_key.parse().map_err(|_| "The api key has characters gRPC metadata can't carry".to_owned())?;
+                    request.metadata_mut().insert(API_KEY_METADATA, api_key);
+                }
+                client.write_tags(request).await.map_err(status)?;
+                println!("{}\t{}", name, format_value(value));
+            }
+            Command::Watch(names) => {
+                let mut updates = client.subscribe(pb::SubscribeRequest { names: names.clone() }).await.map_err(status)?.into_inner();
+                while let Some(update) = updates.message().await.map_err(status)? {
+                    let time = iso_time(now_ms());
+                    for tag in update.tags {
+                        if let Some(value) = from_pb(&tag) {
+                            println!("{}\t{}\t-\t{}", tag.name, format_value(value), time);
+                        }
+                    }
+                }
+                return Err("The PLC ended the subscription".to_owned());
+            }
+            Command::Top => return Err("top needs the PLC's IPC, the gRPC tag service has no bus diagnostics or I/O".to_owned()),
+            Command::Image => return Err("image needs the PLC's IPC, the gRPC tag service has no process image".to_owned()),
+            Command::Backup(_) | Command::Restore(..) => return Err("backup and restore need the PLC's IPC, the gRPC tag service has no totals or run mode commands".to_owned()),
+            Command::Import(_) => unreachable!("imports don't attach to a PLC, main runs them"),
+        }
+        Ok(())
+    })
+}
+
+// The named tags (every tag if none are named) with their values, in the order asked for
+async fn read(client: &mut TagServiceClient<Channel>, names: &[String]) -> Result<Vec<(pb::Tag, TagValue)>, String> {
+    let response = client.read_tags(pb::ReadTagsRequest { names: names.to_vec() }).await.map_err(status)?;
+    response.into_inner().tags.into_iter()
+        .map(|tag| from_pb(&tag).map(|value| (tag.clone(), value)).ok_or_else(|| format!("No value for '{}' in the PLC's answer", tag.name)))
+        .collect()
+}
+
+fn from_pb(tag: &pb::Tag) -> Option<TagValue> {
+    Some(match tag.value.as_ref()?.value? {
+        Value::BoolValue(b) => TagValue::Bool(b),
+        Value::Uint32Value(n) => TagValue::UInt32(n),
+        Value::Int32Value(n) => TagValue::Int32(n),
+        Value::FloatValue(f) => TagValue::Float32(f),
+        Value::DoubleValue(f) => TagValue::Float64(f),
+    })
+}
+
+fn to_pb(value: TagValue) -> pb::TagValue {
+    let value = match value {
+        TagValue::Bool(b) => Value::BoolValue(b),
+        TagValue::UInt32(n) => Value::Uint32Value(n),
+        TagValue::Int32(n) => Value::Int32Value(n),
+        TagValue::Float32(f) => Value::FloatValue(f),
+        TagValue::Float64(f) => Value::DoubleValue(f),
+    };
+    pb::TagValue { value: Some(value) }
+}
+
+fn status(status: Status) -> String {
+    format!("{:?}: {}", status.code(), status.message())
+}
+
@@ -0,0 +1,80 @@
+// gipop image, the raw EtherCAT process image of the attached PLC's last cycle with what every bit is: each
+// SubDevice's inputs, then each SubDevice's outputs, one line per terminal channel or K-bus status/control word with
+// its I/O mirror name and value. Bits the PLC didn't annotate (couplers, terminals it doesn't know) are shown as
+// their bytes. Offsets are byte.bit into the whole image, the same ones `gipop_plc scan` prints.
+//
+// Outputs are what the PLC sends on its next cycle. The image is copied out every cycle and published with the
+// I/O mirror, so a dump is at most one sync interval old.
+use std::fmt::Write;
+
+use gipop_shm::{ImageField, ProcessImage, Subscriber};
+
+pub fn run(table: &Subscriber) -> Result<(), String> {
+    let image = table.read_process_image().ok_or("The PLC hasn't published a process image, is its bus in OP?")?;
+    println!("Process image of cycle {}: inputs {} bytes, outputs {} bytes", image.cycle, image.inputs_len, image.outputs_len);
+    print!("{}", dump(&image));
+    Ok(())
+}
+
+fn dump(image: &ProcessImage) -> String {
+    let mut out = String::new();
+    for (output, direction) in [(false, "INPUTS"), (true, "OUTPUTS")] {
+        _ = writeln!(out, "\n{}", direction);
+        for (idx, sd) in image.subdevices().iter().enumerate() {
+            let (offset, len) = if output { (sd.outputs_offset, sd.outputs_len) } else { (sd.inputs_offset, sd.inputs_len) };
+            let (offset, len) = (offset as usize, len as usize);
+            if len == 0 {
+                continue;
+            }
+            let bytes = image.data[offset..offset + len].iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(" ");
+            _ = writeln!(out, "{} (0x{:04x}) {}", sd.name(), sd.address, bytes);
+
+            let range = offset * 8..(offset + len) * 8;
+            let fields: Vec<&ImageField> = image.fields().iter()
+                .filter(|field| field.subdevice as usize == idx && range.contains(&(field.bit as usize)))
+                .collect();
+            let mut rows: Vec<(usize, bool, String)> = fields.iter()
+                .map(|field| (field.bit as usize, true, row(image, field)))
+                .collect();
+
+            // bytes with bits no field covers, as they are
+            let mut covered = vec![false; len * 8];
+            for field in &fields {
+                for bit in field.bit as usize..(field.bit + field.bits) as usize {
+                    if let Some(covered) = covered.get_mut(bit - range.start) {
+                        *covered = true;
+                    }
+                }
+            }
+            for (byte, bits) in covered.chunks(8).enumerate().filter(|(_, bits)| bits.contains(&false)) {
+                let byte = offset + byte;
+                let unknown: String = (0..8).rev().map(|bit| if bits[bit] { '-' } else if image.bit(byte * 8 + bit) { '1' } else { '0' }).collect();
+                rows.push((byte * 8, false, format!("  {:<12}{:<32}0b{}", format!("{}.0-{}.7", byte, byte), "(not annotated)", unknown)));
+            }
+
+            rows.sort_by_key(|(bit, field, _)| (*bit, *field)); // a byte's raw bits ahead of the fields in it
+            for (_, _, row) in rows {
+                _ = writeln!(out, "{}", row);
+            }
+        }
+    }
+    out
+}
+
+// "  <offset>  <label>  <channel>  <value>", the value as 0/1 for single bits, decimal and hex up to 64 bits and hex
+// bytes beyond that
+fn row(image: &ProcessImage, field: &ImageField) -> String {
+    let (bit, bits) = (field.bit as usize, field.bits as usize);
+    let end = bit + bits.max(1) - 1;
+    let offset = if bits == 1 { format!("{}.{}", bit / 8, bit % 8) } else { format!("{}.{}-{}.{}", bit / 8, bit % 8, end / 8, end % 8) };
+    let value = match bits {
+        1 => (image.bit(bit) as u8).to_string(),
+        2..=64 => {
+            let value = image.read_bits(bit, bits);
+            format!("{} (0x{:0width$x})", value, value, width = bits.div_ceil(4))
+        }
+        _ => (0..bits).step_by(8).map(|from| format!("{:02x}", image.read_bits(bit + from, 8.min(bits - from)))).collect::<Vec<_>>().join(" "),
+    };
+    let channel = field.address().map(|address| address.path()).unwrap_or_default();
+    format!("  {:<12}{:<16}{:<16}{}", offset, field.label(), channel, value)
+}
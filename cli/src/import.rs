@@ -0,0 +1,359 @@
+// gipop import, for migrating a Beckhoff project instead of retyping it. Reads the I/O of a TwinCAT 3 project, or
+// ESI files plus a rack list, and writes two files into the current directory:
+//
+// gipop_hardware.toml  [[hardware.subdevices]] for gipop.toml, the bus in order with the K-bus terminals behind each
+//                      BK coupler. `gipop_plc check-config <interface>` holds the real bus against it
+// gipop_tags.rs        a TagDef for every process data entry, to paste into plc_tags() (plc/src/tags.rs) as needed.
+//                      Named after the PLC variable linked to the entry in the TwinCAT project, or else after the
+//                      terminal, PDO and entry ("term 2 channel 1 input"), filed under IO/<terminal>
+//
+// TwinCAT: the .tsproj (or a device's .xti), with the devices and boxes kept in separate files or not. Only PDOs
+// assigned to a sync manager are in the process image, and so are the only ones imported.
+// ESI: `gipop import <rack list> <ESI file or directory>...`. The rack list has a terminal type per line in bus order,
+// optionally followed by a label, K-bus terminals indented below their coupler, # comments:
+//
+//     EK1100 Term 1
+//     EL1889
+//     BK1120
+//         KL6581
+//         KL1889
+//
+// Each terminal gets its ESI's default PDO assignment, the highest revision if there are several. K-bus terminals
+// have no ESI, digital ones get a tag per channel (the last digit of the type, 9 for 16) and the rest none.
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use gipop_shm::TagType;
+
+use crate::xml::{self, Element};
+
+const HARDWARE_FILE: &str = "gipop_hardware.toml";
+const TAGS_FILE: &str = "gipop_tags.rs";
+
+struct SubDevice {
+    name: String,  // as it reports itself on the bus, "EL1889"
+    label: String, // what the project calls it, "Term 2 (EL1889)"
+    kbus: Vec<SubDevice>,
+    entries: Vec<Entry>,
+}
+
+struct Entry {
+    pdo: String,
+    name: String,
+    index: u16,
+    sub: u8,
+    ty: TagType,
+    variable: Option<String>, // linked PLC variable
+}
+
+pub fn run(paths: &[String]) -> Result<(), String> {
+    let source = Path::new(&paths[0]);
+    let twincat = matches!(source.extension().and_then(|ext| ext.to_str()), Some("tsproj" | "xti"));
+    let rack = match twincat {
+        true if paths.len() > 1 => return Err("A TwinCAT project is imported on its own, ESI files go with a rack list".to_owned()),
+        true => twincat_rack(source)?,
+        false if paths.len() < 2 => return Err("A rack list needs the ESI files (or their directory) after it".to_owned()),
+        false => esi_rack(source, &paths[1..])?,
+    };
+    if rack.is_empty() {
+        return Err(format!("No EtherCAT SubDevices in {}", source.display()));
+    }
+
+    let (tags, tag_count) = tag_skeleton(&rack, source);
+    fs::write(HARDWARE_FILE, hardware_toml(&rack, source)).map_err(|e| format!("Can't write {}: {}", HARDWARE_FILE, e))?;
+    fs::write(TAGS_FILE, tags).map_err(|e| format!("Can't write {}: {}", TAGS_FILE, e))?;
+    println!(
+        "{} SubDevices, {} K-bus terminals, {} tags: {} and {} written",
+        rack.len(), rack.iter().map(|sd| sd.kbus.len()).sum::<usize>(), tag_count, HARDWARE_FILE, TAGS_FILE,
+    );
+    Ok(())
+}
+
+fn load(path: &Path) -> Result<Element, String> {
+    let bytes = fs::read(path).map_err(|e| format!("Can't read {}: {}", path.display(), e))?;
+    xml::parse(&String::from_utf8_lossy(&bytes)).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// TwinCAT
+
+fn twincat_rack(path: &Path) -> Result<Vec<SubDevice>, String> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let root = inline_files(load(path)?, dir)?;
+    let links = links(&root);
+    let mut rack = Vec::new();
+    let mut devices = Vec::new();
+    root.descendants("Device", &mut devices);
+    for device in devices {
+        boxes(device, &links, &mut rack);
+    }
+    Ok(rack)
+}
+
+// TwinCAT 3 can keep devices and boxes in files of their own, the project then only has <Box File="..."/>.
+// Those files are somewhere below the project's directory (_Config/IO/...)
+fn inline_files(mut element: Element, dir: &Path) -> Result<Element, String> {
+    if let Some(file) = element.attr("File").filter(|_| element.children.is_empty()) {
+        let name = file.rsplit(['/', '\\']).next().unwrap_or(file); // written on Windows
+        let path = find_file(dir, name.as_ref())
+            .ok_or_else(|| format!("{} refers to {}, which isn't below {}", element.name, file, dir.display()))?;
+        let mut found = take(load(&path)?, &element.name).ok_or_else(|| format!("No <{}> in {}", element.name, path.display()))?;
+        found.attrs.retain(|(attr, _)| attr != "File");
+        element = found;
+    }
+    element.children = element.children.into_iter().map(|child| inline_files(child, dir)).collect::<Result<_, _>>()?;
+    Ok(element)
+}
+
+fn find_file(dir: &Path, name: &std::ffi::OsStr) -> Option<PathBuf> {
+    let entries: Vec<_> = fs::read_dir(dir).ok()?.flatten().map(|entry| entry.path()).collect();
+    entries.iter().find(|path| path.is_file() && path.file_name() == Some(name)).cloned()
+        .or_else(|| entries.iter().filter(|path| path.is_dir()).find_map(|path| find_file(path, name)))
+}
+
+// The first element called `name`, depth first, out of the tree
+fn take(element: Element, name: &str) -> Option<Element> {
+    if element.name == name {
+        return Some(element);
+    }
+    element.children.into_iter().find_map(|child| take(child, name))
+}
+
+// PLC variables linked to I/O: (box label, "PDO^entry") -> variable. <Mappings> has an OwnerA/OwnerB pair per
+// PLC instance and box, TIPC^... the PLC's side and TIID^...^<box label> the I/O's
+fn links(root: &Element) -> HashMap<(String, String), String> {
+    let mut links = HashMap::new();
+    let mut owners = Vec::new();
+    root.descendants("OwnerA", &mut owners);
+    for owner_a in owners {
+        for owner_b in owner_a.children_named("OwnerB") {
+            let (a, b) = (owner_a.attr("Name").unwrap_or_default(), owner_b.attr("Name").unwrap_or_default());
+            let io_is_a = a.starts_with("TIID^");
+            let io_owner = if io_is_a { a } else { b };
+            let label = io_owner.rsplit('^').next().unwrap_or_default().to_owned();
+            for link in owner_b.children_named("Link") {
+                let (var_a, var_b) = (link.attr("VarA").unwrap_or_default(), link.attr("VarB").unwrap_or_default());
+                let (io, plc) = if io_is_a { (var_a, var_b) } else { (var_b, var_a) };
+                let variable = plc.rsplit('^').next().unwrap_or_default(); // "PlcTask Inputs^MAIN.bStart"
+                links.insert((label.clone(), io.to_owned()), variable.to_owned());
+            }
+        }
+    }
+    links
+}
+
+// Boxes in document order, which is bus order. A box with an <EtherCAT> element is a SubDevice, boxes and terms
+// without one below a BK coupler are its K-bus terminals
+fn boxes(parent: &Element, links: &HashMap<(String, String), String>, rack: &mut Vec<SubDevice>) {
+    for element in parent.children.iter().filter(|child| child.name == "Box" || child.name == "Term") {
+        let label = element.child_text("Name").to_owned();
+        match element.child("EtherCAT") {
+            Some(ethercat) => {
+                let name = ethercat.attr("Type").map(type_name).unwrap_or_else(|| type_from_label(&label));
+                let entries = twincat_entries(ethercat, &label, links);
+                rack.push(SubDevice { name, label, kbus: Vec::new(), entries });
+            }
+            None => {
+                let name = type_from_label(&label);
+                if let Some(coupler) = rack.last_mut().filter(|coupler| coupler.name.starts_with("BK")) && name != "KL9010" {
+                    coupler.kbus.push(kbus_terminal(name, label));
+                }
+            }
+        }
+        boxes(element, links, rack);
+    }
+}
+
+fn twincat_entries(ethercat: &Element, label: &str, links: &HashMap<(String, String), String>) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    for pdo in ethercat.children_named("Pdo").filter(|pdo| pdo.attr("SyncMan").is_some()) {
+        let pdo_name = pdo.attr("Name").unwrap_or_default();
+        for entry in pdo.children_named("Entry") {
+            let Some(index) = entry.attr("Index").and_then(number).filter(|index| *index != 0) else { continue }; // padding
+            let name = entry.attr("Name").unwrap_or_default();
+            entries.push(Entry {
+                pdo: pdo_name.to_owned(),
+                name: name.to_owned(),
+                index: index as u16,
+                sub: entry.attr("Sub").and_then(number).unwrap_or(0) as u8,
+                ty: tag_type(entry.child_text("Type")),
+                variable: links.get(&(label.to_owned(), format!("{}^{}", pdo_name, name))).cloned(),
+            });
+        }
+    }
+    entries
+}
+
+// ESI
+
+fn esi_rack(rack_list: &Path, esi: &[String]) -> Result<Vec<SubDevice>, String> {
+    let mut descriptions: HashMap<String, (u64, Element)> = HashMap::new(); // type -> (revision, <Device>)
+    for path in esi {
+        for file in esi_files(Path::new(path))? {
+            let root = load(&file)?;
+            let mut devices = Vec::new();
+            root.descendants("Device", &mut devices);
+            for device in devices.into_iter().filter(|device| device.child("Type").is_some()) {
+                let ty = device.child("Type").unwrap();
+                let revision = ty.attr("RevisionNo").and_then(number).unwrap_or(0);
+                if descriptions.get(&ty.text).is_none_or(|(known, _)| revision > *known) {
+                    descriptions.insert(ty.text.clone(), (revision, clone(device)));
+                }
+            }
+        }
+    }
+
+    let text = fs::read_to_string(rack_list).map_err(|e| format!("Can't read {}: {}", rack_list.display(), e))?;
+    let mut rack: Vec<SubDevice> = Vec::new();
+    for (line_no, line) in text.lines().enumerate() {
+        let content = line.split('#').next().unwrap_or_default();
+        let Some(name) = content.split_whitespace().next() else { continue };
+        let label = content.trim().strip_prefix(name).unwrap_or_default().trim();
+        let label = if label.is_empty() { format!("Term {} ({})", rack.len() + rack.iter().map(|sd| sd.kbus.len()).sum::<usize>() + 1, name) } else { label.to_owned() };
+        if content.starts_with(char::is_whitespace) {
+            let coupler = rack.last_mut().filter(|coupler| coupler.name.starts_with("BK"))
+                .ok_or_else(|| format!("{}:{}: {} is indented but doesn't follow a BK coupler", rack_list.display(), line_no + 1, name))?;
+            coupler.kbus.push(kbus_terminal(name.to_owned(), label));
+        } else {
+            let (_, device) = descriptions.get(name)
+                .ok_or_else(|| format!("{}:{}: no ESI describes {}", rack_list.display(), line_no + 1, name))?;
+            rack.push(SubDevice { name: name.to_owned(), label, kbus: Vec::new(), entries: esi_entries(device) });
+        }
+    }
+    Ok(rack)
+}
+
+fn esi_files(path: &Path) -> Result<Vec<PathBuf>, String> {
+    if path.is_file() {
+        return Ok(vec![path.to_owned()]);
+    }
+    let mut files: Vec<PathBuf> = fs::read_dir(path).map_err(|e| format!("Can't read {}: {}", path.display(), e))?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("xml")))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+// The PDOs a terminal comes up with, those with a default sync manager (Sm)
+fn esi_entries(device: &Element) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let pdos = device.children.iter().filter(|pdo| (pdo.name == "TxPdo" || pdo.name == "RxPdo") && pdo.attr("Sm").is_some());
+    for pdo in pdos {
+        for entry in pdo.children_named("Entry") {
+            let Some(index) = number(entry.child_text("Index")).filter(|index| *index != 0) else { continue };
+            entries.push(Entry {
+                pdo: pdo.child_text("Name").to_owned(),
+                name: entry.child_text("Name").to_owned(),
+                index: index as u16,
+                sub: number(entry.child_text("SubIndex")).unwrap_or(0) as u8,
+                ty: tag_type(entry.child_text("DataType")),
+                variable: None,
+            });
+        }
+    }
+    entries
+}
+
+fn clone(element: &Element) -> Element {
+    Element {
+        name: element.name.clone(),
+        attrs: element.attrs.clone(),
+        children: element.children.iter().map(clone).collect(),
+        text: element.text.clone(),
+    }
+}
+
+// Both
+
+// KL1xxx/KL2xxx digital terminals, a channel per bit: the last digit of the type, 9 is 16
+fn kbus_terminal(name: String, label: String) -> SubDevice {
+    let digital = name.strip_prefix("KL").filter(|number| number.len() == 4 && (number.starts_with('1') || number.starts_with('2')));
+    let entries = match digital.and_then(|number| number[3..].parse::<u8>().ok()) {
+        Some(channels) => (1..=if channels == 9 { 16 } else { channels }).map(|channel| Entry {
+            pdo: format!("Channel {}", channel),
+            name: if name.starts_with("KL1") { "Input" } else { "Output" }.to_owned(),
+            index: 0,
+            sub: channel,
+            ty: TagType::Bool,
+            variable: None,
+        }).collect(),
+        None => Vec::new(),
+    };
+    SubDevice { name, label, kbus: Vec::new(), entries }
+}
+
+// "EL1889-0000-0018" -> "EL1889", what the SubDevice reports
+fn type_name(ty: &str) -> String {
+    ty.split('-').next().unwrap_or(ty).to_owned()
+}
+
+// TwinCAT names terminals "Term 2 (EL1889)"
+fn type_from_label(label: &str) -> String {
+    label.rsplit_once('(').and_then(|(_, ty)| ty.strip_suffix(')')).map(type_name).unwrap_or_else(|| label.to_owned())
+}
+
+// #x1a00, 0x1a00 or decimal
+fn number(text: &str) -> Option<u64> {
+    let text = text.trim();
+    match text.strip_prefix("#x").or_else(|| text.strip_prefix("0x")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn tag_type(ty: &str) -> TagType {
+    match ty.to_ascii_uppercase().as_str() {
+        "BIT" | "BOOL" => TagType::Bool,
+        "SINT" | "INT" | "DINT" | "INT8" | "INT16" | "INT32" => TagType::Int32,
+        "REAL" | "FLOAT" => TagType::Float32,
+        "LREAL" | "DOUBLE" => TagType::Float64,
+        _ => TagType::UInt32, // USINT, UINT, UDINT, BYTE, WORD, DWORD, BITn
+    }
+}
+
+// Output
+
+fn hardware_toml(rack: &[SubDevice], source: &Path) -> String {
+    let mut toml = format!("# The bus of {} as imported by `gipop import`, for gipop.toml\n", source.display());
+    for sd in rack {
+        _ = write!(toml, "\n[[hardware.subdevices]]\nname = {:?}\nlabel = {:?}\n", sd.name, sd.label);
+        if !sd.kbus.is_empty() {
+            let kbus: Vec<String> = sd.kbus.iter().map(|term| format!("{:?}", term.name)).collect();
+            _ = writeln!(toml, "kbus = [{}]", kbus.join(", "));
+        }
+    }
+    toml
+}
+
+fn tag_skeleton(rack: &[SubDevice], source: &Path) -> (String, usize) {
+    let mut code = format!(
+        "// Tags for the I/O of {}, imported by `gipop import`. Paste what's needed into plc_tags() in\n\
+         // plc/src/tags.rs, give them names the HMIs can live with and scale raw analog values in the logic.\n",
+        source.display(),
+    );
+    let mut names = HashSet::new();
+    let terminals = rack.iter().flat_map(|sd| std::iter::once(sd).chain(&sd.kbus));
+    for terminal in terminals.filter(|terminal| !terminal.entries.is_empty()) {
+        let short = terminal.label.split(" (").next().unwrap_or(&terminal.label).to_lowercase();
+        for entry in &terminal.entries {
+            let base = entry.variable.clone().unwrap_or_else(|| format!("{} {} {}", short, entry.pdo, entry.name).to_lowercase());
+            let mut name = base.clone();
+            for n in 2.. {
+                if names.insert(name.clone()) {
+                    break;
+                }
+                name = format!("{} {}", base, n);
+            }
+            let address = if entry.index == 0 { String::new() } else { format!(", 0x{:04x}:{:02x}", entry.index, entry.sub) };
+            _ = writeln!(
+                code,
+                "        TagDef::new({:?}, TagType::{:?}, 0).in_folder({:?}), // {} {}{}",
+                name, entry.ty, format!("IO/{}", terminal.label), entry.pdo, entry.name, address,
+            );
+        }
+    }
+    (code, names.len())
+}
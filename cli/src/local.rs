@@ -0,0 +1,114 @@
+// gipop attached over the IPC transport ([ipc] in gipop.toml), like any other consumer. It never sends a heartbeat,
+// a shell tool coming and going mustn't look like an HMI to the PLC's watchdog. Writes go through the command ring
+// followed by an ITEM_AUDIT with the unix login, the PLC's audit log names who sent them like it does for OPC UA.
+// Restores (backup.rs) too. The PLC only applies them if the login's role allows it, checking here first gets the
+// error out before anything is sent.
+use std::time::{Duration, Instant};
+
+use gipop_shm::tags::iso_time;
+use gipop_shm::{Action, IpcConfig, RingItem, Subscriber, TagDef, TagSample, Users};
+
+use crate::{bad_value, format_value, parse_value, Command};
+
+const ACK_TIMEOUT: Duration = Duration::from_secs(2); // a running PLC takes commands within a scan or two
+const WATCH_INTERVAL: Duration = Duration::from_millis(100);
+const TOP_INTERVAL: Duration = Duration::from_millis(500);
+
+/// `interval` None for the command's default
+pub fn run(command: &Command, config: &str, interval: Option<Duration>) -> Result<(), String> {
+    let ipc = IpcConfig::load(config)?;
+    let table = Subscriber::connect(&ipc).map_err(|e| format!("Can't attach to the PLC, is it running? {}", e))?;
+    match command {
+        Command::List => {
+            for tag in table.tags() {
+                println!("{}\t{:?}\t{}\t{}\t{}", tag.name, tag.ty, kind(tag), tag.unit, tag.folder);
+            }
+        }
+        Command::Get(names) => {
+            for idx in indices(&table, names)? {
+                println!("{}", sample_line(&table.tags()[idx], &table.read_sample(idx)));
+            }
+        }
+        Command::Set(name, text) => set(&table, config, name, text)?,
+        Command::Watch(names) => {
+            let indices = if names.is_empty() { (0..table.tags().len()).collect() } else { indices(&table, names)? };
+            let mut last: Vec<Option<TagSample>> = vec![None; table.tags().len()];
+            loop {
+                for &idx in &indices {
+                    let sample = table.read_sample(idx);
+                    // a new timestamp alone isn't a change, the PLC stamps every publish
+                    if last[idx].is_none_or(|last| last.value != sample.value || last.quality != sample.quality) {
+                        println!("{}", sample_line(&table.tags()[idx], &sample));
+                        last[idx] = Some(sample);
+                    }
+                }
+                std::thread::sleep(interval.unwrap_or(WATCH_INTERVAL));
+            }
+        }
+        Command::Top => crate::top::run(&table, ipc.heartbeat_timeout(), interval.unwrap_or(TOP_INTERVAL))?,
+        Command::Image => crate::image::run(&table)?,
+        Command::Backup(path) => crate::backup::backup(&table, config, path)?,
+        Command::Restore(path, force) => crate::backup::restore(&table, config, path, *force)?,
+        Command::Import(_) => unreachable!("imports don't attach to a PLC, main runs them"),
+    }
+    Ok(())
+}
+
+fn set(table: &Subscriber, config: &str, name: &str, text: &str) -> Result<(), String> {
+    let users = Users::load(config)?;
+    let user = Users::local_user();
+    users.authorize(user.as_deref(), Action::WriteTag)?;
+    let idx = indices(table, &[name.to_owned()])?[0];
+    let tag = &table.tags()[idx];
+    if !tag.writable() {
+        return Err(format!("Tag '{}' is read only", name));
+    }
+    let value = parse_value(tag.ty, text).filter(|value| tag.accepts(*value)).ok_or_else(|| bad_value(tag, text))?;
+
+    let seq = send(table, user.as_deref().unwrap_or_default(), RingItem::tag_write(idx, value))?;
+    wait_acked(table, seq, "the write")?;
+    println!("{}\t{}", tag.name, format_value(value));
+    Ok(())
+}
+
+/// Queues `item` followed by its audit naming `user`, the command's seq
+pub fn send(table: &Subscriber, user: &str, item: RingItem) -> Result<u32, String> {
+    let seq = table.push_command(item).map_err(|_| "PLC isn't consuming commands".to_owned())?;
+    if table.push_command(RingItem::audit(seq, 0, user)).is_err() {
+        eprintln!("gipop: Audit of command {} rejected, PLC isn't consuming commands", seq);
+    }
+    Ok(seq)
+}
+
+/// Waits for the PLC to take command `seq` and everything before it (acks are cumulative), Err after ACK_TIMEOUT
+pub fn wait_acked(table: &Subscriber, seq: u32, what: &str) -> Result<(), String> {
+    let sent = Instant::now();
+    while !table.is_command_acked(seq) {
+        if sent.elapsed() >= ACK_TIMEOUT {
+            return Err(format!("The PLC didn't take {} within {:?}", what, ACK_TIMEOUT));
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    Ok(())
+}
+
+// Where `names` are in the tag table, Err naming the first that isn't
+fn indices(table: &Subscriber, names: &[String]) -> Result<Vec<usize>, String> {
+    names.iter().map(|name| table.index_of(name).ok_or_else(|| format!("No tag named '{}'", name))).collect()
+}
+
+fn kind(tag: &TagDef) -> &'static str {
+    if tag.is_alarm() {
+        "alarm"
+    } else if tag.is_external() {
+        "external"
+    } else if tag.writable() {
+        "writable"
+    } else {
+        "read only"
+    }
+}
+
+fn sample_line(tag: &TagDef, sample: &TagSample) -> String {
+    format!("{}\t{}\t{:?}\t{}", tag.name, format_value(sample.value), sample.quality, iso_time(sample.ts_ms))
+}
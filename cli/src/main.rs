@@ -0,0 +1,177 @@
+// gipop, the PLC's tags from a shell, for commissioning and scripts without an OPC UA client. Attaches to the PLC
+// like the other consumers do ([ipc] in gipop.toml, local.rs) or, with --grpc and the `grpc` feature, to its gRPC tag
+// service (grpc.rs):
+//
+// gipop list                  every tag: name, type, what it is (writable, read only, alarm, external), unit, folder
+// gipop get <tag>...          name, value, quality and the PLC's timestamp
+// gipop set <tag> <value>     queued as a command like an OPC UA write, true/false/on/off or a number for booleans
+// gipop watch [<tag>...]      a get line for every change until interrupted, every tag if none are named
+// gipop top                   live dashboard: bus and SubDevice states, cycle times, active alarms, I/O (top.rs)
+// gipop image                 raw process image of the last cycle, every bit with its terminal, channel and value
+//                             (image.rs)
+// gipop backup <file>        retained tags, totals, the run state and the EnOcean pairing in one file, for a
+//                             replacement controller
+// gipop restore <file>       put them back, --force across tag lists (backup.rs)
+// gipop import <project>      hardware config and tag skeletons from a TwinCAT project or a rack list and ESI files,
+//                             without a PLC (import.rs)
+//
+// Options, anywhere on the line:
+// --config <path>    gipop.toml for [ipc] and [users], GIPOP_PROJECT's (gipop_shm::project) or ./gipop.toml by default
+// --grpc <url>       e.g. http://127.0.0.1:50051 instead of attaching locally
+// --api-key <key>    [users] api_key for writes over gRPC, GIPOP_API_KEY if not given
+// --interval <ms>    how often watch looks for changes when attached locally (100 by default) and top redraws (500)
+// --force            restore a backup taken with another tag list
+//
+// Output is tab separated, one tag per line, for cut and awk. Writes are checked against [users] like on every other
+// interface: attached locally the unix login running the tool is the user (see gipop_shm::users), over gRPC the api
+// key's owner. Attached locally the PLC checks the same again before it applies the command. Exits with 1 when something
+// fails, 2 on bad usage.
+mod backup;
+mod local;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod image;
+mod import;
+mod top;
+mod xml;
+
+use std::process::ExitCode;
+use std::time::Duration;
+
+use gipop_shm::{TagDef, TagType, TagValue};
+
+const CONFIG_PATH: &str = "gipop.toml";
+const USAGE: &str = "usage: gipop [--config <path>] [--grpc <url>] [--api-key <key>] [--interval <ms>] [--force] <command>
+  list                 every tag with its type, unit and folder
+  get <tag>...         current value, quality and timestamp
+  set <tag> <value>    write a writable tag
+  watch [<tag>...]     print changes until interrupted
+  top                  live bus, alarm and I/O dashboard
+  image                annotated dump of the raw process image
+  backup <file>        save retained tags, totals, the run state and the EnOcean pairing
+  restore <file>       put a backup back into the PLC
+  import <project.tsproj|device.xti>
+  import <rack list> <ESI file or dir>...
+                       gipop_hardware.toml and gipop_tags.rs from a Beckhoff project";
+
+pub enum Command {
+    List,
+    Get(Vec<String>),
+    Set(String, String),
+    Watch(Vec<String>), // empty for every tag
+    Top,
+    Image,
+    Backup(String),
+    Restore(String, bool), // the backup, whether to restore across tag lists
+    Import(Vec<String>), // a TwinCAT project, or a rack list and ESI files
+}
+
+struct Args {
+    config: Option<String>,
+    grpc: Option<String>,
+    api_key: Option<String>,
+    interval: Option<Duration>,
+    command: Command,
+}
+
+fn main() -> ExitCode {
+    if std::env::args().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("gipop: {}\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    let api_key = args.api_key.or_else(|| std::env::var("GIPOP_API_KEY").ok().filter(|key| !key.is_empty()));
+    let result = match (&args.command, &args.grpc) {
+        (Command::Import(paths), _) => import::run(paths),
+        (_, Some(url)) => run_grpc(url, &args.command, api_key.as_deref()),
+        (_, None) => args.config.map_or_else(|| gipop_shm::project::config_path(CONFIG_PATH, env!("CARGO_PKG_VERSION")), Ok)
+            .and_then(|config| local::run(&args.command, &config, args.interval)),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("gipop: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(feature = "grpc")]
+fn run_grpc(url: &str, command: &Command, api_key: Option<&str>) -> Result<(), String> {
+    grpc::run(command, url, api_key)
+}
+
+#[cfg(not(feature = "grpc"))]
+fn run_grpc(_url: &str, _command: &Command, _api_key: Option<&str>) -> Result<(), String> {
+    Err("--grpc needs a build with the `grpc` feature".to_owned())
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let (mut config, mut grpc, mut api_key, mut interval, mut force) = (None, None, None, None, false);
+    let mut operands = Vec::new();
+    while let Some(arg) = args.next() {
+        let mut option_value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--config" => config = Some(option_value()?),
+            "--grpc" => grpc = Some(option_value()?),
+            "--api-key" => api_key = Some(option_value()?),
+            "--force" => force = true,
+            "--interval" => {
+                let ms = option_value()?;
+                interval = Some(Duration::from_millis(ms.parse().map_err(|_| format!("--interval takes milliseconds, not '{}'", ms))?));
+            }
+            option if option.starts_with("--") => return Err(format!("Unknown option {}", option)),
+            _ => operands.push(arg),
+        }
+    }
+    let mut operands = operands.into_iter();
+    let command = match (operands.next().as_deref(), operands.len()) {
+        (Some("list"), 0) => Command::List,
+        (Some("get"), 1..) => Command::Get(operands.collect()),
+        (Some("set"), 2) => Command::Set(operands.next().unwrap(), operands.next().unwrap()),
+        (Some("watch"), _) => Command::Watch(operands.collect()),
+        (Some("top"), 0) => Command::Top,
+        (Some("image"), 0) => Command::Image,
+        (Some("backup"), 1) => Command::Backup(operands.next().unwrap()),
+        (Some("restore"), 1) => Command::Restore(operands.next().unwrap(), force),
+        (Some("import"), 1..) => Command::Import(operands.collect()),
+        (Some(command @ ("list" | "get" | "set" | "top" | "image" | "backup" | "restore" | "import")), _) => return Err(format!("Wrong number of arguments for {}", command)),
+        (Some(command), _) => return Err(format!("Unknown command {}", command)),
+        (None, _) => return Err("No command".to_owned()),
+    };
+    if force && !matches!(command, Command::Restore(..)) {
+        return Err("--force only goes with restore".to_owned());
+    }
+    Ok(Args { config, grpc, api_key, interval, command })
+}
+
+/// `text` as a value for a tag of type `ty`: true/false/on/off or a number for booleans, a number for the rest
+pub fn parse_value(ty: TagType, text: &str) -> Option<TagValue> {
+    match (ty, text) {
+        (TagType::Bool, "true" | "on") => Some(TagValue::Bool(true)),
+        (TagType::Bool, "false" | "off") => Some(TagValue::Bool(false)),
+        _ => TagValue::from_f64(ty, text.parse().ok()?),
+    }
+}
+
+/// Why `text` isn't a value `tag` takes
+pub fn bad_value(tag: &TagDef, text: &str) -> String {
+    let range = tag.write_range().map(|(low, high)| format!(" from {} to {}", low, high)).unwrap_or_default();
+    format!("Tag '{}' takes a {:?}{}, not '{}'", tag.name, tag.ty, range, text)
+}
+
+pub fn format_value(value: TagValue) -> String {
+    match value {
+        TagValue::Bool(b) => b.to_string(),
+        TagValue::UInt32(n) => n.to_string(),
+        TagValue::Int32(n) => n.to_string(),
+        TagValue::Float32(f) => f.to_string(),
+        TagValue::Float64(f) => f.to_string(),
+    }
+}
@@ -0,0 +1,147 @@
+// gipop top, a live dashboard of the attached PLC redrawn in place until interrupted: the PLC and its heartbeat, the
+// EtherCAT cycle statistics, each SubDevice's state and error counters, the active alarms and every I/O channel from
+// the I/O mirror. Plain ANSI escapes, any terminal that shows colors in `ls` does.
+//
+// The bus diagnostics are only as fresh as the PLC's last pass over them, about once a second. Alarms show since when
+// this dashboard has seen them active, the PLC restamps every tag on every publish. The I/O is laid out for $COLUMNS
+// when the shell exports it, 120 columns otherwise.
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::io::Write as _;
+use std::time::Duration;
+
+use gipop_shm::io_mirror::{IO_AI, IO_DI, IO_DO, IO_STATUS_BUS_DOWN, IO_STATUS_FORCED};
+use gipop_shm::tags::{iso_time, now_ms};
+use gipop_shm::{BusDiagnostics, IoChannel, Liveness, Subscriber};
+
+const SCAN_TIME_TAG: &str = "scan time";
+const CELL_WIDTH: usize = 26; // one I/O channel, "KBus/DO1/Ch16        1 F  "
+
+// Home, then each line cleared to its end, then everything below the last one
+const HOME: &str = "\x1b[H";
+const CLEAR_LINE: &str = "\x1b[K";
+const CLEAR_BELOW: &str = "\x1b[J";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const RESET: &str = "\x1b[0m";
+
+pub fn run(table: &Subscriber, heartbeat_timeout: Duration, interval: Duration) -> Result<(), String> {
+    let mut plc = Liveness::new(heartbeat_timeout);
+    let mut active_since: HashMap<usize, u64> = HashMap::new(); // alarm tag -> first seen active
+    print!("\x1b[2J");
+    loop {
+        plc.update(table.plc_heartbeat());
+        let screen = draw(table, plc.is_alive(), &mut active_since);
+        let mut stdout = std::io::stdout().lock();
+        write!(stdout, "{}{}{}", HOME, screen.replace('\n', &format!("{}\n", CLEAR_LINE)), CLEAR_BELOW)
+            .and_then(|()| stdout.flush())
+            .map_err(|e| e.to_string())?;
+        drop(stdout);
+        std::thread::sleep(interval);
+    }
+}
+
+fn draw(table: &Subscriber, alive: bool, active_since: &mut HashMap<usize, u64>) -> String {
+    let mut screen = String::new();
+    let now = now_ms();
+
+    let plc = match table.read_plc_info() {
+        Some(info) => {
+            let project = if info.project().is_empty() { String::new() } else { format!(", {} {}", info.project(), info.project_version()) };
+            format!("PLC {} {} {}{}, up {}", info.version(), info.commit(), info.profile(), project, uptime(now.saturating_sub(info.started_ms)))
+        }
+        None => "PLC".to_owned(),
+    };
+    let state = if alive { "alive".to_owned() } else { format!("{}{}heartbeat stale{}", BOLD, RED, RESET) };
+    _ = writeln!(screen, "{}{}{}, {}    {}", BOLD, plc, RESET, state, iso_time(now));
+
+    let scan = table.index_of(SCAN_TIME_TAG).map(|idx| format!("  scan {:.2} ms", table.read_sample(idx).value.as_f64())).unwrap_or_default();
+    match table.read_bus_diag() {
+        Some(diag) => draw_bus(&mut screen, &diag, &scan),
+        None => _ = writeln!(screen, "No bus diagnostics yet{}\n", scan),
+    }
+
+    draw_alarms(&mut screen, table, now, active_since);
+    draw_io(&mut screen, &table.read_io());
+    screen
+}
+
+fn draw_bus(screen: &mut String, diag: &BusDiagnostics, scan: &str) {
+    let ms = |us: u32| us as f64 / 1000.0;
+    _ = writeln!(
+        screen,
+        "Cycle {:.3} ms  min {:.3}  avg {:.3}  max {:.3}  jitter avg {:.3} max {:.3}{}",
+        ms(diag.cycle_us_last), ms(diag.cycle_us_min), ms(diag.cycle_us_avg), ms(diag.cycle_us_max), ms(diag.jitter_us_avg), ms(diag.jitter_us_max), scan,
+    );
+    let wkc = if diag.wkc_errors > 0 { format!("{}{}{}", YELLOW, diag.wkc_errors, RESET) } else { "0".to_owned() };
+    let kbus = match diag.coupler_status {
+        0 => "OK".to_owned(),
+        u32::MAX => "no coupler".to_owned(),
+        status => format!("{}error 0x{:x}{}", RED, status, RESET),
+    };
+    _ = writeln!(screen, "Cycles {}  WKC errors {}  K-bus {}\n", diag.cycles, wkc, kbus);
+
+    _ = writeln!(screen, "{}ADDR    NAME              STATE        AL CODE  PORT ERRORS             LOST LINKS{}", BOLD, RESET);
+    for subdevice in diag.subdevices() {
+        let state = match (subdevice.has_error(), subdevice.state_name()) {
+            (true, name) => format!("{}{:<13}{}", RED, format!("{}+ERR", name), RESET),
+            (false, "OP") => format!("{:<13}", "OP"),
+            (false, name) => format!("{}{:<13}{}", YELLOW, name, RESET),
+        };
+        let ports = subdevice.port_errors.map(|errors| errors.to_string()).join(" ");
+        _ = writeln!(
+            screen,
+            "{:<8}{:<18}{}0x{:04x}   {:<24}{}",
+            format!("0x{:04x}", subdevice.address), subdevice.name(), state, subdevice.al_status_code, ports, subdevice.lost_links,
+        );
+    }
+    screen.push('\n');
+}
+
+fn draw_alarms(screen: &mut String, table: &Subscriber, now: u64, active_since: &mut HashMap<usize, u64>) {
+    let tags = table.tags();
+    let active_alarms: Vec<usize> = (0..tags.len()).filter(|&idx| tags[idx].is_alarm() && table.read_sample(idx).value.as_f64() != 0.0).collect();
+    active_since.retain(|idx, _| active_alarms.contains(idx));
+    let mut active: Vec<_> = active_alarms.iter()
+        .map(|&idx| (tags[idx].severity, *active_since.entry(idx).or_insert(now), tags[idx].name.as_str()))
+        .collect();
+    active.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1))); // most severe first, then oldest
+
+    _ = writeln!(screen, "{}ALARMS{} ({} active)", BOLD, RESET, active.len());
+    for (severity, since, name) in active {
+        let color = if severity >= 700 { RED } else { YELLOW };
+        _ = writeln!(screen, "{}{:>5}{}  {}  since {}", color, severity, RESET, name, iso_time(since));
+    }
+    screen.push('\n');
+}
+
+fn draw_io(screen: &mut String, channels: &[IoChannel]) {
+    let width = std::env::var("COLUMNS").ok().and_then(|columns| columns.parse().ok()).unwrap_or(120usize);
+    let per_line = (width / CELL_WIDTH).max(1);
+    _ = writeln!(screen, "{}I/O{} ({} channels, F forced, ! bus down)", BOLD, RESET, channels.len());
+    for line in channels.chunks(per_line) {
+        for channel in line {
+            let value = match channel.kind {
+                IO_DI | IO_DO => format!("{}", channel.value as u8),
+                IO_AI => format!("{:.3}", channel.value),
+                _ => format!("0x{:02x}", channel.status & 0xffff), // intelligent terminals, their status byte
+            };
+            let flags = [(IO_STATUS_FORCED, 'F'), (IO_STATUS_BUS_DOWN, '!')].iter()
+                .filter(|(flag, _)| channel.status & flag != 0)
+                .map(|(_, mark)| *mark)
+                .collect::<String>();
+            _ = write!(screen, "{:<14}{:>8} {:<3}", channel.path(), value, flags);
+        }
+        screen.push('\n');
+    }
+}
+
+fn uptime(ms: u64) -> String {
+    let minutes = ms / 60_000;
+    match (minutes / 1440, minutes / 60 % 24, minutes % 60) {
+        (0, 0, minutes) => format!("{}m", minutes),
+        (0, hours, minutes) => format!("{}h{:02}m", hours, minutes),
+        (days, hours, _) => format!("{}d{:02}h", days, hours),
+    }
+}
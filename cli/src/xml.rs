@@ -0,0 +1,146 @@
+// Just enough XML for TwinCAT project files and ESI files: elements, attributes, text, the five predefined entities
+// and character references. Comments, processing instructions, the DOCTYPE and CDATA markers are skipped, namespaces
+// stay part of the names. Everything is read into memory at once, these files are a few MB at most.
+
+#[derive(Debug, Default)]
+pub struct Element {
+    pub name: String,
+    pub attrs: Vec<(String, String)>,
+    pub children: Vec<Element>,
+    pub text: String, // the element's own text, trimmed, without its children's
+}
+
+impl Element {
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs.iter().find(|(attr, _)| attr == name).map(|(_, value)| value.as_str())
+    }
+
+    pub fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    pub fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+
+    /// Text of the first child called `name`, "" if there's none
+    pub fn child_text(&self, name: &str) -> &str {
+        self.child(name).map(|child| child.text.as_str()).unwrap_or_default()
+    }
+
+    /// This element and everything below it called `name`, in document order
+    pub fn descendants<'a>(&'a self, name: &str, found: &mut Vec<&'a Element>) {
+        if self.name == name {
+            found.push(self);
+        }
+        for child in &self.children {
+            child.descendants(name, found);
+        }
+    }
+}
+
+/// The document's root element
+pub fn parse(text: &str) -> Result<Element, String> {
+    let mut stack = vec![Element::default()]; // a pseudo root holding the document element
+    let mut rest = text.trim_start_matches('\u{feff}');
+    while !rest.is_empty() {
+        let Some(lt) = rest.find('<') else {
+            append_text(stack.last_mut().unwrap(), rest)?;
+            break;
+        };
+        append_text(stack.last_mut().unwrap(), &rest[..lt])?;
+        rest = &rest[lt..];
+
+        if let Some(cdata) = rest.strip_prefix("<![CDATA[") {
+            let end = cdata.find("]]>").ok_or("Unterminated CDATA section")?;
+            stack.last_mut().unwrap().text.push_str(&cdata[..end]);
+            rest = &cdata[end + 3..];
+        } else if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = &comment[comment.find("-->").ok_or("Unterminated comment")? + 3..];
+        } else if rest.starts_with("<?") || rest.starts_with("<!") {
+            rest = &rest[rest.find('>').ok_or("Unterminated declaration")? + 1..];
+        } else if let Some(close) = rest.strip_prefix("</") {
+            let end = close.find('>').ok_or("Unterminated end tag")?;
+            let name = close[..end].trim();
+            let element = stack.pop().filter(|_| !stack.is_empty()).ok_or_else(|| format!("</{}> without a start tag", name))?;
+            if element.name != name {
+                return Err(format!("<{}> closed by </{}>", element.name, name));
+            }
+            finish(stack.last_mut().unwrap(), element);
+            rest = &close[end + 1..];
+        } else {
+            let (element, empty, after) = start_tag(&rest[1..])?;
+            if empty {
+                finish(stack.last_mut().unwrap(), element);
+            } else {
+                stack.push(element);
+            }
+            rest = after;
+        }
+    }
+    if stack.len() > 1 {
+        return Err(format!("<{}> isn't closed", stack.last().unwrap().name));
+    }
+    stack.pop().unwrap().children.pop().ok_or_else(|| "No root element".to_owned())
+}
+
+// `<name attr="value"...>` from after the '<': the element, whether it was empty (`/>`) and what follows
+fn start_tag(tag: &str) -> Result<(Element, bool, &str), String> {
+    let name_end = tag.find(|c: char| c.is_whitespace() || c == '>' || c == '/').ok_or("Unterminated start tag")?;
+    let mut element = Element { name: tag[..name_end].to_owned(), ..Default::default() };
+    let mut rest = &tag[name_end..];
+    loop {
+        rest = rest.trim_start();
+        if let Some(after) = rest.strip_prefix("/>") {
+            return Ok((element, true, after));
+        }
+        if let Some(after) = rest.strip_prefix('>') {
+            return Ok((element, false, after));
+        }
+        let eq = rest.find('=').ok_or_else(|| format!("Malformed attribute in <{}>", element.name))?;
+        let attr = rest[..eq].trim().to_owned();
+        rest = rest[eq + 1..].trim_start();
+        let quote = rest.chars().next().filter(|c| *c == '"' || *c == '\'').ok_or_else(|| format!("Unquoted attribute {} in <{}>", attr, element.name))?;
+        let end = rest[1..].find(quote).ok_or_else(|| format!("Unterminated attribute {} in <{}>", attr, element.name))?;
+        element.attrs.push((attr, unescape(&rest[1..end + 1])?));
+        rest = &rest[end + 2..];
+    }
+}
+
+fn finish(parent: &mut Element, mut element: Element) {
+    element.text = element.text.trim().to_owned();
+    parent.children.push(element);
+}
+
+fn append_text(element: &mut Element, text: &str) -> Result<(), String> {
+    if !text.trim().is_empty() {
+        element.text.push_str(&unescape(text)?);
+    }
+    Ok(())
+}
+
+fn unescape(text: &str) -> Result<String, String> {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        let end = rest[amp..].find(';').ok_or_else(|| format!("Unterminated entity in {:?}", text))? + amp;
+        let entity = &rest[amp + 1..end];
+        let c = match entity {
+            "lt" => '<',
+            "gt" => '>',
+            "amp" => '&',
+            "quot" => '"',
+            "apos" => '\'',
+            _ => entity.strip_prefix("#x").map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse))
+                .and_then(|code| code.ok())
+                .and_then(char::from_u32)
+                .ok_or_else(|| format!("Unknown entity &{};", entity))?,
+        };
+        out.push(c);
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
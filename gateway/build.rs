@@ -0,0 +1,14 @@
+fn main() {
+    // Sparkplug payloads are only needed with the `sparkplug` feature, don't make everyone else pay for protoc
+    #[cfg(feature = "sparkplug")]
+    compile_protos();
+}
+
+#[cfg(feature = "sparkplug")]
+fn compile_protos() {
+    println!("cargo:rerun-if-changed=proto/sparkplug_b.proto");
+
+    // SAFETY: build scripts are single threaded
+    unsafe { std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path().expect("vendored protoc")) };
+    prost_build::compile_protos(&["proto/sparkplug_b.proto"], &["proto"]).expect("compile sparkplug_b.proto");
+}
@@ -0,0 +1,88 @@
+// The PLC's audit log ([audit] path, see plc/src/audit.rs) followed from the gateway, for what passes its entries on
+// (kafka.rs, event_store.rs). Entries are read as the PLC appends them, from where the log ended when following
+// started, or from its start if the PLC hasn't created it yet. A log that got shorter was truncated or rotated and is
+// read from its start again.
+use std::fs::File;
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+
+/// One line of the audit log, "1792142564512 seq=41 user=alice session=3 write 'x' = 2"
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    pub ts_ms: u64,
+    pub seq: u32, // the command's sequence number in the PLC's ring
+    pub user: String,
+    pub session: u32,
+    pub action: String,
+}
+
+impl AuditEntry {
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(5, ' ');
+        Some(Self {
+            ts_ms: fields.next()?.parse().ok()?,
+            seq: fields.next()?.strip_prefix("seq=")?.parse().ok()?,
+            user: fields.next()?.strip_prefix("user=")?.to_owned(),
+            session: fields.next()?.strip_prefix("session=")?.parse().ok()?,
+            action: fields.next()?.to_owned(),
+        })
+    }
+}
+
+pub struct AuditLog {
+    path: String,
+    proto: &'static str, // who follows it, for the log
+    file: Option<BufReader<File>>,
+    position: u64,
+    partial: String, // a line the PLC is still writing
+}
+
+impl AuditLog {
+    pub fn follow(path: &str, proto: &'static str) -> Self {
+        let mut log = Self { path: path.to_owned(), proto, file: None, position: 0, partial: String::new() };
+        if let Ok(mut file) = File::open(path) {
+            log.position = file.seek(SeekFrom::End(0)).unwrap_or(0);
+            log.file = Some(BufReader::new(file));
+        }
+        log
+    }
+
+    /// What the PLC appended since the last call. Lines that aren't entries are logged and skipped
+    pub fn new_entries(&mut self) -> Vec<AuditEntry> {
+        let len = std::fs::metadata(&self.path).map(|metadata| metadata.len()).ok();
+        if len.is_none_or(|len| len < self.position) {
+            self.file = None;
+        }
+        if self.file.is_none() {
+            let Ok(file) = File::open(&self.path) else {
+                return Vec::new();
+            };
+            self.file = Some(BufReader::new(file));
+            self.position = 0;
+            self.partial.clear();
+        }
+        let file = self.file.as_mut().unwrap();
+        let mut entries = Vec::new();
+        loop {
+            match file.read_line(&mut self.partial) {
+                Ok(0) => break,
+                Ok(read) => {
+                    self.position += read as u64;
+                    if !self.partial.ends_with('\n') {
+                        break;
+                    }
+                    match AuditEntry::parse(self.partial.trim_end()) {
+                        Some(entry) => entries.push(entry),
+                        None => log::warn!("[{}] Skipping audit log line {:?}, not an audit entry", self.proto, self.partial.trim_end()),
+                    }
+                    self.partial.clear();
+                }
+                Err(e) => {
+                    log::warn!("[{}] Can't read audit log {}: {}", self.proto, self.path, e);
+                    self.file = None;
+                    break;
+                }
+            }
+        }
+        entries
+    }
+}
@@ -0,0 +1,1245 @@
+// BACnet/IP server ([bacnet] in gipop.toml) so building management systems can read and command the tags: every
+// [[bacnet.objects]] entry is an analog/binary input/output object of one Device object, with units, status flags and
+// change of value subscriptions. Writes to an output's present-value are tag writes audited as coming from
+// [bacnet] user, like MQTT commands.
+//
+// A subset of BACnet, what BMS front ends use for points like ours: Who-Is/I-Am, ReadProperty,
+// ReadPropertyMultiple, WriteProperty, SubscribeCOV with confirmed or unconfirmed notifications. No segmentation (an
+// answer that doesn't fit the requester's max APDU is aborted), no BBMD/foreign device registration and no command
+// prioritization: an output takes the last write whatever its priority, relinquishing (writing NULL) leaves the tag
+// alone, the priority-array reads all NULL. Confirmed notifications aren't retried.
+//
+// BACnet has no users either, whoever reaches the port acts as [bacnet] user. Leave it unset for read only access.
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use gipop_shm::{Action, Subscriber, TagDef, TagSample, TagType, Users};
+use tokio::net::UdpSocket;
+
+use crate::commands::{self, to_tag};
+use crate::config::{BacnetConfig, BacnetObjectType};
+
+const MAX_APDU: usize = 1476; // what fits in an Ethernet frame, what we accept and send at most
+const MAX_SUBSCRIPTIONS: usize = 256;
+const VENDOR_NAME: &str = "Gipop";
+const MODEL_NAME: &str = "gipop_gateway";
+const WILDCARD_INSTANCE: u32 = 4_194_303; // "this device" in a device object identifier
+
+// Object types
+const ANALOG_INPUT: u16 = 0;
+const ANALOG_OUTPUT: u16 = 1;
+const BINARY_INPUT: u16 = 3;
+const BINARY_OUTPUT: u16 = 4;
+const DEVICE: u16 = 8;
+
+// Property identifiers
+const APPLICATION_SOFTWARE_VERSION: u32 = 12;
+const APDU_TIMEOUT: u32 = 11;
+const COV_INCREMENT: u32 = 22;
+const DESCRIPTION: u32 = 28;
+const DEVICE_ADDRESS_BINDING: u32 = 30;
+const EVENT_STATE: u32 = 36;
+const FIRMWARE_REVISION: u32 = 44;
+const MAX_APDU_LENGTH_ACCEPTED: u32 = 62;
+const MODEL_NAME_PROPERTY: u32 = 70;
+const NUMBER_OF_APDU_RETRIES: u32 = 73;
+const OBJECT_IDENTIFIER: u32 = 75;
+const OBJECT_LIST: u32 = 76;
+const OBJECT_NAME: u32 = 77;
+const OBJECT_TYPE: u32 = 79;
+const OUT_OF_SERVICE: u32 = 81;
+const POLARITY: u32 = 84;
+const PRESENT_VALUE: u32 = 85;
+const PRIORITY_ARRAY: u32 = 87;
+const PROTOCOL_OBJECT_TYPES_SUPPORTED: u32 = 96;
+const PROTOCOL_SERVICES_SUPPORTED: u32 = 97;
+const PROTOCOL_VERSION: u32 = 98;
+const RELINQUISH_DEFAULT: u32 = 104;
+const SEGMENTATION_SUPPORTED: u32 = 107;
+const STATUS_FLAGS: u32 = 111;
+const SYSTEM_STATUS: u32 = 112;
+const UNITS: u32 = 117;
+const VENDOR_IDENTIFIER: u32 = 120;
+const VENDOR_NAME_PROPERTY: u32 = 121;
+const PROTOCOL_REVISION: u32 = 139;
+const DATABASE_REVISION: u32 = 155;
+const PROPERTY_LIST: u32 = 371;
+// ReadPropertyMultiple only, all three read every property here
+const ALL: u32 = 8;
+const OPTIONAL: u32 = 80;
+const REQUIRED: u32 = 105;
+
+// Services
+const CONFIRMED_COV_NOTIFICATION: u8 = 1;
+const SUBSCRIBE_COV: u8 = 5;
+const READ_PROPERTY: u8 = 12;
+const READ_PROPERTY_MULTIPLE: u8 = 14;
+const WRITE_PROPERTY: u8 = 15;
+const I_AM: u8 = 0;
+const UNCONFIRMED_COV_NOTIFICATION: u8 = 2;
+const WHO_IS: u8 = 8;
+
+// Error classes and codes
+const DEVICE_CLASS: u32 = 0;
+const OBJECT_CLASS: u32 = 1;
+const PROPERTY_CLASS: u32 = 2;
+const RESOURCES_CLASS: u32 = 3;
+const SERVICES_CLASS: u32 = 5;
+const DEVICE_BUSY: u32 = 3;
+const INVALID_DATA_TYPE: u32 = 9;
+const NO_SPACE_TO_ADD_LIST_ELEMENT: u32 = 19;
+const UNKNOWN_OBJECT: u32 = 31;
+const UNKNOWN_PROPERTY: u32 = 32;
+const VALUE_OUT_OF_RANGE: u32 = 37;
+const WRITE_ACCESS_DENIED: u32 = 40;
+const INVALID_ARRAY_INDEX: u32 = 42;
+const COV_SUBSCRIPTION_FAILED: u32 = 43;
+const PROPERTY_IS_NOT_AN_ARRAY: u32 = 50;
+
+// Reject and abort reasons
+const INVALID_TAG: u8 = 4;
+const UNRECOGNIZED_SERVICE: u8 = 9;
+const SEGMENTATION_NOT_SUPPORTED: u8 = 4;
+
+// Application tags
+const NULL: u8 = 0;
+const BOOLEAN: u8 = 1;
+const UNSIGNED: u8 = 2;
+const SIGNED: u8 = 3;
+const REAL: u8 = 4;
+const DOUBLE: u8 = 5;
+const CHARACTER_STRING: u8 = 7;
+const BIT_STRING: u8 = 8;
+const ENUMERATED: u8 = 9;
+const OBJECT_ID: u8 = 12;
+
+// Status flags, as the byte of the 4 bit string
+const FAULT: u8 = 0x40;
+
+/// Engineering units of a tag's unit symbol, for objects that don't set `units`
+fn units_for(unit: &str) -> u32 {
+    match unit {
+        "mA" => 2,
+        "A" => 3,
+        "V" => 5,
+        "Wh" => 18,
+        "kWh" => 19,
+        "Hz" => 27,
+        "%RH" => 29,
+        "lx" => 37,
+        "W" => 47,
+        "kW" => 48,
+        "Pa" => 53,
+        "kPa" => 54,
+        "bar" => 55,
+        "°C" => 62,
+        "K" => 63,
+        "°F" => 64,
+        "h" => 71,
+        "min" => 72,
+        "s" => 73,
+        "l/s" => 87,
+        "l/min" => 88,
+        "ppm" => 96,
+        "%" => 98,
+        "m³/h" | "m3/h" => 135,
+        "ms" => 159,
+        _ => 95, // no-units
+    }
+}
+
+impl BacnetObjectType {
+    fn number(self) -> u16 {
+        match self {
+            BacnetObjectType::AnalogInput => ANALOG_INPUT,
+            BacnetObjectType::AnalogOutput => ANALOG_OUTPUT,
+            BacnetObjectType::BinaryInput => BINARY_INPUT,
+            BacnetObjectType::BinaryOutput => BINARY_OUTPUT,
+        }
+    }
+
+    fn is_analog(self) -> bool {
+        matches!(self, BacnetObjectType::AnalogInput | BacnetObjectType::AnalogOutput)
+    }
+
+    fn is_output(self) -> bool {
+        matches!(self, BacnetObjectType::AnalogOutput | BacnetObjectType::BinaryOutput)
+    }
+}
+
+struct Object {
+    tag: usize,
+    ty: BacnetObjectType,
+    instance: u32,
+    units: u32,
+    cov_increment: f32,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Target {
+    Device,
+    Object(usize),
+}
+
+// Network number and MAC address of a device behind a BACnet router
+type Route = (u16, Vec<u8>);
+
+// Where a request came from. Requests through a BACnet router carry their route, the answer goes back to the router
+// with it as the destination.
+#[derive(Clone, PartialEq)]
+struct Peer {
+    addr: SocketAddr,
+    route: Option<Route>,
+}
+
+struct Subscription {
+    peer: Peer,
+    process: u32,
+    object: usize,
+    confirmed: bool,
+    expires: Option<Instant>, // None lasts until cancelled
+    sent: Option<(f32, u8)>,  // present value and status flags last notified
+}
+
+enum Response {
+    SimpleAck,
+    ComplexAck(Vec<u8>),
+    Error(u32, u32),
+    Reject(u8),
+    Abort(u8),
+}
+
+struct Server {
+    config: BacnetConfig,
+    table: Subscriber,
+    objects: Vec<Object>,
+    user: Option<String>, // who writes come from, None if outputs are read only
+    subscriptions: Vec<Subscription>,
+    invoke_id: u8,
+}
+
+/// Serves until the process ends, Err if the objects don't fit the tag table or the port can't be bound
+pub async fn serve(config: BacnetConfig, table: Subscriber, users: Users) -> Result<(), String> {
+    let objects = resolve_objects(&config, table.tags())?;
+    let user = match users.authorize(config.user.as_deref(), Action::WriteTag) {
+        Ok(_) => config.user.clone(),
+        Err(e) => {
+            if objects.iter().any(|object| object.ty.is_output()) {
+                log::warn!("[BACnet] Outputs are read only, {}", e);
+            }
+            None
+        }
+    };
+    let broadcast: SocketAddr = config.broadcast.parse().map_err(|e| format!("Invalid [bacnet] broadcast {}: {}", config.broadcast, e))?;
+    let socket = UdpSocket::bind(&config.listen).await.map_err(|e| format!("Failed to bind {}: {}", config.listen, e))?;
+    socket.set_broadcast(true).map_err(|e| e.to_string())?;
+    log::info!("[BACnet] Device {} with {} object(s) on {}", config.device_instance, objects.len(), config.listen);
+
+    let poll = Duration::from_millis(config.poll_ms.max(1));
+    let mut server = Server { config, table, objects, user, subscriptions: Vec::new(), invoke_id: 0 };
+    // announce ourselves, BMS front ends pick new devices up from unsolicited I-Ams
+    let i_am = server.i_am();
+    send(&socket, &[(bvlc(true, &npci(None, false), &i_am), broadcast)]).await;
+
+    let mut interval = tokio::time::interval(poll);
+    let mut buf = vec![0u8; 2048];
+    loop {
+        let packets = tokio::select! {
+            received = socket.recv_from(&mut buf) => match received {
+                Ok((len, from)) => server.handle(&buf[..len], from, broadcast),
+                Err(e) => {
+                    log::debug!("[BACnet] {}", e);
+                    Vec::new()
+                }
+            },
+            _ = interval.tick() => server.notify_changes(),
+        };
+        send(&socket, &packets).await;
+    }
+}
+
+async fn send(socket: &UdpSocket, packets: &[(Vec<u8>, SocketAddr)]) {
+    for (packet, to) in packets {
+        if let Err(e) = socket.send_to(packet, to).await {
+            log::debug!("[BACnet] Sending to {} failed: {}", to, e);
+        }
+    }
+}
+
+fn resolve_objects(config: &BacnetConfig, tags: &[TagDef]) -> Result<Vec<Object>, String> {
+    let defaults = |idx: usize, ty: BacnetObjectType, units: Option<u32>, cov_increment: Option<f32>| {
+        let tag = &tags[idx];
+        Object {
+            tag: idx,
+            ty,
+            instance: 0,
+            units: units.unwrap_or_else(|| units_for(&tag.unit)),
+            // 1% of the range the value normally stays in, or every change
+            cov_increment: cov_increment.unwrap_or_else(|| tag.eu_range.map(|(low, high)| ((high - low) / 100.0) as f32).unwrap_or(0.0)),
+        }
+    };
+
+    if config.objects.is_empty() {
+        // every tag, numbered by its index
+        return Ok(tags.iter().enumerate().map(|(idx, tag)| {
+            let ty = match (tag.ty == TagType::Bool, tag.writable()) {
+                (true, false) => BacnetObjectType::BinaryInput,
+                (true, true) => BacnetObjectType::BinaryOutput,
+                (false, false) => BacnetObjectType::AnalogInput,
+                (false, true) => BacnetObjectType::AnalogOutput,
+            };
+            Object { instance: idx as u32, ..defaults(idx, ty, None, None) }
+        }).collect());
+    }
+
+    let mut ids = HashSet::new();
+    config.objects.iter().map(|object| {
+        let idx = tags.iter().position(|tag| tag.name == object.tag)
+            .ok_or_else(|| format!("No tag named '{}' in [[bacnet.objects]]", object.tag))?;
+        if object.ty.is_output() && !tags[idx].writable() {
+            return Err(format!("'{}' isn't writable, it can't be an output in [[bacnet.objects]]", object.tag));
+        }
+        if object.instance >= WILDCARD_INSTANCE || !ids.insert((object.ty.number(), object.instance)) {
+            return Err(format!("[[bacnet.objects]] instance {} of '{}' is out of range or taken", object.instance, object.tag));
+        }
+        Ok(Object { instance: object.instance, ..defaults(idx, object.ty, object.units, object.cov_increment) })
+    }).collect()
+}
+
+impl Server {
+    /// Answers to one datagram
+    fn handle(&mut self, packet: &[u8], from: SocketAddr, broadcast: SocketAddr) -> Vec<(Vec<u8>, SocketAddr)> {
+        // BVLC: type, function, length, then for Forwarded-NPDU the address of whoever sent it originally
+        if packet.len() < 4 || packet[0] != 0x81 || u16::from_be_bytes([packet[2], packet[3]]) as usize != packet.len() {
+            return Vec::new();
+        }
+        let (npdu, addr) = match packet[1] {
+            0x0A | 0x0B => (&packet[4..], from),
+            0x04 if packet.len() >= 10 => {
+                let ip = Ipv4Addr::new(packet[4], packet[5], packet[6], packet[7]);
+                (&packet[10..], SocketAddr::new(IpAddr::V4(ip), u16::from_be_bytes([packet[8], packet[9]])))
+            }
+            _ => return Vec::new(),
+        };
+        let Some((route, apdu)) = parse_npdu(npdu) else { return Vec::new() };
+        let peer = Peer { addr, route };
+        if apdu.len() < 2 {
+            return Vec::new();
+        }
+
+        match apdu[0] >> 4 {
+            0 if apdu.len() >= 4 => {
+                let invoke_id = apdu[2];
+                let service = apdu[3];
+                let response = if apdu[0] & 0x08 != 0 {
+                    Response::Abort(SEGMENTATION_NOT_SUPPORTED)
+                } else {
+                    self.confirmed(service, &apdu[4..], &peer)
+                };
+                let max_apdu = max_apdu(apdu[1]);
+                let mut packets = vec![self.reply(&peer, invoke_id, service, response, max_apdu)];
+                if service == SUBSCRIBE_COV {
+                    // the initial notification, after the ack
+                    packets.extend(self.notify_changes());
+                }
+                packets
+            }
+            1 if apdu[1] == WHO_IS && self.is_asked(&apdu[2..]) => {
+                let i_am = self.i_am();
+                match &peer.route {
+                    Some(_) => vec![(bvlc(false, &npci(peer.route.as_ref(), false), &i_am), peer.addr)],
+                    None => vec![(bvlc(true, &npci(None, false), &i_am), broadcast)],
+                }
+            }
+            _ => Vec::new(), // acks of our confirmed notifications, other devices' broadcasts
+        }
+    }
+
+    fn confirmed(&mut self, service: u8, request: &[u8], peer: &Peer) -> Response {
+        let result = match service {
+            READ_PROPERTY => self.read_property(request),
+            READ_PROPERTY_MULTIPLE => self.read_property_multiple(request),
+            WRITE_PROPERTY => self.write_property(request),
+            SUBSCRIBE_COV => self.subscribe_cov(request, peer),
+            _ => return Response::Reject(UNRECOGNIZED_SERVICE),
+        };
+        result.unwrap_or(Response::Reject(INVALID_TAG))
+    }
+
+    fn reply(&self, peer: &Peer, invoke_id: u8, service: u8, response: Response, max_apdu: usize) -> (Vec<u8>, SocketAddr) {
+        let apdu = match response {
+            Response::SimpleAck => vec![0x20, invoke_id, service],
+            Response::ComplexAck(data) if data.len() + 3 <= max_apdu => [vec![0x30, invoke_id, service], data].concat(),
+            Response::ComplexAck(_) => vec![0x71, invoke_id, SEGMENTATION_NOT_SUPPORTED],
+            Response::Error(class, code) => {
+                let mut apdu = vec![0x50, invoke_id, service];
+                app_enumerated(&mut apdu, class);
+                app_enumerated(&mut apdu, code);
+                apdu
+            }
+            Response::Reject(reason) => vec![0x60, invoke_id, reason],
+            Response::Abort(reason) => vec![0x71, invoke_id, reason],
+        };
+        (bvlc(false, &npci(peer.route.as_ref(), false), &apdu), peer.addr)
+    }
+
+    // Who-Is without a range asks everyone, with one only the devices in it
+    fn is_asked(&self, request: &[u8]) -> bool {
+        let mut reader = Reader::new(request);
+        match (reader.context(0).and_then(unsigned), reader.context(1).and_then(unsigned)) {
+            (Some(low), Some(high)) => (low..=high).contains(&self.config.device_instance),
+            _ => true,
+        }
+    }
+
+    fn i_am(&self) -> Vec<u8> {
+        let mut apdu = vec![0x10, I_AM];
+        app_object_id(&mut apdu, DEVICE, self.config.device_instance);
+        app_unsigned(&mut apdu, MAX_APDU as u32);
+        app_enumerated(&mut apdu, 3); // no segmentation
+        app_unsigned(&mut apdu, self.config.vendor_id as u32);
+        apdu
+    }
+
+    fn target(&self, (ty, instance): (u16, u32)) -> Option<Target> {
+        if ty == DEVICE && (instance == self.config.device_instance || instance == WILDCARD_INSTANCE) {
+            return Some(Target::Device);
+        }
+        self.objects.iter().position(|object| object.ty.number() == ty && object.instance == instance).map(Target::Object)
+    }
+
+    fn object_id(&self, target: Target) -> (u16, u32) {
+        match target {
+            Target::Device => (DEVICE, self.config.device_instance),
+            Target::Object(i) => (self.objects[i].ty.number(), self.objects[i].instance),
+        }
+    }
+
+    fn read_property(&self, request: &[u8]) -> Option<Response> {
+        let mut reader = Reader::new(request);
+        let id = reader.context(0).and_then(object_id)?;
+        let property = reader.context(1).and_then(unsigned)?;
+        let index = optional(reader.context(2))?;
+
+        let Some(target) = self.target(id) else { return Some(Response::Error(OBJECT_CLASS, UNKNOWN_OBJECT)) };
+        let value = match self.read(target, property, index) {
+            Ok(value) => value,
+            Err((class, code)) => return Some(Response::Error(class, code)),
+        };
+        let mut ack = Vec::new();
+        let (ty, instance) = self.object_id(target);
+        context_object_id(&mut ack, 0, ty, instance);
+        context_unsigned(&mut ack, 1, property);
+        if let Some(index) = index {
+            context_unsigned(&mut ack, 2, index);
+        }
+        opening(&mut ack, 3);
+        ack.extend(value);
+        closing(&mut ack, 3);
+        Some(Response::ComplexAck(ack))
+    }
+
+    fn read_property_multiple(&self, request: &[u8]) -> Option<Response> {
+        let mut reader = Reader::new(request);
+        if reader.is_empty() {
+            return None; // at least one object
+        }
+        let mut ack = Vec::new();
+        while !reader.is_empty() {
+            let id = reader.context(0).and_then(object_id)?;
+            reader.opening(1)?;
+            let mut properties = Vec::new();
+            while !reader.closing(1) {
+                let property = reader.context(0).and_then(unsigned)?;
+                let index = optional(reader.context(1))?;
+                properties.push((property, index));
+            }
+
+            let target = self.target(id);
+            context_object_id(&mut ack, 0, id.0, id.1);
+            opening(&mut ack, 1);
+            for (property, index) in properties {
+                let expanded = match target {
+                    Some(target) if matches!(property, ALL | REQUIRED | OPTIONAL) => {
+                        [OBJECT_IDENTIFIER, OBJECT_NAME, OBJECT_TYPE].into_iter().chain(self.properties(target)).map(|p| (p, None)).collect()
+                    }
+                    _ => vec![(property, index)],
+                };
+                for (property, index) in expanded {
+                    context_unsigned(&mut ack, 2, property);
+                    if let Some(index) = index {
+                        context_unsigned(&mut ack, 3, index);
+                    }
+                    let value = target.ok_or((OBJECT_CLASS, UNKNOWN_OBJECT)).and_then(|target| self.read(target, property, index));
+                    match value {
+                        Ok(value) => {
+                            opening(&mut ack, 4);
+                            ack.extend(value);
+                            closing(&mut ack, 4);
+                        }
+                        Err((class, code)) => {
+                            opening(&mut ack, 5);
+                            app_enumerated(&mut ack, class);
+                            app_enumerated(&mut ack, code);
+                            closing(&mut ack, 5);
+                        }
+                    }
+                }
+            }
+            closing(&mut ack, 1);
+        }
+        Some(Response::ComplexAck(ack))
+    }
+
+    // The properties of `target` besides identifier, name and type, in property-list order
+    fn properties(&self, target: Target) -> Vec<u32> {
+        match target {
+            Target::Device => vec![
+                SYSTEM_STATUS, VENDOR_NAME_PROPERTY, VENDOR_IDENTIFIER, MODEL_NAME_PROPERTY, FIRMWARE_REVISION,
+                APPLICATION_SOFTWARE_VERSION, PROTOCOL_VERSION, PROTOCOL_REVISION, PROTOCOL_SERVICES_SUPPORTED,
+                PROTOCOL_OBJECT_TYPES_SUPPORTED, OBJECT_LIST, MAX_APDU_LENGTH_ACCEPTED, SEGMENTATION_SUPPORTED,
+                APDU_TIMEOUT, NUMBER_OF_APDU_RETRIES, DEVICE_ADDRESS_BINDING, DATABASE_REVISION, PROPERTY_LIST,
+            ],
+            Target::Object(i) => {
+                let ty = self.objects[i].ty;
+                let mut properties = vec![PRESENT_VALUE, DESCRIPTION, STATUS_FLAGS, EVENT_STATE, OUT_OF_SERVICE];
+                if ty.is_analog() {
+                    properties.extend([UNITS, COV_INCREMENT]);
+                } else {
+                    properties.push(POLARITY);
+                }
+                if ty.is_output() {
+                    properties.extend([PRIORITY_ARRAY, RELINQUISH_DEFAULT]);
+                }
+                properties.push(PROPERTY_LIST);
+                properties
+            }
+        }
+    }
+
+    // One property's value, application tagged. Err is an error class and code.
+    fn read(&self, target: Target, property: u32, index: Option<u32>) -> Result<Vec<u8>, (u32, u32)> {
+        let is_array = matches!(property, OBJECT_LIST | PRIORITY_ARRAY | PROPERTY_LIST);
+        if index.is_some() && !is_array {
+            return Err((PROPERTY_CLASS, PROPERTY_IS_NOT_AN_ARRAY));
+        }
+        let known = matches!(property, OBJECT_IDENTIFIER | OBJECT_NAME | OBJECT_TYPE) || self.properties(target).contains(&property);
+        if !known {
+            return Err((PROPERTY_CLASS, UNKNOWN_PROPERTY));
+        }
+
+        let (ty, instance) = self.object_id(target);
+        let mut out = Vec::new();
+        match property {
+            OBJECT_IDENTIFIER => app_object_id(&mut out, ty, instance),
+            OBJECT_TYPE => app_enumerated(&mut out, ty as u32),
+            OBJECT_NAME => app_string(&mut out, match target {
+                Target::Device => &self.config.device_name,
+                Target::Object(i) => &self.table.tags()[self.objects[i].tag].name,
+            }),
+            PROPERTY_LIST => {
+                // everything but the three every object has
+                let elements = self.properties(target).into_iter().map(|p| {
+                    let mut element = Vec::new();
+                    app_enumerated(&mut element, p);
+                    element
+                });
+                return array(elements.collect(), index);
+            }
+            _ => return match target {
+                Target::Device => self.read_device(property, index),
+                Target::Object(i) => self.read_object(i, property, index),
+            },
+        }
+        Ok(out)
+    }
+
+    fn read_device(&self, property: u32, index: Option<u32>) -> Result<Vec<u8>, (u32, u32)> {
+        let mut out = Vec::new();
+        match property {
+            SYSTEM_STATUS => app_enumerated(&mut out, 0), // operational
+            VENDOR_NAME_PROPERTY => app_string(&mut out, VENDOR_NAME),
+            VENDOR_IDENTIFIER => app_unsigned(&mut out, self.config.vendor_id as u32),
+            MODEL_NAME_PROPERTY => app_string(&mut out, MODEL_NAME),
+            FIRMWARE_REVISION | APPLICATION_SOFTWARE_VERSION => app_string(&mut out, env!("CARGO_PKG_VERSION")),
+            PROTOCOL_VERSION => app_unsigned(&mut out, 1),
+            PROTOCOL_REVISION => app_unsigned(&mut out, 14),
+            PROTOCOL_SERVICES_SUPPORTED => {
+                let supported = [SUBSCRIBE_COV, READ_PROPERTY, READ_PROPERTY_MULTIPLE, WRITE_PROPERTY, 34]; // 34 who-is
+                app_bits(&mut out, &(0..48).map(|bit| supported.contains(&bit)).collect::<Vec<_>>());
+            }
+            PROTOCOL_OBJECT_TYPES_SUPPORTED => {
+                let supported = [ANALOG_INPUT, ANALOG_OUTPUT, BINARY_INPUT, BINARY_OUTPUT, DEVICE];
+                app_bits(&mut out, &(0..60).map(|bit| supported.contains(&bit)).collect::<Vec<_>>());
+            }
+            OBJECT_LIST => {
+                let elements = std::iter::once(Target::Device).chain((0..self.objects.len()).map(Target::Object)).map(|target| {
+                    let (ty, instance) = self.object_id(target);
+                    let mut element = Vec::new();
+                    app_object_id(&mut element, ty, instance);
+                    element
+                });
+                return array(elements.collect(), index);
+            }
+            MAX_APDU_LENGTH_ACCEPTED => app_unsigned(&mut out, MAX_APDU as u32),
+            SEGMENTATION_SUPPORTED => app_enumerated(&mut out, 3), // no segmentation
+            APDU_TIMEOUT => app_unsigned(&mut out, 3000),
+            NUMBER_OF_APDU_RETRIES => app_unsigned(&mut out, 0),
+            DEVICE_ADDRESS_BINDING => {} // an empty list, we don't bind to other devices
+            DATABASE_REVISION => app_unsigned(&mut out, 0),
+            _ => unreachable!("checked against properties()"),
+        }
+        Ok(out)
+    }
+
+    fn read_object(&self, i: usize, property: u32, index: Option<u32>) -> Result<Vec<u8>, (u32, u32)> {
+        let object = &self.objects[i];
+        let tag = &self.table.tags()[object.tag];
+        let sample = self.table.read_sample(object.tag);
+        let mut out = Vec::new();
+        match property {
+            PRESENT_VALUE | RELINQUISH_DEFAULT => present_value(&mut out, object.ty, &sample),
+            DESCRIPTION => app_string(&mut out, tag.texts.first().map(|text| text.description.as_str()).unwrap_or_default()),
+            STATUS_FLAGS => app_status_flags(&mut out, status_flags(&sample)),
+            EVENT_STATE => app_enumerated(&mut out, 0), // normal, alarms are the alarm tags' business
+            OUT_OF_SERVICE => app_boolean(&mut out, false),
+            UNITS => app_enumerated(&mut out, object.units),
+            COV_INCREMENT => app_real(&mut out, object.cov_increment),
+            POLARITY => app_enumerated(&mut out, 0), // normal
+            PRIORITY_ARRAY => return array(vec![vec![NULL << 4]; 16], index),
+            _ => unreachable!("checked against properties()"),
+        }
+        Ok(out)
+    }
+
+    fn write_property(&mut self, request: &[u8]) -> Option<Response> {
+        let mut reader = Reader::new(request);
+        let id = reader.context(0).and_then(object_id)?;
+        let property = reader.context(1).and_then(unsigned)?;
+        let index = reader.context(2);
+        reader.opening(3)?;
+        let value = reader.next()?;
+        reader.closing(3).then_some(())?;
+        // priority (context 4) doesn't matter, see the top of the file
+
+        let Some(target) = self.target(id) else { return Some(Response::Error(OBJECT_CLASS, UNKNOWN_OBJECT)) };
+        let Target::Object(i) = target else { return Some(Response::Error(PROPERTY_CLASS, WRITE_ACCESS_DENIED)) };
+        let object = &self.objects[i];
+        if index.is_some() {
+            return Some(Response::Error(PROPERTY_CLASS, PROPERTY_IS_NOT_AN_ARRAY));
+        }
+        if property != PRESENT_VALUE || !object.ty.is_output() {
+            let known = matches!(property, OBJECT_IDENTIFIER | OBJECT_NAME | OBJECT_TYPE) || self.properties(target).contains(&property);
+            return Some(Response::Error(PROPERTY_CLASS, if known { WRITE_ACCESS_DENIED } else { UNKNOWN_PROPERTY }));
+        }
+        let Some(user) = &self.user else { return Some(Response::Error(PROPERTY_CLASS, WRITE_ACCESS_DENIED)) };
+
+        let number = match (value.class, value.number, object.ty.is_analog()) {
+            (Class::Application, NULL, _) => {
+                log::debug!("[BACnet] Ignoring relinquish of {:?} {}", object.ty, object.instance);
+                return Some(Response::SimpleAck);
+            }
+            (Class::Application, REAL, true) => f32::from_be_bytes(value.data.try_into().ok()?) as f64,
+            (Class::Application, DOUBLE, true) => f64::from_be_bytes(value.data.try_into().ok()?),
+            (Class::Application, UNSIGNED, true) => unsigned(value.data)? as f64,
+            (Class::Application, SIGNED, true) => signed(value.data)? as f64,
+            (Class::Application, ENUMERATED, false) => unsigned(value.data)? as f64,
+            (Class::Application, BOOLEAN, false) => value.lvt as f64,
+            _ => return Some(Response::Error(PROPERTY_CLASS, INVALID_DATA_TYPE)),
+        };
+        let tag = &self.table.tags()[object.tag];
+        if !object.ty.is_analog() && number > 1.0 {
+            return Some(Response::Error(PROPERTY_CLASS, VALUE_OUT_OF_RANGE));
+        }
+        let Some(value) = to_tag(tag.ty, number).filter(|value| tag.accepts(*value)) else {
+            return Some(Response::Error(PROPERTY_CLASS, VALUE_OUT_OF_RANGE));
+        };
+        Some(match commands::write_tag(&self.table, "BACnet", user, object.tag, value) {
+            Ok(_) => Response::SimpleAck,
+            Err(_) => Response::Error(DEVICE_CLASS, DEVICE_BUSY),
+        })
+    }
+
+    fn subscribe_cov(&mut self, request: &[u8], peer: &Peer) -> Option<Response> {
+        let mut reader = Reader::new(request);
+        let process = reader.context(0).and_then(unsigned)?;
+        let id = reader.context(1).and_then(object_id)?;
+        let confirmed = reader.context(2).map(|data| data.first().is_some_and(|&b| b != 0));
+        let lifetime = optional(reader.context(3))?;
+
+        let object = match self.target(id) {
+            Some(Target::Object(i)) => i,
+            Some(Target::Device) => return Some(Response::Error(SERVICES_CLASS, COV_SUBSCRIPTION_FAILED)),
+            None => return Some(Response::Error(OBJECT_CLASS, UNKNOWN_OBJECT)),
+        };
+        self.subscriptions.retain(|s| !(s.peer == *peer && s.process == process && s.object == object));
+        // neither flag nor lifetime cancels
+        if confirmed.is_none() && lifetime.is_none() {
+            return Some(Response::SimpleAck);
+        }
+        if self.subscriptions.len() >= MAX_SUBSCRIPTIONS {
+            return Some(Response::Error(RESOURCES_CLASS, NO_SPACE_TO_ADD_LIST_ELEMENT));
+        }
+        let expires = lifetime.filter(|&secs| secs > 0).map(|secs| Instant::now() + Duration::from_secs(secs as u64));
+        self.subscriptions.push(Subscription { peer: peer.clone(), process, object, confirmed: confirmed.unwrap_or(false), expires, sent: None });
+        log::debug!("[BACnet] {} subscribed to {:?} {}", peer.addr, self.objects[object].ty, self.objects[object].instance);
+        Some(Response::SimpleAck)
+    }
+
+    /// Notifications for the subscriptions whose object changed since they were last notified (or never were)
+    fn notify_changes(&mut self) -> Vec<(Vec<u8>, SocketAddr)> {
+        let now = Instant::now();
+        self.subscriptions.retain(|s| s.expires.is_none_or(|expires| expires > now));
+
+        let mut packets = Vec::new();
+        for s in 0..self.subscriptions.len() {
+            let object = &self.objects[self.subscriptions[s].object];
+            let sample = self.table.read_sample(object.tag);
+            let current = (sample.value.as_f64() as f32, status_flags(&sample));
+            let changed = match self.subscriptions[s].sent {
+                None => true,
+                Some((value, flags)) if object.ty.is_analog() => {
+                    flags != current.1 || (current.0 - value).abs() >= object.cov_increment.max(f32::MIN_POSITIVE) || value.is_nan() != current.0.is_nan()
+                }
+                Some((value, flags)) => flags != current.1 || (value != 0.0) != (current.0 != 0.0),
+            };
+            if changed {
+                self.subscriptions[s].sent = Some(current);
+                packets.push(self.notification(s, &sample, now));
+            }
+        }
+        packets
+    }
+
+    fn notification(&mut self, s: usize, sample: &TagSample, now: Instant) -> (Vec<u8>, SocketAddr) {
+        let subscription = &self.subscriptions[s];
+        let object = &self.objects[subscription.object];
+        let mut apdu = if subscription.confirmed {
+            self.invoke_id = self.invoke_id.wrapping_add(1);
+            vec![0x00, 0x05, self.invoke_id, CONFIRMED_COV_NOTIFICATION]
+        } else {
+            vec![0x10, UNCONFIRMED_COV_NOTIFICATION]
+        };
+        context_unsigned(&mut apdu, 0, subscription.process);
+        context_object_id(&mut apdu, 1, DEVICE, self.config.device_instance);
+        context_object_id(&mut apdu, 2, object.ty.number(), object.instance);
+        let remaining = subscription.expires.map(|expires| expires.saturating_duration_since(now).as_secs()).unwrap_or(0);
+        context_unsigned(&mut apdu, 3, remaining as u32);
+        opening(&mut apdu, 4);
+        context_unsigned(&mut apdu, 0, PRESENT_VALUE);
+        opening(&mut apdu, 2);
+        present_value(&mut apdu, object.ty, sample);
+        closing(&mut apdu, 2);
+        context_unsigned(&mut apdu, 0, STATUS_FLAGS);
+        opening(&mut apdu, 2);
+        app_status_flags(&mut apdu, status_flags(sample));
+        closing(&mut apdu, 2);
+        closing(&mut apdu, 4);
+        (bvlc(false, &npci(subscription.peer.route.as_ref(), subscription.confirmed), &apdu), subscription.peer.addr)
+    }
+}
+
+fn present_value(out: &mut Vec<u8>, ty: BacnetObjectType, sample: &TagSample) {
+    if ty.is_analog() {
+        app_real(out, sample.value.as_f64() as f32);
+    } else {
+        app_enumerated(out, (sample.value.as_f64() != 0.0) as u32); // active/inactive
+    }
+}
+
+// Fault while the value isn't good (PLC gone, sensor broken)
+fn status_flags(sample: &TagSample) -> u8 {
+    if sample.quality.is_good() { 0 } else { FAULT }
+}
+
+// Max APDU accepted, from the low nibble of a confirmed request's second byte
+fn max_apdu(byte: u8) -> usize {
+    match byte & 0x0F {
+        0 => 50,
+        1 => 128,
+        2 => 206,
+        3 => 480,
+        4 => 1024,
+        _ => MAX_APDU,
+    }
+}
+
+// An array property, or one element of it (1-based, 0 is the length)
+fn array(elements: Vec<Vec<u8>>, index: Option<u32>) -> Result<Vec<u8>, (u32, u32)> {
+    match index {
+        None => Ok(elements.concat()),
+        Some(0) => {
+            let mut out = Vec::new();
+            app_unsigned(&mut out, elements.len() as u32);
+            Ok(out)
+        }
+        Some(i) => elements.into_iter().nth(i as usize - 1).ok_or((PROPERTY_CLASS, INVALID_ARRAY_INDEX)),
+    }
+}
+
+// -- BVLC and NPDU
+
+fn bvlc(broadcast: bool, npdu: &[u8], apdu: &[u8]) -> Vec<u8> {
+    let len = 4 + npdu.len() + apdu.len();
+    let mut packet = vec![0x81, if broadcast { 0x0B } else { 0x0A }, (len >> 8) as u8, len as u8];
+    packet.extend_from_slice(npdu);
+    packet.extend_from_slice(apdu);
+    packet
+}
+
+fn npci(route: Option<&Route>, expecting_reply: bool) -> Vec<u8> {
+    let mut npdu = vec![0x01, if expecting_reply { 0x04 } else { 0x00 }];
+    if let Some((network, mac)) = route {
+        npdu[1] |= 0x20;
+        npdu.extend(network.to_be_bytes());
+        npdu.push(mac.len() as u8);
+        npdu.extend(mac);
+        npdu.push(0xFF); // hop count
+    }
+    npdu
+}
+
+// The source route (if it came through a router) and the APDU. None for network layer messages and anything
+// addressed to another network, we aren't a router.
+fn parse_npdu(npdu: &[u8]) -> Option<(Option<Route>, &[u8])> {
+    let (&version, rest) = npdu.split_first()?;
+    let (&control, mut rest) = rest.split_first()?;
+    if version != 0x01 || control & 0x80 != 0 {
+        return None;
+    }
+    let mut destination = None;
+    if control & 0x20 != 0 {
+        let network = u16::from_be_bytes([*rest.first()?, *rest.get(1)?]);
+        let len = *rest.get(2)? as usize;
+        destination = Some(network);
+        rest = rest.get(3 + len..)?;
+    }
+    let mut source = None;
+    if control & 0x08 != 0 {
+        let network = u16::from_be_bytes([*rest.first()?, *rest.get(1)?]);
+        let len = *rest.get(2)? as usize;
+        source = Some((network, rest.get(3..3 + len)?.to_vec()));
+        rest = rest.get(3 + len..)?;
+    }
+    if let Some(network) = destination {
+        rest = rest.get(1..)?; // hop count
+        if network != 0xFFFF {
+            return None;
+        }
+    }
+    Some((source, rest))
+}
+
+// -- Tag encoding
+
+fn tag_header(out: &mut Vec<u8>, number: u8, context: bool, len: usize) {
+    let class = if context { 0x08 } else { 0x00 };
+    match len {
+        0..=4 => out.push(number << 4 | class | len as u8),
+        5..=253 => out.extend([number << 4 | class | 5, len as u8]),
+        254..=65535 => {
+            out.extend([number << 4 | class | 5, 254]);
+            out.extend((len as u16).to_be_bytes());
+        }
+        _ => {
+            out.extend([number << 4 | class | 5, 255]);
+            out.extend((len as u32).to_be_bytes());
+        }
+    }
+}
+
+// Unsigned in as few bytes as it takes
+fn unsigned_bytes(n: u32) -> Vec<u8> {
+    let bytes = n.to_be_bytes();
+    let skip = bytes.iter().take(3).take_while(|&&b| b == 0).count();
+    bytes[skip..].to_vec()
+}
+
+fn app_unsigned(out: &mut Vec<u8>, n: u32) {
+    let bytes = unsigned_bytes(n);
+    tag_header(out, UNSIGNED, false, bytes.len());
+    out.extend(bytes);
+}
+
+fn app_enumerated(out: &mut Vec<u8>, n: u32) {
+    let bytes = unsigned_bytes(n);
+    tag_header(out, ENUMERATED, false, bytes.len());
+    out.extend(bytes);
+}
+
+fn app_boolean(out: &mut Vec<u8>, value: bool) {
+    out.push(BOOLEAN << 4 | value as u8); // the value is the length
+}
+
+fn app_real(out: &mut Vec<u8>, value: f32) {
+    tag_header(out, REAL, false, 4);
+    out.extend(value.to_be_bytes());
+}
+
+fn app_string(out: &mut Vec<u8>, text: &str) {
+    tag_header(out, CHARACTER_STRING, false, text.len() + 1);
+    out.push(0); // UTF-8
+    out.extend(text.as_bytes());
+}
+
+fn app_bits(out: &mut Vec<u8>, bits: &[bool]) {
+    let mut bytes = vec![0u8; bits.len().div_ceil(8)];
+    for (i, _) in bits.iter().enumerate().filter(|(_, bit)| **bit) {
+        bytes[i / 8] |= 0x80 >> (i % 8);
+    }
+    tag_header(out, BIT_STRING, false, bytes.len() + 1);
+    out.push((bytes.len() * 8 - bits.len()) as u8); // unused bits in the last byte
+    out.extend(bytes);
+}
+
+// in-alarm, fault, overridden, out-of-service
+fn app_status_flags(out: &mut Vec<u8>, flags: u8) {
+    tag_header(out, BIT_STRING, false, 2);
+    out.extend([4, flags]);
+}
+
+fn object_id_bytes(ty: u16, instance: u32) -> [u8; 4] {
+    ((ty as u32) << 22 | instance & 0x3F_FFFF).to_be_bytes()
+}
+
+fn app_object_id(out: &mut Vec<u8>, ty: u16, instance: u32) {
+    tag_header(out, OBJECT_ID, false, 4);
+    out.extend(object_id_bytes(ty, instance));
+}
+
+fn context_unsigned(out: &mut Vec<u8>, number: u8, n: u32) {
+    let bytes = unsigned_bytes(n);
+    tag_header(out, number, true, bytes.len());
+    out.extend(bytes);
+}
+
+fn context_object_id(out: &mut Vec<u8>, number: u8, ty: u16, instance: u32) {
+    tag_header(out, number, true, 4);
+    out.extend(object_id_bytes(ty, instance));
+}
+
+fn opening(out: &mut Vec<u8>, number: u8) {
+    out.push(number << 4 | 0x0E);
+}
+
+fn closing(out: &mut Vec<u8>, number: u8) {
+    out.push(number << 4 | 0x0F);
+}
+
+// -- Tag decoding
+
+#[derive(Clone, Copy, PartialEq)]
+enum Class {
+    Application,
+    Context,
+    Opening,
+    Closing,
+}
+
+#[derive(Clone, Copy)]
+struct Tag<'a> {
+    number: u8,
+    class: Class,
+    lvt: u32, // length, or the value of an application tagged boolean
+    data: &'a [u8],
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    // The next tag without taking it, and what's left after it
+    fn peek(&self) -> Option<(Tag<'a>, &'a [u8])> {
+        let (&first, mut rest) = self.data.split_first()?;
+        let mut number = first >> 4;
+        if number == 0x0F {
+            number = *rest.first()?;
+            rest = &rest[1..];
+        }
+        let lvt = first & 0x07;
+        let class = match (first & 0x08 != 0, lvt) {
+            (true, 6) => Class::Opening,
+            (true, 7) => Class::Closing,
+            (true, _) => Class::Context,
+            (false, _) => Class::Application,
+        };
+        if matches!(class, Class::Opening | Class::Closing) || (class == Class::Application && number == BOOLEAN) {
+            return Some((Tag { number, class, lvt: lvt as u32, data: &[] }, rest));
+        }
+        let len = match lvt {
+            5 => {
+                let (&ext, after) = rest.split_first()?;
+                rest = after;
+                match ext {
+                    254 => {
+                        let len = u16::from_be_bytes(rest.get(..2)?.try_into().ok()?) as usize;
+                        rest = &rest[2..];
+                        len
+                    }
+                    255 => {
+                        let len = u32::from_be_bytes(rest.get(..4)?.try_into().ok()?) as usize;
+                        rest = &rest[4..];
+                        len
+                    }
+                    _ => ext as usize,
+                }
+            }
+            _ => lvt as usize,
+        };
+        Some((Tag { number, class, lvt: len as u32, data: rest.get(..len)? }, &rest[len..]))
+    }
+
+    fn next(&mut self) -> Option<Tag<'a>> {
+        let (tag, rest) = self.peek()?;
+        self.data = rest;
+        Some(tag)
+    }
+
+    // The content of context tag `number` if that's what comes next (optional parameters)
+    fn context(&mut self, number: u8) -> Option<&'a [u8]> {
+        let (tag, rest) = self.peek()?;
+        (tag.class == Class::Context && tag.number == number).then(|| {
+            self.data = rest;
+            tag.data
+        })
+    }
+
+    fn opening(&mut self, number: u8) -> Option<()> {
+        let (tag, rest) = self.peek()?;
+        (tag.class == Class::Opening && tag.number == number).then(|| self.data = rest)
+    }
+
+    // Takes the closing tag if it's next
+    fn closing(&mut self, number: u8) -> bool {
+        match self.peek() {
+            Some((tag, rest)) if tag.class == Class::Closing && tag.number == number => {
+                self.data = rest;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+fn unsigned(data: &[u8]) -> Option<u32> {
+    (1..=4).contains(&data.len()).then(|| data.iter().fold(0u32, |n, &b| n << 8 | b as u32))
+}
+
+// An optional unsigned parameter: Some(None) if it's absent, None if it's there but isn't an unsigned
+fn optional(data: Option<&[u8]>) -> Option<Option<u32>> {
+    match data {
+        None => Some(None),
+        Some(data) => unsigned(data).map(Some),
+    }
+}
+
+fn signed(data: &[u8]) -> Option<i32> {
+    let n = unsigned(data)?;
+    let shift = 32 - 8 * data.len() as u32;
+    Some(((n << shift) as i32) >> shift)
+}
+
+fn object_id(data: &[u8]) -> Option<(u16, u32)> {
+    let n = u32::from_be_bytes(data.try_into().ok()?);
+    Some(((n >> 22) as u16, n & 0x3F_FFFF))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::tests::table;
+    use gipop_shm::ring::{ITEM_AUDIT, ITEM_TAG_WRITE};
+    use gipop_shm::{Publisher, TagValue, TAG_WRITABLE};
+
+    const SUPPLY: usize = 0; // analog input 0
+    const SETPOINT: usize = 1; // analog output 1
+    const PUMP: usize = 2; // binary output 2
+
+    fn from() -> SocketAddr {
+        "10.0.0.9:47808".parse().unwrap()
+    }
+
+    fn broadcast() -> SocketAddr {
+        "10.0.0.255:47808".parse().unwrap()
+    }
+
+    // Device 1 with every tag as an object, outputs written as `user`
+    fn server(name: &str, user: Option<&str>) -> (Publisher, Server) {
+        let tags = [
+            TagDef::new("supply temp", TagType::Float32, 0),
+            TagDef::new("setpoint", TagType::Float32, TAG_WRITABLE),
+            TagDef::new("pump cmd", TagType::Bool, TAG_WRITABLE),
+        ];
+        let (mut plc, table) = table(name, &tags);
+        plc.write(SUPPLY, TagValue::Float32(21.5)).unwrap();
+        let config = BacnetConfig::default();
+        let objects = resolve_objects(&config, &tags).unwrap();
+        let server = Server { config, table, objects, user: user.map(str::to_owned), subscriptions: Vec::new(), invoke_id: 0 };
+        (plc, server)
+    }
+
+    // Confirmed request `service` from a client taking 1476 octet APDUs, as a datagram
+    fn confirmed(invoke_id: u8, service: u8, request: &[u8]) -> Vec<u8> {
+        bvlc(false, &npci(None, true), &[&[0x00, 0x05, invoke_id, service], request].concat())
+    }
+
+    // The APDU of the one answer to `packet`
+    fn answer(server: &mut Server, packet: &[u8]) -> Vec<u8> {
+        let packets = server.handle(packet, from(), broadcast());
+        assert_eq!(packets.len(), 1, "answers to {:02x?}", packet);
+        let (packet, to) = &packets[0];
+        assert_eq!(*to, from());
+        assert_eq!(packet[..6], [0x81, 0x0A, 0x00, packet.len() as u8, 0x01, 0x00]);
+        packet[6..].to_vec()
+    }
+
+    fn read_request(ty: u16, instance: u32, property: u8) -> Vec<u8> {
+        [&[0x0C][..], &object_id_bytes(ty, instance), &[0x19, property]].concat()
+    }
+
+    fn write_request(ty: u16, instance: u32, value: &[u8]) -> Vec<u8> {
+        [&[0x0C][..], &object_id_bytes(ty, instance), &[0x19, PRESENT_VALUE as u8, 0x3E], value, &[0x3F]].concat()
+    }
+
+    #[test]
+    fn who_is_and_i_am() {
+        let (_plc, mut server) = server("bacnet_who_is", None);
+        // as a BMS broadcasts it
+        let who_is = [0x81, 0x0B, 0x00, 0x08, 0x01, 0x00, 0x10, 0x08];
+        let packets = server.handle(&who_is, from(), broadcast());
+        // device 1, max APDU 1476, no segmentation, vendor 0
+        let i_am = vec![
+            0x81, 0x0B, 0x00, 0x14, 0x01, 0x00, 0x10, 0x00, 0xC4, 0x02, 0x00, 0x00, 0x01, 0x22, 0x05, 0xC4, 0x91, 0x03, 0x21, 0x00,
+        ];
+        assert_eq!(packets, [(i_am, broadcast())]);
+
+        // a range with us in it, and one without
+        let ranged = |low: u8, high: u8| bvlc(true, &npci(None, false), &[0x10, WHO_IS, 0x09, low, 0x19, high]);
+        assert_eq!(server.handle(&ranged(0, 10), from(), broadcast()).len(), 1);
+        assert!(server.handle(&ranged(5, 10), from(), broadcast()).is_empty());
+        // I-Ams of other devices aren't answered
+        assert!(server.handle(&bvlc(true, &npci(None, false), &server.i_am()), from(), broadcast()).is_empty());
+    }
+
+    #[test]
+    fn read_property_of_objects_and_device() {
+        let (_plc, mut server) = server("bacnet_read", None);
+        let packet = confirmed(7, READ_PROPERTY, &read_request(ANALOG_INPUT, SUPPLY as u32, PRESENT_VALUE as u8));
+        let mut ack = vec![0x30, 7, READ_PROPERTY, 0x0C];
+        ack.extend(object_id_bytes(ANALOG_INPUT, SUPPLY as u32));
+        ack.extend([0x19, PRESENT_VALUE as u8, 0x3E, 0x44]);
+        ack.extend(21.5f32.to_be_bytes());
+        ack.push(0x3F);
+        assert_eq!(answer(&mut server, &packet), ack);
+
+        // object name of the device, wildcard instance
+        let packet = confirmed(8, READ_PROPERTY, &read_request(DEVICE, WILDCARD_INSTANCE, OBJECT_NAME as u8));
+        assert!(answer(&mut server, &packet).ends_with(&[0x3E, 0x75, 0x06, 0x00, b'G', b'i', b'p', b'o', b'p', 0x3F]));
+
+        // request, error class and code
+        let cases = [
+            (read_request(ANALOG_INPUT, 9, PRESENT_VALUE as u8), OBJECT_CLASS, UNKNOWN_OBJECT),
+            (read_request(ANALOG_INPUT, SUPPLY as u32, 200), PROPERTY_CLASS, UNKNOWN_PROPERTY),
+            ([read_request(ANALOG_INPUT, SUPPLY as u32, PRESENT_VALUE as u8), vec![0x29, 1]].concat(), PROPERTY_CLASS, PROPERTY_IS_NOT_AN_ARRAY),
+        ];
+        for (request, class, code) in cases {
+            let apdu = answer(&mut server, &confirmed(9, READ_PROPERTY, &request));
+            assert_eq!(apdu, [0x50, 9, READ_PROPERTY, 0x91, class as u8, 0x91, code as u8], "{:02x?}", request);
+        }
+    }
+
+    #[test]
+    fn write_property_to_outputs() {
+        let (mut plc, mut server) = server("bacnet_write", Some("bms"));
+        let real = [&[0x44][..], &42.0f32.to_be_bytes()].concat();
+        let packet = confirmed(3, WRITE_PROPERTY, &write_request(ANALOG_OUTPUT, SETPOINT as u32, &real));
+        assert_eq!(answer(&mut server, &packet), [0x20, 3, WRITE_PROPERTY]);
+        let write = plc.pop_command().expect("tag write");
+        assert_eq!((write.kind, write.tag as usize, TagValue::from_raw(TagType::Float32, write.value)), (ITEM_TAG_WRITE, SETPOINT, TagValue::Float32(42.0)));
+        let audit = plc.pop_command().expect("audit");
+        assert_eq!((audit.kind, audit.audited()), (ITEM_AUDIT, (write.seq, 0, "bms".to_owned())));
+
+        // active, as an enumerated
+        let packet = confirmed(4, WRITE_PROPERTY, &write_request(BINARY_OUTPUT, PUMP as u32, &[0x91, 0x01]));
+        assert_eq!(answer(&mut server, &packet), [0x20, 4, WRITE_PROPERTY]);
+        assert_eq!(plc.pop_command().map(|item| (item.tag as usize, item.value)), Some((PUMP, 1)));
+        plc.pop_command();
+
+        // request, error code (all property class)
+        let cases = [
+            (write_request(ANALOG_INPUT, SUPPLY as u32, &real), WRITE_ACCESS_DENIED),
+            (write_request(ANALOG_OUTPUT, SETPOINT as u32, &[0x11]), INVALID_DATA_TYPE), // boolean to an analog
+            (write_request(BINARY_OUTPUT, PUMP as u32, &[0x91, 0x02]), VALUE_OUT_OF_RANGE),
+        ];
+        for (request, code) in cases {
+            let apdu = answer(&mut server, &confirmed(5, WRITE_PROPERTY, &request));
+            assert_eq!(apdu, [0x50, 5, WRITE_PROPERTY, 0x91, PROPERTY_CLASS as u8, 0x91, code as u8], "{:02x?}", request);
+        }
+        assert!(plc.pop_command().is_none());
+
+        // without a user outputs are read only
+        let (mut plc, mut server) = self::server("bacnet_read_only", None);
+        let apdu = answer(&mut server, &confirmed(6, WRITE_PROPERTY, &write_request(ANALOG_OUTPUT, SETPOINT as u32, &real)));
+        assert_eq!(apdu, [0x50, 6, WRITE_PROPERTY, 0x91, PROPERTY_CLASS as u8, 0x91, WRITE_ACCESS_DENIED as u8]);
+        assert!(plc.pop_command().is_none());
+    }
+
+    #[test]
+    fn malformed_and_truncated_datagrams() {
+        let (mut plc, mut server) = server("bacnet_malformed", Some("bms"));
+        let real = [&[0x44][..], &42.0f32.to_be_bytes()].concat();
+        let requests = [
+            confirmed(1, READ_PROPERTY, &read_request(ANALOG_INPUT, SUPPLY as u32, PRESENT_VALUE as u8)),
+            confirmed(2, WRITE_PROPERTY, &write_request(ANALOG_OUTPUT, SETPOINT as u32, &real)),
+            confirmed(3, READ_PROPERTY_MULTIPLE, &[&[0x0C][..], &object_id_bytes(DEVICE, 1), &[0x1E, 0x09, ALL as u8, 0x1F]].concat()),
+            confirmed(4, SUBSCRIBE_COV, &[&[0x09, 0x01, 0x1C][..], &object_id_bytes(ANALOG_INPUT, 0), &[0x29, 0x00, 0x39, 0x3C]].concat()),
+            bvlc(true, &npci(None, false), &[0x10, WHO_IS, 0x09, 0x00, 0x19, 0x0A]),
+        ];
+        for (i, request) in requests.iter().enumerate() {
+            // cut short in transit: the BVLC length no longer matches, dropped
+            for len in 0..request.len() {
+                assert!(server.handle(&request[..len], from(), broadcast()).is_empty(), "{:02x?}", &request[..len]);
+            }
+            // cut short but consistent: never a panic or a command. Reads and writes cut short get a reject if
+            // anything, what's left of the others can be a request of its own (a SubscribeCOV without lifetime cancels)
+            for len in 4..request.len() {
+                let mut short = request[..len].to_vec();
+                short[2..4].copy_from_slice(&(len as u16).to_be_bytes());
+                for (packet, _) in server.handle(&short, from(), broadcast()) {
+                    let apdu = &packet[6..];
+                    assert!(i >= 2 || apdu[0] >> 4 == 6, "{:02x?} answered {:02x?}", short, apdu);
+                }
+            }
+        }
+        assert!(plc.pop_command().is_none());
+
+        // datagram, what's wrong with it
+        let cases: &[(&[u8], &str)] = &[
+            (&[0x82, 0x0A, 0x00, 0x04], "not BACnet/IP"),
+            (&[0x81, 0x05, 0x00, 0x04], "BVLC function we don't take"),
+            (&[0x81, 0x04, 0x00, 0x08, 10, 0, 0, 1], "forwarded without its address"),
+            (&[0x81, 0x0A, 0x00, 0x06, 0x02, 0x00], "NPDU version"),
+            (&[0x81, 0x0A, 0x00, 0x08, 0x01, 0x80, 0x00, 0x00], "network layer message"),
+            (&[0x81, 0x0A, 0x00, 0x09, 0x01, 0x20, 0x00, 0x05, 0x06], "to another network, length past the end"),
+            (&[0x81, 0x0A, 0x00, 0x09, 0x01, 0x08, 0x00, 0x05, 0x06], "source route past the end"),
+        ];
+        for &(packet, why) in cases {
+            assert!(server.handle(packet, from(), broadcast()).is_empty(), "{}", why);
+        }
+
+        // tags whose length runs past the end
+        for data in [&[0x0D][..], &[0x0D, 0xFE, 0x00], &[0x0D, 0xFF, 0x00, 0x00, 0x00], &[0xF9], &[0x0C, 0x00, 0x00]] {
+            assert!(Reader::new(data).next().is_none(), "{:02x?}", data);
+        }
+    }
+}
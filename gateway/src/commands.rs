@@ -0,0 +1,80 @@
+// Commands the gateway sends the PLC on behalf of its users. Each is followed by an ITEM_AUDIT naming the user, so the
+// PLC's audit log says who sent it no matter which protocol it came in over, like it does for OPC UA writes. Tag values
+// in and out of the JSON payloads MQTT, NATS, Redis and the REST API share are here too, whichever of them are built.
+use gipop_shm::{RingItem, Subscriber, TagType, TagValue};
+use serde_json::{json, Value};
+
+use crate::config::PayloadFormat;
+
+/// Pushes `item` for `user` and audits it. `via` (the protocol) and `what` ("acknowledged 'x'") are for the log.
+/// The command's sequence number, Err if the PLC isn't consuming commands
+pub fn send(table: &Subscriber, via: &str, user: &str, item: RingItem, what: &str) -> Result<u32, String> {
+    let Ok(seq) = table.push_command(item) else {
+        log::error!("[{}] Command rejected, PLC isn't consuming commands", via);
+        return Err("PLC isn't consuming commands".to_owned());
+    };
+    log::info!("[{}] {} {}", via, user, what);
+    if table.push_command(RingItem::audit(seq, 0, user)).is_err() {
+        log::error!("Audit of command {} rejected, PLC isn't consuming commands", seq);
+    }
+    Ok(seq)
+}
+
+/// Writes `value` to tag `idx`, see `send`
+pub fn write_tag(table: &Subscriber, via: &str, user: &str, idx: usize, value: TagValue) -> Result<u32, String> {
+    let what = format!("wrote {} to '{}'", value.as_f64(), table.tags()[idx].name);
+    send(table, via, user, RingItem::tag_write(idx, value), &what)
+}
+
+/// `number` as a value of type `ty`, None if it doesn't fit, see TagValue::from_f64
+pub fn to_tag(ty: TagType, number: f64) -> Option<TagValue> {
+    TagValue::from_f64(ty, number)
+}
+
+/// `value` as a JSON number or bool
+pub fn json_value(value: TagValue) -> Value {
+    match value {
+        TagValue::Bool(b) => json!(b),
+        TagValue::UInt32(n) => json!(n),
+        TagValue::Int32(n) => json!(n),
+        TagValue::Float32(f) => json!(f),
+        TagValue::Float64(f) => json!(f),
+    }
+}
+
+/// The value in a command message, None if there isn't one or it doesn't fit the tag's type
+pub fn parse(ty: TagType, format: PayloadFormat, payload: &[u8]) -> Option<TagValue> {
+    let text = std::str::from_utf8(payload).ok()?.trim();
+    let number = match format {
+        PayloadFormat::Raw => match text.to_ascii_lowercase().as_str() {
+            "true" | "on" => 1.0,
+            "false" | "off" => 0.0,
+            number => number.parse().ok()?,
+        },
+        PayloadFormat::Json => {
+            let message: Value = serde_json::from_str(text).ok()?;
+            match message.get("value").unwrap_or(&message) {
+                Value::Bool(b) => *b as u8 as f64,
+                Value::Number(n) => n.as_f64()?,
+                _ => return None,
+            }
+        }
+    };
+    to_tag(ty, number)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use gipop_shm::{IpcConfig, Publisher, Subscriber, TagDef, Transport};
+
+    /// A shm region with `tags` for a protocol's tests, the Publisher plays the PLC. `name` keeps the regions of tests
+    /// running at once apart, the file is gone once both ends are.
+    pub fn table(name: &str, tags: &[TagDef]) -> (Publisher, Subscriber) {
+        let path = std::env::temp_dir().join(format!("gipop_gateway_{}_{}.shm", name, std::process::id()));
+        let ipc = IpcConfig { transport: Transport::Shm, shm_path: path.to_str().unwrap().to_owned(), ..Default::default() };
+        let publisher = Publisher::create(&ipc, tags).expect("create shm region");
+        let subscriber = Subscriber::connect(&ipc).expect("open shm region");
+        _ = std::fs::remove_file(&path);
+        (publisher, subscriber)
+    }
+}
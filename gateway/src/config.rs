@@ -0,0 +1,952 @@
+// gipop_gateway's sections of gipop.toml. `[ipc]` is shared with the PLC and read by gipop_shm::IpcConfig, `[users]`
+// by gipop_shm::Users. `[compression]` (historian::compression) goes for the OPC UA server's history too, here it
+// thins out what [influx], [file_log] and [mqtt] write of each tag.
+//
+// [mqtt]
+// broker = "mqtt://localhost:1883"
+// client_id = "gipop-gateway"
+// username = "gipop"           # broker login, optional
+// password = "..."
+// user = "mqtt"                # who commands come from, in [users]. Needs the operator role
+// poll_ms = 100                # how often tags are checked for changes
+//
+// [[mqtt.publish]]
+// tag = "temperature"
+// topic = "gipop/environment/temperature"
+// qos = 0                      # 0, 1 or 2
+// retain = true
+// payload = "json"             # {"value":21.5,"quality":"Good","ts":1700000000000}, or "raw": 21.5
+//
+// [[mqtt.commands]]
+// topic = "gipop/area1/lights/set"
+// tag = "area 1 lights hmi cmd" # writable tags only
+// qos = 1
+// payload = "raw"              # "json" takes {"value":3} or a bare JSON value
+//
+// [sparkplug]                  # needs the `sparkplug` cargo feature
+// broker = "mqtt://localhost:1883"
+// client_id = "gipop-sparkplug"
+// username = "gipop"
+// password = "..."
+// group_id = "Gipop"
+// edge_node_id = "gipop-1"
+// primary_host = "scada"       # host application whose STATE we wait for, empty publishes right away
+// user = "scada"               # who NCMD/DCMD writes come from, in [users]. Needs the operator role
+// poll_ms = 100
+// node_tags = ["running", "scan time"] # metrics of the edge node itself
+//
+// [[sparkplug.devices]]
+// id = "environment"
+// tags = ["temperature", "humidity"]
+//
+// [http]
+// enabled = false
+// listen = "127.0.0.1:8080"
+// anonymous_read = true        # GETs without an X-Api-Key header, as a viewer
+// hmi = true                   # the web HMI at /, served with the API
+// tls_cert = "/etc/gipop/http.crt" # PEM certificate chain, with tls_key serves HTTPS only. Needs the `tls` cargo feature
+// tls_key = "/etc/gipop/http.key"  # PEM private key (PKCS#8, PKCS#1 or SEC1)
+// jwt_secret = "..."           # takes Authorization: Bearer <JWT> signed HS256/384/512 with this. Needs the `jwt` feature
+// jwt_public_key = "/etc/gipop/jwt.pem" # or signed RS*/PS*/ES*/EdDSA, verified with this PEM public key
+// jwt_issuer = ""              # iss the tokens must carry, unchecked when empty
+// jwt_audience = ""            # aud the tokens must carry, unchecked when empty
+//
+// [influx]                     # needs the `influx` cargo feature
+// url = "http://localhost:8086"
+// org = "plant"
+// bucket = "gipop"
+// token = "..."                # API token with write access to the bucket
+// measurement = "gipop"        # for tags without a mapping: gipop,tag=<name> value=<value>
+// tags = []                    # tags to write on change, empty writes every tag
+// poll_ms = 100
+// flush_ms = 1000              # a batch goes out this often, or once batch_size samples are pending
+// batch_size = 5000
+// buffer_dir = "/var/lib/gipop/influx" # spooled here while InfluxDB can't be reached
+// buffer_max_mb = 64
+// buffer_max_days = 7
+//
+// [[influx.mappings]]
+// tag = "temperature"
+// measurement = "environment"
+// field = "temperature"
+// tags = { area = "1" }
+//
+// [file_log]
+// enabled = false
+// format = "csv"               # or "parquet", needs the `parquet` cargo feature
+// dir = "trends"
+// prefix = "gipop"             # gipop_20240501.csv
+// tags = ["temperature", "humidity"] # columns, empty logs every tag
+// interval_ms = 1000
+// rotation = "daily"           # or "hourly"
+// keep_days = 30
+//
+// [bacnet]
+// enabled = false
+// listen = "0.0.0.0:47808"
+// broadcast = "255.255.255.255:47808" # where I-Am goes, the subnet's broadcast address
+// device_instance = 1          # unique on the BACnet internetwork, 0-4194302
+// device_name = "Gipop"
+// vendor_id = 0
+// user = "bms"                 # who output writes come from, in [users]. Needs the operator role
+// poll_ms = 100                # how often subscribed objects are checked for changes
+//
+// [[bacnet.objects]]           # none exposes every tag, numbered by tag index
+// tag = "temperature"
+// type = "analog-input"        # analog-output, binary-input, binary-output. Outputs need a writable tag
+// instance = 1
+// units = 62                   # BACnet engineering units, from the tag's unit if unset
+// cov_increment = 0.5          # analog only, 1% of the tag's range if unset
+//
+// [knx]
+// gateway = "192.168.1.20:3671" # KNX IP interface or router to tunnel through, empty disables
+// user = "knx"                 # who writes from the bus come from, in [users]. Needs the operator role
+// poll_ms = 100
+//
+// [[knx.mappings]]
+// tag = "area 1 lights"
+// group = "1/0/1"              # 3 level, 2 level ("1/1") or raw
+// dpt = "1.001"                # 1.x, 5.001, 5.x, 7.x, 9.x, 12.x, 13.x, 14.x
+// direction = "out"            # tag to bus, "in" bus to tag (writable tags), or "both"
+// min_change = 0.0             # "out" values are only sent once they moved this much, 0 sends every change
+//
+// [iec104]
+// enabled = false
+// listen = "0.0.0.0:2404"
+// common_address = 1
+// user = "scada"               # who commands come from, in [users]. Needs the operator role
+// poll_ms = 100
+// time_tags = true             # spontaneous values as M_SP_TB_1/M_ME_TF_1 with the sample time
+//
+// [[iec104.points]]
+// tag = "temperature"
+// ioa = 1001
+// type = "float"               # M_ME_NC_1, or "single" M_SP_NA_1 (nonzero is on)
+// min_change = 0.1             # spontaneous only once the value moved this much, 0 sends every change
+//
+// [[iec104.commands]]
+// tag = "area 1 lights hmi cmd" # writable tags only
+// ioa = 2001
+// type = "single"              # C_SC_NA_1, or "setpoint" C_SE_NC_1
+//
+// [dnp3]
+// enabled = false
+// listen = "0.0.0.0:20000"
+// address = 1024               # link address of the outstation
+// master = 1                   # link address of the master, frames from others are ignored
+// user = "scada"               # who commands come from, in [users]. Needs the operator role
+// poll_ms = 100
+// unsolicited = false          # push events once the master enables unsolicited responses
+//
+// [[dnp3.points]]
+// tag = "temperature"
+// index = 0                    # per type
+// type = "analog"              # g30v5/g32v7, or "binary" g1v2/g2v2 (nonzero is on)
+// class = 2                    # event class 1-3, 0 for no events
+// deadband = 0.1               # analog events only once the value moved this much, 0 on every change
+//
+// [[dnp3.commands]]
+// tag = "area 1 lights hmi cmd" # writable tags only
+// index = 0                    # per type
+// type = "binary"              # CROB g12v1, or "analog" output g41v1-4
+//
+// [webhooks]
+// poll_ms = 100                # how often the PLC's events are checked
+//
+// [[webhooks.hooks]]
+// url = "https://example.com/hooks/gipop"
+// events = ["alarm", "clear"]  # also "ack" and "enocean"
+// tags = []                    # alarm tags to notify about, empty takes every one
+// min_severity = 1             # alarms below this severity (1-1000) are left out
+// template = '{"text": "{{message}}", "severity": {{severity}}}' # the body, empty sends the default JSON
+// headers = { Authorization = "Bearer ..." }
+// timeout_ms = 10000
+// retries = 5                  # further attempts after a failure, waiting backoff_ms, then twice as long...
+// backoff_ms = 1000
+//
+// [notify]
+// site = "Plant 1"             # in front of every message, tells sites apart
+// poll_ms = 100
+// max_per_hour = 20            # messages per recipient and channel, the rest is summed up once there's room again
+//
+// [notify.smtp]                # needs the `email` cargo feature
+// server = "smtp.example.com"
+// port = 587
+// security = "starttls"        # "tls" (implicit, port 465) or "none"
+// username = "gipop@example.com"
+// password = "..."
+// from = "Gipop <gipop@example.com>"
+//
+// [notify.telegram]
+// bot_token = "123456:ABC..."  # from @BotFather
+//
+// [[notify.recipients]]
+// name = "on-call"
+// email = "oncall@example.com" # either or both
+// telegram_chat = "-1001234567890" # chat id the bot posts to
+// events = ["alarm"]           # as for webhooks
+// tags = []                    # empty takes every alarm
+// min_severity = 700
+//
+// [nats]
+// url = "nats://localhost:4222" # empty disables NATS
+// name = "gipop-gateway"       # the connection's name on the server
+// username = "gipop"           # server login, optional, or token = "..."
+// password = "..."
+// user = "nats"                # who commands come from, in [users]. Needs the operator role
+// prefix = "gipop"             # gipop.tags.<folder>.<tag>, gipop.cmd.<folder>.<tag>, gipop.events.<event>[.<tag>]
+// tags = []                    # tags to publish on change, empty publishes every one
+// commands = false             # take writes to writable tags on gipop.cmd.>
+// poll_ms = 100
+// stream = "GIPOP_EVENTS"      # JetStream stream the events are kept in, empty publishes them without persistence
+// max_age_hours = 168          # how long the stream keeps events, 0 for as long as the server has room
+//
+// [redis]
+// url = "redis://localhost:6379/0" # redis://[user:password@]host[:port][/db], empty disables the mirror
+// prefix = "gipop"             # hashes gipop:tags:<folder> (gipop:tags for top level tags), changes on channels of the same name
+// tags = []                    # tags to mirror, empty mirrors every one
+// poll_ms = 100
+//
+// [kafka]                      # needs the `kafka` cargo feature
+// brokers = "kafka1:9092,kafka2:9092" # bootstrap servers, empty disables Kafka
+// client_id = "gipop-gateway"
+// alarms_topic = "gipop.alarms"       # alarms going active, clearing and acknowledged. Empty leaves them out, like the others
+// audit_topic = "gipop.audit"         # every user command, from the PLC's audit log
+// audit_log = "gipop_audit.log"       # the PLC's [audit] path, followed from its end
+// telemetry_topic = "gipop.telemetry" # min, max, average and last of each numeric tag per telemetry_ms
+// telemetry_ms = 60000
+// tags = []                    # telemetry tags, empty takes every numeric tag
+// schema_registry = "http://registry:8081" # registers JSON Schemas as <topic>-value and frames payloads for it
+// poll_ms = 100
+//
+// [kafka.properties]           # passed to librdkafka as they are, e.g. for SASL and TLS
+// "security.protocol" = "sasl_ssl"
+// "sasl.mechanisms" = "PLAIN"
+//
+// [event_store]                # needs the `event_store` cargo feature
+// path = "gipop_events.db"     # SQLite database, created if missing. Empty disables the store and GET /api/events
+// retention_days = 90          # older events are deleted hourly, 0 keeps them
+// max_events = 1000000         # the oldest beyond this go too, 0 for no limit
+// audit_log = "gipop_audit.log" # the PLC's [audit] path, its entries are stored too. Empty leaves them out
+// poll_ms = 100
+use historian::compression::CompressionSettings;
+use historian::file_log::{FileFormat, Rotation};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::AtomicU32;
+use std::path::Path;
+
+/// Bumped on SIGHUP and POST /api/config/reload, [notify] and [webhooks] read gipop.toml again when it moves
+pub static RELOADS: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct GatewayConfig {
+    pub mqtt: MqttConfig,
+    pub sparkplug: SparkplugConfig,
+    pub http: HttpConfig,
+    pub influx: InfluxConfig,
+    pub file_log: FileLogConfig,
+    pub bacnet: BacnetConfig,
+    pub knx: KnxConfig,
+    pub iec104: Iec104Config,
+    pub dnp3: Dnp3Config,
+    pub webhooks: WebhooksConfig,
+    pub notify: NotifyConfig,
+    pub nats: NatsConfig,
+    pub redis: RedisConfig,
+    pub kafka: KafkaConfig,
+    pub event_store: EventStoreConfig,
+    pub compression: CompressionSettings,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MqttConfig {
+    pub broker: String, // empty disables MQTT
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: String,
+    pub user: Option<String>,
+    pub poll_ms: u64,
+    pub publish: Vec<PublishMapping>,
+    pub commands: Vec<CommandMapping>,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        Self {
+            broker: String::new(),
+            client_id: "gipop-gateway".to_string(),
+            username: None,
+            password: String::new(),
+            user: None,
+            poll_ms: 100,
+            publish: Vec::new(),
+            commands: Vec::new(),
+        }
+    }
+}
+
+/// A tag whose changes go out on `topic`
+#[derive(Debug, Clone, Deserialize)]
+pub struct PublishMapping {
+    pub tag: String,
+    pub topic: String,
+    #[serde(default)]
+    pub qos: u8,
+    #[serde(default)]
+    pub retain: bool,
+    #[serde(default)]
+    pub payload: PayloadFormat,
+}
+
+/// A topic whose messages are written to `tag`
+#[derive(Debug, Clone, Deserialize)]
+pub struct CommandMapping {
+    pub topic: String,
+    pub tag: String,
+    #[serde(default = "default_command_qos")]
+    pub qos: u8,
+    #[serde(default)]
+    pub payload: PayloadFormat,
+}
+
+fn default_command_qos() -> u8 {
+    1 // commands shouldn't get lost on the way
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PayloadFormat {
+    #[default]
+    Json,
+    Raw, // the value as text, "21.5", "true"
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SparkplugConfig {
+    pub broker: String, // empty disables Sparkplug
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: String,
+    pub group_id: String,
+    pub edge_node_id: String,
+    pub primary_host: String,
+    pub user: Option<String>,
+    pub poll_ms: u64,
+    pub node_tags: Vec<String>,
+    #[cfg(feature = "sparkplug")]
+    pub devices: Vec<SparkplugDevice>,
+}
+
+impl Default for SparkplugConfig {
+    fn default() -> Self {
+        Self {
+            broker: String::new(),
+            client_id: "gipop-sparkplug".to_string(),
+            username: None,
+            password: String::new(),
+            group_id: "Gipop".to_string(),
+            edge_node_id: "gipop".to_string(),
+            primary_host: String::new(),
+            user: None,
+            poll_ms: 100,
+            node_tags: Vec::new(),
+            #[cfg(feature = "sparkplug")]
+            devices: Vec::new(),
+        }
+    }
+}
+
+/// A Sparkplug device under the edge node, its tags are its metrics
+#[cfg(feature = "sparkplug")]
+#[derive(Debug, Clone, Deserialize)]
+pub struct SparkplugDevice {
+    pub id: String,
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    pub enabled: bool,
+    pub listen: String, // use 0.0.0.0 to accept other hosts
+    pub anonymous_read: bool,
+    pub hmi: bool,
+    pub tls_cert: String, // empty serves plain HTTP
+    pub tls_key: String,
+    pub jwt_secret: String,
+    pub jwt_public_key: String,
+    pub jwt_issuer: String,
+    pub jwt_audience: String,
+    pub scan_budget_us: u32, // /readyz fails while the PLC's average cycle takes longer
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: "127.0.0.1:8080".to_string(),
+            anonymous_read: true,
+            hmi: true,
+            tls_cert: String::new(),
+            tls_key: String::new(),
+            jwt_secret: String::new(),
+            jwt_public_key: String::new(),
+            jwt_issuer: String::new(),
+            jwt_audience: String::new(),
+            scan_budget_us: 10_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct InfluxConfig {
+    pub url: String, // empty disables InfluxDB
+    pub org: String,
+    pub bucket: String,
+    pub token: String,
+    pub measurement: String,
+    pub tags: Vec<String>,
+    pub poll_ms: u64,
+    pub flush_ms: u64,
+    pub batch_size: usize,
+    pub buffer_dir: String,
+    pub buffer_max_mb: u64,
+    pub buffer_max_days: u64,
+    #[cfg(feature = "influx")]
+    pub mappings: Vec<InfluxTag>,
+}
+
+impl Default for InfluxConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            org: String::new(),
+            bucket: "gipop".to_string(),
+            token: String::new(),
+            measurement: "gipop".to_string(),
+            tags: Vec::new(),
+            poll_ms: 100,
+            flush_ms: 1000,
+            batch_size: 5000,
+            buffer_dir: "influx_buffer".to_string(),
+            buffer_max_mb: 64,
+            buffer_max_days: 7,
+            #[cfg(feature = "influx")]
+            mappings: Vec::new(),
+        }
+    }
+}
+
+/// Where one tag goes in InfluxDB, see historian::influx::InfluxMapping
+#[cfg(feature = "influx")]
+#[derive(Debug, Clone, Deserialize)]
+pub struct InfluxTag {
+    pub tag: String,
+    #[serde(default)]
+    pub measurement: Option<String>,
+    #[serde(default = "default_influx_field")]
+    pub field: String,
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+}
+
+#[cfg(feature = "influx")]
+fn default_influx_field() -> String {
+    "value".to_string()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct FileLogConfig {
+    pub enabled: bool,
+    pub format: FileFormat,
+    pub dir: String,
+    pub prefix: String,
+    pub tags: Vec<String>,
+    pub interval_ms: u64,
+    pub rotation: Rotation,
+    pub keep_days: u64,
+}
+
+impl Default for FileLogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            format: FileFormat::Csv,
+            dir: "trends".to_string(),
+            prefix: "gipop".to_string(),
+            tags: Vec::new(),
+            interval_ms: 1000,
+            rotation: Rotation::Daily,
+            keep_days: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BacnetConfig {
+    pub enabled: bool,
+    pub listen: String,
+    pub broadcast: String,
+    pub device_instance: u32,
+    pub device_name: String,
+    pub vendor_id: u16,
+    pub user: Option<String>,
+    pub poll_ms: u64,
+    pub objects: Vec<BacnetObject>,
+}
+
+impl Default for BacnetConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: "0.0.0.0:47808".to_string(),
+            broadcast: "255.255.255.255:47808".to_string(),
+            device_instance: 1,
+            device_name: "Gipop".to_string(),
+            vendor_id: 0,
+            user: None,
+            poll_ms: 100,
+            objects: Vec::new(),
+        }
+    }
+}
+
+/// A tag as a BACnet object
+#[derive(Debug, Clone, Deserialize)]
+pub struct BacnetObject {
+    pub tag: String,
+    #[serde(rename = "type")]
+    pub ty: BacnetObjectType,
+    pub instance: u32,
+    #[serde(default)]
+    pub units: Option<u32>,
+    #[serde(default)]
+    pub cov_increment: Option<f32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BacnetObjectType {
+    AnalogInput,
+    AnalogOutput,
+    BinaryInput,
+    BinaryOutput,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct KnxConfig {
+    pub gateway: String, // empty disables KNX
+    pub user: Option<String>,
+    pub poll_ms: u64,
+    pub mappings: Vec<KnxMapping>,
+}
+
+impl Default for KnxConfig {
+    fn default() -> Self {
+        Self { gateway: String::new(), user: None, poll_ms: 100, mappings: Vec::new() }
+    }
+}
+
+/// A tag tied to a KNX group address
+#[derive(Debug, Clone, Deserialize)]
+pub struct KnxMapping {
+    pub tag: String,
+    pub group: String,
+    pub dpt: String,
+    #[serde(default)]
+    pub direction: KnxDirection,
+    #[serde(default)]
+    pub min_change: f64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KnxDirection {
+    #[default]
+    Out,
+    In,
+    Both,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Iec104Config {
+    pub enabled: bool,
+    pub listen: String,
+    pub common_address: u16,
+    pub user: Option<String>,
+    pub poll_ms: u64,
+    pub time_tags: bool,
+    pub points: Vec<Iec104Point>,
+    pub commands: Vec<Iec104Command>,
+}
+
+impl Default for Iec104Config {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: "0.0.0.0:2404".to_string(),
+            common_address: 1,
+            user: None,
+            poll_ms: 100,
+            time_tags: true,
+            points: Vec::new(),
+            commands: Vec::new(),
+        }
+    }
+}
+
+/// A tag monitored as information object `ioa`
+#[derive(Debug, Clone, Deserialize)]
+pub struct Iec104Point {
+    pub tag: String,
+    pub ioa: u32,
+    #[serde(rename = "type")]
+    pub ty: Iec104PointType,
+    #[serde(default)]
+    pub min_change: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Iec104PointType {
+    Single,
+    Float,
+}
+
+/// Commands to information object `ioa` written to a tag
+#[derive(Debug, Clone, Deserialize)]
+pub struct Iec104Command {
+    pub tag: String,
+    pub ioa: u32,
+    #[serde(rename = "type")]
+    pub ty: Iec104CommandType,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Iec104CommandType {
+    Single,
+    Setpoint,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Dnp3Config {
+    pub enabled: bool,
+    pub listen: String,
+    pub address: u16,
+    pub master: u16,
+    pub user: Option<String>,
+    pub poll_ms: u64,
+    pub unsolicited: bool,
+    pub points: Vec<Dnp3Point>,
+    pub commands: Vec<Dnp3Command>,
+}
+
+impl Default for Dnp3Config {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: "0.0.0.0:20000".to_string(),
+            address: 1024,
+            master: 1,
+            user: None,
+            poll_ms: 100,
+            unsolicited: false,
+            points: Vec::new(),
+            commands: Vec::new(),
+        }
+    }
+}
+
+/// A tag monitored as input point `index`
+#[derive(Debug, Clone, Deserialize)]
+pub struct Dnp3Point {
+    pub tag: String,
+    pub index: u16,
+    #[serde(rename = "type")]
+    pub ty: Dnp3Type,
+    #[serde(default)]
+    pub class: u8,
+    #[serde(default)]
+    pub deadband: f64,
+}
+
+/// Controls of output `index` written to a tag
+#[derive(Debug, Clone, Deserialize)]
+pub struct Dnp3Command {
+    pub tag: String,
+    pub index: u16,
+    #[serde(rename = "type")]
+    pub ty: Dnp3Type,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Dnp3Type {
+    Binary,
+    Analog,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct WebhooksConfig {
+    pub poll_ms: u64,
+    pub hooks: Vec<Webhook>, // none disables webhooks
+}
+
+impl Default for WebhooksConfig {
+    fn default() -> Self {
+        Self { poll_ms: 100, hooks: Vec::new() }
+    }
+}
+
+/// An URL that gets a POST for each of `events`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Webhook {
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+    pub tags: Vec<String>,
+    pub min_severity: u16,
+    pub template: String,
+    pub headers: BTreeMap<String, String>,
+    pub timeout_ms: u64,
+    pub retries: u32,
+    pub backoff_ms: u64,
+}
+
+impl Default for Webhook {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            events: vec![WebhookEvent::Alarm, WebhookEvent::Clear],
+            tags: Vec::new(),
+            min_severity: 1,
+            template: String::new(),
+            headers: BTreeMap::new(),
+            timeout_ms: 10_000,
+            retries: 5,
+            backoff_ms: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookEvent {
+    Alarm,   // an alarm went active
+    Clear,   // an alarm cleared
+    Ack,     // an alarm was acknowledged
+    Enocean, // an EnOcean telegram came in
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct NotifyConfig {
+    pub site: String,
+    pub poll_ms: u64,
+    pub max_per_hour: u32,
+    pub smtp: SmtpConfig,
+    pub telegram: TelegramConfig,
+    pub recipients: Vec<Recipient>, // none disables notifications
+}
+
+impl Default for NotifyConfig {
+    fn default() -> Self {
+        Self {
+            site: "Gipop".to_string(),
+            poll_ms: 100,
+            max_per_hour: 20,
+            smtp: SmtpConfig::default(),
+            telegram: TelegramConfig::default(),
+            recipients: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SmtpConfig {
+    pub server: String,
+    pub port: u16,
+    pub security: SmtpSecurity,
+    pub username: String, // empty doesn't log in
+    pub password: String,
+    pub from: String,
+}
+
+impl Default for SmtpConfig {
+    fn default() -> Self {
+        Self {
+            server: String::new(),
+            port: 587,
+            security: SmtpSecurity::default(),
+            username: String::new(),
+            password: String::new(),
+            from: String::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    #[default]
+    Starttls,
+    Tls,
+    None,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct TelegramConfig {
+    pub bot_token: String,
+}
+
+/// Someone who gets alarms by email, Telegram or both
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Recipient {
+    pub name: String,
+    pub email: String,
+    pub telegram_chat: String,
+    pub events: Vec<WebhookEvent>,
+    pub tags: Vec<String>,
+    pub min_severity: u16,
+}
+
+impl Default for Recipient {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            email: String::new(),
+            telegram_chat: String::new(),
+            events: vec![WebhookEvent::Alarm],
+            tags: Vec::new(),
+            min_severity: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct NatsConfig {
+    pub url: String, // empty disables NATS
+    pub name: String,
+    pub username: Option<String>,
+    pub password: String,
+    pub token: String,
+    pub user: Option<String>,
+    pub prefix: String,
+    pub tags: Vec<String>, // empty publishes every tag
+    pub commands: bool,
+    pub poll_ms: u64,
+    pub stream: String, // empty: events without JetStream
+    pub max_age_hours: u64,
+}
+
+impl Default for NatsConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            name: "gipop-gateway".to_string(),
+            username: None,
+            password: String::new(),
+            token: String::new(),
+            user: None,
+            prefix: "gipop".to_string(),
+            tags: Vec::new(),
+            commands: false,
+            poll_ms: 100,
+            stream: "GIPOP_EVENTS".to_string(),
+            max_age_hours: 168,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RedisConfig {
+    pub url: String, // empty disables the mirror
+    pub prefix: String,
+    pub tags: Vec<String>, // empty mirrors every tag
+    pub poll_ms: u64,
+}
+
+impl Default for RedisConfig {
+    fn default() -> Self {
+        Self { url: String::new(), prefix: "gipop".to_string(), tags: Vec::new(), poll_ms: 100 }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct KafkaConfig {
+    pub brokers: String, // empty disables Kafka
+    pub client_id: String,
+    pub alarms_topic: String, // empty topics aren't produced
+    pub audit_topic: String,
+    pub audit_log: String,
+    pub telemetry_topic: String,
+    pub telemetry_ms: u64,
+    pub tags: Vec<String>, // empty takes every numeric tag
+    pub schema_registry: String, // empty sends plain JSON
+    pub poll_ms: u64,
+    pub properties: BTreeMap<String, String>,
+}
+
+impl Default for KafkaConfig {
+    fn default() -> Self {
+        Self {
+            brokers: String::new(),
+            client_id: "gipop-gateway".to_string(),
+            alarms_topic: "gipop.alarms".to_string(),
+            audit_topic: "gipop.audit".to_string(),
+            audit_log: "gipop_audit.log".to_string(),
+            telemetry_topic: "gipop.telemetry".to_string(),
+            telemetry_ms: 60_000,
+            tags: Vec::new(),
+            schema_registry: String::new(),
+            poll_ms: 100,
+            properties: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct EventStoreConfig {
+    pub path: String, // empty disables the store
+    pub retention_days: u64, // 0 keeps events however old
+    pub max_events: u64, // 0 for no limit
+    pub audit_log: String, // empty leaves audit entries out
+    pub poll_ms: u64,
+}
+
+impl Default for EventStoreConfig {
+    fn default() -> Self {
+        Self {
+            path: String::new(),
+            retention_days: 90,
+            max_events: 1_000_000,
+            audit_log: "gipop_audit.log".to_string(),
+            poll_ms: 100,
+        }
+    }
+}
+
+impl GatewayConfig {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        gipop_shm::config::load(path)
+    }
+}
@@ -0,0 +1,1009 @@
+// DNP3 outstation over TCP ([dnp3] in gipop.toml) for utility-adjacent sites whose SCADA polls DNP3. [[dnp3.points]]
+// are binary inputs (g1v2, events g2v2) or analog inputs (short float g30v5, events g32v7), [[dnp3.commands]] take
+// CROBs (g12v1) and analog outputs (g41v1-4) as tag writes, audited as coming from [dnp3] user like MQTT commands.
+//
+// Points with an event class (1-3) get an event whenever they change (analogs once they moved `deadband`) with the
+// PLC's sample time. Events are kept until the master confirms the response that carried them, at most MAX_EVENTS per
+// connection (then the oldest go and IIN2.3 says so). Class 0 reads get every point's current value. With `unsolicited`
+// the outstation announces itself with a null unsolicited response and, once the master enabled unsolicited
+// responses for a class, pushes that class's events on its own, repeated until confirmed.
+//
+// Select-before-operate, direct operate and direct operate without ack are taken. Pulses write 1 (pulse on, close)
+// or 0 (pulse off, trip), their on and off times aren't timed, that's the logic's job. Time sync writes are accepted
+// but don't set the clock, the time is the host's. Link layer confirmed and unconfirmed user data both work, we only
+// send unconfirmed. Each response is one fragment, with hundreds of points mind the master's fragment size.
+//
+// Like Modbus and IEC 104 there's no authentication (no Secure Authentication), whoever reaches the port acts as
+// [dnp3] user. Mind `listen`, or leave user unset to take no commands.
+use std::collections::{HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use gipop_shm::{Action, Subscriber, TagDef, TagSample, TagValue, Users};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::commands::{self, to_tag};
+use crate::config::{Dnp3Config, Dnp3Type};
+
+const MAX_EVENTS: usize = 1000; // per connection
+const EVENTS_PER_RESPONSE: usize = 100; // the rest waits for the next read, IIN1 tells the master they're there
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(5); // for unsolicited responses, repeated after this
+const SELECT_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_SEGMENT: usize = 249; // application bytes per link frame, after the transport header
+
+// Link layer
+const LINK_START: [u8; 2] = [0x05, 0x64];
+const DIR: u8 = 0x80;
+const PRM: u8 = 0x40;
+const FCB: u8 = 0x20;
+const FCV: u8 = 0x10;
+const LINK_RESET: u8 = 0;
+const LINK_TEST: u8 = 2;
+const LINK_CONFIRMED_DATA: u8 = 3;
+const LINK_UNCONFIRMED_DATA: u8 = 4;
+const LINK_STATUS_REQUEST: u8 = 9;
+const LINK_ACK: u8 = 0;
+const LINK_STATUS: u8 = 11;
+const LINK_NOT_SUPPORTED: u8 = 15;
+const BROADCAST: u16 = 0xFFFD; // and up
+
+// Transport layer
+const TRANSPORT_FIN: u8 = 0x80;
+const TRANSPORT_FIR: u8 = 0x40;
+
+// Application layer control and function codes
+const APP_FIR: u8 = 0x80;
+const APP_FIN: u8 = 0x40;
+const APP_CON: u8 = 0x20;
+const APP_UNS: u8 = 0x10;
+const CONFIRM: u8 = 0;
+const READ: u8 = 1;
+const WRITE: u8 = 2;
+const SELECT: u8 = 3;
+const OPERATE: u8 = 4;
+const DIRECT_OPERATE: u8 = 5;
+const DIRECT_OPERATE_NR: u8 = 6;
+const ENABLE_UNSOLICITED: u8 = 20;
+const DISABLE_UNSOLICITED: u8 = 21;
+const RESPONSE: u8 = 129;
+const UNSOLICITED_RESPONSE: u8 = 130;
+
+// Internal indications, IIN1 in the low byte and IIN2 in the high one
+const IIN_CLASS: [u16; 3] = [0x0002, 0x0004, 0x0008];
+const IIN_RESTART: u16 = 0x0080;
+const IIN_NO_FUNCTION: u16 = 0x0100;
+const IIN_UNKNOWN_OBJECT: u16 = 0x0200;
+const IIN_PARAMETER_ERROR: u16 = 0x0400;
+const IIN_OVERFLOW: u16 = 0x0800;
+
+// Object flags
+const ONLINE: u8 = 0x01;
+const STATE: u8 = 0x80; // binary value
+
+// Control status codes
+const SUCCESS: u8 = 0;
+const NO_SELECT: u8 = 2;
+const FORMAT_ERROR: u8 = 3;
+const NOT_SUPPORTED: u8 = 4;
+const HARDWARE_ERROR: u8 = 6;
+
+struct Point {
+    tag: usize,
+    index: u16,
+    ty: Dnp3Type,
+    class: u8,
+    deadband: f64,
+}
+
+struct Command {
+    tag: usize,
+    index: u16,
+    ty: Dnp3Type,
+}
+
+struct Station {
+    table: Subscriber,
+    address: u16,
+    master: u16,
+    unsolicited: bool,
+    poll: Duration,
+    points: Vec<Point>, // by type, then index
+    commands: Vec<Command>,
+    user: String,
+}
+
+fn resolve(config: &Dnp3Config, tags: &[TagDef]) -> Result<(Vec<Point>, Vec<Command>), String> {
+    let find = |name: &str, section: &str| {
+        tags.iter().position(|tag| tag.name == name).ok_or_else(|| format!("No tag named '{}' in [[dnp3.{}]]", name, section))
+    };
+    let mut indexes = HashSet::new();
+    let mut points = Vec::new();
+    for point in &config.points {
+        let tag = find(&point.tag, "points")?;
+        if !indexes.insert((point.ty, point.index)) {
+            return Err(format!("[[dnp3.points]] index {} of '{}' is taken", point.index, point.tag));
+        }
+        if point.class > 3 {
+            return Err(format!("[[dnp3.points]] class {} of '{}' isn't 0-3", point.class, point.tag));
+        }
+        points.push(Point { tag, index: point.index, ty: point.ty, class: point.class, deadband: point.deadband });
+    }
+    points.sort_by_key(|point| (point.ty == Dnp3Type::Analog, point.index));
+    let mut indexes = HashSet::new();
+    let mut commands = Vec::new();
+    for command in &config.commands {
+        let tag = find(&command.tag, "commands")?;
+        if !tags[tag].writable() {
+            return Err(format!("'{}' in [[dnp3.commands]] isn't writable", command.tag));
+        }
+        if !indexes.insert((command.ty, command.index)) {
+            return Err(format!("[[dnp3.commands]] index {} of '{}' is taken", command.index, command.tag));
+        }
+        commands.push(Command { tag, index: command.index, ty: command.ty });
+    }
+    Ok((points, commands))
+}
+
+/// Serves until the process ends, Err if the points don't fit the tag table or it can't listen
+pub async fn serve(config: Dnp3Config, table: Subscriber, users: Users) -> Result<(), String> {
+    let (points, mut commands) = resolve(&config, table.tags())?;
+    if !commands.is_empty() && let Err(e) = users.authorize(config.user.as_deref(), Action::WriteTag) {
+        log::error!("[DNP3] Not taking commands, {}", e);
+        commands.clear();
+    }
+    let listener = TcpListener::bind(&config.listen).await.map_err(|e| format!("Failed to listen on {}: {}", config.listen, e))?;
+    log::info!("[DNP3] {} point(s), {} command(s) on {}, outstation {} for master {}", points.len(), commands.len(), config.listen, config.address, config.master);
+
+    let station = Arc::new(Station {
+        table,
+        address: config.address,
+        master: config.master,
+        unsolicited: config.unsolicited,
+        poll: Duration::from_millis(config.poll_ms.max(1)),
+        points,
+        commands,
+        user: config.user.unwrap_or_default(),
+    });
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                log::warn!("[DNP3] {}", e);
+                continue;
+            }
+        };
+        log::info!("[DNP3] {} connected", peer);
+        let connection = Connection::new(station.clone(), stream, peer);
+        tokio::spawn(async move {
+            match connection.run().await {
+                Ok(()) => log::info!("[DNP3] {} disconnected", peer),
+                Err(e) => log::warn!("[DNP3] {} dropped: {}", peer, e),
+            }
+        });
+    }
+}
+
+struct Event {
+    id: u64,
+    point: usize,
+    value: TagValue,
+    good: bool,
+    ts_ms: u64,
+    in_flight: bool, // in a response that wasn't confirmed yet
+}
+
+// A response waiting for the master's confirm
+struct Unconfirmed {
+    seq: u8,
+    events: Vec<u64>,
+    fragment: Vec<u8>, // unsolicited ones are sent again
+    sent: Instant,
+}
+
+struct Connection {
+    station: Arc<Station>,
+    stream: TcpStream,
+    peer: SocketAddr,
+    last_fcb: Option<bool>,          // of the master's last confirmed frame, a repeat is acked but not processed
+    fragment: Vec<u8>,               // the master's request being reassembled
+    transport_seq: u8,
+    restart: bool,                   // IIN1.7 until the master clears it
+    overflow: bool,
+    sent: Vec<Option<(TagValue, bool)>>, // per point, value and validity of the last event
+    events: VecDeque<Event>,
+    next_event: u64,
+    solicited: Option<Unconfirmed>,
+    unsolicited: Option<Unconfirmed>,
+    unsolicited_seq: u8,
+    unsolicited_ready: bool,         // the null unsolicited response was confirmed
+    unsolicited_classes: [bool; 3],  // enabled by the master
+    selected: Option<(u8, Vec<u8>, Instant)>, // sequence number and objects of the last select
+}
+
+impl Connection {
+    fn new(station: Arc<Station>, stream: TcpStream, peer: SocketAddr) -> Self {
+        // changes from here on, the master gets the rest with its class 0 poll
+        let sent = station.points.iter().map(|point| Some(current(&station.table.read_sample(point.tag)))).collect();
+        Self {
+            station,
+            stream,
+            peer,
+            last_fcb: None,
+            fragment: Vec::new(),
+            transport_seq: 0,
+            restart: true,
+            overflow: false,
+            sent,
+            events: VecDeque::new(),
+            next_event: 0,
+            solicited: None,
+            unsolicited: None,
+            unsolicited_seq: 0,
+            unsolicited_ready: false,
+            unsolicited_classes: [false; 3],
+            selected: None,
+        }
+    }
+
+    async fn run(mut self) -> Result<(), String> {
+        if self.station.unsolicited {
+            self.send_unsolicited().await?; // the null one, announces the restart
+        }
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 1024];
+        let mut tick = tokio::time::interval(self.station.poll);
+        loop {
+            tokio::select! {
+                read = self.stream.read(&mut chunk) => {
+                    let n = read.map_err(|e| e.to_string())?;
+                    if n == 0 {
+                        return Ok(());
+                    }
+                    buf.extend_from_slice(&chunk[..n]);
+                    while let Some(frame) = take_frame(&mut buf) {
+                        self.link(&frame).await?;
+                    }
+                }
+                _ = tick.tick() => {
+                    self.changes();
+                    self.unsolicited_timers().await?;
+                }
+            }
+        }
+    }
+
+    // One link frame, header (control, destination, source) and user data without the CRCs
+    async fn link(&mut self, frame: &[u8]) -> Result<(), String> {
+        let control = frame[0];
+        let destination = u16::from_le_bytes([frame[1], frame[2]]);
+        let source = u16::from_le_bytes([frame[3], frame[4]]);
+        let broadcast = destination >= BROADCAST;
+        if control & DIR == 0 || control & PRM == 0 || (destination != self.station.address && !broadcast) {
+            return Ok(()); // not a request for us
+        }
+        if source != self.station.master {
+            log::debug!("[DNP3] Ignoring a frame from {} at {}, not the master", source, self.peer);
+            return Ok(());
+        }
+
+        let data = match control & 0x0F {
+            LINK_RESET => {
+                self.last_fcb = None;
+                return self.send_link(LINK_ACK, &[]).await;
+            }
+            LINK_TEST => return self.send_link(LINK_ACK, &[]).await,
+            LINK_STATUS_REQUEST => return self.send_link(LINK_STATUS, &[]).await,
+            LINK_CONFIRMED_DATA => {
+                self.send_link(LINK_ACK, &[]).await?;
+                let fcb = control & FCB != 0;
+                if control & FCV != 0 && self.last_fcb.replace(fcb) == Some(fcb) {
+                    return Ok(()); // our ack got lost, the master repeated the frame
+                }
+                &frame[5..]
+            }
+            LINK_UNCONFIRMED_DATA => &frame[5..],
+            _ => return self.send_link(LINK_NOT_SUPPORTED, &[]).await,
+        };
+
+        // transport layer
+        let Some((&header, segment)) = data.split_first() else { return Ok(()) };
+        if header & TRANSPORT_FIR != 0 {
+            self.fragment.clear();
+        }
+        self.fragment.extend_from_slice(segment);
+        if header & TRANSPORT_FIN == 0 {
+            return Ok(());
+        }
+        let fragment = std::mem::take(&mut self.fragment);
+        if fragment.len() < 2 {
+            return Ok(());
+        }
+        self.request(&fragment, broadcast).await
+    }
+
+    // One application fragment from the master
+    async fn request(&mut self, fragment: &[u8], broadcast: bool) -> Result<(), String> {
+        let (control, function, objects) = (fragment[0], fragment[1], &fragment[2..]);
+        let seq = control & 0x0F;
+        log::trace!("[DNP3] from {}: function {} {:02x?}", self.peer, function, objects);
+        match function {
+            CONFIRM => {
+                let pending = if control & APP_UNS != 0 { &mut self.unsolicited } else { &mut self.solicited };
+                if let Some(confirmed) = pending.take_if(|pending| pending.seq == seq) {
+                    let ids: HashSet<u64> = confirmed.events.into_iter().collect();
+                    self.events.retain(|event| !ids.contains(&event.id));
+                    if control & APP_UNS != 0 {
+                        self.unsolicited_ready = true;
+                    }
+                }
+                Ok(())
+            }
+            READ => {
+                let (objects, iin) = self.read(objects);
+                self.respond(seq, &objects, iin, broadcast).await
+            }
+            WRITE => {
+                let iin = self.write(objects);
+                self.respond(seq, &[], iin, broadcast).await
+            }
+            SELECT | OPERATE | DIRECT_OPERATE | DIRECT_OPERATE_NR => {
+                let (objects, iin) = self.control(function, seq, objects);
+                if function == DIRECT_OPERATE_NR {
+                    return Ok(());
+                }
+                self.respond(seq, &objects, iin, broadcast).await
+            }
+            ENABLE_UNSOLICITED | DISABLE_UNSOLICITED => {
+                let mut iin = 0;
+                if !self.station.unsolicited {
+                    iin |= IIN_NO_FUNCTION;
+                } else {
+                    for header in Headers::new(objects, false) {
+                        match header {
+                            Some(Header { group: 60, variation: class @ 2..=4, .. }) => {
+                                self.unsolicited_classes[class as usize - 2] = function == ENABLE_UNSOLICITED;
+                            }
+                            _ => iin |= IIN_UNKNOWN_OBJECT,
+                        }
+                    }
+                }
+                self.respond(seq, &[], iin, broadcast).await
+            }
+            _ => self.respond(seq, &[], IIN_NO_FUNCTION, broadcast).await,
+        }
+    }
+
+    // The objects answering a read and the IIN bits it caused
+    fn read(&mut self, request: &[u8]) -> (Vec<u8>, u16) {
+        // a new request gives up on the last response's confirm, its events go out again
+        if let Some(abandoned) = self.solicited.take() {
+            let ids: HashSet<u64> = abandoned.events.into_iter().collect();
+            self.events.iter_mut().filter(|event| ids.contains(&event.id)).for_each(|event| event.in_flight = false);
+        }
+        let mut objects = Vec::new();
+        let mut iin = 0;
+        let mut classes = [false; 3];
+        let mut limit = EVENTS_PER_RESPONSE;
+        for header in Headers::new(request, false) {
+            let Some(header) = header else {
+                iin |= IIN_PARAMETER_ERROR;
+                break;
+            };
+            match (header.group, header.variation) {
+                (60, 1) => {
+                    objects.extend(self.static_data(Dnp3Type::Binary, &header.range));
+                    objects.extend(self.static_data(Dnp3Type::Analog, &header.range));
+                }
+                (60, class @ 2..=4) => {
+                    classes[class as usize - 2] = true;
+                    if let Range::Count(count) = header.range {
+                        limit = limit.min(count as usize);
+                    }
+                }
+                (1, 0 | 2) => objects.extend(self.static_data(Dnp3Type::Binary, &header.range)),
+                (30, 0 | 5) => objects.extend(self.static_data(Dnp3Type::Analog, &header.range)),
+                (2, 0 | 2) | (32, 0 | 7) => classes = [true; 3],
+                _ => iin |= IIN_UNKNOWN_OBJECT,
+            }
+        }
+        let events = self.take_events(classes, limit);
+        if !events.is_empty() {
+            objects.extend(self.event_objects(&events));
+            self.solicited = Some(Unconfirmed { seq: 0, events, fragment: Vec::new(), sent: Instant::now() });
+        }
+        (objects, iin)
+    }
+
+    fn write(&mut self, request: &[u8]) -> u16 {
+        let mut iin = 0;
+        for header in Headers::new(request, true) {
+            match header {
+                // IIN1.7 cleared
+                Some(Header { group: 80, variation: 1, range: Range::StartStop(7, 7), data, .. }) if data.first() == Some(&0) => self.restart = false,
+                // time sync, fine but the clock is the host's
+                Some(Header { group: 50, variation: 1 | 3, .. }) => {}
+                Some(_) => iin |= IIN_UNKNOWN_OBJECT,
+                None => iin |= IIN_PARAMETER_ERROR,
+            }
+        }
+        iin
+    }
+
+    // Select, operate and direct operate: the request's objects back with their status
+    fn control(&mut self, function: u8, seq: u8, request: &[u8]) -> (Vec<u8>, u16) {
+        let mut objects = request.to_vec();
+        let mut iin = 0;
+        let mut statuses = Vec::new();
+        for header in Headers::new(request, true) {
+            let Some(header) = header else {
+                iin |= IIN_PARAMETER_ERROR;
+                break;
+            };
+            let (Some(size), Range::Indexed(count, prefix)) = (object_size(header.group, header.variation), header.range) else {
+                iin |= IIN_UNKNOWN_OBJECT;
+                break;
+            };
+            if header.group != 12 && header.group != 41 {
+                iin |= IIN_UNKNOWN_OBJECT;
+                break;
+            }
+            for i in 0..count as usize {
+                let object = &header.data[i * (prefix + size)..(i + 1) * (prefix + size)];
+                let index = if prefix == 1 { object[0] as u16 } else { u16::from_le_bytes([object[0], object[1]]) };
+                let status_at = header.offset + (i + 1) * (prefix + size) - 1;
+                statuses.push((status_at, index, header.group, header.variation, object[prefix..].to_vec()));
+            }
+        }
+        if iin != 0 {
+            return (objects, iin);
+        }
+
+        let selected = match function {
+            OPERATE => self.selected.take().is_some_and(|(select_seq, select_objects, at)| {
+                select_seq.wrapping_add(1) & 0x0F == seq && at.elapsed() < SELECT_TIMEOUT && select_objects == request
+            }),
+            _ => true,
+        };
+        for (status_at, index, group, variation, object) in statuses {
+            let status = match self.command_value(index, group, variation, &object) {
+                Err(status) => status,
+                Ok(_) if !selected => NO_SELECT,
+                Ok((command, value)) if function != SELECT => {
+                    let tag = &self.station.table.tags()[command];
+                    match commands::write_tag(&self.station.table, "DNP3", &self.station.user, command, value) {
+                        Ok(_) => SUCCESS,
+                        Err(_) => {
+                            log::warn!("[DNP3] Failed to write '{}'", tag.name);
+                            HARDWARE_ERROR
+                        }
+                    }
+                }
+                Ok(_) => SUCCESS,
+            };
+            objects[status_at] = status;
+        }
+        if function == SELECT {
+            self.selected = Some((seq, request.to_vec(), Instant::now()));
+        }
+        (objects, iin)
+    }
+
+    // The tag and value a control object is for, Err with the status to answer with if it can't be done
+    fn command_value(&self, index: u16, group: u8, variation: u8, object: &[u8]) -> Result<(usize, TagValue), u8> {
+        let ty = if group == 12 { Dnp3Type::Binary } else { Dnp3Type::Analog };
+        let command = self.station.commands.iter().find(|command| command.ty == ty && command.index == index).ok_or(NOT_SUPPORTED)?;
+        let number = match (group, variation) {
+            (12, _) => match (object[0] >> 6, object[0] & 0x0F) {
+                (1, _) | (0, 1 | 3) => 1.0, // close, pulse on, latch on
+                (2, _) | (0, 2 | 4) => 0.0, // trip, pulse off, latch off
+                _ => return Err(NOT_SUPPORTED),
+            },
+            (41, 1) => i32::from_le_bytes([object[0], object[1], object[2], object[3]]) as f64,
+            (41, 2) => i16::from_le_bytes([object[0], object[1]]) as f64,
+            (41, 3) => f32::from_le_bytes([object[0], object[1], object[2], object[3]]) as f64,
+            (41, 4) => f64::from_le_bytes(object[..8].try_into().unwrap()),
+            _ => return Err(FORMAT_ERROR),
+        };
+        let tag = &self.station.table.tags()[command.tag];
+        match to_tag(tag.ty, number).filter(|value| tag.accepts(*value)) {
+            Some(value) => Ok((command.tag, value)),
+            None => {
+                log::warn!("[DNP3] Ignoring control of {}: {} isn't a value for '{}' {:?}", index, number, tag.name, tag.write_range());
+                Err(NOT_SUPPORTED)
+            }
+        }
+    }
+
+    // Events for the points that changed
+    fn changes(&mut self) {
+        for (i, point) in self.station.points.iter().enumerate() {
+            if point.class == 0 {
+                continue;
+            }
+            let sample = self.station.table.read_sample(point.tag);
+            let now = current(&sample);
+            let changed = match self.sent[i] {
+                None => true,
+                Some((value, good)) if point.deadband > 0.0 => good != now.1 || (value.as_f64() - now.0.as_f64()).abs() >= point.deadband,
+                Some(sent) => sent != now,
+            };
+            if !changed {
+                continue;
+            }
+            self.sent[i] = Some(now);
+            if self.events.len() >= MAX_EVENTS {
+                self.events.pop_front();
+                self.overflow = true;
+            }
+            self.events.push_back(Event { id: self.next_event, point: i, value: now.0, good: now.1, ts_ms: sample.ts_ms, in_flight: false });
+            self.next_event += 1;
+        }
+        if self.overflow && self.events.len() < MAX_EVENTS / 2 {
+            self.overflow = false;
+        }
+    }
+
+    async fn unsolicited_timers(&mut self) -> Result<(), String> {
+        if let Some(pending) = &mut self.unsolicited {
+            if pending.sent.elapsed() >= CONFIRM_TIMEOUT {
+                pending.sent = Instant::now();
+                let fragment = pending.fragment.clone();
+                return self.send_fragment(&fragment).await;
+            }
+            return Ok(());
+        }
+        if self.unsolicited_ready && self.solicited.is_none() {
+            let classes = self.unsolicited_classes;
+            if self.events.iter().any(|event| !event.in_flight && classes[self.station.points[event.point].class as usize - 1]) {
+                return self.send_unsolicited().await;
+            }
+        }
+        Ok(())
+    }
+
+    // An unsolicited response with the events of the enabled classes, the null one before the first was confirmed
+    async fn send_unsolicited(&mut self) -> Result<(), String> {
+        let events = if self.unsolicited_ready { self.take_events(self.unsolicited_classes, EVENTS_PER_RESPONSE) } else { Vec::new() };
+        let objects = self.event_objects(&events);
+        let seq = self.unsolicited_seq;
+        self.unsolicited_seq = (seq + 1) & 0x0F;
+        let fragment = self.fragment(APP_UNS | APP_CON | seq, UNSOLICITED_RESPONSE, &objects, 0);
+        self.send_fragment(&fragment).await?;
+        self.unsolicited = Some(Unconfirmed { seq, events, fragment, sent: Instant::now() });
+        Ok(())
+    }
+
+    async fn respond(&mut self, seq: u8, objects: &[u8], iin: u16, broadcast: bool) -> Result<(), String> {
+        if broadcast {
+            return Ok(());
+        }
+        let mut control = seq;
+        if let Some(pending) = &mut self.solicited && pending.fragment.is_empty() {
+            pending.seq = seq;
+            control |= APP_CON;
+        }
+        let fragment = self.fragment(control, RESPONSE, objects, iin);
+        if let Some(pending) = &mut self.solicited && pending.fragment.is_empty() {
+            pending.fragment = fragment.clone();
+        }
+        self.send_fragment(&fragment).await
+    }
+
+    // Up to `limit` events of `classes` that aren't in a response yet, marked as they are now
+    fn take_events(&mut self, classes: [bool; 3], limit: usize) -> Vec<u64> {
+        let points = &self.station.points;
+        self.events.iter_mut()
+            .filter(|event| !event.in_flight && classes[points[event.point].class as usize - 1])
+            .take(limit)
+            .map(|event| {
+                event.in_flight = true;
+                event.id
+            })
+            .collect()
+    }
+
+    // g2v2 and g32v7 objects for events `ids`, 2 octet count and index prefix
+    fn event_objects(&self, ids: &[u64]) -> Vec<u8> {
+        let ids: HashSet<u64> = ids.iter().copied().collect();
+        let mut objects = Vec::new();
+        for (ty, group, variation) in [(Dnp3Type::Binary, 2, 2), (Dnp3Type::Analog, 32, 7)] {
+            let events: Vec<&Event> = self.events.iter()
+                .filter(|event| ids.contains(&event.id) && self.station.points[event.point].ty == ty)
+                .collect();
+            if events.is_empty() {
+                continue;
+            }
+            objects.extend([group, variation, 0x28]);
+            objects.extend((events.len() as u16).to_le_bytes());
+            for event in events {
+                objects.extend(self.station.points[event.point].index.to_le_bytes());
+                objects.extend(point_value(ty, event.value, event.good));
+                objects.extend(&event.ts_ms.to_le_bytes()[..6]);
+            }
+        }
+        objects
+    }
+
+    // g1v2 or g30v5 objects for the points of `ty` in `range`, a header per run of consecutive indexes
+    fn static_data(&self, ty: Dnp3Type, range: &Range) -> Vec<u8> {
+        let (group, variation) = if ty == Dnp3Type::Binary { (1, 2) } else { (30, 5) };
+        let points: Vec<&Point> = self.station.points.iter()
+            .filter(|point| point.ty == ty)
+            .filter(|point| match *range {
+                Range::StartStop(start, stop) => (start..=stop).contains(&point.index),
+                _ => true,
+            })
+            .collect();
+        let mut objects = Vec::new();
+        for run in points.chunk_by(|a, b| a.index + 1 == b.index) {
+            objects.extend([group, variation, 0x01]);
+            objects.extend(run[0].index.to_le_bytes());
+            objects.extend(run[run.len() - 1].index.to_le_bytes());
+            for point in run {
+                let (value, good) = current(&self.station.table.read_sample(point.tag));
+                objects.extend(point_value(ty, value, good));
+            }
+        }
+        objects
+    }
+
+    fn fragment(&self, control: u8, function: u8, objects: &[u8], iin: u16) -> Vec<u8> {
+        let mut iin = iin;
+        if self.restart {
+            iin |= IIN_RESTART;
+        }
+        if self.overflow {
+            iin |= IIN_OVERFLOW;
+        }
+        for (class, bit) in IIN_CLASS.iter().enumerate() {
+            if self.events.iter().any(|event| !event.in_flight && self.station.points[event.point].class as usize == class + 1) {
+                iin |= bit;
+            }
+        }
+        let mut fragment = vec![APP_FIR | APP_FIN | control, function];
+        fragment.extend(iin.to_le_bytes());
+        fragment.extend_from_slice(objects);
+        fragment
+    }
+
+    // Split into transport segments, one link frame each
+    async fn send_fragment(&mut self, fragment: &[u8]) -> Result<(), String> {
+        log::trace!("[DNP3] to {}: {:02x?}", self.peer, fragment);
+        let segments: Vec<&[u8]> = fragment.chunks(MAX_SEGMENT).collect();
+        for (i, segment) in segments.iter().enumerate() {
+            let mut header = self.transport_seq;
+            self.transport_seq = (self.transport_seq + 1) & 0x3F;
+            if i == 0 {
+                header |= TRANSPORT_FIR;
+            }
+            if i == segments.len() - 1 {
+                header |= TRANSPORT_FIN;
+            }
+            let mut data = vec![header];
+            data.extend_from_slice(segment);
+            self.send_link(PRM | LINK_UNCONFIRMED_DATA, &data).await?;
+        }
+        Ok(())
+    }
+
+    async fn send_link(&mut self, control: u8, data: &[u8]) -> Result<(), String> {
+        let frame = link_frame(control, self.station.master, self.station.address, data);
+        self.stream.write_all(&frame).await.map_err(|e| e.to_string())
+    }
+}
+
+// Flags and value of a binary (g1v2/g2v2) or analog (g30v5/g32v7) object
+fn point_value(ty: Dnp3Type, value: TagValue, good: bool) -> Vec<u8> {
+    let flags = if good { ONLINE } else { 0 };
+    match ty {
+        Dnp3Type::Binary => vec![flags | if value.as_f64() != 0.0 { STATE } else { 0 }],
+        Dnp3Type::Analog => {
+            let mut object = vec![flags];
+            object.extend((value.as_f64() as f32).to_le_bytes());
+            object
+        }
+    }
+}
+
+// What an object carries: the value and whether it's valid
+fn current(sample: &TagSample) -> (TagValue, bool) {
+    (sample.value, sample.quality.is_good())
+}
+
+enum Range {
+    All,
+    StartStop(u16, u16),
+    Count(u16),
+    Indexed(u16, usize), // count of objects, each with an index prefix of this many octets
+}
+
+struct Header<'a> {
+    group: u8,
+    variation: u8,
+    range: Range,
+    offset: usize,  // where the objects after the header start in the request
+    data: &'a [u8], // those objects, empty in reads
+}
+
+// The object headers of a request, None for one that can't be parsed (and the end). `with_data` takes the objects
+// after each header into account, as in writes and controls.
+struct Headers<'a> {
+    request: &'a [u8],
+    at: usize,
+    with_data: bool,
+}
+
+impl<'a> Headers<'a> {
+    fn new(request: &'a [u8], with_data: bool) -> Self {
+        Self { request, at: 0, with_data }
+    }
+}
+
+impl<'a> Iterator for Headers<'a> {
+    type Item = Option<Header<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.at >= self.request.len() {
+            return None;
+        }
+        let header = self.parse();
+        if header.is_none() {
+            self.at = self.request.len();
+        }
+        Some(header)
+    }
+}
+
+impl<'a> Headers<'a> {
+    fn parse(&mut self) -> Option<Header<'a>> {
+        let rest = &self.request[self.at..];
+        let (&group, &variation, &qualifier) = (rest.first()?, rest.get(1)?, rest.get(2)?);
+        let u16_at = |at: usize| Some(u16::from_le_bytes([*rest.get(at)?, *rest.get(at + 1)?]));
+        let (range, len) = match qualifier {
+            0x00 => (Range::StartStop(*rest.get(3)? as u16, *rest.get(4)? as u16), 5),
+            0x01 => (Range::StartStop(u16_at(3)?, u16_at(5)?), 7),
+            0x06 => (Range::All, 3),
+            0x07 => (Range::Count(*rest.get(3)? as u16), 4),
+            0x08 => (Range::Count(u16_at(3)?), 5),
+            0x17 => (Range::Indexed(*rest.get(3)? as u16, 1), 4),
+            0x28 => (Range::Indexed(u16_at(3)?, 2), 5),
+            _ => return None,
+        };
+        let count = match range {
+            Range::All => 0,
+            Range::StartStop(start, stop) => (stop.checked_sub(start)? as usize) + 1,
+            Range::Count(count) => count as usize,
+            Range::Indexed(count, _) => count as usize,
+        };
+        let data_len = if !self.with_data {
+            0
+        } else {
+            match (group, variation, &range) {
+                (80, 1, _) => count.div_ceil(8), // packed bits
+                (_, _, Range::Indexed(_, prefix)) => count * (prefix + object_size(group, variation)?),
+                _ => count * object_size(group, variation)?,
+            }
+        };
+        let offset = self.at + len;
+        let data = self.request.get(offset..offset + data_len)?;
+        self.at = offset + data_len;
+        Some(Header { group, variation, range, offset, data })
+    }
+}
+
+// Octets of one object in a write or control
+fn object_size(group: u8, variation: u8) -> Option<usize> {
+    match (group, variation) {
+        (12, 1) => Some(11),
+        (41, 1) => Some(5),
+        (41, 2) => Some(3),
+        (41, 3) => Some(5),
+        (41, 4) => Some(9),
+        (50, 1) | (50, 3) => Some(6),
+        _ => None,
+    }
+}
+
+// Splits the next link frame off `buf` as its header (control, destination, source) and user data without the CRCs.
+// None until it's all there, bytes that don't start a valid frame are skipped, frames with a bad CRC dropped.
+fn take_frame(buf: &mut Vec<u8>) -> Option<Vec<u8>> {
+    loop {
+        let start = buf.windows(2).position(|window| window == LINK_START)?;
+        buf.drain(..start);
+        if buf.len() < 10 {
+            return None;
+        }
+        let total = match frame_len(&buf[..10]) {
+            Ok(total) => total,
+            Err(e) => {
+                log::debug!("[DNP3] {}, skipping", e);
+                buf.drain(..2);
+                continue;
+            }
+        };
+        if buf.len() < total {
+            return None;
+        }
+        let frame = decode_frame(&buf[..total]);
+        buf.drain(..total);
+        match frame {
+            Ok(frame) => return Some(frame),
+            Err(e) => log::debug!("[DNP3] {}, dropped", e),
+        }
+    }
+}
+
+// Octets of the whole link frame starting with `header`, the data's CRCs included
+fn frame_len(header: &[u8]) -> Result<usize, String> {
+    let Some(header) = header.get(..10) else {
+        return Err("Short link header".to_owned());
+    };
+    if header[..2] != LINK_START || header[2] < 5 || crc(&header[..8]) != u16::from_le_bytes([header[8], header[9]]) {
+        return Err("Bad link header".to_owned());
+    }
+    let data_len = header[2] as usize - 5;
+    Ok(10 + data_len + data_len.div_ceil(16) * 2)
+}
+
+// One whole link frame as its header (control, destination, source) and user data without the CRCs
+fn decode_frame(frame: &[u8]) -> Result<Vec<u8>, String> {
+    let total = frame_len(frame)?;
+    if frame.len() != total {
+        return Err(format!("Link frame of {} octets, its header says {}", frame.len(), total));
+    }
+    let mut decoded = frame[3..8].to_vec();
+    for block in frame[10..].chunks(18) {
+        let (data, block_crc) = block.split_at(block.len() - 2);
+        if crc(data) != u16::from_le_bytes([block_crc[0], block_crc[1]]) {
+            return Err("Bad CRC in link frame".to_owned());
+        }
+        decoded.extend_from_slice(data);
+    }
+    Ok(decoded)
+}
+
+// A link frame from us: header, then the data in blocks of 16 octets, each with its CRC
+fn link_frame(control: u8, destination: u16, source: u16, data: &[u8]) -> Vec<u8> {
+    let mut frame = vec![LINK_START[0], LINK_START[1], (5 + data.len()) as u8, control];
+    frame.extend(destination.to_le_bytes());
+    frame.extend(source.to_le_bytes());
+    frame.extend(crc(&frame).to_le_bytes());
+    for block in data.chunks(16) {
+        frame.extend_from_slice(block);
+        frame.extend(crc(block).to_le_bytes());
+    }
+    frame
+}
+
+// CRC-16/DNP
+fn crc(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xA6BC } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // master (1024) to outstation (1)
+    const MASTER: u16 = 1024;
+    const OUTSTATION: u16 = 1;
+
+    // A request from the master as it goes on the wire: link header, one transport segment, the CRCs
+    fn request(control: u8, fragment: &[u8]) -> Vec<u8> {
+        let mut data = vec![TRANSPORT_FIR | TRANSPORT_FIN];
+        data.extend_from_slice(fragment);
+        link_frame(DIR | PRM | control, OUTSTATION, MASTER, &data)
+    }
+
+    #[test]
+    fn crc_is_crc16_dnp() {
+        assert_eq!(crc(b"123456789"), 0xEA82);
+    }
+
+    #[test]
+    fn reset_link() {
+        // as a master sends it, CRC included
+        let mut buf = vec![0x05, 0x64, 0x05, 0xC0, 0x01, 0x00, 0x00, 0x04, 0xE9, 0x21];
+        assert_eq!(take_frame(&mut buf), Some(vec![0xC0, 0x01, 0x00, 0x00, 0x04]));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn class_0_poll() {
+        // read, seq 1, g60v1 all objects
+        let mut buf = request(LINK_UNCONFIRMED_DATA, &[APP_FIR | APP_FIN | 1, READ, 60, 1, 0x06]);
+        let frame = take_frame(&mut buf).expect("frame");
+        assert_eq!(frame[..5], [DIR | PRM | LINK_UNCONFIRMED_DATA, 0x01, 0x00, 0x00, 0x04]);
+        assert_eq!(frame[5..], [TRANSPORT_FIR | TRANSPORT_FIN, APP_FIR | APP_FIN | 1, READ, 60, 1, 0x06]);
+
+        let headers: Vec<_> = Headers::new(&frame[8..], false).collect();
+        assert!(matches!(headers[..], [Some(Header { group: 60, variation: 1, range: Range::All, .. })]));
+    }
+
+    #[test]
+    fn data_across_crc_blocks() {
+        // each 16 octets of data get their own CRC, the last block is whatever is left
+        for len in [1usize, 15, 16, 17, 32, 33, 250] {
+            let data: Vec<u8> = (0..len as u8).collect();
+            let wire = link_frame(DIR | PRM | LINK_UNCONFIRMED_DATA, OUTSTATION, MASTER, &data);
+            assert_eq!(wire.len(), 10 + len + len.div_ceil(16) * 2, "{} octets", len);
+            assert_eq!(decode_frame(&wire).map(|frame| frame[5..].to_vec()), Ok(data.clone()), "{} octets", len);
+
+            // a frame trickling in is taken once it's all there
+            let mut buf = Vec::new();
+            for (i, &byte) in wire.iter().enumerate() {
+                buf.push(byte);
+                let frame = take_frame(&mut buf);
+                assert_eq!(frame.is_some(), i == wire.len() - 1, "{} octets, at {}", len, i);
+            }
+            assert!(buf.is_empty());
+        }
+    }
+
+    #[test]
+    fn short_and_garbled_frames() {
+        let wire = request(LINK_UNCONFIRMED_DATA, &[APP_FIR | APP_FIN, READ, 60, 2, 0x06, 60, 3, 0x06, 60, 4, 0x06, 60, 1, 0x06, 1, 2, 0x06, 30, 5, 0x06]);
+        assert!(wire.len() > 28, "data in two blocks");
+
+        // name, frame
+        let mut length_below_header = wire.clone();
+        length_below_header[2] = 4;
+        let mut bad_header_crc = wire.clone();
+        bad_header_crc[8] ^= 0x01;
+        let mut bad_data_crc = wire.clone();
+        bad_data_crc[10 + 16 + 2] ^= 0x01; // first octet of the second block
+        let cases: &[(&str, &[u8])] = &[
+            ("empty", &[]),
+            ("start only", &LINK_START),
+            ("short header", &wire[..9]),
+            ("truncated data", &wire[..wire.len() - 1]),
+            ("length below header", &length_below_header),
+            ("bad header CRC", &bad_header_crc),
+            ("bad data CRC", &bad_data_crc),
+            ("no start", &wire[1..]),
+        ];
+        for &(name, frame) in cases {
+            assert!(decode_frame(frame).is_err(), "{}", name);
+            let mut buf = frame.to_vec();
+            assert_eq!(take_frame(&mut buf), None, "{}", name);
+        }
+
+        // garbage and a broken frame ahead of a good one are skipped
+        let mut buf = vec![0x00, 0x05, 0xFF];
+        buf.extend_from_slice(&bad_data_crc);
+        buf.extend_from_slice(&bad_header_crc);
+        buf.extend_from_slice(&wire);
+        assert_eq!(take_frame(&mut buf), decode_frame(&wire).ok());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn malformed_object_headers() {
+        // request, with objects (write/control), whether it parses
+        let cases: &[(&[u8], bool, bool)] = &[
+            (&[60], false, false),
+            (&[60, 1], false, false),
+            (&[1, 2, 0x00, 5], false, false),                      // start/stop cut short
+            (&[1, 2, 0x00, 5, 3], false, false),                   // stop before start
+            (&[1, 2, 0x01, 0, 0, 0xFF, 0xFF], false, true),        // 0-65535
+            (&[1, 2, 0x5B, 1], false, false),                      // qualifier we don't take
+            (&[12, 1, 0x28, 1, 0, 0, 0, 0x03, 1], true, false),    // CROB cut short
+            (&[12, 9, 0x28, 1, 0, 0, 0], true, false),             // unknown object size
+            (&[41, 2, 0x17, 1, 0, 0x10, 0x00, 0], true, true),     // 16 to analog output 0
+        ];
+        for &(request, with_data, parses) in cases {
+            let headers: Vec<_> = Headers::new(request, with_data).collect();
+            assert_eq!(headers.len(), 1, "{:02x?}", request);
+            assert_eq!(headers[0].is_some(), parses, "{:02x?}", request);
+        }
+    }
+}
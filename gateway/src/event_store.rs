@@ -0,0 +1,107 @@
+// Alarm and event history ([event_store] in gipop.toml, `event_store` feature): the PLC's alarms going active,
+// clearing and acknowledged, EnOcean telegrams and the audit log's entries recorded into an SQLite database
+// (historian::event_store), so the history survives restarts of the PLC and its consumers. GET /api/events reads it
+// back, as does the OPC UA server's HistoryReadEvents when its [opcua.history] events_path points at the same file.
+//
+// Each event is stored with whether the PLC's clock was synchronized at the time ([time_sync] on the PLC, its
+// gipop_shm::time_sync::SYNCED_TAG), a sequence of events recorded off a drifting clock can't be trusted to line up
+// with anything else. Unknown when the PLC doesn't monitor its clock.
+//
+// Events older than retention_days and the oldest beyond max_events are deleted on start and then hourly. Blocking,
+// run it on its own thread with a Subscriber of its own.
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use gipop_shm::time_sync::SYNCED_TAG;
+use gipop_shm::Subscriber;
+use historian::event_store::EventStore;
+use historian::record::{EventQuery, StoredEvent};
+
+use crate::audit_log::AuditLog;
+use crate::config::EventStoreConfig;
+use crate::webhooks::{event_name, Notification};
+
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+const AUDIT_SEVERITY: u16 = 300; // operator actions, like the OPC UA server's audit events
+
+/// Records until `stop`, Err if the database can't be opened
+pub fn run(config: EventStoreConfig, events: Subscriber, stop: &AtomicBool) -> Result<(), String> {
+    let store = EventStore::open(&config.path)?;
+    let max_age = (config.retention_days > 0).then(|| Duration::from_secs(config.retention_days * 24 * 3600));
+    let mut audit_log = (!config.audit_log.is_empty()).then(|| AuditLog::follow(&config.audit_log, "Event store"));
+    log::info!("[Event store] Recording alarms, events{} to {}", if audit_log.is_some() { " and audit entries" } else { "" }, config.path);
+
+    let mut pruned: Option<Instant> = None;
+    let mut failing = false;
+    while !stop.load(Ordering::Relaxed) {
+        if pruned.is_none_or(|at| at.elapsed() >= PRUNE_INTERVAL) {
+            match store.prune(max_age, config.max_events) {
+                Ok(0) => {}
+                Ok(deleted) => log::info!("[Event store] Deleted {} expired event(s)", deleted),
+                Err(e) => log::warn!("[Event store] Pruning failed: {}", e),
+            }
+            pruned = Some(Instant::now());
+        }
+
+        let clock_synced = clock_synced(&events);
+        let mut batch = Vec::new();
+        while let Some(event) = events.pop_event() {
+            if let Some(notification) = Notification::from_event(&events, &event) {
+                batch.push(StoredEvent {
+                    id: 0,
+                    ts_ms: notification.ts_ms,
+                    kind: event_name(notification.event).to_owned(),
+                    source: notification.tag,
+                    severity: notification.severity,
+                    user: String::new(),
+                    message: notification.message,
+                    clock_synced,
+                });
+            }
+        }
+        for entry in audit_log.as_mut().map(AuditLog::new_entries).unwrap_or_default() {
+            batch.push(StoredEvent {
+                id: 0,
+                ts_ms: entry.ts_ms,
+                kind: "audit".to_owned(),
+                source: String::new(),
+                severity: AUDIT_SEVERITY,
+                user: entry.user,
+                message: entry.action,
+                clock_synced,
+            });
+        }
+        for event in &batch {
+            let recorded = store.record(event);
+            // once per failure, not for every event
+            match &recorded {
+                Err(e) if !failing => log::error!("[Event store] Not recording: {}", e),
+                Ok(_) if failing => log::info!("[Event store] Recording again"),
+                _ => {}
+            }
+            failing = recorded.is_err();
+        }
+        std::thread::sleep(Duration::from_millis(config.poll_ms));
+    }
+    Ok(())
+}
+
+// The PLC's verdict on its clock, None while it isn't monitoring it
+fn clock_synced(table: &Subscriber) -> Option<bool> {
+    let sample = table.read_sample(table.index_of(SYNCED_TAG)?);
+    sample.quality.is_good().then(|| sample.value.as_f64() != 0.0)
+}
+
+/// The stored events read back, for GET /api/events. A connection of its own next to the recorder's
+pub struct Events(Mutex<EventStore>);
+
+impl Events {
+    pub fn open(path: &str) -> Result<Self, String> {
+        EventStore::open(path).map(|store| Self(Mutex::new(store)))
+    }
+
+    pub fn query(&self, query: &EventQuery) -> Result<Vec<StoredEvent>, String> {
+        self.0.lock().unwrap().query(query)
+    }
+}
@@ -0,0 +1,60 @@
+// Trend files ([file_log] in gipop.toml): the selected tags every interval_ms as a row of CSV or Parquet
+// (historian::file_log, Parquet needs the `parquet` feature), one file per hour or day, files older than keep_days
+// deleted. Rows fall on multiples of the interval so files from different days line up. Bad quality values are left
+// empty, rows where no value got through [compression] are left out. Blocking, run it on its own thread and set `stop` to have the current file closed properly: a Parquet file
+// that isn't closed can't be read.
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use gipop_shm::tags::now_ms;
+use gipop_shm::Subscriber;
+use historian::compression::CompressionSettings;
+use historian::file_log::FileLogger;
+
+use crate::config::FileLogConfig;
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10); // at most this much is lost with a power cut (CSV)
+const STOP_CHECK: Duration = Duration::from_millis(100);
+
+/// Logs until `stop` is set, Err if the configuration doesn't fit the tag table or the directory can't be created
+pub fn run(config: FileLogConfig, compression: CompressionSettings, table: Subscriber, stop: &AtomicBool) -> Result<(), String> {
+    let tags: Vec<usize> = if config.tags.is_empty() {
+        (0..table.tags().len()).collect()
+    } else {
+        config.tags.iter()
+            .map(|name| table.index_of(name).ok_or_else(|| format!("No tag named '{}' in [file_log] tags", name)))
+            .collect::<Result<_, _>>()?
+    };
+    let columns = tags.iter().map(|&idx| table.tags()[idx].name.clone()).collect();
+    let max_age = Duration::from_secs(config.keep_days * 24 * 3600);
+    let mut logger = FileLogger::new(&config.dir, &config.prefix, config.format, config.rotation, columns, max_age)
+        .map_err(|e| format!("Failed to log to {}: {}", config.dir, e))?
+        .with_compression(compression);
+    log::info!("[File log] {} tag(s) every {} ms to {} ({:?})", tags.len(), config.interval_ms, config.dir, config.format);
+
+    let interval = config.interval_ms.max(1);
+    let mut last_flush = Instant::now();
+    loop {
+        let ts_ms = (now_ms() / interval + 1) * interval;
+        while let Some(wait) = ts_ms.checked_sub(now_ms()).filter(|&wait| wait > 0) {
+            if stop.load(Ordering::Relaxed) {
+                return logger.close().map_err(|e| format!("Failed to close the current file: {}", e));
+            }
+            std::thread::sleep(Duration::from_millis(wait).min(STOP_CHECK));
+        }
+
+        let values: Vec<Option<f64>> = tags.iter()
+            .map(|&idx| table.read_sample(idx))
+            .map(|sample| sample.quality.is_good().then(|| sample.value.as_f64()))
+            .collect();
+        if let Err(e) = logger.log(ts_ms, &values) {
+            log::error!("[File log] Failed to write: {}", e);
+        }
+        if last_flush.elapsed() >= FLUSH_INTERVAL {
+            last_flush = Instant::now();
+            if let Err(e) = logger.flush() {
+                log::error!("[File log] Failed to write: {}", e);
+            }
+        }
+    }
+}
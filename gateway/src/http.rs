@@ -0,0 +1,623 @@
+// REST API ([http] in gipop.toml), JSON over HTTP for integrations that find OPC UA too heavy:
+//
+// GET    /api/tags                    every tag: name, folder, type, unit, value, quality, ts, writable
+// GET    /api/tags/{name}             one tag (names are URL encoded, "area%201%20lights")
+// PUT    /api/tags/{name}             {"value": 3}, writable tags only
+// GET    /api/alarms                  every alarm tag: name, severity, active, acked
+// POST   /api/alarms/{name}/ack
+// GET    /api/forces                  forced DO channels: channel ("EBus/DO0/Ch2"), value
+// PUT    /api/forces/{channel}        {"value": true}
+// DELETE /api/forces/{channel}        release the force
+// PUT    /api/log-level               {"module": "plc::ctrl_loop", "level": "debug"}, the PLC's log level for a module
+//                                     ("" or none for all), "level": "" for what its [logging] says again
+// POST   /api/config/reload           read gipop.toml again: the PLC applies what it can without stopping the bus and
+//                                     refuses the rest (plc/src/reload.rs, the outcome is in its log and diagnostics),
+//                                     the gateway its [notify] recipients and [webhooks] hooks
+// GET    /api/health                  whether the PLC is there and alive, 503 if not
+// GET    /api/diagnostics             PLC build and project, EtherCAT bus and SubDevice diagnostics
+// GET    /api/trends                  numeric tags' last half hour every 5 s: {"temperature": [[ts, 21.5], ...]}
+// GET    /api/events                  stored alarms, events and audit entries newest first, with [event_store]:
+//                                     ?from=&to= (epoch ms) &kind=alarm,ack &source=<alarm tag> &min_severity= &limit=
+//                                     clock_synced false on events stamped while the PLC's clock wasn't synchronized
+// GET    /healthz                     200 while the PLC's shared memory is attached and its heartbeat moves, 503 if not
+// GET    /readyz                      200 when also every SubDevice is in OP without an error, the K-bus is fine and the
+//                                     average scan is within scan_budget_us, 503 with the checks that failed if not
+//
+// /healthz and /readyz are for container orchestrators and load balancers and take no api key or token, they only
+// say how the controller is doing.
+//
+// With hmi, / serves a web HMI (hmi.html, built in) on top of the API for sites without a SCADA: trend tiles, the tag
+// table, the alarm list and on/off switches for writable boolean tags. It works with the API's rules, switching and
+// acknowledging take an api key or token, entered on the page and kept in the browser.
+//
+// Callers identify with their api_key from [users] in the X-Api-Key header or, with jwt_secret or jwt_public_key, a
+// JWT in Authorization: Bearer whose sub is their [users] entry. What they may do follows their role like on every
+// other interface. Without either they're anonymous viewers, which only gets them the GETs with anonymous_read; a key
+// or token that doesn't check out is a 401 whatever the request. Commands are audited like OPC UA writes. Errors are
+// {"error": "..."} with a fitting status code.
+//
+// With tls_cert and tls_key the API and HMI are served over HTTPS (rustls, `tls` feature) and nothing listens for plain
+// HTTP. Serving plain HTTP beyond the loopback interface is logged as a warning on every start.
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::extract::{Extension, Path, Query, Request, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::{get, post, put};
+use axum::{Json, Router};
+use gipop_shm::tags::iso_time;
+use gipop_shm::io_mirror::{IO_DO, IO_STATUS_FORCED};
+use gipop_shm::ring::{ITEM_ALARM, ITEM_ALARM_ACK};
+use gipop_shm::{Action, IoAddress, Liveness, RingItem, Subscriber, TagDef, TagSample, TagType, Users};
+use historian::record::{EventQuery, StoredEvent};
+use serde_json::{json, Value};
+
+use crate::commands::{self, json_value, to_tag};
+use crate::config::{self, HttpConfig};
+#[cfg(feature = "event_store")]
+use crate::event_store::Events;
+
+const API_KEY_HEADER: &str = "x-api-key";
+const MONITOR_INTERVAL: Duration = Duration::from_millis(100);
+const TREND_INTERVAL: Duration = Duration::from_secs(5);
+const TREND_POINTS: usize = 360; // half an hour
+const HMI: &str = include_str!("hmi.html");
+const EVENTS_LIMIT: usize = 1000; // per GET /api/events without a limit, and at most with one
+
+struct HttpApi {
+    table: Subscriber,
+    users: Users,
+    anonymous_read: bool,
+    acked: Mutex<HashMap<usize, bool>>, // per alarm tag, from the PLC's alarm and acknowledge events
+    plc_alive: AtomicBool,
+    scan_budget_us: u32,
+    trends: Mutex<Vec<(usize, Trend)>>, // per numeric tag
+    jwt: Option<Jwt>,
+    events: Option<Events>,
+}
+
+// Who's behind a request, None for anonymous. Put in the request's extensions by identify()
+#[derive(Clone)]
+struct Caller(Option<String>);
+
+type Trend = VecDeque<(u64, f64)>; // ts, value, the oldest first
+
+type Api = Arc<HttpApi>;
+
+struct ApiError(StatusCode, String);
+
+type ApiResult<T> = Result<T, ApiError>;
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+/// Serves the API until the process ends, Err if it can't listen or the TLS or JWT settings don't work out.
+/// `event_store` is [event_store] path, the database GET /api/events reads, "" for none.
+pub async fn serve(config: HttpConfig, table: Subscriber, users: Users, heartbeat_timeout: Duration, event_store: &str) -> Result<(), String> {
+    let jwt = Jwt::from_config(&config)?;
+    let events = match event_store {
+        "" => None,
+        path => Events::open(path).inspect_err(|e| log::warn!("[HTTP] No /api/events: {}", e)).ok(),
+    };
+    let acked = table.tags().iter().enumerate().filter(|(_, tag)| tag.is_alarm()).map(|(idx, _)| (idx, true)).collect();
+    let trends = table.tags().iter().enumerate().filter(|(_, tag)| tag.ty != TagType::Bool).map(|(idx, _)| (idx, VecDeque::new())).collect();
+    let api = Arc::new(HttpApi {
+        table,
+        users,
+        anonymous_read: config.anonymous_read,
+        acked: Mutex::new(acked),
+        plc_alive: AtomicBool::new(false),
+        scan_budget_us: config.scan_budget_us,
+        trends: Mutex::new(trends),
+        jwt,
+        events,
+    });
+    tokio::spawn(monitor(api.clone(), heartbeat_timeout));
+
+    let mut app = Router::new()
+        .route("/api/tags", get(list_tags))
+        .route("/api/tags/{name}", get(get_tag).put(put_tag))
+        .route("/api/alarms", get(list_alarms))
+        .route("/api/alarms/{name}/ack", post(ack_alarm))
+        .route("/api/forces", get(list_forces))
+        .route("/api/forces/{*channel}", put(force).delete(unforce))
+        .route("/api/log-level", put(log_level))
+        .route("/api/config/reload", post(reload_config))
+        .route("/api/health", get(health))
+        .route("/api/diagnostics", get(diagnostics))
+        .route("/api/trends", get(list_trends))
+        .route("/api/events", get(list_events))
+        .route_layer(middleware::from_fn_with_state(api.clone(), identify))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz));
+    if config.hmi {
+        app = app.route("/", get(|| async { Html(HMI) }));
+    }
+    let app = app.with_state(api);
+
+    let listener = tokio::net::TcpListener::bind(&config.listen).await.map_err(|e| format!("Failed to listen on {}: {}", config.listen, e))?;
+    let what = if config.hmi { "REST API and HMI" } else { "REST API" };
+    if !config.tls_cert.is_empty() || !config.tls_key.is_empty() {
+        return serve_tls(listener, app, &config, what).await;
+    }
+    log::info!("[HTTP] {} listening on http://{}", what, config.listen);
+    if listener.local_addr().is_ok_and(|address| !address.ip().is_loopback()) {
+        log::warn!("[HTTP] Plain HTTP on {}: api keys, tokens and writes cross the network readable, set tls_cert and tls_key", config.listen);
+    }
+    axum::serve(listener, app).await.map_err(|e| e.to_string())
+}
+
+// Resolves the caller's api key or token for the handlers, rejects the request when it doesn't check out
+async fn identify(State(api): State<Api>, mut request: Request, next: Next) -> Result<Response, ApiError> {
+    let caller = api.caller(request.headers())?;
+    request.extensions_mut().insert(Caller(caller));
+    Ok(next.run(request).await)
+}
+
+// Follows the PLC's heartbeat and its alarm events, the event ring is drained here and nowhere else in this process.
+// Samples the trends too
+async fn monitor(api: Api, heartbeat_timeout: Duration) {
+    let mut plc = Liveness::new(heartbeat_timeout);
+    let mut interval = tokio::time::interval(MONITOR_INTERVAL);
+    let mut sampled: Option<Instant> = None;
+    loop {
+        interval.tick().await;
+        if sampled.is_none_or(|at| at.elapsed() >= TREND_INTERVAL) {
+            sampled = Some(Instant::now());
+            for (idx, points) in api.trends.lock().unwrap().iter_mut() {
+                let sample = api.table.read_sample(*idx);
+                if !sample.quality.is_good() {
+                    continue; // a gap in the trend
+                }
+                if points.len() == TREND_POINTS {
+                    points.pop_front();
+                }
+                points.push_back((sample.ts_ms, sample.value.as_f64()));
+            }
+        }
+        match plc.update(api.table.plc_heartbeat()) {
+            Some(true) => log::info!("[HTTP] PLC heartbeat detected"),
+            Some(false) => log::warn!("[HTTP] PLC heartbeat went stale"),
+            None => {}
+        }
+        api.plc_alive.store(plc.is_alive(), Ordering::Relaxed);
+
+        while let Some(event) = api.table.pop_event() {
+            let mut acked = api.acked.lock().unwrap();
+            match event.kind {
+                ITEM_ALARM if event.value != 0 => _ = acked.insert(event.tag as usize, false),
+                ITEM_ALARM_ACK => _ = acked.insert(event.tag as usize, true),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl HttpApi {
+    // The user behind the request's api key or bearer token, None without either
+    fn caller(&self, headers: &HeaderMap) -> ApiResult<Option<String>> {
+        let unauthorized = |e: &str| ApiError(StatusCode::UNAUTHORIZED, e.to_owned());
+        if let Some(key) = headers.get(API_KEY_HEADER) {
+            let key = key.to_str().map_err(|_| unauthorized("Malformed api key"))?;
+            return self.users.by_api_key(key).map(|user| Some(user.to_owned())).ok_or_else(|| unauthorized("Unknown api key"));
+        }
+        let Some(authorization) = headers.get(header::AUTHORIZATION) else {
+            return Ok(None);
+        };
+        let token = authorization.to_str().ok()
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| unauthorized("Authorization takes Bearer <token>"))?;
+        let jwt = self.jwt.as_ref().ok_or_else(|| unauthorized("Bearer tokens aren't taken here, no jwt_secret or jwt_public_key"))?;
+        jwt.verify(token.trim()).map(Some).map_err(|e| {
+            log::warn!("[HTTP] Rejected a bearer token: {}", e);
+            ApiError(StatusCode::UNAUTHORIZED, format!("Invalid token: {}", e))
+        })
+    }
+
+    // Who's asking, if they may do `action`
+    fn authorize(&self, caller: &Caller, action: Action) -> ApiResult<String> {
+        let user = caller.0.clone();
+        if user.is_none() && (action != Action::Read || !self.anonymous_read) {
+            return Err(ApiError(StatusCode::UNAUTHORIZED, format!("{:?} needs an api key or token", action)));
+        }
+        self.users.authorize(user.as_deref(), action).map_err(|e| {
+            log::warn!("[HTTP] {}", e);
+            ApiError(StatusCode::FORBIDDEN, e)
+        })?;
+        Ok(user.unwrap_or_else(|| "anonymous".to_owned()))
+    }
+
+    fn index_of(&self, name: &str) -> ApiResult<usize> {
+        self.table.index_of(name).ok_or_else(|| ApiError(StatusCode::NOT_FOUND, format!("No tag named '{}'", name)))
+    }
+
+    // A DO channel that exists, the only kind the PLC forces
+    fn do_channel(&self, channel: &str) -> ApiResult<IoAddress> {
+        let address = IoAddress::parse(channel)
+            .filter(|address| self.table.read_io().iter().any(|io| io.address() == *address))
+            .ok_or_else(|| ApiError(StatusCode::NOT_FOUND, format!("No I/O channel {}", channel)))?;
+        if address.kind != IO_DO {
+            return Err(ApiError(StatusCode::BAD_REQUEST, format!("{} isn't a DO channel, only those can be forced", channel)));
+        }
+        Ok(address)
+    }
+}
+
+fn accepted(sent: Result<u32, String>) -> ApiResult<(StatusCode, Json<Value>)> {
+    let seq = sent.map_err(|e| ApiError(StatusCode::SERVICE_UNAVAILABLE, e))?;
+    Ok((StatusCode::ACCEPTED, Json(json!({ "seq": seq }))))
+}
+
+fn tag_json(tag: &TagDef, sample: &TagSample) -> Value {
+    json!({
+        "name": tag.name,
+        "folder": tag.folder,
+        "type": format!("{:?}", tag.ty),
+        "unit": tag.unit,
+        "value": json_value(sample.value),
+        "quality": format!("{:?}", sample.quality),
+        "ts": sample.ts_ms,
+        "writable": tag.writable(),
+    })
+}
+
+async fn list_tags(State(api): State<Api>, Extension(caller): Extension<Caller>) -> ApiResult<Json<Value>> {
+    api.authorize(&caller, Action::Read)?;
+    let samples = api.table.read_all_samples();
+    Ok(Json(api.table.tags().iter().zip(&samples).map(|(tag, sample)| tag_json(tag, sample)).collect()))
+}
+
+async fn get_tag(State(api): State<Api>, Extension(caller): Extension<Caller>, Path(name): Path<String>) -> ApiResult<Json<Value>> {
+    api.authorize(&caller, Action::Read)?;
+    let idx = api.index_of(&name)?;
+    Ok(Json(tag_json(&api.table.tags()[idx], &api.table.read_sample(idx))))
+}
+
+async fn put_tag(State(api): State<Api>, Extension(caller): Extension<Caller>, Path(name): Path<String>, Json(body): Json<Value>) -> ApiResult<(StatusCode, Json<Value>)> {
+    let user = api.authorize(&caller, Action::WriteTag)?;
+    let idx = api.index_of(&name)?;
+    let tag = &api.table.tags()[idx];
+    if !tag.writable() {
+        return Err(ApiError(StatusCode::FORBIDDEN, format!("Tag '{}' is read only", name)));
+    }
+    let number = match body.get("value") {
+        Some(Value::Bool(b)) => Some(*b as u8 as f64),
+        Some(Value::Number(n)) => n.as_f64(),
+        _ => None,
+    };
+    let value = number.and_then(|number| to_tag(tag.ty, number)).filter(|value| tag.accepts(*value)).ok_or_else(|| {
+        let range = tag.write_range().map(|(lo, hi)| format!(" from {} to {}", lo, hi)).unwrap_or_default();
+        ApiError(StatusCode::BAD_REQUEST, format!("Tag '{}' takes {{\"value\": ...}}, a {:?}{}", name, tag.ty, range))
+    })?;
+    accepted(commands::write_tag(&api.table, "HTTP", &user, idx, value))
+}
+
+async fn list_alarms(State(api): State<Api>, Extension(caller): Extension<Caller>) -> ApiResult<Json<Value>> {
+    api.authorize(&caller, Action::Read)?;
+    let acked = api.acked.lock().unwrap().clone();
+    let alarms: Vec<Value> = api.table.tags().iter().enumerate()
+        .filter(|(_, tag)| tag.is_alarm())
+        .map(|(idx, tag)| {
+            let sample = api.table.read_sample(idx);
+            json!({
+                "name": tag.name,
+                "severity": tag.severity,
+                "active": sample.value.as_f64() != 0.0,
+                "acked": acked.get(&idx).copied().unwrap_or(true),
+                "ts": sample.ts_ms,
+            })
+        })
+        .collect();
+    Ok(Json(alarms.into()))
+}
+
+async fn ack_alarm(State(api): State<Api>, Extension(caller): Extension<Caller>, Path(name): Path<String>) -> ApiResult<(StatusCode, Json<Value>)> {
+    let user = api.authorize(&caller, Action::AckAlarm)?;
+    let idx = api.index_of(&name)?;
+    if !api.table.tags()[idx].is_alarm() {
+        return Err(ApiError(StatusCode::NOT_FOUND, format!("Tag '{}' isn't an alarm", name)));
+    }
+    accepted(commands::send(&api.table, "HTTP", &user, RingItem::alarm_ack(idx), &format!("acknowledged '{}'", name)))
+}
+
+async fn list_forces(State(api): State<Api>, Extension(caller): Extension<Caller>) -> ApiResult<Json<Value>> {
+    api.authorize(&caller, Action::Read)?;
+    let forces: Vec<Value> = api.table.read_io().iter()
+        .filter(|channel| channel.status & IO_STATUS_FORCED != 0)
+        .map(|channel| json!({ "channel": channel.path(), "value": channel.value != 0.0 }))
+        .collect();
+    Ok(Json(forces.into()))
+}
+
+async fn force(State(api): State<Api>, Extension(caller): Extension<Caller>, Path(channel): Path<String>, Json(body): Json<Value>) -> ApiResult<(StatusCode, Json<Value>)> {
+    let user = api.authorize(&caller, Action::ForceIo)?;
+    let address = api.do_channel(&channel)?;
+    let value = body.get("value").and_then(Value::as_bool)
+        .ok_or_else(|| ApiError(StatusCode::BAD_REQUEST, "Forces take {\"value\": true/false}".to_owned()))?;
+    let what = format!("forced {} to {}", address.path(), value);
+    accepted(commands::send(&api.table, "HTTP", &user, RingItem::force(address, value as u8 as f64), &what))
+}
+
+async fn unforce(State(api): State<Api>, Extension(caller): Extension<Caller>, Path(channel): Path<String>) -> ApiResult<(StatusCode, Json<Value>)> {
+    let user = api.authorize(&caller, Action::ForceIo)?;
+    let address = api.do_channel(&channel)?;
+    let what = format!("released the force on {}", address.path());
+    accepted(commands::send(&api.table, "HTTP", &user, RingItem::unforce(address), &what))
+}
+
+async fn log_level(State(api): State<Api>, Extension(caller): Extension<Caller>, Json(body): Json<Value>) -> ApiResult<(StatusCode, Json<Value>)> {
+    let user = api.authorize(&caller, Action::LogLevel)?;
+    let module = body.get("module").and_then(Value::as_str).unwrap_or("");
+    let level = body.get("level").and_then(Value::as_str)
+        .ok_or_else(|| ApiError(StatusCode::BAD_REQUEST, "Log levels take {\"module\": \"...\", \"level\": \"debug\"}".to_owned()))?;
+    let level = gipop_shm::logging::parse_level(level).map_err(|e| ApiError(StatusCode::BAD_REQUEST, e))?;
+    let item = RingItem::log_level(module, level).map_err(|e| ApiError(StatusCode::BAD_REQUEST, e))?;
+    let what = match level {
+        Some(level) => format!("set the log level of '{}' to {}", module, level.as_str().to_lowercase()),
+        None => format!("reset the log level of '{}'", module),
+    };
+    accepted(commands::send(&api.table, "HTTP", &user, item, &what))
+}
+
+async fn reload_config(State(api): State<Api>, Extension(caller): Extension<Caller>) -> ApiResult<(StatusCode, Json<Value>)> {
+    let user = api.authorize(&caller, Action::ReloadConfig)?;
+    config::RELOADS.fetch_add(1, Ordering::Relaxed);
+    accepted(commands::send(&api.table, "HTTP", &user, RingItem::reload_config(), "asked for a config reload"))
+}
+
+async fn health(State(api): State<Api>, Extension(caller): Extension<Caller>) -> ApiResult<(StatusCode, Json<Value>)> {
+    api.authorize(&caller, Action::Read)?;
+    let (connected, alive) = (api.table.is_connected(), api.plc_alive.load(Ordering::Relaxed));
+    let status = if connected && alive { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    Ok((status, Json(json!({ "plc_connected": connected, "plc_alive": alive }))))
+}
+
+async fn healthz(State(api): State<Api>) -> (StatusCode, Json<Value>) {
+    let (connected, alive) = (api.table.is_connected(), api.plc_alive.load(Ordering::Relaxed));
+    let status = if connected && alive { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(json!({ "shm_attached": connected, "plc_alive": alive })))
+}
+
+async fn readyz(State(api): State<Api>) -> (StatusCode, Json<Value>) {
+    let mut failed = Vec::new();
+    if !api.table.is_connected() {
+        failed.push("shared memory not attached".to_owned());
+    }
+    else if !api.plc_alive.load(Ordering::Relaxed) {
+        failed.push("PLC heartbeat stopped".to_owned());
+    }
+    let diag = api.table.read_bus_diag();
+    match &diag {
+        None => failed.push("no bus diagnostics yet".to_owned()),
+        Some(diag) => {
+            if diag.subdevices().is_empty() {
+                failed.push("no SubDevices on the bus".to_owned());
+            }
+            for sub in diag.subdevices().iter().filter(|sub| sub.al_state & 0x0f != 0x8 || sub.has_error()) {
+                let error = if sub.has_error() { format!(", error 0x{:04x}", sub.al_status_code) } else { String::new() };
+                failed.push(format!("{} ({:#06x}) in {}{}", sub.name(), sub.address, sub.state_name(), error));
+            }
+            if diag.coupler_status != 0 && diag.coupler_status != u32::MAX {
+                failed.push(format!("K-bus status {:#x}", diag.coupler_status));
+            }
+            if diag.cycle_us_avg > api.scan_budget_us {
+                failed.push(format!("scan {} us over the {} us budget", diag.cycle_us_avg, api.scan_budget_us));
+            }
+        }
+    }
+    let status = if failed.is_empty() { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(json!({
+        "ready": failed.is_empty(),
+        "scan_us": diag.as_ref().map(|diag| diag.cycle_us_avg),
+        "scan_budget_us": api.scan_budget_us,
+        "failed": failed,
+    })))
+}
+
+async fn diagnostics(State(api): State<Api>, Extension(caller): Extension<Caller>) -> ApiResult<Json<Value>> {
+    api.authorize(&caller, Action::Read)?;
+    let plc = api.table.read_plc_info().map(|info| json!({
+        "version": info.version(),
+        "commit": info.commit(),
+        "profile": info.profile(),
+        "started_ms": info.started_ms,
+        "project": info.project(),
+        "project_version": info.project_version(),
+    }));
+    let bus = api.table.read_bus_diag().map(|diag| json!({
+        "cycles": diag.cycles,
+        "wkc_errors": diag.wkc_errors,
+        "cycle_us": { "last": diag.cycle_us_last, "min": diag.cycle_us_min, "max": diag.cycle_us_max, "avg": diag.cycle_us_avg },
+        "jitter_us": { "max": diag.jitter_us_max, "avg": diag.jitter_us_avg },
+        "coupler_status": diag.coupler_status,
+        "subdevices": diag.subdevices().iter().map(|sub| json!({
+            "name": sub.name(),
+            "address": sub.address,
+            "state": sub.state_name(),
+            "error": sub.has_error(),
+            "al_status_code": sub.al_status_code,
+            "port_errors": sub.port_errors,
+            "lost_links": sub.lost_links,
+        })).collect::<Vec<_>>(),
+    }));
+    Ok(Json(json!({ "plc": plc, "bus": bus, "io_channels": api.table.read_io().len() })))
+}
+
+async fn list_trends(State(api): State<Api>, Extension(caller): Extension<Caller>) -> ApiResult<Json<Value>> {
+    api.authorize(&caller, Action::Read)?;
+    let trends = api.trends.lock().unwrap();
+    let tags = api.table.tags();
+    Ok(Json(trends.iter().map(|(idx, points)| (tags[*idx].name.clone(), json!(points))).collect::<serde_json::Map<_, _>>().into()))
+}
+
+#[derive(serde::Deserialize)]
+struct EventParams {
+    from: Option<u64>,
+    to: Option<u64>,
+    kind: Option<String>, // comma separated
+    source: Option<String>,
+    min_severity: Option<u16>,
+    limit: Option<usize>,
+}
+
+async fn list_events(State(api): State<Api>, Extension(caller): Extension<Caller>, Query(params): Query<EventParams>) -> ApiResult<Json<Value>> {
+    api.authorize(&caller, Action::Read)?;
+    if api.events.is_none() {
+        return Err(ApiError(StatusCode::NOT_FOUND, "No event store, see [event_store] in gipop.toml".to_owned()));
+    }
+    let query = EventQuery {
+        start_ms: params.from.unwrap_or(0),
+        end_ms: params.to.unwrap_or(u64::MAX),
+        kinds: params.kind.map(|kinds| kinds.split(',').map(str::to_owned).collect()).unwrap_or_default(),
+        source: params.source,
+        min_severity: params.min_severity.unwrap_or(0),
+        limit: params.limit.unwrap_or(EVENTS_LIMIT).clamp(1, EVENTS_LIMIT),
+        newest_first: true,
+    };
+    // SQLite blocks, keep it off the runtime's threads
+    let events = tokio::task::spawn_blocking(move || api.events.as_ref().map_or(Ok(Vec::new()), |events| events.query(&query)))
+        .await
+        .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    Ok(Json(events.iter().map(event_json).collect()))
+}
+
+fn event_json(event: &StoredEvent) -> Value {
+    json!({
+        "id": event.id,
+        "ts": event.ts_ms,
+        "time": iso_time(event.ts_ms),
+        "kind": event.kind,
+        "source": event.source,
+        "severity": event.severity,
+        "user": event.user,
+        "message": event.message,
+        "clock_synced": event.clock_synced,
+    })
+}
+
+#[cfg(feature = "tls")]
+async fn serve_tls(listener: tokio::net::TcpListener, app: Router, config: &HttpConfig, what: &str) -> Result<(), String> {
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+    let certs = CertificateDer::pem_file_iter(&config.tls_cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("Failed to read tls_cert {}: {}", config.tls_cert, e))?;
+    let key = PrivateKeyDer::from_pem_file(&config.tls_key).map_err(|e| format!("Failed to read tls_key {}: {}", config.tls_key, e))?;
+    let mut tls = rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("{} and {} don't make a certificate: {}", config.tls_cert, config.tls_key, e))?;
+    tls.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    let listener = listener.into_std().map_err(|e| e.to_string())?;
+    log::info!("[HTTP] {} listening on https://{}", what, config.listen);
+    axum_server::from_tcp_rustls(listener, axum_server::tls_rustls::RustlsConfig::from_config(Arc::new(tls)))
+        .serve(app.into_make_service())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "tls"))]
+async fn serve_tls(_listener: tokio::net::TcpListener, _app: Router, _config: &HttpConfig, _what: &str) -> Result<(), String> {
+    Err("tls_cert and tls_key are set but this build doesn't have the `tls` feature, not serving plain HTTP instead".to_owned())
+}
+
+// Checks bearer tokens against jwt_secret or jwt_public_key
+#[cfg(feature = "jwt")]
+struct Jwt {
+    key: jsonwebtoken::DecodingKey,
+    validation: jsonwebtoken::Validation,
+}
+
+#[cfg(feature = "jwt")]
+impl Jwt {
+    // None when neither is set
+    fn from_config(config: &HttpConfig) -> Result<Option<Self>, String> {
+        use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+
+        let (key, algorithms) = match (config.jwt_secret.as_str(), config.jwt_public_key.as_str()) {
+            ("", "") => return Ok(None),
+            (secret, "") => (DecodingKey::from_secret(secret.as_bytes()), vec![Algorithm::HS256, Algorithm::HS384, Algorithm::HS512]),
+            ("", path) => {
+                let pem = std::fs::read(path).map_err(|e| format!("Failed to read jwt_public_key {}: {}", path, e))?;
+                if let Ok(key) = DecodingKey::from_rsa_pem(&pem) {
+                    let rsa = [Algorithm::RS256, Algorithm::RS384, Algorithm::RS512, Algorithm::PS256, Algorithm::PS384, Algorithm::PS512];
+                    (key, rsa.to_vec())
+                }
+                else if let Ok(key) = DecodingKey::from_ec_pem(&pem) {
+                    (key, vec![Algorithm::ES256, Algorithm::ES384])
+                }
+                else {
+                    let key = DecodingKey::from_ed_pem(&pem).map_err(|_| format!("{} isn't an RSA, EC or Ed25519 public key", path))?;
+                    (key, vec![Algorithm::EdDSA])
+                }
+            }
+            _ => return Err("Set either jwt_secret or jwt_public_key, not both".to_owned()),
+        };
+        let mut validation = Validation::new(algorithms[0]);
+        validation.algorithms = algorithms;
+        validation.set_required_spec_claims(&["exp", "sub"]);
+        if !config.jwt_issuer.is_empty() {
+            validation.set_issuer(&[&config.jwt_issuer]);
+        }
+        if config.jwt_audience.is_empty() {
+            validation.validate_aud = false;
+        }
+        else {
+            validation.set_audience(&[&config.jwt_audience]);
+        }
+        Ok(Some(Self { key, validation }))
+    }
+
+    // The token's sub if it's signed right and current
+    fn verify(&self, token: &str) -> Result<String, String> {
+        #[derive(serde::Deserialize)]
+        struct Claims {
+            sub: String,
+        }
+        jsonwebtoken::decode::<Claims>(token, &self.key, &self.validation).map(|data| data.claims.sub).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(not(feature = "jwt"))]
+struct Jwt;
+
+#[cfg(not(feature = "jwt"))]
+impl Jwt {
+    fn from_config(config: &HttpConfig) -> Result<Option<Self>, String> {
+        if config.jwt_secret.is_empty() && config.jwt_public_key.is_empty() {
+            return Ok(None);
+        }
+        Err("jwt_secret or jwt_public_key is set but this build doesn't have the `jwt` feature".to_owned())
+    }
+
+    fn verify(&self, _token: &str) -> Result<String, String> {
+        unreachable!("never built without the jwt feature")
+    }
+}
+
+// Stands in for the event store in builds without it, [event_store] path is reported and /api/events is a 404
+#[cfg(not(feature = "event_store"))]
+enum Events {}
+
+#[cfg(not(feature = "event_store"))]
+impl Events {
+    fn open(_path: &str) -> Result<Self, String> {
+        Err("[event_store] path is set but this build doesn't have the `event_store` feature".to_owned())
+    }
+
+    fn query(&self, _query: &EventQuery) -> Result<Vec<StoredEvent>, String> {
+        match *self {}
+    }
+}
@@ -0,0 +1,668 @@
+// IEC 60870-5-104 outstation ([iec104] in gipop.toml) for utility and remote SCADA masters that don't speak OPC UA.
+// [[iec104.points]] are monitored as single points or short floats, sent on general interrogation and spontaneously on
+// change (with CP56Time2a time tags if time_tags is set, the PLC's sample time). [[iec104.commands]] take single
+// commands and short float set points as tag writes, audited as coming from [iec104] user like MQTT commands.
+//
+// Every connection is served on its own with the standard k=12, w=8, t1=15 s, t2=10 s, t3=20 s. Data flows after the
+// master's STARTDT. Select-before-operate is confirmed without acting, only the execute writes. Clock sync is
+// confirmed but doesn't set anything, the time is the host's. One information object per ASDU.
+//
+// Like Modbus there's no authentication, whoever reaches the port acts as [iec104] user. Mind `listen`, or leave user
+// unset to take no commands.
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use gipop_shm::tags::{iso_time, now_ms};
+use gipop_shm::{Action, Subscriber, TagDef, TagSample, TagValue, Users};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::commands::{self, to_tag};
+use crate::config::{Iec104CommandType, Iec104Config, Iec104PointType};
+
+const K: u16 = 12; // I-frames sent but not acknowledged at most
+const W: u16 = 8; // I-frames received before we acknowledge
+const T1: Duration = Duration::from_secs(15); // for the master's acknowledgement
+const T2: Duration = Duration::from_secs(10); // we acknowledge at the latest after this
+const T3: Duration = Duration::from_secs(20); // idle time before a test frame
+const SEQ_MODULO: u16 = 0x8000;
+
+// U-format functions
+const STARTDT_ACT: u8 = 0x07;
+const STARTDT_CON: u8 = 0x0B;
+const STOPDT_ACT: u8 = 0x13;
+const STOPDT_CON: u8 = 0x23;
+const TESTFR_ACT: u8 = 0x43;
+const TESTFR_CON: u8 = 0x83;
+
+// Type identifications
+const M_SP_NA_1: u8 = 1;
+const M_ME_NC_1: u8 = 13;
+const M_SP_TB_1: u8 = 30;
+const M_ME_TF_1: u8 = 36;
+const C_SC_NA_1: u8 = 45;
+const C_SE_NC_1: u8 = 50;
+const C_SC_TA_1: u8 = 58;
+const C_SE_TC_1: u8 = 63;
+const M_EI_NA_1: u8 = 70;
+const C_IC_NA_1: u8 = 100;
+const C_CS_NA_1: u8 = 103;
+
+// Causes of transmission
+const SPONTANEOUS: u8 = 3;
+const INITIALIZED: u8 = 4;
+const ACTIVATION: u8 = 6;
+const ACTIVATION_CON: u8 = 7;
+const DEACTIVATION: u8 = 8;
+const DEACTIVATION_CON: u8 = 9;
+const ACTIVATION_TERMINATION: u8 = 10;
+const INTERROGATED_BY_STATION: u8 = 20;
+const UNKNOWN_TYPE: u8 = 44;
+const UNKNOWN_CAUSE: u8 = 45;
+const UNKNOWN_COMMON_ADDRESS: u8 = 46;
+const UNKNOWN_OBJECT_ADDRESS: u8 = 47;
+const NEGATIVE: u8 = 0x40; // P/N bit of the cause
+
+const INVALID: u8 = 0x80; // IV bit of the quality descriptor
+const SELECT: u8 = 0x80; // S/E bit of command qualifiers
+const BROADCAST_ADDRESS: u16 = 0xFFFF;
+
+struct Point {
+    tag: usize,
+    ioa: u32,
+    ty: Iec104PointType,
+    min_change: f64,
+}
+
+struct Command {
+    tag: usize,
+    ioa: u32,
+    ty: Iec104CommandType,
+}
+
+struct Station {
+    table: Subscriber,
+    common_address: u16,
+    time_tags: bool,
+    poll: Duration,
+    points: Vec<Point>,
+    commands: Vec<Command>,
+    user: String,
+}
+
+fn resolve(config: &Iec104Config, tags: &[TagDef]) -> Result<(Vec<Point>, Vec<Command>), String> {
+    let find = |name: &str, section: &str| {
+        tags.iter().position(|tag| tag.name == name).ok_or_else(|| format!("No tag named '{}' in [[iec104.{}]]", name, section))
+    };
+    let mut ioas = std::collections::HashSet::new();
+    let mut points = Vec::new();
+    for point in &config.points {
+        let tag = find(&point.tag, "points")?;
+        if !ioas.insert(point.ioa) || point.ioa > 0xFF_FFFF {
+            return Err(format!("[[iec104.points]] ioa {} of '{}' is out of range or taken", point.ioa, point.tag));
+        }
+        points.push(Point { tag, ioa: point.ioa, ty: point.ty, min_change: point.min_change });
+    }
+    let mut commands = Vec::new();
+    for command in &config.commands {
+        let tag = find(&command.tag, "commands")?;
+        if !tags[tag].writable() {
+            return Err(format!("'{}' in [[iec104.commands]] isn't writable", command.tag));
+        }
+        if !ioas.insert(command.ioa) || command.ioa > 0xFF_FFFF {
+            return Err(format!("[[iec104.commands]] ioa {} of '{}' is out of range or taken", command.ioa, command.tag));
+        }
+        commands.push(Command { tag, ioa: command.ioa, ty: command.ty });
+    }
+    Ok((points, commands))
+}
+
+/// Serves until the process ends, Err if the points don't fit the tag table or it can't listen
+pub async fn serve(config: Iec104Config, table: Subscriber, users: Users) -> Result<(), String> {
+    let (points, mut commands) = resolve(&config, table.tags())?;
+    if !commands.is_empty() && let Err(e) = users.authorize(config.user.as_deref(), Action::WriteTag) {
+        log::error!("[IEC 104] Not taking commands, {}", e);
+        commands.clear();
+    }
+    let listener = TcpListener::bind(&config.listen).await.map_err(|e| format!("Failed to listen on {}: {}", config.listen, e))?;
+    log::info!("[IEC 104] {} point(s), {} command(s) on {}, common address {}", points.len(), commands.len(), config.listen, config.common_address);
+
+    let station = Arc::new(Station {
+        table,
+        common_address: config.common_address,
+        time_tags: config.time_tags,
+        poll: Duration::from_millis(config.poll_ms.max(1)),
+        points,
+        commands,
+        user: config.user.unwrap_or_default(),
+    });
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                log::warn!("[IEC 104] {}", e);
+                continue;
+            }
+        };
+        log::info!("[IEC 104] {} connected", peer);
+        let connection = Connection::new(station.clone(), stream, peer);
+        tokio::spawn(async move {
+            match connection.run().await {
+                Ok(()) => log::info!("[IEC 104] {} disconnected", peer),
+                Err(e) => log::warn!("[IEC 104] {} dropped: {}", peer, e),
+            }
+        });
+    }
+}
+
+struct Connection {
+    station: Arc<Station>,
+    stream: TcpStream,
+    peer: SocketAddr,
+    started: bool,
+    vs: u16,    // send sequence number of our next I-frame
+    vr: u16,    // the master's I-frame we expect next
+    acked: u16, // our I-frames the master acknowledged, up to this one
+    unacked_received: u16,
+    first_unacked_received: Option<Instant>, // for t2
+    oldest_unacked_sent: Option<Instant>,    // for t1
+    last_received: Instant,                  // for t3
+    test_sent: Option<Instant>,
+    queue: VecDeque<Vec<u8>>,                // ASDUs waiting for the k window
+    sent: Vec<Option<(TagValue, bool)>>,     // per point, value and validity last sent
+}
+
+impl Connection {
+    fn new(station: Arc<Station>, stream: TcpStream, peer: SocketAddr) -> Self {
+        let points = station.points.len();
+        Self {
+            station,
+            stream,
+            peer,
+            started: false,
+            vs: 0,
+            vr: 0,
+            acked: 0,
+            unacked_received: 0,
+            first_unacked_received: None,
+            oldest_unacked_sent: None,
+            last_received: Instant::now(),
+            test_sent: None,
+            queue: VecDeque::new(),
+            sent: vec![None; points],
+        }
+    }
+
+    async fn run(mut self) -> Result<(), String> {
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 1024];
+        let mut tick = tokio::time::interval(self.station.poll);
+        loop {
+            tokio::select! {
+                read = self.stream.read(&mut chunk) => {
+                    let n = read.map_err(|e| e.to_string())?;
+                    if n == 0 {
+                        return Ok(());
+                    }
+                    buf.extend_from_slice(&chunk[..n]);
+                    while let Some(frame) = take_frame(&mut buf)? {
+                        self.last_received = Instant::now();
+                        self.frame(&frame).await?;
+                    }
+                }
+                _ = tick.tick() => {
+                    self.timers().await?;
+                    if self.started {
+                        self.changes();
+                    }
+                }
+            }
+            self.flush().await?;
+        }
+    }
+
+    async fn frame(&mut self, frame: &[u8]) -> Result<(), String> {
+        let control = &frame[..4];
+        match control[0] & 0x03 {
+            0 | 2 => {
+                let ns = u16::from_le_bytes([control[0], control[1]]) >> 1;
+                if ns != self.vr {
+                    return Err(format!("expected I-frame {}, got {}", self.vr, ns));
+                }
+                self.vr = (self.vr + 1) % SEQ_MODULO;
+                self.acknowledged(u16::from_le_bytes([control[2], control[3]]) >> 1);
+                self.unacked_received += 1;
+                self.first_unacked_received.get_or_insert_with(Instant::now);
+                if self.unacked_received >= W {
+                    self.send_s().await?;
+                }
+                if self.started {
+                    let responses = self.station.asdu(&frame[4..]);
+                    self.queue.extend(responses);
+                }
+                Ok(())
+            }
+            1 => {
+                self.acknowledged(u16::from_le_bytes([control[2], control[3]]) >> 1);
+                Ok(())
+            }
+            _ => match control[0] {
+                STARTDT_ACT => {
+                    self.send_u(STARTDT_CON).await?;
+                    if !self.started {
+                        self.started = true;
+                        // changes from here on, the master gets the rest with its general interrogation
+                        self.sent = self.station.points.iter().map(|point| Some(current(&self.station.table.read_sample(point.tag)))).collect();
+                        self.queue.push_back(self.station.end_of_initialization());
+                    }
+                    Ok(())
+                }
+                STOPDT_ACT => {
+                    self.started = false;
+                    self.send_u(STOPDT_CON).await
+                }
+                TESTFR_ACT => self.send_u(TESTFR_CON).await,
+                TESTFR_CON => {
+                    self.test_sent = None;
+                    Ok(())
+                }
+                _ => Ok(()),
+            },
+        }
+    }
+
+    fn acknowledged(&mut self, nr: u16) {
+        self.acked = nr;
+        self.oldest_unacked_sent = (self.acked != self.vs).then(Instant::now);
+    }
+
+    async fn timers(&mut self) -> Result<(), String> {
+        if self.oldest_unacked_sent.is_some_and(|sent| sent.elapsed() >= T1) || self.test_sent.is_some_and(|sent| sent.elapsed() >= T1) {
+            return Err("no acknowledgement within t1".to_owned());
+        }
+        if self.first_unacked_received.is_some_and(|received| received.elapsed() >= T2) {
+            self.send_s().await?;
+        }
+        if self.test_sent.is_none() && self.last_received.elapsed() >= T3 {
+            self.test_sent = Some(Instant::now());
+            self.send_u(TESTFR_ACT).await?;
+        }
+        Ok(())
+    }
+
+    // Spontaneous ASDUs for the points that changed
+    fn changes(&mut self) {
+        for (i, point) in self.station.points.iter().enumerate() {
+            let sample = self.station.table.read_sample(point.tag);
+            let now = current(&sample);
+            let changed = match self.sent[i] {
+                None => true,
+                Some((value, good)) if point.min_change > 0.0 => good != now.1 || (value.as_f64() - now.0.as_f64()).abs() >= point.min_change,
+                Some(sent) => sent != now,
+            };
+            if changed {
+                self.sent[i] = Some(now);
+                self.queue.push_back(self.station.point_asdu(point, &sample, SPONTANEOUS, self.station.time_tags));
+            }
+        }
+    }
+
+    // Sends queued ASDUs as far as the k window allows
+    async fn flush(&mut self) -> Result<(), String> {
+        while (self.vs + SEQ_MODULO - self.acked) % SEQ_MODULO < K && let Some(asdu) = self.queue.pop_front() {
+            let mut frame = vec![0x68, (4 + asdu.len()) as u8];
+            frame.extend((self.vs << 1).to_le_bytes());
+            frame.extend((self.vr << 1).to_le_bytes());
+            frame.extend(asdu);
+            self.write(&frame).await?;
+            self.vs = (self.vs + 1) % SEQ_MODULO;
+            self.oldest_unacked_sent.get_or_insert_with(Instant::now);
+            // an I-frame acknowledges what we received too
+            self.unacked_received = 0;
+            self.first_unacked_received = None;
+        }
+        Ok(())
+    }
+
+    async fn send_s(&mut self) -> Result<(), String> {
+        self.unacked_received = 0;
+        self.first_unacked_received = None;
+        let mut frame = vec![0x68, 0x04, 0x01, 0x00];
+        frame.extend((self.vr << 1).to_le_bytes());
+        self.write(&frame).await
+    }
+
+    async fn send_u(&mut self, function: u8) -> Result<(), String> {
+        self.write(&[0x68, 0x04, function, 0x00, 0x00, 0x00]).await
+    }
+
+    async fn write(&mut self, frame: &[u8]) -> Result<(), String> {
+        log::trace!("[IEC 104] to {}: {:02x?}", self.peer, frame);
+        self.stream.write_all(frame).await.map_err(|e| e.to_string())
+    }
+}
+
+// Splits the next APDU (the 4 control octets and the ASDU) off `buf`, None until it's all there
+fn take_frame(buf: &mut Vec<u8>) -> Result<Option<Vec<u8>>, String> {
+    if buf.len() < 2 {
+        return Ok(None);
+    }
+    if buf[0] != 0x68 || buf[1] < 4 {
+        return Err(format!("not an APCI: {:02x} {:02x}", buf[0], buf[1]));
+    }
+    let len = buf[1] as usize;
+    if buf.len() < 2 + len {
+        return Ok(None);
+    }
+    let frame = buf[2..2 + len].to_vec();
+    buf.drain(..2 + len);
+    Ok(Some(frame))
+}
+
+// What a point's ASDU would carry: the value and whether it's valid
+fn current(sample: &TagSample) -> (TagValue, bool) {
+    (sample.value, sample.quality.is_good())
+}
+
+impl Station {
+    // The responses to one ASDU from the master
+    fn asdu(&self, asdu: &[u8]) -> Vec<Vec<u8>> {
+        if asdu.len() < 9 {
+            return Vec::new();
+        }
+        let (ty, cause) = (asdu[0], asdu[2] & 0x3F);
+        let common_address = u16::from_le_bytes([asdu[4], asdu[5]]);
+        let ioa = u32::from_le_bytes([asdu[6], asdu[7], asdu[8], 0]);
+        let element = &asdu[9..];
+        if common_address != self.common_address && common_address != BROADCAST_ADDRESS {
+            return vec![mirror(asdu, UNKNOWN_COMMON_ADDRESS | NEGATIVE)];
+        }
+
+        match ty {
+            C_IC_NA_1 => match cause {
+                ACTIVATION => {
+                    let mut responses = vec![mirror(asdu, ACTIVATION_CON)];
+                    for point in &self.points {
+                        responses.push(self.point_asdu(point, &self.table.read_sample(point.tag), INTERROGATED_BY_STATION, false));
+                    }
+                    responses.push(mirror(asdu, ACTIVATION_TERMINATION));
+                    responses
+                }
+                DEACTIVATION => vec![mirror(asdu, DEACTIVATION_CON)],
+                _ => vec![mirror(asdu, UNKNOWN_CAUSE | NEGATIVE)],
+            },
+            C_CS_NA_1 if cause == ACTIVATION => vec![mirror(asdu, ACTIVATION_CON)],
+            C_SC_NA_1 | C_SC_TA_1 | C_SE_NC_1 | C_SE_TC_1 => {
+                let single = matches!(ty, C_SC_NA_1 | C_SC_TA_1);
+                let expected = if single { Iec104CommandType::Single } else { Iec104CommandType::Setpoint };
+                let Some(command) = self.commands.iter().find(|command| command.ioa == ioa && command.ty == expected) else {
+                    return vec![mirror(asdu, UNKNOWN_OBJECT_ADDRESS | NEGATIVE)];
+                };
+                if cause != ACTIVATION {
+                    return vec![mirror(asdu, UNKNOWN_CAUSE | NEGATIVE)];
+                }
+                let parsed = if single {
+                    element.first().map(|&sco| ((sco & 0x01) as f64, sco & SELECT != 0))
+                } else {
+                    element.get(..5).map(|e| (f32::from_le_bytes([e[0], e[1], e[2], e[3]]) as f64, e[4] & SELECT != 0))
+                };
+                let Some((number, select)) = parsed else { return vec![mirror(asdu, ACTIVATION_CON | NEGATIVE)] };
+                if select {
+                    return vec![mirror(asdu, ACTIVATION_CON)];
+                }
+                if self.execute(command, number) {
+                    vec![mirror(asdu, ACTIVATION_CON), mirror(asdu, ACTIVATION_TERMINATION)]
+                } else {
+                    vec![mirror(asdu, ACTIVATION_CON | NEGATIVE)]
+                }
+            }
+            _ => vec![mirror(asdu, UNKNOWN_TYPE | NEGATIVE)],
+        }
+    }
+
+    fn execute(&self, command: &Command, number: f64) -> bool {
+        let tag = &self.table.tags()[command.tag];
+        let Some(value) = to_tag(tag.ty, number).filter(|value| tag.accepts(*value)) else {
+            log::warn!("[IEC 104] Ignoring command {}: {} isn't a value for '{}' {:?}", command.ioa, number, tag.name, tag.write_range());
+            return false;
+        };
+        commands::write_tag(&self.table, "IEC 104", &self.user, command.tag, value).is_ok()
+    }
+
+    fn point_asdu(&self, point: &Point, sample: &TagSample, cause: u8, time_tag: bool) -> Vec<u8> {
+        let ty = match (point.ty, time_tag) {
+            (Iec104PointType::Single, false) => M_SP_NA_1,
+            (Iec104PointType::Single, true) => M_SP_TB_1,
+            (Iec104PointType::Float, false) => M_ME_NC_1,
+            (Iec104PointType::Float, true) => M_ME_TF_1,
+        };
+        let quality = if sample.quality.is_good() { 0 } else { INVALID };
+        let mut asdu = self.header(ty, cause, point.ioa);
+        match point.ty {
+            Iec104PointType::Single => asdu.push((sample.value.as_f64() != 0.0) as u8 | quality),
+            Iec104PointType::Float => {
+                asdu.extend((sample.value.as_f64() as f32).to_le_bytes());
+                asdu.push(quality);
+            }
+        }
+        if time_tag {
+            asdu.extend(cp56time2a(sample.ts_ms));
+        }
+        asdu
+    }
+
+    fn end_of_initialization(&self) -> Vec<u8> {
+        let mut asdu = self.header(M_EI_NA_1, INITIALIZED, 0);
+        asdu.push(0); // local power on
+        asdu
+    }
+
+    // Type, one object, cause, originator 0, common address, information object address
+    fn header(&self, ty: u8, cause: u8, ioa: u32) -> Vec<u8> {
+        let mut asdu = vec![ty, 0x01, cause, 0x00];
+        asdu.extend(self.common_address.to_le_bytes());
+        asdu.extend(&ioa.to_le_bytes()[..3]);
+        asdu
+    }
+}
+
+// The master's ASDU back with another cause, how confirmations and refusals are sent
+fn mirror(asdu: &[u8], cause: u8) -> Vec<u8> {
+    let mut asdu = asdu.to_vec();
+    asdu[2] = cause;
+    asdu
+}
+
+// CP56Time2a in UTC: milliseconds of the minute, minute, hour, day of month and week, month, year of the century
+fn cp56time2a(ts_ms: u64) -> [u8; 7] {
+    let ts_ms = if ts_ms == 0 { now_ms() } else { ts_ms };
+    let time = iso_time(ts_ms); // "2024-05-01T12:00:00.000Z"
+    let field = |range: std::ops::Range<usize>| time[range].parse::<u16>().unwrap_or_default();
+    let ms = (ts_ms % 60_000) as u16;
+    let day_of_week = ((ts_ms / 86_400_000 + 3) % 7 + 1) as u8; // 1970-01-01 was a Thursday, Monday is 1
+    [
+        ms as u8,
+        (ms >> 8) as u8,
+        field(14..16) as u8,
+        field(11..13) as u8,
+        field(8..10) as u8 | day_of_week << 5,
+        field(5..7) as u8,
+        (field(0..4) % 100) as u8,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::tests::table;
+    use crate::config::Iec104PointType;
+    use gipop_shm::{Publisher, TagType, TAG_WRITABLE};
+    use tokio::task::JoinHandle;
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    // A connection to a station with a single point (ioa 100) and a float (101), its sequence numbers where a
+    // connection that has been up a while might be. The master's end, and the connection's outcome.
+    async fn connect(name: &str, vs: u16, vr: u16) -> (Publisher, TcpStream, JoinHandle<Result<(), String>>) {
+        let tags = [
+            TagDef::new("pump running", TagType::Bool, 0),
+            TagDef::new("flow", TagType::Float32, 0),
+            TagDef::new("pump cmd", TagType::Bool, TAG_WRITABLE),
+        ];
+        let (plc, table) = table(name, &tags);
+        let station = Arc::new(Station {
+            table,
+            common_address: 1,
+            time_tags: false,
+            poll: Duration::from_millis(10),
+            points: vec![
+                Point { tag: 0, ioa: 100, ty: Iec104PointType::Single, min_change: 0.0 },
+                Point { tag: 1, ioa: 101, ty: Iec104PointType::Float, min_change: 0.0 },
+            ],
+            commands: vec![Command { tag: 2, ioa: 200, ty: Iec104CommandType::Single }],
+            user: "scada".to_owned(),
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let master = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (stream, peer) = listener.accept().await.unwrap();
+        let mut connection = Connection::new(station, stream, peer);
+        (connection.vs, connection.vr, connection.acked) = (vs, vr, vs);
+        (plc, master, tokio::spawn(connection.run()))
+    }
+
+    async fn send(master: &mut TcpStream, apdu: &[u8]) {
+        master.write_all(apdu).await.unwrap();
+    }
+
+    // The next APDU from the outstation, without the start and length
+    async fn receive(master: &mut TcpStream) -> Vec<u8> {
+        let mut header = [0u8; 2];
+        tokio::time::timeout(TIMEOUT, master.read_exact(&mut header)).await.expect("an APDU").unwrap();
+        assert_eq!(header[0], 0x68);
+        let mut apdu = vec![0u8; header[1] as usize];
+        master.read_exact(&mut apdu).await.unwrap();
+        apdu
+    }
+
+    fn u_frame(function: u8) -> [u8; 6] {
+        [0x68, 0x04, function, 0x00, 0x00, 0x00]
+    }
+
+    fn i_frame(ns: u16, nr: u16, asdu: &[u8]) -> Vec<u8> {
+        let mut apdu = vec![0x68, (4 + asdu.len()) as u8];
+        apdu.extend((ns << 1).to_le_bytes());
+        apdu.extend((nr << 1).to_le_bytes());
+        apdu.extend(asdu);
+        apdu
+    }
+
+    // (N(S), N(R), type, cause) of an I-frame
+    fn i_fields(apdu: &[u8]) -> (u16, u16, u8, u8) {
+        assert_eq!(apdu[0] & 0x01, 0, "not an I-frame: {:02x?}", apdu);
+        (u16::from_le_bytes([apdu[0], apdu[1]]) >> 1, u16::from_le_bytes([apdu[2], apdu[3]]) >> 1, apdu[4], apdu[6])
+    }
+
+    // C_IC_NA_1 activation to common address 1, station interrogation
+    const INTERROGATION: [u8; 10] = [C_IC_NA_1, 0x01, ACTIVATION, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 20];
+
+    #[tokio::test]
+    async fn startdt_and_testfr() {
+        let (_plc, mut master, connection) = connect("iec104_startdt", 0, 0).await;
+
+        // test frames are answered before STARTDT, data isn't
+        send(&mut master, &u_frame(TESTFR_ACT)).await;
+        assert_eq!(receive(&mut master).await, [TESTFR_CON, 0, 0, 0]);
+        send(&mut master, &i_frame(0, 0, &INTERROGATION)).await;
+
+        send(&mut master, &u_frame(STARTDT_ACT)).await;
+        assert_eq!(receive(&mut master).await, [STARTDT_CON, 0, 0, 0]);
+        let end_of_initialization = receive(&mut master).await;
+        assert_eq!(i_fields(&end_of_initialization), (0, 1, M_EI_NA_1, INITIALIZED));
+
+        send(&mut master, &u_frame(TESTFR_ACT)).await;
+        assert_eq!(receive(&mut master).await, [TESTFR_CON, 0, 0, 0]);
+        send(&mut master, &u_frame(STOPDT_ACT)).await;
+        assert_eq!(receive(&mut master).await, [STOPDT_CON, 0, 0, 0]);
+
+        drop(master);
+        assert_eq!(tokio::time::timeout(TIMEOUT, connection).await.unwrap().unwrap(), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn sequence_numbers_wrap_at_32768() {
+        let (_plc, mut master, connection) = connect("iec104_wrap", SEQ_MODULO - 1, SEQ_MODULO - 1).await;
+        send(&mut master, &u_frame(STARTDT_ACT)).await;
+        assert_eq!(receive(&mut master).await, [STARTDT_CON, 0, 0, 0]);
+        assert_eq!(i_fields(&receive(&mut master).await), (32767, 32767, M_EI_NA_1, INITIALIZED));
+
+        // the master's 32767, acknowledging ours: confirmation, both points, termination, numbered from 0 on
+        send(&mut master, &i_frame(32767, 0, &INTERROGATION)).await;
+        let responses = [
+            (0, C_IC_NA_1, ACTIVATION_CON),
+            (1, M_SP_NA_1, INTERROGATED_BY_STATION),
+            (2, M_ME_NC_1, INTERROGATED_BY_STATION),
+            (3, C_IC_NA_1, ACTIVATION_TERMINATION),
+        ];
+        for (ns, ty, cause) in responses {
+            assert_eq!(i_fields(&receive(&mut master).await), (ns, 0, ty, cause));
+        }
+
+        // its next is 0, then 1. Another 32767 is out of sequence and drops the connection
+        send(&mut master, &i_frame(0, 4, &INTERROGATION)).await;
+        assert_eq!(i_fields(&receive(&mut master).await), (4, 1, C_IC_NA_1, ACTIVATION_CON));
+        send(&mut master, &i_frame(32767, 4, &INTERROGATION)).await;
+        let outcome = tokio::time::timeout(TIMEOUT, connection).await.unwrap().unwrap();
+        assert_eq!(outcome, Err("expected I-frame 1, got 32767".to_owned()));
+    }
+
+    // buffer, what take_frame makes of it
+    type Case = (&'static [u8], Result<Option<&'static [u8]>, ()>);
+
+    #[test]
+    fn short_apdus() {
+        let cases: &[Case] = &[
+            (&[], Ok(None)),
+            (&[0x68], Ok(None)),
+            (&[0x68, 0x04, 0x07, 0x00, 0x00], Ok(None)),
+            (&[0x68, 0x04, 0x07, 0x00, 0x00, 0x00], Ok(Some(&[0x07, 0x00, 0x00, 0x00]))),
+            (&[0x68, 0x03, 0x07, 0x00, 0x00], Err(())),
+            (&[0x68, 0x00], Err(())),
+            (&[0x67, 0x04, 0x07, 0x00, 0x00, 0x00], Err(())),
+        ];
+        for &(buf, expected) in cases {
+            let mut buf = buf.to_vec();
+            let frame = take_frame(&mut buf).map_err(|_| ());
+            assert_eq!(frame.as_ref().map(|frame| frame.as_deref()), expected.as_ref().map(|frame| *frame), "{:02x?}", buf);
+        }
+    }
+
+    #[tokio::test]
+    async fn short_asdus_and_bad_apci() {
+        let (mut plc, mut master, connection) = connect("iec104_short", 0, 0).await;
+        send(&mut master, &u_frame(STARTDT_ACT)).await;
+        receive(&mut master).await;
+        receive(&mut master).await;
+
+        // an ASDU cut short in its header gets no answer, one without its element a negative confirmation. Neither
+        // commands anything, they count as received all the same (acknowledged after w of them)
+        let command = [C_SC_NA_1, 0x01, ACTIVATION, 0x00, 0x01, 0x00, 200, 0x00, 0x00, 0x01];
+        for (ns, len) in (0..command.len()).enumerate() {
+            send(&mut master, &i_frame(ns as u16, 1, &command[..len])).await;
+        }
+        assert_eq!(receive(&mut master).await, [0x01, 0x00, (W << 1) as u8, 0x00]);
+        assert_eq!(i_fields(&receive(&mut master).await), (1, 10, C_SC_NA_1, ACTIVATION_CON | NEGATIVE));
+        assert!(plc.pop_command().is_none());
+
+        // the whole one does
+        send(&mut master, &i_frame(command.len() as u16, 2, &command)).await;
+        assert_eq!(i_fields(&receive(&mut master).await), (2, 11, C_SC_NA_1, ACTIVATION_CON));
+        assert_eq!(plc.pop_command().map(|item| (item.tag, item.value)), Some((2, 1)));
+
+        // a length below the control field isn't an APCI, the connection goes
+        send(&mut master, &[0x68, 0x02, 0x01, 0x00]).await;
+        let outcome = tokio::time::timeout(TIMEOUT, connection).await.unwrap().unwrap();
+        assert_eq!(outcome, Err("not an APCI: 68 02".to_owned()));
+    }
+}
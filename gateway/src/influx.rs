@@ -0,0 +1,59 @@
+// InfluxDB writer ([influx] in gipop.toml, `influx` feature). Every change of the selected tags becomes a sample,
+// samples go through [compression] and out in batches (historian::batch) and are spooled to disk while InfluxDB can't
+// be reached (historian::store_fwd), so an outage costs nothing but the delay. Blocking, run it on its own thread.
+//
+// Like the OPC UA history only good samples are written, the history is what the PLC actually measured.
+use std::time::Duration;
+
+use gipop_shm::{Subscriber, TagValue};
+use historian::batch::Batcher;
+use historian::compression::CompressionSettings;
+use historian::influx::{InfluxMapping, InfluxSink};
+use historian::record::{Record, Sample};
+use historian::store_fwd::{RetentionLimits, StoreAndForward};
+
+use crate::config::InfluxConfig;
+
+/// Writes until the process ends, Err if the configuration doesn't fit the tag table or the buffer can't be opened
+pub fn run(config: InfluxConfig, compression: CompressionSettings, table: Subscriber) -> Result<(), String> {
+    let tags: Vec<usize> = if config.tags.is_empty() {
+        (0..table.tags().len()).collect()
+    } else {
+        config.tags.iter()
+            .map(|name| table.index_of(name).ok_or_else(|| format!("No tag named '{}' in [influx] tags", name)))
+            .collect::<Result<_, _>>()?
+    };
+    let mappings = config.mappings.into_iter()
+        .map(|tag| InfluxMapping { tag: tag.tag, measurement: tag.measurement, field: tag.field, tags: tag.tags })
+        .collect();
+
+    let sink = InfluxSink::new(&config.url, &config.org, &config.bucket, &config.token, &config.measurement, mappings);
+    let limits = RetentionLimits {
+        max_bytes: config.buffer_max_mb * 1024 * 1024,
+        max_age: Duration::from_secs(config.buffer_max_days * 24 * 3600),
+        ..RetentionLimits::default()
+    };
+    let forward = StoreAndForward::new(sink, &config.buffer_dir, limits)
+        .map_err(|e| format!("Failed to open buffer {}: {}", config.buffer_dir, e))?;
+    let mut batcher = Batcher::new(forward, Duration::from_millis(config.flush_ms), config.batch_size).with_compression(compression);
+    log::info!("[Influx] Writing {} tag(s) to {}, bucket {}", tags.len(), config.url, config.bucket);
+
+    let mut last: Vec<Option<TagValue>> = vec![None; tags.len()];
+    loop {
+        for (last, &idx) in last.iter_mut().zip(&tags) {
+            let sample = table.read_sample(idx);
+            if !sample.quality.is_good() || *last == Some(sample.value) {
+                continue;
+            }
+            *last = Some(sample.value);
+            let record = Record::Sample(Sample { tag: table.tags()[idx].name.clone(), ts_ms: sample.ts_ms, value: sample.value.as_f64() });
+            if let Err(e) = batcher.push(record) {
+                log::error!("[Influx] Failed to buffer samples: {}", e);
+            }
+        }
+        if let Err(e) = batcher.poll() {
+            log::error!("[Influx] Failed to buffer samples: {}", e);
+        }
+        std::thread::sleep(Duration::from_millis(config.poll_ms));
+    }
+}
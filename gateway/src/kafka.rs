@@ -0,0 +1,272 @@
+// Kafka producer ([kafka] in gipop.toml, `kafka` feature) for plants piping OT data into a central data platform. Up
+// to three topics, keyed so one tag's (or user's) records stay in order on one partition:
+//
+// alarms_topic     the PLC's alarms going active, clearing and acknowledged, keyed by tag, the webhooks' JSON:
+//                  {"event":"alarm","tag":"pump 1 fault","severity":700,"message":"pump 1 fault active","ts":...,"time":"..."}
+// audit_topic      each entry the PLC appends to its audit log, whichever interface the command came in over, keyed
+//                  by user: {"ts":...,"time":"...","seq":41,"user":"alice","session":3,"action":"write 'x' = 2"}
+// telemetry_topic  every telemetry_ms the min, max, average and last good value of each numeric tag, keyed by tag:
+//                  {"tag":"temperature","unit":"°C","start_ts":...,"ts":...,"count":600,"min":..,"max":..,"avg":..,"last":..}
+//
+// With a schema_registry each topic's JSON Schema is registered as <topic>-value (the registry's default subject
+// naming) before anything is sent and payloads get the registry's framing, a zero byte and the schema id ahead of the
+// JSON, so consumers with its JSON Schema deserializers take them as they are. Delivery, retries and buffering while
+// brokers are down are librdkafka's, tuned through [kafka.properties]. The audit log is followed from where it ended
+// when the gateway started, see audit_log.rs. Blocking, run it on its own thread with a Subscriber of its own.
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use gipop_shm::tags::{iso_time, now_ms};
+use gipop_shm::{Subscriber, TagType};
+use rdkafka::config::ClientConfig;
+use rdkafka::message::Message;
+use rdkafka::producer::{BaseProducer, BaseRecord, DeliveryResult, Producer, ProducerContext};
+use rdkafka::ClientContext;
+use serde_json::{json, Value};
+
+use crate::audit_log::AuditLog;
+use crate::config::{KafkaConfig, WebhookEvent};
+use crate::webhooks::Notification;
+
+const REGISTRY_RETRY: Duration = Duration::from_secs(10);
+const REGISTRY_TIMEOUT: Duration = Duration::from_secs(10);
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Produces until `stop`, Err if the configuration doesn't fit the tag table or librdkafka won't take it
+pub fn run(config: KafkaConfig, table: Subscriber, stop: &AtomicBool) -> Result<(), String> {
+    let mut windows: Vec<Window> = if config.telemetry_topic.is_empty() {
+        Vec::new()
+    } else if config.tags.is_empty() {
+        table.tags().iter().enumerate().filter(|(_, tag)| tag.ty != TagType::Bool).map(|(idx, _)| Window::new(idx)).collect()
+    } else {
+        config.tags.iter()
+            .map(|name| table.index_of(name).map(Window::new).ok_or_else(|| format!("No tag named '{}' in [kafka] tags", name)))
+            .collect::<Result<_, _>>()?
+    };
+    let mut client = ClientConfig::new();
+    client.set("bootstrap.servers", &config.brokers).set("client.id", &config.client_id);
+    for (key, value) in &config.properties {
+        client.set(key, value);
+    }
+    let producer: BaseProducer<Reports> = client.create_with_context(Reports).map_err(|e| format!("Can't set up the producer: {}", e))?;
+
+    let topics = [
+        (&config.alarms_topic, alarm_schema()),
+        (&config.audit_topic, audit_schema()),
+        (&config.telemetry_topic, telemetry_schema()),
+    ];
+    let mut schemas = [None; 3];
+    if !config.schema_registry.is_empty() {
+        for ((topic, schema), id) in topics.iter().zip(&mut schemas).filter(|((topic, _), _)| !topic.is_empty()) {
+            *id = loop {
+                match register(&config.schema_registry, topic, schema) {
+                    Ok(id) => break Some(id),
+                    Err(e) => log::warn!("[Kafka] Can't register the schema for {}: {}", topic, e),
+                }
+                std::thread::sleep(REGISTRY_RETRY);
+                if stop.load(Ordering::Relaxed) {
+                    return Ok(());
+                }
+            };
+        }
+    }
+    let [alarms, audit, telemetry] = schemas;
+    let out = Out { producer: &producer };
+    let mut audit_log = (!config.audit_topic.is_empty()).then(|| AuditLog::follow(&config.audit_log, "Kafka"));
+    log::info!("[Kafka] Producing to {} for {} telemetry tag(s)", config.brokers, windows.len());
+
+    let mut window_start = (Instant::now(), now_ms());
+    while !stop.load(Ordering::Relaxed) {
+        if !config.alarms_topic.is_empty() {
+            while let Some(event) = table.pop_event() {
+                if let Some(alarm) = Notification::from_event(&table, &event).filter(|n| n.event != WebhookEvent::Enocean) {
+                    out.send(&config.alarms_topic, &alarm.tag, alarms, alarm.default_body());
+                }
+            }
+        }
+        if let Some(audit_log) = &mut audit_log {
+            for entry in audit_log.new_entries() {
+                let record = json!({
+                    "ts": entry.ts_ms,
+                    "time": iso_time(entry.ts_ms),
+                    "seq": entry.seq,
+                    "user": entry.user,
+                    "session": entry.session,
+                    "action": entry.action,
+                });
+                out.send(&config.audit_topic, &entry.user, audit, record.to_string());
+            }
+        }
+
+        for window in &mut windows {
+            window.sample(&table);
+        }
+        if !windows.is_empty() && window_start.0.elapsed() >= Duration::from_millis(config.telemetry_ms) {
+            let (start_ts, ts) = (window_start.1, now_ms());
+            for window in &mut windows {
+                let tag = &table.tags()[window.idx];
+                if let Some(record) = window.take(&tag.name, &tag.unit, start_ts, ts) {
+                    out.send(&config.telemetry_topic, &tag.name, telemetry, record.to_string());
+                }
+            }
+            window_start = (Instant::now(), ts);
+        }
+
+        producer.poll(Duration::ZERO); // delivery reports
+        std::thread::sleep(Duration::from_millis(config.poll_ms));
+    }
+    if let Err(e) = producer.flush(FLUSH_TIMEOUT) {
+        log::warn!("[Kafka] {} record(s) weren't delivered before shutting down: {}", producer.in_flight_count(), e);
+    }
+    Ok(())
+}
+
+// Logs what librdkafka gave up on, after its own retries
+struct Reports;
+
+impl ClientContext for Reports {}
+
+impl ProducerContext for Reports {
+    type DeliveryOpaque = ();
+
+    fn delivery(&self, result: &DeliveryResult<'_>, _: ()) {
+        if let Err((e, message)) = result {
+            log::warn!("[Kafka] Record for {} not delivered: {}", message.topic(), e);
+        }
+    }
+}
+
+struct Out<'a> {
+    producer: &'a BaseProducer<Reports>,
+}
+
+impl Out<'_> {
+    // Queues `json` for `topic`, in the registry's framing with a schema id
+    fn send(&self, topic: &str, key: &str, schema: Option<u32>, json: String) {
+        let payload = match schema {
+            Some(id) => [&[0][..], &id.to_be_bytes(), json.as_bytes()].concat(),
+            None => json.into_bytes(),
+        };
+        if let Err((e, _)) = self.producer.send(BaseRecord::to(topic).key(key).payload(&payload)) {
+            log::warn!("[Kafka] Dropped a record for {}: {}", topic, e);
+        }
+    }
+}
+
+// Registers `schema` as <topic>-value, the id it has in the registry
+fn register(registry: &str, topic: &str, schema: &Value) -> Result<u32, String> {
+    let url = format!("{}/subjects/{}-value/versions", registry.trim_end_matches('/'), topic);
+    let body = json!({ "schemaType": "JSON", "schema": schema.to_string() });
+    let response = ureq::AgentBuilder::new().timeout(REGISTRY_TIMEOUT).build()
+        .post(&url)
+        .set("Content-Type", "application/vnd.schemaregistry.v1+json")
+        .send_string(&body.to_string())
+        .map_err(|e| match e {
+            ureq::Error::Status(status, response) => format!("status {}: {}", status, response.into_string().unwrap_or_default()),
+            ureq::Error::Transport(e) => e.to_string(),
+        })?;
+    let response: Value = serde_json::from_reader(response.into_reader()).map_err(|e| e.to_string())?;
+    response["id"].as_u64().and_then(|id| u32::try_from(id).ok()).ok_or_else(|| format!("no schema id in {}", response))
+}
+
+fn alarm_schema() -> Value {
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "GipopAlarm",
+        "type": "object",
+        "properties": {
+            "event": { "type": "string", "enum": ["alarm", "clear", "ack"] },
+            "tag": { "type": "string" },
+            "severity": { "type": "integer" },
+            "message": { "type": "string" },
+            "ts": { "type": "integer", "description": "unix epoch ms" },
+            "time": { "type": "string", "format": "date-time" },
+        },
+        "required": ["event", "tag", "severity", "message", "ts", "time"],
+    })
+}
+
+fn audit_schema() -> Value {
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "GipopAudit",
+        "type": "object",
+        "properties": {
+            "ts": { "type": "integer", "description": "unix epoch ms" },
+            "time": { "type": "string", "format": "date-time" },
+            "seq": { "type": "integer" },
+            "user": { "type": "string" },
+            "session": { "type": "integer" },
+            "action": { "type": "string" },
+        },
+        "required": ["ts", "time", "seq", "user", "session", "action"],
+    })
+}
+
+fn telemetry_schema() -> Value {
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "GipopTelemetry",
+        "type": "object",
+        "properties": {
+            "tag": { "type": "string" },
+            "unit": { "type": "string" },
+            "start_ts": { "type": "integer", "description": "unix epoch ms" },
+            "ts": { "type": "integer", "description": "unix epoch ms" },
+            "count": { "type": "integer" },
+            "min": { "type": "number" },
+            "max": { "type": "number" },
+            "avg": { "type": "number" },
+            "last": { "type": "number" },
+        },
+        "required": ["tag", "unit", "start_ts", "ts", "count", "min", "max", "avg", "last"],
+    })
+}
+
+// One telemetry tag's good values since the last record
+struct Window {
+    idx: usize,
+    count: u64,
+    min: f64,
+    max: f64,
+    sum: f64,
+    last: f64,
+    last_ts: u64, // the PLC's timestamp of `last`, a value is counted once however often it's polled
+}
+
+impl Window {
+    fn new(idx: usize) -> Self {
+        Self { idx, count: 0, min: f64::INFINITY, max: f64::NEG_INFINITY, sum: 0.0, last: 0.0, last_ts: 0 }
+    }
+
+    fn sample(&mut self, table: &Subscriber) {
+        let sample = table.read_sample(self.idx);
+        if !sample.quality.is_good() || sample.ts_ms == self.last_ts {
+            return;
+        }
+        let value = sample.value.as_f64();
+        self.count += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value;
+        self.last = value;
+        self.last_ts = sample.ts_ms;
+    }
+
+    // The record for the window, None without good values in it. Starts the next window
+    fn take(&mut self, name: &str, unit: &str, start_ts: u64, ts: u64) -> Option<Value> {
+        let record = (self.count > 0).then(|| json!({
+            "tag": name,
+            "unit": unit,
+            "start_ts": start_ts,
+            "ts": ts,
+            "count": self.count,
+            "min": self.min,
+            "max": self.max,
+            "avg": self.sum / self.count as f64,
+            "last": self.last,
+        }));
+        *self = Self { last_ts: self.last_ts, ..Self::new(self.idx) };
+        record
+    }
+}
@@ -0,0 +1,688 @@
+// KNXnet/IP tunneling client ([knx] in gipop.toml): the KNX side of buildings that pair EnOcean with KNX. Each
+// [[knx.mappings]] entry ties a tag to a group address: "out" tags are written to their group address on change and
+// answer read requests for it, group writes to "in" addresses are tag writes audited as coming from [knx] user, like
+// MQTT commands. "both" does both, a value that came in from the bus isn't echoed back out.
+//
+// One tunnel to a KNX IP interface or router (port 3671), link layer, NAT mode so it works across subnets. Lost
+// tunnels (no heartbeat answer, missing acks, the interface disconnecting us) are reconnected, and every "out" tag goes
+// out again afterwards like MQTT's resync. Interfaces only have a few tunnels, the tunnel is closed properly on
+// shutdown so it doesn't stay taken until the interface times it out. Blocking, run it on its own thread.
+use std::collections::VecDeque;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use gipop_shm::{Action, Subscriber, TagDef, TagValue, Users};
+
+use crate::commands::{self, to_tag};
+use crate::config::{KnxConfig, KnxDirection, KnxMapping};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(60); // the spec's, interfaces drop tunnels after 120 s
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10); // for connect and heartbeat responses
+const ACK_TIMEOUT: Duration = Duration::from_secs(1); // for tunneling acks, one retry then the tunnel counts as lost
+
+// Services
+const CONNECT_REQUEST: u16 = 0x0205;
+const CONNECT_RESPONSE: u16 = 0x0206;
+const CONNECTIONSTATE_REQUEST: u16 = 0x0207;
+const CONNECTIONSTATE_RESPONSE: u16 = 0x0208;
+const DISCONNECT_REQUEST: u16 = 0x0209;
+const DISCONNECT_RESPONSE: u16 = 0x020A;
+const TUNNELING_REQUEST: u16 = 0x0420;
+const TUNNELING_ACK: u16 = 0x0421;
+
+// cEMI message codes
+const L_DATA_REQ: u8 = 0x11;
+const L_DATA_IND: u8 = 0x29;
+
+// APCI
+const GROUP_VALUE_READ: u16 = 0x000;
+const GROUP_VALUE_RESPONSE: u16 = 0x040;
+const GROUP_VALUE_WRITE: u16 = 0x080;
+
+// NAT mode: the interface answers whatever address the request came from
+const ROUTE_BACK_HPAI: [u8; 8] = [0x08, 0x01, 0, 0, 0, 0, 0, 0];
+
+/// Datapoint types, by main number (and 5.001 for its scaling)
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Dpt {
+    Bool,    // 1.x, one bit
+    Percent, // 5.001, 0-100 % as 0-255
+    U8,      // 5.x
+    U16,     // 7.x
+    Float16, // 9.x, KNX's 2 byte float (temperatures, lux...)
+    U32,     // 12.x
+    I32,     // 13.x
+    Float32, // 14.x
+}
+
+impl Dpt {
+    fn parse(text: &str) -> Option<Self> {
+        let (main, sub) = text.split_once('.').unwrap_or((text, ""));
+        match (main.parse::<u16>().ok()?, sub.parse::<u16>().ok()) {
+            (1, _) => Some(Dpt::Bool),
+            (5, Some(1)) => Some(Dpt::Percent),
+            (5, _) => Some(Dpt::U8),
+            (7, _) => Some(Dpt::U16),
+            (9, _) => Some(Dpt::Float16),
+            (12, _) => Some(Dpt::U32),
+            (13, _) => Some(Dpt::I32),
+            (14, _) => Some(Dpt::Float32),
+            _ => None,
+        }
+    }
+
+    /// The payload after the APCI, or the 6 bit value that goes into it (1.x)
+    fn encode(self, value: f64) -> Payload {
+        match self {
+            Dpt::Bool => Payload::Small((value != 0.0) as u8),
+            Dpt::Percent => Payload::Bytes(vec![(value.clamp(0.0, 100.0) * 255.0 / 100.0).round() as u8]),
+            Dpt::U8 => Payload::Bytes(vec![value.clamp(0.0, 255.0).round() as u8]),
+            Dpt::U16 => Payload::Bytes((value.clamp(0.0, 65535.0).round() as u16).to_be_bytes().to_vec()),
+            Dpt::Float16 => Payload::Bytes(encode_float16(value).to_be_bytes().to_vec()),
+            Dpt::U32 => Payload::Bytes((value.clamp(0.0, u32::MAX as f64).round() as u32).to_be_bytes().to_vec()),
+            Dpt::I32 => Payload::Bytes((value.clamp(i32::MIN as f64, i32::MAX as f64).round() as i32).to_be_bytes().to_vec()),
+            Dpt::Float32 => Payload::Bytes((value as f32).to_be_bytes().to_vec()),
+        }
+    }
+
+    /// None if the payload doesn't have this type's length, or is DPT 9's invalid value
+    fn decode(self, payload: &Payload) -> Option<f64> {
+        match (self, payload) {
+            (Dpt::Bool, Payload::Small(bit)) => Some((bit & 0x01) as f64),
+            (Dpt::Percent, Payload::Bytes(b)) if b.len() == 1 => Some(b[0] as f64 * 100.0 / 255.0),
+            (Dpt::U8, Payload::Bytes(b)) if b.len() == 1 => Some(b[0] as f64),
+            (Dpt::U16, Payload::Bytes(b)) if b.len() == 2 => Some(u16::from_be_bytes([b[0], b[1]]) as f64),
+            (Dpt::Float16, Payload::Bytes(b)) if b.len() == 2 => decode_float16(u16::from_be_bytes([b[0], b[1]])),
+            (Dpt::U32, Payload::Bytes(b)) if b.len() == 4 => Some(u32::from_be_bytes(b[..4].try_into().ok()?) as f64),
+            (Dpt::I32, Payload::Bytes(b)) if b.len() == 4 => Some(i32::from_be_bytes(b[..4].try_into().ok()?) as f64),
+            (Dpt::Float32, Payload::Bytes(b)) if b.len() == 4 => Some(f32::from_be_bytes(b[..4].try_into().ok()?) as f64),
+            _ => None,
+        }
+    }
+}
+
+// DPT 9's "invalid data", what NaN goes out as
+const FLOAT16_INVALID: u16 = 0x7FFF;
+
+// DPT 9: 0.01 * mantissa * 2^exponent, 12 bit two's complement mantissa, 4 bit exponent. Out of range values saturate
+// to the largest and smallest value, the largest one short of FLOAT16_INVALID.
+fn encode_float16(value: f64) -> u16 {
+    if value.is_nan() {
+        return FLOAT16_INVALID;
+    }
+    let mut mantissa = value * 100.0;
+    let mut exponent = 0u16;
+    while !(-2048.0..=2047.0).contains(&mantissa.round()) && exponent < 15 {
+        mantissa /= 2.0;
+        exponent += 1;
+    }
+    let mantissa = (mantissa.round() as i32).clamp(-2048, 2047);
+    let raw = ((mantissa < 0) as u16) << 15 | exponent << 11 | (mantissa as u16 & 0x07FF);
+    if raw == FLOAT16_INVALID { raw - 1 } else { raw }
+}
+
+// None for FLOAT16_INVALID
+fn decode_float16(raw: u16) -> Option<f64> {
+    if raw == FLOAT16_INVALID {
+        return None;
+    }
+    let mantissa = (raw & 0x07FF) as i32 - if raw & 0x8000 != 0 { 2048 } else { 0 };
+    Some(0.01 * mantissa as f64 * (1 << ((raw >> 11) & 0x0F)) as f64)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Payload {
+    Small(u8), // up to 6 bits, in the APCI octet
+    Bytes(Vec<u8>),
+}
+
+/// "1/2/3" (main/middle/sub), "1/234" (main/sub) or the raw 16 bit address
+fn parse_group_address(text: &str) -> Option<u16> {
+    let parts: Vec<u16> = text.split('/').map(|part| part.trim().parse().ok()).collect::<Option<_>>()?;
+    match parts[..] {
+        [main, middle, sub] if main < 32 && middle < 8 && sub < 256 => Some(main << 11 | middle << 8 | sub),
+        [main, sub] if main < 32 && sub < 2048 => Some(main << 11 | sub),
+        [raw] => Some(raw),
+        _ => None,
+    }
+}
+
+fn format_group_address(address: u16) -> String {
+    format!("{}/{}/{}", address >> 11, (address >> 8) & 0x07, address & 0xFF)
+}
+
+struct Mapping {
+    tag: usize,
+    group: u16,
+    dpt: Dpt,
+    direction: KnxDirection,
+    min_change: f64,
+}
+
+impl Mapping {
+    fn outgoing(&self) -> bool {
+        matches!(self.direction, KnxDirection::Out | KnxDirection::Both)
+    }
+
+    fn incoming(&self) -> bool {
+        matches!(self.direction, KnxDirection::In | KnxDirection::Both)
+    }
+
+    // Whether `value` is worth a telegram after `sent`
+    fn changed(&self, sent: Option<TagValue>, value: TagValue) -> bool {
+        match sent {
+            None => true,
+            Some(sent) if self.min_change > 0.0 => (value.as_f64() - sent.as_f64()).abs() >= self.min_change,
+            Some(sent) => sent != value,
+        }
+    }
+}
+
+fn resolve_mappings(mappings: &[KnxMapping], tags: &[TagDef]) -> Result<Vec<Mapping>, String> {
+    mappings.iter().map(|mapping| {
+        let tag = tags.iter().position(|tag| tag.name == mapping.tag)
+            .ok_or_else(|| format!("No tag named '{}' in [[knx.mappings]]", mapping.tag))?;
+        let group = parse_group_address(&mapping.group)
+            .ok_or_else(|| format!("Invalid group address '{}' for '{}' in [[knx.mappings]]", mapping.group, mapping.tag))?;
+        let dpt = Dpt::parse(&mapping.dpt)
+            .ok_or_else(|| format!("Unsupported datapoint type '{}' for '{}' in [[knx.mappings]]", mapping.dpt, mapping.tag))?;
+        if mapping.direction != KnxDirection::Out && !tags[tag].writable() {
+            return Err(format!("'{}' isn't writable, it can only be \"out\" in [[knx.mappings]]", mapping.tag));
+        }
+        Ok(Mapping { tag, group, dpt, direction: mapping.direction, min_change: mapping.min_change })
+    }).collect()
+}
+
+/// Bridges until `stop` is set, Err if the mappings don't fit the tag table or the socket can't be opened
+pub fn run(config: KnxConfig, table: Subscriber, users: Users, stop: &AtomicBool) -> Result<(), String> {
+    let mut mappings = resolve_mappings(&config.mappings, table.tags())?;
+    let user = config.user.clone().unwrap_or_default();
+    if mappings.iter().any(Mapping::incoming) && let Err(e) = users.authorize(config.user.as_deref(), Action::WriteTag) {
+        log::error!("[KNX] Not taking writes from the bus, {}", e);
+        mappings.retain(Mapping::outgoing);
+        for mapping in &mut mappings {
+            mapping.direction = KnxDirection::Out;
+        }
+    }
+    let gateway = config.gateway.to_socket_addrs().ok().and_then(|mut addrs| addrs.find(SocketAddr::is_ipv4))
+        .ok_or_else(|| format!("Invalid [knx] gateway {}", config.gateway))?;
+    let socket = UdpSocket::bind("0.0.0.0:0").map_err(|e| format!("Failed to open a UDP socket: {}", e))?;
+    socket.set_read_timeout(Some(Duration::from_millis(config.poll_ms.max(1)))).map_err(|e| e.to_string())?;
+    log::info!("[KNX] {} group address mapping(s) via {}", mappings.len(), gateway);
+
+    let mut bridge = Bridge { socket, gateway, table, user, mappings, channel: 0, send_seq: 0, recv_seq: 0, outbox: VecDeque::new() };
+    while !stop.load(Ordering::Relaxed) {
+        match bridge.connect() {
+            Ok(()) => {
+                let lost = bridge.tunnel(stop);
+                log::warn!("[KNX] Tunnel to {} lost: {}", gateway, lost);
+            }
+            Err(e) => log::warn!("[KNX] Connecting to {} failed: {}", gateway, e),
+        }
+        let retry = Instant::now() + RECONNECT_DELAY;
+        while Instant::now() < retry && !stop.load(Ordering::Relaxed) {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+    Ok(())
+}
+
+struct Bridge {
+    socket: UdpSocket,
+    gateway: SocketAddr,
+    table: Subscriber,
+    user: String,
+    mappings: Vec<Mapping>,
+    channel: u8,
+    send_seq: u8,
+    recv_seq: u8, // the sequence number the next tunneling request from the interface should have
+    outbox: VecDeque<Vec<u8>>, // answers to read requests, sent between our own frames
+}
+
+impl Bridge {
+    fn connect(&mut self) -> Result<(), String> {
+        let body = [&ROUTE_BACK_HPAI[..], &ROUTE_BACK_HPAI, &[0x04, 0x04, 0x02, 0x00]].concat(); // tunnel, link layer
+        self.send(CONNECT_REQUEST, &body)?;
+        let response = self.wait_for(CONNECT_RESPONSE, |_| true)?;
+        match response.get(..2) {
+            Some(&[channel, 0]) => {
+                self.channel = channel;
+                self.send_seq = 0;
+                self.recv_seq = 0;
+                self.outbox.clear();
+                let address = response.get(18..20).map(|a| u16::from_be_bytes([a[0], a[1]])).unwrap_or_default();
+                log::info!("[KNX] Connected to {}, channel {}, individual address {}.{}.{}", self.gateway, channel, address >> 12, (address >> 8) & 0x0F, address & 0xFF);
+                Ok(())
+            }
+            Some(&[_, 0x24]) => Err("the interface has no free tunnel".to_owned()),
+            Some(&[_, status]) => Err(format!("refused with status {:#04x}", status)),
+            _ => Err("malformed connect response".to_owned()),
+        }
+    }
+
+    // Runs the tunnel until it's lost (the reason) or `stop` is set
+    fn tunnel(&mut self, stop: &AtomicBool) -> String {
+        let mut sent: Vec<Option<TagValue>> = vec![None; self.mappings.len()];
+        let mut last_heartbeat = Instant::now();
+        loop {
+            if stop.load(Ordering::Relaxed) {
+                self.disconnect();
+                return "shutting down".to_owned();
+            }
+            if let Err(e) = self.receive(&mut sent) {
+                return e;
+            }
+
+            let mut changed = Vec::new();
+            for (i, mapping) in self.mappings.iter().enumerate().filter(|(_, mapping)| mapping.outgoing()) {
+                let sample = self.table.read_sample(mapping.tag);
+                if sample.quality.is_good() && mapping.changed(sent[i], sample.value) {
+                    sent[i] = Some(sample.value);
+                    changed.push(group_frame(mapping.group, GROUP_VALUE_WRITE, mapping.dpt.encode(sample.value.as_f64())));
+                }
+            }
+            for frame in self.outbox.drain(..).chain(changed).collect::<Vec<_>>() {
+                if let Err(e) = self.send_frame(&frame, &mut sent) {
+                    return e;
+                }
+            }
+
+            if last_heartbeat.elapsed() >= HEARTBEAT_INTERVAL {
+                last_heartbeat = Instant::now();
+                let body = [&[self.channel, 0][..], &ROUTE_BACK_HPAI].concat();
+                let channel = self.channel;
+                if let Err(e) = self.send(CONNECTIONSTATE_REQUEST, &body)
+                    .and_then(|_| self.wait_for(CONNECTIONSTATE_RESPONSE, |body| body.first() == Some(&channel)))
+                    .and_then(|body| match body.get(1) {
+                        Some(0) => Ok(()),
+                        status => Err(format!("connection state {:?}", status)),
+                    }) {
+                    return format!("no heartbeat, {}", e);
+                }
+            }
+        }
+    }
+
+    fn disconnect(&mut self) {
+        let body = [&[self.channel, 0][..], &ROUTE_BACK_HPAI].concat();
+        let channel = self.channel;
+        if self.send(DISCONNECT_REQUEST, &body).and_then(|_| self.wait_for(DISCONNECT_RESPONSE, |body| body.first() == Some(&channel))).is_ok() {
+            log::info!("[KNX] Disconnected from {}", self.gateway);
+        }
+    }
+
+    fn send(&self, service: u16, body: &[u8]) -> Result<(), String> {
+        let len = (6 + body.len()) as u16;
+        let mut packet = vec![0x06, 0x10];
+        packet.extend(service.to_be_bytes());
+        packet.extend(len.to_be_bytes());
+        packet.extend(body);
+        self.socket.send_to(&packet, self.gateway).map(|_| ()).map_err(|e| e.to_string())
+    }
+
+    // The next packet from the interface: service and body. None on timeout.
+    fn recv(&self) -> Result<Option<(u16, Vec<u8>)>, String> {
+        let mut buf = [0u8; 512];
+        let (len, from) = match self.socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => return Ok(None),
+            Err(e) => return Err(e.to_string()),
+        };
+        if from != self.gateway || len < 6 || buf[0] != 0x06 || buf[1] != 0x10 || u16::from_be_bytes([buf[4], buf[5]]) as usize != len {
+            return Ok(None);
+        }
+        Ok(Some((u16::from_be_bytes([buf[2], buf[3]]), buf[6..len].to_vec())))
+    }
+
+    // Waits for a `service` packet whose body `matches`, dropping everything else (only used outside the tunnel or
+    // for short exchanges, a bus telegram arriving meanwhile is lost)
+    fn wait_for(&mut self, service: u16, matches: impl Fn(&[u8]) -> bool) -> Result<Vec<u8>, String> {
+        let deadline = Instant::now() + RESPONSE_TIMEOUT;
+        while Instant::now() < deadline {
+            if let Some((received, body)) = self.recv()? && received == service && matches(&body) {
+                return Ok(body);
+            }
+        }
+        Err("no response".to_owned())
+    }
+
+    // Takes one packet from the interface if there is one. Err if the tunnel is gone.
+    fn receive(&mut self, sent: &mut [Option<TagValue>]) -> Result<(), String> {
+        let Some((service, body)) = self.recv()? else { return Ok(()) };
+        match service {
+            TUNNELING_REQUEST if body.len() >= 4 && body[1] == self.channel => {
+                let seq = body[2];
+                // acked even when it's a repeat (our ack got lost), only handled the first time
+                self.send(TUNNELING_ACK, &[0x04, self.channel, seq, 0x00])?;
+                if seq == self.recv_seq {
+                    self.recv_seq = self.recv_seq.wrapping_add(1);
+                    self.telegram(&body[4..], sent);
+                }
+                Ok(())
+            }
+            DISCONNECT_REQUEST if body.first() == Some(&self.channel) => {
+                _ = self.send(DISCONNECT_RESPONSE, &[self.channel, 0x00]);
+                Err("disconnected by the interface".to_owned())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Sends a cEMI frame through the tunnel and waits for the interface's ack, resending once
+    fn send_frame(&mut self, cemi: &[u8], sent: &mut [Option<TagValue>]) -> Result<(), String> {
+        let seq = self.send_seq;
+        self.send_seq = self.send_seq.wrapping_add(1);
+        let body = [&[0x04, self.channel, seq, 0x00][..], cemi].concat();
+        for _ in 0..2 {
+            self.send(TUNNELING_REQUEST, &body)?;
+            let deadline = Instant::now() + ACK_TIMEOUT;
+            while Instant::now() < deadline {
+                match self.recv()? {
+                    Some((TUNNELING_ACK, ack)) if ack.len() >= 4 && ack[1] == self.channel && ack[2] == seq => {
+                        return if ack[3] == 0 { Ok(()) } else { Err(format!("tunneling request refused with status {:#04x}", ack[3])) };
+                    }
+                    // the interface may send telegrams before it acks ours, those must be acked too
+                    Some((TUNNELING_REQUEST, request)) if request.len() >= 4 && request[1] == self.channel => {
+                        self.send(TUNNELING_ACK, &[0x04, self.channel, request[2], 0x00])?;
+                        if request[2] == self.recv_seq {
+                            self.recv_seq = self.recv_seq.wrapping_add(1);
+                            self.telegram(&request[4..], sent);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Err("no tunneling ack".to_owned())
+    }
+
+    // Handles an L_Data.ind from the bus, reads of "out" addresses get their GroupValueResponse queued
+    fn telegram(&mut self, cemi: &[u8], sent: &mut [Option<TagValue>]) -> Option<()> {
+        let (&code, rest) = cemi.split_first()?;
+        let (&info_len, rest) = rest.split_first()?;
+        let frame = rest.get(info_len as usize..)?;
+        // ctrl1, ctrl2, source, destination, length, TPCI/APCI...
+        if code != L_DATA_IND || frame.len() < 8 || frame[1] & 0x80 == 0 {
+            return None; // confirmations of our own frames, individually addressed frames
+        }
+        let group = u16::from_be_bytes([frame[4], frame[5]]);
+        let len = frame[6] as usize;
+        if len == 0 {
+            return None;
+        }
+        let tpdu = frame.get(7..8 + len)?;
+        let apci = ((tpdu[0] & 0x03) as u16) << 8 | (tpdu[1] & 0xC0) as u16;
+        let payload = if len == 1 { Payload::Small(tpdu[1] & 0x3F) } else { Payload::Bytes(tpdu[2..].to_vec()) };
+
+        match apci {
+            GROUP_VALUE_READ => {
+                let mapping = self.mappings.iter().find(|mapping| mapping.group == group && mapping.outgoing())?;
+                let sample = self.table.read_sample(mapping.tag);
+                if sample.quality.is_good() {
+                    self.outbox.push_back(group_frame(group, GROUP_VALUE_RESPONSE, mapping.dpt.encode(sample.value.as_f64())));
+                }
+            }
+            GROUP_VALUE_WRITE => {
+                for (i, mapping) in self.mappings.iter().enumerate().filter(|(_, mapping)| mapping.group == group && mapping.incoming()) {
+                    let tag = &self.table.tags()[mapping.tag];
+                    let value = mapping.dpt.decode(&payload).and_then(|number| to_tag(tag.ty, number)).filter(|value| tag.accepts(*value));
+                    let Some(value) = value else {
+                        log::warn!("[KNX] Ignoring write to {}: {:?} isn't a value for '{}' {:?}", format_group_address(group), payload, tag.name, tag.write_range());
+                        continue;
+                    };
+                    if commands::write_tag(&self.table, "KNX", &self.user, mapping.tag, value).is_ok() {
+                        sent[i] = Some(value); // it came from the bus, no need to send it back
+                    }
+                }
+            }
+            _ => {} // responses to someone's read, other services
+        }
+        Some(())
+    }
+}
+
+// L_Data.req to a group address: standard frame, low priority, hop count 6, source filled in by the interface
+fn group_frame(group: u16, apci: u16, payload: Payload) -> Vec<u8> {
+    let mut frame = vec![L_DATA_REQ, 0x00, 0xBC, 0xE0, 0x00, 0x00];
+    frame.extend(group.to_be_bytes());
+    match payload {
+        Payload::Small(bits) => frame.extend([1, (apci >> 8) as u8, apci as u8 | (bits & 0x3F)]),
+        Payload::Bytes(bytes) => {
+            frame.extend([1 + bytes.len() as u8, (apci >> 8) as u8, apci as u8]);
+            frame.extend(bytes);
+        }
+    }
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::tests::table;
+    use gipop_shm::ring::{ITEM_AUDIT, ITEM_TAG_WRITE};
+    use gipop_shm::{Publisher, TagType, TAG_WRITABLE};
+
+    const CHANNEL: u8 = 7;
+    const ROOM_TEMP: usize = 0; // 1/2/3 DPT 9, both ways
+    const LIGHT: usize = 1; // 1/2/4 DPT 1, in
+
+    #[test]
+    fn datapoint_types() {
+        let cases = [
+            ("1.001", Some(Dpt::Bool)),
+            ("1", Some(Dpt::Bool)),
+            ("5.001", Some(Dpt::Percent)),
+            ("5.010", Some(Dpt::U8)),
+            ("7.001", Some(Dpt::U16)),
+            ("9.001", Some(Dpt::Float16)),
+            ("12.001", Some(Dpt::U32)),
+            ("13.010", Some(Dpt::I32)),
+            ("14.068", Some(Dpt::Float32)),
+            ("16.000", None),
+            ("9.x", Some(Dpt::Float16)),
+            ("", None),
+            ("temperature", None),
+        ];
+        for (text, dpt) in cases {
+            assert_eq!(Dpt::parse(text), dpt, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn bool_and_percent_conversions() {
+        // type, value, payload, the value it decodes back to
+        let cases = [
+            (Dpt::Bool, 0.0, Payload::Small(0), 0.0),
+            (Dpt::Bool, 1.0, Payload::Small(1), 1.0),
+            (Dpt::Bool, 0.5, Payload::Small(1), 1.0),
+            (Dpt::Bool, -1.0, Payload::Small(1), 1.0),
+            (Dpt::Percent, 0.0, Payload::Bytes(vec![0]), 0.0),
+            (Dpt::Percent, 100.0, Payload::Bytes(vec![255]), 100.0),
+            (Dpt::Percent, 50.0, Payload::Bytes(vec![128]), 128.0 * 100.0 / 255.0),
+            (Dpt::Percent, -5.0, Payload::Bytes(vec![0]), 0.0),
+            (Dpt::Percent, 150.0, Payload::Bytes(vec![255]), 100.0),
+            (Dpt::U8, 12.4, Payload::Bytes(vec![12]), 12.0),
+            (Dpt::U8, 300.0, Payload::Bytes(vec![255]), 255.0),
+            (Dpt::U8, -1.0, Payload::Bytes(vec![0]), 0.0),
+        ];
+        for (dpt, value, payload, decoded) in cases {
+            assert_eq!(dpt.encode(value), payload, "{:?} {}", dpt, value);
+            assert_eq!(dpt.decode(&payload), Some(decoded), "{:?} {:?}", dpt, payload);
+        }
+
+        // only the low bit of a 1.x, lengths that aren't the type's
+        assert_eq!(Dpt::Bool.decode(&Payload::Small(0x3E)), Some(0.0));
+        assert_eq!(Dpt::Bool.decode(&Payload::Bytes(vec![1])), None);
+        assert_eq!(Dpt::Percent.decode(&Payload::Small(1)), None);
+        assert_eq!(Dpt::Percent.decode(&Payload::Bytes(vec![1, 2])), None);
+        assert_eq!(Dpt::U8.decode(&Payload::Bytes(Vec::new())), None);
+    }
+
+    #[test]
+    fn float16_conversions() {
+        // value, raw, the value it decodes back to
+        let cases = [
+            (0.0, 0x0000, 0.0),
+            (0.01, 0x0001, 0.01),
+            (-0.01, 0x87FF, -0.01),
+            (21.5, 0x0C33, 21.5),
+            (-10.0, 0x8418, -10.0),
+            (20.47, 0x07FF, 20.47),
+            (20.48, 0x0C00, 20.48),
+            (-20.48, 0x8000, -20.48),
+            (0.004, 0x0000, 0.0),
+            (-671088.64, 0xF800, -671088.64),
+            (670433.28, 0x7FFE, 670433.28),
+            // saturated, the largest value short of the invalid one
+            (670760.96, 0x7FFE, 670433.28),
+            (1e9, 0x7FFE, 670433.28),
+            (f64::INFINITY, 0x7FFE, 670433.28),
+            (-1e9, 0xF800, -671088.64),
+            (f64::NEG_INFINITY, 0xF800, -671088.64),
+        ];
+        for (value, raw, decoded) in cases {
+            assert_eq!(encode_float16(value), raw, "{}", value);
+            let back = decode_float16(raw).unwrap();
+            assert!((back - decoded).abs() <= 1e-9 * decoded.abs().max(1.0), "{:#06x} is {}, not {}", raw, back, decoded);
+        }
+
+        // NaN goes out as the invalid value, which doesn't come back in as a number
+        assert_eq!(encode_float16(f64::NAN), FLOAT16_INVALID);
+        assert_eq!(decode_float16(FLOAT16_INVALID), None);
+        assert_eq!(Dpt::Float16.decode(&Payload::Bytes(vec![0x7F, 0xFF])), None);
+        // not normalized, as some devices send them
+        assert_eq!(decode_float16(0x0864), Some(2.0));
+        assert_eq!(Dpt::Float16.encode(21.5), Payload::Bytes(vec![0x0C, 0x33]));
+        assert_eq!(Dpt::Float16.decode(&Payload::Bytes(vec![0x0C])), None);
+    }
+
+    // A tunnel on CHANNEL to `interface`, which the test plays
+    fn bridge(name: &str) -> (Publisher, Bridge, UdpSocket) {
+        let tags = [TagDef::new("room temp", TagType::Float32, TAG_WRITABLE), TagDef::new("light", TagType::Bool, TAG_WRITABLE)];
+        let (plc, table) = table(name, &tags);
+        let mappings = vec![
+            Mapping { tag: ROOM_TEMP, group: 0x0A03, dpt: Dpt::Float16, direction: KnxDirection::Both, min_change: 0.0 },
+            Mapping { tag: LIGHT, group: 0x0A04, dpt: Dpt::Bool, direction: KnxDirection::In, min_change: 0.0 },
+        ];
+        let interface = UdpSocket::bind("127.0.0.1:0").unwrap();
+        interface.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_millis(20))).unwrap();
+        let gateway = interface.local_addr().unwrap();
+        let bridge = Bridge {
+            socket, gateway, table, user: "knx".to_owned(), mappings, channel: CHANNEL, send_seq: 0, recv_seq: 0, outbox: VecDeque::new(),
+        };
+        (plc, bridge, interface)
+    }
+
+    fn packet(service: u16, body: &[u8]) -> Vec<u8> {
+        [&[0x06, 0x10][..], &service.to_be_bytes(), &(6 + body.len() as u16).to_be_bytes(), body].concat()
+    }
+
+    // The interface's tunneling request `seq` carrying a telegram from the bus
+    fn from_bus(bridge: &Bridge, interface: &UdpSocket, channel: u8, seq: u8, group: u16, apci: u16, payload: Payload) {
+        let mut cemi = group_frame(group, apci, payload);
+        cemi[0] = L_DATA_IND;
+        let body = [&[0x04, channel, seq, 0x00][..], &cemi].concat();
+        interface.send_to(&packet(TUNNELING_REQUEST, &body), bridge.socket.local_addr().unwrap()).unwrap();
+    }
+
+    fn ack(bridge: &Bridge, interface: &UdpSocket, seq: u8, status: u8) {
+        interface.send_to(&packet(TUNNELING_ACK, &[0x04, CHANNEL, seq, status]), bridge.socket.local_addr().unwrap()).unwrap();
+    }
+
+    // The next packet the bridge sent, None if there's none
+    fn to_interface(interface: &UdpSocket) -> Option<Vec<u8>> {
+        let mut buf = [0u8; 512];
+        interface.recv(&mut buf).ok().map(|len| buf[..len].to_vec())
+    }
+
+    fn written(plc: &mut Publisher) -> Option<(usize, TagValue, String)> {
+        let write = plc.pop_command()?;
+        assert_eq!(write.kind, ITEM_TAG_WRITE);
+        let audit = plc.pop_command().expect("audit");
+        assert_eq!(audit.kind, ITEM_AUDIT);
+        let ty = if write.tag as usize == LIGHT { TagType::Bool } else { TagType::Float32 };
+        Some((write.tag as usize, TagValue::from_raw(ty, write.value), audit.audited().2))
+    }
+
+    #[test]
+    fn tunneling_requests_are_acked() {
+        let (mut plc, mut bridge, interface) = bridge("knx_acks");
+        let mut sent = vec![None; 2];
+
+        from_bus(&bridge, &interface, CHANNEL, 0, 0x0A03, GROUP_VALUE_WRITE, Dpt::Float16.encode(21.5));
+        bridge.receive(&mut sent).unwrap();
+        assert_eq!(to_interface(&interface), Some(packet(TUNNELING_ACK, &[0x04, CHANNEL, 0, 0])));
+        assert_eq!(written(&mut plc), Some((ROOM_TEMP, TagValue::Float32(21.5), "knx".to_owned())));
+        assert_eq!(sent[0], Some(TagValue::Float32(21.5)), "not echoed back");
+
+        // a repeat (our ack got lost) is acked again but not written twice
+        from_bus(&bridge, &interface, CHANNEL, 0, 0x0A03, GROUP_VALUE_WRITE, Dpt::Float16.encode(21.5));
+        bridge.receive(&mut sent).unwrap();
+        assert_eq!(to_interface(&interface), Some(packet(TUNNELING_ACK, &[0x04, CHANNEL, 0, 0])));
+        assert_eq!(written(&mut plc), None);
+
+        // a read of an "out" address gets its response queued
+        plc.write(ROOM_TEMP, TagValue::Float32(19.0)).unwrap();
+        from_bus(&bridge, &interface, CHANNEL, 1, 0x0A03, GROUP_VALUE_READ, Payload::Small(0));
+        bridge.receive(&mut sent).unwrap();
+        assert_eq!(to_interface(&interface), Some(packet(TUNNELING_ACK, &[0x04, CHANNEL, 1, 0])));
+        assert_eq!(bridge.outbox, [group_frame(0x0A03, GROUP_VALUE_RESPONSE, Dpt::Float16.encode(19.0))]);
+
+        // other channels' requests aren't ours to ack, nor is garbage
+        from_bus(&bridge, &interface, CHANNEL + 1, 2, 0x0A04, GROUP_VALUE_WRITE, Payload::Small(1));
+        bridge.receive(&mut sent).unwrap();
+        interface.send_to(&packet(TUNNELING_REQUEST, &[0x04, CHANNEL]), bridge.socket.local_addr().unwrap()).unwrap();
+        bridge.receive(&mut sent).unwrap();
+        interface.send_to(&[0x06, 0x10, 0x04, 0x20, 0x00, 0xFF], bridge.socket.local_addr().unwrap()).unwrap();
+        bridge.receive(&mut sent).unwrap();
+        assert_eq!(to_interface(&interface), None);
+        assert_eq!((bridge.recv_seq, written(&mut plc)), (2, None));
+
+        // nothing there isn't an error
+        bridge.receive(&mut sent).unwrap();
+
+        from_bus(&bridge, &interface, CHANNEL, 2, 0x0A04, GROUP_VALUE_WRITE, Payload::Small(1));
+        bridge.receive(&mut sent).unwrap();
+        assert_eq!(to_interface(&interface), Some(packet(TUNNELING_ACK, &[0x04, CHANNEL, 2, 0])));
+        assert_eq!(written(&mut plc), Some((LIGHT, TagValue::Bool(true), "knx".to_owned())));
+
+        let disconnect = [&[CHANNEL, 0][..], &ROUTE_BACK_HPAI].concat();
+        interface.send_to(&packet(DISCONNECT_REQUEST, &disconnect), bridge.socket.local_addr().unwrap()).unwrap();
+        assert!(bridge.receive(&mut sent).is_err());
+        assert_eq!(to_interface(&interface), Some(packet(DISCONNECT_RESPONSE, &[CHANNEL, 0])));
+    }
+
+    #[test]
+    fn sent_frames_wait_for_their_ack() {
+        let (mut plc, mut bridge, interface) = bridge("knx_send");
+        let mut sent = vec![None; 2];
+        let frame = group_frame(0x0A03, GROUP_VALUE_WRITE, Dpt::Float16.encode(21.5));
+        let request = |seq: u8| packet(TUNNELING_REQUEST, &[&[0x04, CHANNEL, seq, 0x00][..], &frame].concat());
+
+        // a telegram from the bus and an ack for some other frame before ours
+        from_bus(&bridge, &interface, CHANNEL, 0, 0x0A04, GROUP_VALUE_WRITE, Payload::Small(1));
+        ack(&bridge, &interface, 5, 0);
+        ack(&bridge, &interface, 0, 0);
+        bridge.send_frame(&frame, &mut sent).unwrap();
+        assert_eq!(to_interface(&interface), Some(request(0)));
+        assert_eq!(to_interface(&interface), Some(packet(TUNNELING_ACK, &[0x04, CHANNEL, 0, 0])));
+        assert_eq!(to_interface(&interface), None);
+        assert_eq!(written(&mut plc), Some((LIGHT, TagValue::Bool(true), "knx".to_owned())));
+        assert_eq!(bridge.recv_seq, 1);
+
+        // refused
+        ack(&bridge, &interface, 1, 0x29);
+        assert!(bridge.send_frame(&frame, &mut sent).unwrap_err().contains("0x29"));
+        assert_eq!(to_interface(&interface), Some(request(1)));
+
+        // no ack, sent once more then given up
+        assert_eq!(bridge.send_frame(&frame, &mut sent), Err("no tunneling ack".to_owned()));
+        assert_eq!(to_interface(&interface), Some(request(2)));
+        assert_eq!(to_interface(&interface), Some(request(2)));
+        assert_eq!(bridge.send_seq, 3);
+    }
+}
@@ -0,0 +1,367 @@
+// Gateway between the PLC's tags and systems that speak neither OPC UA nor gRPC, a consumer like the OPC UA server.
+// Plain MQTT (mqtt.rs, [mqtt]), Sparkplug B (sparkplug.rs, [sparkplug], `sparkplug` feature), a REST API (http.rs,
+// [http]) with a web HMI, an InfluxDB writer (influx.rs, [influx], `influx` feature), trend files (file_log.rs, [file_log]), a
+// BACnet/IP server (bacnet.rs, [bacnet]), a KNXnet/IP tunneling client (knx.rs, [knx]), an IEC 60870-5-104
+// outstation (iec104.rs, [iec104]), a DNP3 outstation (dnp3.rs, [dnp3]), webhooks on alarms and events (webhooks.rs, [webhooks]), alarm notifications by
+// email and Telegram (notify.rs, [notify]), NATS subjects with JetStream for events (nats.rs, [nats]), a Redis tag
+// mirror (redis.rs, [redis]), a Kafka producer (kafka.rs, [kafka], `kafka` feature) and an SQLite alarm and event
+// store (event_store.rs, [event_store], `event_store` feature), see config.rs. The protocols without a feature named
+// here are features too, on by default, so a build for a small device can leave out what it doesn't speak
+// (`--no-default-features --features mqtt`), see Cargo.toml.
+// The config sections and shared helpers of protocols left out stay, unused
+#![cfg_attr(
+    not(all(feature = "mqtt", feature = "http", feature = "bacnet", feature = "knx", feature = "iec104", feature = "dnp3", feature = "nats", feature = "redis")),
+    allow(dead_code)
+)]
+#[cfg(any(feature = "kafka", feature = "event_store"))]
+mod audit_log;
+#[cfg(feature = "bacnet")]
+mod bacnet;
+mod commands;
+mod config;
+#[cfg(feature = "dnp3")]
+mod dnp3;
+#[cfg(feature = "event_store")]
+mod event_store;
+mod file_log;
+#[cfg(feature = "http")]
+mod http;
+#[cfg(feature = "iec104")]
+mod iec104;
+#[cfg(feature = "kafka")]
+mod kafka;
+#[cfg(feature = "influx")]
+mod influx;
+#[cfg(feature = "knx")]
+mod knx;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+#[cfg(feature = "nats")]
+mod nats;
+mod notify;
+#[cfg(feature = "redis")]
+mod redis;
+#[cfg(feature = "sparkplug")]
+mod sparkplug;
+mod webhooks;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use gipop_shm::{IpcConfig, Startup, Subscriber, Users};
+use historian::compression::CompressionSettings;
+
+use config::{
+    BacnetConfig, Dnp3Config, EventStoreConfig, GatewayConfig, HttpConfig, Iec104Config, InfluxConfig, KafkaConfig, KnxConfig, MqttConfig,
+    NatsConfig, RedisConfig, SparkplugConfig,
+};
+
+const CONFIG_PATH: &str = "../gipop.toml"; // same working directory assumption as the OPC UA server
+static CONFIG: OnceLock<String> = OnceLock::new();
+
+// gipop.toml, the project's with GIPOP_PROJECT (gipop_shm::project)
+fn config_path() -> &'static str {
+    CONFIG.get().map_or(CONFIG_PATH, String::as_str)
+}
+
+#[tokio::main]
+async fn main() {
+    match gipop_shm::project::config_path(CONFIG_PATH, env!("CARGO_PKG_VERSION")) {
+        Ok(config) => CONFIG.set(config).expect("config path set once"),
+        Err(e) => {
+            eprintln!("gipop_gateway: {}", e);
+            std::process::exit(1);
+        }
+    }
+    gipop_shm::logging::init(config_path(), "gipop_gateway");
+    gipop_shm::logging::reload_on_sighup();
+    gipop_shm::logging::on_sighup(|| {
+        config::RELOADS.fetch_add(1, Ordering::Relaxed);
+    });
+
+    let ipc = match IpcConfig::load(config_path()) {
+        Ok(ipc) => ipc,
+        Err(e) => {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let cfg = match GatewayConfig::load(config_path()) {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    // commands are checked against the same [users] as OPC UA writes
+    let users = match Users::load(config_path()) {
+        Ok(users) => users,
+        Err(e) => {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if cfg.mqtt.broker.is_empty() && cfg.sparkplug.broker.is_empty() && cfg.influx.url.is_empty() && !cfg.http.enabled && !cfg.file_log.enabled
+        && !cfg.bacnet.enabled && cfg.knx.gateway.is_empty() && !cfg.iec104.enabled && !cfg.dnp3.enabled && cfg.webhooks.hooks.is_empty()
+        && cfg.notify.recipients.is_empty() && cfg.nats.url.is_empty() && cfg.redis.url.is_empty() && cfg.kafka.brokers.is_empty()
+        && cfg.event_store.path.is_empty() {
+        log::warn!(
+            "No [mqtt], [sparkplug] or [kafka] broker, [influx], [nats] or [redis] url, [event_store] path, [knx] gateway, [webhooks] hook, [notify] recipient, [http], [file_log], [bacnet], [iec104] or [dnp3] enabled in {}, nothing to do",
+            config_path(),
+        );
+        return;
+    }
+
+    // Like the server, waits [ipc] connect_timeout_ms for the PLC when started before it (gipopd starts the gateway once
+    // it publishes). The sinks that attach on their own below find it up by then
+    let mut startup = Startup::new("gipop_gateway");
+    let table = startup.up(ipc.connect_step(), || Subscriber::connect(&ipc)).unwrap_or_else(|e| startup.fail(&e));
+
+    if !cfg.mqtt.broker.is_empty() {
+        start_mqtt(cfg.mqtt, cfg.compression.clone(), table.clone(), users.clone());
+    }
+    if !cfg.sparkplug.broker.is_empty() {
+        start_sparkplug(cfg.sparkplug, table.clone(), users.clone());
+    }
+    if !cfg.influx.url.is_empty() {
+        start_influx(cfg.influx, cfg.compression.clone(), table.clone());
+    }
+    // stopped and waited for at the end, so they can close their file and tunnel and report what wasn't delivered
+    let stop = Arc::new(AtomicBool::new(false));
+    let file_log = cfg.file_log.enabled.then(|| {
+        let (file_log, compression, table, stop) = (cfg.file_log, cfg.compression.clone(), table.clone(), stop.clone());
+        std::thread::spawn(move || {
+            if let Err(e) = file_log::run(file_log, compression, table, &stop) {
+                log::error!("[File log] {}", e);
+            }
+        })
+    });
+    let knx = (!cfg.knx.gateway.is_empty()).then(|| start_knx(cfg.knx, table.clone(), users.clone(), stop.clone())).flatten();
+    let webhooks = (!cfg.webhooks.hooks.is_empty()).then(|| {
+        let (webhooks, ipc, stop) = (cfg.webhooks, ipc.clone(), stop.clone());
+        std::thread::spawn(move || {
+            // a Subscriber of its own for the events, the HTTP API drains those of `table`
+            if let Err(e) = Subscriber::connect(&ipc).and_then(|events| webhooks::run(webhooks, events, &stop)) {
+                log::error!("[Webhooks] {}", e);
+            }
+        })
+    });
+    let notify = (!cfg.notify.recipients.is_empty()).then(|| {
+        let (notify, ipc, stop) = (cfg.notify, ipc.clone(), stop.clone());
+        std::thread::spawn(move || {
+            if let Err(e) = Subscriber::connect(&ipc).and_then(|events| notify::run(notify, events, &stop)) {
+                log::error!("[Notify] {}", e);
+            }
+        })
+    });
+    let redis = (!cfg.redis.url.is_empty()).then(|| start_redis(cfg.redis, table.clone(), stop.clone())).flatten();
+    let kafka = (!cfg.kafka.brokers.is_empty()).then(|| start_kafka(cfg.kafka, ipc.clone(), stop.clone())).flatten();
+    let event_store = cfg.event_store.path.clone();
+    let events = (!event_store.is_empty()).then(|| start_event_store(cfg.event_store, ipc.clone(), stop.clone())).flatten();
+    if !cfg.nats.url.is_empty() {
+        start_nats(cfg.nats, table.clone(), ipc.clone(), users.clone());
+    }
+    if cfg.bacnet.enabled {
+        start_bacnet(cfg.bacnet, table.clone(), users.clone());
+    }
+    if cfg.iec104.enabled {
+        start_iec104(cfg.iec104, table.clone(), users.clone());
+    }
+    if cfg.dnp3.enabled {
+        start_dnp3(cfg.dnp3, table.clone(), users.clone());
+    }
+    if cfg.http.enabled {
+        start_http(cfg.http, table.clone(), users, ipc.heartbeat_timeout(), event_store);
+    }
+
+    // we count as a consumer, the PLC sees us through the shared heartbeat
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_millis(500)) => table.heartbeat(),
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+    stop.store(true, Ordering::Relaxed);
+    for thread in [file_log, knx, webhooks, notify, redis, kafka, events].into_iter().flatten() {
+        _ = thread.join();
+    }
+    log::info!("Gateway terminated");
+}
+
+// Each protocol is a feature (Cargo.toml), all of them on by default. One left out warns if it's configured anyway
+#[cfg(feature = "mqtt")]
+fn start_mqtt(config: MqttConfig, compression: CompressionSettings, table: Subscriber, users: Users) {
+    tokio::spawn(async move {
+        if let Err(e) = mqtt::run(config, compression, table, users).await {
+            log::error!("[MQTT] {}", e);
+        }
+    });
+}
+
+#[cfg(not(feature = "mqtt"))]
+fn start_mqtt(_config: MqttConfig, _compression: CompressionSettings, _table: Subscriber, _users: Users) {
+    log::warn!("MQTT is configured in {} but this build doesn't have the `mqtt` feature", config_path());
+}
+
+#[cfg(feature = "knx")]
+fn start_knx(config: KnxConfig, table: Subscriber, users: Users, stop: Arc<AtomicBool>) -> Option<std::thread::JoinHandle<()>> {
+    Some(std::thread::spawn(move || {
+        if let Err(e) = knx::run(config, table, users, &stop) {
+            log::error!("[KNX] {}", e);
+        }
+    }))
+}
+
+#[cfg(not(feature = "knx"))]
+fn start_knx(_config: KnxConfig, _table: Subscriber, _users: Users, _stop: Arc<AtomicBool>) -> Option<std::thread::JoinHandle<()>> {
+    log::warn!("KNX is configured in {} but this build doesn't have the `knx` feature", config_path());
+    None
+}
+
+#[cfg(feature = "redis")]
+fn start_redis(config: RedisConfig, table: Subscriber, stop: Arc<AtomicBool>) -> Option<std::thread::JoinHandle<()>> {
+    Some(std::thread::spawn(move || {
+        if let Err(e) = redis::run(config, table, &stop) {
+            log::error!("[Redis] {}", e);
+        }
+    }))
+}
+
+#[cfg(not(feature = "redis"))]
+fn start_redis(_config: RedisConfig, _table: Subscriber, _stop: Arc<AtomicBool>) -> Option<std::thread::JoinHandle<()>> {
+    log::warn!("Redis is configured in {} but this build doesn't have the `redis` feature", config_path());
+    None
+}
+
+#[cfg(feature = "nats")]
+fn start_nats(config: NatsConfig, table: Subscriber, ipc: IpcConfig, users: Users) {
+    tokio::spawn(async move {
+        let result = match Subscriber::connect(&ipc) {
+            Ok(events) => nats::run(config, table, events, users).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            log::error!("[NATS] {}", e);
+        }
+    });
+}
+
+#[cfg(not(feature = "nats"))]
+fn start_nats(_config: NatsConfig, _table: Subscriber, _ipc: IpcConfig, _users: Users) {
+    log::warn!("NATS is configured in {} but this build doesn't have the `nats` feature", config_path());
+}
+
+#[cfg(feature = "bacnet")]
+fn start_bacnet(config: BacnetConfig, table: Subscriber, users: Users) {
+    tokio::spawn(async move {
+        if let Err(e) = bacnet::serve(config, table, users).await {
+            log::error!("[BACnet] {}", e);
+        }
+    });
+}
+
+#[cfg(not(feature = "bacnet"))]
+fn start_bacnet(_config: BacnetConfig, _table: Subscriber, _users: Users) {
+    log::warn!("BACnet is enabled in {} but this build doesn't have the `bacnet` feature", config_path());
+}
+
+#[cfg(feature = "iec104")]
+fn start_iec104(config: Iec104Config, table: Subscriber, users: Users) {
+    tokio::spawn(async move {
+        if let Err(e) = iec104::serve(config, table, users).await {
+            log::error!("[IEC 104] {}", e);
+        }
+    });
+}
+
+#[cfg(not(feature = "iec104"))]
+fn start_iec104(_config: Iec104Config, _table: Subscriber, _users: Users) {
+    log::warn!("IEC 104 is enabled in {} but this build doesn't have the `iec104` feature", config_path());
+}
+
+#[cfg(feature = "dnp3")]
+fn start_dnp3(config: Dnp3Config, table: Subscriber, users: Users) {
+    tokio::spawn(async move {
+        if let Err(e) = dnp3::serve(config, table, users).await {
+            log::error!("[DNP3] {}", e);
+        }
+    });
+}
+
+#[cfg(not(feature = "dnp3"))]
+fn start_dnp3(_config: Dnp3Config, _table: Subscriber, _users: Users) {
+    log::warn!("DNP3 is enabled in {} but this build doesn't have the `dnp3` feature", config_path());
+}
+
+#[cfg(feature = "http")]
+fn start_http(config: HttpConfig, table: Subscriber, users: Users, timeout: Duration, event_store: String) {
+    tokio::spawn(async move {
+        if let Err(e) = http::serve(config, table, users, timeout, &event_store).await {
+            log::error!("[HTTP] {}", e);
+        }
+    });
+}
+
+#[cfg(not(feature = "http"))]
+fn start_http(_config: HttpConfig, _table: Subscriber, _users: Users, _timeout: Duration, _event_store: String) {
+    log::warn!("The REST API is enabled in {} but this build doesn't have the `http` feature", config_path());
+}
+
+#[cfg(feature = "sparkplug")]
+fn start_sparkplug(config: SparkplugConfig, table: Subscriber, users: Users) {
+    tokio::spawn(async move {
+        if let Err(e) = sparkplug::run(config, table, users).await {
+            log::error!("[Sparkplug] {}", e);
+        }
+    });
+}
+
+#[cfg(not(feature = "sparkplug"))]
+fn start_sparkplug(_config: SparkplugConfig, _table: Subscriber, _users: Users) {
+    log::warn!("Sparkplug is configured in {} but this build doesn't have the `sparkplug` feature", config_path());
+}
+
+#[cfg(feature = "influx")]
+fn start_influx(config: InfluxConfig, compression: CompressionSettings, table: Subscriber) {
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = influx::run(config, compression, table) {
+            log::error!("[Influx] {}", e);
+        }
+    });
+}
+
+#[cfg(not(feature = "influx"))]
+fn start_influx(_config: InfluxConfig, _compression: CompressionSettings, _table: Subscriber) {
+    log::warn!("InfluxDB is configured in {} but this build doesn't have the `influx` feature", config_path());
+}
+
+#[cfg(feature = "kafka")]
+fn start_kafka(config: KafkaConfig, ipc: IpcConfig, stop: Arc<AtomicBool>) -> Option<std::thread::JoinHandle<()>> {
+    Some(std::thread::spawn(move || {
+        if let Err(e) = Subscriber::connect(&ipc).and_then(|events| kafka::run(config, events, &stop)) {
+            log::error!("[Kafka] {}", e);
+        }
+    }))
+}
+
+#[cfg(not(feature = "kafka"))]
+fn start_kafka(_config: KafkaConfig, _ipc: IpcConfig, _stop: Arc<AtomicBool>) -> Option<std::thread::JoinHandle<()>> {
+    log::warn!("Kafka is configured in {} but this build doesn't have the `kafka` feature", config_path());
+    None
+}
+
+#[cfg(feature = "event_store")]
+fn start_event_store(config: EventStoreConfig, ipc: IpcConfig, stop: Arc<AtomicBool>) -> Option<std::thread::JoinHandle<()>> {
+    Some(std::thread::spawn(move || {
+        if let Err(e) = Subscriber::connect(&ipc).and_then(|events| event_store::run(config, events, &stop)) {
+            log::error!("[Event store] {}", e);
+        }
+    }))
+}
+
+#[cfg(not(feature = "event_store"))]
+fn start_event_store(_config: EventStoreConfig, _ipc: IpcConfig, _stop: Arc<AtomicBool>) -> Option<std::thread::JoinHandle<()>> {
+    log::warn!("The event store is configured in {} but this build doesn't have the `event_store` feature", config_path());
+    None
+}
@@ -0,0 +1,210 @@
+// MQTT bridge: tag changes go out to their [[mqtt.publish]] topics, messages on [[mqtt.commands]] topics come in as
+// tag writes, the same path OPC UA writes take, audited as coming from [mqtt] user.
+//
+// Tags are polled every poll_ms and published when their value or quality changed, and all of them again after every
+// (re)connect so retained topics are current. Good values that only moved within [compression] aren't published.
+// Commands are only subscribed to if [mqtt] user may write tags per [users]. Whoever can publish to a command topic
+// acts as that user, lock the topics down in the broker's ACLs.
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use gipop_shm::{Action, Quality, Subscriber, TagSample, TagType, TagValue, Users};
+use historian::compression::{CompressionSettings, Compressor};
+use historian::record::Sample;
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Packet, QoS};
+use serde_json::json;
+
+use crate::commands::{self, json_value, parse};
+use crate::config::{CommandMapping, MqttConfig, PayloadFormat, PublishMapping};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const REQUEST_BACKLOG: usize = 64; // publishes/subscribes waiting for the event loop
+
+struct Command {
+    idx: usize,
+    ty: TagType,
+    qos: QoS,
+    payload: PayloadFormat,
+}
+
+/// Connects to the broker and bridges until the process ends, Err only for a config it can't start with
+pub async fn run(config: MqttConfig, compression: CompressionSettings, table: Subscriber, users: Users) -> Result<(), String> {
+    let publish = resolve_publish(&config.publish, &table);
+    let mut commands = resolve_commands(&config.commands, &table);
+    let user = config.user.clone().unwrap_or_default();
+    if !commands.is_empty() && let Err(e) = users.authorize(config.user.as_deref(), Action::WriteTag) {
+        log::error!("[MQTT] Not taking commands, {}", e);
+        commands.clear();
+    }
+
+    let options = broker_options(&config.broker, &config.client_id, config.username.as_deref(), &config.password)?;
+    let (client, event_loop) = AsyncClient::new(options, REQUEST_BACKLOG);
+    let resync = Arc::new(AtomicBool::new(false));
+    log::info!("[MQTT] {} tags out, {} command topics in via {}", publish.len(), commands.len(), config.broker);
+    tokio::spawn(receive(event_loop, client.clone(), config.broker.clone(), commands, table.clone(), user, resync.clone()));
+    let compressor = Compressor::new(compression);
+    publish_changes(client, publish, table, compressor, Duration::from_millis(config.poll_ms.max(1)), resync).await;
+    Ok(())
+}
+
+/// Connection options for `broker` (mqtt://host[:port]), with a login if `username` is set
+pub fn broker_options(broker: &str, client_id: &str, username: Option<&str>, password: &str) -> Result<MqttOptions, String> {
+    let address = broker.strip_prefix("mqtt://").ok_or_else(|| format!("{} isn't an mqtt:// URL", broker))?;
+    let address = address.trim_end_matches('/');
+    let (host, port) = match address.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().map_err(|_| format!("bad port in {}", broker))?),
+        None => (address, 1883),
+    };
+    let mut options = MqttOptions::new(client_id, host, port);
+    options.set_keep_alive(Duration::from_secs(30));
+    if let Some(username) = username {
+        options.set_credentials(username, password);
+    }
+    Ok(options)
+}
+
+// Skips mappings to tags the PLC doesn't have
+fn resolve_publish(mappings: &[PublishMapping], table: &Subscriber) -> Vec<(usize, PublishMapping)> {
+    mappings.iter()
+        .filter_map(|mapping| match table.index_of(&mapping.tag) {
+            Some(idx) => Some((idx, mapping.clone())),
+            None => {
+                log::error!("[MQTT] No tag '{}' in the PLC's directory, not publishing it", mapping.tag);
+                None
+            }
+        })
+        .collect()
+}
+
+// Topic -> command, skipping mappings to tags the PLC wouldn't take writes on
+fn resolve_commands(mappings: &[CommandMapping], table: &Subscriber) -> HashMap<String, Command> {
+    let mut commands = HashMap::new();
+    for mapping in mappings {
+        match table.index_of(&mapping.tag).map(|idx| (idx, &table.tags()[idx])) {
+            Some((idx, tag)) if tag.writable() => {
+                let command = Command { idx, ty: tag.ty, qos: qos(mapping.qos), payload: mapping.payload };
+                commands.insert(mapping.topic.clone(), command);
+            }
+            Some(_) => log::error!("[MQTT] Tag '{}' is read only, ignoring {}", mapping.tag, mapping.topic),
+            None => log::error!("[MQTT] No tag '{}' in the PLC's directory, ignoring {}", mapping.tag, mapping.topic),
+        }
+    }
+    commands
+}
+
+pub fn qos(level: u8) -> QoS {
+    match level {
+        0 => QoS::AtMostOnce,
+        1 => QoS::AtLeastOnce,
+        _ => QoS::ExactlyOnce,
+    }
+}
+
+// Drives the connection: the event loop does the actual network IO and reconnects, nothing goes out unless it's polled
+async fn receive(
+    mut event_loop: EventLoop,
+    client: AsyncClient,
+    broker: String,
+    commands: HashMap<String, Command>,
+    table: Subscriber,
+    user: String,
+    resync: Arc<AtomicBool>,
+) {
+    loop {
+        match event_loop.poll().await {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                log::info!("[MQTT] Connected to {}", broker);
+                // clean session, subscriptions don't survive a reconnect
+                for (topic, command) in &commands {
+                    if let Err(e) = client.try_subscribe(topic.as_str(), command.qos) {
+                        log::error!("[MQTT] Subscribing to {} failed: {}", topic, e);
+                    }
+                }
+                resync.store(true, Ordering::Relaxed);
+            }
+            Ok(Event::Incoming(Packet::Publish(message))) => match commands.get(&message.topic) {
+                Some(command) => write(&table, &user, &message.topic, command, &message.payload),
+                None => log::debug!("[MQTT] Ignoring message on {}", message.topic),
+            },
+            Ok(_) => {}
+            Err(e) => {
+                log::warn!("[MQTT] {}: {}", broker, e);
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        }
+    }
+}
+
+fn write(table: &Subscriber, user: &str, topic: &str, command: &Command, payload: &[u8]) {
+    let tag = &table.tags()[command.idx];
+    let Some(value) = parse(command.ty, command.payload, payload).filter(|value| tag.accepts(*value)) else {
+        log::warn!("[MQTT] Ignoring {}: {:?} isn't a value for '{}' {:?}", topic, String::from_utf8_lossy(payload), tag.name, tag.write_range());
+        return;
+    };
+    _ = commands::write_tag(table, "MQTT", user, command.idx, value);
+}
+
+async fn publish_changes(
+    client: AsyncClient,
+    publish: Vec<(usize, PublishMapping)>,
+    table: Subscriber,
+    mut compressor: Compressor,
+    poll: Duration,
+    resync: Arc<AtomicBool>,
+) {
+    let mut published: Vec<Option<(TagValue, Quality)>> = vec![None; publish.len()];
+    let mut interval = tokio::time::interval(poll);
+    loop {
+        interval.tick().await;
+        if resync.swap(false, Ordering::Relaxed) {
+            published.fill(None);
+        }
+        for ((idx, mapping), published) in publish.iter().zip(&mut published) {
+            let sample = table.read_sample(*idx);
+            if *published == Some((sample.value, sample.quality)) {
+                continue;
+            }
+            // a quality change goes out as it is, a good value once it got through compression
+            let as_is = published.is_none_or(|(_, quality)| quality != sample.quality) || !sample.quality.is_good();
+            *published = Some((sample.value, sample.quality));
+            let samples = if as_is {
+                vec![sample]
+            } else {
+                let tag = &table.tags()[*idx];
+                compressor.sample(Sample { tag: tag.name.clone(), ts_ms: sample.ts_ms, value: sample.value.as_f64() }).into_iter()
+                    .map(|kept| TagSample { value: TagValue::from_f64(tag.ty, kept.value).unwrap_or(sample.value), ts_ms: kept.ts_ms, quality: Quality::Good })
+                    .collect()
+            };
+            for sample in samples {
+                // while disconnected the backlog fills up, what didn't fit goes out with the resync or next time round
+                if let Err(e) = client.try_publish(mapping.topic.as_str(), qos(mapping.qos), mapping.retain, payload(mapping.payload, &sample)) {
+                    log::debug!("[MQTT] {} not sent: {}", mapping.topic, e);
+                    *published = None;
+                }
+            }
+        }
+    }
+}
+
+fn payload(format: PayloadFormat, sample: &TagSample) -> String {
+    match format {
+        PayloadFormat::Raw => raw(sample.value),
+        PayloadFormat::Json => json!({
+            "value": json_value(sample.value),
+            "quality": format!("{:?}", sample.quality),
+            "ts": sample.ts_ms,
+        }).to_string(),
+    }
+}
+
+fn raw(value: TagValue) -> String {
+    match value {
+        TagValue::Bool(b) => b.to_string(),
+        TagValue::UInt32(n) => n.to_string(),
+        TagValue::Int32(n) => n.to_string(),
+        TagValue::Float32(f) => f.to_string(),
+        TagValue::Float64(f) => f.to_string(),
+    }
+}
@@ -0,0 +1,579 @@
+// NATS ([nats] in gipop.toml), a lighter alternative to MQTT for in-plant microservices. Tags go out on change as
+// <prefix>.tags.<folder>.<tag>, the PLC's folders as subject tokens so a service takes a whole group with
+// gipop.tags.area_1.>, and writes to writable tags come in on <prefix>.cmd.<the same tokens>, audited as coming from
+// [nats] user like MQTT commands. A command with a reply subject is answered, {"seq":12} once the PLC has it queued or
+// {"error":"..."}.
+//
+// The PLC's events (alarms going active, clearing and acknowledged, EnOcean telegrams, as for webhooks) go to
+// <prefix>.events.<event>[.<tag>]. With a `stream` they're kept in JetStream: the stream is created, or updated, on
+// connect and each event is published again until the server acknowledged storing it, with a Nats-Msg-Id so a resend
+// after a lost ack isn't stored twice. Unacknowledged events survive reconnects, not restarts. Without a stream they're
+// plain NATS messages, gone if nobody listens.
+//
+// Speaks the client protocol itself, over plain TCP: no TLS, keep the server on the plant network. A space, '.', '*' or
+// '>' in a folder or tag name becomes '_' in the subject, tags in "Area 1/Lights" are under gipop.tags.Area_1.Lights.
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use gipop_shm::tags::now_ms;
+use gipop_shm::{Action, Quality, Subscriber, TagDef, TagValue, Users};
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+use crate::commands::{self, json_value};
+use crate::config::{NatsConfig, PayloadFormat};
+use crate::webhooks::{event_name, Notification};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const PING_INTERVAL: Duration = Duration::from_secs(30); // the server counts as gone after two without an answer
+const ACK_TIMEOUT: Duration = Duration::from_secs(5); // a JetStream publish is repeated after this without an ack
+const MAX_PENDING: usize = 10_000; // events waiting for JetStream, the oldest are dropped beyond this
+const MAX_IN_FLIGHT: usize = 256; // published, not acknowledged yet
+const MAX_MESSAGE: usize = 64 << 20; // the most a server can be configured to send (max_payload), anything longer is garbage
+const INBOX_SID: u32 = 1;
+const COMMAND_SID: u32 = 2;
+
+// What the server sent, as far as we care
+#[derive(Debug, PartialEq)]
+enum Op {
+    Msg { subject: String, reply: Option<String>, status: Option<u16>, payload: Vec<u8> },
+    Ping,
+    Pong,
+    Err(String),
+    Other,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stream {
+    None, // events go out without JetStream
+    Creating,
+    Updating,
+    Ready,
+}
+
+struct Event {
+    id: u64,
+    subject: String,
+    body: String,
+    sent: Option<Instant>, // None: not (or no longer) in flight
+}
+
+struct Bridge {
+    config: NatsConfig,
+    table: Subscriber,
+    events: Subscriber,
+    user: String,
+    points: Vec<(usize, String)>, // tag index, subject
+    commands: HashMap<String, usize>, // subject -> tag index
+    published: Vec<Option<(TagValue, Quality)>>,
+    pending: VecDeque<Event>,
+    next_id: u64,
+    run_id: u64, // in message ids, so they don't repeat those of an earlier run
+    inbox: String,
+    stream: Stream,
+}
+
+/// Connects to the server and bridges until the process ends, Err only for a config it can't start with. `events` is a
+/// Subscriber of its own, the HTTP API drains the events of `table`.
+pub async fn run(config: NatsConfig, table: Subscriber, events: Subscriber, users: Users) -> Result<(), String> {
+    let address = address(&config.url)?;
+    let points = resolve_points(&config, &table)?;
+    let mut commands = if config.commands { resolve_commands(&config, &table)? } else { HashMap::new() };
+    if !commands.is_empty() && let Err(e) = users.authorize(config.user.as_deref(), Action::WriteTag) {
+        log::error!("[NATS] Not taking commands, {}", e);
+        commands.clear();
+    }
+    log::info!("[NATS] {} tags out, {} command subjects in via {}", points.len(), commands.len(), config.url);
+
+    let run_id = now_ms();
+    let mut bridge = Bridge {
+        user: config.user.clone().unwrap_or_default(),
+        published: vec![None; points.len()],
+        points,
+        commands,
+        table,
+        events,
+        pending: VecDeque::new(),
+        next_id: 0,
+        run_id,
+        inbox: format!("_INBOX.{}.{:x}", config.name.replace(['.', ' ', '*', '>'], "_"), run_id),
+        stream: Stream::None,
+        config,
+    };
+    loop {
+        match bridge.session(&address).await {
+            Ok(()) => log::warn!("[NATS] {} closed the connection", bridge.config.url),
+            Err(e) => log::warn!("[NATS] {}: {}", bridge.config.url, e),
+        }
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+// host:port of nats://host[:port]
+fn address(url: &str) -> Result<String, String> {
+    let address = url.strip_prefix("nats://").ok_or_else(|| format!("{} isn't a nats:// URL", url))?.trim_end_matches('/');
+    Ok(if address.contains(':') { address.to_owned() } else { format!("{}:4222", address) })
+}
+
+// <prefix>.<kind>.Area_1.Lights.hall_light for "hall light" in "Area 1/Lights"
+fn subject(prefix: &str, kind: &str, tag: &TagDef) -> String {
+    let mut subject = format!("{}.{}", prefix, kind);
+    for part in tag.folder_path().chain([tag.name.as_str()]) {
+        subject.push('.');
+        subject.push_str(&token(part));
+    }
+    subject
+}
+
+fn token(text: &str) -> String {
+    let token: String = text.chars().map(|c| if c.is_whitespace() || matches!(c, '.' | '*' | '>') { '_' } else { c }).collect();
+    if token.is_empty() { "_".to_owned() } else { token }
+}
+
+fn resolve_points(config: &NatsConfig, table: &Subscriber) -> Result<Vec<(usize, String)>, String> {
+    let indexes: Vec<usize> = if config.tags.is_empty() {
+        (0..table.tags().len()).collect()
+    }
+    else {
+        config.tags.iter()
+            .map(|name| table.index_of(name).ok_or_else(|| format!("No tag named '{}' in [nats] tags", name)))
+            .collect::<Result<_, _>>()?
+    };
+    let mut subjects: HashMap<String, usize> = HashMap::new();
+    let mut points = Vec::new();
+    for idx in indexes {
+        let tag = &table.tags()[idx];
+        let subject = subject(&config.prefix, "tags", tag);
+        match subjects.insert(subject.clone(), idx) {
+            Some(other) if other != idx => {
+                return Err(format!("Tags '{}' and '{}' both end up on {}", table.tags()[other].name, tag.name, subject));
+            }
+            Some(_) => {} // listed twice
+            None => points.push((idx, subject)),
+        }
+    }
+    Ok(points)
+}
+
+fn resolve_commands(config: &NatsConfig, table: &Subscriber) -> Result<HashMap<String, usize>, String> {
+    let mut commands = HashMap::new();
+    for (idx, tag) in table.tags().iter().enumerate().filter(|(_, tag)| tag.writable()) {
+        let subject = subject(&config.prefix, "cmd", tag);
+        if let Some(other) = commands.insert(subject.clone(), idx) {
+            return Err(format!("Tags '{}' and '{}' both end up on {}", table.tags()[other].name, tag.name, subject));
+        }
+    }
+    Ok(commands)
+}
+
+impl Bridge {
+    // One connection, until it fails
+    async fn session(&mut self, address: &str) -> Result<(), String> {
+        let stream = TcpStream::connect(address).await.map_err(|e| e.to_string())?;
+        let (read, mut write) = stream.into_split();
+        let mut read = BufReader::new(read);
+
+        let mut line = String::new();
+        read.read_line(&mut line).await.map_err(|e| e.to_string())?;
+        let info: Value = line.strip_prefix("INFO ")
+            .and_then(|info| serde_json::from_str(info).ok())
+            .ok_or_else(|| format!("not a NATS server, it said {:?}", line.trim_end()))?;
+        if !self.config.stream.is_empty() && info["headers"] != json!(true) {
+            return Err("the server doesn't take headers, which JetStream publishes need".to_owned());
+        }
+        let mut connect = json!({
+            "verbose": false,
+            "pedantic": false,
+            "name": self.config.name,
+            "lang": "rust",
+            "version": env!("CARGO_PKG_VERSION"),
+            "protocol": 1,
+            "headers": true,
+            "no_responders": true,
+        });
+        if let Some(username) = &self.config.username {
+            connect["user"] = json!(username);
+            connect["pass"] = json!(self.config.password);
+        }
+        if !self.config.token.is_empty() {
+            connect["auth_token"] = json!(self.config.token);
+        }
+        send(&mut write, format!("CONNECT {}\r\nPING\r\n", connect).as_bytes()).await?;
+        loop {
+            line.clear();
+            if read.read_line(&mut line).await.map_err(|e| e.to_string())? == 0 {
+                return Err("connection closed while logging in".to_owned());
+            }
+            match line.trim_end() {
+                "PONG" => break,
+                error if error.starts_with("-ERR") => return Err(error.to_owned()),
+                _ => {}
+            }
+        }
+        log::info!("[NATS] Connected to {} ({})", self.config.url, info["server_name"].as_str().unwrap_or("?"));
+
+        // a task of its own reads, reading isn't safe to cancel in the middle of a message
+        let (ops_tx, mut ops) = mpsc::channel(256);
+        let reader = tokio::spawn(read_ops(read, ops_tx));
+        let result = self.bridge(&mut write, &mut ops).await;
+        reader.abort();
+        result
+    }
+
+    async fn bridge(&mut self, write: &mut OwnedWriteHalf, ops: &mut mpsc::Receiver<Result<Op, String>>) -> Result<(), String> {
+        let mut out = format!("SUB {}.* {}\r\n", self.inbox, INBOX_SID);
+        if !self.commands.is_empty() {
+            out += &format!("SUB {}.cmd.> {}\r\n", self.config.prefix, COMMAND_SID);
+        }
+        send(write, out.as_bytes()).await?;
+        self.stream = Stream::None;
+        if !self.config.stream.is_empty() {
+            self.request_stream(write, "CREATE").await?;
+        }
+        // everything again, subscribers may have missed changes while we were gone
+        self.published.fill(None);
+        self.pending.iter_mut().for_each(|event| event.sent = None);
+
+        let mut poll = tokio::time::interval(Duration::from_millis(self.config.poll_ms.max(1)));
+        let mut ping = tokio::time::interval(PING_INTERVAL);
+        let mut last_heard = Instant::now();
+        loop {
+            tokio::select! {
+                op = ops.recv() => {
+                    last_heard = Instant::now();
+                    match op {
+                        Some(Ok(op)) => self.handle(write, op).await?,
+                        Some(Err(e)) => return Err(e),
+                        None => return Ok(()),
+                    }
+                }
+                _ = poll.tick() => {
+                    let out = self.changes();
+                    if !out.is_empty() {
+                        send(write, &out).await?;
+                    }
+                }
+                _ = ping.tick() => {
+                    if last_heard.elapsed() > 2 * PING_INTERVAL {
+                        return Err("the server stopped answering".to_owned());
+                    }
+                    send(write, b"PING\r\n").await?;
+                }
+            }
+        }
+    }
+
+    // $JS.API.STREAM.CREATE/UPDATE for our stream, the answer comes in on <inbox>.stream
+    async fn request_stream(&mut self, write: &mut OwnedWriteHalf, action: &str) -> Result<(), String> {
+        let config = json!({
+            "name": self.config.stream,
+            "subjects": [format!("{}.events.>", self.config.prefix)],
+            "retention": "limits",
+            "storage": "file",
+            "max_age": self.config.max_age_hours * 3600 * 1_000_000_000, // ns
+            "duplicate_window": 120_000_000_000u64, // how long message ids are remembered, 2 minutes
+        }).to_string();
+        let subject = format!("$JS.API.STREAM.{}.{}", action, self.config.stream);
+        self.stream = if action == "CREATE" { Stream::Creating } else { Stream::Updating };
+        send(write, format!("PUB {} {}.stream {}\r\n{}\r\n", subject, self.inbox, config.len(), config).as_bytes()).await
+    }
+
+    async fn handle(&mut self, write: &mut OwnedWriteHalf, op: Op) -> Result<(), String> {
+        match op {
+            Op::Msg { subject, reply, status, payload } => {
+                if let Some(token) = subject.strip_prefix(&self.inbox).and_then(|rest| rest.strip_prefix('.')) {
+                    let answer: Value = serde_json::from_slice(&payload).unwrap_or(Value::Null);
+                    if token == "stream" {
+                        return self.stream_answer(write, status, &answer).await;
+                    }
+                    if let Ok(id) = token.parse() {
+                        self.ack(id, status, &answer);
+                    }
+                }
+                else if let Some(&idx) = self.commands.get(&subject) {
+                    let answer = self.command(idx, &payload);
+                    if let Some(reply) = reply {
+                        let answer = answer.to_string();
+                        send(write, format!("PUB {} {}\r\n{}\r\n", reply, answer.len(), answer).as_bytes()).await?;
+                    }
+                }
+                else {
+                    log::debug!("[NATS] Ignoring message on {}", subject);
+                }
+            }
+            Op::Ping => send(write, b"PONG\r\n").await?,
+            Op::Err(e) => log::warn!("[NATS] Server says {}", e),
+            Op::Pong | Op::Other => {}
+        }
+        Ok(())
+    }
+
+    async fn stream_answer(&mut self, write: &mut OwnedWriteHalf, status: Option<u16>, answer: &Value) -> Result<(), String> {
+        let error = match status {
+            Some(503) => Some("JetStream isn't enabled on the server".to_owned()),
+            _ => answer.get("error").map(|error| error["description"].as_str().unwrap_or("failed").to_owned()),
+        };
+        match (error, self.stream) {
+            (None, _) => {
+                log::info!("[NATS] Events go to JetStream stream {}", self.config.stream);
+                self.stream = Stream::Ready;
+            }
+            // there already, but set up differently
+            (Some(_), Stream::Creating) if status != Some(503) => self.request_stream(write, "UPDATE").await?,
+            (Some(e), _) => {
+                // maybe it's there and only we may not set it up, publishing will tell
+                log::error!("[NATS] Can't set up stream {}: {}", self.config.stream, e);
+                self.stream = Stream::Ready;
+            }
+        }
+        Ok(())
+    }
+
+    // A JetStream publish ack, or the lack of a stream for the subject
+    fn ack(&mut self, id: u64, status: Option<u16>, answer: &Value) {
+        let Some(at) = self.pending.iter().position(|event| event.id == id) else {
+            return; // acknowledged before, this is the ack of a resend
+        };
+        let error = match status {
+            Some(503) => Some("no stream takes the subject".to_owned()),
+            _ => answer.get("error").map(|error| error["description"].as_str().unwrap_or("failed").to_owned()),
+        };
+        match error {
+            None => {
+                self.pending.remove(at);
+            }
+            // tried again once ACK_TIMEOUT is up, warned about once a round rather than for every event
+            Some(e) if at == 0 => log::warn!("[NATS] JetStream didn't store {}: {}", self.pending[at].subject, e),
+            Some(_) => {}
+        }
+    }
+
+    fn command(&self, idx: usize, payload: &[u8]) -> Value {
+        let tag = &self.table.tags()[idx];
+        let Some(value) = commands::parse(tag.ty, PayloadFormat::Json, payload).filter(|value| tag.accepts(*value)) else {
+            log::warn!("[NATS] Ignoring {:?}, not a value for '{}' {:?}", String::from_utf8_lossy(payload), tag.name, tag.write_range());
+            return json!({ "error": format!("not a value for '{}'", tag.name) });
+        };
+        match commands::write_tag(&self.table, "NATS", &self.user, idx, value) {
+            Ok(seq) => json!({ "seq": seq }),
+            Err(e) => json!({ "error": e }),
+        }
+    }
+
+    // What's to send this poll: changed tags, new events and events due for a (re)send
+    fn changes(&mut self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for ((idx, subject), published) in self.points.iter().zip(&mut self.published) {
+            let sample = self.table.read_sample(*idx);
+            if *published == Some((sample.value, sample.quality)) {
+                continue;
+            }
+            *published = Some((sample.value, sample.quality));
+            let body = json!({
+                "tag": self.table.tags()[*idx].name,
+                "value": json_value(sample.value),
+                "quality": format!("{:?}", sample.quality),
+                "ts": sample.ts_ms,
+            }).to_string();
+            pub_message(&mut out, subject, None, &body);
+        }
+
+        while let Some(event) = self.events.pop_event() {
+            let Some(notification) = Notification::from_event(&self.events, &event) else {
+                continue;
+            };
+            let mut subject = format!("{}.events.{}", self.config.prefix, event_name(notification.event));
+            if !notification.tag.is_empty() {
+                subject = format!("{}.{}", subject, token(&notification.tag));
+            }
+            let body = notification.default_body();
+            if self.config.stream.is_empty() {
+                pub_message(&mut out, &subject, None, &body);
+                continue;
+            }
+            if self.pending.len() >= MAX_PENDING {
+                self.pending.pop_front();
+                log::warn!("[NATS] More than {} events waiting for JetStream, dropping the oldest", MAX_PENDING);
+            }
+            self.next_id += 1;
+            self.pending.push_back(Event { id: self.next_id, subject, body, sent: None });
+        }
+
+        if self.stream == Stream::Ready {
+            let mut in_flight = self.pending.iter().filter(|event| event.sent.is_some_and(|sent| sent.elapsed() < ACK_TIMEOUT)).count();
+            for event in &mut self.pending {
+                if in_flight >= MAX_IN_FLIGHT {
+                    break;
+                }
+                if event.sent.is_some_and(|sent| sent.elapsed() < ACK_TIMEOUT) {
+                    continue;
+                }
+                let headers = format!("NATS/1.0\r\nNats-Msg-Id: gipop-{:x}-{}\r\n\r\n", self.run_id, event.id);
+                let reply = format!("{}.{}", self.inbox, event.id);
+                out.extend_from_slice(format!("HPUB {} {} {} {}\r\n", event.subject, reply, headers.len(), headers.len() + event.body.len()).as_bytes());
+                out.extend_from_slice(headers.as_bytes());
+                out.extend_from_slice(event.body.as_bytes());
+                out.extend_from_slice(b"\r\n");
+                event.sent = Some(Instant::now());
+                in_flight += 1;
+            }
+        }
+        out
+    }
+}
+
+fn pub_message(out: &mut Vec<u8>, subject: &str, reply: Option<&str>, body: &str) {
+    let reply = reply.map(|reply| format!("{} ", reply)).unwrap_or_default();
+    out.extend_from_slice(format!("PUB {} {}{}\r\n{}\r\n", subject, reply, body.len(), body).as_bytes());
+}
+
+async fn send(write: &mut OwnedWriteHalf, bytes: &[u8]) -> Result<(), String> {
+    write.write_all(bytes).await.map_err(|e| e.to_string())
+}
+
+// Parses what the server sends into `ops` until the connection ends or something doesn't parse
+async fn read_ops(mut read: impl AsyncBufRead + Unpin, ops: mpsc::Sender<Result<Op, String>>) {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let op = match read.read_line(&mut line).await {
+            Ok(0) => return,
+            Ok(_) => read_op(&mut read, line.trim_end()).await,
+            Err(e) => Err(e.to_string()),
+        };
+        let failed = op.is_err();
+        if ops.send(op).await.is_err() || failed {
+            return;
+        }
+    }
+}
+
+async fn read_op(read: &mut (impl AsyncBufRead + Unpin), line: &str) -> Result<Op, String> {
+    let mut words = line.split_ascii_whitespace();
+    let op = words.next().unwrap_or_default().to_ascii_uppercase();
+    let words: Vec<&str> = words.collect();
+    let bad = || format!("can't make sense of {:?}", line);
+    match op.as_str() {
+        // MSG <subject> <sid> [reply-to] <#bytes>, HMSG <subject> <sid> [reply-to] <#header bytes> <#total bytes>
+        "MSG" | "HMSG" => {
+            let lengths = if op == "MSG" { 1 } else { 2 };
+            if words.len() < 2 + lengths || words.len() > 3 + lengths {
+                return Err(bad());
+            }
+            let subject = words[0].to_owned();
+            let reply = (words.len() == 3 + lengths).then(|| words[2].to_owned());
+            let numbers: Vec<usize> = words[words.len() - lengths..].iter().map(|n| n.parse()).collect::<Result<_, _>>().map_err(|_| bad())?;
+            let (header_len, total) = if op == "MSG" { (0, numbers[0]) } else { (numbers[0], numbers[1]) };
+            if header_len > total || total > MAX_MESSAGE {
+                return Err(bad());
+            }
+            let mut data = vec![0; total + 2]; // and the \r\n
+            read.read_exact(&mut data).await.map_err(|e| e.to_string())?;
+            // "NATS/1.0 503" for no responders
+            let status = std::str::from_utf8(&data[..header_len]).ok()
+                .and_then(|headers| headers.lines().next())
+                .and_then(|first| first.split_ascii_whitespace().nth(1))
+                .and_then(|status| status.parse().ok());
+            data.truncate(total);
+            Ok(Op::Msg { subject, reply, status, payload: data.split_off(header_len) })
+        }
+        "PING" => Ok(Op::Ping),
+        "PONG" => Ok(Op::Pong),
+        "-ERR" => Ok(Op::Err(line.trim_start()[4..].trim().to_owned())),
+        _ => Ok(Op::Other), // +OK, INFO
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // What read_ops makes of `input`, which arrives a few bytes at a time
+    async fn ops(input: &[u8]) -> Vec<Result<Op, String>> {
+        let (mut server, client) = tokio::io::duplex(3);
+        let input = input.to_vec();
+        let writer = tokio::spawn(async move { _ = server.write_all(&input).await });
+        let (tx, mut rx) = mpsc::channel(64);
+        read_ops(BufReader::new(client), tx).await;
+        writer.await.unwrap();
+        let mut ops = Vec::new();
+        while let Some(op) = rx.recv().await {
+            ops.push(op);
+        }
+        ops
+    }
+
+    fn msg(subject: &str, reply: Option<&str>, status: Option<u16>, payload: &[u8]) -> Result<Op, String> {
+        Ok(Op::Msg { subject: subject.to_owned(), reply: reply.map(str::to_owned), status, payload: payload.to_vec() })
+    }
+
+    #[tokio::test]
+    async fn messages_split_across_reads() {
+        let input = concat!(
+            "INFO {\"server_name\":\"n1\"}\r\n",
+            "MSG gipop.cmd.Area_1.setpoint 2 _INBOX.7 4\r\n21.5\r\n",
+            "msg gipop.cmd.pump 2 4\r\ntrue\r\n",
+            "PING\r\n",
+            // the payload is bytes, line ends in it included
+            "MSG gipop.cmd.note 2 6\r\na\r\nb\r\n\r\n",
+            "PONG\r\n",
+            // no responders, headers only
+            "HMSG _INBOX.1 1 16 16\r\nNATS/1.0 503\r\n\r\n\r\n",
+            // a JetStream ack with headers
+            "HMSG _INBOX.2 1 20 31\r\nNATS/1.0\r\nX-A: 1\r\n\r\n{\"seq\":9}\r\n\r\n",
+            "-ERR 'Stale Connection'\r\n",
+            "+OK\r\n",
+        );
+        let expected = [
+            Ok(Op::Other),
+            msg("gipop.cmd.Area_1.setpoint", Some("_INBOX.7"), None, b"21.5"),
+            msg("gipop.cmd.pump", None, None, b"true"),
+            Ok(Op::Ping),
+            msg("gipop.cmd.note", None, None, b"a\r\nb\r\n"),
+            Ok(Op::Pong),
+            msg("_INBOX.1", None, Some(503), b""),
+            msg("_INBOX.2", None, None, b"{\"seq\":9}\r\n"),
+            Ok(Op::Err("'Stale Connection'".to_owned())),
+            Ok(Op::Other),
+        ];
+        assert_eq!(ops(input.as_bytes()).await, expected);
+    }
+
+    #[tokio::test]
+    async fn bad_lengths_end_the_connection() {
+        let cases = [
+            "MSG a 2 -1\r\n",
+            "MSG a 2 _INBOX.1 -5\r\nhello\r\n",
+            "MSG a 2 5x\r\nhello\r\n",
+            "HMSG a 1 -1 5\r\nhello\r\n",
+            "HMSG a 1 12 4\r\nNATS/1.0\r\n\r\n\r\n", // headers longer than the whole
+            "MSG a 2 99999999999999999999999\r\n",
+            "MSG a 2 18446744073709551615\r\n",
+            "MSG a 2 67108865\r\n",
+            "MSG a 2\r\n",
+            "MSG a 2 b c 5\r\n",
+            "HMSG a 1 5\r\n",
+            // the connection ends before the payload does
+            "MSG a 2 10\r\nhello",
+        ];
+        for input in cases {
+            // nothing after the error is read
+            let ops = ops(format!("{}PING\r\n", input).as_bytes()).await;
+            assert_eq!(ops.len(), 1, "{:?}: {:?}", input, ops);
+            assert!(ops[0].is_err(), "{:?}: {:?}", input, ops);
+        }
+
+        // as long as a server may send
+        let payload = vec![b'x'; MAX_MESSAGE];
+        let input = [format!("MSG a 2 {}\r\n", MAX_MESSAGE).as_bytes(), &payload, b"\r\n"].concat();
+        let (mut server, client) = tokio::io::duplex(1 << 16);
+        tokio::spawn(async move { _ = server.write_all(&input).await });
+        let mut read = BufReader::new(client);
+        let mut line = String::new();
+        read.read_line(&mut line).await.unwrap();
+        assert_eq!(read_op(&mut read, line.trim_end()).await, msg("a", None, None, &payload));
+    }
+}
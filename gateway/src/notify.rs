@@ -0,0 +1,321 @@
+// Alarm notifications to people ([notify] in gipop.toml): email over SMTP (`email` feature) and Telegram messages
+// through a bot, so high-priority alarms reach whoever is on call when nobody watches a SCADA screen. Recipients pick
+// events, tags and a minimum severity like webhooks do (webhooks::Notification).
+//
+// Each recipient gets at most max_per_hour messages per channel in any hour. What's over is counted, not sent, and a
+// summary goes out once there's room again, so an alarm storm neither buries the on-call phone nor gets the bot
+// throttled by Telegram. A failed send is retried a few times, then dropped. Blocking, run it on its own thread with a
+// Subscriber of its own, see webhooks.rs.
+//
+// Recipients, their channels and the transports change with a config reload (config::RELOADS), what's pending and the
+// rate limits carry over for recipients that stay. A gateway started without recipients takes a restart to get some.
+use std::collections::{HashSet, VecDeque};
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use gipop_shm::tags::iso_time;
+use gipop_shm::Subscriber;
+use serde_json::json;
+
+use crate::config::{GatewayConfig, NotifyConfig, Recipient, TelegramConfig, WebhookEvent, RELOADS};
+use crate::webhooks::{alarm_tags, Notification};
+
+const TIMEOUT: Duration = Duration::from_secs(15);
+const RETRIES: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+const HOUR: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Channel {
+    Email(String),    // address
+    Telegram(String), // chat id
+}
+
+impl std::fmt::Display for Channel {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Channel::Email(address) => write!(f, "email {}", address),
+            Channel::Telegram(chat) => write!(f, "Telegram chat {}", chat),
+        }
+    }
+}
+
+// One recipient on one channel
+struct Outbox {
+    recipient: String,
+    channel: Channel,
+    events: Vec<WebhookEvent>,
+    tags: HashSet<String>,
+    min_severity: u16,
+    sent: VecDeque<Instant>, // within the last hour, for the rate limit
+    suppressed: u32,         // over the rate limit since the last message
+    pending: VecDeque<Message>,
+}
+
+struct Message {
+    subject: String,
+    text: String,
+    attempts: u32,
+    due: Instant,
+}
+
+impl Outbox {
+    fn offer(&mut self, notification: &Notification, site: &str, max_per_hour: u32) {
+        if !notification.matches(&self.events, &self.tags, self.min_severity) {
+            return;
+        }
+        self.expire();
+        if self.sent.len() + self.pending.len() >= max_per_hour as usize {
+            self.suppressed += 1;
+            log::debug!("[Notify] Rate limit for {}, not sending '{}'", self.recipient, notification.message);
+            return;
+        }
+        let (subject, text) = format(notification, site, std::mem::take(&mut self.suppressed));
+        self.pending.push_back(Message { subject, text, attempts: 0, due: Instant::now() });
+    }
+
+    // The summary of what the rate limit held back, once it lets a message through again
+    fn catch_up(&mut self, site: &str, max_per_hour: u32) {
+        self.expire();
+        if self.suppressed > 0 && self.sent.len() + self.pending.len() < max_per_hour as usize {
+            let text = format!("[{}] {} more notification(s) weren't sent, too many in the last hour", site, self.suppressed);
+            self.pending.push_back(Message { subject: text.clone(), text, attempts: 0, due: Instant::now() });
+            self.suppressed = 0;
+        }
+    }
+
+    fn expire(&mut self) {
+        while self.sent.front().is_some_and(|sent| sent.elapsed() >= HOUR) {
+            self.sent.pop_front();
+        }
+    }
+
+    fn deliver(&mut self, transports: &Transports) {
+        while let Some(message) = self.pending.front_mut().filter(|message| message.due <= Instant::now()) {
+            match transports.send(&self.channel, &message.subject, &message.text) {
+                Ok(()) => {
+                    log::info!("[Notify] Sent '{}' to {} ({})", message.subject, self.recipient, self.channel);
+                    self.sent.push_back(Instant::now());
+                    self.pending.pop_front();
+                }
+                Err(e) if message.attempts < RETRIES => {
+                    log::warn!("[Notify] Failed to notify {} ({}), trying again: {}", self.recipient, self.channel, e);
+                    message.attempts += 1;
+                    message.due = Instant::now() + RETRY_DELAY;
+                    break;
+                }
+                Err(e) => {
+                    log::error!("[Notify] Giving up on notifying {} ({}) of '{}': {}", self.recipient, self.channel, message.subject, e);
+                    self.pending.pop_front();
+                }
+            }
+        }
+    }
+}
+
+// Subject and text, "[Plant 1] pump 1 fault active, severity 700"
+fn format(notification: &Notification, site: &str, suppressed: u32) -> (String, String) {
+    let subject = match notification.event {
+        WebhookEvent::Enocean => format!("[{}] {}", site, notification.message),
+        _ => format!("[{}] {}, severity {}", site, notification.message, notification.severity),
+    };
+    let mut text = format!("{} at {}", subject, iso_time(notification.ts_ms));
+    if suppressed > 0 {
+        text += &format!("\n{} earlier notification(s) weren't sent, too many in the last hour", suppressed);
+    }
+    (subject, text)
+}
+
+struct Transports {
+    mailer: Option<Mailer>,
+    telegram: Option<Telegram>,
+}
+
+impl Transports {
+    fn send(&self, channel: &Channel, subject: &str, text: &str) -> Result<(), String> {
+        match channel {
+            Channel::Email(to) => self.mailer.as_ref().expect("checked in run").send(to, subject, text),
+            Channel::Telegram(chat) => self.telegram.as_ref().expect("checked in run").send(chat, text),
+        }
+    }
+}
+
+struct Telegram {
+    url: String,
+    agent: ureq::Agent,
+}
+
+impl Telegram {
+    fn new(config: &TelegramConfig) -> Result<Self, String> {
+        if config.bot_token.is_empty() {
+            return Err("Telegram recipients need [notify.telegram] bot_token".to_owned());
+        }
+        let url = format!("https://api.telegram.org/bot{}/sendMessage", config.bot_token);
+        Ok(Self { url, agent: ureq::AgentBuilder::new().timeout(TIMEOUT).build() })
+    }
+
+    fn send(&self, chat: &str, text: &str) -> Result<(), String> {
+        let body = json!({ "chat_id": chat, "text": text, "disable_web_page_preview": true });
+        self.agent.post(&self.url)
+            .set("Content-Type", "application/json")
+            .send_string(&body.to_string())
+            .map(|_| ())
+            // the error's URL has the bot token in it, keep that out of the log
+            .map_err(|e| match e {
+                ureq::Error::Status(status, response) => format!("status {}: {}", status, response.into_string().unwrap_or_default()),
+                ureq::Error::Transport(e) => e.kind().to_string(),
+            })
+    }
+}
+
+#[cfg(feature = "email")]
+struct Mailer {
+    transport: lettre::SmtpTransport,
+    from: lettre::message::Mailbox,
+}
+
+#[cfg(feature = "email")]
+impl Mailer {
+    fn new(config: &crate::config::SmtpConfig) -> Result<Self, String> {
+        use crate::config::SmtpSecurity;
+        use lettre::transport::smtp::authentication::Credentials;
+        use lettre::SmtpTransport;
+
+        if config.server.is_empty() || config.from.is_empty() {
+            return Err("Email recipients need [notify.smtp] server and from".to_owned());
+        }
+        let builder = match config.security {
+            SmtpSecurity::Starttls => SmtpTransport::starttls_relay(&config.server),
+            SmtpSecurity::Tls => SmtpTransport::relay(&config.server),
+            SmtpSecurity::None => Ok(SmtpTransport::builder_dangerous(&config.server)),
+        };
+        let mut builder = builder.map_err(|e| format!("Bad SMTP server {}: {}", config.server, e))?.port(config.port).timeout(Some(TIMEOUT));
+        if !config.username.is_empty() {
+            builder = builder.credentials(Credentials::new(config.username.clone(), config.password.clone()));
+        }
+        let from = config.from.parse().map_err(|e| format!("Bad [notify.smtp] from address {}: {}", config.from, e))?;
+        Ok(Self { transport: builder.build(), from })
+    }
+
+    fn check_address(&self, address: &str) -> Result<(), String> {
+        address.parse::<lettre::message::Mailbox>().map(|_| ()).map_err(|e| format!("Bad email address {}: {}", address, e))
+    }
+
+    fn send(&self, to: &str, subject: &str, text: &str) -> Result<(), String> {
+        use lettre::Transport;
+
+        let message = lettre::Message::builder()
+            .from(self.from.clone())
+            .to(to.parse().map_err(|e| format!("Bad email address {}: {}", to, e))?)
+            .subject(subject)
+            .header(lettre::message::header::ContentType::TEXT_PLAIN)
+            .body(text.to_owned())
+            .map_err(|e| e.to_string())?;
+        self.transport.send(&message).map(|_| ()).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(not(feature = "email"))]
+struct Mailer;
+
+#[cfg(not(feature = "email"))]
+impl Mailer {
+    fn new(_config: &crate::config::SmtpConfig) -> Result<Self, String> {
+        Err("Email recipients need the gateway's `email` cargo feature".to_owned())
+    }
+
+    fn check_address(&self, _address: &str) -> Result<(), String> {
+        unreachable!()
+    }
+
+    fn send(&self, _to: &str, _subject: &str, _text: &str) -> Result<(), String> {
+        unreachable!()
+    }
+}
+
+fn outboxes(recipient: Recipient, table: &Subscriber, transports: &Transports) -> Result<Vec<Outbox>, String> {
+    let tags = alarm_tags(table, &recipient.tags, &format!("recipient '{}'", recipient.name))?;
+    let mut channels = Vec::new();
+    if let Some(mailer) = &transports.mailer && !recipient.email.is_empty() {
+        mailer.check_address(&recipient.email)?;
+        channels.push(Channel::Email(recipient.email.clone()));
+    }
+    if !recipient.telegram_chat.is_empty() {
+        channels.push(Channel::Telegram(recipient.telegram_chat.clone()));
+    }
+    if channels.is_empty() {
+        return Err(format!("Recipient '{}' has neither an email nor a telegram_chat", recipient.name));
+    }
+    Ok(channels.into_iter()
+        .map(|channel| Outbox {
+            recipient: recipient.name.clone(),
+            channel,
+            events: recipient.events.clone(),
+            tags: tags.clone(),
+            min_severity: recipient.min_severity,
+            sent: VecDeque::new(),
+            suppressed: 0,
+            pending: VecDeque::new(),
+        })
+        .collect())
+}
+
+// The transports `config`'s recipients need and their outboxes
+fn setup(config: &NotifyConfig, table: &Subscriber) -> Result<(Transports, Vec<Outbox>), String> {
+    let email = config.recipients.iter().any(|recipient| !recipient.email.is_empty());
+    let telegram = config.recipients.iter().any(|recipient| !recipient.telegram_chat.is_empty());
+    let transports = Transports {
+        mailer: email.then(|| Mailer::new(&config.smtp)).transpose()?,
+        telegram: telegram.then(|| Telegram::new(&config.telegram)).transpose()?,
+    };
+    let outboxes = config.recipients.iter()
+        .map(|recipient| outboxes(recipient.clone(), table, &transports))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .flatten()
+        .collect();
+    Ok((transports, outboxes))
+}
+
+/// Notifies until `stop` is set, Err if a recipient doesn't fit the tag table or their channel isn't set up
+pub fn run(mut config: NotifyConfig, table: Subscriber, stop: &AtomicBool) -> Result<(), String> {
+    let (mut transports, mut outboxes) = setup(&config, &table)?;
+    log::info!("[Notify] {} recipient channel(s), at most {} messages an hour each", outboxes.len(), config.max_per_hour);
+
+    let mut reloads = RELOADS.load(Ordering::Relaxed);
+    while !stop.load(Ordering::Relaxed) {
+        if RELOADS.load(Ordering::Relaxed) != reloads {
+            reloads = RELOADS.load(Ordering::Relaxed); // a reload asked for meanwhile reads the same file
+            let reloaded = GatewayConfig::load(crate::config_path())
+                .and_then(|cfg| setup(&cfg.notify, &table).map(|setup| (cfg.notify, setup)));
+            match reloaded {
+                Ok((new, (new_transports, mut new_outboxes))) => {
+                    for outbox in &mut new_outboxes {
+                        if let Some(old) = outboxes.iter_mut().find(|old| old.recipient == outbox.recipient && old.channel == outbox.channel) {
+                            outbox.sent = mem::take(&mut old.sent);
+                            outbox.suppressed = old.suppressed;
+                            outbox.pending = mem::take(&mut old.pending);
+                        }
+                    }
+                    log::info!("[Notify] Reloaded, {} recipient channel(s), at most {} messages an hour each", new_outboxes.len(), new.max_per_hour);
+                    (config, transports, outboxes) = (new, new_transports, new_outboxes);
+                }
+                Err(e) => log::error!("[Notify] Not reloaded, the recipients stay as they were: {}", e),
+            }
+        }
+        while let Some(event) = table.pop_event() {
+            if let Some(notification) = Notification::from_event(&table, &event) {
+                for outbox in &mut outboxes {
+                    outbox.offer(&notification, &config.site, config.max_per_hour);
+                }
+            }
+        }
+        for outbox in &mut outboxes {
+            outbox.catch_up(&config.site, config.max_per_hour);
+            outbox.deliver(&transports);
+        }
+        std::thread::sleep(Duration::from_millis(config.poll_ms));
+    }
+    Ok(())
+}
@@ -0,0 +1,324 @@
+// Redis tag mirror ([redis] in gipop.toml) for web backends that already read Redis. Each mirrored tag is a field of
+// its folder's hash, <prefix>:tags:<folder> ("gipop:tags:Area 1/Lights", top level tags in "gipop:tags"), holding
+// {"value":true,"quality":"Good","ts":...}. Each change is also published on the channel named like the hash, as
+// {"tag":"hall light","value":true,"quality":"Good","ts":...}.
+//
+// On every (re)connect the hashes are rewritten in one MULTI/EXEC, so readers never see half a mirror or fields of
+// tags the PLC no longer has. Nothing is queued while Redis can't be reached, the rewrite on reconnect brings it up to
+// date. Plain TCP, no TLS. Blocking, run it on its own thread.
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use gipop_shm::{Quality, Subscriber, TagSample, TagValue};
+use serde_json::json;
+
+use crate::commands::json_value;
+use crate::config::RedisConfig;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+const PING_INTERVAL: Duration = Duration::from_secs(10); // while nothing changes, so a restarted Redis is noticed and refilled
+const MAX_BULK: i64 = 512 << 20; // Redis' own limit on a bulk string (proto-max-bulk-len)
+const MAX_DEPTH: usize = 8; // arrays in arrays, EXEC's replies are one down
+
+// Where redis://[user:password@]host[:port][/db] points
+struct Target {
+    address: String,
+    username: Option<String>,
+    password: Option<String>,
+    db: u32,
+}
+
+/// Mirrors until `stop`, Err if the configuration doesn't fit the tag table
+pub fn run(config: RedisConfig, table: Subscriber, stop: &AtomicBool) -> Result<(), String> {
+    let target = parse_url(&config.url)?;
+    let tags: Vec<usize> = if config.tags.is_empty() {
+        (0..table.tags().len()).collect()
+    } else {
+        config.tags.iter()
+            .map(|name| table.index_of(name).ok_or_else(|| format!("No tag named '{}' in [redis] tags", name)))
+            .collect::<Result<_, _>>()?
+    };
+    let keys: Vec<String> = tags.iter().map(|&idx| hash_key(&config.prefix, &table.tags()[idx].folder)).collect();
+    log::info!("[Redis] Mirroring {} tag(s) to {}", tags.len(), target.address);
+
+    let mut last: Vec<Option<(TagValue, Quality)>> = vec![None; tags.len()];
+    let mut connection: Option<Connection> = None;
+    let mut retry_at = Instant::now();
+    let mut last_sent = Instant::now();
+    while !stop.load(Ordering::Relaxed) {
+        if connection.is_none() && Instant::now() >= retry_at {
+            match Connection::open(&target).and_then(|mut redis| redis.rewrite(&table, &tags, &keys).map(|()| redis)) {
+                Ok(redis) => {
+                    log::info!("[Redis] Connected to {}, mirror rewritten", target.address);
+                    connection = Some(redis);
+                    last_sent = Instant::now();
+                }
+                Err(e) => {
+                    log::warn!("[Redis] {}: {}", target.address, e);
+                    retry_at = Instant::now() + RECONNECT_DELAY;
+                }
+            }
+        }
+
+        let mut commands = Vec::new();
+        let mut count = 0;
+        for ((last, &idx), key) in last.iter_mut().zip(&tags).zip(&keys) {
+            let sample = table.read_sample(idx);
+            if *last == Some((sample.value, sample.quality)) {
+                continue;
+            }
+            *last = Some((sample.value, sample.quality));
+            let name = &table.tags()[idx].name;
+            command(&mut commands, &["HSET", key, name, &field(&sample)]);
+            command(&mut commands, &["PUBLISH", key, &change(name, &sample)]);
+            count += 2;
+        }
+        if count == 0 && last_sent.elapsed() >= PING_INTERVAL {
+            command(&mut commands, &["PING"]);
+            count = 1;
+        }
+        if count > 0 && let Some(redis) = &mut connection {
+            if let Err(e) = redis.send(&commands, count) {
+                log::warn!("[Redis] Lost {}: {}", target.address, e);
+                connection = None; // reconnects right away, the rewrite catches up on what this missed
+            }
+            last_sent = Instant::now();
+        }
+        std::thread::sleep(Duration::from_millis(config.poll_ms));
+    }
+    Ok(())
+}
+
+fn parse_url(url: &str) -> Result<Target, String> {
+    let rest = url.strip_prefix("redis://").ok_or_else(|| format!("{} isn't a redis:// URL (rediss:// isn't supported)", url))?;
+    let (credentials, rest) = match rest.rsplit_once('@') {
+        Some((credentials, rest)) => (Some(credentials), rest),
+        None => (None, rest),
+    };
+    let (host, db) = match rest.split_once('/') {
+        Some((host, "")) => (host, 0),
+        Some((host, db)) => (host, db.parse().map_err(|_| format!("Database '{}' in {} isn't a number", db, url))?),
+        None => (rest, 0),
+    };
+    let (username, password) = match credentials.map(|credentials| credentials.split_once(':').unwrap_or(("", credentials))) {
+        Some((user, password)) => ((!user.is_empty()).then(|| user.to_owned()), Some(password.to_owned())),
+        None => (None, None),
+    };
+    let address = if host.contains(':') { host.to_owned() } else { format!("{}:6379", host) };
+    Ok(Target { address, username, password, db })
+}
+
+fn hash_key(prefix: &str, folder: &str) -> String {
+    if folder.is_empty() { format!("{}:tags", prefix) } else { format!("{}:tags:{}", prefix, folder) }
+}
+
+fn field(sample: &TagSample) -> String {
+    json!({ "value": json_value(sample.value), "quality": format!("{:?}", sample.quality), "ts": sample.ts_ms }).to_string()
+}
+
+fn change(name: &str, sample: &TagSample) -> String {
+    json!({ "tag": name, "value": json_value(sample.value), "quality": format!("{:?}", sample.quality), "ts": sample.ts_ms }).to_string()
+}
+
+// Appends `args` as a RESP command
+fn command(out: &mut Vec<u8>, args: &[&str]) {
+    out.extend_from_slice(format!("*{}\r\n", args.len()).as_bytes());
+    for arg in args {
+        out.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        out.extend_from_slice(arg.as_bytes());
+        out.extend_from_slice(b"\r\n");
+    }
+}
+
+struct Connection {
+    stream: TcpStream,
+    replies: BufReader<TcpStream>,
+}
+
+impl Connection {
+    fn open(target: &Target) -> Result<Self, String> {
+        let address = target.address.to_socket_addrs().map_err(|e| e.to_string())?
+            .next()
+            .ok_or_else(|| "doesn't resolve".to_owned())?;
+        let stream = TcpStream::connect_timeout(&address, IO_TIMEOUT).map_err(|e| e.to_string())?;
+        stream.set_read_timeout(Some(IO_TIMEOUT)).and_then(|()| stream.set_write_timeout(Some(IO_TIMEOUT))).map_err(|e| e.to_string())?;
+        let replies = BufReader::new(stream.try_clone().map_err(|e| e.to_string())?);
+        let mut redis = Self { stream, replies };
+
+        let mut commands = Vec::new();
+        let mut count = 0;
+        if let Some(password) = &target.password {
+            match &target.username {
+                Some(username) => command(&mut commands, &["AUTH", username, password]),
+                None => command(&mut commands, &["AUTH", password]),
+            }
+            count += 1;
+        }
+        if target.db != 0 {
+            command(&mut commands, &["SELECT", &target.db.to_string()]);
+            count += 1;
+        }
+        if count > 0 {
+            redis.send(&commands, count)?;
+        }
+        Ok(redis)
+    }
+
+    // Replaces the hashes with the current values in one transaction
+    fn rewrite(&mut self, table: &Subscriber, tags: &[usize], keys: &[String]) -> Result<(), String> {
+        let mut commands = Vec::new();
+        command(&mut commands, &["MULTI"]);
+        let mut distinct: Vec<&String> = keys.iter().collect();
+        distinct.sort();
+        distinct.dedup();
+        for key in &distinct {
+            command(&mut commands, &["DEL", key]);
+        }
+        for (&idx, key) in tags.iter().zip(keys) {
+            command(&mut commands, &["HSET", key, &table.tags()[idx].name, &field(&table.read_sample(idx))]);
+        }
+        command(&mut commands, &["EXEC"]);
+        self.send(&commands, distinct.len() + tags.len() + 2)
+    }
+
+    // Writes pipelined `commands` and reads their `count` replies, Err with the first error Redis answered
+    fn send(&mut self, commands: &[u8], count: usize) -> Result<(), String> {
+        self.stream.write_all(commands).map_err(|e| e.to_string())?;
+        let mut error = None;
+        for _ in 0..count {
+            let reply = read_reply(&mut self.replies, 0)?;
+            error = error.or(reply);
+        }
+        error.map_or(Ok(()), Err)
+    }
+}
+
+// One reply, Some with the error text if it is (or, for arrays like EXEC's, holds) an error. `depth`: how many arrays
+// it's in.
+fn read_reply(replies: &mut impl BufRead, depth: usize) -> Result<Option<String>, String> {
+    let mut line = String::new();
+    if replies.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+        return Err("connection closed".to_owned());
+    }
+    let line = line.trim_end();
+    let bad = || format!("can't make sense of reply {:?}", line);
+    match line.as_bytes().first() {
+        Some(b'+' | b':') => Ok(None),
+        Some(b'-') => Ok(Some(line[1..].to_owned())),
+        Some(b'$') => match line[1..].parse::<i64>().map_err(|_| bad())? {
+            -1 => Ok(None), // nil
+            len @ 0..=MAX_BULK => {
+                // skipped rather than read into memory, nothing we send gets more than OK back
+                let expected = len as u64 + 2;
+                let skipped = std::io::copy(&mut (&mut *replies).take(expected), &mut std::io::sink()).map_err(|e| e.to_string())?;
+                if skipped < expected {
+                    return Err("connection closed".to_owned());
+                }
+                Ok(None)
+            }
+            _ => Err(bad()),
+        },
+        Some(b'*') => match line[1..].parse::<i64>().map_err(|_| bad())? {
+            -1 | 0 => Ok(None), // nil, empty
+            len if len > 0 && depth < MAX_DEPTH => {
+                let mut error = None;
+                for _ in 0..len {
+                    let reply = read_reply(replies, depth + 1)?;
+                    error = error.or(reply);
+                }
+                Ok(error)
+            }
+            _ => Err(bad()),
+        },
+        _ => Err(bad()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The replies in `input`, read a couple of bytes at a time, up to the first one that doesn't parse
+    fn replies(input: &[u8]) -> Vec<Result<Option<String>, String>> {
+        let mut read = BufReader::with_capacity(2, input);
+        let mut replies = Vec::new();
+        while !read.fill_buf().unwrap().is_empty() {
+            let reply = read_reply(&mut read, 0);
+            let failed = reply.is_err();
+            replies.push(reply);
+            if failed {
+                break;
+            }
+        }
+        replies
+    }
+
+    #[test]
+    fn replies_split_across_reads() {
+        let input = concat!(
+            "+OK\r\n",
+            ":42\r\n",
+            "$5\r\nhello\r\n",
+            "$0\r\n\r\n",
+            "$-1\r\n",
+            "$4\r\na\r\nb\r\n", // line ends in a bulk string are data
+            "-WRONGPASS invalid username-password pair\r\n",
+            // EXEC: queued commands' replies, one failed
+            "*3\r\n:1\r\n-WRONGTYPE Operation against a key holding the wrong kind of value\r\n$2\r\nok\r\n",
+            "*2\r\n*1\r\n+OK\r\n*0\r\n",
+            "*-1\r\n", // EXEC after a WATCH failed
+            "+PONG\r\n",
+        );
+        let expected = [
+            Ok(None),
+            Ok(None),
+            Ok(None),
+            Ok(None),
+            Ok(None),
+            Ok(None),
+            Ok(Some("WRONGPASS invalid username-password pair".to_owned())),
+            Ok(Some("WRONGTYPE Operation against a key holding the wrong kind of value".to_owned())),
+            Ok(None),
+            Ok(None),
+            Ok(None),
+        ];
+        assert_eq!(replies(input.as_bytes()), expected);
+    }
+
+    #[test]
+    fn bad_lengths_are_errors() {
+        let cases = [
+            "$-2\r\n",
+            "$-9223372036854775808\r\n",
+            "$536870913\r\n",
+            "$9223372036854775807\r\n",
+            "$99999999999999999999\r\n",
+            "$5x\r\nhello\r\n",
+            "$\r\n",
+            "*-2\r\n",
+            "*1x\r\n+OK\r\n",
+            "*99999999999999999999\r\n",
+            "?what\r\n",
+            "\r\n",
+            // the connection ends before the reply does
+            "$10\r\nhello",
+            "*2\r\n+OK\r\n",
+            "*3\r\n*1\r\n",
+        ];
+        for input in cases {
+            let replies = replies(input.as_bytes());
+            assert!(matches!(replies[..], [Err(_)]), "{:?}: {:?}", input, replies);
+        }
+
+        // nested deeper than any reply we ask for
+        let nested = "*1\r\n".repeat(MAX_DEPTH) + "+OK\r\n";
+        assert_eq!(replies(nested.as_bytes()), [Ok(None)]);
+        let nested = "*1\r\n".repeat(MAX_DEPTH + 1) + "+OK\r\n";
+        assert!(replies(nested.as_bytes())[0].is_err());
+        let nested = "*1\r\n".repeat(1_000_000);
+        assert!(replies(nested.as_bytes())[0].is_err());
+    }
+}
@@ -0,0 +1,333 @@
+// Sparkplug B edge node (`sparkplug` feature, [sparkplug] in gipop.toml), so Ignition and other Sparkplug hosts find
+// the PLC's tags without configuring each one. Gipop is edge node `edge_node_id` in `group_id`: node_tags are the
+// node's own metrics, every [[sparkplug.devices]] entry is a device under it. Metrics are named by folder path and tag
+// name ("Environment/temperature"), the alias is the tag's index + 1 (0 is Node Control/Rebirth).
+//
+// Session, per Sparkplug 3.0:
+// - connect with NDEATH as the will, carrying bdSeq, which goes up by one with every connect
+// - with primary_host set, wait for its STATE (spBv1.0/STATE/<host>, {"online":true,...}) before publishing anything,
+//   and publish NDEATH and disconnect once it goes offline
+// - NBIRTH with every node metric by name, alias and type, then a DBIRTH per device
+// - then NDATA/DDATA every poll_ms with what changed, by alias only
+// - seq counts 0..255 across all of those, NBIRTH is 0
+// - NCMD Node Control/Rebirth = true repeats the births
+//
+// NCMD/DCMD metrics on writable tags (by name or alias) are written to the PLC as [sparkplug] user, who needs the
+// operator role in [users]. Only values are carried, not tag quality.
+use std::time::Duration;
+
+use gipop_shm::{Action, Subscriber, TagSample, TagValue, Users};
+use prost::Message;
+use rumqttc::{AsyncClient, Event, LastWill, Outgoing, Packet, QoS};
+
+use crate::commands::{self, to_tag};
+use crate::config::SparkplugConfig;
+use crate::mqtt::broker_options;
+
+#[allow(clippy::all)] // generated
+mod pb {
+    include!(concat!(env!("OUT_DIR"), "/org.eclipse.tahu.protobuf.rs"));
+}
+use pb::Payload;
+use pb::payload::Metric;
+use pb::payload::metric::Value;
+
+const NAMESPACE: &str = "spBv1.0";
+const BD_SEQ: &str = "bdSeq";
+const REBIRTH: &str = "Node Control/Rebirth";
+const REBIRTH_ALIAS: u64 = 0;
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+const REQUEST_BACKLOG: usize = 64;
+
+// Sparkplug DataType
+const INT8: u32 = 1;
+const INT16: u32 = 2;
+const INT32: u32 = 3;
+const INT64: u32 = 4;
+const UINT32: u32 = 7;
+const UINT64: u32 = 8;
+const FLOAT: u32 = 9;
+const DOUBLE: u32 = 10;
+const BOOLEAN: u32 = 11;
+
+/// The edge node itself (device None) or one of its devices, with its metrics as (tag index, metric name)
+struct Group {
+    device: Option<String>,
+    tags: Vec<(usize, String)>,
+}
+
+struct EdgeNode {
+    config: SparkplugConfig,
+    table: Subscriber,
+    groups: Vec<Group>,   // the node first, births have to go out in that order
+    user: Option<String>, // None when commands aren't taken
+}
+
+/// Runs the edge node until the process ends, Err only for a config it can't start with
+pub async fn run(config: SparkplugConfig, table: Subscriber, users: Users) -> Result<(), String> {
+    let ids = [&config.group_id, &config.edge_node_id].into_iter().chain(config.devices.iter().map(|device| &device.id));
+    for id in ids.chain((!config.primary_host.is_empty()).then_some(&config.primary_host)) {
+        if id.is_empty() || id.contains(['/', '+', '#']) {
+            return Err(format!("'{}' can't be a Sparkplug id, it's empty or has one of / + #", id));
+        }
+    }
+
+    let mut groups = vec![group(None, &config.node_tags, &table)];
+    groups.extend(config.devices.iter().map(|device| group(Some(device.id.clone()), &device.tags, &table)));
+    let user = match users.authorize(config.user.as_deref(), Action::WriteTag) {
+        Ok(_) => config.user.clone(),
+        Err(e) => {
+            log::warn!("[Sparkplug] Not taking commands, {}", e);
+            None
+        }
+    };
+    let node = EdgeNode { config, table, groups, user };
+    log::info!("[Sparkplug] Edge node {}/{} with {} devices via {}", node.config.group_id, node.config.edge_node_id, node.groups.len() - 1, node.config.broker);
+
+    let mut bd_seq: u64 = 0;
+    loop {
+        let ended = node.session(bd_seq).await?;
+        log::warn!("[Sparkplug] {}, reconnecting in {:?}", ended, RECONNECT_DELAY);
+        bd_seq = (bd_seq + 1) % 256;
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+// Skips tags the PLC doesn't have
+fn group(device: Option<String>, names: &[String], table: &Subscriber) -> Group {
+    let tags = names.iter()
+        .filter_map(|name| match table.index_of(name) {
+            Some(idx) => {
+                let tag = &table.tags()[idx];
+                let metric = tag.folder_path().chain([tag.name.as_str()]).collect::<Vec<_>>().join("/");
+                Some((idx, metric))
+            }
+            None => {
+                log::error!("[Sparkplug] No tag '{}' in the PLC's directory, leaving it out", name);
+                None
+            }
+        })
+        .collect();
+    Group { device, tags }
+}
+
+fn alias(idx: usize) -> u64 {
+    idx as u64 + 1
+}
+
+impl EdgeNode {
+    fn topic(&self, kind: &str, device: Option<&str>) -> String {
+        let topic = format!("{}/{}/{}/{}", NAMESPACE, self.config.group_id, kind, self.config.edge_node_id);
+        match device {
+            Some(device) => format!("{}/{}", topic, device),
+            None => topic,
+        }
+    }
+
+    // One MQTT session, from connect to whatever ended it. Err for a broker URL that won't ever work
+    async fn session(&self, bd_seq: u64) -> Result<String, String> {
+        let death = Payload { timestamp: Some(gipop_shm::tags::now_ms()), metrics: vec![bd_seq_metric(bd_seq)], ..Default::default() };
+        let config = &self.config;
+        let mut options = broker_options(&config.broker, &config.client_id, config.username.as_deref(), &config.password)?;
+        options.set_clean_session(true);
+        options.set_last_will(LastWill::new(self.topic("NDEATH", None), death.encode_to_vec(), QoS::AtLeastOnce, false));
+        let (client, mut event_loop) = AsyncClient::new(options, REQUEST_BACKLOG);
+
+        let ncmd = self.topic("NCMD", None);
+        let dcmd = format!("{}/", self.topic("DCMD", None));
+        let state = format!("{}/STATE/{}", NAMESPACE, config.primary_host);
+        let mut host_online = config.primary_host.is_empty();
+        let mut born = false;
+        let mut seq: u8 = 0;
+        let mut published: Vec<Vec<Option<TagValue>>> = self.groups.iter().map(|group| vec![None; group.tags.len()]).collect();
+        let mut interval = tokio::time::interval(Duration::from_millis(config.poll_ms.max(1)));
+
+        loop {
+            tokio::select! {
+                event = event_loop.poll() => match event {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        log::info!("[Sparkplug] Connected to {}, bdSeq {}", config.broker, bd_seq);
+                        let mut topics = vec![ncmd.clone(), format!("{}+", dcmd)];
+                        if !config.primary_host.is_empty() {
+                            topics.push(state.clone());
+                        }
+                        for topic in topics {
+                            if let Err(e) = client.try_subscribe(topic.as_str(), QoS::AtLeastOnce) {
+                                log::error!("[Sparkplug] Subscribing to {} failed: {}", topic, e);
+                            }
+                        }
+                        if host_online {
+                            self.birth(&client, bd_seq, &mut seq, &mut published);
+                            born = true;
+                        }
+                    }
+                    Ok(Event::Incoming(Packet::Publish(message))) if message.topic == state => match host_state(&message.payload) {
+                        Some(true) if !host_online => {
+                            log::info!("[Sparkplug] Primary host {} is online", config.primary_host);
+                            host_online = true;
+                            self.birth(&client, bd_seq, &mut seq, &mut published);
+                            born = true;
+                        }
+                        Some(false) if host_online => {
+                            _ = client.try_publish(self.topic("NDEATH", None), QoS::AtLeastOnce, false, death.encode_to_vec());
+                            _ = client.try_disconnect();
+                            // the death certificate and the disconnect only go out while the event loop is polled
+                            _ = tokio::time::timeout(DISCONNECT_TIMEOUT, async {
+                                while let Ok(event) = event_loop.poll().await {
+                                    if matches!(event, Event::Outgoing(Outgoing::Disconnect)) {
+                                        break;
+                                    }
+                                }
+                            }).await;
+                            return Ok(format!("Primary host {} went offline", config.primary_host));
+                        }
+                        Some(_) => {}
+                        None => log::warn!("[Sparkplug] Ignoring malformed STATE from {}", config.primary_host),
+                    },
+                    Ok(Event::Incoming(Packet::Publish(message))) => {
+                        let device = if message.topic == ncmd { None } else { message.topic.strip_prefix(&dcmd) };
+                        match Payload::decode(&message.payload[..]) {
+                            Ok(payload) if born && self.command(&payload, device) => {
+                                log::info!("[Sparkplug] Rebirth requested");
+                                self.birth(&client, bd_seq, &mut seq, &mut published);
+                            }
+                            Ok(_) => {}
+                            Err(e) => log::warn!("[Sparkplug] Ignoring malformed command on {}: {}", message.topic, e),
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => return Ok(format!("{}: {}", config.broker, e)), // a new connection needs a new bdSeq
+                },
+                _ = interval.tick(), if born => self.data(&client, &mut seq, &mut published),
+            }
+        }
+    }
+
+    // NBIRTH then DBIRTHs, every metric with its current value
+    fn birth(&self, client: &AsyncClient, bd_seq: u64, seq: &mut u8, published: &mut [Vec<Option<TagValue>>]) {
+        *seq = 0;
+        for (group, published) in self.groups.iter().zip(published) {
+            let mut metrics = Vec::new();
+            if group.device.is_none() {
+                metrics.push(bd_seq_metric(bd_seq));
+                metrics.push(Metric {
+                    name: Some(REBIRTH.to_owned()),
+                    alias: Some(REBIRTH_ALIAS),
+                    datatype: Some(BOOLEAN),
+                    value: Some(Value::BooleanValue(false)),
+                    ..Default::default()
+                });
+            }
+            for ((idx, name), published) in group.tags.iter().zip(published.iter_mut()) {
+                let sample = self.table.read_sample(*idx);
+                *published = Some(sample.value);
+                metrics.push(Metric { name: Some(name.clone()), ..metric(*idx, &sample) });
+            }
+            let kind = if group.device.is_none() { "NBIRTH" } else { "DBIRTH" };
+            self.send(client, kind, group.device.as_deref(), seq, metrics);
+        }
+    }
+
+    // NDATA/DDATA with whatever changed since the last birth or data message
+    fn data(&self, client: &AsyncClient, seq: &mut u8, published: &mut [Vec<Option<TagValue>>]) {
+        for (group, published) in self.groups.iter().zip(published) {
+            let mut metrics = Vec::new();
+            for ((idx, _), published) in group.tags.iter().zip(published.iter_mut()) {
+                let sample = self.table.read_sample(*idx);
+                if *published != Some(sample.value) {
+                    *published = Some(sample.value);
+                    metrics.push(metric(*idx, &sample));
+                }
+            }
+            if !metrics.is_empty() {
+                let kind = if group.device.is_none() { "NDATA" } else { "DDATA" };
+                self.send(client, kind, group.device.as_deref(), seq, metrics);
+            }
+        }
+    }
+
+    fn send(&self, client: &AsyncClient, kind: &str, device: Option<&str>, seq: &mut u8, metrics: Vec<Metric>) {
+        let payload = Payload { timestamp: Some(gipop_shm::tags::now_ms()), metrics, seq: Some(*seq as u64), ..Default::default() };
+        *seq = seq.wrapping_add(1);
+        let topic = self.topic(kind, device);
+        if let Err(e) = client.try_publish(topic.as_str(), QoS::AtMostOnce, false, payload.encode_to_vec()) {
+            log::warn!("[Sparkplug] {} not sent: {}", topic, e);
+        }
+    }
+
+    // Writes the NCMD (device None) or DCMD metrics to their tags, true if the host asked for a rebirth
+    fn command(&self, payload: &Payload, device: Option<&str>) -> bool {
+        let Some(group) = self.groups.iter().find(|group| group.device.as_deref() == device) else {
+            log::warn!("[Sparkplug] Ignoring command for unknown device {:?}", device);
+            return false;
+        };
+        let mut rebirth = false;
+        for metric in &payload.metrics {
+            if device.is_none() && (metric.name.as_deref() == Some(REBIRTH) || metric.alias == Some(REBIRTH_ALIAS)) {
+                rebirth |= metric.value == Some(Value::BooleanValue(true));
+                continue;
+            }
+            let Some((idx, name)) = group.tags.iter().find(|(idx, name)| metric.alias == Some(alias(*idx)) || metric.name.as_deref() == Some(name)) else {
+                log::warn!("[Sparkplug] Ignoring command for unknown metric {:?}/{:?}", metric.name, metric.alias);
+                continue;
+            };
+            let tag = &self.table.tags()[*idx];
+            let Some(user) = &self.user else {
+                log::warn!("[Sparkplug] Ignoring command for {}, not taking commands", name);
+                continue;
+            };
+            if !tag.writable() {
+                log::warn!("[Sparkplug] Ignoring command for {}, '{}' is read only", name, tag.name);
+                continue;
+            }
+            match number(metric).and_then(|number| to_tag(tag.ty, number)).filter(|value| tag.accepts(*value)) {
+                Some(value) => _ = commands::write_tag(&self.table, "Sparkplug", user, *idx, value),
+                None => log::warn!("[Sparkplug] Ignoring command for {}, {:?} isn't a value for it {:?}", name, metric.value, tag.write_range()),
+            }
+        }
+        rebirth
+    }
+}
+
+fn bd_seq_metric(bd_seq: u64) -> Metric {
+    Metric { name: Some(BD_SEQ.to_owned()), datatype: Some(UINT64), value: Some(Value::LongValue(bd_seq)), ..Default::default() }
+}
+
+// By alias, births add the name
+fn metric(idx: usize, sample: &TagSample) -> Metric {
+    let (datatype, value) = match sample.value {
+        TagValue::Bool(b) => (BOOLEAN, Value::BooleanValue(b)),
+        TagValue::UInt32(n) => (UINT32, Value::IntValue(n)),
+        TagValue::Int32(n) => (INT32, Value::IntValue(n as u32)),
+        TagValue::Float32(f) => (FLOAT, Value::FloatValue(f)),
+        TagValue::Float64(f) => (DOUBLE, Value::DoubleValue(f)),
+    };
+    Metric {
+        alias: Some(alias(idx)),
+        timestamp: Some(sample.ts_ms),
+        datatype: Some(datatype),
+        value: Some(value),
+        ..Default::default()
+    }
+}
+
+// A command metric's value as a number, signed types are two's complement in the unsigned fields
+fn number(metric: &Metric) -> Option<f64> {
+    let signed = matches!(metric.datatype, Some(INT8 | INT16 | INT32 | INT64));
+    Some(match metric.value.as_ref()? {
+        Value::IntValue(n) if signed => *n as i32 as f64,
+        Value::IntValue(n) => *n as f64,
+        Value::LongValue(n) if signed => *n as i64 as f64,
+        Value::LongValue(n) => *n as f64,
+        Value::FloatValue(f) => *f as f64,
+        Value::DoubleValue(f) => *f,
+        Value::BooleanValue(b) => *b as u8 as f64,
+        Value::StringValue(_) => return None,
+    })
+}
+
+// `online` of a Sparkplug 3.0 STATE message, None if it isn't one
+fn host_state(payload: &[u8]) -> Option<bool> {
+    serde_json::from_slice::<serde_json::Value>(payload).ok()?.get("online")?.as_bool()
+}
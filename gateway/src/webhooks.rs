@@ -0,0 +1,252 @@
+// Webhooks ([webhooks] in gipop.toml): a POST to each hook's URL when one of the PLC's events it picked comes in, an
+// alarm going active, clearing or being acknowledged, or an EnOcean telegram. The body is the hook's template with
+// the event filled in, or the default JSON below without one.
+//
+// Failed deliveries are retried after backoff_ms, then twice as long each time, up to `retries` times. A hook's
+// deliveries go out in order, a failing one holds back the ones behind it so the receiver never sees a clear before
+// its alarm. A 4xx other than 429 isn't retried, the request itself is wrong and won't get better. Nothing is kept
+// across restarts. Blocking, run it on its own thread with a Subscriber of its own: every Subscriber follows the
+// event ring with its own cursor and the HTTP API drains the gateway's.
+//
+// The hooks change with a config reload (config::RELOADS), what's pending carries over for URLs that stay. A gateway
+// started without hooks takes a restart to get some.
+use std::collections::{HashSet, VecDeque};
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use gipop_shm::ring::{ITEM_ALARM, ITEM_ALARM_ACK, ITEM_ENOCEAN_TELEGRAM};
+use gipop_shm::tags::{iso_time, now_ms};
+use gipop_shm::{RingItem, Subscriber};
+use serde_json::json;
+
+use crate::config::{GatewayConfig, Webhook, WebhookEvent, WebhooksConfig, RELOADS};
+
+const MAX_PENDING: usize = 1000; // per hook, the oldest are dropped beyond this while its endpoint is down
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// One of the PLC's events as hooks see it
+#[derive(Debug, Clone)]
+pub struct Notification {
+    pub event: WebhookEvent,
+    pub tag: String,   // the alarm tag, empty for EnOcean telegrams
+    pub severity: u16, // the alarm's, 0 for EnOcean telegrams
+    pub message: String,
+    pub ts_ms: u64, // when the gateway got the event, the ring doesn't carry a time
+}
+
+impl Notification {
+    /// None for events that aren't notified about
+    pub fn from_event(table: &Subscriber, event: &RingItem) -> Option<Self> {
+        let ts_ms = now_ms();
+        let kind = match event.kind {
+            ITEM_ALARM if event.value != 0 => WebhookEvent::Alarm,
+            ITEM_ALARM => WebhookEvent::Clear,
+            ITEM_ALARM_ACK => WebhookEvent::Ack,
+            ITEM_ENOCEAN_TELEGRAM => {
+                let hex: Vec<String> = event.payload().iter().map(|b| format!("{:02X}", b)).collect();
+                let message = format!("EnOcean telegram {}", hex.join(" "));
+                return Some(Self { event: WebhookEvent::Enocean, tag: String::new(), severity: 0, message, ts_ms });
+            }
+            _ => return None,
+        };
+        let tag = table.tags().get(event.tag as usize).filter(|tag| tag.is_alarm())?;
+        let what = match kind {
+            WebhookEvent::Alarm => "active",
+            WebhookEvent::Clear => "cleared",
+            _ => "acknowledged",
+        };
+        Some(Self { event: kind, tag: tag.name.clone(), severity: tag.severity, message: format!("{} {}", tag.name, what), ts_ms })
+    }
+
+    /// Whether it's one of `events` and, for alarms, of `tags` (empty takes every alarm) and severe enough
+    pub fn matches(&self, events: &[WebhookEvent], tags: &HashSet<String>, min_severity: u16) -> bool {
+        events.contains(&self.event)
+            && (self.event == WebhookEvent::Enocean
+                || (self.severity >= min_severity && (tags.is_empty() || tags.contains(&self.tag))))
+    }
+
+    /// {"event":"alarm","tag":"pump 1 fault","severity":700,"message":"pump 1 fault active","ts":1700000000000,"time":"2023-11-14T22:13:20.000Z"}
+    pub fn default_body(&self) -> String {
+        json!({
+            "event": event_name(self.event),
+            "tag": self.tag,
+            "severity": self.severity,
+            "message": self.message,
+            "ts": self.ts_ms,
+            "time": iso_time(self.ts_ms),
+        }).to_string()
+    }
+
+    /// `template` with {{event}}, {{tag}}, {{severity}}, {{message}}, {{ts}} and {{time}} filled in. Strings are
+    /// escaped but not quoted, they go inside the template's quotes: "text": "{{message}}".
+    pub fn render(&self, template: &str) -> String {
+        [
+            ("{{event}}", event_name(self.event).to_owned()),
+            ("{{tag}}", json_escape(&self.tag)),
+            ("{{severity}}", self.severity.to_string()),
+            ("{{message}}", json_escape(&self.message)),
+            ("{{ts}}", self.ts_ms.to_string()),
+            ("{{time}}", iso_time(self.ts_ms)),
+        ]
+        .iter()
+        .fold(template.to_owned(), |body, (placeholder, value)| body.replace(placeholder, value))
+    }
+}
+
+pub fn event_name(event: WebhookEvent) -> &'static str {
+    match event {
+        WebhookEvent::Alarm => "alarm",
+        WebhookEvent::Clear => "clear",
+        WebhookEvent::Ack => "ack",
+        WebhookEvent::Enocean => "enocean",
+    }
+}
+
+/// `names` as a set, Err naming `owner` if one isn't an alarm tag
+pub fn alarm_tags(table: &Subscriber, names: &[String], owner: &str) -> Result<HashSet<String>, String> {
+    for name in names {
+        match table.index_of(name).map(|idx| &table.tags()[idx]) {
+            Some(tag) if tag.is_alarm() => {}
+            Some(_) => return Err(format!("Tag '{}' of {} isn't an alarm", name, owner)),
+            None => return Err(format!("No tag named '{}' in {} tags", name, owner)),
+        }
+    }
+    Ok(names.iter().cloned().collect())
+}
+
+// The JSON string `text` without its quotes
+fn json_escape(text: &str) -> String {
+    let quoted = serde_json::to_string(text).unwrap();
+    quoted[1..quoted.len() - 1].to_owned()
+}
+
+struct Hook {
+    config: Webhook,
+    tags: HashSet<String>, // empty takes every alarm
+    agent: ureq::Agent,
+    pending: VecDeque<Delivery>,
+}
+
+struct Delivery {
+    body: String,
+    attempts: u32,
+    due: Instant,
+}
+
+impl Hook {
+    fn new(config: Webhook, table: &Subscriber) -> Result<Self, String> {
+        if config.url.is_empty() {
+            return Err("A [[webhooks.hooks]] entry has no url".to_owned());
+        }
+        let tags = alarm_tags(table, &config.tags, &format!("webhook {}", config.url))?;
+        if !config.template.is_empty() {
+            let sample = Notification {
+                event: WebhookEvent::Alarm,
+                tag: "tag".to_owned(),
+                severity: 500,
+                message: "tag active".to_owned(),
+                ts_ms: now_ms(),
+            };
+            serde_json::from_str::<serde_json::Value>(&sample.render(&config.template))
+                .map_err(|e| format!("The template of webhook {} isn't JSON once filled in: {}", config.url, e))?;
+        }
+        let agent = ureq::AgentBuilder::new().timeout(Duration::from_millis(config.timeout_ms)).build();
+        Ok(Self { config, tags, agent, pending: VecDeque::new() })
+    }
+
+    // Queues the notification if the hook wants it
+    fn offer(&mut self, notification: &Notification) {
+        if !notification.matches(&self.config.events, &self.tags, self.config.min_severity) {
+            return;
+        }
+        if self.pending.len() >= MAX_PENDING {
+            self.pending.pop_front();
+            log::warn!("[Webhooks] More than {} notifications pending for {}, dropping the oldest", MAX_PENDING, self.config.url);
+        }
+        let body = if self.config.template.is_empty() { notification.default_body() } else { notification.render(&self.config.template) };
+        self.pending.push_back(Delivery { body, attempts: 0, due: Instant::now() });
+    }
+
+    // Sends what's due, in order, until one fails
+    fn deliver(&mut self) {
+        while let Some(delivery) = self.pending.front_mut().filter(|delivery| delivery.due <= Instant::now()) {
+            let mut request = self.agent.post(&self.config.url).set("Content-Type", "application/json");
+            for (name, value) in &self.config.headers {
+                request = request.set(name, value);
+            }
+            let retry = match request.send_string(&delivery.body) {
+                Ok(_) => {
+                    log::debug!("[Webhooks] Delivered to {}", self.config.url);
+                    self.pending.pop_front();
+                    continue;
+                }
+                Err(ureq::Error::Status(status, _)) if status < 500 && status != 429 => {
+                    log::error!("[Webhooks] {} refused a notification with status {}, dropping it", self.config.url, status);
+                    false
+                }
+                Err(e) => {
+                    log::warn!("[Webhooks] Failed to deliver to {}: {}", self.config.url, e);
+                    true
+                }
+            };
+            if !retry || delivery.attempts >= self.config.retries {
+                if retry {
+                    log::error!("[Webhooks] Giving up on a notification for {} after {} attempts", self.config.url, delivery.attempts + 1);
+                }
+                self.pending.pop_front();
+                continue;
+            }
+            let backoff = Duration::from_millis(self.config.backoff_ms).saturating_mul(1 << delivery.attempts.min(16));
+            delivery.attempts += 1;
+            delivery.due = Instant::now() + backoff.min(MAX_BACKOFF);
+            break;
+        }
+    }
+}
+
+/// Notifies until `stop` is set, Err if a hook doesn't fit the tag table
+pub fn run(mut config: WebhooksConfig, table: Subscriber, stop: &AtomicBool) -> Result<(), String> {
+    let mut hooks = config.hooks.iter().map(|hook| Hook::new(hook.clone(), &table)).collect::<Result<Vec<_>, _>>()?;
+    log::info!("[Webhooks] {} hook(s)", hooks.len());
+
+    let mut reloads = RELOADS.load(Ordering::Relaxed);
+    while !stop.load(Ordering::Relaxed) {
+        if RELOADS.load(Ordering::Relaxed) != reloads {
+            reloads = RELOADS.load(Ordering::Relaxed); // a reload asked for meanwhile reads the same file
+            let reloaded = GatewayConfig::load(crate::config_path()).and_then(|cfg| {
+                let hooks = cfg.webhooks.hooks.iter().map(|hook| Hook::new(hook.clone(), &table)).collect::<Result<Vec<_>, _>>()?;
+                Ok((cfg.webhooks, hooks))
+            });
+            match reloaded {
+                Ok((new, mut new_hooks)) => {
+                    for hook in &mut new_hooks {
+                        if let Some(old) = hooks.iter_mut().find(|old| old.config.url == hook.config.url) {
+                            hook.pending = mem::take(&mut old.pending);
+                        }
+                    }
+                    log::info!("[Webhooks] Reloaded, {} hook(s)", new_hooks.len());
+                    (config, hooks) = (new, new_hooks);
+                }
+                Err(e) => log::error!("[Webhooks] Not reloaded, the hooks stay as they were: {}", e),
+            }
+        }
+        while let Some(event) = table.pop_event() {
+            if let Some(notification) = Notification::from_event(&table, &event) {
+                log::debug!("[Webhooks] {}", notification.message);
+                for hook in &mut hooks {
+                    hook.offer(&notification);
+                }
+            }
+        }
+        for hook in &mut hooks {
+            hook.deliver();
+        }
+        std::thread::sleep(Duration::from_millis(config.poll_ms));
+    }
+    let lost: usize = hooks.iter().map(|hook| hook.pending.len()).sum();
+    if lost > 0 {
+        log::warn!("[Webhooks] {} notification(s) not delivered", lost);
+    }
+    Ok(())
+}
@@ -169,51 +169,7 @@ pub async fn entry_loop(network_interface: &str) -> Result<(), anyhow::Error> {
 
         }
 
-        {
-            let peek_kl6581 = group.subdevice(&maindevice, 4).expect("No BK1120 found as final subdevice");
-            let peek_input = peek_kl6581.inputs_raw()[8]; // DB3
-            let peek_bits = peek_input.view_bits::<Lsb0>();
-            let subslice = &peek_bits[0..8];
-            let value: u8 = subslice.load::<u8>();
-            
-            if value != 0 {
-                log::info!(
-                    "DB3 bytes direct: {:08b}",
-                    value
-                );
-            }
-        }
-
-        {
-            let rd_guard = &*TERM_KL6581.read().expect("Acquire TERM_KL6581 read guard");
-
-            let reading = rd_guard.read(None).unwrap();
-            let value: BitVec<u8, Lsb0> = reading.pick_smart().unwrap(); // 192 bits = 24 bytes
-            let bits: &BitSlice<u8, Lsb0> = value.as_bitslice();
-
-            let subslice = bits[6*8..56].load::<u8>();
-            if subslice != 0 {
-                log::info!(
-                    "DB3 bytes through Getter: {:08b}",
-                    subslice
-                );
-            }
-        }
-
-        {
-            let peek_el3024 = group.subdevice(&maindevice, 3).expect("No EL3024 found as 3rd subdevice to 1st EK1100");
-            let peek_input = &peek_el3024.inputs_raw()[0..16]; // Ch1 inputs_raw is by bit
-            let peek_bits = peek_input.view_bits::<Lsb0>();
-            let subslice = &peek_bits[0..7];
-
-            // log::info!(
-            //     "\nUnderrange bit: {} \nOverrange: bit: {}\nError bit: {}",
-            //     subslice[0],
-            //     subslice[1],
-            //     subslice[6]
-            // );   
-        }
-
+        // Raw bytes of the images: `gipop image` dumps them from a running PLC, annotated with what every bit is
     }
 
     let group = group.into_safe_op(&maindevice).await.expect("OP -> SAFE-OP");
@@ -0,0 +1,62 @@
+// Fieldbus health bookkeeping, independent of how the numbers get to consumers.
+//
+// CycleStats follows the process data cycle (timing, working counter), the ESC register helpers decode what the
+// PLC reads from each SubDevice.
+use std::time::Duration;
+
+// ESC registers the diagnostics pass reads, see the EtherCAT slave controller datasheets
+pub const REG_AL_STATUS: u16 = 0x0130;
+pub const REG_AL_STATUS_CODE: u16 = 0x0134;
+pub const REG_RX_ERROR_COUNTERS: u16 = 0x0300; // 8 bytes: per port, invalid frame counter then RX error counter
+pub const REG_LOST_LINK_COUNTERS: u16 = 0x0310; // 4 bytes: one per port
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CycleStats {
+    pub cycles: u64,
+    pub wkc_errors: u64,
+    pub last: Duration,
+    pub min: Duration,
+    pub max: Duration,
+    pub avg: Duration, // moving average over roughly the last 100 cycles
+    pub jitter_max: Duration,
+    pub jitter_avg: Duration,
+}
+
+impl CycleStats {
+    /// One process data cycle that took `cycle` and came back with working counter `wkc`
+    pub fn record(&mut self, cycle: Duration, wkc: u16, expected_wkc: u16) {
+        if wkc != expected_wkc {
+            self.wkc_errors += 1;
+        }
+
+        if self.cycles == 0 {
+            (self.min, self.max, self.avg) = (cycle, cycle, cycle);
+        }
+        else {
+            let jitter = cycle.abs_diff(self.last);
+            self.jitter_max = self.jitter_max.max(jitter);
+            self.jitter_avg = moving_average(self.jitter_avg, jitter);
+            self.min = self.min.min(cycle);
+            self.max = self.max.max(cycle);
+            self.avg = moving_average(self.avg, cycle);
+        }
+        self.last = cycle;
+        self.cycles += 1;
+    }
+}
+
+fn moving_average(avg: Duration, sample: Duration) -> Duration {
+    (avg * 99 + sample) / 100
+}
+
+/// Expected working counter of one LRW over the group: outputs count 2 per SubDevice (written), inputs 1 (read)
+pub fn expected_wkc(io_sizes: impl IntoIterator<Item = (usize, usize)>) -> u16 {
+    io_sizes.into_iter()
+        .map(|(inputs, outputs)| 2 * (outputs > 0) as u16 + (inputs > 0) as u16)
+        .sum()
+}
+
+/// Invalid frame + RX error counts per port from REG_RX_ERROR_COUNTERS
+pub fn port_errors(raw: [u8; 8]) -> [u32; 4] {
+    std::array::from_fn(|port| raw[2 * port] as u32 + raw[2 * port + 1] as u32)
+}
@@ -1,6 +1,6 @@
 use crate::term_cfg::*;
-use bitvec::prelude::*;
-use std::sync::{Arc, RwLock, LazyLock};
+use crate::terminals::terminals;
+use std::sync::{Arc, RwLock};
 
 #[derive(Clone)]
 pub struct TermStates {
@@ -10,7 +10,9 @@ pub struct TermStates {
     pub ebus_ai_terms: Vec<Arc<RwLock<AITerm>>>,
 }
 
-// Where all the terminal states are stored dynamically on the heap
+// Where all the terminal states are stored dynamically on the heap. Filled in while the bus comes up, then shared as it
+// is (Arc<TermStates>, never behind a lock of its own): the list doesn't change anymore, only the terminals in it do,
+// each behind its own lock
 impl TermStates {
     pub fn new() -> Self {
         Self {
@@ -22,263 +24,13 @@ impl TermStates {
     }
 }
 
-pub fn init_term_states() -> Arc<RwLock<TermStates>> {
-    Arc::new(RwLock::new(TermStates::new()))
+// The terminals the PLC drives: TERM_<NAME> statics, their handlers and <NAME>_IMG_LEN_BITS, see terminals.rs.
+// Tag names are the PLC's (plc/src/tags.rs), check-config catches one that isn't there anymore.
+terminals! {
+    EL1889: di 16;
+    EL2889: do 16 => { Ch1: "area 2 lights" };
+    EL3024: ai 4 => { Ch1: "humidity", Ch2: "temperature" };
+    KL1889: kbus di 16 => { Ch6: "status" };
+    KL2889: kbus do 16 => { Ch1: "area 1 lights" };
+    KL6581: intelligent, 12 + 12 bytes;
 }
-
-pub static TERM_KL1889: LazyLock<Arc<RwLock<KBusSubDevice>>> = LazyLock::new(|| {
-    Arc::new(
-        RwLock::new(
-            KBusSubDevice {
-                hr_name: 1889,
-                intelligent: false,
-                size_in_bits: KL1889_IMG_LEN_BITS,
-                is_kl1212: false,
-                gender: KBusTerminalGender::Input,
-                tx_data: None,
-                rx_data: Some(BitVec::<u8, Lsb0>::repeat(false, KL1889_IMG_LEN_BITS as usize)), // Capacity must match input process image size
-            }
-        )
-    )
-});
-
-pub fn kl1889_handler(dst: &Arc<RwLock<KBusSubDevice>>, bits: &BitSlice<u8, Lsb0>) {
-    let mut rw_guard = dst.write().expect("Acquire TERM_KL1889 read/write guard");
-
-    let num_of_channels = rw_guard.rx_data.as_ref().unwrap().len();
-
-    if bits.len() != num_of_channels as usize {
-        panic!(
-            "Actual DITerm Values len {} does not match defined number of channels {}",
-            bits.len(),
-            num_of_channels
-        );
-    }
-
-    for i in 0..num_of_channels as usize {
-        rw_guard.rx_data.as_mut().unwrap().set(i, bits[i]);
-    }
-}
-
-pub static TERM_KL2889: LazyLock<Arc<RwLock<KBusSubDevice>>> = LazyLock::new(|| {
-    Arc::new(
-        RwLock::new(
-            KBusSubDevice {
-                hr_name: 2889,
-                intelligent: false,
-                size_in_bits: KL2889_IMG_LEN_BITS,
-                is_kl1212: false,
-                gender: KBusTerminalGender::Output,
-                tx_data: Some(BitVec::<u8, Lsb0>::repeat(false, KL2889_IMG_LEN_BITS as usize)), // Capacity must match output process image size
-                rx_data: None
-            }
-        )
-    )
-});
-
-pub fn kl2889_handler(dst: &mut BitSlice<u8, Lsb0>, bits: &Arc<RwLock<KBusSubDevice>>) {
-    let rd_guard = bits.read().expect("Acquire TERM_KL2889 read guard"); // RO access
-
-    let num_of_channels = rd_guard.tx_data.as_ref().unwrap().len();
-
-    if dst.len() != num_of_channels as usize {
-        panic!(
-            "Actual DOTerm Values len {} does not match defined number of channels {}",
-            dst.len(),
-            num_of_channels
-        );
-    }
-
-    for i in 0..num_of_channels as usize {
-        dst.set(i, rd_guard.tx_data.as_ref().unwrap()[i]);
-    }
-}
-
-pub static TERM_EL3024: LazyLock<Arc<RwLock<AITerm4Ch>>> = LazyLock::new(|| {
-    Arc::new(
-        RwLock::new(
-            AITerm4Ch::new()
-        )
-    )
-});
-
-pub fn el3024_handler(dst: &Arc<RwLock<AITerm4Ch>>, bits: &BitSlice<u8, Lsb0>, channel: TermChannel) {
-    let channel: u8 = channel as u8;
-    let bits: &BitSlice<u8, Lsb0> = &bits[32*(channel as usize - 1)..(32*channel as usize)];
-    let mut rw_guard = dst.write().expect("Acquire TERM_EL3024 read/write guard");
-
-    match channel { // will reimplement using bitmasking later; should be way neater
-        1 => {
-            rw_guard.ch_statuses.ch1.txpdo_toggle = *bits.get(15).unwrap() as bool;
-            if !rw_guard.ch_statuses.ch1.txpdo_toggle { // The TxPDO toggle is toggled by the slave when the data of the associated TxPDO is updated.
-                return; }
-        },
-        2 => {
-            rw_guard.ch_statuses.ch2.txpdo_toggle = *bits.get(15).unwrap() as bool;
-            if !rw_guard.ch_statuses.ch2.txpdo_toggle { // The TxPDO toggle is toggled by the slave when the data of the associated TxPDO is updated.
-                return;}
-        },
-        3 => {
-            rw_guard.ch_statuses.ch3.txpdo_toggle = *bits.get(15).unwrap() as bool;
-            if !rw_guard.ch_statuses.ch3.txpdo_toggle { // The TxPDO toggle is toggled by the slave when the data of the associated TxPDO is updated.
-                return;}
-        },
-        4 => {
-            rw_guard.ch_statuses.ch4.txpdo_toggle = *bits.get(15).unwrap() as bool;
-            if !rw_guard.ch_statuses.ch4.txpdo_toggle { // The TxPDO toggle is toggled by the slave when the data of the associated TxPDO is updated.
-                return;}
-        },
-        _ => {unreachable!();}
-    }
-
-    match channel { // this is really ugly, but i don't want to add more abstractions and having to deal with more borrow checking gymnastics
-        1 => {
-            rw_guard.ch_values.ch1.copy_from_bitslice(bits.get(16..32).unwrap());
-            rw_guard.ch_statuses.ch1.txpdo_state = *bits.get(14).unwrap() as bool;
-            rw_guard.ch_statuses.ch1.err         = *bits.get(6).unwrap() as bool;
-            rw_guard.ch_statuses.ch1.limit2      =  bits.get(4..6).unwrap().load_le::<u8>();
-            rw_guard.ch_statuses.ch1.limit1      =  bits.get(2..4).unwrap().load_le::<u8>();
-            rw_guard.ch_statuses.ch1.overrange   = *bits.get(1).unwrap() as bool;
-            rw_guard.ch_statuses.ch1.underrange  = *bits.get(0).unwrap() as bool;
-        },
-        2 => {
-            rw_guard.ch_values.ch2.copy_from_bitslice(bits.get(16..32).unwrap());
-            rw_guard.ch_statuses.ch2.txpdo_state = *bits.get(14).unwrap() as bool;
-            rw_guard.ch_statuses.ch2.err         = *bits.get(6).unwrap() as bool;
-            rw_guard.ch_statuses.ch2.limit2      =  bits.get(4..6).unwrap().load_le::<u8>();
-            rw_guard.ch_statuses.ch2.limit1      =  bits.get(2..4).unwrap().load_le::<u8>();
-            rw_guard.ch_statuses.ch2.overrange   = *bits.get(1).unwrap() as bool;
-            rw_guard.ch_statuses.ch2.underrange  = *bits.get(0).unwrap() as bool;
-        },
-        3 => {
-            rw_guard.ch_values.ch3.copy_from_bitslice(bits.get(16..32).unwrap());
-            rw_guard.ch_statuses.ch3.txpdo_state = *bits.get(14).unwrap() as bool;
-            rw_guard.ch_statuses.ch3.err         = *bits.get(6).unwrap() as bool;
-            rw_guard.ch_statuses.ch3.limit2      =  bits.get(4..6).unwrap().load_le::<u8>();
-            rw_guard.ch_statuses.ch3.limit1      =  bits.get(2..4).unwrap().load_le::<u8>();
-            rw_guard.ch_statuses.ch3.overrange   = *bits.get(1).unwrap() as bool;
-            rw_guard.ch_statuses.ch3.underrange  = *bits.get(0).unwrap() as bool;
-        },
-        4 => {
-            rw_guard.ch_values.ch4.copy_from_bitslice(bits.get(16..32).unwrap());
-            rw_guard.ch_statuses.ch4.txpdo_state = *bits.get(14).unwrap() as bool;
-            rw_guard.ch_statuses.ch4.err         = *bits.get(6).unwrap() as bool;
-            rw_guard.ch_statuses.ch4.limit2      =  bits.get(4..6).unwrap().load_le::<u8>();
-            rw_guard.ch_statuses.ch4.limit1      =  bits.get(2..4).unwrap().load_le::<u8>();
-            rw_guard.ch_statuses.ch4.overrange   = *bits.get(1).unwrap() as bool;
-            rw_guard.ch_statuses.ch4.underrange  = *bits.get(0).unwrap() as bool;
-        },
-        _ => {unreachable!();}
-    }
-
-}
-
-pub static TERM_EL1889: LazyLock<Arc<RwLock<DITerm>>> = LazyLock::new(|| {
-    Arc::new(
-        RwLock::new(
-            DITerm {
-                values: BitVec::<u8, Lsb0>::repeat(false, EL1889_IMG_LEN_BITS as usize), // Capacity must match num_of_channels (yes ik i couldve used dynamic dispatch here, zig's comptime would be great here)
-                num_of_channels: EL1889_IMG_LEN_BITS,
-            }
-        )
-    )
-});
-
-pub fn el1889_handler(dst: &Arc<RwLock<DITerm>>, bits: &BitSlice<u8, Lsb0>) {
-    let mut rw_guard = dst.write().expect("Acquire TERM_EL1889 read/write guard");
-
-    let num_of_channels = rw_guard.values.len();
-
-    if bits.len() != num_of_channels as usize {
-        panic!(
-            "Actual DITerm Values len {} does not match defined number of channels {}",
-            bits.len(),
-            num_of_channels
-        );
-    }
-
-    for i in 0..num_of_channels as usize {
-        rw_guard.values.set(i, bits[i]);
-    }
-}
-
-pub static TERM_EL2889: LazyLock<Arc<RwLock<DOTerm>>> = LazyLock::new(|| {
-    Arc::new(
-        RwLock::new(
-            DOTerm {
-                values: BitVec::<u8, Lsb0>::repeat(false, EL2889_IMG_LEN_BITS as usize), // Capacity must match num_of_channels (yes ik i couldve used dynamic dispatch here, zig's comptime would be great here)
-                num_of_channels: EL2889_IMG_LEN_BITS,
-            }
-        )
-    )
-});
-
-pub fn el2889_handler(dst: &mut BitSlice<u8, Lsb0>, bits: &Arc<RwLock<DOTerm>>) {
-    let rd_guard = bits.read().expect("Acquire TERM_EL2889 read guard"); // RO access
-
-    let num_of_channels = rd_guard.values.len();
-
-    if dst.len() != num_of_channels as usize {
-        panic!(
-            "Actual DOTerm Values len {} does not match defined number of channels {}",
-            dst.len(),
-            num_of_channels
-        );
-    }
-
-    for i in 0..num_of_channels as usize {
-        dst.set(i, rd_guard.values[i]);
-    }
-}
-
-pub static TERM_KL6581: LazyLock<Arc<RwLock<KBusSubDevice>>> = LazyLock::new(|| {
-    Arc::new(
-        RwLock::new(
-            KBusSubDevice {
-                hr_name: 6581,
-                intelligent: true,
-                size_in_bits: KL6581_IMG_LEN_BITS, // 12 bytes input, 12 bytes output
-                is_kl1212: false,
-                gender: KBusTerminalGender::Enby,
-                tx_data: Some(BitVec::<u8, Lsb0>::repeat(false, (KL6581_IMG_LEN_BITS / 2) as usize)), // Capacity must match output process image size
-                rx_data: Some(BitVec::<u8, Lsb0>::repeat(false, (KL6581_IMG_LEN_BITS / 2) as usize)), // Capacity must match input process image size
-            }
-        )
-    )
-});
-
-pub fn kl6581_output_handler(dst: &mut BitSlice<u8, Lsb0>, bits: &Arc<RwLock<KBusSubDevice>>) {
-    let rd_guard = bits.read().expect("Acquire TERM_KL6581 read guard"); // RO access
-
-    let num_of_channels = rd_guard.tx_data.as_ref().unwrap().len();
-
-    if dst.len() != num_of_channels as usize {
-        panic!(
-            "Actual DOTerm Values len {} does not match defined number of channels {}",
-            dst.len(),
-            num_of_channels
-        );
-    }
-
-    for i in 0..num_of_channels as usize {
-        dst.set(i, rd_guard.tx_data.as_ref().unwrap()[i]);
-    }
-}
-
-pub fn kl6581_input_handler(dst: &Arc<RwLock<KBusSubDevice>>, bits: &BitSlice<u8, Lsb0>) {
-    let mut rw_guard = dst.write().expect("Acquire TERM_KL6581 read/write guard");
-
-    let num_of_channels = rw_guard.rx_data.as_ref().unwrap().len();
-
-    if bits.len() != num_of_channels as usize {
-        panic!(
-            "Actual DITerm Values len {} does not match defined number of channels {}",
-            bits.len(),
-            num_of_channels
-        );
-    }
-
-    for i in 0..num_of_channels as usize {
-        rw_guard.rx_data.as_mut().unwrap().set(i, bits[i]);
-    }
-}
\ No newline at end of file
@@ -1,3 +1,6 @@
 pub mod term_cfg;
 pub mod io_defs;
-pub mod enocean_driver;
\ No newline at end of file
+pub mod terminals;
+pub mod pdi;
+pub mod enocean_driver;
+pub mod diagnostics;
\ No newline at end of file
@@ -0,0 +1,49 @@
+// Where a terminal's bits are in its SubDevice's input or output image. Resolved once when the bus comes up, from the
+// image sizes and the K-bus slot ranges, so a cycle doesn't look anything up: it takes the views of the images it got
+// from the SubDevices and hands them to the handlers as they are, borrowed, nothing copied on the way.
+//
+// let kl6581 = PdiRange::slots(term.slot_idx_range);  // at init, checked with fits() against the coupler's images
+// kl6581_input_handler(&*TERM_KL6581, kl6581.view(input_bits));  // every cycle
+use bitvec::prelude::*;
+
+/// `len` bits from bit `begin` of a SubDevice's input or output image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PdiRange {
+    pub begin: usize,
+    pub len: usize,
+}
+
+impl PdiRange {
+    pub const fn new(begin: usize, len: usize) -> Self {
+        Self { begin, len }
+    }
+
+    /// A whole image of `bytes`
+    pub const fn image(bytes: usize) -> Self {
+        Self::new(0, 8 * bytes)
+    }
+
+    /// The bits a K-bus terminal's closed slot range (begin, end) covers, none if it has no slot range
+    pub const fn slots(slot_idx_range: (u8, u8)) -> Self {
+        let (begin, end) = (slot_idx_range.0 as usize, slot_idx_range.1 as usize);
+        Self::new(begin, if end < begin { 0 } else { end - begin + 1 })
+    }
+
+    pub const fn end(&self) -> usize {
+        self.begin + self.len
+    }
+
+    /// Whether the range is inside an image of `image_bits`, what view() and view_mut() rely on
+    pub const fn fits(&self, image_bits: usize) -> bool {
+        self.end() <= image_bits
+    }
+
+    /// The range's bits of `image`. Panics if it doesn't fit, check with fits() when resolving
+    pub fn view<'a>(&self, image: &'a BitSlice<u8, Lsb0>) -> &'a BitSlice<u8, Lsb0> {
+        &image[self.begin..self.end()]
+    }
+
+    pub fn view_mut<'a>(&self, image: &'a mut BitSlice<u8, Lsb0>) -> &'a mut BitSlice<u8, Lsb0> {
+        &mut image[self.begin..self.end()]
+    }
+}
@@ -1,6 +1,7 @@
 use bitvec::prelude::*;
+use crate::pdi::PdiRange;
 use enum_iterator::Sequence;
-use std::ops::Deref;
+use std::ops::Range;
 use std::sync::{Arc, RwLock};
 
 #[repr(u8)]
@@ -17,15 +18,54 @@ pub enum ChannelInput {
     Index(u8) // For EnOcean/intelligent digital terminals
 }
 
-#[derive(PartialEq)]
-pub enum ElectricalObservable {
+#[derive(PartialEq, Clone, Copy)]
+pub enum ElectricalObservable<'a> {
     Voltage(f32),
     Current(f32),
     Simple(u8), // Boolean values
-    Smart(BitVec<u8, Lsb0>), // For intelligent digital terminals
+    Smart(SmartReading<'a>), // For intelligent digital terminals
+}
+
+/// An intelligent terminal's process data as read() finds it, borrowed from the terminal: [rx_data, tx_data]
+#[derive(PartialEq, Clone, Copy)]
+pub struct SmartReading<'a> {
+    pub rx_data: &'a BitSlice<u8, Lsb0>,
+    pub tx_data: &'a BitSlice<u8, Lsb0>,
+}
+
+impl<'a> SmartReading<'a> {
+    pub fn len(&self) -> usize {
+        self.rx_data.len() + self.tx_data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Bit `idx` of [rx_data, tx_data]
+    pub fn bit(&self, idx: usize) -> bool {
+        self.bits(idx..idx + 1)[0]
+    }
+
+    /// Bits `range` of [rx_data, tx_data], which has to be in one of the two
+    pub fn bits(&self, range: Range<usize>) -> &'a BitSlice<u8, Lsb0> {
+        let split = self.rx_data.len();
+        if range.end <= split {
+            &self.rx_data[range]
+        }
+        else if range.start >= split {
+            &self.tx_data[range.start - split..range.end - split]
+        }
+        else {
+            panic!("Bits {:?} straddle rx_data and tx_data ({} bits each)", range, split)
+        }
+    }
 }
 
-impl ElectricalObservable { // there has to be a better way, will refactor later
+/// Non-value bits Checker::check() returns, at most 16 of them
+pub type StatusBits = BitArr!(for 16, in u8, Lsb0);
+
+impl<'a> ElectricalObservable<'a> { // there has to be a better way, will refactor later
     pub fn pick_voltage(&self) -> Option<f32> {
         match self {
             ElectricalObservable::Voltage(v) => Some(*v),
@@ -44,9 +84,9 @@ impl ElectricalObservable { // there has to be a better way, will refactor later
             _ => None
         }
     }
-    pub fn pick_smart(&self) -> Option<BitVec<u8, Lsb0>> {
+    pub fn pick_smart(&self) -> Option<SmartReading<'a>> {
         match self {
-            ElectricalObservable::Smart(val) => Some(val.clone()),
+            ElectricalObservable::Smart(val) => Some(*val),
             _ => None
         }
     }
@@ -65,16 +105,8 @@ pub enum VoltageOrCurrent {
     Current
 }
 
-pub const EL1889_IMG_LEN_BITS: u8 = 2*8;
-pub const KL1889_IMG_LEN_BITS: u8 = 2*8;
-pub const EL2889_IMG_LEN_BITS: u8 = 2*8;
-pub const KL2889_IMG_LEN_BITS: u8 = 2*8;
-pub const KL6581_IMG_LEN_BITS: u8 = 12*2*8; // 24 bytes total, 12 each for Input/Output
-pub const EL3024_IMG_LEN_BITS: u8 = 16*8; // 16 bytes total, for each channel value is 2 bytes and status is 2 bytes
-pub const EL3024_NUM_CHANNELS: u8 = 4;
-
 pub trait Getter { // channel should be passed as None for Enby terms
-    fn read(&self, channel: Option<ChannelInput>) -> Result<ElectricalObservable, String>;
+    fn read(&self, channel: Option<ChannelInput>) -> Result<ElectricalObservable<'_>, String>;
 }
 
 pub trait Setter {
@@ -82,7 +114,7 @@ pub trait Setter {
 }
 
 pub trait Checker { // this is a trait not shared by simple terminals w/o status bits
-    fn check(&self, channel: Option<ChannelInput>) -> Option<Result<BitVec::<u8, Lsb0>, String>>; // Returns all non-value bits
+    fn check(&self, channel: Option<ChannelInput>) -> Option<Result<StatusBits, String>>; // Returns all non-value bits
 }
 
 #[derive(PartialEq, Clone)]
@@ -127,27 +159,14 @@ impl KBusTerm {
         }
     }
 
-    /// `dst` is RxPDO of term.
+    /// `dst` is RxPDO of term, its bits in the coupler's output image (pdi_range())
     /// 
     /// this is for setting outputs
     pub fn refresh_term(&self, dst: &mut BitSlice<u8, Lsb0>) {
-        let (slot_idx_begin, slot_idx_end) = self.slot_idx_range;
-        let dst = &mut dst[slot_idx_begin as usize .. (slot_idx_end + 1) as usize];
-
-        if self.gender == KBusTerminalGender::Output {
-            for (idx, bit) in self.rx_data.as_ref().unwrap().iter().enumerate() {
-                dst.set(idx, *bit);
-            }
-        }
-
-        if self.gender == KBusTerminalGender::Enby {
-            for (idx, bit) in self.tx_data.as_ref().unwrap().iter().enumerate() {
-                dst.set(idx, *bit);
-            }
-
-            for (idx, bit) in self.rx_data.as_ref().unwrap().iter().enumerate() {
-                dst.set(idx, *bit);
-            }
+        match self.gender {
+            KBusTerminalGender::Output => dst.copy_from_bitslice(self.rx_data.as_deref().unwrap()),
+            KBusTerminalGender::Enby => dst.copy_from_bitslice(&self.rx_data.as_deref().unwrap()[..self.image_bits()]),
+            KBusTerminalGender::Input => {}
         }
     }
 
@@ -156,84 +175,112 @@ impl KBusTerm {
     /// NB: If `output_bits` is not None, the actual RxPDO from the terminal will overwrite the controller copy in memory.
     /// If there is contention between terminal and controller (i.e. faulty terminal), the command stored in controller memory may be overwritten by terminal due to refusal to change state (some fault or error)
     pub fn refresh_ctrlr(&mut self, input_bits: Option<&BitSlice<u8, Lsb0>>, output_bits: Option<&BitSlice<u8, Lsb0>>) {
-        // `input_bits`, `output_bits` passed as input param is the entire input/output image of the BK coupler
-        let (slot_idx_begin, slot_idx_end) = self.slot_idx_range;
-
-        if input_bits != None {
-            let input_bits = &input_bits.unwrap()[slot_idx_begin as usize .. (slot_idx_end + 1) as usize];
-            if self.gender == KBusTerminalGender::Input {
-                for (idx, bit) in input_bits.iter().enumerate() {
-                    self.tx_data.as_mut().unwrap().set(idx, *bit);
-                }
-            }
+        // `input_bits`, `output_bits` passed as input param are the terminal's bits in the BK coupler's input/output image (pdi_range())
+        // An Enby terminal's tx_data and rx_data are sized for both images, its bits in each are the first image_bits()
+        let image_bits = self.image_bits();
+        if let Some(input_bits) = input_bits && self.gender != KBusTerminalGender::Output {
+            self.tx_data.as_mut().unwrap()[..image_bits].copy_from_bitslice(input_bits);
         }
 
-        if output_bits != None {
-            let output_bits: &BitSlice<u8, Lsb0> = &output_bits.unwrap()[slot_idx_begin as usize .. (slot_idx_end + 1) as usize];
-            if self.gender == KBusTerminalGender::Output {
-                for (idx, bit) in output_bits.iter().enumerate() {
-                    self.rx_data.as_mut().unwrap().set(idx, *bit);
-                }
-            }
+        if let Some(output_bits) = output_bits && self.gender != KBusTerminalGender::Input {
+            self.rx_data.as_mut().unwrap()[..image_bits].copy_from_bitslice(output_bits);
         }
+    }
 
-        if self.gender == KBusTerminalGender::Enby {
-            let input_bits = &input_bits.unwrap()[slot_idx_begin as usize .. (slot_idx_end + 1) as usize];
-            let output_bits: &BitSlice<u8, Lsb0> = &output_bits.unwrap()[slot_idx_begin as usize .. (slot_idx_end + 1) as usize];
+    /// Where the terminal's bits are in the coupler's images, the same bits in both for an Enby terminal
+    pub fn pdi_range(&self) -> PdiRange {
+        PdiRange::slots(self.slot_idx_range)
+    }
 
-            for (idx, bit) in input_bits.iter().enumerate() {
-                self.tx_data.as_mut().unwrap().set(idx, *bit);
-            }
+    /// KL number of intelligent terminals, which report it. Simple ones only report their size and direction
+    pub fn label(&self) -> String {
+        match self.gender {
+            _ if self.intelligent => format!("KL{}", self.name),
+            KBusTerminalGender::Input => format!("simple input 0x{:04x}", self.name),
+            KBusTerminalGender::Output => format!("simple output 0x{:04x}", self.name),
+            KBusTerminalGender::Enby => format!("simple input/output 0x{:04x}", self.name),
+        }
+    }
 
-            for (idx, bit) in output_bits.iter().enumerate() {
-                self.rx_data.as_mut().unwrap().set(idx, *bit);
-            }
+    /// Whether the terminal takes bits in the coupler's input (false) or output (true) image, Enby ones are in both
+    pub fn in_image(&self, output: bool) -> bool {
+        match self.gender {
+            KBusTerminalGender::Input => !output,
+            KBusTerminalGender::Output => output,
+            KBusTerminalGender::Enby => true,
         }
+    }
 
+    /// Bits the terminal takes in each image it's in, an Enby terminal's size counts both
+    pub fn image_bits(&self) -> usize {
+        if self.gender == KBusTerminalGender::Enby { self.size_in_bits as usize / 2 } else { self.size_in_bits as usize }
     }
 }
 
+pub const KBUS_CTRL_BITS: usize = 16; // status word (inputs) and control word (outputs) in front of the terminals in the BK1120 images
+
+/// Problems with where `terms` sit in the BK1120 images, one line each: slot ranges that don't fit the terminal
+/// (its channels would be read from or written to bits of something else), overlap the coupler's status/control
+/// word or another terminal, or run past the image. `image_bits` is (inputs, outputs) once the PDI is mapped.
+pub fn kbus_layout_problems(terms: &[&KBusTerm], image_bits: Option<(usize, usize)>) -> Vec<String> {
+    let mut problems = Vec::new();
+    let describe = |pos: usize, term: &KBusTerm| format!("K-bus terminal {} ({})", pos + 1, term.label());
+    for (pos, term) in terms.iter().enumerate() {
+        let (begin, end) = (term.slot_idx_range.0 as usize, term.slot_idx_range.1 as usize);
+        if end < begin {
+            problems.push(format!("{} has no slot range, ({}, {})", describe(pos, term), begin, end));
+            continue;
+        }
+        if end - begin + 1 != term.image_bits() {
+            problems.push(format!("{} has {} bits of channels but slot range {}-{} is {} bits", describe(pos, term), term.image_bits(), begin, end, end - begin + 1));
+        }
+        if begin < KBUS_CTRL_BITS {
+            problems.push(format!("{} at {}-{} overlaps the coupler's status/control word (bits 0-{})", describe(pos, term), begin, end, KBUS_CTRL_BITS - 1));
+        }
+        for (output, image) in [(false, "input"), (true, "output")] {
+            if !term.in_image(output) {
+                continue;
+            }
+            if let Some(len) = image_bits.map(|(inputs, outputs)| if output { outputs } else { inputs }) && end >= len {
+                problems.push(format!("{} at {}-{} runs past the coupler's {} bit {} image", describe(pos, term), begin, end, len, image));
+            }
+            for (other_pos, other) in terms.iter().enumerate().take(pos) {
+                let (other_begin, other_end) = (other.slot_idx_range.0 as usize, other.slot_idx_range.1 as usize);
+                if other.in_image(output) && begin <= other_end && other_begin <= end {
+                    problems.push(format!(
+                        "{} at {}-{} overlaps {} at {}-{} in the {} image",
+                        describe(pos, term), begin, end, describe(other_pos, other), other_begin, other_end, image,
+                    ));
+                }
+            }
+        }
+    }
+    problems
+}
+
 impl Getter for KBusTerm {
-    // For Enby terminals the inputs and outputs are concatenated in this order (Lsb) as a single bitvec: [rx_data, tx_data]
+    // For Enby terminals the inputs and outputs are concatenated in this order (Lsb) as a single reading: [rx_data, tx_data]
     // for reading Enby terminals, channel should be passed as None
-    fn read(&self, channel: Option<ChannelInput>) -> Result<ElectricalObservable, String> {
+    fn read(&self, channel: Option<ChannelInput>) -> Result<ElectricalObservable<'_>, String> {
         let channel: usize = match channel {
             Some(ChannelInput::Channel(tc)) => tc as usize - 1, // TermChannel starts at 1
             Some(ChannelInput::Index(idx)) => idx as usize, // Index starts at 0
             None => 0,
         };
-    
-        let mut buf: BitVec<u8> = match self.gender {
-            KBusTerminalGender::Input | KBusTerminalGender::Output => BitVec::<u8, Lsb0>::repeat(false, 16),
-            KBusTerminalGender::Enby if channel == 0 => BitVec::<u8, Lsb0>::repeat(false, 32*8),
-            _ => return Err(format!("Must pass channel input param as None for Enby terms"))
-        };
 
-        if self.gender == KBusTerminalGender::Input {
-            buf = self.tx_data.clone().expect("tx_data not initialized");
-        }
-        if self.gender == KBusTerminalGender::Output {
-            buf = self.rx_data.clone().expect("rx_data not initialized");
-        }
-        if self.gender == KBusTerminalGender::Enby {
-            buf = self.rx_data.clone().expect("rx_data not initialized");
-            buf.extend(self.tx_data.clone().expect("tx_data not initialized"));
-        }
+        let values = match self.gender {
+            KBusTerminalGender::Input => self.tx_data.as_deref().expect("tx_data not initialized"),
+            KBusTerminalGender::Output => self.rx_data.as_deref().expect("rx_data not initialized"),
+            KBusTerminalGender::Enby if channel == 0 => return Ok(ElectricalObservable::Smart(SmartReading {
+                rx_data: self.rx_data.as_deref().expect("rx_data not initialized"),
+                tx_data: self.tx_data.as_deref().expect("tx_data not initialized"),
+            })),
+            KBusTerminalGender::Enby => return Err("Must pass channel input param as None for Enby terms".into()),
+        };
 
-        if self.gender == KBusTerminalGender::Input || self.gender == KBusTerminalGender::Output {
-            let readout = match buf.get(channel) {
-                Some(bit) => bit,
-                None => return Err(format!("Error reading channel {}: Index out of bounds", channel)),
-            };
-            let readout_cast = readout.deref().clone() as u8;
-            Ok(ElectricalObservable::Simple(readout_cast))
-        }
-        else {
-            if self.gender == KBusTerminalGender::Enby {
-                let readout = buf;
-                Ok(ElectricalObservable::Smart(readout))
-            }
-            else {unreachable!()} // there are only three genders
+        match values.get(channel) {
+            Some(bit) => Ok(ElectricalObservable::Simple(*bit as u8)),
+            None => Err(format!("Error reading channel {}: Index out of bounds", channel)),
         }
     }
 }
@@ -266,46 +313,28 @@ pub struct KBusSubDevice {
 }
 
 impl Getter for KBusSubDevice {
-    // For Enby terminals the inputs and outputs are concatenated in this order (Lsb) as a single bitvec: [rx_data, tx_data]
+    // For Enby terminals the inputs and outputs are concatenated in this order (Lsb) as a single reading: [rx_data, tx_data]
     // for reading Enby terminals, channel should be passed as None
-    fn read(&self, channel: Option<ChannelInput>) -> Result<ElectricalObservable, String> {
+    fn read(&self, channel: Option<ChannelInput>) -> Result<ElectricalObservable<'_>, String> {
         let channel: usize = match channel {
             Some(ChannelInput::Channel(tc)) => tc as usize - 1, // TermChannel starts at 1
             Some(ChannelInput::Index(idx)) => idx as usize, // Index starts at 0
             None => 0,
         };
-    
-        let mut values: BitVec<u8> = match self.gender {
-            KBusTerminalGender::Input | KBusTerminalGender::Output => BitVec::<u8, Lsb0>::repeat(false, 16),
-            KBusTerminalGender::Enby if channel == 0 => BitVec::<u8, Lsb0>::repeat(false, 32*8),
-            _ => return Err(format!("Must pass channel input param as None for Enby terms"))
-        };
 
-        if self.gender == KBusTerminalGender::Input {
-            values = self.rx_data.clone().unwrap();
-        }
-        if self.gender == KBusTerminalGender::Output {
-            values = self.tx_data.clone().unwrap();
-        }
-        if self.gender == KBusTerminalGender::Enby {
-            values = self.rx_data.clone().unwrap();
-            values.extend(self.tx_data.clone().unwrap());
-        }
+        let values = match self.gender {
+            KBusTerminalGender::Input => self.rx_data.as_deref().unwrap(),
+            KBusTerminalGender::Output => self.tx_data.as_deref().unwrap(),
+            KBusTerminalGender::Enby if channel == 0 => return Ok(ElectricalObservable::Smart(SmartReading {
+                rx_data: self.rx_data.as_deref().unwrap(),
+                tx_data: self.tx_data.as_deref().unwrap(),
+            })),
+            KBusTerminalGender::Enby => return Err("Must pass channel input param as None for Enby terms".into()),
+        };
 
-        if self.gender == KBusTerminalGender::Input || self.gender == KBusTerminalGender::Output {
-            let readout = match values.get(channel) {
-                Some(bit) => bit,
-                None => return Err(format!("Error reading channel {}: Index out of bounds", channel)),
-            };
-            let readout_cast = readout.deref().clone() as u8;
-            Ok(ElectricalObservable::Simple(readout_cast))
-        }
-        else {
-            if self.gender == KBusTerminalGender::Enby {
-                let readout = values;
-                Ok(ElectricalObservable::Smart(readout))
-            }
-            else {unreachable!()} // there are only three genders
+        match values.get(channel) {
+            Some(bit) => Ok(ElectricalObservable::Simple(*bit as u8)),
+            None => Err(format!("Error reading channel {}: Index out of bounds", channel)),
         }
     }
 }
@@ -354,9 +383,7 @@ impl DITerm {
             );
         }
     
-        for i in 0..num_of_channels {
-            self.values.set(i, bits[i]);
-        }
+        self.values.copy_from_bitslice(bits);
     }
 }
 
@@ -366,23 +393,17 @@ impl DITerm {
 //     log::info!("Limit switch hit");
 // }
 impl Getter for DITerm {
-    fn read(&self, channel: Option<ChannelInput>) -> Result<ElectricalObservable, String> {
+    fn read(&self, channel: Option<ChannelInput>) -> Result<ElectricalObservable<'_>, String> {
         let channel: usize = match channel {
             Some(ChannelInput::Channel(tc)) => (tc as usize) - 1,
             Some(ChannelInput::Index(idx)) => idx as usize,
             None => return Err(format!("Can only pass None for Enby terms"))
         };
 
-        let values = self.values.clone();
-
-        let readout = match values.get(channel) {
-            Some(bit) => bit,
-            None => return Err(format!("Error reading channel {}: Index out of bounds", channel)),
-        };
-
-        let readout_cast = readout.deref().clone() as u8;
-
-        Ok(ElectricalObservable::Simple(readout_cast))
+        match self.values.get(channel) {
+            Some(bit) => Ok(ElectricalObservable::Simple(*bit as u8)),
+            None => Err(format!("Error reading channel {}: Index out of bounds", channel)),
+        }
     }
 }
 
@@ -411,9 +432,7 @@ impl DOTerm {
             );
         }
     
-        for i in 0..num_of_channels {
-            dst.set(i, self.values[i]);
-        }
+        dst.copy_from_bitslice(&self.values);
     }
 }
 
@@ -438,23 +457,17 @@ impl Setter for DOTerm {
 }
 
 impl Getter for DOTerm {
-    fn read(&self, channel: Option<ChannelInput>) -> Result<ElectricalObservable, String> {
+    fn read(&self, channel: Option<ChannelInput>) -> Result<ElectricalObservable<'_>, String> {
         let channel: usize = match channel {
             Some(ChannelInput::Channel(tc)) => (tc as usize) - 1,
             Some(ChannelInput::Index(idx)) => idx as usize,
             None => return Err(format!("Can only pass None for Enby terms"))
         };
 
-        let values = self.values.clone();
-
-        let readout = match values.get(channel) {
-            Some(bit) => bit,
-            None => return Err(format!("Error reading channel {}: Index out of bounds", channel)),
-        };
-
-        let readout_cast = readout.deref().clone() as u8;
-
-        Ok(ElectricalObservable::Simple(readout_cast))
+        match self.values.get(channel) {
+            Some(bit) => Ok(ElectricalObservable::Simple(*bit as u8)),
+            None => Err(format!("Error reading channel {}: Index out of bounds", channel)),
+        }
     }
 }
 
@@ -545,19 +558,19 @@ impl AITerm4Ch {
 }
 
 impl Getter for AITerm4Ch {
-    fn read(&self, channel: Option<ChannelInput>) -> Result<ElectricalObservable, String> {
+    fn read(&self, channel: Option<ChannelInput>) -> Result<ElectricalObservable<'_>, String> {
         let channel: usize = match channel {
             Some(ChannelInput::Channel(tc)) => tc as usize,
             Some(ChannelInput::Index(idx)) => idx as usize + 1,
             None => return Err(format!("Can only pass None for Enby terms"))
         };
 
-        let raw_int: BitVec::<u8, Lsb0> =
+        let raw_int: &BitSlice::<u8, Lsb0> =
             match channel {
-                1 => self.ch_values.ch1.clone(),
-                2 => self.ch_values.ch2.clone(),
-                3 => self.ch_values.ch3.clone(),
-                4 => self.ch_values.ch4.clone(),
+                1 => &self.ch_values.ch1,
+                2 => &self.ch_values.ch2,
+                3 => &self.ch_values.ch3,
+                4 => &self.ch_values.ch4,
                 _ => return Err("Invalid channel. Can only specify Channels 1-4.".into())
             };
 
@@ -574,7 +587,7 @@ impl Getter for AITerm4Ch {
 }
 
 impl Checker for AITerm4Ch {
-    fn check(&self, channel: Option<ChannelInput>) -> Option<Result<BitVec::<u8, Lsb0>, String>> {
+    fn check(&self, channel: Option<ChannelInput>) -> Option<Result<StatusBits, String>> {
         let channel: usize = match channel {
             Some(ChannelInput::Channel(tc)) => tc as usize,
             Some(ChannelInput::Index(idx)) => idx as usize + 1,
@@ -582,31 +595,31 @@ impl Checker for AITerm4Ch {
         };
         
         let ch_status = match channel {
-            1 => self.ch_statuses.ch1.clone(),
-            2 => self.ch_statuses.ch2.clone(),
-            3 => self.ch_statuses.ch3.clone(),
-            4 => self.ch_statuses.ch4.clone(),
+            1 => &self.ch_statuses.ch1,
+            2 => &self.ch_statuses.ch2,
+            3 => &self.ch_statuses.ch3,
+            4 => &self.ch_statuses.ch4,
             _ => return Some(Err("Invalid channel. Can only specify Channels 1-4.".into()))
         };
 
-        let mut bits = BitVec::<u8, Lsb0>::new();
+        let mut bits = StatusBits::ZERO;
 
         // these are bools
-        bits.push(ch_status.txpdo_toggle);
-        bits.push(ch_status.txpdo_state);
-        bits.push(ch_status.err);
+        bits.set(0, ch_status.txpdo_toggle);
+        bits.set(1, ch_status.txpdo_state);
+        bits.set(2, ch_status.err);
 
-        // push first Lsb 2 bits from limit2
-        bits.push((ch_status.limit2 & 0b01) != 0);
-        bits.push((ch_status.limit2 & 0b10) != 0);
+        // first Lsb 2 bits from limit2
+        bits.set(3, (ch_status.limit2 & 0b01) != 0);
+        bits.set(4, (ch_status.limit2 & 0b10) != 0);
 
-        // push first Lsb 2 bits from limit1
-        bits.push((ch_status.limit1 & 0b01) != 0);
-        bits.push((ch_status.limit1 & 0b10) != 0);
+        // first Lsb 2 bits from limit1
+        bits.set(5, (ch_status.limit1 & 0b01) != 0);
+        bits.set(6, (ch_status.limit1 & 0b10) != 0);
 
         // remaining bools
-        bits.push(ch_status.overrange);
-        bits.push(ch_status.underrange);
+        bits.set(7, ch_status.overrange);
+        bits.set(8, ch_status.underrange);
 
         Some(Ok(bits))
     }
@@ -631,64 +644,40 @@ impl AITerm {
         }
     }
 
+    /// Takes the channels' status and value words over from the terminal's input image, a status word then a value
+    /// word per channel, straight into ch_statuses and ch_values
     pub fn refresh(&mut self, bits: &BitSlice<u8, Lsb0>) {
-        let num_of_channels = (self.ch_values.len() + self.ch_statuses.len()) / 32;
-        let origin_bits_len = bits.len() / (8*num_of_channels);
-    
-        if origin_bits_len != num_of_channels {
+        let num_of_channels = self.num_of_channels as usize;
+        if bits.len() != 32 * num_of_channels {
             panic!(
-                "Actual AITerm Values len {} does not match defined number of channels {}",
-                origin_bits_len,
+                "Actual AITerm input image of {} bits does not match defined number of channels {}",
+                bits.len(),
                 num_of_channels
             );
         }
 
-        let mut buf = BitVec::<u8, Lsb0>::new();
-        let mut j: usize = 0;
-        while j < bits.len() {
-            buf.push(bits[j]);
-            j += 1;
-            if j % 16 == 0 {
-                j += 16;
-                continue;
-            }
-        }
-
-        for i in 0..16*num_of_channels {
-            self.ch_statuses.set(i, buf[i]);
-        }
-
-        let mut buf = BitVec::<u8, Lsb0>::new();
-        j = 0;
-        while j < bits.len() {
-            buf.push(bits[j+16]);
-            j += 1;
-            if j % 16 == 0 {
-                j += 16;
-                continue;
-            }
-        }
-        
-        for i in 0..16*num_of_channels {
-            self.ch_values.set(i, buf[i]);
+        for (ch, words) in bits.chunks_exact(32).enumerate() {
+            let word = 16*ch..16*(ch + 1);
+            self.ch_statuses[word.clone()].store_le(words[0..16].load_le::<u16>());
+            self.ch_values[word].store_le(words[16..32].load_le::<u16>());
         }
     }
 }
 
 impl Getter for AITerm {
-    fn read(&self, channel: Option<ChannelInput>) -> Result<ElectricalObservable, String> {
+    fn read(&self, channel: Option<ChannelInput>) -> Result<ElectricalObservable<'_>, String> {
         let channel: usize = match channel {
             Some(ChannelInput::Channel(tc)) => tc as usize,
             Some(ChannelInput::Index(idx)) => idx as usize + 1,
             None => return Err(format!("Can only pass None for Enby terms"))
         };
 
-        let raw_int: BitVec::<u8, Lsb0> =
+        let raw_int: &BitSlice::<u8, Lsb0> =
             match channel {
-                1 => self.ch_values[0..16].to_bitvec(),
-                2 => self.ch_values[16..32].to_bitvec(),
-                3 => self.ch_values[32..48].to_bitvec(),
-                4 => self.ch_values[48..64].to_bitvec(),
+                1 => &self.ch_values[0..16],
+                2 => &self.ch_values[16..32],
+                3 => &self.ch_values[32..48],
+                4 => &self.ch_values[48..64],
                 _ => return Err("Invalid channel. Can only specify Channels 1-4.".into())
             };
 
@@ -705,7 +694,7 @@ impl Getter for AITerm {
 }
 
 impl Checker for AITerm {
-    fn check(&self, channel: Option<ChannelInput>) -> Option<Result<BitVec::<u8, Lsb0>, String>> {
+    fn check(&self, channel: Option<ChannelInput>) -> Option<Result<StatusBits, String>> {
         let channel: usize = match channel {
             Some(ChannelInput::Channel(tc)) => tc as usize,
             Some(ChannelInput::Index(idx)) => idx as usize + 1,
@@ -713,18 +702,15 @@ impl Checker for AITerm {
         };
         
         let ch_status = match channel {
-            1 => self.ch_statuses[0..16].to_bitvec(),
-            2 => self.ch_statuses[16..32].to_bitvec(),
-            3 => self.ch_statuses[32..48].to_bitvec(),
-            4 => self.ch_statuses[48..64].to_bitvec(),
+            1 => &self.ch_statuses[0..16],
+            2 => &self.ch_statuses[16..32],
+            3 => &self.ch_statuses[32..48],
+            4 => &self.ch_statuses[48..64],
             _ => return Some(Err("Invalid channel. Can only specify Channels 1-4.".into()))
         };
 
-        let mut bits = BitVec::<u8, Lsb0>::new();
-
-        for bit in ch_status.iter() {
-            bits.push(*bit);
-        }
+        let mut bits = StatusBits::ZERO;
+        bits.copy_from_bitslice(ch_status);
 
         Some(Ok(bits))
     }
@@ -733,11 +719,12 @@ impl Checker for AITerm {
 
 
 impl Checker for KBusSubDevice {
-    fn check(&self, _channel: Option<ChannelInput>) -> Option<Result<BitVec::<u8, Lsb0>, String>> {
+    fn check(&self, _channel: Option<ChannelInput>) -> Option<Result<StatusBits, String>> {
         if self.intelligent && self.hr_name == 6581 {
-            let value: BitVec::<u8, Lsb0> = self.tx_data.clone().unwrap(); // Input image, transmitted from terminal to controller
-            let bits: &BitSlice<u8, Lsb0> = value.as_bitslice();
-            return Some(Ok(BitVec::from_bitslice(&bits[0..8]))) // SB - Status Byte
+            let bits: &BitSlice<u8, Lsb0> = self.tx_data.as_deref().unwrap(); // Input image, transmitted from terminal to controller
+            let mut status = StatusBits::ZERO;
+            status[0..8].copy_from_bitslice(&bits[0..8]); // SB - Status Byte
+            return Some(Ok(status))
         }
 
         if self.gender != KBusTerminalGender::Enby {
@@ -0,0 +1,211 @@
+// terminals! declares the terminals the PLC drives, one line each, and expands into what io_defs.rs used to spell out
+// for every one of them: a <NAME>_IMG_LEN_BITS constant, the TERM_<NAME> static holding the terminal's state, the
+// handler(s) copying between that and the process image, and which tags the terminal's channels feed.
+//
+//     EL1889: di 16;                       EtherCAT digital inputs, DITerm,       el1889_handler(term, bits)
+//     EL2889: do 16;                       EtherCAT digital outputs, DOTerm,      el2889_handler(bits, term)
+//     EL3024: ai 4;                        EtherCAT analog inputs, AITerm4Ch,     el3024_handler(term, bits, channel)
+//     KL1889: kbus di 16;                  simple K-bus inputs, KBusSubDevice,    kl1889_handler(term, bits)
+//     KL2889: kbus do 16;                  simple K-bus outputs, KBusSubDevice,   kl2889_handler(bits, term)
+//     KL6581: intelligent, 12 + 12 bytes;  intelligent K-bus terminal, inputs + outputs, KBusSubDevice,
+//                                          kl6581_input_handler(term, bits) and kl6581_output_handler(bits, term)
+//
+// Sizes are channels (bits) for digital terminals. Analog terminals only come with 4 channels for now, AITerm4Ch.
+// Any but intelligent terminals can end in `=> { Ch2: "temperature", .. }` before the `;`, binding their channels to
+// tags by name. Those all land in TAG_BINDINGS, which check-config holds against the PLC's tag list.
+use crate::term_cfg::{AITerm4Ch, KBusSubDevice, KBusTerminalGender, TermChannel};
+use bitvec::prelude::*;
+
+/// A terminal channel feeding a tag, from terminals!
+pub struct TagBinding {
+    pub terminal: &'static str,
+    pub channels: u8, // the terminal has
+    pub channel: TermChannel,
+    pub tag: &'static str,
+}
+
+macro_rules! terminals {
+    // One declaration at a time, the tag bindings pile up in [..] until the last one
+    (@munch [$($bindings:tt)*]) => {
+        pub const TAG_BINDINGS: &[$crate::terminals::TagBinding] = &[$($bindings)*];
+    };
+    (@munch [$($bindings:tt)*] $name:ident: di $bits:literal $(=> { $($ch:ident: $tag:expr),* $(,)? })?; $($rest:tt)*) => {
+        $crate::terminals::terminals!(@ebus_input $name $bits);
+        $crate::terminals::terminals!(@munch [$($bindings)* $($($crate::terminals::terminals!(@bind $name $bits $ch $tag),)*)?] $($rest)*);
+    };
+    (@munch [$($bindings:tt)*] $name:ident: do $bits:literal $(=> { $($ch:ident: $tag:expr),* $(,)? })?; $($rest:tt)*) => {
+        $crate::terminals::terminals!(@ebus_output $name $bits);
+        $crate::terminals::terminals!(@munch [$($bindings)* $($($crate::terminals::terminals!(@bind $name $bits $ch $tag),)*)?] $($rest)*);
+    };
+    (@munch [$($bindings:tt)*] $name:ident: ai 4 $(=> { $($ch:ident: $tag:expr),* $(,)? })?; $($rest:tt)*) => {
+        $crate::terminals::terminals!(@ebus_analog $name);
+        $crate::terminals::terminals!(@munch [$($bindings)* $($($crate::terminals::terminals!(@bind $name 4 $ch $tag),)*)?] $($rest)*);
+    };
+    (@munch [$($bindings:tt)*] $name:ident: kbus di $bits:literal $(=> { $($ch:ident: $tag:expr),* $(,)? })?; $($rest:tt)*) => {
+        $crate::terminals::terminals!(@kbus_input $name $bits);
+        $crate::terminals::terminals!(@munch [$($bindings)* $($($crate::terminals::terminals!(@bind $name $bits $ch $tag),)*)?] $($rest)*);
+    };
+    (@munch [$($bindings:tt)*] $name:ident: kbus do $bits:literal $(=> { $($ch:ident: $tag:expr),* $(,)? })?; $($rest:tt)*) => {
+        $crate::terminals::terminals!(@kbus_output $name $bits);
+        $crate::terminals::terminals!(@munch [$($bindings)* $($($crate::terminals::terminals!(@bind $name $bits $ch $tag),)*)?] $($rest)*);
+    };
+    (@munch [$($bindings:tt)*] $name:ident: intelligent, $inputs:literal + $outputs:literal bytes; $($rest:tt)*) => {
+        $crate::terminals::terminals!(@kbus_intelligent $name $inputs $outputs);
+        $crate::terminals::terminals!(@munch [$($bindings)*] $($rest)*);
+    };
+
+    (@bind $name:ident $channels:literal $ch:ident $tag:expr) => {
+        $crate::terminals::TagBinding {
+            terminal: stringify!($name),
+            channels: $channels,
+            channel: $crate::term_cfg::TermChannel::$ch,
+            tag: $tag,
+        }
+    };
+
+    (@ebus_input $name:ident $bits:literal) => { ::paste::paste! {
+        pub const [<$name _IMG_LEN_BITS>]: u8 = $bits;
+
+        pub static [<TERM_ $name>]: ::std::sync::LazyLock<::std::sync::Arc<::std::sync::RwLock<$crate::term_cfg::DITerm>>> =
+            ::std::sync::LazyLock::new(|| ::std::sync::Arc::new(::std::sync::RwLock::new($crate::term_cfg::DITerm::new($bits))));
+
+        pub fn [<$name:lower _handler>](dst: &::std::sync::Arc<::std::sync::RwLock<$crate::term_cfg::DITerm>>, bits: &::bitvec::slice::BitSlice<u8, ::bitvec::order::Lsb0>) {
+            let mut rw_guard = dst.write().expect(concat!("Acquire TERM_", stringify!($name), " read/write guard"));
+            $crate::terminals::copy_bits(&mut rw_guard.values, bits, stringify!($name));
+        }
+    }};
+
+    (@ebus_output $name:ident $bits:literal) => { ::paste::paste! {
+        pub const [<$name _IMG_LEN_BITS>]: u8 = $bits;
+
+        pub static [<TERM_ $name>]: ::std::sync::LazyLock<::std::sync::Arc<::std::sync::RwLock<$crate::term_cfg::DOTerm>>> =
+            ::std::sync::LazyLock::new(|| ::std::sync::Arc::new(::std::sync::RwLock::new($crate::term_cfg::DOTerm::new($bits))));
+
+        pub fn [<$name:lower _handler>](dst: &mut ::bitvec::slice::BitSlice<u8, ::bitvec::order::Lsb0>, bits: &::std::sync::Arc<::std::sync::RwLock<$crate::term_cfg::DOTerm>>) {
+            let rd_guard = bits.read().expect(concat!("Acquire TERM_", stringify!($name), " read guard")); // RO access
+            $crate::terminals::copy_bits(dst, &rd_guard.values, stringify!($name));
+        }
+    }};
+
+    // 4 channels of status (16 bits) and value (16 bits) each
+    (@ebus_analog $name:ident) => { ::paste::paste! {
+        pub const [<$name _IMG_LEN_BITS>]: u8 = 4 * 32;
+        pub const [<$name _NUM_CHANNELS>]: u8 = 4;
+
+        pub static [<TERM_ $name>]: ::std::sync::LazyLock<::std::sync::Arc<::std::sync::RwLock<$crate::term_cfg::AITerm4Ch>>> =
+            ::std::sync::LazyLock::new(|| ::std::sync::Arc::new(::std::sync::RwLock::new($crate::term_cfg::AITerm4Ch::new())));
+
+        pub fn [<$name:lower _handler>](dst: &::std::sync::Arc<::std::sync::RwLock<$crate::term_cfg::AITerm4Ch>>, bits: &::bitvec::slice::BitSlice<u8, ::bitvec::order::Lsb0>, channel: $crate::term_cfg::TermChannel) {
+            let mut rw_guard = dst.write().expect(concat!("Acquire TERM_", stringify!($name), " read/write guard"));
+            $crate::terminals::refresh_analog_channel(&mut rw_guard, bits, channel);
+        }
+    }};
+
+    (@kbus_input $name:ident $bits:literal) => { ::paste::paste! {
+        pub const [<$name _IMG_LEN_BITS>]: u8 = $bits;
+
+        pub static [<TERM_ $name>]: ::std::sync::LazyLock<::std::sync::Arc<::std::sync::RwLock<$crate::term_cfg::KBusSubDevice>>> =
+            ::std::sync::LazyLock::new(|| ::std::sync::Arc::new(::std::sync::RwLock::new(
+                $crate::terminals::kbus_subdevice(stringify!($name), false, $bits, $crate::term_cfg::KBusTerminalGender::Input)
+            )));
+
+        pub fn [<$name:lower _handler>](dst: &::std::sync::Arc<::std::sync::RwLock<$crate::term_cfg::KBusSubDevice>>, bits: &::bitvec::slice::BitSlice<u8, ::bitvec::order::Lsb0>) {
+            let mut rw_guard = dst.write().expect(concat!("Acquire TERM_", stringify!($name), " read/write guard"));
+            $crate::terminals::copy_bits(rw_guard.rx_data.as_mut().unwrap(), bits, stringify!($name));
+        }
+    }};
+
+    (@kbus_output $name:ident $bits:literal) => { ::paste::paste! {
+        pub const [<$name _IMG_LEN_BITS>]: u8 = $bits;
+
+        pub static [<TERM_ $name>]: ::std::sync::LazyLock<::std::sync::Arc<::std::sync::RwLock<$crate::term_cfg::KBusSubDevice>>> =
+            ::std::sync::LazyLock::new(|| ::std::sync::Arc::new(::std::sync::RwLock::new(
+                $crate::terminals::kbus_subdevice(stringify!($name), false, $bits, $crate::term_cfg::KBusTerminalGender::Output)
+            )));
+
+        pub fn [<$name:lower _handler>](dst: &mut ::bitvec::slice::BitSlice<u8, ::bitvec::order::Lsb0>, bits: &::std::sync::Arc<::std::sync::RwLock<$crate::term_cfg::KBusSubDevice>>) {
+            let rd_guard = bits.read().expect(concat!("Acquire TERM_", stringify!($name), " read guard")); // RO access
+            $crate::terminals::copy_bits(dst, rd_guard.tx_data.as_ref().unwrap(), stringify!($name));
+        }
+    }};
+
+    // size_in_bits counts both directions, inputs and outputs are half of it each
+    (@kbus_intelligent $name:ident $inputs:literal $outputs:literal) => { ::paste::paste! {
+        pub const [<$name _IMG_LEN_BITS>]: u8 = ($inputs + $outputs) * 8;
+
+        pub static [<TERM_ $name>]: ::std::sync::LazyLock<::std::sync::Arc<::std::sync::RwLock<$crate::term_cfg::KBusSubDevice>>> =
+            ::std::sync::LazyLock::new(|| ::std::sync::Arc::new(::std::sync::RwLock::new(
+                $crate::terminals::kbus_subdevice(stringify!($name), true, ($inputs + $outputs) * 8, $crate::term_cfg::KBusTerminalGender::Enby)
+            )));
+
+        pub fn [<$name:lower _output_handler>](dst: &mut ::bitvec::slice::BitSlice<u8, ::bitvec::order::Lsb0>, bits: &::std::sync::Arc<::std::sync::RwLock<$crate::term_cfg::KBusSubDevice>>) {
+            let rd_guard = bits.read().expect(concat!("Acquire TERM_", stringify!($name), " read guard")); // RO access
+            $crate::terminals::copy_bits(dst, rd_guard.tx_data.as_ref().unwrap(), stringify!($name));
+        }
+
+        pub fn [<$name:lower _input_handler>](dst: &::std::sync::Arc<::std::sync::RwLock<$crate::term_cfg::KBusSubDevice>>, bits: &::bitvec::slice::BitSlice<u8, ::bitvec::order::Lsb0>) {
+            let mut rw_guard = dst.write().expect(concat!("Acquire TERM_", stringify!($name), " read/write guard"));
+            $crate::terminals::copy_bits(rw_guard.rx_data.as_mut().unwrap(), bits, stringify!($name));
+        }
+    }};
+
+    (@munch [$($bindings:tt)*] $($rest:tt)+) => {
+        compile_error!(concat!("terminals!: can't read the declaration starting at `", stringify!($($rest)+), "`"));
+    };
+
+    ($($declarations:tt)*) => {
+        $crate::terminals::terminals!(@munch [] $($declarations)*);
+    };
+}
+pub(crate) use terminals;
+
+// What all digital handlers do, the terminal's channels and its part of the process image have to be the same length
+pub(crate) fn copy_bits(dst: &mut BitSlice<u8, Lsb0>, src: &BitSlice<u8, Lsb0>, terminal: &str) {
+    if dst.len() != src.len() {
+        panic!("{}: {} bits don't fit the {} they're copied into", terminal, src.len(), dst.len());
+    }
+    dst.copy_from_bitslice(src);
+}
+
+// hr_name is the number in the name, "KL6581" -> 6581
+pub(crate) fn kbus_subdevice(name: &str, intelligent: bool, size_in_bits: u8, gender: KBusTerminalGender) -> KBusSubDevice {
+    let image_bits = (if gender == KBusTerminalGender::Enby { size_in_bits / 2 } else { size_in_bits }) as usize;
+    let inputs = gender != KBusTerminalGender::Output;
+    let outputs = gender != KBusTerminalGender::Input;
+    KBusSubDevice {
+        hr_name: name.trim_start_matches(|c: char| c.is_ascii_alphabetic()).parse().expect("terminal named KL<number>"),
+        intelligent,
+        size_in_bits,
+        is_kl1212: false,
+        gender,
+        tx_data: outputs.then(|| BitVec::<u8, Lsb0>::repeat(false, image_bits)), // Capacity must match output process image size
+        rx_data: inputs.then(|| BitVec::<u8, Lsb0>::repeat(false, image_bits)), // Capacity must match input process image size
+    }
+}
+
+// One channel of an EL30xx 4 channel analog input terminal from its whole input image. The status word is only taken
+// over, and the value only read, when the TxPDO toggle says the terminal updated them.
+pub(crate) fn refresh_analog_channel(term: &mut AITerm4Ch, bits: &BitSlice<u8, Lsb0>, channel: TermChannel) {
+    let channel = channel as usize;
+    let bits = &bits[32*(channel - 1)..32*channel];
+    let AITerm4Ch { ch_values, ch_statuses, .. } = term;
+    let (value, status) = match channel {
+        1 => (&mut ch_values.ch1, &mut ch_statuses.ch1),
+        2 => (&mut ch_values.ch2, &mut ch_statuses.ch2),
+        3 => (&mut ch_values.ch3, &mut ch_statuses.ch3),
+        4 => (&mut ch_values.ch4, &mut ch_statuses.ch4),
+        _ => unreachable!(),
+    };
+
+    status.txpdo_toggle = bits[15]; // The TxPDO toggle is toggled by the slave when the data of the associated TxPDO is updated.
+    if !status.txpdo_toggle {
+        return;
+    }
+    value.copy_from_bitslice(&bits[16..32]);
+    status.txpdo_state = bits[14];
+    status.err         = bits[6];
+    status.limit2      = bits[4..6].load_le::<u8>();
+    status.limit1      = bits[2..4].load_le::<u8>();
+    status.overrange   = bits[1];
+    status.underrange  = bits[0];
+}
@@ -0,0 +1,93 @@
+use crate::compression::{CompressionSettings, Compressor};
+use crate::history::{Aggregate, History};
+use crate::record::Sample;
+
+/// Where a historized tag's samples go and are read back from, for OPC UA HistoryRead and the like. `History` keeps
+/// the most recent samples in memory, `SqlHistory` (`sql` feature) keeps every sample in an SQLite file.
+///
+/// Reads return samples oldest first, errors are the backend's own (disk full, database locked...).
+pub trait HistoryBackend: Send {
+    fn name(&self) -> &str;
+
+    fn record(&mut self, sample: Sample) -> Result<(), String>;
+
+    /// Samples of `tag` with `start <= ts_ms <= end`, see `History::read_raw` for `max_values` and `bounds`
+    fn read_raw(&self, tag: &str, start_ms: u64, end_ms: u64, max_values: usize, bounds: bool) -> Result<Vec<Sample>, String>;
+
+    /// See `History::read_processed`, computed from read_raw unless the backend can do better
+    fn read_processed(&self, tag: &str, start_ms: u64, end_ms: u64, interval_ms: u64, aggregate: Aggregate) -> Result<Vec<(u64, Option<f64>)>, String> {
+        let raw = self.read_raw(tag, start_ms, end_ms, 0, false)?;
+        Ok(aggregate_intervals(&raw, start_ms, end_ms, interval_ms, aggregate))
+    }
+}
+
+/// One value per `interval_ms` wide interval from `start` up to `end` over `raw` (oldest first, within start..=end),
+/// keyed by the interval's start. None for intervals without any samples, 0 for `interval_ms` means one interval.
+pub fn aggregate_intervals(raw: &[Sample], start_ms: u64, end_ms: u64, interval_ms: u64, aggregate: Aggregate) -> Vec<(u64, Option<f64>)> {
+    let interval_ms = if interval_ms == 0 { end_ms.saturating_sub(start_ms).max(1) } else { interval_ms };
+
+    let mut out = Vec::new();
+    let mut at = 0;
+    let mut interval_start = start_ms;
+    while interval_start < end_ms {
+        let interval_end = interval_start.saturating_add(interval_ms).min(end_ms);
+        let len = raw[at..].partition_point(|s| s.ts_ms < interval_end);
+        let values: Vec<f64> = raw[at..at + len].iter().map(|s| s.value).collect();
+        at += len;
+
+        let value = (!values.is_empty()).then(|| match aggregate {
+            Aggregate::Average => values.iter().sum::<f64>() / values.len() as f64,
+            Aggregate::Minimum => values.iter().copied().fold(f64::INFINITY, f64::min),
+            Aggregate::Maximum => values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            Aggregate::Count => values.len() as f64,
+        });
+        out.push((interval_start, value));
+        interval_start = interval_end;
+    }
+    out
+}
+
+impl HistoryBackend for History {
+    fn name(&self) -> &str {
+        "memory"
+    }
+
+    fn record(&mut self, sample: Sample) -> Result<(), String> {
+        History::record(self, sample);
+        Ok(())
+    }
+
+    fn read_raw(&self, tag: &str, start_ms: u64, end_ms: u64, max_values: usize, bounds: bool) -> Result<Vec<Sample>, String> {
+        Ok(History::read_raw(self, tag, start_ms, end_ms, max_values, bounds))
+    }
+}
+
+/// `store` recording only what compression lets through, reads go to `store` as they are
+pub struct Compressed {
+    store: Box<dyn HistoryBackend>,
+    compressor: Compressor,
+}
+
+impl Compressed {
+    pub fn new(store: Box<dyn HistoryBackend>, settings: CompressionSettings) -> Self {
+        Self { store, compressor: Compressor::new(settings) }
+    }
+}
+
+impl HistoryBackend for Compressed {
+    fn name(&self) -> &str {
+        self.store.name()
+    }
+
+    fn record(&mut self, sample: Sample) -> Result<(), String> {
+        self.compressor.sample(sample).into_iter().try_for_each(|kept| self.store.record(kept))
+    }
+
+    fn read_raw(&self, tag: &str, start_ms: u64, end_ms: u64, max_values: usize, bounds: bool) -> Result<Vec<Sample>, String> {
+        self.store.read_raw(tag, start_ms, end_ms, max_values, bounds)
+    }
+
+    fn read_processed(&self, tag: &str, start_ms: u64, end_ms: u64, interval_ms: u64, aggregate: Aggregate) -> Result<Vec<(u64, Option<f64>)>, String> {
+        self.store.read_processed(tag, start_ms, end_ms, interval_ms, aggregate)
+    }
+}
@@ -0,0 +1,125 @@
+use crate::compression::{CompressionSettings, Compressor};
+use crate::record::Record;
+use crate::sink::Sink;
+use crate::store_fwd::StoreAndForward;
+use std::io;
+use std::time::{Duration, Instant};
+
+/// Collects records one at a time and hands them to a `StoreAndForward` in batches, for sinks where every write is a
+/// round trip (InfluxDB, SQL servers). A batch goes out once `max_records` are pending or `interval` has passed since
+/// the last one, whichever comes first.
+///
+/// Call `poll` regularly even when nothing is pushed, it's what flushes a quiet stream and retries the on-disk backlog.
+pub struct Batcher<S: Sink> {
+    forward: StoreAndForward<S>,
+    interval: Duration,
+    max_records: usize,
+    pending: Vec<Record>,
+    last_flush: Instant,
+    compressor: Option<Compressor>,
+}
+
+impl<S: Sink> Batcher<S> {
+    pub fn new(forward: StoreAndForward<S>, interval: Duration, max_records: usize) -> Self {
+        Self { forward, interval, max_records: max_records.max(1), pending: Vec::new(), last_flush: Instant::now(), compressor: None }
+    }
+
+    /// Samples pushed from now on are compressed per `settings` before they're batched
+    pub fn with_compression(mut self, settings: CompressionSettings) -> Self {
+        self.compressor = Some(Compressor::new(settings));
+        self
+    }
+
+    pub fn forward(&self) -> &StoreAndForward<S> {
+        &self.forward
+    }
+
+    pub fn push(&mut self, record: Record) -> io::Result<()> {
+        match &mut self.compressor {
+            Some(compressor) => self.pending.extend(compressor.apply(vec![record])),
+            None => self.pending.push(record),
+        }
+        if self.pending.len() >= self.max_records {
+            self.send()?;
+        }
+        Ok(())
+    }
+
+    pub fn poll(&mut self) -> io::Result<()> {
+        if self.last_flush.elapsed() < self.interval {
+            return Ok(());
+        }
+        if self.pending.is_empty() && self.forward.has_backlog() {
+            self.last_flush = Instant::now();
+            self.forward.replay();
+            return Ok(());
+        }
+        self.send()
+    }
+
+    /// Hand whatever is pending to the sink now, samples compression still holds back included, e.g. before shutting
+    /// down
+    pub fn flush(&mut self) -> io::Result<()> {
+        if let Some(compressor) = &mut self.compressor {
+            self.pending.extend(compressor.flush());
+        }
+        self.send()
+    }
+
+    fn send(&mut self) -> io::Result<()> {
+        self.last_flush = Instant::now();
+        self.forward.push(std::mem::take(&mut self.pending))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression::Compression;
+    use crate::record::Sample;
+    use crate::store_fwd::RetentionLimits;
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    struct Stored(Vec<u64>);
+
+    impl Sink for Stored {
+        fn name(&self) -> &str {
+            "stored"
+        }
+
+        fn write(&mut self, batch: &[Record]) -> Result<(), String> {
+            self.0.extend(batch.iter().map(Record::ts_ms));
+            Ok(())
+        }
+    }
+
+    // name, compression, samples stored of a level creeping up 1/64 a second for 10 minutes
+    type Case = (&'static str, Option<Compression>, usize);
+
+    #[test]
+    fn slow_tag_is_compressed() {
+        let cases: &[Case] = &[
+            ("uncompressed", None, 600),
+            ("absolute deadband", Some(Compression::AbsoluteDeadband { deadband: 1.0, max_interval_ms: 0 }), 10),
+            ("swinging door", Some(Compression::SwingingDoor { deviation: 0.1, max_interval_ms: 0 }), 2),
+            ("max interval", Some(Compression::AbsoluteDeadband { deadband: 100.0, max_interval_ms: 60_000 }), 10),
+        ];
+        let dir = std::env::temp_dir().join(format!("gipop_batch_{}", std::process::id()));
+        for (name, compression, stored) in cases {
+            let forward = StoreAndForward::new(Stored::default(), &dir, RetentionLimits::default()).unwrap();
+            let mut batcher = Batcher::new(forward, Duration::from_secs(60), 100);
+            if let Some(mode) = compression {
+                batcher = batcher.with_compression(CompressionSettings { default: Compression::None, tags: HashMap::from([("level".into(), mode.clone())]) });
+            }
+            for n in 0..600 {
+                batcher.push(Record::Sample(Sample { tag: "level".into(), ts_ms: n * 1000, value: n as f64 / 64.0 })).unwrap();
+            }
+            batcher.flush().unwrap();
+            let sink = &batcher.forward().sink().0;
+            assert_eq!(sink.len(), *stored, "{}", name);
+            assert!(sink.is_sorted() && sink.first() == Some(&0), "{}: {:?}", name, sink);
+        }
+        _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,265 @@
+// `[compression]` section of gipop.toml, shared by everything that stores or publishes samples: the gateway's InfluxDB
+// batches (batch.rs), trend files (file_log.rs) and MQTT publishes, and the OPC UA server's history backends
+// (backend::Compressed). Each runs its samples through a Compressor of its own before they reach the sink.
+//
+// [compression.default]         # every tag without its own entry, mode "none" (the default) keeps every sample
+// mode = "absolute_deadband"    # or "percent_deadband" (percent = ...), "swinging_door" (deviation = ...)
+// deadband = 0.1
+// max_interval_ms = 60000       # a sample at least this often anyway, 0 for never
+//
+// [compression.tags.temperature]
+// mode = "swinging_door"
+// deviation = 0.05
+use crate::record::{Record, Sample};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Per-tag compression applied before samples leave the controller.
+///
+/// `PercentDeadband` is relative to the last stored value, so it behaves like a relative tolerance and
+/// falls back to storing every change around zero.
+/// `SwingingDoor` keeps a sample only when a straight line from the last stored sample can no longer
+/// represent the samples in between within `deviation`.
+/// `max_interval_ms` forces a sample through at least that often (0 = never), so a flatlined tag still
+/// shows up in trends.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum Compression {
+    #[default]
+    None,
+    AbsoluteDeadband { deadband: f64, #[serde(default)] max_interval_ms: u64 },
+    PercentDeadband { percent: f64, #[serde(default)] max_interval_ms: u64 },
+    SwingingDoor { deviation: f64, #[serde(default)] max_interval_ms: u64 },
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct CompressionSettings {
+    #[serde(default)]
+    pub default: Compression,
+    #[serde(default)]
+    pub tags: HashMap<String, Compression>, // tag name -> override
+}
+
+impl CompressionSettings {
+    pub fn for_tag(&self, tag: &str) -> &Compression {
+        self.tags.get(tag).unwrap_or(&self.default)
+    }
+}
+
+#[derive(Default)]
+struct TagState {
+    stored: Option<Sample>, // last sample that was let through
+    held: Option<Sample>,   // swinging door: most recent sample not (yet) stored
+    slope_hi: f64,
+    slope_lo: f64,
+}
+
+pub struct Compressor {
+    settings: CompressionSettings,
+    state: HashMap<String, TagState>,
+}
+
+impl Compressor {
+    pub fn new(settings: CompressionSettings) -> Self {
+        Self { settings, state: HashMap::new() }
+    }
+
+    /// Filter a batch; events pass through untouched, samples are dropped or delayed according to their tag's setting.
+    /// Swinging door emits the *previous* sample when the door closes, so output order per tag is preserved but
+    /// may lag input by one sample.
+    pub fn apply(&mut self, batch: Vec<Record>) -> Vec<Record> {
+        let mut out = Vec::with_capacity(batch.len());
+        for record in batch {
+            match record {
+                Record::Sample(s) => {
+                    for kept in self.sample(s) {
+                        out.push(Record::Sample(kept));
+                    }
+                }
+                event => out.push(event),
+            }
+        }
+        out
+    }
+
+    /// Release samples still held back by swinging door, e.g. on shutdown
+    pub fn flush(&mut self) -> Vec<Record> {
+        let mut out = Vec::new();
+        for state in self.state.values_mut() {
+            if let Some(held) = state.held.take() {
+                state.stored = Some(held.clone());
+                out.push(Record::Sample(held));
+            }
+        }
+        out
+    }
+
+    /// What's let through of one sample: nothing, the sample, or for swinging door the one held back before it
+    /// (and the sample too when max_interval_ms is up)
+    pub fn sample(&mut self, sample: Sample) -> Vec<Sample> {
+        let mode = self.settings.for_tag(&sample.tag).clone();
+        let state = self.state.entry(sample.tag.clone()).or_default();
+
+        let Some(stored) = state.stored.clone() else {
+            state.stored = Some(sample.clone());
+            return vec![sample];
+        };
+
+        match mode {
+            Compression::None => {
+                state.stored = Some(sample.clone());
+                vec![sample]
+            }
+            Compression::AbsoluteDeadband { deadband, max_interval_ms } => {
+                let keep = (sample.value - stored.value).abs() > deadband
+                    || interval_elapsed(&stored, &sample, max_interval_ms);
+                keep_if(state, sample, keep)
+            }
+            Compression::PercentDeadband { percent, max_interval_ms } => {
+                let band = stored.value.abs() * percent / 100.0;
+                let keep = (sample.value - stored.value).abs() > band
+                    || interval_elapsed(&stored, &sample, max_interval_ms);
+                keep_if(state, sample, keep)
+            }
+            Compression::SwingingDoor { deviation, max_interval_ms } => {
+                swinging_door(state, stored, sample, deviation, max_interval_ms)
+            }
+        }
+    }
+}
+
+fn interval_elapsed(stored: &Sample, sample: &Sample, max_interval_ms: u64) -> bool {
+    max_interval_ms != 0 && sample.ts_ms.saturating_sub(stored.ts_ms) >= max_interval_ms
+}
+
+fn keep_if(state: &mut TagState, sample: Sample, keep: bool) -> Vec<Sample> {
+    if keep {
+        state.stored = Some(sample.clone());
+        vec![sample]
+    }
+    else {
+        vec![]
+    }
+}
+
+fn swinging_door(state: &mut TagState, stored: Sample, sample: Sample, deviation: f64, max_interval_ms: u64) -> Vec<Sample> {
+    let dt = sample.ts_ms.saturating_sub(stored.ts_ms) as f64;
+    if dt == 0.0 { // same timestamp as the archived point, nothing to interpolate
+        return vec![];
+    }
+
+    let Some(held) = state.held.clone() else { // first sample after an archive opens the door
+        if interval_elapsed(&stored, &sample, max_interval_ms) { // unless it's been flat for long enough already
+            state.stored = Some(sample.clone());
+            return vec![sample];
+        }
+        state.slope_hi = (sample.value + deviation - stored.value) / dt;
+        state.slope_lo = (sample.value - deviation - stored.value) / dt;
+        state.held = Some(sample);
+        return vec![];
+    };
+
+    if interval_elapsed(&stored, &sample, max_interval_ms) {
+        state.stored = Some(sample.clone());
+        state.held = None;
+        return vec![held, sample];
+    }
+
+    let slope_hi = state.slope_hi.min((sample.value + deviation - stored.value) / dt);
+    let slope_lo = state.slope_lo.max((sample.value - deviation - stored.value) / dt);
+
+    if slope_lo <= slope_hi { // door still open, `held` is representable by the line
+        state.slope_hi = slope_hi;
+        state.slope_lo = slope_lo;
+        state.held = Some(sample);
+        return vec![];
+    }
+
+    // door closed: archive the held point and restart the corridor from it
+    let dt = sample.ts_ms.saturating_sub(held.ts_ms).max(1) as f64;
+    state.slope_hi = (sample.value + deviation - held.value) / dt;
+    state.slope_lo = (sample.value - deviation - held.value) / dt;
+    state.stored = Some(held.clone());
+    state.held = Some(sample);
+    vec![held]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(ts_ms: u64, value: f64) -> Sample {
+        Sample { tag: "t".into(), ts_ms, value }
+    }
+
+    // What a Compressor with `mode` for every tag lets through of `input`, (ts_ms, value) pairs, flushed at the end
+    fn kept(mode: Compression, input: &[(u64, f64)]) -> Vec<(u64, f64)> {
+        let mut compressor = Compressor::new(CompressionSettings { default: mode, tags: HashMap::new() });
+        let batch = input.iter().map(|&(ts_ms, value)| Record::Sample(sample(ts_ms, value))).collect();
+        let mut out = compressor.apply(batch);
+        out.extend(compressor.flush());
+        out.into_iter().map(|record| match record {
+            Record::Sample(s) => (s.ts_ms, s.value),
+            Record::Event(e) => panic!("unexpected event {:?}", e),
+        }).collect()
+    }
+
+    // name, mode, samples in, samples kept
+    type Case = (&'static str, Compression, &'static [(u64, f64)], &'static [(u64, f64)]);
+
+    #[test]
+    fn compression_table() {
+        let absolute = |deadband, max_interval_ms| Compression::AbsoluteDeadband { deadband, max_interval_ms };
+        let percent = |percent, max_interval_ms| Compression::PercentDeadband { percent, max_interval_ms };
+        let door = |deviation, max_interval_ms| Compression::SwingingDoor { deviation, max_interval_ms };
+        let cases: &[Case] = &[
+            // first sample of a tag always goes through, whatever the mode
+            ("none keeps all", Compression::None, &[(0, 1.0), (10, 1.0)], &[(0, 1.0), (10, 1.0)]),
+            ("absolute first", absolute(5.0, 0), &[(0, 1.0)], &[(0, 1.0)]),
+            ("percent first", percent(10.0, 0), &[(0, 1.0)], &[(0, 1.0)]),
+            ("door first", door(1.0, 0), &[(0, 1.0)], &[(0, 1.0)]),
+            // deadbands compare against the last stored value, not the last sample
+            ("absolute band", absolute(1.0, 0),
+                &[(0, 0.0), (10, 0.5), (20, 1.0), (30, 1.5), (40, 0.2)],
+                &[(0, 0.0), (30, 1.5), (40, 0.2)]),
+            ("percent band", percent(10.0, 0),
+                &[(0, 100.0), (10, 105.0), (20, 111.0), (30, 105.0), (40, 99.0)],
+                &[(0, 100.0), (20, 111.0), (40, 99.0)]),
+            ("percent around zero", percent(10.0, 0), &[(0, 0.0), (10, 0.0), (20, 0.1)], &[(0, 0.0), (20, 0.1)]),
+            // max_interval_ms lets a flat signal through
+            ("absolute interval", absolute(1.0, 100),
+                &[(0, 0.0), (50, 0.0), (100, 0.0), (150, 0.0)],
+                &[(0, 0.0), (100, 0.0)]),
+            // a straight line stays inside the door, only its end comes out on flush
+            ("door line", door(0.5, 0),
+                &[(0, 0.0), (10, 1.0), (20, 2.0), (30, 3.0)],
+                &[(0, 0.0), (30, 3.0)]),
+            // the slope changes at 20: the door closes at 30 and archives the corner
+            ("door slope", door(0.1, 0),
+                &[(0, 0.0), (10, 1.0), (20, 2.0), (30, 2.0), (40, 2.0)],
+                &[(0, 0.0), (20, 2.0), (40, 2.0)]),
+            ("door interval with held sample", door(0.5, 100),
+                &[(0, 0.0), (50, 0.0), (100, 0.0)],
+                &[(0, 0.0), (50, 0.0), (100, 0.0)]),
+            // flat after an archive: the next sample is past the interval before any door opened
+            ("door interval after archive", door(0.5, 100),
+                &[(0, 0.0), (150, 0.0), (300, 0.0)],
+                &[(0, 0.0), (150, 0.0), (300, 0.0)]),
+        ];
+        for (name, mode, input, expected) in cases {
+            assert_eq!(kept(mode.clone(), input), expected.to_vec(), "{}", name);
+        }
+    }
+
+    // A sample past max_interval_ms goes out with the batch it came in, not once a later sample closes the door
+    #[test]
+    fn door_interval_is_not_held_back() {
+        let mut compressor = Compressor::new(CompressionSettings {
+            default: Compression::SwingingDoor { deviation: 0.5, max_interval_ms: 100 },
+            tags: HashMap::new(),
+        });
+        assert_eq!(compressor.apply(vec![Record::Sample(sample(0, 0.0))]), vec![Record::Sample(sample(0, 0.0))]);
+        assert_eq!(compressor.apply(vec![Record::Sample(sample(150, 0.0))]), vec![Record::Sample(sample(150, 0.0))]);
+        assert_eq!(compressor.flush(), vec![]);
+    }
+}
@@ -0,0 +1,116 @@
+use crate::record::{EventQuery, StoredEvent};
+use crate::sql::clamp;
+use rusqlite::types::ToSql;
+use rusqlite::{params, params_from_iter, Connection, Row};
+use std::path::Path;
+use std::time::Duration;
+
+/// Alarm transitions, other PLC events and audit entries in an SQLite database, so the alarm and event history
+/// survives restarts. One process records (the gateway), others read the same file with a store of their own (the
+/// gateway's REST API, the OPC UA server's HistoryReadEvents), WAL mode keeps them from holding each other up.
+///
+/// One table, `events(id, ts_ms, kind, source, severity, user, message, clock_synced)`, indexed by time and by
+/// source. `clock_synced` is NULL when nobody knew, databases from before it had it get it added on open. Nothing
+/// goes unless `prune` is called, the recorder's part. The file is created with incremental auto-vacuum so pruning
+/// gives the space back to the file system instead of leaving it to be reused.
+pub struct EventStore {
+    conn: Connection,
+}
+
+impl EventStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let conn = Connection::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        conn.busy_timeout(Duration::from_secs(5)).map_err(|e| e.to_string())?;
+        // auto_vacuum only takes on a database without tables, i.e. one this creates
+        conn.execute_batch(
+            "PRAGMA auto_vacuum = INCREMENTAL;
+             PRAGMA journal_mode = WAL;
+             PRAGMA synchronous = NORMAL;
+             CREATE TABLE IF NOT EXISTS events (
+                 id INTEGER PRIMARY KEY AUTOINCREMENT, -- never reused, not even after pruning everything
+                 ts_ms INTEGER NOT NULL,
+                 kind TEXT NOT NULL,
+                 source TEXT NOT NULL,
+                 severity INTEGER NOT NULL,
+                 user TEXT NOT NULL,
+                 message TEXT NOT NULL,
+                 clock_synced INTEGER
+             );
+             CREATE INDEX IF NOT EXISTS events_by_time ON events (ts_ms);
+             CREATE INDEX IF NOT EXISTS events_by_source ON events (source, ts_ms);",
+        ).map_err(|e| format!("Failed to set up {}: {}", path.display(), e))?;
+        let has_clock_synced: bool = conn
+            .query_row("SELECT COUNT(*) > 0 FROM pragma_table_info('events') WHERE name = 'clock_synced'", [], |row| row.get(0))
+            .map_err(|e| e.to_string())?;
+        if !has_clock_synced {
+            conn.execute_batch("ALTER TABLE events ADD COLUMN clock_synced INTEGER;")
+                .map_err(|e| format!("Failed to upgrade {}: {}", path.display(), e))?;
+        }
+        Ok(Self { conn })
+    }
+
+    /// Stores `event` (its `id` is ignored), returns the id it got
+    pub fn record(&self, event: &StoredEvent) -> Result<i64, String> {
+        self.conn
+            .prepare_cached("INSERT INTO events (ts_ms, kind, source, severity, user, message, clock_synced) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)")
+            .and_then(|mut insert| {
+                insert.insert(params![clamp(event.ts_ms), event.kind, event.source, event.severity, event.user, event.message, event.clock_synced])
+            })
+            .map_err(|e| e.to_string())
+    }
+
+    /// The events `query` asks for, by time (then by id, events of the same millisecond stay in order)
+    pub fn query(&self, query: &EventQuery) -> Result<Vec<StoredEvent>, String> {
+        let mut sql = "SELECT id, ts_ms, kind, source, severity, user, message, clock_synced FROM events WHERE ts_ms >= ? AND ts_ms <= ? AND severity >= ?".to_owned();
+        let (start_ms, end_ms) = (clamp(query.start_ms), clamp(query.end_ms));
+        let mut args: Vec<&dyn ToSql> = vec![&start_ms, &end_ms, &query.min_severity];
+        if !query.kinds.is_empty() {
+            sql += &format!(" AND kind IN ({})", vec!["?"; query.kinds.len()].join(", "));
+            args.extend(query.kinds.iter().map(|kind| kind as &dyn ToSql));
+        }
+        if let Some(source) = &query.source {
+            sql += " AND source = ?";
+            args.push(source);
+        }
+        sql += if query.newest_first { " ORDER BY ts_ms DESC, id DESC" } else { " ORDER BY ts_ms ASC, id ASC" };
+        let limit = if query.limit == 0 { -1 } else { query.limit as i64 }; // -1: no limit
+        sql += " LIMIT ?";
+        args.push(&limit);
+
+        let mut select = self.conn.prepare(&sql).map_err(|e| e.to_string())?;
+        let rows = select.query_map(params_from_iter(args), stored_event).map_err(|e| e.to_string())?;
+        rows.collect::<Result<_, _>>().map_err(|e| e.to_string())
+    }
+
+    /// Deletes events older than `max_age` and the oldest beyond `max_events` (0 for no limit), returns how many
+    pub fn prune(&self, max_age: Option<Duration>, max_events: u64) -> Result<usize, String> {
+        let mut deleted = 0;
+        if let Some(max_age) = max_age {
+            let cutoff = clamp(crate::record::now_ms().saturating_sub(max_age.as_millis() as u64));
+            deleted += self.conn.execute("DELETE FROM events WHERE ts_ms < ?1", params![cutoff]).map_err(|e| e.to_string())?;
+        }
+        if max_events > 0 {
+            deleted += self.conn
+                .execute("DELETE FROM events WHERE id <= (SELECT id FROM events ORDER BY id DESC LIMIT 1 OFFSET ?1)", params![clamp(max_events)])
+                .map_err(|e| e.to_string())?;
+        }
+        if deleted > 0 {
+            self.conn.execute_batch("PRAGMA incremental_vacuum;").map_err(|e| e.to_string())?;
+        }
+        Ok(deleted)
+    }
+}
+
+fn stored_event(row: &Row) -> rusqlite::Result<StoredEvent> {
+    Ok(StoredEvent {
+        id: row.get(0)?,
+        ts_ms: row.get::<_, i64>(1)?.max(0) as u64,
+        kind: row.get(2)?,
+        source: row.get(3)?,
+        severity: row.get(4)?,
+        user: row.get(5)?,
+        message: row.get(6)?,
+        clock_synced: row.get(7)?,
+    })
+}
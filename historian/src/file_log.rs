@@ -0,0 +1,305 @@
+use crate::compression::{CompressionSettings, Compressor};
+use crate::record::Sample;
+use gipop_shm::tags::iso_time;
+use serde::Deserialize;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+#[cfg(feature = "parquet")]
+use std::sync::Arc;
+
+/// How a `FileLogger` writes its files. Parquet needs the `parquet` feature.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileFormat {
+    #[default]
+    Csv,
+    Parquet,
+}
+
+impl FileFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            FileFormat::Csv => "csv",
+            FileFormat::Parquet => "parquet",
+        }
+    }
+}
+
+/// When a `FileLogger` starts a new file, on UTC boundaries
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rotation {
+    Hourly,
+    #[default]
+    Daily,
+}
+
+impl Rotation {
+    fn period_ms(self) -> u64 {
+        match self {
+            Rotation::Hourly => 3600 * 1000,
+            Rotation::Daily => 24 * 3600 * 1000,
+        }
+    }
+}
+
+/// Rows of tag values at fixed times into one file per hour or day, for trend exports at sites without a database.
+///
+/// Every row is a timestamp plus one column per tag, `None` leaves the cell empty (bad quality, no value yet).
+/// Files are named `<prefix>_<yyyymmdd>.csv` (`_<yyyymmdd>_<hh>` when hourly) by the UTC time of their first row.
+/// Whenever a file is started the ones with the same prefix and format older than `max_age` are deleted.
+///
+/// CSV rows are written as they come in and have an ISO 8601 timestamp column. Parquet rows are held until `flush`
+/// and only readable once the file is closed (rotation, `close` or drop), a crash loses the current file.
+///
+/// With compression a row is only written when a value in it made it through its column's compression or turned good
+/// or bad. Swinging door lets a sample through a row late, that row is written then.
+pub struct FileLogger {
+    dir: PathBuf,
+    prefix: String,
+    format: FileFormat,
+    rotation: Rotation,
+    columns: Vec<String>,
+    max_age: Duration,
+    current: Option<(u64, Writer)>, // period the file is for, in ms since the epoch divided by the period length
+    compressor: Option<Compressor>,
+    last: Option<(u64, Vec<Option<f64>>, bool)>, // compression: the row before, and whether it was written
+}
+
+enum Writer {
+    Csv(BufWriter<File>),
+    #[cfg(feature = "parquet")]
+    Parquet(Box<parquet::arrow::ArrowWriter<File>>, Vec<(u64, Vec<Option<f64>>)>),
+}
+
+impl FileLogger {
+    pub fn new(dir: impl Into<PathBuf>, prefix: &str, format: FileFormat, rotation: Rotation, columns: Vec<String>, max_age: Duration) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        if format == FileFormat::Parquet && !cfg!(feature = "parquet") {
+            return Err(io::Error::other("Parquet files need the historian's `parquet` feature"));
+        }
+        Ok(Self { dir, prefix: prefix.to_owned(), format, rotation, columns, max_age, current: None, compressor: None, last: None })
+    }
+
+    /// Rows from now on are compressed per `settings`, a column's tag is its name
+    pub fn with_compression(mut self, settings: CompressionSettings) -> Self {
+        self.compressor = Some(Compressor::new(settings));
+        self
+    }
+
+    /// One row at `ts_ms`, values in the order of the columns passed to `new`
+    pub fn log(&mut self, ts_ms: u64, values: &[Option<f64>]) -> io::Result<()> {
+        let Some(compressor) = &mut self.compressor else {
+            return self.write(ts_ms, values);
+        };
+        let last = self.last.take();
+        let (mut now, mut late) = (last.is_none(), false);
+        for (i, (column, value)) in self.columns.iter().zip(values).enumerate() {
+            now |= last.as_ref().is_some_and(|(_, before, _)| before.get(i).copied().flatten().is_some() != value.is_some());
+            let Some(value) = *value else { continue };
+            for kept in compressor.sample(Sample { tag: column.clone(), ts_ms, value }) {
+                now |= kept.ts_ms == ts_ms;
+                late |= last.as_ref().is_some_and(|(before_ms, _, _)| kept.ts_ms == *before_ms);
+            }
+        }
+        if late && let Some((before_ms, before, false)) = &last {
+            self.write(*before_ms, before)?;
+        }
+        if now {
+            self.write(ts_ms, values)?;
+        }
+        self.last = Some((ts_ms, values.to_vec(), now));
+        Ok(())
+    }
+
+    fn write(&mut self, ts_ms: u64, values: &[Option<f64>]) -> io::Result<()> {
+        let period = ts_ms / self.rotation.period_ms();
+        if self.current.as_ref().is_none_or(|(current, _)| *current != period) {
+            self.finish()?;
+            self.current = Some((period, self.open(ts_ms)?));
+            self.enforce_retention();
+        }
+
+        let Some((_, writer)) = &mut self.current else { unreachable!() };
+        match writer {
+            Writer::Csv(file) => {
+                let cells: Vec<String> = values.iter().map(|value| value.map(|v| v.to_string()).unwrap_or_default()).collect();
+                writeln!(file, "{},{}", iso_time(ts_ms), cells.join(","))
+            }
+            #[cfg(feature = "parquet")]
+            Writer::Parquet(_, rows) => {
+                rows.push((ts_ms, values.to_vec()));
+                Ok(())
+            }
+        }
+    }
+
+    /// Push buffered rows to the current file
+    pub fn flush(&mut self) -> io::Result<()> {
+        match &mut self.current {
+            Some((_, Writer::Csv(file))) => file.flush(),
+            #[cfg(feature = "parquet")]
+            Some((_, Writer::Parquet(writer, rows))) => {
+                if rows.is_empty() {
+                    return Ok(());
+                }
+                let batch = record_batch(schema(&self.columns), std::mem::take(rows))?;
+                writer.write(&batch).and_then(|_| writer.flush()).map_err(io::Error::other)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Finish the current file, the next row starts a new one. Writes the row compression still holds back first
+    pub fn close(&mut self) -> io::Result<()> {
+        let held = self.compressor.as_mut().is_some_and(|compressor| !compressor.flush().is_empty());
+        if held && let Some((ts_ms, values, false)) = self.last.take() {
+            self.write(ts_ms, &values)?;
+        }
+        self.finish()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.flush()?;
+        match self.current.take() {
+            Some((_, Writer::Csv(file))) => file.into_inner().map_err(|e| e.into_error())?.sync_data(),
+            #[cfg(feature = "parquet")]
+            Some((_, Writer::Parquet(writer, _))) => (*writer).close().map(|_| ()).map_err(io::Error::other),
+            None => Ok(()),
+        }
+    }
+
+    fn open(&self, ts_ms: u64) -> io::Result<Writer> {
+        let time = iso_time(ts_ms);
+        let date = format!("{}{}{}", &time[0..4], &time[5..7], &time[8..10]);
+        let name = match self.rotation {
+            Rotation::Hourly => format!("{}_{}_{}.{}", self.prefix, date, &time[11..13], self.format.extension()),
+            Rotation::Daily => format!("{}_{}.{}", self.prefix, date, self.format.extension()),
+        };
+        let path = self.dir.join(name);
+        log::info!("File log: writing {}", path.display());
+
+        match self.format {
+            FileFormat::Csv => {
+                // appending keeps what an earlier run wrote to the same file, the header only goes into new ones
+                let fresh = !path.exists();
+                let mut file = BufWriter::new(File::options().create(true).append(true).open(&path)?);
+                if fresh {
+                    let header: Vec<String> = self.columns.iter().map(|column| csv_field(column)).collect();
+                    writeln!(file, "timestamp,{}", header.join(","))?;
+                }
+                Ok(Writer::Csv(file))
+            }
+            #[cfg(feature = "parquet")]
+            FileFormat::Parquet => {
+                let path = unique(&path); // a parquet file can't be appended to
+                let writer = parquet::arrow::ArrowWriter::try_new(File::create(path)?, schema(&self.columns), None).map_err(io::Error::other)?;
+                Ok(Writer::Parquet(Box::new(writer), Vec::new()))
+            }
+            #[cfg(not(feature = "parquet"))]
+            FileFormat::Parquet => unreachable!("checked in new"),
+        }
+    }
+
+    fn enforce_retention(&self) {
+        let Ok(entries) = fs::read_dir(&self.dir) else { return };
+        let now = SystemTime::now();
+        for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+            let ours = path.extension().is_some_and(|ext| ext == self.format.extension())
+                && path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.starts_with(&format!("{}_", self.prefix)));
+            let expired = fs::metadata(&path)
+                .and_then(|m| m.modified())
+                .is_ok_and(|modified| now.duration_since(modified).unwrap_or_default() > self.max_age);
+            if ours && expired {
+                log::info!("File log: deleting {}, past max age", path.display());
+                let _ = fs::remove_file(&path);
+            }
+        }
+    }
+}
+
+impl Drop for FileLogger {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            log::error!("File log: failed to close {}: {}", self.prefix, e);
+        }
+    }
+}
+
+#[cfg(feature = "parquet")]
+fn unique(path: &std::path::Path) -> PathBuf {
+    (1..).map(|n| if n == 1 { path.to_owned() } else { path.with_extension(format!("{}.parquet", n)) })
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+#[cfg(feature = "parquet")]
+fn schema(columns: &[String]) -> Arc<arrow_schema::Schema> {
+    use arrow_schema::{DataType, Field, TimeUnit};
+    let timestamp = Field::new("timestamp", DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())), false);
+    let fields = std::iter::once(timestamp).chain(columns.iter().map(|column| Field::new(column, DataType::Float64, true)));
+    Arc::new(arrow_schema::Schema::new(fields.collect::<Vec<_>>()))
+}
+
+#[cfg(feature = "parquet")]
+fn record_batch(schema: Arc<arrow_schema::Schema>, rows: Vec<(u64, Vec<Option<f64>>)>) -> io::Result<arrow_array::RecordBatch> {
+    use arrow_array::{ArrayRef, Float64Array, TimestampMillisecondArray};
+    let timestamps = TimestampMillisecondArray::from_iter_values(rows.iter().map(|(ts, _)| *ts as i64)).with_timezone("UTC");
+    let mut arrays: Vec<ArrayRef> = vec![Arc::new(timestamps)];
+    for column in 0..schema.fields().len() - 1 {
+        arrays.push(Arc::new(rows.iter().map(|(_, values)| values.get(column).copied().flatten()).collect::<Float64Array>()));
+    }
+    arrow_array::RecordBatch::try_new(schema, arrays).map_err(io::Error::other)
+}
+
+// Quoted if it has to be, tag names may have commas
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression::Compression;
+    use std::collections::HashMap;
+
+    // name, compression, seconds of the rows written
+    type Case = (&'static str, Compression, &'static [u64]);
+
+    // A level creeping up 1/64 a second and a pump that's off and goes bad for two rows, a row a second
+    #[test]
+    fn slow_rows_are_compressed() {
+        let cases: &[Case] = &[
+            ("absolute deadband", Compression::AbsoluteDeadband { deadband: 1.0, max_interval_ms: 0 }, &[0, 65, 100, 102, 130, 195]),
+            ("swinging door", Compression::SwingingDoor { deviation: 0.1, max_interval_ms: 0 }, &[0, 100, 102, 199]),
+        ];
+        for (name, mode, rows) in cases {
+            let dir = std::env::temp_dir().join(format!("gipop_file_log_{}_{}", name.replace(' ', "_"), std::process::id()));
+            _ = fs::remove_dir_all(&dir);
+            let columns = vec!["level".to_owned(), "pump".to_owned()];
+            let settings = CompressionSettings { default: mode.clone(), tags: HashMap::new() };
+            let mut logger = FileLogger::new(&dir, "trend", FileFormat::Csv, Rotation::Daily, columns, Duration::MAX)
+                .unwrap()
+                .with_compression(settings);
+            for n in 0..200 {
+                let pump = (!(100..102).contains(&n)).then_some(0.0);
+                logger.log(n * 1000, &[Some(n as f64 / 64.0), pump]).unwrap();
+            }
+            logger.close().unwrap();
+
+            let text = fs::read_to_string(dir.join("trend_19700101.csv")).unwrap();
+            let written: Vec<&str> = text.lines().skip(1).map(|line| line.split(',').next().unwrap()).collect();
+            let expected: Vec<String> = rows.iter().map(|s| iso_time(s * 1000)).collect();
+            assert_eq!(written, expected, "{}", name);
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+}
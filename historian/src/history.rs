@@ -0,0 +1,80 @@
+use crate::backend::aggregate_intervals;
+use crate::record::{Record, Sample};
+use crate::sink::Sink;
+use std::collections::{HashMap, VecDeque};
+
+/// Aggregates for `History::read_processed`, computed over the raw samples inside each interval
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Aggregate {
+    Average,
+    Minimum,
+    Maximum,
+    Count,
+}
+
+/// Recent samples of every tag kept in memory, for consumers that want to look back (OPC UA history reads,
+/// trends on a local HMI) without a round trip to the real historian.
+///
+/// Each tag keeps at most `capacity` samples, the oldest go first. Samples are expected in timestamp order per tag,
+/// one that's older than the newest stored sample is dropped.
+pub struct History {
+    capacity: usize,
+    tags: HashMap<String, VecDeque<Sample>>,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity: capacity.max(1), tags: HashMap::new() }
+    }
+
+    pub fn record(&mut self, sample: Sample) {
+        let samples = self.tags.entry(sample.tag.clone()).or_default();
+        if samples.back().is_some_and(|last| last.ts_ms > sample.ts_ms) {
+            log::debug!("History: dropping out of order sample of {}", sample.tag);
+            return;
+        }
+        if samples.len() == self.capacity {
+            samples.pop_front();
+        }
+        samples.push_back(sample);
+    }
+
+    /// Raw samples of `tag` with `start <= ts_ms <= end`, oldest first. `max_values` 0 means no limit.
+    /// With `bounds` the last sample before `start` and the first after `end` are included too, when there are any.
+    pub fn read_raw(&self, tag: &str, start_ms: u64, end_ms: u64, max_values: usize, bounds: bool) -> Vec<Sample> {
+        let Some(samples) = self.tags.get(tag) else {
+            return Vec::new();
+        };
+        let from = samples.partition_point(|s| s.ts_ms < start_ms);
+        let to = samples.partition_point(|s| s.ts_ms <= end_ms);
+
+        let from = if bounds { from.saturating_sub(1) } else { from };
+        let to = if bounds { (to + 1).min(samples.len()) } else { to };
+        let limit = if max_values == 0 { usize::MAX } else { max_values };
+
+        samples.range(from..to.max(from)).take(limit).cloned().collect()
+    }
+
+    /// One value per `interval_ms` wide interval from `start` up to `end`, keyed by the interval's start.
+    /// None for intervals without any samples.
+    pub fn read_processed(&self, tag: &str, start_ms: u64, end_ms: u64, interval_ms: u64, aggregate: Aggregate) -> Vec<(u64, Option<f64>)> {
+        let raw = self.read_raw(tag, start_ms, end_ms, 0, false);
+        aggregate_intervals(&raw, start_ms, end_ms, interval_ms, aggregate)
+    }
+}
+
+/// So a History can sit behind the same pipeline as any other sink. Events aren't kept.
+impl Sink for History {
+    fn name(&self) -> &str {
+        "history"
+    }
+
+    fn write(&mut self, batch: &[Record]) -> Result<(), String> {
+        for record in batch {
+            if let Record::Sample(sample) = record {
+                self.record(sample.clone());
+            }
+        }
+        Ok(())
+    }
+}
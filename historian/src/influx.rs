@@ -0,0 +1,121 @@
+use crate::record::{Event, Record, Sample};
+use crate::sink::Sink;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where one tag's samples go in InfluxDB. Tags without a mapping are written to the sink's measurement as
+/// `<measurement>,tag=<name> value=<value>`.
+#[derive(Clone, Debug)]
+pub struct InfluxMapping {
+    pub tag: String,
+    pub measurement: Option<String>, // the sink's measurement if unset
+    pub field: String,
+    pub tags: BTreeMap<String, String>, // tag set of the point, e.g. { area = "1", sensor = "TT101" }
+}
+
+/// Writes batches to InfluxDB through the v2 HTTP write API (`/api/v2/write`, which InfluxDB 1.8+ and 3 also take) as
+/// line protocol with millisecond timestamps. A batch is one request, so InfluxDB takes all of it or none.
+///
+/// Events go to `<measurement>_events` with their source as a tag and the message as a string field. Samples that
+/// aren't finite (NaN, inf) are left out, InfluxDB would reject the whole batch over them.
+pub struct InfluxSink {
+    write_url: String,
+    org: String,
+    bucket: String,
+    token: String,
+    measurement: String,
+    mappings: HashMap<String, InfluxMapping>,
+    agent: ureq::Agent,
+}
+
+impl InfluxSink {
+    /// `url` is the server's base URL, "http://localhost:8086". An empty `token` sends no Authorization header.
+    pub fn new(url: &str, org: &str, bucket: &str, token: &str, measurement: &str, mappings: Vec<InfluxMapping>) -> Self {
+        Self {
+            write_url: format!("{}/api/v2/write", url.trim_end_matches('/')),
+            org: org.to_owned(),
+            bucket: bucket.to_owned(),
+            token: token.to_owned(),
+            measurement: measurement.to_owned(),
+            mappings: mappings.into_iter().map(|mapping| (mapping.tag.clone(), mapping)).collect(),
+            agent: ureq::AgentBuilder::new().timeout(TIMEOUT).build(),
+        }
+    }
+
+    /// The record as a line of line protocol, None if it can't be written
+    pub fn line(&self, record: &Record) -> Option<String> {
+        match record {
+            Record::Sample(sample) => self.sample_line(sample),
+            Record::Event(event) => Some(self.event_line(event)),
+        }
+    }
+
+    fn sample_line(&self, sample: &Sample) -> Option<String> {
+        if !sample.value.is_finite() {
+            log::debug!("Influx: leaving out non-finite sample of {}", sample.tag);
+            return None;
+        }
+        let mut line = match self.mappings.get(&sample.tag) {
+            Some(mapping) => {
+                let mut line = escape_measurement(mapping.measurement.as_deref().unwrap_or(&self.measurement));
+                for (key, value) in &mapping.tags {
+                    line += &format!(",{}={}", escape_key(key), escape_key(value));
+                }
+                line + &format!(" {}=", escape_key(&mapping.field))
+            }
+            None => format!("{},tag={} value=", escape_measurement(&self.measurement), escape_key(&sample.tag)),
+        };
+        line += &format!("{:?} {}", sample.value, sample.ts_ms); // {:?} keeps the ".0", integers would be a different field type
+        Some(line)
+    }
+
+    fn event_line(&self, event: &Event) -> String {
+        format!(
+            "{}_events,source={} message=\"{}\" {}",
+            escape_measurement(&self.measurement),
+            escape_key(&event.source),
+            event.message.replace('\\', "\\\\").replace('"', "\\\""),
+            event.ts_ms,
+        )
+    }
+}
+
+fn escape_measurement(name: &str) -> String {
+    name.replace(',', "\\,").replace(' ', "\\ ")
+}
+
+// tag keys, tag values and field keys
+fn escape_key(key: &str) -> String {
+    key.replace(',', "\\,").replace('=', "\\=").replace(' ', "\\ ")
+}
+
+impl Sink for InfluxSink {
+    fn name(&self) -> &str {
+        "influx"
+    }
+
+    fn write(&mut self, batch: &[Record]) -> Result<(), String> {
+        let body: Vec<String> = batch.iter().filter_map(|record| self.line(record)).collect();
+        if body.is_empty() {
+            return Ok(());
+        }
+
+        let mut request = self.agent.post(&self.write_url)
+            .query("org", &self.org)
+            .query("bucket", &self.bucket)
+            .query("precision", "ms")
+            .set("Content-Type", "text/plain; charset=utf-8");
+        if !self.token.is_empty() {
+            request = request.set("Authorization", &format!("Token {}", self.token));
+        }
+        match request.send_string(&body.join("\n")) {
+            Ok(_) => Ok(()),
+            Err(ureq::Error::Status(status, response)) => {
+                Err(format!("HTTP {}: {}", status, response.into_string().unwrap_or_default()))
+            }
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
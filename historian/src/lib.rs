@@ -0,0 +1,16 @@
+pub mod backend;
+pub mod batch;
+pub mod compression;
+pub mod file_log;
+pub mod history;
+pub mod record;
+pub mod sink;
+pub mod store_fwd;
+#[cfg(feature = "sql")]
+pub mod sql;
+#[cfg(feature = "sql")]
+pub mod event_store;
+#[cfg(feature = "influx")]
+pub mod influx;
+#[cfg(feature = "postgres")]
+pub mod postgres;
@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A single tag value at a point in time
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Sample {
+    pub tag: String,
+    pub ts_ms: u64, // unix epoch, milliseconds
+    pub value: f64,
+}
+
+/// Discrete occurrence, e.g. alarm transition or EnOcean telegram
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    pub ts_ms: u64,
+    pub source: String,
+    pub message: String,
+}
+
+/// What gets handed to a sink. Samples and events share one stream so their relative order survives buffering
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Record {
+    Sample(Sample),
+    Event(Event),
+}
+
+impl Record {
+    pub fn ts_ms(&self) -> u64 {
+        match self {
+            Record::Sample(s) => s.ts_ms,
+            Record::Event(e) => e.ts_ms,
+        }
+    }
+}
+
+/// An event as the event store (`event_store`, `sql` feature) keeps it: alarm transitions, other PLC events and
+/// audit entries, with who and how severe
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StoredEvent {
+    pub id: i64, // rowid, rising with every event stored. 0 until stored
+    pub ts_ms: u64,
+    pub kind: String,   // "alarm", "clear", "ack", "enocean", "audit"...
+    pub source: String, // the alarm tag, empty for events that aren't about one
+    pub severity: u16,  // 1..1000 like OPC UA's
+    pub user: String,   // who did it, for audit entries
+    pub message: String,
+    pub clock_synced: Option<bool>, // whether the PLC's clock was synchronized when it was recorded, None if unknown
+}
+
+/// Which stored events to read back, `EventQuery::default()` is every one oldest first
+#[derive(Clone, Debug, PartialEq)]
+pub struct EventQuery {
+    pub start_ms: u64,
+    pub end_ms: u64, // inclusive
+    pub kinds: Vec<String>, // empty takes every kind
+    pub source: Option<String>,
+    pub min_severity: u16,
+    pub limit: usize, // 0 means no limit
+    pub newest_first: bool, // and the limit keeps the newest
+}
+
+impl Default for EventQuery {
+    fn default() -> Self {
+        Self { start_ms: 0, end_ms: u64::MAX, kinds: Vec::new(), source: None, min_severity: 0, limit: 0, newest_first: false }
+    }
+}
+
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
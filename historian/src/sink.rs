@@ -0,0 +1,10 @@
+use crate::record::Record;
+
+/// Something that ships records off the controller in batches. InfluxSink, and the in-memory and Postgres histories.
+///
+/// `write` must either accept the whole batch or return an error, partial writes are treated as a failure
+/// and the batch will be offered again later by `StoreAndForward`.
+pub trait Sink: Send {
+    fn name(&self) -> &str;
+    fn write(&mut self, batch: &[Record]) -> Result<(), String>;
+}
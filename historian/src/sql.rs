@@ -0,0 +1,85 @@
+use crate::backend::HistoryBackend;
+use crate::record::Sample;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+
+/// Every sample in an SQLite database, for history that outlives restarts and the in-memory window.
+///
+/// One table, `samples(tag, ts_ms, value)`, indexed by tag and time. Kept in WAL mode so readers (HistoryRead,
+/// someone poking at the file with the sqlite3 shell) don't hold up recording.
+pub struct SqlHistory {
+    conn: Connection,
+}
+
+impl SqlHistory {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let conn = Connection::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             PRAGMA synchronous = NORMAL;
+             CREATE TABLE IF NOT EXISTS samples (tag TEXT NOT NULL, ts_ms INTEGER NOT NULL, value REAL NOT NULL);
+             CREATE INDEX IF NOT EXISTS samples_by_tag ON samples (tag, ts_ms);",
+        ).map_err(|e| format!("Failed to set up {}: {}", path.display(), e))?;
+        Ok(Self { conn })
+    }
+
+    // The sample right before `ts_ms` (`before`) or right after it
+    fn bound(&self, tag: &str, ts_ms: u64, before: bool) -> Result<Option<Sample>, String> {
+        let sql = if before {
+            "SELECT ts_ms, value FROM samples WHERE tag = ?1 AND ts_ms < ?2 ORDER BY ts_ms DESC LIMIT 1"
+        } else {
+            "SELECT ts_ms, value FROM samples WHERE tag = ?1 AND ts_ms > ?2 ORDER BY ts_ms ASC LIMIT 1"
+        };
+        self.conn.query_row(sql, params![tag, clamp(ts_ms)], |row| Ok(sample(tag, row.get(0)?, row.get(1)?)))
+            .optional()
+            .map_err(|e| e.to_string())
+    }
+}
+
+// SQLite integers are signed, u64::MAX for "up to the end" doesn't fit. Also event_store's.
+pub(crate) fn clamp(ts_ms: u64) -> i64 {
+    ts_ms.min(i64::MAX as u64) as i64
+}
+
+fn sample(tag: &str, ts_ms: i64, value: f64) -> Sample {
+    Sample { tag: tag.to_owned(), ts_ms: ts_ms.max(0) as u64, value }
+}
+
+impl HistoryBackend for SqlHistory {
+    fn name(&self) -> &str {
+        "sql"
+    }
+
+    fn record(&mut self, sample: Sample) -> Result<(), String> {
+        self.conn
+            .prepare_cached("INSERT INTO samples (tag, ts_ms, value) VALUES (?1, ?2, ?3)")
+            .and_then(|mut insert| insert.execute(params![sample.tag, clamp(sample.ts_ms), sample.value]))
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    fn read_raw(&self, tag: &str, start_ms: u64, end_ms: u64, max_values: usize, bounds: bool) -> Result<Vec<Sample>, String> {
+        let limit = if max_values == 0 { -1 } else { max_values as i64 }; // -1: no limit
+        let mut samples = Vec::new();
+        if bounds && let Some(before) = self.bound(tag, start_ms, true)? {
+            samples.push(before);
+        }
+        let mut select = self.conn
+            .prepare_cached("SELECT ts_ms, value FROM samples WHERE tag = ?1 AND ts_ms >= ?2 AND ts_ms <= ?3 ORDER BY ts_ms ASC LIMIT ?4")
+            .map_err(|e| e.to_string())?;
+        let rows = select
+            .query_map(params![tag, clamp(start_ms), clamp(end_ms), limit], |row| Ok(sample(tag, row.get(0)?, row.get(1)?)))
+            .map_err(|e| e.to_string())?;
+        for row in rows {
+            samples.push(row.map_err(|e| e.to_string())?);
+        }
+        if bounds && let Some(after) = self.bound(tag, end_ms, false)? {
+            samples.push(after);
+        }
+        if max_values != 0 {
+            samples.truncate(max_values);
+        }
+        Ok(samples)
+    }
+}
@@ -0,0 +1,388 @@
+use crate::record::Record;
+use crate::sink::Sink;
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+const SEGMENT_EXT: &str = "jsonl";
+const REPLAY_CHUNK: usize = 500; // records per sink write during replay
+
+/// How much backlog we're willing to keep on disk while a sink is down.
+/// Whichever limit is hit first wins, the oldest segments are dropped.
+#[derive(Clone, Debug)]
+pub struct RetentionLimits {
+    pub max_bytes: u64,
+    pub max_age: Duration,
+    pub segment_bytes: u64, // a new segment file is started once the newest one grows past this
+}
+
+impl Default for RetentionLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes: 64 * 1024 * 1024,
+            max_age: Duration::from_secs(7 * 24 * 3600),
+            segment_bytes: 1024 * 1024,
+        }
+    }
+}
+
+/// Wraps a `Sink` and spools to disk whenever it can't be reached.
+///
+/// Records are always delivered in the order they were pushed: once anything is on disk, new batches are
+/// appended behind it and only reach the sink through `replay()`.
+/// Segments are JSON lines files named by a monotonically increasing sequence number, so a backlog left over
+/// from a previous run is picked up again on startup.
+///
+/// Only the gateway's InfluxDB output runs behind one (gateway/src/influx.rs). MQTT publishes straight to the broker
+/// and the SQL and Postgres histories are written directly, what they miss while unreachable is lost.
+pub struct StoreAndForward<S: Sink> {
+    sink: S,
+    dir: PathBuf,
+    limits: RetentionLimits,
+    segments: VecDeque<u64>, // oldest first
+    next_segment: u64,
+}
+
+impl<S: Sink> StoreAndForward<S> {
+    pub fn new(sink: S, dir: impl Into<PathBuf>, limits: RetentionLimits) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+
+        let mut segments: Vec<u64> = fs::read_dir(&dir)?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let path = entry.path();
+                if path.extension()? != SEGMENT_EXT { return None; }
+                path.file_stem()?.to_str()?.parse::<u64>().ok()
+            })
+            .collect();
+        segments.sort_unstable();
+
+        if !segments.is_empty() {
+            log::info!("Sink {}: found {} buffered segment(s) from a previous run", sink.name(), segments.len());
+        }
+
+        let next_segment = segments.last().map(|s| s + 1).unwrap_or(0);
+
+        Ok(Self {
+            sink,
+            dir,
+            limits,
+            segments: segments.into(),
+            next_segment,
+        })
+    }
+
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    pub fn sink_mut(&mut self) -> &mut S {
+        &mut self.sink
+    }
+
+    /// Hand a batch to the sink, or buffer it if the sink fails or there's still a backlog ahead of it
+    pub fn push(&mut self, batch: Vec<Record>) -> io::Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        if self.segments.is_empty() {
+            match self.sink.write(&batch) {
+                Ok(()) => return Ok(()),
+                Err(e) => log::warn!("Sink {} unreachable, buffering to disk: {}", self.sink.name(), e),
+            }
+        }
+
+        self.spool(&batch)?;
+        self.enforce_retention();
+        self.replay();
+        Ok(())
+    }
+
+    /// Try to forward the on-disk backlog, oldest first. Stops at the first failure.
+    /// Returns the number of records that made it to the sink.
+    pub fn replay(&mut self) -> usize {
+        let mut forwarded = 0;
+
+        while let Some(&seg) = self.segments.front() {
+            let path = self.segment_path(seg);
+            let records = match read_segment(&path) {
+                Ok(r) => r,
+                Err(e) => {
+                    log::error!("Sink {}: dropping unreadable segment {}: {}", self.sink.name(), path.display(), e);
+                    self.remove_front();
+                    continue;
+                }
+            };
+
+            let mut sent = 0;
+            for chunk in records.chunks(REPLAY_CHUNK) {
+                if let Err(e) = self.sink.write(chunk) {
+                    log::debug!("Sink {} still unreachable: {}", self.sink.name(), e);
+                    break;
+                }
+                sent += chunk.len();
+            }
+            forwarded += sent;
+
+            if sent == records.len() {
+                self.remove_front();
+                continue;
+            }
+
+            // partially replayed, keep only what hasn't been delivered so nothing is sent twice
+            if sent > 0
+                && let Err(e) = write_segment(&path, &records[sent..]) {
+                log::error!("Sink {}: failed to rewrite segment {}: {}", self.sink.name(), path.display(), e);
+            }
+            break;
+        }
+
+        if forwarded > 0 {
+            log::info!("Sink {}: replayed {} buffered record(s), {} segment(s) left", self.sink.name(), forwarded, self.segments.len());
+        }
+        forwarded
+    }
+
+    pub fn backlog_bytes(&self) -> u64 {
+        self.segments
+            .iter()
+            .filter_map(|&seg| fs::metadata(self.segment_path(seg)).ok())
+            .map(|m| m.len())
+            .sum()
+    }
+
+    pub fn has_backlog(&self) -> bool {
+        !self.segments.is_empty()
+    }
+
+    fn spool(&mut self, batch: &[Record]) -> io::Result<()> {
+        let reuse_newest = match self.segments.back() {
+            Some(&seg) => fs::metadata(self.segment_path(seg))
+                .map(|m| m.len() < self.limits.segment_bytes)
+                .unwrap_or(false),
+            None => false,
+        };
+
+        if !reuse_newest {
+            self.segments.push_back(self.next_segment);
+            self.next_segment += 1;
+        }
+
+        let path = self.segment_path(*self.segments.back().unwrap());
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        append_records(&mut file, batch)
+    }
+
+    fn enforce_retention(&mut self) {
+        let now = SystemTime::now();
+
+        // age first: a segment's mtime is its last append, so everything in it is at least that old
+        while let Some(&seg) = self.segments.front() {
+            let expired = fs::metadata(self.segment_path(seg))
+                .and_then(|m| m.modified())
+                .map(|t| now.duration_since(t).unwrap_or_default() > self.limits.max_age)
+                .unwrap_or(false);
+            if !expired { break; }
+            log::warn!("Sink {}: dropping segment {} past max age", self.sink.name(), seg);
+            self.remove_front();
+        }
+
+        // never drop the segment currently being appended to
+        while self.segments.len() > 1 && self.backlog_bytes() > self.limits.max_bytes {
+            log::warn!("Sink {}: backlog over {} bytes, dropping oldest segment", self.sink.name(), self.limits.max_bytes);
+            self.remove_front();
+        }
+    }
+
+    fn remove_front(&mut self) {
+        if let Some(seg) = self.segments.pop_front() {
+            let _ = fs::remove_file(self.segment_path(seg));
+        }
+    }
+
+    fn segment_path(&self, seg: u64) -> PathBuf {
+        self.dir.join(format!("{:020}.{}", seg, SEGMENT_EXT))
+    }
+}
+
+fn append_records(file: &mut File, records: &[Record]) -> io::Result<()> {
+    let mut buf = Vec::new();
+    for record in records {
+        serde_json::to_writer(&mut buf, record).map_err(io::Error::other)?;
+        buf.push(b'\n');
+    }
+    file.write_all(&buf)?;
+    file.sync_data() // the whole point is surviving a power cut
+}
+
+fn write_segment(path: &Path, records: &[Record]) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    append_records(&mut file, records)?;
+    fs::rename(tmp, path)
+}
+
+fn read_segment(path: &Path) -> io::Result<Vec<Record>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut records = Vec::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        if line.is_empty() { continue; }
+        match serde_json::from_str::<Record>(&line) {
+            Ok(r) => records.push(r),
+            Err(e) => log::warn!("Skipping corrupt record in {}: {}", path.display(), e), // e.g. torn last line after a crash
+        }
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::Sample;
+
+    // Takes `budget` writes (None: all of them) and fails the rest, keeps the timestamps of what it took
+    #[derive(Default)]
+    struct Flaky {
+        budget: Option<usize>,
+        received: Vec<u64>,
+    }
+
+    impl Flaky {
+        fn down() -> Self {
+            Self { budget: Some(0), received: Vec::new() }
+        }
+    }
+
+    impl Sink for Flaky {
+        fn name(&self) -> &str {
+            "flaky"
+        }
+
+        fn write(&mut self, batch: &[Record]) -> Result<(), String> {
+            match &mut self.budget {
+                Some(0) => return Err("down".into()),
+                Some(budget) => *budget -= 1,
+                None => {}
+            }
+            self.received.extend(batch.iter().map(Record::ts_ms));
+            Ok(())
+        }
+    }
+
+    fn records(ts_ms: impl IntoIterator<Item = u64>) -> Vec<Record> {
+        ts_ms.into_iter().map(|ts_ms| Record::Sample(Sample { tag: "level".into(), ts_ms, value: 1.5 })).collect()
+    }
+
+    fn spool_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("gipop_store_fwd_{}_{}", name, std::process::id()));
+        _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn files(dir: &Path) -> usize {
+        fs::read_dir(dir).unwrap().count()
+    }
+
+    #[test]
+    fn outage_is_replayed_in_order() {
+        let dir = spool_dir("outage");
+        let mut forward = StoreAndForward::new(Flaky::down(), &dir, RetentionLimits::default()).unwrap();
+        forward.push(records([1, 2])).unwrap();
+        forward.push(records([3])).unwrap();
+        assert!(forward.sink().received.is_empty());
+        assert!(forward.has_backlog());
+
+        // still buffered behind the backlog with the sink back, then everything goes out oldest first
+        forward.sink_mut().budget = None;
+        forward.push(records([4])).unwrap();
+        assert_eq!(forward.sink().received, [1, 2, 3, 4]);
+        assert!(!forward.has_backlog());
+        forward.push(records([5])).unwrap();
+        assert_eq!(forward.sink().received, [1, 2, 3, 4, 5]);
+        assert_eq!(files(&dir), 0);
+
+        // a backlog left behind by the last run goes out first on startup
+        forward.sink_mut().budget = Some(0);
+        forward.push(records([6, 7])).unwrap();
+        drop(forward);
+        let mut forward = StoreAndForward::new(Flaky::default(), &dir, RetentionLimits::default()).unwrap();
+        assert_eq!(forward.replay(), 2);
+        assert_eq!(forward.sink().received, [6, 7]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn partial_replay_sends_nothing_twice() {
+        let dir = spool_dir("partial");
+        let mut forward = StoreAndForward::new(Flaky::down(), &dir, RetentionLimits::default()).unwrap();
+        let total = 2 * REPLAY_CHUNK as u64 + 200;
+        forward.push(records(0..total)).unwrap();
+
+        // one chunk through before the sink goes down again, the front segment keeps only the rest
+        forward.sink_mut().budget = Some(1);
+        assert_eq!(forward.replay(), REPLAY_CHUNK);
+        assert_eq!(read_segment(&forward.segment_path(0)).unwrap(), records(REPLAY_CHUNK as u64..total));
+        assert_eq!(forward.replay(), 0);
+
+        forward.sink_mut().budget = None;
+        assert_eq!(forward.replay(), total as usize - REPLAY_CHUNK);
+        assert_eq!(forward.sink().received, (0..total).collect::<Vec<_>>());
+        assert!(!forward.has_backlog());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn retention_drops_the_oldest_segments() {
+        // every push its own segment of one record, all of the same size
+        let line = serde_json::to_vec(&records([10])[0]).unwrap().len() as u64 + 1;
+        let limits = RetentionLimits { max_bytes: 3 * line, max_age: Duration::from_secs(3600), segment_bytes: 1 };
+
+        let dir = spool_dir("max_bytes");
+        let mut forward = StoreAndForward::new(Flaky::down(), &dir, limits.clone()).unwrap();
+        for ts_ms in 10..20 {
+            forward.push(records([ts_ms])).unwrap();
+        }
+        assert_eq!(forward.backlog_bytes(), 3 * line);
+        forward.sink_mut().budget = None;
+        forward.replay();
+        assert_eq!(forward.sink().received, [17, 18, 19]);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let dir = spool_dir("max_age");
+        let mut forward = StoreAndForward::new(Flaky::down(), &dir, limits.clone()).unwrap();
+        forward.push(records([10])).unwrap();
+        forward.push(records([11])).unwrap();
+        let old = SystemTime::now() - limits.max_age - Duration::from_secs(60);
+        File::options().write(true).open(forward.segment_path(0)).unwrap().set_modified(old).unwrap();
+        forward.push(records([12])).unwrap();
+        forward.sink_mut().budget = None;
+        forward.replay();
+        assert_eq!(forward.sink().received, [11, 12]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unreadable_segments_are_skipped() {
+        let dir = spool_dir("unreadable");
+        fs::create_dir_all(&dir).unwrap();
+        let segment = |seg: u64| dir.join(format!("{:020}.{}", seg, SEGMENT_EXT));
+        fs::write(segment(0), b"\xff\xfe not UTF-8\n").unwrap();
+        write_segment(&segment(1), &records([1, 2])).unwrap();
+        OpenOptions::new().append(true).open(segment(1)).unwrap().write_all(b"{\"Sample\":{\"tag\":\"le").unwrap(); // torn
+        write_segment(&segment(2), &records([3])).unwrap();
+        fs::write(dir.join("notes.txt"), "not a segment").unwrap();
+
+        let mut forward = StoreAndForward::new(Flaky::default(), &dir, RetentionLimits::default()).unwrap();
+        assert_eq!(forward.replay(), 3);
+        assert_eq!(forward.sink().received, [1, 2, 3]);
+        assert!(!forward.has_backlog());
+        assert_eq!(files(&dir), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
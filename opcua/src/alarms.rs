@@ -0,0 +1,349 @@
+// PLC alarms as OPC UA Alarms & Conditions. Every alarm tag in the shm directory (TAG_ALARM) becomes an
+// AlarmConditionType object under Objects/Alarms with the usual state variables and an Acknowledge method.
+//
+// The tag says whether the alarm is active, acknowledging is the operator's part and lives here. A condition is
+// retained (shown in alarm lists) while it's active or unacknowledged. Every state change is reported as an event
+// on the Server object.
+//
+// Acknowledgements are passed on to the PLC, which echoes them as events so acknowledging on one consumer (e.g. a
+// web HMI) acknowledges here too.
+//
+// RefreshConditions on the Alarms object does what ConditionRefresh does: RefreshStartEvent, every retained
+// condition again, RefreshEndEvent, so a client that just subscribed gets the current alarm list.
+//
+// With [opcua.history] events_path the Alarms object answers HistoryReadEvents with the alarm history the gateway
+// stored, see event_history.rs.
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use opcua::nodes::{BaseEventType, Event, EventField};
+use opcua::server::address_space::{AccessLevel, MethodBuilder, ObjectBuilder, VariableBuilder};
+use opcua::server::SubscriptionCache;
+use opcua::types::{
+    AttributeId, ByteString, DataTypeId, DataValue, DateTime, EventNotifier, LocalizedText, NodeId, NumericRange, ObjectId,
+    ObjectTypeId, QualifiedName, StatusCode, UAString, Variant,
+};
+use gipop_shm::{Action, RingItem, Subscriber, TagDef, TagValue};
+
+use crate::audit;
+use crate::layout::TagLayout;
+use crate::node_manager::GipopNodeManager;
+
+// What the Acknowledge method and the sync task share
+#[derive(Default)]
+struct AckState {
+    event_id: ByteString, // of the last event, Acknowledge has to quote it
+    acked: bool,
+    ack_pending: bool, // acknowledged by a client, not reported yet
+}
+
+struct Condition {
+    tag_idx: usize,
+    name: String,
+    ns: u16,
+    node: NodeId, // "alarms/<tag name>", its properties are below that path
+    source: NodeId, // the alarm tag's variable
+    severity: u16,
+    active: bool,
+    time: DateTime, // of the last state change
+    ack: Arc<Mutex<AckState>>,
+}
+
+impl Condition {
+    fn retain(&self, acked: bool) -> bool {
+        self.active || !acked
+    }
+
+    fn child(&self, path: &str) -> NodeId {
+        NodeId::new(self.ns, format!("alarms/{}/{}", self.name, path))
+    }
+}
+
+// Both Acknowledge and the Operator object's AckAlarm end up here. `event_id` is only checked when given.
+fn acknowledge(ack: &Mutex<AckState>, event_id: Option<&ByteString>, tag_idx: usize, name: &str, table: &Subscriber) -> Result<(), StatusCode> {
+    let mut ack = ack.lock().unwrap();
+    if event_id.is_some_and(|event_id| *event_id != ack.event_id) {
+        return Err(StatusCode::BadEventIdUnknown);
+    }
+    if ack.acked {
+        return Err(StatusCode::BadConditionBranchAlreadyAcked);
+    }
+    audit::command(table, RingItem::alarm_ack(tag_idx))?;
+    log::info!("[OPC UA] Alarm acknowledged: {}", name);
+    ack.acked = true;
+    ack.ack_pending = true;
+    Ok(())
+}
+
+/// Acknowledges alarms by tag name, handed to whoever needs to besides the conditions' own Acknowledge methods
+#[derive(Clone)]
+pub struct AlarmAcks {
+    acks: Arc<HashMap<String, (usize, Arc<Mutex<AckState>>)>>,
+    table: Subscriber,
+}
+
+impl AlarmAcks {
+    pub fn acknowledge(&self, name: &str) -> Result<(), StatusCode> {
+        let (tag_idx, ack) = self.acks.get(name).ok_or(StatusCode::BadInvalidArgument)?;
+        acknowledge(ack, None, *tag_idx, name, &self.table)
+    }
+}
+
+pub struct AlarmNodes {
+    conditions: Vec<Condition>,
+    refresh: Arc<AtomicBool>,
+    events: u64, // for event ids
+    table: Subscriber,
+}
+
+impl AlarmNodes {
+    pub fn new(layout: &TagLayout, manager: &GipopNodeManager, tags: &[TagDef], table: Subscriber) -> Self {
+        let ns = layout.ns;
+        let folder = NodeId::new(ns, "alarms");
+        let refresh = Arc::new(AtomicBool::new(false));
+        let mut conditions = Vec::new();
+        let event_history = manager.inner().historize_events(folder.clone());
+        {
+            let address_space = manager.address_space();
+            let mut address_space = address_space.write();
+            ObjectBuilder::new(&folder, "Alarms", "Alarms")
+                .organized_by(ObjectId::ObjectsFolder)
+                .event_notifier(if event_history { EventNotifier::HISTORY_READ } else { EventNotifier::empty() })
+                .insert(&mut address_space);
+
+            let refresh_id = NodeId::new(ns, "alarms/RefreshConditions");
+            MethodBuilder::new(&refresh_id, "RefreshConditions", "RefreshConditions")
+                .component_of(folder.clone())
+                .insert(&mut address_space);
+            let flag = refresh.clone();
+            manager.inner().add_method_callback(refresh_id, move |_| {
+                flag.store(true, Ordering::Relaxed);
+                Ok(Vec::new())
+            });
+
+            for (tag_idx, tag) in tags.iter().enumerate().filter(|(_, tag)| tag.is_alarm()) {
+                let condition = Condition {
+                    tag_idx,
+                    name: tag.name.clone(),
+                    ns,
+                    node: NodeId::new(ns, format!("alarms/{}", tag.name)),
+                    source: layout.node(tag),
+                    severity: tag.severity,
+                    active: false,
+                    time: DateTime::now(),
+                    ack: Arc::new(Mutex::new(AckState { acked: true, ..Default::default() })),
+                };
+
+                ObjectBuilder::new(&condition.node, tag.name.as_str(), tag.name.as_str())
+                    .has_type_definition(ObjectTypeId::AlarmConditionType)
+                    .organized_by(folder.clone())
+                    .insert(&mut address_space);
+
+                let property = |path: &str, value: Variant, data_type: DataTypeId| {
+                    let browse_name = path.rsplit('/').next().unwrap_or(path);
+                    VariableBuilder::new(&condition.child(path), browse_name, browse_name)
+                        .value(value)
+                        .data_type(data_type)
+                        .access_level(AccessLevel::CURRENT_READ)
+                        .user_access_level(AccessLevel::CURRENT_READ)
+                        .build()
+                };
+                let _ = address_space.add_variables(vec![
+                    property("Severity", Variant::UInt16(condition.severity), DataTypeId::UInt16),
+                    property("Message", Variant::from(LocalizedText::from(tag.name.as_str())), DataTypeId::LocalizedText),
+                    property("Retain", Variant::Boolean(false), DataTypeId::Boolean),
+                    property("ActiveState", Variant::from(LocalizedText::from("Inactive")), DataTypeId::LocalizedText),
+                    property("AckedState", Variant::from(LocalizedText::from("Acknowledged")), DataTypeId::LocalizedText),
+                ], &condition.node);
+                let _ = address_space.add_variables(vec![property("ActiveState/Id", Variant::Boolean(false), DataTypeId::Boolean)], &condition.child("ActiveState"));
+                let _ = address_space.add_variables(vec![property("AckedState/Id", Variant::Boolean(true), DataTypeId::Boolean)], &condition.child("AckedState"));
+
+                // Acknowledge(EventId, Comment), same arguments as AcknowledgeableConditionType's
+                let acknowledge_id = condition.child("Acknowledge");
+                MethodBuilder::new(&acknowledge_id, "Acknowledge", "Acknowledge")
+                    .component_of(condition.node.clone())
+                    .input_args(&mut address_space, &condition.child("Acknowledge/InputArguments"), &[
+                        ("EventId", DataTypeId::ByteString).into(),
+                        ("Comment", DataTypeId::LocalizedText).into(),
+                    ])
+                    .insert(&mut address_space);
+                manager.inner().require(acknowledge_id.clone(), Action::AckAlarm);
+                let ack = condition.ack.clone();
+                let name = condition.name.clone();
+                let table = table.clone();
+                manager.inner().add_method_callback(acknowledge_id, move |args| {
+                    let Some(Variant::ByteString(event_id)) = args.first() else {
+                        return Err(StatusCode::BadInvalidArgument);
+                    };
+                    acknowledge(&ack, Some(event_id), tag_idx, &name, &table).map(|_| Vec::new())
+                });
+
+                conditions.push(condition);
+            }
+        }
+        log::info!("Added {} alarm conditions", conditions.len());
+        Self { conditions, refresh, events: 0, table }
+    }
+
+    pub fn acks(&self) -> AlarmAcks {
+        let acks = self.conditions.iter().map(|condition| (condition.name.clone(), (condition.tag_idx, condition.ack.clone()))).collect();
+        AlarmAcks { acks: Arc::new(acks), table: self.table.clone() }
+    }
+
+    /// The PLC says alarm tag `tag_idx` was acknowledged, by us or another consumer. Reported on the next update.
+    pub fn acknowledged(&self, tag_idx: usize) {
+        if let Some(condition) = self.conditions.iter().find(|condition| condition.tag_idx == tag_idx) {
+            let mut ack = condition.ack.lock().unwrap();
+            if !ack.acked {
+                ack.acked = true;
+                ack.ack_pending = true;
+            }
+        }
+    }
+
+    /// Picks up alarm tag changes and client acknowledgements, called from the sync task
+    pub fn update(&mut self, manager: &GipopNodeManager, subscriptions: &SubscriptionCache, table: &Subscriber) {
+        let mut changed = Vec::new();
+        for (idx, condition) in self.conditions.iter_mut().enumerate() {
+            let sample = table.read_sample(condition.tag_idx);
+            let active = sample.quality.is_good() && sample.value == TagValue::Bool(true);
+            let mut ack = condition.ack.lock().unwrap();
+
+            if active != condition.active {
+                condition.active = active;
+                condition.time = DateTime::now();
+                if active {
+                    ack.acked = false; // every new occurrence needs its own acknowledgement
+                }
+                changed.push(idx);
+            }
+            else if ack.ack_pending {
+                condition.time = DateTime::now();
+                changed.push(idx);
+            }
+            ack.ack_pending = false;
+        }
+
+        for idx in changed {
+            self.report(idx, manager, subscriptions);
+        }
+
+        if self.refresh.swap(false, Ordering::Relaxed) {
+            self.refresh(subscriptions);
+        }
+    }
+
+    // Condition state into the address space and out as an event
+    fn report(&mut self, idx: usize, manager: &GipopNodeManager, subscriptions: &SubscriptionCache) {
+        let event_id = self.next_event_id();
+        let condition = &self.conditions[idx];
+        let acked = {
+            let mut ack = condition.ack.lock().unwrap();
+            ack.event_id = event_id.clone();
+            ack.acked
+        };
+
+        let values = [
+            ("Retain", Variant::Boolean(condition.retain(acked))),
+            ("ActiveState", Variant::from(LocalizedText::from(if condition.active { "Active" } else { "Inactive" }))),
+            ("ActiveState/Id", Variant::Boolean(condition.active)),
+            ("AckedState", Variant::from(LocalizedText::from(if acked { "Acknowledged" } else { "Unacknowledged" }))),
+            ("AckedState/Id", Variant::Boolean(acked)),
+        ].map(|(path, value)| (condition.child(path), value));
+        let _ = manager.set_values(
+            subscriptions,
+            values.iter().map(|(id, value)| (id, None::<&NumericRange>, DataValue::new_now(value.clone()))),
+        );
+
+        log::info!("[OPC UA] Alarm {} {}: {}",
+            if condition.active { "active" } else { "inactive" },
+            if acked { "acknowledged" } else { "unacknowledged" },
+            condition.name);
+        let event = ConditionEvent::new(condition, event_id, acked);
+        subscriptions.notify_events([(&event as &dyn Event, &ObjectId::Server.into())].into_iter());
+    }
+
+    fn refresh(&mut self, subscriptions: &SubscriptionCache) {
+        let server: NodeId = ObjectId::Server.into();
+        let start = BaseEventType::new_now(ObjectTypeId::RefreshStartEventType, self.next_event_id(), "Refresh start");
+        subscriptions.notify_events([(&start as &dyn Event, &server)].into_iter());
+
+        for condition in &self.conditions {
+            let ack = condition.ack.lock().unwrap();
+            if condition.retain(ack.acked) {
+                let event = ConditionEvent::new(condition, ack.event_id.clone(), ack.acked);
+                subscriptions.notify_events([(&event as &dyn Event, &server)].into_iter());
+            }
+        }
+
+        let end = BaseEventType::new_now(ObjectTypeId::RefreshEndEventType, self.next_event_id(), "Refresh end");
+        subscriptions.notify_events([(&end as &dyn Event, &server)].into_iter());
+    }
+
+    fn next_event_id(&mut self) -> ByteString {
+        self.events += 1;
+        ByteString::from(format!("gipop-alarm-{}-{}", DateTime::now().ticks(), self.events).into_bytes())
+    }
+}
+
+// AlarmConditionType event: the base event fields plus the condition state clients filter and display on
+struct ConditionEvent {
+    base: BaseEventType,
+    condition_name: UAString,
+    retain: bool,
+    active: bool,
+    acked: bool,
+}
+
+impl ConditionEvent {
+    fn new(condition: &Condition, event_id: ByteString, acked: bool) -> Self {
+        let base = BaseEventType::new(ObjectTypeId::AlarmConditionType, event_id, condition.name.as_str(), condition.time)
+            .set_source_node(condition.source.clone())
+            .set_source_name(condition.name.as_str().into())
+            .set_severity(condition.severity);
+        Self { base, condition_name: condition.name.as_str().into(), retain: condition.retain(acked), active: condition.active, acked }
+    }
+
+    fn field(&self, browse_path: &[QualifiedName]) -> Option<Variant> {
+        let path: Vec<&str> = browse_path.iter().map(|name| name.name.as_ref()).collect();
+        let state = |on: bool, on_text: &str, off_text: &str| Variant::from(LocalizedText::from(if on { on_text } else { off_text }));
+        Some(match path[..] {
+            ["ConditionName"] => Variant::from(self.condition_name.clone()),
+            ["Retain"] => Variant::Boolean(self.retain),
+            ["EnabledState"] => state(true, "Enabled", "Disabled"),
+            ["EnabledState", "Id"] => Variant::Boolean(true),
+            ["ActiveState"] => state(self.active, "Active", "Inactive"),
+            ["ActiveState", "Id"] => Variant::Boolean(self.active),
+            ["AckedState"] => state(self.acked, "Acknowledged", "Unacknowledged"),
+            ["AckedState", "Id"] => Variant::Boolean(self.acked),
+            _ => return None,
+        })
+    }
+}
+
+impl EventField for ConditionEvent {
+    fn get_value(&self, attribute_id: AttributeId, index_range: &NumericRange, remaining_path: &[QualifiedName]) -> Variant {
+        match (attribute_id, self.field(remaining_path)) {
+            (AttributeId::Value, Some(value)) => value,
+            _ => self.base.get_value(attribute_id, index_range, remaining_path),
+        }
+    }
+}
+
+impl Event for ConditionEvent {
+    fn get_field(&self, type_definition_id: &NodeId, attribute_id: AttributeId, index_range: &NumericRange, browse_path: &[QualifiedName]) -> Variant {
+        match (attribute_id, self.field(browse_path)) {
+            (AttributeId::Value, Some(value)) => value,
+            _ => self.base.get_field(type_definition_id, attribute_id, index_range, browse_path),
+        }
+    }
+
+    fn time(&self) -> &DateTime {
+        self.base.time()
+    }
+
+    fn event_type_id(&self) -> &NodeId {
+        self.base.event_type_id()
+    }
+}
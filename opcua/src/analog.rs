@@ -0,0 +1,84 @@
+// Analog item properties for tags the PLC declared a unit or range for (TagDef::analog), so HMIs can label and
+// scale them without per-tag configuration:
+//
+// <tag>/EngineeringUnits  EUInformation, the unit's UNECE code when we know it (see UNITS)
+// <tag>/EURange           Range, what the value normally stays within
+// <tag>/InstrumentRange   Range, what the sensor can report at all
+//
+// Tags with an EU range are AnalogItemType, the ones with just a unit BaseAnalogType (EURange is mandatory on the former).
+use opcua::server::address_space::{AddressSpace, VariableBuilder};
+use opcua::types::{DataTypeId, EUInformation, ExtensionObject, LocalizedText, Range, Variant, VariableTypeId};
+use gipop_shm::TagDef;
+
+use crate::layout::TagLayout;
+
+const UNECE_NAMESPACE: &str = "http://www.opcfoundation.org/UA/units/un/cefact";
+
+// Unit symbol as the PLC writes it -> UNECE Rec. 20 common code
+const UNITS: [(&str, &str, &str); 15] = [
+    ("°C", "CEL", "degree Celsius"),
+    ("K", "KEL", "kelvin"),
+    ("%", "P1", "percent"),
+    ("%RH", "P1", "percent relative humidity"),
+    ("mA", "4K", "milliampere"),
+    ("A", "AMP", "ampere"),
+    ("V", "VLT", "volt"),
+    ("W", "WTT", "watt"),
+    ("kWh", "KWH", "kilowatt hour"),
+    ("Pa", "PAL", "pascal"),
+    ("bar", "BAR", "bar"),
+    ("lx", "LUX", "lux"),
+    ("ppm", "59", "part per million"),
+    ("s", "SEC", "second"),
+    ("ms", "C26", "millisecond"),
+];
+
+/// Type definition for the tag's variable
+pub fn type_definition(tag: &TagDef) -> VariableTypeId {
+    match tag {
+        tag if !tag.is_analog() => VariableTypeId::BaseDataVariableType,
+        tag if tag.eu_range.is_some() => VariableTypeId::AnalogItemType,
+        _ => VariableTypeId::BaseAnalogType,
+    }
+}
+
+/// Properties of an analog tag's variable, nothing for the others
+pub fn add_analog_properties(address_space: &mut AddressSpace, layout: &TagLayout, tag: &TagDef) {
+    if !tag.is_analog() {
+        return;
+    }
+    let variable = layout.node(tag);
+    let mut properties = Vec::new();
+    if !tag.unit.is_empty() {
+        properties.push(("EngineeringUnits", DataTypeId::EUInformation, Variant::from(ExtensionObject::from_message(eu_information(&tag.unit)))));
+    }
+    for (name, range) in [("EURange", tag.eu_range), ("InstrumentRange", tag.instrument_range)] {
+        if let Some((low, high)) = range {
+            properties.push((name, DataTypeId::Range, Variant::from(ExtensionObject::from_message(Range { low, high }))));
+        }
+    }
+
+    for (name, data_type, value) in properties {
+        VariableBuilder::new(&layout.child(tag, name), name, name)
+            .property_of(variable.clone())
+            .has_type_definition(VariableTypeId::PropertyType)
+            .data_type(data_type)
+            .value(value)
+            .insert(address_space);
+    }
+}
+
+fn eu_information(unit: &str) -> EUInformation {
+    let known = UNITS.iter().find(|(symbol, _, _)| *symbol == unit);
+    EUInformation {
+        namespace_uri: UNECE_NAMESPACE.into(),
+        unit_id: known.map_or(-1, |(_, code, _)| unit_id(code)),
+        display_name: LocalizedText::new("", unit),
+        description: LocalizedText::new("en", known.map_or(unit, |(_, _, description)| description)),
+    }
+}
+
+// UNECE code -> UnitId, the code's ascii bytes packed big endian (OPC UA Part 8, EUInformation)
+fn unit_id(code: &str) -> i32 {
+    code.bytes().fold(0, |id, b| (id << 8) | b as i32)
+}
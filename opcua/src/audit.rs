@@ -0,0 +1,145 @@
+// Audit trail for client writes to command nodes (tags the PLC takes commands on, RunMode, DO channel arrays): every
+// write becomes an AuditWriteUpdateEventType event on the Server object, successful or not.
+//
+// Every command a client's write or method call sends (see `command`) is followed by an ITEM_AUDIT naming the client's
+// user, the PLC checks it against their role in [users] and drops commands it doesn't allow (plc/src/audit.rs).
+//
+// ActionTimeStamp  when the write was handled
+// Status           whether it succeeded
+// ServerId         our application URI
+// ClientUserId     the user token id, the user name in [users]
+// AttributeId      13 (Value), the only attribute command nodes take
+// IndexRange       as written, Null for whole values
+// OldValue         the node's value before the write
+// NewValue         the value written
+//
+// SourceNode is the written node, Message "<user> (session <id>) wrote <node>: <status>".
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use opcua::nodes::{BaseEventType, Event, EventField};
+use opcua::server::node_manager::RequestContext;
+use opcua::types::{
+    AttributeId, ByteString, DateTime, NodeId, NumericRange, ObjectId, ObjectTypeId, QualifiedName, StatusCode, UAString, Variant,
+    WriteValue,
+};
+use gipop_shm::{RingItem, Subscriber};
+
+const SEVERITY: u16 = 300; // operator actions, above telegrams and below alarms
+const SOURCE_NAME: &str = "Attribute/Write"; // what Part 5 says audit write events are from
+
+static EVENTS: AtomicU64 = AtomicU64::new(0); // for event ids
+
+tokio::task_local! {
+    // (user, session) of the client whose request is being handled
+    static CLIENT: (String, u32);
+}
+
+/// Runs `f` for the client of `context`, the commands it sends are audited as theirs
+pub fn as_client<R>(context: &RequestContext, f: impl FnOnce() -> R) -> R {
+    CLIENT.sync_scope((context.token.0.clone(), context.session_id), f)
+}
+
+/// `as_client` for a request handled asynchronously
+pub async fn as_client_async<F: Future>(context: &RequestContext, request: F) -> F::Output {
+    CLIENT.scope((context.token.0.clone(), context.session_id), request).await
+}
+
+/// Sends `item` to the PLC followed by its audit, naming the client it's sent for (see `as_client`). A command sent
+/// outside a client's request isn't audited, the PLC rejects it as anonymous.
+pub fn command(table: &Subscriber, item: RingItem) -> Result<u32, StatusCode> {
+    let Ok(seq) = table.push_command(item) else {
+        log::error!("Command rejected, PLC isn't consuming commands");
+        return Err(StatusCode::BadResourceUnavailable);
+    };
+    match CLIENT.try_with(|(user, session)| table.push_command(RingItem::audit(seq, *session, user))) {
+        Ok(Ok(_)) => {}
+        Ok(Err(_)) => log::error!("Audit of command {} rejected, PLC isn't consuming commands", seq),
+        Err(_) => log::error!("Command {} sent outside a client's request, the PLC won't take it", seq),
+    }
+    Ok(seq)
+}
+
+/// Audits `write` by the client of `context`. `result` is the command's sequence number, or why it failed.
+pub fn audit_write(context: &RequestContext, write: &WriteValue, old_value: Variant, result: Result<u32, StatusCode>) {
+    let user = context.token.0.as_str();
+    let status = result.err().unwrap_or(StatusCode::Good);
+    log::info!("[OPC UA] {} (session {}) wrote {}: {}", user, context.session_id, write.node_id, status);
+
+    let event_id = ByteString::from(format!("gipop-audit-{}-{}", DateTime::now().ticks(), EVENTS.fetch_add(1, Ordering::Relaxed)).into_bytes());
+    let message = format!("{} (session {}) wrote {}: {}", user, context.session_id, write.node_id, status);
+    let event = AuditWriteEvent {
+        base: BaseEventType::new_now(ObjectTypeId::AuditWriteUpdateEventType, event_id, message.as_str())
+            .set_source_node(write.node_id.clone())
+            .set_source_name(SOURCE_NAME.into())
+            .set_severity(SEVERITY),
+        action_time: DateTime::now(),
+        status: status.is_good(),
+        server_id: context.info.application_uri.clone(),
+        client_user_id: user.into(),
+        attribute_id: write.attribute_id,
+        index_range: match write.index_range {
+            NumericRange::None => UAString::null(),
+            ref range => range.to_string().into(),
+        },
+        old_value,
+        new_value: write.value.value.clone().unwrap_or(Variant::Empty),
+    };
+    let server: NodeId = ObjectId::Server.into();
+    context.subscriptions.notify_events([(&event as &dyn Event, &server)].into_iter());
+}
+
+struct AuditWriteEvent {
+    base: BaseEventType,
+    action_time: DateTime,
+    status: bool,
+    server_id: UAString,
+    client_user_id: UAString,
+    attribute_id: u32,
+    index_range: UAString,
+    old_value: Variant,
+    new_value: Variant,
+}
+
+impl AuditWriteEvent {
+    fn field(&self, browse_path: &[QualifiedName]) -> Option<Variant> {
+        let path: Vec<&str> = browse_path.iter().map(|name| name.name.as_ref()).collect();
+        Some(match path[..] {
+            ["ActionTimeStamp"] => Variant::from(self.action_time),
+            ["Status"] => Variant::from(self.status),
+            ["ServerId"] => Variant::from(self.server_id.clone()),
+            ["ClientAuditEntryId"] => Variant::from(UAString::null()), // the request header's, which we don't get to see
+            ["ClientUserId"] => Variant::from(self.client_user_id.clone()),
+            ["AttributeId"] => Variant::from(self.attribute_id),
+            ["IndexRange"] => Variant::from(self.index_range.clone()),
+            ["OldValue"] => self.old_value.clone(),
+            ["NewValue"] => self.new_value.clone(),
+            _ => return None,
+        })
+    }
+}
+
+impl EventField for AuditWriteEvent {
+    fn get_value(&self, attribute_id: AttributeId, index_range: &NumericRange, remaining_path: &[QualifiedName]) -> Variant {
+        match (attribute_id, self.field(remaining_path)) {
+            (AttributeId::Value, Some(value)) => value,
+            _ => self.base.get_value(attribute_id, index_range, remaining_path),
+        }
+    }
+}
+
+impl Event for AuditWriteEvent {
+    fn get_field(&self, type_definition_id: &NodeId, attribute_id: AttributeId, index_range: &NumericRange, browse_path: &[QualifiedName]) -> Variant {
+        match (attribute_id, self.field(browse_path)) {
+            (AttributeId::Value, Some(value)) => value,
+            _ => self.base.get_field(type_definition_id, attribute_id, index_range, browse_path),
+        }
+    }
+
+    fn time(&self) -> &DateTime {
+        self.base.time()
+    }
+
+    fn event_type_id(&self) -> &NodeId {
+        self.base.event_type_id()
+    }
+}
@@ -0,0 +1,134 @@
+// Fieldbus health in its own namespace, so HMIs for the process don't have to wade through it:
+//
+// Objects/Fieldbus               cycle counters and timing, K-bus coupler status
+//   SubDevices/<address> <name>  AL state, AL status code, RX errors per port, lost links
+//
+// Fed from the PLC's bus diagnostics (gipop_shm::bus_diag), which it refreshes about once a second.
+use std::collections::HashSet;
+
+use opcua::server::address_space::{AccessLevel, VariableBuilder};
+use opcua::server::SubscriptionCache;
+use opcua::server::address_space::Variable;
+use opcua::types::{DataTypeId, DataValue, NodeId, Variant};
+use gipop_shm::{BusDiagnostics, SubDeviceDiagnostics};
+
+use crate::node_manager::GipopNodeManager;
+
+pub const FIELDBUS_NAMESPACE: &str = "urn:GipopFieldbus";
+
+const CYCLE_VARIABLES: [(&str, DataTypeId); 9] = [
+    ("Cycles", DataTypeId::UInt64),
+    ("WkcErrors", DataTypeId::UInt64),
+    ("CycleTimeLastUs", DataTypeId::UInt32),
+    ("CycleTimeMinUs", DataTypeId::UInt32),
+    ("CycleTimeMaxUs", DataTypeId::UInt32),
+    ("CycleTimeAvgUs", DataTypeId::UInt32),
+    ("JitterMaxUs", DataTypeId::UInt32),
+    ("JitterAvgUs", DataTypeId::UInt32),
+    ("CouplerStatus", DataTypeId::UInt32), // K-bus status word of the BK1120, 0 = OK, 0xFFFFFFFF = no coupler
+];
+
+const SUBDEVICE_VARIABLES: [(&str, DataTypeId); 8] = [
+    ("AlState", DataTypeId::String), // INIT, PRE-OP, BOOT, SAFE-OP, OP
+    ("AlError", DataTypeId::Boolean),
+    ("AlStatusCode", DataTypeId::UInt16),
+    ("Port0Errors", DataTypeId::UInt32),
+    ("Port1Errors", DataTypeId::UInt32),
+    ("Port2Errors", DataTypeId::UInt32),
+    ("Port3Errors", DataTypeId::UInt32),
+    ("LostLinks", DataTypeId::UInt32),
+];
+
+pub struct FieldbusNodes {
+    ns: u16,
+    subdevices_folder: NodeId,
+    known: HashSet<u16>, // SubDevices that have nodes, by configured address
+    last: Option<BusDiagnostics>,
+}
+
+impl FieldbusNodes {
+    pub fn new(ns: u16, manager: &GipopNodeManager) -> Self {
+        let folder = NodeId::new(ns, "fieldbus");
+        let subdevices_folder = NodeId::new(ns, "fieldbus/SubDevices");
+        let address_space = manager.address_space();
+        let mut address_space = address_space.write();
+        address_space.add_folder(&folder, "Fieldbus", "Fieldbus", &NodeId::objects_folder_id());
+        address_space.add_folder(&subdevices_folder, "SubDevices", "SubDevices", &folder);
+        let _ = address_space.add_variables(variables(ns, "fieldbus", &CYCLE_VARIABLES), &folder);
+        Self { ns, subdevices_folder, known: HashSet::new(), last: None }
+    }
+
+    pub fn update(&mut self, manager: &GipopNodeManager, subscriptions: &SubscriptionCache, diag: Option<BusDiagnostics>) {
+        let Some(diag) = diag else {
+            return;
+        };
+        // the PLC rewrites the snapshot once a second, the sync task polls faster than that
+        if self.last == Some(diag) {
+            return;
+        }
+        self.last = Some(diag);
+
+        for subdevice in diag.subdevices() {
+            if self.known.insert(subdevice.address) {
+                let name = format!("{:#06x} {}", subdevice.address, subdevice.name());
+                let object = NodeId::new(self.ns, subdevice_path(subdevice));
+                let address_space = manager.address_space();
+                let mut address_space = address_space.write();
+                address_space.add_folder(&object, name.as_str(), name.as_str(), &self.subdevices_folder);
+                let _ = address_space.add_variables(variables(self.ns, &subdevice_path(subdevice), &SUBDEVICE_VARIABLES), &object);
+                log::info!("[OPC UA sync] Fieldbus SubDevice {}", name);
+            }
+        }
+
+        let cycle_values = [
+            Variant::UInt64(diag.cycles),
+            Variant::UInt64(diag.wkc_errors),
+            Variant::UInt32(diag.cycle_us_last),
+            Variant::UInt32(diag.cycle_us_min),
+            Variant::UInt32(diag.cycle_us_max),
+            Variant::UInt32(diag.cycle_us_avg),
+            Variant::UInt32(diag.jitter_us_max),
+            Variant::UInt32(diag.jitter_us_avg),
+            Variant::UInt32(diag.coupler_status),
+        ];
+        let mut values: Vec<(NodeId, Variant)> = CYCLE_VARIABLES.iter()
+            .zip(cycle_values)
+            .map(|((name, _), value)| (NodeId::new(self.ns, format!("fieldbus/{}", name)), value))
+            .collect();
+
+        for subdevice in diag.subdevices() {
+            let subdevice_values = [
+                Variant::from(subdevice.state_name()),
+                Variant::Boolean(subdevice.has_error()),
+                Variant::UInt16(subdevice.al_status_code),
+                Variant::UInt32(subdevice.port_errors[0]),
+                Variant::UInt32(subdevice.port_errors[1]),
+                Variant::UInt32(subdevice.port_errors[2]),
+                Variant::UInt32(subdevice.port_errors[3]),
+                Variant::UInt32(subdevice.lost_links),
+            ];
+            let path = subdevice_path(subdevice);
+            values.extend(SUBDEVICE_VARIABLES.iter()
+                .zip(subdevice_values)
+                .map(|((name, _), value)| (NodeId::new(self.ns, format!("{}/{}", path, name)), value)));
+        }
+
+        let _ = manager.set_values(subscriptions, values.iter().map(|(id, value)| (id, None, DataValue::new_now(value.clone()))));
+    }
+}
+
+fn subdevice_path(subdevice: &SubDeviceDiagnostics) -> String {
+    format!("fieldbus/SubDevices/{}", subdevice.address)
+}
+
+// Read-only variables "<path>/<name>", values come with the first update
+fn variables(ns: u16, path: &str, names: &[(&str, DataTypeId)]) -> Vec<Variable> {
+    names.iter().map(|&(name, data_type)| {
+        VariableBuilder::new(&NodeId::new(ns, format!("{}/{}", path, name)), name, name)
+            .data_type(data_type)
+            .value(Variant::Empty)
+            .access_level(AccessLevel::CURRENT_READ)
+            .user_access_level(AccessLevel::CURRENT_READ)
+            .build()
+    }).collect()
+}
@@ -0,0 +1,61 @@
+// gipop_opcua_client's section of gipop.toml. `[ipc]` is shared with the PLC and read by gipop_shm::IpcConfig.
+//
+// [opcua_client]
+// user = "opcua_client"   # who the PLC audits its writes as, needs the operator role in [users]
+//
+// [[opcua_client.servers]]
+// endpoint = "opc.tcp://chiller.local:4840"
+// publishing_interval_ms = 1000
+// tags = [
+//     { node = "ns=2;s=SupplyTemperature", tag = "chiller supply temp" },
+// ]
+use serde::Deserialize;
+use std::path::Path;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ClientConfig {
+    pub opcua_client: OpcuaClientConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct OpcuaClientConfig {
+    pub user: String,
+    pub servers: Vec<RemoteServer>,
+}
+
+impl Default for OpcuaClientConfig {
+    fn default() -> Self {
+        Self { user: "opcua_client".to_owned(), servers: Vec::new() }
+    }
+}
+
+/// One third-party server and which of its nodes feed which external PLC tags
+#[derive(Debug, Clone, Deserialize)]
+pub struct RemoteServer {
+    pub endpoint: String, // connected without security, anonymous unless `user` is set
+    #[serde(default)]
+    pub user: Option<String>,
+    #[serde(default)]
+    pub password: String,
+    #[serde(default = "default_publishing_interval_ms")]
+    pub publishing_interval_ms: u64,
+    pub tags: Vec<TagMapping>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TagMapping {
+    pub node: String, // node id on the remote server, e.g. "ns=2;s=SupplyTemperature"
+    pub tag: String,  // external tag in the PLC's directory (TagDef::external)
+}
+
+fn default_publishing_interval_ms() -> u64 {
+    1000
+}
+
+impl ClientConfig {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        gipop_shm::config::load(path)
+    }
+}
@@ -0,0 +1,225 @@
+// OPC UA client feeding values from third-party servers (a chiller's embedded server, an energy meter...) into the
+// PLC's external tags (TagDef::external), so the logic can use them.
+//
+// One session per server in [opcua_client], each subscribes to its mapped nodes and every data change goes to the
+// PLC as a tag write command, the same path HMI writes take, audited as [opcua_client] user. Sessions reconnect on
+// their own, while a server is away its tags hold their last value.
+mod config;
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use opcua::client::{Client, ClientBuilder, DataChangeCallback, IdentityToken, MonitoredItem, Session};
+use opcua::crypto::SecurityPolicy;
+use opcua::types::{DataValue, MessageSecurityMode, MonitoredItemCreateRequest, NodeId, TimestampsToReturn, UserTokenPolicy, Variant};
+use gipop_shm::{Action, IpcConfig, RingItem, Startup, Subscriber, TagType, TagValue, Users};
+
+use config::{ClientConfig, RemoteServer};
+
+const CONFIG_PATH: &str = "../gipop.toml"; // same working directory assumption as the server
+const RETRY_INTERVAL: Duration = Duration::from_secs(10); // between attempts to reach a server that isn't there
+
+#[tokio::main]
+async fn main() {
+    let config = gipop_shm::project::config_path(CONFIG_PATH, env!("CARGO_PKG_VERSION")).unwrap_or_else(|e| {
+        eprintln!("gipop_opcua_client: {}", e);
+        std::process::exit(1);
+    });
+    let config = config.as_str();
+    gipop_shm::logging::init(config, "gipop_opcua_client");
+    gipop_shm::logging::reload_on_sighup();
+
+    let ipc = match IpcConfig::load(config) {
+        Ok(ipc) => ipc,
+        Err(e) => {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let cfg = match ClientConfig::load(config) {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if cfg.opcua_client.servers.is_empty() {
+        log::warn!("No [[opcua_client.servers]] in {}, nothing to do", config);
+        return;
+    }
+    let user = cfg.opcua_client.user;
+    if let Err(e) = Users::load(config).and_then(|users| users.authorize(Some(&user), Action::WriteTag)) {
+        log::warn!("The PLC will reject our writes: {}", e);
+    }
+
+    // like the server, waits [ipc] connect_timeout_ms for the PLC when started before it
+    let mut startup = Startup::new("gipop_opcua_client");
+    let table = startup.up(ipc.connect_step(), || Subscriber::connect(&ipc)).unwrap_or_else(|e| startup.fail(&e));
+
+    for server in cfg.opcua_client.servers {
+        let mappings = resolve(&table, &server);
+        if mappings.is_empty() {
+            log::warn!("Nothing to subscribe to on {}", server.endpoint);
+            continue;
+        }
+        let (table, user) = (table.clone(), user.clone());
+        tokio::spawn(async move {
+            // a fresh client per attempt, until the server was reachable once
+            while let Err(e) = subscribe(&server, mappings.clone(), table.clone(), &user).await {
+                log::error!("{}: {}, retrying in {:?}", server.endpoint, e, RETRY_INTERVAL);
+                tokio::time::sleep(RETRY_INTERVAL).await;
+            }
+        });
+    }
+
+    // we count as a consumer, the PLC sees us through the shared heartbeat
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_millis(500)) => table.heartbeat(),
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+    log::info!("Client terminated");
+}
+
+// Remote node -> (tag index, tag type), skipping mappings to tags the PLC doesn't have as external
+fn resolve(table: &Subscriber, server: &RemoteServer) -> HashMap<NodeId, (usize, TagType)> {
+    let mut mappings = HashMap::new();
+    for mapping in &server.tags {
+        let Ok(node) = NodeId::from_str(&mapping.node) else {
+            log::error!("{}: '{}' isn't a node id", server.endpoint, mapping.node);
+            continue;
+        };
+        match table.index_of(&mapping.tag).map(|idx| (idx, &table.tags()[idx])) {
+            Some((idx, tag)) if tag.is_external() => {
+                mappings.insert(node, (idx, tag.ty));
+            }
+            Some(_) => log::error!("{}: tag '{}' isn't an external tag, the PLC would ignore it", server.endpoint, mapping.tag),
+            None => log::error!("{}: no tag '{}' in the PLC's directory", server.endpoint, mapping.tag),
+        }
+    }
+    mappings
+}
+
+// Connects, subscribes and then runs the session until it gives up for good
+async fn subscribe(server: &RemoteServer, mappings: HashMap<NodeId, (usize, TagType)>, table: Subscriber, user: &str) -> Result<(), String> {
+    let mut client = client()?;
+    let identity = match &server.user {
+        Some(user) => IdentityToken::UserName(user.clone(), server.password.clone().into()),
+        None => IdentityToken::Anonymous,
+    };
+    let token_policy = if server.user.is_some() { UserTokenPolicy::username() } else { UserTokenPolicy::anonymous() };
+    let (session, event_loop) = client
+        .connect_to_matching_endpoint(
+            (server.endpoint.as_str(), SecurityPolicy::None.to_str(), MessageSecurityMode::None, token_policy),
+            identity,
+        )
+        .await
+        .map_err(|status| format!("connect failed ({})", status))?;
+    let running = event_loop.spawn();
+    if let Err(e) = monitor(server, &session, mappings, table, user).await {
+        let _ = session.disconnect().await; // or its event loop would keep going next to the retry's
+        return Err(e);
+    }
+
+    // the event loop reconnects and recreates the subscription by itself, it only returns once it gave up
+    let status = running.await.map_err(|e| e.to_string())?;
+    Err(format!("session ended ({})", status))
+}
+
+async fn monitor(
+    server: &RemoteServer,
+    session: &Session,
+    mappings: HashMap<NodeId, (usize, TagType)>,
+    table: Subscriber,
+    user: &str,
+) -> Result<(), String> {
+    if !session.wait_for_connection().await {
+        return Err("session didn't come up".to_owned());
+    }
+
+    let nodes: Vec<NodeId> = mappings.keys().cloned().collect();
+    let mappings = Arc::new(mappings);
+    let (endpoint, user) = (server.endpoint.clone(), user.to_owned());
+    let subscription = session
+        .create_subscription(
+            Duration::from_millis(server.publishing_interval_ms),
+            30, // lifetime count
+            10, // max keep alive count
+            0,
+            0,
+            true,
+            DataChangeCallback::new(move |value: DataValue, item: &MonitoredItem| {
+                let node = &item.item_to_monitor().node_id;
+                let Some(&(idx, ty)) = mappings.get(node) else {
+                    return;
+                };
+                if let Some(status) = value.status.filter(|status| !status.is_good()) {
+                    log::warn!("{}: {} is {}, keeping the last value", endpoint, node, status);
+                    return;
+                }
+                match value.value.as_ref().and_then(|variant| variant_to_tag(ty, variant)) {
+                    Some(value) => match table.push_command(RingItem::tag_write(idx, value)) {
+                        Ok(seq) => {
+                            if table.push_command(RingItem::audit(seq, 0, &user)).is_err() {
+                                log::error!("Audit of command {} rejected, PLC isn't consuming commands", seq);
+                            }
+                        }
+                        Err(_) => log::error!("Command rejected, PLC isn't consuming commands"),
+                    },
+                    None => log::warn!("{}: {} isn't a number: {:?}", endpoint, node, value.value),
+                }
+            }),
+        )
+        .await
+        .map_err(|status| format!("create subscription failed ({})", status))?;
+
+    let items: Vec<MonitoredItemCreateRequest> = nodes.into_iter().map(Into::into).collect();
+    let count = items.len();
+    session
+        .create_monitored_items(subscription, TimestampsToReturn::Source, items)
+        .await
+        .map_err(|status| format!("create monitored items failed ({})", status))?;
+    log::info!("{}: subscribed to {} nodes", server.endpoint, count);
+    Ok(())
+}
+
+fn client() -> Result<Client, String> {
+    ClientBuilder::new()
+        .application_name("Gipop OPC UA Client")
+        .application_uri("urn:GipopOpcUaClient")
+        .product_uri("urn:GipopOpcUaClient")
+        .pki_dir("./pki-client")
+        .create_sample_keypair(true)
+        .trust_server_certs(true)
+        .session_retry_limit(-1) // forever, see RETRY_INTERVAL for the first connect
+        .client()
+        .map_err(|errors| format!("invalid client config: {}", errors.join(", ")))
+}
+
+// Remote servers use whatever numeric type they like, anything numeric goes into the tag's type
+fn variant_to_tag(ty: TagType, variant: &Variant) -> Option<TagValue> {
+    let number = match *variant {
+        Variant::Boolean(b) => b as u8 as f64,
+        Variant::SByte(n) => n as f64,
+        Variant::Byte(n) => n as f64,
+        Variant::Int16(n) => n as f64,
+        Variant::UInt16(n) => n as f64,
+        Variant::Int32(n) => n as f64,
+        Variant::UInt32(n) => n as f64,
+        Variant::Int64(n) => n as f64,
+        Variant::UInt64(n) => n as f64,
+        Variant::Float(f) => f as f64,
+        Variant::Double(f) => f,
+        _ => return None,
+    };
+    Some(match ty {
+        TagType::Bool => TagValue::Bool(number != 0.0),
+        TagType::UInt32 => TagValue::UInt32(number as u32),
+        TagType::Int32 => TagValue::Int32(number as i32),
+        TagType::Float32 => TagValue::Float32(number as f32),
+        TagType::Float64 => TagValue::Float64(number),
+    })
+}
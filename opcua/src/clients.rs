@@ -0,0 +1,130 @@
+// Who is connected and how heavily, for operators wondering why the server is busy:
+//
+// Objects/Diagnostics/Clients
+//   SessionCount        UInt32    current sessions
+//   SubscriptionCount   UInt32    subscriptions over every session
+//   MonitoredItemCount  UInt32    monitored items over every subscription
+//   PublishQueueDepth   UInt32    publish requests clients have queued with us, over every session
+//   Sessions            String[]  one line per session, e.g.
+//                                 "3 'UaExpert' alice@192.168.1.20:50412 subs=2 items=48 queued=4 acks=1"
+//
+// "queued" are the session's outstanding publish requests, "acks" the notifications still waiting for the client's
+// acknowledgement. A session with no publish requests queued and growing acks is a client falling behind.
+// Read from the server's session and subscription bookkeeping once a second, the variables only change when
+// something did.
+use std::time::{Duration, Instant};
+
+use opcua::server::address_space::{AccessLevel, VariableBuilder};
+use opcua::server::{ServerHandle, SubscriptionCache};
+use opcua::types::{DataTypeId, DataValue, NodeId, Variant};
+
+use crate::node_manager::GipopNodeManager;
+
+const INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SessionMetrics {
+    id: u32,
+    name: String,
+    user: String,
+    address: String,
+    subscriptions: u32,
+    monitored_items: u32,
+    queued: u32, // publish requests
+    acks: u32,   // retransmission queue
+}
+
+impl SessionMetrics {
+    fn line(&self) -> String {
+        format!(
+            "{} '{}' {}@{} subs={} items={} queued={} acks={}",
+            self.id, self.name, self.user, self.address, self.subscriptions, self.monitored_items, self.queued, self.acks,
+        )
+    }
+}
+
+pub struct ClientNodes {
+    ns: u16,
+    last: Option<Vec<SessionMetrics>>,
+    read: Option<Instant>,
+}
+
+impl ClientNodes {
+    /// `diagnostics` is the Diagnostics folder
+    pub fn new(ns: u16, manager: &GipopNodeManager, diagnostics: &NodeId) -> Self {
+        let folder = NodeId::new(ns, "diagnostics/clients");
+        let address_space = manager.address_space();
+        let mut address_space = address_space.write();
+        address_space.add_folder(&folder, "Clients", "Clients", diagnostics);
+        for name in ["SessionCount", "SubscriptionCount", "MonitoredItemCount", "PublishQueueDepth", "Sessions"] {
+            let builder = VariableBuilder::new(&NodeId::new(ns, format!("diagnostics/clients/{}", name)), name, name)
+                .access_level(AccessLevel::CURRENT_READ)
+                .user_access_level(AccessLevel::CURRENT_READ)
+                .organized_by(folder.clone());
+            let builder = match name {
+                "Sessions" => builder.data_type(DataTypeId::String).value(Vec::<String>::new()).value_rank(1),
+                _ => builder.data_type(DataTypeId::UInt32).value(0u32),
+            };
+            builder.insert(&mut address_space);
+        }
+        Self { ns, last: None, read: None }
+    }
+
+    pub fn update(&mut self, manager: &GipopNodeManager, subscriptions: &SubscriptionCache, handle: &ServerHandle) {
+        if self.read.is_some_and(|read| read.elapsed() < INTERVAL) {
+            return;
+        }
+        self.read = Some(Instant::now());
+        let sessions = sessions(handle, subscriptions);
+        if self.last.as_ref() == Some(&sessions) {
+            return;
+        }
+        if self.last.as_ref().map(Vec::len) != Some(sessions.len()) {
+            log::info!("[OPC UA] {} sessions", sessions.len());
+        }
+
+        let sum = |metric: fn(&SessionMetrics) -> u32| Variant::from(sessions.iter().map(metric).sum::<u32>());
+        let values = [
+            ("SessionCount", Variant::from(sessions.len() as u32)),
+            ("SubscriptionCount", sum(|session| session.subscriptions)),
+            ("MonitoredItemCount", sum(|session| session.monitored_items)),
+            ("PublishQueueDepth", sum(|session| session.queued)),
+            ("Sessions", Variant::from(sessions.iter().map(SessionMetrics::line).collect::<Vec<String>>())),
+        ];
+        let ids: Vec<NodeId> = values.iter().map(|(name, _)| NodeId::new(self.ns, format!("diagnostics/clients/{}", name))).collect();
+        let _ = manager.set_values(subscriptions, ids.iter().zip(values).map(|(id, (_, value))| (id, None, DataValue::new_now(value))));
+        self.last = Some(sessions);
+    }
+}
+
+// Every active session, ordered by id
+fn sessions(handle: &ServerHandle, subscriptions: &SubscriptionCache) -> Vec<SessionMetrics> {
+    let active: Vec<_> = handle.session_manager().read().sessions().cloned().collect();
+    let mut sessions: Vec<SessionMetrics> = active.iter().filter_map(|session| {
+        let session = session.read();
+        if !session.is_activated() {
+            return None; // created but not activated yet, nobody's there
+        }
+        let id = session.session_id_numeric();
+        let mut metrics = SessionMetrics {
+            id,
+            name: session.session_name().to_string(),
+            user: session.user_token().map_or_else(|| "?".to_owned(), |token| token.0.clone()),
+            address: session.client_address().map_or_else(|| "?".to_owned(), |address| address.to_string()),
+            subscriptions: 0,
+            monitored_items: 0,
+            queued: 0,
+            acks: 0,
+        };
+        if let Some(session_subscriptions) = subscriptions.get_session_subscriptions(id) {
+            let session_subscriptions = session_subscriptions.lock();
+            metrics.subscriptions = session_subscriptions.len() as u32;
+            metrics.monitored_items = session_subscriptions.subscriptions().map(|sub| sub.len() as u32).sum();
+            metrics.queued = session_subscriptions.publish_request_queue_len() as u32;
+            metrics.acks = session_subscriptions.retransmission_queue_len() as u32;
+        }
+        Some(metrics)
+    }).collect();
+    sessions.sort_by_key(|session| session.id);
+    sessions
+}
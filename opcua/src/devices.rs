@@ -0,0 +1,172 @@
+// The controller and its terminals the way the OPC UA for Devices (DI) and PLCopen (IEC 61131-3) companion
+// specifications lay them out, so tools that know those models can browse Gipop without knowing Gipop:
+//
+// Objects/DeviceSet                         DI's entry point, its standard node id
+//   Gipop PLC         CtrlConfigurationType  nameplate properties (Manufacturer, Model, SoftwareRevision...)
+//     Resources       ConfigurableObjectType
+//       Resource      CtrlResourceType
+//         GlobalVars  FunctionalGroupType    every PLC tag
+//         Programs    ConfigurableObjectType
+//           Logic     CtrlProgramType        InputVariables (command tags), OutputVariables (everything else)
+//   EBus/DO0 ...      DeviceType             one per terminal in the I/O mirror, ParameterSet = its channels
+//
+// Nothing is duplicated, the groups reference the tag variables and the IO folder, so values and history are the same.
+// The server doesn't load the DI and PLCopen nodesets, the few types used here are stand-ins with the standard
+// node ids and no members, enough for clients to recognise them.
+use std::collections::HashSet;
+
+use opcua::server::address_space::{AddressSpace, ObjectBuilder, ObjectTypeBuilder, VariableBuilder};
+use opcua::types::{
+    BuildInfo, DataTypeId, LocalizedText, NodeId, ObjectId, ObjectTypeId, QualifiedName, ReferenceTypeId, Variant, VariableTypeId,
+};
+use gipop_shm::{IoChannel, TagDef};
+
+use crate::layout::TagLayout;
+use crate::node_manager::GipopNodeManager;
+
+pub const DI_NAMESPACE: &str = "http://opcfoundation.org/UA/DI/";
+pub const PLCOPEN_NAMESPACE: &str = "http://PLCopen.org/OpcUa/IEC61131-3/";
+
+// Ids from Opc.Ua.Di.NodeSet2.xml and Opc.Ua.PLCopen.NodeSet2_V1.02.xml
+const DI_TOPOLOGY_ELEMENT_TYPE: u32 = 1001;
+const DI_DEVICE_TYPE: u32 = 1002;
+const DI_CONFIGURABLE_OBJECT_TYPE: u32 = 1004;
+const DI_FUNCTIONAL_GROUP_TYPE: u32 = 1005;
+const DI_DEVICE_SET: u32 = 5001;
+const PLCOPEN_CTRL_CONFIGURATION_TYPE: u32 = 1001;
+const PLCOPEN_CTRL_RESOURCE_TYPE: u32 = 1002;
+const PLCOPEN_CTRL_PROGRAM_TYPE: u32 = 1004;
+
+pub struct Devices {
+    ns: u16,
+    di: u16,
+    device_set: NodeId,
+    known: HashSet<String>, // terminals that have a device
+}
+
+impl Devices {
+    pub fn new(layout: &TagLayout, di: u16, plcopen: u16, manager: &GipopNodeManager, tags: &[TagDef], build_info: &BuildInfo) -> Self {
+        let ns = layout.ns;
+        let address_space = manager.address_space();
+        let mut address_space = address_space.write();
+        add_types(&mut address_space, di, plcopen);
+
+        let device_set = NodeId::new(di, DI_DEVICE_SET);
+        ObjectBuilder::new(&device_set, QualifiedName::new(di, "DeviceSet"), "DeviceSet")
+            .organized_by(ObjectId::ObjectsFolder)
+            .insert(&mut address_space);
+
+        let node = |path: &str| NodeId::new(ns, format!("devices/{}", path));
+        let object = |address_space: &mut AddressSpace, path: &str, browse_name: QualifiedName, ty: NodeId, parent: &NodeId| {
+            let id = node(path);
+            let display_name = browse_name.name.to_string();
+            ObjectBuilder::new(&id, browse_name, display_name.as_str())
+                .has_type_definition(ty)
+                .component_of(parent.clone())
+                .insert(address_space);
+            id
+        };
+        let configurable = NodeId::new(di, DI_CONFIGURABLE_OBJECT_TYPE);
+        let functional_group = NodeId::new(di, DI_FUNCTIONAL_GROUP_TYPE);
+
+        let plc = object(&mut address_space, "plc", QualifiedName::new(ns, "Gipop PLC"), NodeId::new(plcopen, PLCOPEN_CTRL_CONFIGURATION_TYPE), &device_set);
+        add_nameplate(&mut address_space, ns, di, "plc", &[
+            ("Manufacturer", Variant::from(LocalizedText::from(build_info.manufacturer_name.as_ref()))),
+            ("Model", Variant::from(LocalizedText::from(build_info.product_name.as_ref()))),
+            ("SoftwareRevision", Variant::from(build_info.software_version.clone())),
+            ("DeviceRevision", Variant::from(build_info.build_number.clone())),
+        ]);
+
+        let resources = object(&mut address_space, "plc/Resources", QualifiedName::new(plcopen, "Resources"), configurable.clone(), &plc);
+        let resource = object(&mut address_space, "plc/Resource", QualifiedName::new(ns, "Resource"), NodeId::new(plcopen, PLCOPEN_CTRL_RESOURCE_TYPE), &resources);
+        let global_vars = object(&mut address_space, "plc/Resource/GlobalVars", QualifiedName::new(plcopen, "GlobalVars"), functional_group.clone(), &resource);
+        let programs = object(&mut address_space, "plc/Resource/Programs", QualifiedName::new(plcopen, "Programs"), configurable, &resource);
+        let logic = object(&mut address_space, "plc/Resource/Programs/Logic", QualifiedName::new(ns, "Logic"), NodeId::new(plcopen, PLCOPEN_CTRL_PROGRAM_TYPE), &programs);
+        let inputs = object(&mut address_space, "plc/Resource/Programs/Logic/InputVariables", QualifiedName::new(plcopen, "InputVariables"), functional_group.clone(), &logic);
+        let outputs = object(&mut address_space, "plc/Resource/Programs/Logic/OutputVariables", QualifiedName::new(plcopen, "OutputVariables"), functional_group, &logic);
+
+        for tag in tags {
+            let variable = layout.node(tag);
+            address_space.insert_reference(&global_vars, &variable, ReferenceTypeId::Organizes);
+            let group = if tag.writable() { &inputs } else { &outputs };
+            address_space.insert_reference(group, &variable, ReferenceTypeId::Organizes);
+        }
+
+        Self { ns, di, device_set, known: HashSet::new() }
+    }
+
+    /// A device for every terminal that showed up in the I/O mirror since the last call. The IO folder's
+    /// nodes have to exist already, call after IoNodes::update.
+    pub fn update(&mut self, manager: &GipopNodeManager, channels: &[IoChannel]) {
+        let new: Vec<IoChannel> = channels.iter()
+            .filter(|channel| !self.known.contains(&terminal_path(channel)))
+            .copied()
+            .collect();
+        if new.is_empty() {
+            return;
+        }
+
+        let address_space = manager.address_space();
+        let mut address_space = address_space.write();
+        for channel in new {
+            let terminal = terminal_path(&channel);
+            let device = NodeId::new(self.ns, format!("devices/{}", terminal));
+            let parameter_set = NodeId::new(self.ns, format!("devices/{}/ParameterSet", terminal));
+            if self.known.insert(terminal.clone()) {
+                ObjectBuilder::new(&device, QualifiedName::new(self.ns, terminal.as_str()), terminal.as_str())
+                    .has_type_definition(NodeId::new(self.di, DI_DEVICE_TYPE))
+                    .component_of(self.device_set.clone())
+                    .insert(&mut address_space);
+                ObjectBuilder::new(&parameter_set, QualifiedName::new(self.di, "ParameterSet"), "ParameterSet")
+                    .has_type_definition(ObjectTypeId::BaseObjectType)
+                    .component_of(device)
+                    .insert(&mut address_space);
+            }
+
+            for path in [channel.path(), format!("{}/Status", channel.path())] {
+                address_space.insert_reference(&parameter_set, &NodeId::new(self.ns, format!("io/{}", path)), ReferenceTypeId::Organizes);
+            }
+        }
+        log::info!("[OPC UA sync] {} devices in the DeviceSet", self.known.len());
+    }
+}
+
+// "EBus/DO0/Ch3" -> "EBus/DO0"
+fn terminal_path(channel: &IoChannel) -> String {
+    let mut terminal = channel.address();
+    terminal.channel = 0;
+    terminal.path()
+}
+
+// DI's nameplate properties, in the DI namespace like the spec declares them
+fn add_nameplate(address_space: &mut AddressSpace, ns: u16, di: u16, path: &str, properties: &[(&str, Variant)]) {
+    let device = NodeId::new(ns, format!("devices/{}", path));
+    for (name, value) in properties {
+        let data_type = if matches!(value, Variant::LocalizedText(_)) { DataTypeId::LocalizedText } else { DataTypeId::String };
+        VariableBuilder::new(&NodeId::new(ns, format!("devices/{}/{}", path, name)), QualifiedName::new(di, *name), *name)
+            .property_of(device.clone())
+            .has_type_definition(VariableTypeId::PropertyType)
+            .data_type(data_type)
+            .value(value.clone())
+            .insert(address_space);
+    }
+}
+
+// Stand-ins for the DI and PLCopen types used above, see the module comment
+fn add_types(address_space: &mut AddressSpace, di: u16, plcopen: u16) {
+    let types = [
+        (NodeId::new(di, DI_TOPOLOGY_ELEMENT_TYPE), "TopologyElementType", NodeId::from(ObjectTypeId::BaseObjectType), true),
+        (NodeId::new(di, DI_DEVICE_TYPE), "DeviceType", NodeId::new(di, DI_TOPOLOGY_ELEMENT_TYPE), true),
+        (NodeId::new(di, DI_CONFIGURABLE_OBJECT_TYPE), "ConfigurableObjectType", NodeId::from(ObjectTypeId::BaseObjectType), false),
+        (NodeId::new(di, DI_FUNCTIONAL_GROUP_TYPE), "FunctionalGroupType", NodeId::from(ObjectTypeId::FolderType), false),
+        (NodeId::new(plcopen, PLCOPEN_CTRL_CONFIGURATION_TYPE), "CtrlConfigurationType", NodeId::new(di, DI_DEVICE_TYPE), false),
+        (NodeId::new(plcopen, PLCOPEN_CTRL_RESOURCE_TYPE), "CtrlResourceType", NodeId::new(di, DI_DEVICE_TYPE), false),
+        (NodeId::new(plcopen, PLCOPEN_CTRL_PROGRAM_TYPE), "CtrlProgramType", NodeId::from(ObjectTypeId::BaseObjectType), false),
+    ];
+    for (id, name, supertype, is_abstract) in types {
+        ObjectTypeBuilder::new(&id, QualifiedName::new(id.namespace, name), name)
+            .is_abstract(is_abstract)
+            .subtype_of(supertype)
+            .insert(address_space);
+    }
+}
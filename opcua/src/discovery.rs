@@ -0,0 +1,182 @@
+// Making the server findable, from [opcua.discovery]:
+//
+// - server_url: a Local Discovery Server or GDS we register with every interval_s, RegisterServer2 with our mDNS
+//   name and capabilities (an LDS-ME announces us on mDNS from those), plain RegisterServer for discovery servers
+//   that don't know RegisterServer2. On shutdown we register once more as offline.
+// - mdns: announce the endpoint on mDNS ourselves, _opcua-tcp._tcp with the path and capabilities in TXT as Part 12
+//   describes, for networks without an LDS-ME.
+//
+// Clients then find our discovery URLs through FindServers (or FindServersOnNetwork) on the LDS, or browse mDNS.
+use std::time::Duration;
+
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use opcua::client::{ClientBuilder, IdentityToken, Session};
+use opcua::crypto::SecurityPolicy;
+use opcua::server::ServerConfig as UaServerConfig;
+use opcua::types::{
+    ApplicationType, ExtensionObject, LocalizedText, MdnsDiscoveryConfiguration, MessageSecurityMode, RegisteredServer, StatusCode,
+    UAString, UserTokenPolicy,
+};
+
+use crate::config::DiscoveryConfig;
+
+const MDNS_SERVICE: &str = "_opcua-tcp._tcp.local.";
+
+#[derive(Clone)]
+pub struct Registration {
+    server_url: String,
+    interval: Duration,
+    pki_dir: String,
+    server: RegisteredServer,
+    mdns: MdnsDiscoveryConfiguration,
+}
+
+impl Registration {
+    /// None when there's no discovery server to register with
+    pub fn new(config: &DiscoveryConfig, server: &UaServerConfig) -> Option<Self> {
+        if config.server_url.is_empty() {
+            return None;
+        }
+        Some(Self {
+            server_url: config.server_url.clone(),
+            interval: Duration::from_secs(config.interval_s.max(1)),
+            pki_dir: server.pki_dir.to_string_lossy().into_owned(),
+            server: RegisteredServer {
+                server_uri: server.application_uri.as_str().into(),
+                product_uri: server.product_uri.as_str().into(),
+                server_names: Some(vec![LocalizedText::new("", &server.application_name)]),
+                server_type: ApplicationType::Server,
+                gateway_server_uri: UAString::null(),
+                discovery_urls: Some(server.discovery_urls.iter().map(|url| url.as_str().into()).collect()),
+                semaphore_file_path: UAString::null(),
+                is_online: true,
+            },
+            mdns: MdnsDiscoveryConfiguration {
+                mdns_server_name: mdns_name(config, server).into(),
+                server_capabilities: Some(config.capabilities.iter().map(|cap| cap.as_str().into()).collect()),
+            },
+        })
+    }
+
+    /// Registers every interval until the server stops. A discovery server that's down only costs a warning, we
+    /// keep trying.
+    pub async fn run(self) {
+        log::info!("[Discovery] Registering with {} every {:?}", self.server_url, self.interval);
+        let mut registered = false;
+        loop {
+            match self.register(true).await {
+                Ok(()) if !registered => {
+                    log::info!("[Discovery] Registered with {}", self.server_url);
+                    registered = true;
+                }
+                Ok(()) => {}
+                Err(e) => {
+                    log::warn!("[Discovery] Registering with {} failed: {}", self.server_url, e);
+                    registered = false;
+                }
+            }
+            tokio::time::sleep(self.interval).await;
+        }
+    }
+
+    /// Tells the discovery server we're gone, so it doesn't hand out our URL until our registration times out
+    pub async fn unregister(&self) {
+        match self.register(false).await {
+            Ok(()) => log::info!("[Discovery] Unregistered from {}", self.server_url),
+            Err(e) => log::warn!("[Discovery] Unregistering from {} failed: {}", self.server_url, e),
+        }
+    }
+
+    async fn register(&self, online: bool) -> Result<(), String> {
+        let server = RegisteredServer { is_online: online, ..self.server.clone() };
+        // our own certificate, the discovery server trusts the server rather than some client of it
+        let mut client = ClientBuilder::new()
+            .application_name("Gipop Discovery Registration")
+            .application_uri(server.server_uri.as_ref())
+            .pki_dir(self.pki_dir.as_str())
+            .trust_server_certs(true)
+            .session_retry_limit(0)
+            .client()
+            .map_err(|errors| format!("invalid client config: {}", errors.join(", ")))?;
+        let (session, event_loop) = client
+            .connect_to_matching_endpoint(
+                (self.server_url.as_str(), SecurityPolicy::None.to_str(), MessageSecurityMode::None, UserTokenPolicy::anonymous()),
+                IdentityToken::Anonymous,
+            )
+            .await
+            .map_err(|status| format!("connect failed ({})", status))?;
+        let running = event_loop.spawn();
+        let result = register_with(&session, server, &self.mdns).await;
+        let _ = session.disconnect().await;
+        let _ = running.await;
+        result
+    }
+}
+
+async fn register_with(session: &Session, server: RegisteredServer, mdns: &MdnsDiscoveryConfiguration) -> Result<(), String> {
+    if !session.wait_for_connection().await {
+        return Err("session didn't come up".to_owned());
+    }
+    match session.register_server2(server.clone(), Some(vec![ExtensionObject::from_message(mdns.clone())])).await {
+        Ok(_) => Ok(()),
+        // Part 4: fall back to RegisterServer when the discovery server doesn't implement RegisterServer2
+        Err(StatusCode::BadServiceUnsupported) => {
+            session.register_server(server).await.map_err(|status| format!("RegisterServer failed ({})", status))
+        }
+        Err(status) => Err(format!("RegisterServer2 failed ({})", status)),
+    }
+}
+
+/// Announces the discovery URLs on mDNS, for as long as the returned daemon lives. None when mdns is off or the
+/// daemon can't start.
+pub fn announce(config: &DiscoveryConfig, server: &UaServerConfig) -> Option<ServiceDaemon> {
+    if !config.mdns {
+        return None;
+    }
+    let daemon = match ServiceDaemon::new() {
+        Ok(daemon) => daemon,
+        Err(e) => {
+            log::error!("[Discovery] mDNS unavailable: {}", e);
+            return None;
+        }
+    };
+    let name = mdns_name(config, server);
+    let host = format!("{}.local.", hostname());
+    let caps = config.capabilities.join(",");
+    for url in &server.discovery_urls {
+        let Some((port, path)) = port_and_path(url) else {
+            log::warn!("[Discovery] Not announcing {}, not an opc.tcp URL", url);
+            continue;
+        };
+        // the mDNS host name is what clients connect to, whatever host the URL has
+        let properties = [("path", path), ("caps", caps.as_str())];
+        match ServiceInfo::new(MDNS_SERVICE, &name, &host, "", port, &properties[..]) {
+            Ok(info) => match daemon.register(info.enable_addr_auto()) {
+                Ok(()) => log::info!("[Discovery] Announcing '{}' on mDNS, {}:{}{}", name, host, port, path),
+                Err(e) => log::error!("[Discovery] mDNS announcement failed: {}", e),
+            },
+            Err(e) => log::error!("[Discovery] Invalid mDNS service for {}: {}", url, e),
+        }
+    }
+    Some(daemon)
+}
+
+fn mdns_name(config: &DiscoveryConfig, server: &UaServerConfig) -> String {
+    config.mdns_server_name.clone().unwrap_or_else(|| server.application_name.clone())
+}
+
+// "opc.tcp://host:4855/path" -> (4855, "/path")
+fn port_and_path(url: &str) -> Option<(u16, &str)> {
+    let rest = url.strip_prefix("opc.tcp://")?;
+    let (authority, path) = rest.find('/').map_or((rest, "/"), |slash| (&rest[..slash], &rest[slash..]));
+    let port = authority.rsplit_once(':').map_or(Some(4840), |(_, port)| port.parse().ok())?;
+    Some((port, path))
+}
+
+fn hostname() -> String {
+    std::fs::read_to_string("/etc/hostname")
+        .ok()
+        .map(|name| name.trim().to_owned())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "gipop".to_owned())
+}
@@ -0,0 +1,133 @@
+// The server's identity and endpoints: async-opcua's own config file (base_config) for what gipop.toml doesn't
+// cover, user tokens and certificates mostly, with [opcua] on top of it. Without a base config the server starts
+// from async-opcua's defaults and a self-signed certificate in ./pki.
+//
+// host is where we listen, hostnames what clients reach us by. They differ behind NAT or a reverse proxy, or when
+// listening on 0.0.0.0: the discovery URLs are then one per hostname, and so are the DNS/IP SANs of the self-signed
+// certificate, which clients check against the host they connected to. A certificate that's already in the PKI
+// directory is kept, we only warn when it doesn't cover a hostname (delete it to have it recreated).
+use std::net::IpAddr;
+use std::path::Path;
+
+use opcua::core::config::Config;
+use opcua::crypto::{CertificateStore, SecurityPolicy, X509Data};
+use opcua::server::{ServerConfig as UaServerConfig, ServerEndpoint, ANONYMOUS_USER_TOKEN_ID};
+use opcua::types::MessageSecurityMode;
+
+use crate::config::{OpcuaConfig, SecurityModeName, SecurityPolicyName};
+
+pub fn server_config(cfg: &OpcuaConfig) -> Result<UaServerConfig, String> {
+    let base = Path::new(&cfg.base_config);
+    let mut config = if !cfg.base_config.is_empty() && base.exists() {
+        UaServerConfig::load(base).map_err(|e| format!("Failed to load {}: {:?}", base.display(), e))?
+    }
+    else {
+        log::info!("No OPC UA base config, starting from defaults");
+        UaServerConfig { create_sample_keypair: true, pki_dir: "./pki".into(), ..Default::default() }
+    };
+
+    if let Some(name) = &cfg.application_name {
+        config.application_name = name.clone();
+    }
+    if let Some(uri) = &cfg.application_uri {
+        config.application_uri = uri.clone();
+        config.product_uri = uri.clone();
+    }
+    if let Some(host) = &cfg.host {
+        config.tcp_config.host = host.clone();
+    }
+    if let Some(port) = cfg.port {
+        config.tcp_config.port = port;
+    }
+    if !cfg.hostnames.is_empty() {
+        config.discovery_urls = cfg.hostnames.iter().map(|host| endpoint_url(host, config.tcp_config.port)).collect();
+    }
+    else if cfg.host.is_some() || cfg.port.is_some() {
+        config.discovery_urls = vec![endpoint_url(&config.tcp_config.host, config.tcp_config.port)];
+    }
+
+    if !cfg.endpoints.is_empty() {
+        let all_tokens: Vec<String> = config.user_tokens.keys().cloned()
+            .chain([ANONYMOUS_USER_TOKEN_ID.to_owned()])
+            .collect();
+        config.endpoints = cfg.endpoints.iter().map(|endpoint| {
+            let id = format!("{} {:?} {:?}", endpoint.path, endpoint.security_policy, endpoint.security_mode);
+            let tokens = endpoint.user_tokens.as_deref().unwrap_or(&all_tokens);
+            let endpoint = ServerEndpoint::new(
+                endpoint.path.as_str(),
+                security_policy(endpoint.security_policy),
+                security_mode(endpoint.security_mode),
+                tokens,
+            );
+            (id, endpoint)
+        }).collect();
+        config.default_endpoint = None;
+    }
+
+    // the simple node manager owns whatever is in its namespace, the server's own URI belongs to diagnostics
+    if cfg.namespace_uri == config.application_uri {
+        return Err(format!("[opcua] namespace_uri can't be the application URI {}", config.application_uri));
+    }
+    if !cfg.hostnames.is_empty() {
+        certificate_for(&config, &cfg.hostnames)?;
+    }
+    Ok(config)
+}
+
+// IPv6 addresses go in brackets
+fn endpoint_url(host: &str, port: u16) -> String {
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V6(address)) => format!("opc.tcp://[{}]:{}/", address, port),
+        _ => format!("opc.tcp://{}:{}/", host, port),
+    }
+}
+
+// Creates the self-signed certificate with the hostnames in it, before async-opcua would create one with only the
+// local host name. An existing certificate is left alone.
+fn certificate_for(config: &UaServerConfig, hostnames: &[String]) -> Result<(), String> {
+    let mut store = CertificateStore::new(&config.pki_dir);
+    if let Ok(cert) = store.read_own_cert() {
+        let names = cert.alt_names().unwrap_or_default();
+        for host in hostnames.iter().filter(|host| !names.iter().any(|name| name.eq_ignore_ascii_case(host))) {
+            log::warn!("The server certificate doesn't cover {}, clients connecting by that name will reject it", host);
+        }
+        return Ok(());
+    }
+    if !config.create_sample_keypair {
+        return Ok(()); // async-opcua reports the missing certificate
+    }
+    let data = X509Data {
+        key_size: 2048,
+        common_name: config.application_name.clone(),
+        organization: config.application_name.clone(),
+        organizational_unit: config.application_name.clone(),
+        country: "MY".to_owned(),
+        state: String::new(),
+        // the application URI has to be the first SAN, Part 6
+        alt_host_names: [config.application_uri.clone()].into_iter().chain(hostnames.iter().cloned()).collect(),
+        certificate_duration_days: 365 * 10,
+    };
+    store.create_and_store_application_instance_cert(&data, false)
+        .map_err(|e| format!("Failed to create the server certificate in {}: {}", config.pki_dir.display(), e))?;
+    log::info!("Created a self-signed server certificate for {}", hostnames.join(", "));
+    Ok(())
+}
+
+fn security_policy(name: SecurityPolicyName) -> SecurityPolicy {
+    match name {
+        SecurityPolicyName::None => SecurityPolicy::None,
+        SecurityPolicyName::Basic128Rsa15 => SecurityPolicy::Basic128Rsa15,
+        SecurityPolicyName::Basic256 => SecurityPolicy::Basic256,
+        SecurityPolicyName::Basic256Sha256 => SecurityPolicy::Basic256Sha256,
+        SecurityPolicyName::Aes128Sha256RsaOaep => SecurityPolicy::Aes128Sha256RsaOaep,
+        SecurityPolicyName::Aes256Sha256RsaPss => SecurityPolicy::Aes256Sha256RsaPss,
+    }
+}
+
+fn security_mode(name: SecurityModeName) -> MessageSecurityMode {
+    match name {
+        SecurityModeName::None => MessageSecurityMode::None,
+        SecurityModeName::Sign => MessageSecurityMode::Sign,
+        SecurityModeName::SignAndEncrypt => MessageSecurityMode::SignAndEncrypt,
+    }
+}
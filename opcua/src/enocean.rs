@@ -0,0 +1,227 @@
+// EnOcean telegrams as OPC UA events, so SCADA can log button presses and sensor transmissions without polling.
+// Every telegram the PLC passes on (ITEM_ENOCEAN_TELEGRAM) becomes an EnOceanTelegramEventType event, notified on
+// the Server object and on Objects/EnOcean:
+//
+// SenderId   String      the sender's 32 bit ID in hex, "0181A1B2"
+// SenderName String      from [[enocean.devices]], the ID again for unknown senders
+// Eep        String      EnOcean equipment profile from [[enocean.devices]], e.g. "F6-02-01". Just the RORG ("F6")
+//                        for unknown senders, the telegram doesn't say more
+// Data       ByteString  the data bytes as received, DATA_BYTE3 first
+// Payload    String      the data decoded per EEP, e.g. "Rocker A I pressed" or "21.5 °C". Empty if we can't decode it
+// Rssi       Int16       signal strength in dBm, Null when the gateway doesn't report it (the KL6581 doesn't)
+//
+// Message is "<sender name>: <payload>", SourceNode the EnOcean object.
+use std::collections::HashMap;
+
+use opcua::nodes::{BaseEventType, Event, EventField};
+use opcua::server::address_space::{ObjectBuilder, ObjectTypeBuilder, VariableBuilder};
+use opcua::server::SubscriptionCache;
+use opcua::types::{
+    AttributeId, ByteString, DataTypeId, DateTime, EventNotifier, NodeId, NumericRange, ObjectId, ObjectTypeId, QualifiedName, UAString,
+    Variant, VariableTypeId,
+};
+
+use crate::config::EnoceanConfig;
+use crate::node_manager::GipopNodeManager;
+
+const SEVERITY: u16 = 100; // informational
+
+// ESP2 ORG codes the KL6581 reports -> radio RORG, which EEPs are named by
+const ORG_TO_RORG: [(u8, u8); 3] = [(0x05, 0xF6), (0x06, 0xD5), (0x07, 0xA5)];
+const RORG_1BS: u8 = 0xD5; // one data byte, contacts
+const RORG_4BS: u8 = 0xA5; // four data bytes, sensors
+
+/// One received telegram
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Telegram {
+    pub sender: u32,
+    pub rorg: u8,
+    pub data: [u8; 4], // DATA_BYTE3..DATA_BYTE0, RPS and 1BS only use DATA_BYTE3
+    pub rssi: Option<i16>, // dBm
+}
+
+impl Telegram {
+    /// From the KL6581 input process image as logic.rs passes it on, an ESP2 style telegram:
+    /// [0] SB, [1] ORG, [2..6] sender ID (MSB first), [6..10] DATA_BYTE3..DATA_BYTE0, [10] STATUS, [11] KL6583 address
+    pub fn from_kl6581(image: &[u8]) -> Option<Self> {
+        let image: &[u8; 12] = image.get(..12)?.try_into().ok()?;
+        let org = image[1];
+        let rorg = ORG_TO_RORG.iter().find(|(esp2, _)| *esp2 == org).map_or(org, |(_, rorg)| *rorg);
+        Some(Self {
+            sender: u32::from_be_bytes([image[2], image[3], image[4], image[5]]),
+            rorg,
+            data: [image[6], image[7], image[8], image[9]],
+            rssi: None,
+        })
+    }
+
+    // 1BS and 4BS telegrams with the LRN bit (bit 3 of their last data byte) cleared are teach-in requests, not data
+    fn is_teach_in(&self) -> bool {
+        match self.rorg {
+            RORG_1BS => self.data[0] & 0b1000 == 0,
+            RORG_4BS => self.data[3] & 0b1000 == 0,
+            _ => false,
+        }
+    }
+}
+
+/// What `telegram` means under `eep`, None for profiles we don't decode
+pub fn decode(eep: &str, telegram: &Telegram) -> Option<String> {
+    if telegram.is_teach_in() {
+        return Some("Teach-in".to_owned());
+    }
+    let [db3, db2, db1, db0] = telegram.data;
+    let scale = |raw: u8, raw_max: f64, max: f64| raw as f64 * max / raw_max;
+    match eep.to_ascii_uppercase().as_str() {
+        // light and blind control rockers, the same R1 + energy bow part in both
+        "F6-02-01" | "F6-02-02" => {
+            let button = ["A I", "A O", "B I", "B O"][((db3 >> 5) & 0b11) as usize];
+            let pressed = db3 & 0b1_0000 != 0;
+            Some(if pressed { format!("Rocker {} pressed", button) } else { "Rocker released".to_owned() })
+        }
+        "D5-00-01" => Some(if db3 & 1 != 0 { "Contact closed" } else { "Contact open" }.to_owned()),
+        // 0..40 °C, inverted
+        "A5-02-05" => Some(format!("{:.1} °C", 40.0 - scale(db1, 255.0, 40.0))),
+        "A5-04-01" => Some(format!("{:.1} °C, {:.1} %RH", scale(db1, 250.0, 40.0), scale(db2, 250.0, 100.0))),
+        // illumination 0..1020 lx (DB1) or 0..510 lx (DB2) by the range bit, supply voltage 0..5.1 V
+        "A5-06-02" => {
+            let lux = if db0 & 1 == 0 { scale(db1, 255.0, 1020.0) } else { scale(db2, 255.0, 510.0) };
+            Some(format!("{:.0} lx, {:.1} V", lux, scale(db3, 255.0, 5.1)))
+        }
+        _ => None,
+    }
+}
+
+pub struct EnoceanEvents {
+    source: NodeId, // Objects/EnOcean
+    event_type: NodeId,
+    devices: HashMap<u32, (String, String)>, // sender -> (name, EEP)
+    events: u64, // for event ids
+}
+
+impl EnoceanEvents {
+    pub fn new(ns: u16, manager: &GipopNodeManager, config: &EnoceanConfig) -> Self {
+        let source = NodeId::new(ns, "enocean");
+        let event_type = NodeId::new(ns, "EnOceanTelegramEventType");
+        let address_space = manager.address_space();
+        let mut address_space = address_space.write();
+        ObjectTypeBuilder::new(&event_type, "EnOceanTelegramEventType", "EnOceanTelegramEventType")
+            .subtype_of(ObjectTypeId::BaseEventType)
+            .insert(&mut address_space);
+        for (name, data_type) in [
+            ("SenderId", DataTypeId::String),
+            ("SenderName", DataTypeId::String),
+            ("Eep", DataTypeId::String),
+            ("Data", DataTypeId::ByteString),
+            ("Payload", DataTypeId::String),
+            ("Rssi", DataTypeId::Int16),
+        ] {
+            VariableBuilder::new(&NodeId::new(ns, format!("EnOceanTelegramEventType/{}", name)), name, name)
+                .property_of(event_type.clone())
+                .has_type_definition(VariableTypeId::PropertyType)
+                .data_type(data_type)
+                .insert(&mut address_space);
+        }
+        ObjectBuilder::new(&source, "EnOcean", "EnOcean")
+            .organized_by(ObjectId::ObjectsFolder)
+            .event_notifier(EventNotifier::SUBSCRIBE_TO_EVENTS)
+            .insert(&mut address_space);
+
+        let mut devices = HashMap::new();
+        for device in &config.devices {
+            match u32::from_str_radix(device.id.trim_start_matches("0x"), 16) {
+                Ok(id) => {
+                    let name = if device.name.is_empty() { device.id.clone() } else { device.name.clone() };
+                    devices.insert(id, (name, device.eep.to_ascii_uppercase()));
+                }
+                Err(_) => log::error!("[[enocean.devices]] '{}' isn't a hex sender ID", device.id),
+            }
+        }
+        log::info!("Added EnOcean telegram events, {} known devices", devices.len());
+        Self { source, event_type, devices, events: 0 }
+    }
+
+    /// A telegram event for the KL6581 image in an ITEM_ENOCEAN_TELEGRAM event
+    pub fn telegram(&mut self, subscriptions: &SubscriptionCache, image: &[u8]) {
+        let Some(telegram) = Telegram::from_kl6581(image) else {
+            log::warn!("[OPC UA sync] EnOcean telegram too short: {:02x?}", image);
+            return;
+        };
+        let sender_id = format!("{:08X}", telegram.sender);
+        let (name, eep) = match self.devices.get(&telegram.sender) {
+            Some((name, eep)) => (name.clone(), eep.clone()),
+            None => (sender_id.clone(), format!("{:02X}", telegram.rorg)),
+        };
+        let payload = decode(&eep, &telegram).unwrap_or_default();
+        log::info!("[OPC UA sync] EnOcean {} ({}): {}", name, eep, if payload.is_empty() { format!("{:02x?}", telegram.data) } else { payload.clone() });
+
+        self.events += 1;
+        let event_id = ByteString::from(format!("gipop-enocean-{}-{}", DateTime::now().ticks(), self.events).into_bytes());
+        let message = if payload.is_empty() { format!("{}: telegram", name) } else { format!("{}: {}", name, payload) };
+        let event = TelegramEvent {
+            base: BaseEventType::new_now(self.event_type.clone(), event_id, message.as_str())
+                .set_source_node(self.source.clone())
+                .set_source_name(name.as_str().into())
+                .set_severity(SEVERITY),
+            sender_id: sender_id.into(),
+            sender_name: name.into(),
+            eep: eep.into(),
+            data: ByteString::from(telegram.data.to_vec()),
+            payload: payload.into(),
+            rssi: telegram.rssi,
+        };
+        let server: NodeId = ObjectId::Server.into();
+        subscriptions.notify_events([(&event as &dyn Event, &server), (&event as &dyn Event, &self.source)].into_iter());
+    }
+}
+
+struct TelegramEvent {
+    base: BaseEventType,
+    sender_id: UAString,
+    sender_name: UAString,
+    eep: UAString,
+    data: ByteString,
+    payload: UAString,
+    rssi: Option<i16>,
+}
+
+impl TelegramEvent {
+    fn field(&self, browse_path: &[QualifiedName]) -> Option<Variant> {
+        let path: Vec<&str> = browse_path.iter().map(|name| name.name.as_ref()).collect();
+        Some(match path[..] {
+            ["SenderId"] => Variant::from(self.sender_id.clone()),
+            ["SenderName"] => Variant::from(self.sender_name.clone()),
+            ["Eep"] => Variant::from(self.eep.clone()),
+            ["Data"] => Variant::from(self.data.clone()),
+            ["Payload"] => Variant::from(self.payload.clone()),
+            ["Rssi"] => self.rssi.map_or(Variant::Empty, Variant::Int16),
+            _ => return None,
+        })
+    }
+}
+
+impl EventField for TelegramEvent {
+    fn get_value(&self, attribute_id: AttributeId, index_range: &NumericRange, remaining_path: &[QualifiedName]) -> Variant {
+        match (attribute_id, self.field(remaining_path)) {
+            (AttributeId::Value, Some(value)) => value,
+            _ => self.base.get_value(attribute_id, index_range, remaining_path),
+        }
+    }
+}
+
+impl Event for TelegramEvent {
+    fn get_field(&self, type_definition_id: &NodeId, attribute_id: AttributeId, index_range: &NumericRange, browse_path: &[QualifiedName]) -> Variant {
+        match (attribute_id, self.field(browse_path)) {
+            (AttributeId::Value, Some(value)) => value,
+            _ => self.base.get_field(type_definition_id, attribute_id, index_range, browse_path),
+        }
+    }
+
+    fn time(&self) -> &DateTime {
+        self.base.time()
+    }
+
+    fn event_type_id(&self) -> &NodeId {
+        self.base.event_type_id()
+    }
+}
@@ -0,0 +1,93 @@
+// Event history: HistoryReadEvents on the Alarms object, answered from the gateway's event store ([event_store] in
+// gipop.toml, historian::event_store) when [opcua.history] events_path points at its database. Alarm transitions,
+// EnOcean telegrams and the audit trail reach back as far as the store keeps them, across restarts of this server.
+//
+// Stored events come back as the types they were reported as: AlarmConditionType for alarm, clear and ack (with the
+// ActiveState or AckedState the transition left), AuditWriteUpdateEventType for audit entries (ClientUserId the user)
+// and BaseEventType for the rest. Of the event filter only the select clauses are applied, the where clause isn't
+// evaluated.
+use historian::record::StoredEvent;
+use opcua::nodes::{BaseEventType, Event, EventField};
+use opcua::types::{
+    AttributeId, ByteString, DateTime, EventFilter, HistoryEvent, HistoryEventFieldList, LocalizedText, NodeId, NumericRange, ObjectTypeId,
+    QualifiedName, UAString, Variant,
+};
+
+/// `events` with the fields `filter` selects
+pub fn history_event(events: &[StoredEvent], filter: &EventFilter) -> HistoryEvent {
+    let clauses = filter.select_clauses.as_deref().unwrap_or(&[]);
+    let events = events.iter().map(|stored| {
+        let event = StoredEventType::new(stored);
+        let fields = clauses.iter().map(|clause| {
+            let attribute_id = AttributeId::from_u32(clause.attribute_id).unwrap_or(AttributeId::Value);
+            event.get_field(&clause.type_definition_id, attribute_id, &clause.index_range, clause.browse_path.as_deref().unwrap_or(&[]))
+        });
+        HistoryEventFieldList { event_fields: Some(fields.collect()) }
+    });
+    HistoryEvent { events: Some(events.collect()) }
+}
+
+struct StoredEventType {
+    base: BaseEventType,
+    kind: String,
+    source: UAString,
+    user: UAString,
+}
+
+impl StoredEventType {
+    fn new(stored: &StoredEvent) -> Self {
+        let event_type = match stored.kind.as_str() {
+            "alarm" | "clear" | "ack" => ObjectTypeId::AlarmConditionType,
+            "audit" => ObjectTypeId::AuditWriteUpdateEventType,
+            _ => ObjectTypeId::BaseEventType,
+        };
+        // store ids are never reused, they make event ids that stay the same however often an event is read back
+        let event_id = ByteString::from(format!("gipop-stored-{}", stored.id).into_bytes());
+        let time = chrono::DateTime::from_timestamp_millis(stored.ts_ms as i64).map(DateTime::from).unwrap_or_else(DateTime::null);
+        let source_name = if stored.source.is_empty() { &stored.kind } else { &stored.source };
+        let base = BaseEventType::new(event_type, event_id, stored.message.as_str(), time)
+            .set_source_name(source_name.as_str().into())
+            .set_severity(stored.severity);
+        Self { base, kind: stored.kind.clone(), source: stored.source.as_str().into(), user: stored.user.as_str().into() }
+    }
+
+    fn field(&self, browse_path: &[QualifiedName]) -> Option<Variant> {
+        let path: Vec<&str> = browse_path.iter().map(|name| name.name.as_ref()).collect();
+        let state = |on: bool, on_text: &str, off_text: &str| Variant::from(LocalizedText::from(if on { on_text } else { off_text }));
+        Some(match (self.kind.as_str(), &path[..]) {
+            ("alarm" | "clear" | "ack", ["ConditionName"]) => Variant::from(self.source.clone()),
+            ("alarm" | "clear", ["ActiveState"]) => state(self.kind == "alarm", "Active", "Inactive"),
+            ("alarm" | "clear", ["ActiveState", "Id"]) => Variant::Boolean(self.kind == "alarm"),
+            ("alarm" | "ack", ["AckedState"]) => state(self.kind == "ack", "Acknowledged", "Unacknowledged"),
+            ("alarm" | "ack", ["AckedState", "Id"]) => Variant::Boolean(self.kind == "ack"),
+            ("audit", ["ClientUserId"]) => Variant::from(self.user.clone()),
+            _ => return None,
+        })
+    }
+}
+
+impl EventField for StoredEventType {
+    fn get_value(&self, attribute_id: AttributeId, index_range: &NumericRange, remaining_path: &[QualifiedName]) -> Variant {
+        match (attribute_id, self.field(remaining_path)) {
+            (AttributeId::Value, Some(value)) => value,
+            _ => self.base.get_value(attribute_id, index_range, remaining_path),
+        }
+    }
+}
+
+impl Event for StoredEventType {
+    fn get_field(&self, type_definition_id: &NodeId, attribute_id: AttributeId, index_range: &NumericRange, browse_path: &[QualifiedName]) -> Variant {
+        match (attribute_id, self.field(browse_path)) {
+            (AttributeId::Value, Some(value)) => value,
+            _ => self.base.get_field(type_definition_id, attribute_id, index_range, browse_path),
+        }
+    }
+
+    fn time(&self) -> &DateTime {
+        self.base.time()
+    }
+
+    fn event_type_id(&self) -> &NodeId {
+        self.base.event_type_id()
+    }
+}
@@ -0,0 +1,150 @@
+// Multi-channel terminals as one array variable each, next to the per channel nodes in the IO folder:
+//
+// Objects/IO
+//   EBus/DI0/Channels   Boolean[n]  element i is channel i+1, e.g. Boolean[16] for an EL1809
+//   EBus/DO0/Channels   Boolean[n]  same, writable by engineers: writing forces the written channels
+//   EBus/AI0/Channels   Double[n]   same unit as the channel nodes, e.g. Double[4] for an EL3024
+//
+// Reads take an index range like any array (IndexRange "3" on EBus/DI0/Channels is channel 4). So do
+// writes: "2:3" with a Boolean[2] forces channels 3 and 4, no range forces every channel. Forces go through the
+// same command as Operator.ForceChannel, releasing them is UnforceChannel's job.
+//
+// An array's status is its worst channel's (see channel_status in main.rs), channels that aren't good keep their
+// last value in it like the per channel nodes do.
+use std::collections::HashMap;
+
+use opcua::server::address_space::{AccessLevel, VariableBuilder};
+use opcua::server::SubscriptionCache;
+use opcua::types::{DataTypeId, DataValue, NodeId, NumericRange, StatusCode, Variant};
+use gipop_shm::io_mirror::{IO_AI, IO_DI, IO_DO};
+use gipop_shm::{Action, IoAddress, IoChannel, RingItem, Subscriber};
+
+use crate::audit;
+use crate::node_manager::GipopNodeManager;
+use crate::{channel_status, value_with_status};
+
+pub struct IoArrays {
+    ns: u16,
+    folder: NodeId,
+    table: Subscriber,
+    last: HashMap<IoAddress, (Vec<f64>, StatusCode)>, // terminal (channel 0) -> what its array holds
+}
+
+impl IoArrays {
+    /// `folder` is the IO folder
+    pub fn new(ns: u16, folder: NodeId, table: Subscriber) -> Self {
+        Self { ns, folder, table, last: HashMap::new() }
+    }
+
+    pub fn update(&mut self, manager: &GipopNodeManager, subscriptions: &SubscriptionCache, channels: &[IoChannel], plc_alive: bool) {
+        let mut terminals: HashMap<IoAddress, Vec<(f64, StatusCode)>> = HashMap::new();
+        for channel in channels.iter().filter(|channel| channel.channel > 0 && [IO_DI, IO_DO, IO_AI].contains(&channel.kind)) {
+            let values = terminals.entry(IoAddress { channel: 0, ..channel.address() }).or_default();
+            let idx = channel.channel as usize - 1;
+            if values.len() <= idx {
+                values.resize(idx + 1, (0.0, StatusCode::Good));
+            }
+            values[idx] = (channel.value, channel_status(channel, plc_alive));
+        }
+
+        let mut changed = Vec::new();
+        for (terminal, channels) in terminals {
+            let last = self.last.get(&terminal).map(|(values, _)| values);
+            if last.is_some_and(|last| last.len() != channels.len()) {
+                log::warn!("[OPC UA sync] {} changed its channel count, keeping the array as it was", terminal.path());
+                continue;
+            }
+            let values: Vec<f64> = channels.iter().enumerate().map(|(idx, (value, status))| match last {
+                Some(last) if !status.is_good() && *status != StatusCode::UncertainEngineeringUnitsExceeded => last[idx],
+                _ => *value,
+            }).collect();
+            let status = worst(channels.iter().map(|(_, status)| *status));
+            match self.last.get(&terminal) {
+                Some(last) if *last == (values.clone(), status) => continue,
+                Some(_) => {}
+                None => self.add(manager, terminal, values.len()),
+            }
+            changed.push((self.node(terminal), value_with_status(array(terminal, &values), status)));
+            self.last.insert(terminal, (values, status));
+        }
+        if !changed.is_empty() {
+            let _ = manager.set_values(subscriptions, changed.iter().map(|(id, value)| (id, None, value.clone())));
+        }
+    }
+
+    fn node(&self, terminal: IoAddress) -> NodeId {
+        NodeId::new(self.ns, format!("io/{}/Channels", terminal.path()))
+    }
+
+    fn add(&self, manager: &GipopNodeManager, terminal: IoAddress, len: usize) {
+        let id = self.node(terminal);
+        let name = format!("{}/Channels", terminal.path());
+        let access = if terminal.kind == IO_DO { AccessLevel::CURRENT_READ | AccessLevel::CURRENT_WRITE } else { AccessLevel::CURRENT_READ };
+        VariableBuilder::new(&id, name.as_str(), name.as_str())
+            .data_type(if terminal.kind == IO_AI { DataTypeId::Double } else { DataTypeId::Boolean })
+            .value(array(terminal, &vec![0.0; len]))
+            .value_rank(1)
+            .array_dimensions(&[len as u32])
+            .access_level(access)
+            .user_access_level(access)
+            .organized_by(self.folder.clone())
+            .insert(&mut manager.address_space().write());
+        log::info!("[OPC UA sync] Added {} with {} channels", name, len);
+
+        if terminal.kind == IO_DO {
+            let callbacks = manager.inner();
+            callbacks.require(id.clone(), Action::ForceIo);
+            let table = self.table.clone();
+            callbacks.add_command_write(id, move |value: DataValue, range: &NumericRange| force(&table, terminal, len, value, range));
+        }
+    }
+}
+
+// Bad beats uncertain beats good
+fn worst(statuses: impl Iterator<Item = StatusCode>) -> StatusCode {
+    statuses.max_by_key(|status| (!status.is_good() as u8) + status.is_bad() as u8).unwrap_or(StatusCode::Good)
+}
+
+fn array(terminal: IoAddress, values: &[f64]) -> Variant {
+    if terminal.kind == IO_AI {
+        Variant::from(values.to_vec())
+    }
+    else {
+        Variant::from(values.iter().map(|value| *value != 0.0).collect::<Vec<bool>>())
+    }
+}
+
+// Forces the DO channels a write to the array covers, each force audited, returns the last force's sequence number.
+// Nothing is forced unless every element is a Boolean
+fn force(table: &Subscriber, terminal: IoAddress, len: usize, value: DataValue, range: &NumericRange) -> Result<u32, StatusCode> {
+    let Some(Variant::Array(array)) = value.value else {
+        return Err(StatusCode::BadTypeMismatch);
+    };
+    let Some(values) = array.values.iter().map(|element| match element {
+        Variant::Boolean(on) => Some(*on),
+        _ => None,
+    }).collect::<Option<Vec<bool>>>() else {
+        return Err(StatusCode::BadTypeMismatch);
+    };
+
+    let first = match *range {
+        NumericRange::None if values.len() == len => 0,
+        NumericRange::Index(idx) if values.len() == 1 => idx as usize,
+        NumericRange::Range(low, high) if high.checked_sub(low).map(|n| n + 1) == Some(values.len() as u32) => low as usize,
+        NumericRange::None | NumericRange::Index(_) | NumericRange::Range(..) => return Err(StatusCode::BadIndexRangeInvalid),
+        _ => return Err(StatusCode::BadWriteNotSupported), // multi dimensional, this is a flat array
+    };
+    if values.is_empty() {
+        return Err(StatusCode::BadIndexRangeInvalid);
+    }
+    if first + values.len() > len {
+        return Err(StatusCode::BadIndexRangeNoData);
+    }
+
+    let mut seq = 0;
+    for (idx, on) in values.into_iter().enumerate() {
+        let channel = IoAddress { channel: (first + idx + 1) as u16, ..terminal };
+        seq = audit::command(table, RingItem::force(channel, on as u8 as f64))?;
+    }
+    Ok(seq)
+}
@@ -0,0 +1,80 @@
+// Where PLC tags go in the address space, from [opcua.address_space]: the folder they're filed under and their node
+// ids. Everything that points at a tag's variable (alarms, devices, analog properties...) asks this for its id.
+//
+// With area_namespaces the top level folder of a tag is its plant area (or station): "Area 1/Lights" is in area
+// "Area 1". Every area gets its own namespace, urn:GipopPlcServer:Area1 for the default namespace_uri, holding the
+// area's folders and tag variables. Tags without a folder stay in the PLC's namespace, as does everything else.
+use std::collections::HashMap;
+
+use gipop_shm::TagDef;
+use opcua::types::NodeId;
+
+use crate::config::{AddressSpaceConfig, NodeIdScheme};
+
+/// Area -> namespace URI, for every area the tags are in. Empty unless area_namespaces is set.
+pub fn area_namespaces(namespace_uri: &str, config: &AddressSpaceConfig, tags: &[TagDef]) -> Vec<(String, String)> {
+    let mut areas: Vec<(String, String)> = Vec::new();
+    if !config.area_namespaces {
+        return areas;
+    }
+    for area in tags.iter().filter_map(|tag| tag.folder_path().next()) {
+        if !areas.iter().any(|(known, _)| known == area) {
+            // whitespace doesn't belong in a URI, "Area 1" -> "Area1"
+            areas.push((area.to_owned(), format!("{}:{}", namespace_uri, area.split_whitespace().collect::<String>())));
+        }
+    }
+    areas
+}
+
+pub struct TagLayout {
+    pub ns: u16, // the PLC's namespace
+    areas: HashMap<String, u16>, // area -> its namespace index
+    tags_folder: String,
+    node_ids: NodeIdScheme,
+}
+
+impl TagLayout {
+    pub fn new(ns: u16, config: &AddressSpaceConfig, areas: HashMap<String, u16>) -> Self {
+        Self { ns, areas, tags_folder: config.tags_folder.clone(), node_ids: config.node_ids }
+    }
+
+    /// The tag's variable
+    pub fn node(&self, tag: &TagDef) -> NodeId {
+        NodeId::new(self.ns_of(&tag.folder), self.id(tag))
+    }
+
+    /// A node below the tag's variable, e.g. its EURange property
+    pub fn child(&self, tag: &TagDef, name: &str) -> NodeId {
+        NodeId::new(self.ns_of(&tag.folder), format!("{}/{}", self.id(tag), name))
+    }
+
+    fn id(&self, tag: &TagDef) -> String {
+        match self.node_ids {
+            NodeIdScheme::Name => tag.name.clone(),
+            NodeIdScheme::Path => tag.folder_path().chain([tag.name.as_str()]).collect::<Vec<_>>().join("/"),
+        }
+    }
+
+    /// Browse name of the folder holding every tag, None when the tags go straight under Objects
+    pub fn tags_folder(&self) -> Option<&str> {
+        (!self.tags_folder.is_empty()).then_some(self.tags_folder.as_str())
+    }
+
+    /// Node id of the folder at `path` below the tags folder ("" for the tags folder itself),
+    /// e.g. "plc_tags/Area 1/Lights"
+    pub fn folder(&self, path: &str) -> NodeId {
+        match path {
+            "" => NodeId::new(self.ns, "plc_tags"),
+            path => NodeId::new(self.ns_of(path), format!("plc_tags/{}", path)),
+        }
+    }
+
+    // Namespace of whatever is at `path`, the area's if its top level folder is one
+    fn ns_of(&self, path: &str) -> u16 {
+        path.split('/')
+            .find(|part| !part.is_empty())
+            .and_then(|area| self.areas.get(area))
+            .copied()
+            .unwrap_or(self.ns)
+    }
+}
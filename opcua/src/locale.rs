@@ -0,0 +1,36 @@
+// Tag display names and descriptions in the client's language. The PLC declares them per locale (TagDef::text), the
+// address space holds the default (first) locale and reads of DisplayName/Description on a tag are answered in the
+// locale the session asked for when it was activated, following Part 4's LocaleId rules:
+//
+// "ms-MY" requested   "ms-MY" if the tag has it, else "ms", else the default
+// "ms" requested      "ms", else any "ms-XX", else the default
+// nothing requested   the default
+//
+// Locales are tried in the order the client listed them. Tags without texts keep their name as display name.
+use gipop_shm::TagText;
+use opcua::server::node_manager::RequestContext;
+use opcua::types::LocalizedText;
+
+/// The locales the client asked for in ActivateSession, most preferred first
+pub fn requested(context: &RequestContext) -> Vec<String> {
+    context.session.read().locale_ids().iter().filter_map(|id| id.value().clone()).collect()
+}
+
+/// The text to show a client that asked for `locales`, None when the tag has no texts at all
+pub fn best<'a>(texts: &'a [TagText], locales: &[String]) -> Option<&'a TagText> {
+    let language = |locale: &str| locale.split('-').next().unwrap_or_default().to_ascii_lowercase();
+    locales.iter()
+        .find_map(|wanted| {
+            texts.iter().find(|text| text.locale.eq_ignore_ascii_case(wanted))
+                .or_else(|| texts.iter().find(|text| language(&text.locale) == language(wanted)))
+        })
+        .or_else(|| texts.first())
+}
+
+pub fn display_name(text: &TagText) -> LocalizedText {
+    LocalizedText::new(&text.locale, &text.display_name)
+}
+
+pub fn description(text: &TagText) -> LocalizedText {
+    LocalizedText::new(&text.locale, &text.description)
+}
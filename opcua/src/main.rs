@@ -3,83 +3,156 @@
 // Copyright (C) 2017-2024 Adam Lock
 // Modified 2025 Ander Jiloh
 
-use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
-use std::sync::{Arc, Mutex};
+mod alarms;
+mod analog;
+mod audit;
+mod bus_diag;
+mod clients;
+mod config;
+mod devices;
+mod discovery;
+mod endpoints;
+#[cfg(feature = "enocean")]
+mod enocean;
+mod event_history;
+mod io_arrays;
+mod layout;
+mod locale;
+mod node_manager;
+mod operator;
+// The tag list the PLC is built with, only for simulation when there's no PLC to read the directory from
+#[path = "../../plc/src/tags.rs"]
+#[allow(dead_code)]
+mod plc_tags;
+mod pubsub;
+mod runtime;
+mod sampling;
+mod writes;
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
-use std::{fs::OpenOptions, path::Path};
 
 use log::warn;
-use opcua::server::address_space::{Variable, VariableBuilder, AccessLevel, NodeType};
+use opcua::server::address_space::{VariableBuilder, AccessLevel};
 use opcua::server::diagnostics::NamespaceMetadata;
-use opcua::server::node_manager::memory::{
-    simple_node_manager, InMemoryNodeManager, SimpleNodeManager, SimpleNodeManagerImpl,
-};
 use opcua::server::{ServerBuilder, SubscriptionCache};
-use opcua::types::{BuildInfo, DataValue, DateTime, NodeId, UAString, StatusCode, DataTypeId, NumericRange, Variant, TimestampsToReturn};
-mod shared;
-use crate::shared::{SharedData, SHM_PATH, map_shared_memory, read_data, write_data};
+use opcua::types::{AttributeId, BuildInfo, DataValue, DateTime, NodeId, StatusCode, DataTypeId, Variant};
+use gipop_shm::telemetry::{self, TelemetryConfig};
+use gipop_shm::{IoChannel, IpcConfig, Liveness, Quality, Startup, Step, Subscriber, TagDef, TagSample, TagType, TagValue, Users};
+use gipop_shm::ring::{ITEM_ENOCEAN_TELEGRAM, ITEM_ALARM, ITEM_ALARM_ACK};
+use gipop_shm::blob::{BLOB_ENOCEAN_TELEGRAM, BLOB_DIAGNOSTIC, BLOB_TOPOLOGY};
+use gipop_shm::io_mirror::{
+    AI_STATUS_ERROR, AI_STATUS_OVERRANGE, AI_STATUS_TXPDO_STATE, AI_STATUS_UNDERRANGE, IO_AI, IO_STATUS_BUS_DOWN,
+};
+
+use alarms::AlarmNodes;
+use bus_diag::{FieldbusNodes, FIELDBUS_NAMESPACE};
+use clients::ClientNodes;
+use devices::{Devices, DI_NAMESPACE, PLCOPEN_NAMESPACE};
+#[cfg(feature = "enocean")]
+use enocean::EnoceanEvents;
+use io_arrays::IoArrays;
+use config::ServerConfig;
+use layout::TagLayout;
+use node_manager::{gipop_node_manager, GipopNodeManager};
+use runtime::RuntimeNodes;
+use sampling::Decision;
+
+const CONFIG_PATH: &str = "../gipop.toml"; // same working directory assumption as server.conf
 
 #[tokio::main]
 async fn main() {
-    env_logger::init();
-    // Open shared memory file. NOTE: The file is created by plc/main.rs
-    // PLC must be running
-    let file = OpenOptions::new().read(true).write(true).open(SHM_PATH).unwrap();
-    let mut mmap = map_shared_memory(&file);
-
-    let shared_data = Arc::new(Mutex::new(SharedData {
-        temperature: 0.0,
-        humidity: 0.0,
-        status: 0,
-        area_1_lights: 0,
-        area_2_lights: 0,
-        area_1_lights_hmi_cmd: 0,
-    }));
+    let config = gipop_shm::project::config_path(CONFIG_PATH, env!("CARGO_PKG_VERSION")).unwrap_or_else(|e| {
+        eprintln!("gipop_opcua: {}", e);
+        std::process::exit(1);
+    });
+    let config = config.as_str();
+    gipop_shm::logging::init(config, "gipop_opcua");
+    gipop_shm::logging::reload_on_sighup();
+    let ipc = match IpcConfig::load(config) {
+        Ok(ipc) => ipc,
+        Err(e) => {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let cfg = match ServerConfig::load(config) {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    match TelemetryConfig::load(config) {
+        Ok(trace) => telemetry::init(&trace, "gipop_opcua"),
+        Err(e) => log::error!("{}, not tracing", e),
+    }
+    // who may write, acknowledge, force..., the same [users] the PLC's gRPC service checks
+    let users = match Users::load(config) {
+        Ok(users) => users,
+        Err(e) => {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
 
-    // spawn polling task
-    let shared_data_clone = shared_data.clone();
-    tokio::spawn(async move {
-        loop {
-            {
-                let mut local = shared_data_clone.lock().unwrap();
-                let data = read_data(&mmap);
-                local.temperature = data.temperature;
-                local.humidity = data.humidity;
-                local.status = data.status;
-                local.area_1_lights = data.area_1_lights;
-                local.area_2_lights = data.area_2_lights;
-                local.area_1_lights_hmi_cmd = data.area_1_lights_hmi_cmd;
-
-                log::info!(
-                    "[OPC UA sync] temp: {}, humd: {}, stat: {}, area1: {}, area2: {}, area1_cmd: {}",
-                    local.temperature, local.humidity, local.status, local.area_1_lights, local.area_2_lights, local.area_1_lights_hmi_cmd
-                );
-            }
-            tokio::time::sleep(Duration::from_millis(100)).await;
+    // Attach to the PLC over the configured transport, the shm region/socket plc/main.rs creates. Started before the PLC
+    // (gipopd starts us once it publishes, by hand it can be the other way round) we wait [ipc] connect_timeout_ms for
+    // it, unless we're simulating it (--simulate or [opcua] simulate): then the address space is the same but the values
+    // are made up right away, see gipop_shm::sim.
+    // Mapped/connected once here, the poller and every node callback get a clone of the handle
+    let simulate = cfg.opcua.simulate || std::env::args().any(|arg| arg == "--simulate");
+    let mut startup = Startup::new("gipop_opcua");
+    let table = match startup.up(if simulate { Step::new("plc") } else { ipc.connect_step() }, || Subscriber::connect(&ipc)) {
+        Ok(t) => t,
+        Err(e) if simulate => {
+            log::warn!("{}, simulating the PLC", e);
+            Subscriber::simulate(&plc_tags::plc_tags())
         }
-    });
+        Err(e) => startup.fail(&e),
+    };
+    let tags = table.tags().to_vec();
+    log::info!("Found {} tags over {:?} IPC", tags.len(), ipc.transport);
 
-    // Create an OPC UA server with sample configuration and default node set
+    let build_info = BuildInfo {
+        product_uri: "https://github.com/freeopcua/async-opcua".into(),
+        manufacturer_name: "Pongipop Tohog Oundar Gipop".into(),
+        product_name: "Gipop OPC-UA Server".into(),
+        // Here you could use something to inject the build time, version, number at compile time
+        software_version: "0.1.0".into(),
+        build_number: "1".into(),
+        build_date: DateTime::now(),
+    };
+
+    // Identity and endpoints from [opcua] over the base server config, see endpoints.rs
+    let server_config = match endpoints::server_config(&cfg.opcua) {
+        Ok(config) => config,
+        Err(e) => {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    // discovery server registration and mDNS, see discovery.rs
+    let registration = discovery::Registration::new(&cfg.opcua.discovery, &server_config);
+    let _mdns = discovery::announce(&cfg.opcua.discovery, &server_config);
+    let areas = layout::area_namespaces(&cfg.opcua.namespace_uri, &cfg.opcua.address_space, &tags);
     let (server, handle) = ServerBuilder::new()
-        .with_config_from("../server.conf")
-        .build_info(BuildInfo {
-            product_uri: "https://github.com/freeopcua/async-opcua".into(),
-            manufacturer_name: "Pongipop Tohog Oundar Gipop".into(),
-            product_name: "Gipop OPC-UA Server".into(),
-            // Here you could use something to inject the build time, version, number at compile time
-            software_version: "0.1.0".into(),
-            build_number: "1".into(),
-            build_date: DateTime::now(),
-        })
-        .with_node_manager(simple_node_manager(
+        .with_config(server_config)
+        .build_info(build_info.clone())
+        .with_node_manager(gipop_node_manager(
             // Set the namespace for the node manager. For simple node managers this decides
-            // node ownership, so make sure to use a different value here than the application URI
-            // in server.conf, as that is the namespace used by the diagnostic node manager.
+            // node ownership, which is why it has to differ from the application URI, the namespace
+            // used by the diagnostic node manager.
             NamespaceMetadata {
-                namespace_uri: "urn:GipopPlcServer".to_owned(),
+                namespace_uri: cfg.opcua.namespace_uri.clone(),
                 ..Default::default()
             },
             "simple",
+            &cfg.opcua,
+            cfg.compression.clone(),
+            users,
+            areas.iter().map(|(_, uri)| uri.clone()).collect(),
         ))
         .trust_client_certs(true)
         .diagnostics_enabled(true)
@@ -87,21 +160,112 @@ async fn main() {
         .unwrap();
     let node_manager = handle
         .node_managers()
-        .get_of_type::<SimpleNodeManager>()
+        .get_of_type::<GipopNodeManager>()
         .unwrap();
-    let ns = handle.get_namespace_index("urn:GipopPlcServer").unwrap();
+    let ns = handle.get_namespace_index(&cfg.opcua.namespace_uri).unwrap();
+    let area_ns = areas.iter().map(|(area, uri)| (area.clone(), handle.get_namespace_index(uri).unwrap())).collect();
+    let layout = TagLayout::new(ns, &cfg.opcua.address_space, area_ns);
+    for (area, uri) in &areas {
+        log::info!("Area '{}' in namespace {}", area, uri);
+    }
+
+    // One variable per tag in the shm directory, filed under the folders the PLC declared
+    let mut tag_nodes = add_plc_variables(&layout, node_manager.clone(), handle.subscriptions().clone(), &tags, table.clone());
+    let mut alarm_nodes = AlarmNodes::new(&layout, &node_manager, &tags, table.clone());
+    operator::add_operator_methods(ns, &node_manager, &table, alarm_nodes.acks());
+    let plc_alive_id = add_diagnostics(ns, &node_manager);
+    let mut client_nodes = ClientNodes::new(ns, &node_manager, &NodeId::new(ns, "diagnostics"));
+    let mut runtime_nodes = RuntimeNodes::new(&layout, &node_manager, &table);
+    let mut io_nodes = IoNodes::new(ns, &node_manager);
+    let mut io_arrays = IoArrays::new(ns, io_nodes.folder.clone(), table.clone());
+    let di = handle.get_namespace_index(DI_NAMESPACE).unwrap();
+    let plcopen = handle.get_namespace_index(PLCOPEN_NAMESPACE).unwrap();
+    let mut devices = Devices::new(&layout, di, plcopen, &node_manager, &tags, &build_info);
+    let fieldbus = handle.get_namespace_index(FIELDBUS_NAMESPACE).unwrap();
+    let mut fieldbus_nodes = FieldbusNodes::new(fieldbus, &node_manager);
+    #[cfg(feature = "enocean")]
+    let mut enocean_events = EnoceanEvents::new(ns, &node_manager, &cfg.enocean);
+    pubsub::start(&cfg.pubsub, &table);
+
+    // spawn polling task
+    let poll_table = table.clone();
+    let subscriptions = handle.subscriptions().clone();
+    let mut plc = Liveness::new(ipc.heartbeat_timeout());
+    let server_handle = handle.clone();
+    tokio::spawn(async move {
+        let table = poll_table;
+        loop {
+            {
+                let _sync = tracing::info_span!("opcua_sync").entered();
+                // Heartbeats: ours out, the PLC's in
+                table.heartbeat();
+                if let Some(alive) = plc.update(table.plc_heartbeat()) {
+                    if alive {
+                        log::info!("[OPC UA sync] PLC heartbeat detected");
+                    }
+                    else {
+                        log::warn!("[OPC UA sync] PLC heartbeat went stale, values are no longer updated");
+                    }
+                    let _ = node_manager.set_value(&subscriptions, &plc_alive_id, None, DataValue::new_now(alive));
+                }
+
+                tag_nodes.update(&node_manager, &subscriptions, &table);
+                alarm_nodes.update(&node_manager, &subscriptions, &table);
+                runtime_nodes.update(&node_manager, &subscriptions, &table);
+                let io_channels = table.read_io();
+                io_nodes.update(&node_manager, &subscriptions, &io_channels, plc.is_alive());
+                io_arrays.update(&node_manager, &subscriptions, &io_channels, plc.is_alive());
+                devices.update(&node_manager, &io_channels);
+                fieldbus_nodes.update(&node_manager, &subscriptions, table.read_bus_diag());
+                client_nodes.update(&node_manager, &subscriptions, &server_handle);
+
+                let values = table.read_all();
+                let summary: Vec<String> = table.tags().iter()
+                    .zip(values.iter())
+                    .map(|(tag, value)| format!("{}: {}", tag.name, value.as_f64()))
+                    .collect();
+
+                log::info!("[OPC UA sync] {}", summary.join(", "));
 
-    // Add some variables of our own
-    add_plc_variables(ns, node_manager, handle.subscriptions().clone());
+                while let Some(event) = table.pop_event() {
+                    match event.kind {
+                        #[cfg(feature = "enocean")]
+                        ITEM_ENOCEAN_TELEGRAM => enocean_events.telegram(&subscriptions, event.payload()),
+                        #[cfg(not(feature = "enocean"))]
+                        ITEM_ENOCEAN_TELEGRAM => {} // no EnOcean nodes in this build
+                        ITEM_ALARM => log::info!("[OPC UA sync] Alarm on tag {}: {}", event.tag, event.value != 0),
+                        ITEM_ALARM_ACK => alarm_nodes.acknowledged(event.tag as usize),
+                        other => log::warn!("[OPC UA sync] Unknown event kind {}", other),
+                    }
+                }
+
+                while let Some(blob) = table.pop_blob() {
+                    match blob.kind {
+                        BLOB_ENOCEAN_TELEGRAM => log::info!("[OPC UA sync] EnOcean process image: {:02x?}", blob.data),
+                        BLOB_DIAGNOSTIC => log::warn!("[OPC UA sync] PLC diagnostic: {}", blob.text()),
+                        BLOB_TOPOLOGY => log::info!("[OPC UA sync] Bus topology: {}", blob.text()),
+                        other => log::warn!("[OPC UA sync] Unknown payload kind {}", other),
+                    }
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    });
 
     // If you don't register a ctrl-c handler, the server will close without
     // informing clients.
+    if let Some(registration) = registration.clone() {
+        tokio::spawn(registration.run());
+    }
     let handle_c = handle.clone();
     tokio::spawn(async move {
         if let Err(e) = tokio::signal::ctrl_c().await {
             warn!("Failed to register CTRL-C handler: {e}");
             return;
         }
+        if let Some(registration) = &registration {
+            registration.unregister().await;
+        }
         handle_c.cancel();
     });
     
@@ -111,161 +275,336 @@ async fn main() {
 }
 
 fn add_plc_variables(
-    ns: u16,
-    manager: Arc<InMemoryNodeManager<SimpleNodeManagerImpl>>,
-    _subscriptions: Arc<SubscriptionCache>,
-) {
-    let temp_node = NodeId::new(ns, "temperature");
-    let humd_node = NodeId::new(ns, "humidity");
-    let stat_node = NodeId::new(ns, "status");
-    let ar1_lights_node = NodeId::new(ns, "area 1 lights");
-    let ar2_lights_node = NodeId::new(ns, "area 2 lights");
-    let ar1_lights_hmi_cmd_node = NodeId::new(ns, "area 1 lights hmi cmd");
-
+    layout: &TagLayout,
+    manager: Arc<GipopNodeManager>,
+    subscriptions: Arc<SubscriptionCache>,
+    tags: &[TagDef],
+    table: Subscriber,
+) -> TagNodes {
     let address_space = manager.address_space();
 
     {
         let mut address_space = address_space.write();
 
-        // Create a sample folder under objects folder
-        let plc_folder_id = NodeId::new(ns, "plc_tags");
-        address_space.add_folder(
-            &plc_folder_id,
-            "PlcTags", // browse_name
-            "PlcTags", // display_name
-            &NodeId::objects_folder_id(), // parent_node_id
-        );
-
-        // Add some variables to our folder
-        let builder =
-            VariableBuilder::new(&ar1_lights_hmi_cmd_node, "area 1 lights hmi cmd", "area 1 lights hmi cmd")
-                .value(0_u32)
-                .data_type(DataTypeId::UInt32)
-                .historizing(false)
-                .access_level(AccessLevel::all())
-                .user_access_level(AccessLevel::all());
-        let ar1_lights_hmi_cmd_node_var = builder.build();
-        
-        let _ = address_space.add_variables(
-            vec![
-                Variable::new(&temp_node, "temperature", "temperature", 0_f32),
-                Variable::new(&humd_node, "humidity", "humidity", 0_f32),
-                Variable::new(&stat_node, "status", "status", 0_u32),
-                Variable::new(&ar1_lights_node, "area 1 lights", "area 1 lights", 0_u32),
-                Variable::new(&ar2_lights_node, "area 2 lights", "area 2 lights", 0_u32),
-                ar1_lights_hmi_cmd_node_var,
-            ],
-            &plc_folder_id,
-        );
-        
+        // Everything goes under the tags folder ([opcua.address_space]), in the folder tree the PLC declared for each tag
+        let plc_folder_id = match layout.tags_folder() {
+            Some(name) => {
+                let id = layout.folder("");
+                address_space.add_folder(&id, name, name, &NodeId::objects_folder_id());
+                id
+            }
+            None => NodeId::objects_folder_id(),
+        };
+
+        let mut folders = HashSet::new();
+        for tag in tags {
+            let mut parent = plc_folder_id.clone();
+            let mut path = String::new();
+            for part in tag.folder_path() {
+                path = if path.is_empty() { part.to_owned() } else { format!("{}/{}", path, part) };
+                let folder_id = layout.folder(&path);
+                if folders.insert(path.clone()) {
+                    address_space.add_folder(&folder_id, part, part, &parent);
+                }
+                parent = folder_id;
+            }
+
+            // Tag names are the browse names and, unless the tag has localized texts (locale.rs), the display names.
+            // Node ids per layout.node. Historizing only if the tag has a history backend ([opcua.history])
+            let historizing = manager.inner().historize(layout.node(tag), &tag.name, tag.ty);
+            let mut access = if tag.writable() { AccessLevel::all() } else { AccessLevel::CURRENT_READ | AccessLevel::HISTORY_READ };
+            if !historizing {
+                access.remove(AccessLevel::HISTORY_READ);
+            }
+            let mut builder = VariableBuilder::new(&layout.node(tag), tag.name.as_str(), tag.name.as_str());
+            if let Some(text) = tag.texts.first() {
+                builder = builder.display_name(locale::display_name(text)).description(locale::description(text));
+            }
+            builder
+                .value(tag_to_variant(tag.ty.default_value()))
+                .data_type(data_type_of(tag.ty))
+                .historizing(historizing)
+                .access_level(access)
+                .user_access_level(access)
+                .organized_by(parent)
+                .has_type_definition(analog::type_definition(tag))
+                .insert(&mut address_space);
+            analog::add_analog_properties(&mut address_space, layout, tag);
+        }
+        log::info!("Added {} tags in {} folders", tags.len(), folders.len());
+    }
+
+    for tag in tags {
+        let node = layout.node(tag);
+        if let Some(eu_range) = tag.eu_range {
+            manager.inner().analog(node.clone(), eu_range);
+        }
+        if !tag.texts.is_empty() {
+            manager.inner().localize(node.clone(), tag.texts.clone());
+        }
     }
+    // Writable tags are commands to the PLC, see writes.rs
+    writes::add_tag_writes(layout, &manager, tags, &table);
 
-    {
-        // Client write callback
-        manager.inner().add_write_callback(
-            ar1_lights_hmi_cmd_node.clone(),
-            move |val: DataValue, _| {
-                write_ar1_lights_to_shmem(val, &NumericRange::None)
+    // No read callbacks, reads and monitored items are served from the address space, which the polling task keeps
+    // current. Fill it in now so nobody sees the builder defaults.
+    let mut nodes = TagNodes {
+        ids: tags.iter().map(|tag| layout.node(tag)).collect(),
+        types: tags.iter().map(|tag| tag.ty).collect(),
+        last: vec![None; tags.len()],
+        reported: vec![None; tags.len()],
+        pending: vec![false; tags.len()],
+        connected: true,
+    };
+    nodes.update(&manager, &subscriptions, &table);
+    nodes
+}
+
+// Pushes tag values into the address space when they change, and on to monitored items as far as their sampling
+// interval and deadband ask for (sampling.rs). The PLC restamps every tag each cycle, only value or quality changes
+// count, so the source timestamp is that of the last change.
+struct TagNodes {
+    ids: Vec<NodeId>,
+    types: Vec<TagType>,
+    last: Vec<Option<TagSample>>, // what the address space holds, None forces the next update
+    reported: Vec<Option<(TagSample, Instant)>>, // what monitored items last heard and when
+    pending: Vec<bool>, // changed since, not reported yet
+    connected: bool,
+}
+
+impl TagNodes {
+    fn update(&mut self, manager: &GipopNodeManager, subscriptions: &SubscriptionCache, table: &Subscriber) {
+        if !table.is_connected() {
+            if self.connected {
+                log::error!("Lost connection to the PLC");
+                // last known values stay readable, flagged as such
+                let values = self.ids.iter().zip(self.last.iter()).filter_map(|(id, last)| {
+                    let mut value = sample_to_data_value((*last)?);
+                    value.status = Some(StatusCode::BadNoCommunication);
+                    Some((id, None, value))
+                });
+                let _ = manager.set_values(subscriptions, values);
+                self.last.iter_mut().for_each(|last| *last = None);
+                self.reported.iter_mut().for_each(|reported| *reported = None);
+                self.connected = false;
+            }
+            return;
+        }
+        self.connected = true;
+
+        let samples = table.read_all_samples();
+        let mut changed = Vec::new();
+        let mut report = Vec::new();
+        for (idx, sample) in samples.into_iter().enumerate() {
+            let id = &self.ids[idx];
+            if self.last[idx].is_none_or(|last| last.value != sample.value || last.quality != sample.quality) {
+                self.last[idx] = Some(sample);
+                manager.inner().record(&self.ids[idx], &sample);
+                changed.push((id, sample_to_data_value(sample)));
+                self.pending[idx] = true;
             }
-        );
-
-        manager.inner().add_read_callback(
-            temp_node.clone(),
-            move |_, _, _| {
-                Ok(DataValue::new_now(
-                    fetch_temp_from_shmem() // call fetcher function
-                )
-            )
-        });
-        manager.inner().add_read_callback(
-            humd_node.clone(),
-            move |_, _, _| {
-                Ok(DataValue::new_now(
-                    fetch_humd_from_shmem()// call fetcher function
-                )
-            )
-        });
-        manager.inner().add_read_callback(stat_node.clone(),
-        move |_, _, _| {
-            Ok(DataValue::new_now(
-                    fetch_status_from_shmem()// call fetcher function
-                )
-            )
-        });
-        manager.inner().add_read_callback(ar1_lights_node.clone(),
-        move |_, _, _| {
-            Ok(DataValue::new_now(
-                    fetch_ar1_lights_from_shmem() // call fetcher function
-                )
-            )
-        });
-        manager.inner().add_read_callback(ar2_lights_node.clone(),
-            move |_, _, _| {
-                Ok(DataValue::new_now(
-                    fetch_ar2_lights_from_shmem() // call fetcher function
-                )
-            )
-        });
+            if !self.pending[idx] {
+                continue;
+            }
+
+            // nobody subscribed means nobody to tell, the next subscriber gets the current value from the address space
+            let Some(sampling) = manager.inner().sampling(id) else {
+                self.pending[idx] = false;
+                self.reported[idx] = None;
+                continue;
+            };
+            match sampling::decide(sampling, self.types[idx], &sample, self.reported[idx].as_ref()) {
+                Decision::Report => {
+                    report.push((id, sample_to_data_value(sample)));
+                    self.reported[idx] = Some((sample, Instant::now()));
+                    self.pending[idx] = false;
+                }
+                Decision::Wait => {}
+                Decision::Drop => self.pending[idx] = false,
+            }
+        }
+
+        // joins the trace of the PLC publish that brought the newest value, see gipop_shm::telemetry
+        let newest = self.last.iter().flatten().map(|sample| sample.ts_ms).max().unwrap_or(0);
+        let _notify = (!changed.is_empty() || !report.is_empty())
+            .then(|| tracing::info_span!("opcua_notify", from_publish_ts = newest, changed = changed.len(), reported = report.len()).entered());
+        if !changed.is_empty() {
+            let address_space = manager.address_space();
+            let mut address_space = address_space.write();
+            for (id, value) in changed {
+                if let Some(variable) = address_space.find_variable_mut(id) {
+                    variable.set_data_value(value);
+                }
+            }
+        }
+        if !report.is_empty() {
+            subscriptions.notify_data_change(report.into_iter().map(|(id, value)| (value, id, AttributeId::Value)));
+        }
     }
+}
+
+// Server side diagnostics, not backed by PLC tags. Returns the PlcAlive node, kept up to date by the polling task
+fn add_diagnostics(ns: u16, manager: &GipopNodeManager) -> NodeId {
+    let address_space = manager.address_space();
+    let mut address_space = address_space.write();
 
+    let diagnostics_folder_id = NodeId::new(ns, "diagnostics");
+    address_space.add_folder(&diagnostics_folder_id, "Diagnostics", "Diagnostics", &NodeId::objects_folder_id());
+
+    let plc_alive_id = NodeId::new(ns, "plc_alive");
+    let plc_alive = VariableBuilder::new(&plc_alive_id, "PlcAlive", "PlcAlive")
+        .value(false)
+        .data_type(DataTypeId::Boolean)
+        .access_level(AccessLevel::CURRENT_READ)
+        .user_access_level(AccessLevel::CURRENT_READ)
+        .build();
+    let _ = address_space.add_variables(vec![plc_alive], &diagnostics_folder_id);
+
+    plc_alive_id
 }
 
-fn fetch_temp_from_shmem() -> f32 {
-    let file = OpenOptions::new().read(true).write(true).open(SHM_PATH).unwrap();
-    let mut mmap = map_shared_memory(&file);
-    let data = read_data(&mmap);
-    return data.temperature
+// Every terminal channel from the PLC's I/O mirror, one node for the value and one for the status bits.
+// Nodes are added as channels show up, so new terminals need no changes here. Values carry the channel's status
+// (channel_status), bad channels keep showing the last value they had while they were good.
+struct IoNodes {
+    ns: u16,
+    folder: NodeId,
+    known: HashSet<String>,
+    last_good: HashMap<String, f64>,
 }
 
-fn fetch_humd_from_shmem() -> f32 {
-    let file = OpenOptions::new().read(true).write(true).open(SHM_PATH).unwrap();
-    let mut mmap = map_shared_memory(&file);
-    let data = read_data(&mmap);
-    return data.humidity
+impl IoNodes {
+    fn new(ns: u16, manager: &GipopNodeManager) -> Self {
+        let folder = NodeId::new(ns, "io");
+        manager.address_space().write().add_folder(&folder, "IO", "IO", &NodeId::objects_folder_id());
+        Self { ns, folder, known: HashSet::new(), last_good: HashMap::new() }
+    }
+
+    fn update(&mut self, manager: &GipopNodeManager, subscriptions: &SubscriptionCache, channels: &[IoChannel], plc_alive: bool) {
+        let new: Vec<_> = channels.iter().map(|channel| channel.path()).filter(|path| !self.known.contains(path)).collect();
+        if !new.is_empty() {
+            let variables = new.iter().flat_map(|path| {
+                let value = VariableBuilder::new(&NodeId::new(self.ns, format!("io/{}", path)), path.as_str(), path.as_str())
+                    .value(0.0f64)
+                    .data_type(DataTypeId::Double)
+                    .access_level(AccessLevel::CURRENT_READ)
+                    .user_access_level(AccessLevel::CURRENT_READ)
+                    .build();
+                let status_name = format!("{}/Status", path);
+                let status = VariableBuilder::new(&NodeId::new(self.ns, format!("io/{}", status_name)), status_name.as_str(), status_name.as_str())
+                    .value(0u32)
+                    .data_type(DataTypeId::UInt32)
+                    .access_level(AccessLevel::CURRENT_READ)
+                    .user_access_level(AccessLevel::CURRENT_READ)
+                    .build();
+                [value, status]
+            }).collect();
+            let _ = manager.address_space().write().add_variables(variables, &self.folder);
+            log::info!("[OPC UA sync] Added {} I/O channels", new.len());
+            self.known.extend(new);
+        }
+
+        for channel in channels {
+            let path = channel.path();
+            let status = channel_status(channel, plc_alive);
+            let value = if status.is_good() {
+                self.last_good.insert(path.clone(), channel.value);
+                channel.value
+            }
+            else if status == StatusCode::UncertainEngineeringUnitsExceeded {
+                channel.value // clipped, but it still says which way
+            }
+            else {
+                self.last_good.get(&path).copied().unwrap_or(channel.value)
+            };
+            let _ = manager.set_value(subscriptions, &NodeId::new(self.ns, format!("io/{}", path)), None, value_with_status(value, status));
+            let _ = manager.set_value(subscriptions, &NodeId::new(self.ns, format!("io/{}/Status", path)), None, DataValue::new_now(channel.status));
+        }
+    }
 }
 
-fn fetch_status_from_shmem() -> u32 {
-    let file = OpenOptions::new().read(true).write(true).open(SHM_PATH).unwrap();
-    let mut mmap = map_shared_memory(&file);
-    let data = read_data(&mmap);
-    return data.status
+fn data_type_of(ty: TagType) -> DataTypeId {
+    match ty {
+        TagType::Bool => DataTypeId::Boolean,
+        TagType::UInt32 => DataTypeId::UInt32,
+        TagType::Int32 => DataTypeId::Int32,
+        TagType::Float32 => DataTypeId::Float,
+        TagType::Float64 => DataTypeId::Double,
+    }
 }
 
-fn fetch_ar1_lights_from_shmem() -> u32 {
-    let file = OpenOptions::new().read(true).write(true).open(SHM_PATH).unwrap();
-    let mut mmap = map_shared_memory(&file);
-    let data = read_data(&mmap);
-    return data.area_1_lights
+pub(crate) fn tag_to_variant(value: TagValue) -> Variant {
+    match value {
+        TagValue::Bool(b) => Variant::Boolean(b),
+        TagValue::UInt32(n) => Variant::UInt32(n),
+        TagValue::Int32(n) => Variant::Int32(n),
+        TagValue::Float32(f) => Variant::Float(f),
+        TagValue::Float64(f) => Variant::Double(f),
+    }
 }
 
-fn fetch_ar2_lights_from_shmem() -> u32 {
-    let file = OpenOptions::new().read(true).write(true).open(SHM_PATH).unwrap();
-    let mut mmap = map_shared_memory(&file);
-    let data = read_data(&mmap);
-    return data.area_2_lights
+// Source timestamp and status come from the PLC, only the server timestamp is ours
+fn sample_to_data_value(sample: TagSample) -> DataValue {
+    let source_timestamp = chrono::DateTime::from_timestamp_millis(sample.ts_ms as i64)
+        .filter(|_| sample.ts_ms != 0) // never published
+        .map(DateTime::from);
+    DataValue {
+        value: Some(tag_to_variant(sample.value)),
+        status: Some(quality_to_status(sample.quality)),
+        source_timestamp,
+        source_picoseconds: None,
+        server_timestamp: Some(DateTime::now()),
+        server_picoseconds: None,
+    }
 }
 
-fn write_ar1_lights_to_shmem(val: DataValue, _range: &NumericRange) -> StatusCode {
-    let file = match OpenOptions::new().read(true).write(true).open(SHM_PATH) {
-        Ok(f) => f,
-        Err(e) => {
-            log::error!("Failed to open shared memory file: {}", e);
-            return StatusCode::Bad;
-        }
-    };
+/// What the status bits of a channel from the I/O mirror make of its value. Without the PLC the mirror is frozen, every
+/// value is the last one it published.
+pub(crate) fn channel_status(channel: &IoChannel, plc_alive: bool) -> StatusCode {
+    let ai = |bit: u32| channel.kind == IO_AI && channel.status & bit != 0;
+    if !plc_alive {
+        StatusCode::UncertainNoCommunicationLastUsableValue
+    }
+    else if channel.status & IO_STATUS_BUS_DOWN != 0 {
+        StatusCode::BadNoCommunication
+    }
+    else if channel.value.is_nan() {
+        StatusCode::BadDeviceFailure // the PLC couldn't read it
+    }
+    else if ai(AI_STATUS_UNDERRANGE) {
+        StatusCode::BadSensorFailure
+    }
+    else if ai(AI_STATUS_OVERRANGE) {
+        StatusCode::UncertainEngineeringUnitsExceeded
+    }
+    else if ai(AI_STATUS_ERROR) {
+        StatusCode::BadDeviceFailure
+    }
+    else if ai(AI_STATUS_TXPDO_STATE) {
+        StatusCode::UncertainLastUsableValue
+    }
+    else {
+        StatusCode::Good
+    }
+}
 
-    let mut mmap = map_shared_memory(&file);
-    let mut data = read_data(&mmap);
+pub(crate) fn value_with_status(value: impl Into<Variant>, status: StatusCode) -> DataValue {
+    let now = DateTime::now();
+    DataValue {
+        value: Some(value.into()),
+        status: Some(status),
+        source_timestamp: Some(now),
+        server_timestamp: Some(now),
+        ..Default::default()
+    }
+}
 
-    match val.value {
-        Some(Variant::UInt32(n)) => {
-            data.area_1_lights_hmi_cmd = n;
-            write_data(&mut mmap, data);
-            StatusCode::Good
-        }
-        other => {
-            log::error!("Unexpected value type: {:?}", other);
-            StatusCode::Bad
-        }
+fn quality_to_status(quality: Quality) -> StatusCode {
+    match quality {
+        Quality::Good => StatusCode::Good,
+        Quality::Uncertain => StatusCode::UncertainLastUsableValue,
+        Quality::Bad => StatusCode::Bad,
+        Quality::BadNotConnected => StatusCode::BadNotConnected,
+        Quality::WaitingForInitialData => StatusCode::BadWaitingForInitialData,
     }
 }
@@ -0,0 +1,600 @@
+// Node manager for the PLC namespace: the simple node manager (address space, write callbacks) plus what it can't
+// do on its own, answering HistoryRead for PLC tags from the history backend each tag records into ([opcua.history]:
+// historian's in-memory History, SqlHistory or PgHistory), HistoryReadEvents on the Alarms object from the gateway's
+// event store (event_history.rs) and checking
+// the client's rights (gipop_shm::Users, [users]) before writes and method calls. It also owns the DI and PLCopen namespaces
+// for the DeviceSet (devices.rs), the fieldbus diagnostics namespace (bus_diag.rs) and the plant area namespaces
+// (layout.rs). Monitored items on tags are tracked with their sampling interval and deadband, see sampling.rs.
+// Writes to command nodes are handled here too rather than by write callbacks, so they can be audited (audit.rs).
+// DisplayName/Description reads on tags with localized texts are answered in the session's locale (locale.rs).
+use std::collections::{HashMap, HashSet};
+use std::ops::Deref;
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use historian::backend::{Compressed, HistoryBackend};
+use historian::compression::CompressionSettings;
+use historian::event_store::EventStore;
+use historian::history::{Aggregate, History};
+use historian::record::{EventQuery, Sample};
+use historian::postgres::PgHistory;
+use historian::sql::SqlHistory;
+use opcua::server::address_space::AddressSpace;
+use opcua::server::diagnostics::NamespaceMetadata;
+use opcua::server::node_manager::memory::{
+    InMemoryNodeManager, InMemoryNodeManagerImpl, InMemoryNodeManagerImplBuilder, SimpleNodeManagerBuilder, SimpleNodeManagerImpl,
+};
+use opcua::server::node_manager::{HistoryNode, MethodCall, ParsedReadValueId, RequestContext, ServerContext, WriteNode};
+use opcua::server::{CreateMonitoredItem, FilterType, MonitoredItemHandle, MonitoredItemRef, MonitoredItemUpdateRef};
+use opcua::sync::RwLock as AddressSpaceLock;
+use opcua::types::{
+    AttributeId, DataEncoding, DataValue, DateTime, HistoryData, NodeId, NumericRange, ObjectId, ReadEventDetails, ReadProcessedDetails,
+    ReadRawModifiedDetails, StatusCode, TimestampsToReturn, Variant,
+};
+use gipop_shm::{Action, TagSample, TagText, TagType, TagValue, Users};
+use tracing::Instrument;
+
+use crate::audit;
+use crate::bus_diag::FIELDBUS_NAMESPACE;
+use crate::config::{HistoryBackendName, HistoryConfig, OpcuaConfig};
+use crate::devices::{DI_NAMESPACE, PLCOPEN_NAMESPACE};
+use crate::event_history;
+use crate::locale;
+use crate::sampling::Sampling;
+
+pub type GipopNodeManager = InMemoryNodeManager<GipopNodeManagerImpl>;
+
+/// Sends a written value to the PLC, returns the command's sequence number
+pub type CommandWrite = Box<dyn Fn(DataValue, &NumericRange) -> Result<u32, StatusCode> + Send + Sync>;
+
+/// `areas` are the URIs of the plant area namespaces, see layout::area_namespaces. History records what `compression`
+/// lets through
+pub fn gipop_node_manager(
+    namespace: NamespaceMetadata,
+    name: &str,
+    config: &OpcuaConfig,
+    compression: CompressionSettings,
+    users: Users,
+    areas: Vec<String>,
+) -> GipopNodeManagerBuilder {
+    GipopNodeManagerBuilder {
+        simple: SimpleNodeManagerBuilder::new(namespace, name),
+        history: config.history.clone(),
+        compression,
+        users,
+        areas,
+    }
+}
+
+pub struct GipopNodeManagerBuilder {
+    simple: SimpleNodeManagerBuilder,
+    history: HistoryConfig,
+    compression: CompressionSettings,
+    users: Users,
+    areas: Vec<String>,
+}
+
+impl InMemoryNodeManagerImplBuilder for GipopNodeManagerBuilder {
+    type Impl = GipopNodeManagerImpl;
+
+    fn build(self, context: ServerContext, address_space: &mut AddressSpace) -> Self::Impl {
+        let companions = [DI_NAMESPACE, PLCOPEN_NAMESPACE, FIELDBUS_NAMESPACE].iter().copied()
+            .chain(self.areas.iter().map(String::as_str))
+            .map(|uri| {
+                let namespace_index = context.type_tree.write().namespaces_mut().add_namespace(uri);
+                address_space.add_namespace(uri, namespace_index);
+                NamespaceMetadata { namespace_uri: uri.to_owned(), namespace_index, ..Default::default() }
+            })
+            .collect();
+        GipopNodeManagerImpl {
+            simple: self.simple.build(context, address_space),
+            companions,
+            backends: open_backends(&self.history, &self.compression),
+            events: open_events(&self.history),
+            event_history: RwLock::new(HashSet::new()),
+            history: self.history,
+            historized: RwLock::new(HashMap::new()),
+            users: self.users,
+            required: RwLock::new(HashMap::new()),
+            eu_spans: RwLock::new(HashMap::new()),
+            sampling: RwLock::new(HashMap::new()),
+            commands: RwLock::new(HashMap::new()),
+            texts: RwLock::new(HashMap::new()),
+        }
+    }
+}
+
+pub struct GipopNodeManagerImpl {
+    simple: SimpleNodeManagerImpl,
+    companions: Vec<NamespaceMetadata>, // DI, PLCopen, fieldbus diagnostics, plant areas
+    history: HistoryConfig,
+    backends: HashMap<HistoryBackendName, Mutex<Backend>>, // the ones that opened
+    historized: RwLock<HashMap<NodeId, Historized>>,
+    events: Option<Mutex<EventStore>>, // the gateway's, if events_path is set and it opened
+    event_history: RwLock<HashSet<NodeId>>, // objects answering HistoryReadEvents from `events`
+    users: Users, // user token ids are the user names
+    required: RwLock<HashMap<NodeId, Action>>, // what writing a variable or calling a method counts as, reading otherwise
+    eu_spans: RwLock<HashMap<NodeId, f64>>, // EURange high - low of analog tags, for percent deadbands
+    sampling: RwLock<HashMap<NodeId, HashMap<MonitoredItemHandle, Sampling>>>, // monitored items on tags
+    commands: RwLock<HashMap<NodeId, CommandWrite>>, // command nodes, what writing their value does
+    texts: RwLock<HashMap<NodeId, Vec<TagText>>>, // display name and description per locale
+}
+
+// Callbacks are still registered on the simple node manager
+impl Deref for GipopNodeManagerImpl {
+    type Target = SimpleNodeManagerImpl;
+    fn deref(&self) -> &SimpleNodeManagerImpl { &self.simple }
+}
+
+impl GipopNodeManagerImpl {
+    /// Keep history for `node`, which shows tag `name`, in the tag's backend. False if it has none (or that
+    /// didn't open), the node isn't historizing then.
+    pub fn historize(&self, node: NodeId, name: &str, ty: TagType) -> bool {
+        let backend = self.history.backend_of(name);
+        if !self.backends.contains_key(&backend) {
+            return false;
+        }
+        self.historized.write().unwrap().insert(node, Historized { tag: name.to_string(), ty, backend });
+        true
+    }
+
+    /// HistoryReadEvents on `node` reads the event store. False if there's none, the node doesn't keep event
+    /// history then.
+    pub fn historize_events(&self, node: NodeId) -> bool {
+        if self.events.is_none() {
+            return false;
+        }
+        self.event_history.write().unwrap().insert(node);
+        true
+    }
+
+    /// Only good samples go in, the history is what the PLC actually measured
+    pub fn record(&self, node: &NodeId, sample: &TagSample) {
+        if !sample.quality.is_good() {
+            return;
+        }
+        let Some(historized) = self.tag_of(node) else { return };
+        let mut backend = self.backends[&historized.backend].lock().unwrap();
+        let recorded = backend.store.record(Sample { tag: historized.tag, ts_ms: sample.ts_ms, value: sample.value.as_f64() });
+        // once per failure, not for every sample
+        match recorded {
+            Err(e) if !backend.failing => log::error!("[OPC UA] {} history isn't recording: {}", backend.store.name(), e),
+            Ok(()) if backend.failing => log::info!("[OPC UA] {} history recording again", backend.store.name()),
+            _ => {}
+        }
+        backend.failing = recorded.is_err();
+    }
+
+    /// Writing `node` (variables) or calling it (methods) is `action`, only users allowed to do it may
+    pub fn require(&self, node: NodeId, action: Action) {
+        self.required.write().unwrap().insert(node, action);
+    }
+
+    /// Writing the value of `node` sends a command to the PLC, audited. Use this instead of add_write_callback for
+    /// anything the PLC acts on.
+    pub fn add_command_write(&self, node: NodeId, write: impl Fn(DataValue, &NumericRange) -> Result<u32, StatusCode> + Send + Sync + 'static) {
+        self.commands.write().unwrap().insert(node, Box::new(write));
+    }
+
+    /// `node` is an analog tag with that EURange
+    pub fn analog(&self, node: NodeId, eu_range: (f64, f64)) {
+        self.eu_spans.write().unwrap().insert(node, eu_range.1 - eu_range.0);
+    }
+
+    /// `node` is shown as `texts` to clients asking for their locales
+    pub fn localize(&self, node: NodeId, texts: Vec<TagText>) {
+        self.texts.write().unwrap().insert(node, texts);
+    }
+
+    /// What the monitored items on `node` all get, None if there are none
+    pub fn sampling(&self, node: &NodeId) -> Option<Sampling> {
+        Sampling::strictest(self.sampling.read().unwrap().get(node)?.values().copied())
+    }
+
+    fn requested(&self, node: &NodeId, interval_ms: f64, filter: &FilterType) -> Result<Sampling, StatusCode> {
+        let filter = match filter {
+            FilterType::DataChangeFilter(filter) => Some(filter),
+            _ => None,
+        };
+        Sampling::requested(interval_ms, filter, self.eu_spans.read().unwrap().get(node).copied())
+    }
+
+    fn current_value(address_space: &AddressSpaceLock<AddressSpace>, node: &NodeId) -> Variant {
+        address_space.read()
+            .find_variable(node)
+            .and_then(|variable| variable.value(TimestampsToReturn::Neither, &NumericRange::None, &DataEncoding::Binary, 0.0).value)
+            .unwrap_or(Variant::Empty)
+    }
+
+    fn tag_of(&self, node: &NodeId) -> Option<Historized> {
+        self.historized.read().unwrap().get(node).cloned()
+    }
+
+    // Checked here rather than through the access level attributes, those are the same for every user
+    fn allowed(&self, context: &RequestContext, node: &NodeId) -> bool {
+        let action = self.required.read().unwrap().get(node).copied().unwrap_or(Action::Read);
+        match self.users.authorize(Some(&context.token.0), action) {
+            Ok(_) => true,
+            Err(e) => {
+                log::warn!("[OPC UA] Denied on {}: {}", node, e);
+                false
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Historized {
+    tag: String, // name in the backend
+    ty: TagType,
+    backend: HistoryBackendName,
+}
+
+struct Backend {
+    store: Box<dyn HistoryBackend>,
+    failing: bool, // last record failed
+}
+
+// Every backend some tag records into, behind [compression]. One that doesn't open is left out, its tags aren't
+// historized.
+fn open_backends(config: &HistoryConfig, compression: &CompressionSettings) -> HashMap<HistoryBackendName, Mutex<Backend>> {
+    let wanted: HashSet<HistoryBackendName> = config.tags.values().copied().chain([config.backend]).collect();
+    wanted.into_iter().filter_map(|name| {
+        let store: Box<dyn HistoryBackend> = match name {
+            HistoryBackendName::None => return None,
+            HistoryBackendName::Memory => Box::new(History::new(config.samples)),
+            HistoryBackendName::Sql => match SqlHistory::open(&config.sql_path) {
+                Ok(sql) => Box::new(sql),
+                Err(e) => {
+                    log::error!("[OPC UA] {}, tags meant for it aren't historized", e);
+                    return None;
+                }
+            },
+            HistoryBackendName::Postgres => match open_postgres(config) {
+                Ok(pg) => Box::new(pg),
+                Err(e) => {
+                    log::error!("[OPC UA] {}, tags meant for it aren't historized", e);
+                    return None;
+                }
+            },
+        };
+        let store = Box::new(Compressed::new(store, compression.clone()));
+        Some((name, Mutex::new(Backend { store, failing: false })))
+    }).collect()
+}
+
+fn open_events(config: &HistoryConfig) -> Option<Mutex<EventStore>> {
+    if config.events_path.is_empty() {
+        return None;
+    }
+    match EventStore::open(&config.events_path) {
+        Ok(store) => Some(Mutex::new(store)),
+        Err(e) => {
+            log::error!("[OPC UA] {}, no event history", e);
+            None
+        }
+    }
+}
+
+fn open_postgres(config: &HistoryConfig) -> Result<PgHistory, String> {
+    let pg = PgHistory::open(&config.postgres_url)?;
+    if config.postgres_retention_days > 0 {
+        pg.set_retention(Duration::from_secs(config.postgres_retention_days * 24 * 3600))?;
+    }
+    log::info!("[OPC UA] Recording history to PostgreSQL{}", if pg.is_timescale() { " (TimescaleDB hypertable)" } else { "" });
+    Ok(pg)
+}
+
+fn to_ms(time: &DateTime) -> u64 {
+    time.as_chrono().timestamp_millis().max(0) as u64
+}
+
+fn to_date_time(ts_ms: u64) -> Option<DateTime> {
+    chrono::DateTime::from_timestamp_millis(ts_ms as i64).map(DateTime::from)
+}
+
+fn history_value(ty: TagType, value: f64, ts_ms: u64) -> DataValue {
+    let value = match ty {
+        TagType::Bool => TagValue::Bool(value != 0.0),
+        TagType::UInt32 => TagValue::UInt32(value as u32),
+        TagType::Int32 => TagValue::Int32(value as i32),
+        TagType::Float32 => TagValue::Float32(value as f32),
+        TagType::Float64 => TagValue::Float64(value),
+    };
+    DataValue {
+        value: Some(crate::tag_to_variant(value)),
+        status: Some(StatusCode::Good),
+        source_timestamp: to_date_time(ts_ms),
+        ..Default::default()
+    }
+}
+
+fn aggregate_of(id: &NodeId) -> Option<Aggregate> {
+    [
+        (ObjectId::AggregateFunction_Average, Aggregate::Average),
+        (ObjectId::AggregateFunction_Minimum, Aggregate::Minimum),
+        (ObjectId::AggregateFunction_Maximum, Aggregate::Maximum),
+        (ObjectId::AggregateFunction_Count, Aggregate::Count),
+    ].into_iter().find(|(object, _)| *id == NodeId::from(*object)).map(|(_, aggregate)| aggregate)
+}
+
+#[async_trait]
+impl InMemoryNodeManagerImpl for GipopNodeManagerImpl {
+    async fn init(&self, address_space: &mut AddressSpace, context: ServerContext) {
+        self.simple.init(address_space, context).await
+    }
+
+    fn name(&self) -> &str {
+        self.simple.name()
+    }
+
+    fn namespaces(&self) -> Vec<NamespaceMetadata> {
+        let mut namespaces = self.simple.namespaces();
+        namespaces.extend(self.companions.iter().cloned());
+        namespaces
+    }
+
+    async fn read_values(
+        &self,
+        context: &RequestContext,
+        address_space: &AddressSpaceLock<AddressSpace>,
+        nodes: &[&ParsedReadValueId],
+        max_age: f64,
+        timestamps_to_return: TimestampsToReturn,
+    ) -> Vec<DataValue> {
+        let mut values = self.simple.read_values(context, address_space, nodes, max_age, timestamps_to_return).await;
+        let texts = self.texts.read().unwrap();
+        let mut locales = None;
+        for (node, value) in nodes.iter().zip(values.iter_mut()) {
+            let localized = match node.attribute_id {
+                AttributeId::DisplayName => locale::display_name,
+                AttributeId::Description => locale::description,
+                _ => continue,
+            };
+            let Some(node_texts) = texts.get(&node.node_id) else { continue };
+            let locales = locales.get_or_insert_with(|| locale::requested(context));
+            if let Some(text) = locale::best(node_texts, locales) {
+                value.value = Some(Variant::from(localized(text)));
+            }
+        }
+        values
+    }
+
+    async fn create_value_monitored_items(
+        &self,
+        context: &RequestContext,
+        address_space: &AddressSpaceLock<AddressSpace>,
+        items: &mut [&mut &mut CreateMonitoredItem],
+    ) {
+        self.simple.create_value_monitored_items(context, address_space, items).await;
+
+        let mut sampling = self.sampling.write().unwrap();
+        for item in items.iter_mut().filter(|item| item.status_code().is_good()) {
+            let node = item.item_to_monitor().node_id.clone();
+            if self.tag_of(&node).is_none() {
+                continue; // not a tag, nothing for the sync task to hold back
+            }
+            match self.requested(&node, item.sampling_interval(), item.filter()) {
+                Ok(requested) => {
+                    sampling.entry(node).or_default().insert(item.handle(), requested);
+                }
+                Err(status) => item.set_status(status),
+            }
+        }
+    }
+
+    async fn modify_monitored_items(&self, context: &RequestContext, items: &[&MonitoredItemUpdateRef]) {
+        self.simple.modify_monitored_items(context, items).await;
+
+        let mut sampling = self.sampling.write().unwrap();
+        for item in items.iter().filter(|item| item.status_code().is_good()) {
+            let Some(requests) = sampling.get_mut(item.node_id()) else {
+                continue;
+            };
+            // an invalid new filter keeps the old request, there's no rejecting a modify from here
+            if let Ok(requested) = self.requested(item.node_id(), item.sampling_interval(), item.filter()) {
+                requests.insert(item.handle(), requested);
+            }
+        }
+    }
+
+    async fn delete_monitored_items(&self, context: &RequestContext, items: &[&MonitoredItemRef]) {
+        self.simple.delete_monitored_items(context, items).await;
+
+        let mut sampling = self.sampling.write().unwrap();
+        for item in items {
+            if let Some(requests) = sampling.get_mut(item.node_id()) {
+                requests.remove(&item.handle());
+                if requests.is_empty() {
+                    sampling.remove(item.node_id());
+                }
+            }
+        }
+    }
+
+    async fn write(
+        &self,
+        context: &RequestContext,
+        address_space: &AddressSpaceLock<AddressSpace>,
+        nodes_to_write: &mut [&mut WriteNode],
+    ) -> Result<(), StatusCode> {
+        let mut allowed = Vec::new();
+        let commands = self.commands.read().unwrap();
+        for node in nodes_to_write.iter_mut() {
+            let write = node.value().clone();
+            if !self.allowed(context, &write.node_id) {
+                node.set_status(StatusCode::BadUserAccessDenied);
+                continue;
+            }
+            match commands.get(&write.node_id) {
+                Some(command) if write.attribute_id == AttributeId::Value as u32 => {
+                    let old_value = Self::current_value(address_space, &write.node_id);
+                    let span = tracing::info_span!("opcua_write", node = %write.node_id);
+                    let result = span.in_scope(|| audit::as_client(context, || command(write.value.clone(), &write.index_range)));
+                    node.set_status(result.err().unwrap_or(StatusCode::Good));
+                    audit::audit_write(context, &write, old_value, result);
+                }
+                _ => allowed.push(&mut **node),
+            }
+        }
+        drop(commands);
+        self.simple.write(context, address_space, &mut allowed).await
+    }
+
+    async fn call(
+        &self,
+        context: &RequestContext,
+        address_space: &AddressSpaceLock<AddressSpace>,
+        methods_to_call: &mut [&mut &mut MethodCall],
+    ) -> Result<(), StatusCode> {
+        let mut allowed = Vec::new();
+        for call in methods_to_call.iter_mut() {
+            if self.allowed(context, call.method_id()) {
+                allowed.push(&mut **call);
+            }
+            else {
+                call.set_status(StatusCode::BadUserAccessDenied);
+            }
+        }
+        let mut allowed: Vec<_> = allowed.iter_mut().collect();
+        let span = tracing::info_span!("opcua_call", methods = allowed.len());
+        audit::as_client_async(context, self.simple.call(context, address_space, &mut allowed)).instrument(span).await
+    }
+
+    // ReadRaw. Modified values aren't a thing here, the PLC never rewrites history.
+    // Everything fits in one response, no continuation points.
+    async fn history_read_raw_modified(
+        &self,
+        _context: &RequestContext,
+        details: &ReadRawModifiedDetails,
+        nodes: &mut [&mut &mut HistoryNode],
+        _timestamps_to_return: TimestampsToReturn,
+    ) -> Result<(), StatusCode> {
+        if details.is_read_modified {
+            return Err(StatusCode::BadHistoryOperationUnsupported);
+        }
+
+        // a null end time means "from start on", a null start time "up to end, newest first"
+        let (start, end) = (to_ms(&details.start_time), to_ms(&details.end_time));
+        let reverse = details.start_time.is_null() || (!details.end_time.is_null() && end < start);
+        let (from, to) = match (details.start_time.is_null(), details.end_time.is_null()) {
+            (true, _) => (0, end),
+            (_, true) => (start, u64::MAX),
+            _ => (start.min(end), start.max(end)),
+        };
+
+        for node in nodes.iter_mut() {
+            let Some(historized) = self.tag_of(node.node_id()) else {
+                node.set_status(StatusCode::BadHistoryOperationUnsupported);
+                continue;
+            };
+            let backend = self.backends[&historized.backend].lock().unwrap();
+            let mut samples = match backend.store.read_raw(&historized.tag, from, to, 0, details.return_bounds) {
+                Ok(samples) => samples,
+                Err(e) => {
+                    log::error!("[OPC UA] HistoryRead of {} failed: {}", historized.tag, e);
+                    node.set_status(StatusCode::BadHistoryOperationInvalid);
+                    continue;
+                }
+            };
+            if reverse {
+                samples.reverse();
+            }
+            if details.num_values_per_node != 0 {
+                samples.truncate(details.num_values_per_node as usize);
+            }
+
+            let data_values = samples.iter().map(|s| history_value(historized.ty, s.value, s.ts_ms)).collect();
+            node.set_result(HistoryData { data_values: Some(data_values) });
+        }
+        Ok(())
+    }
+
+    // Average/Minimum/Maximum/Count over each processing interval, empty intervals come back as BadNoData
+    async fn history_read_processed(
+        &self,
+        _context: &RequestContext,
+        details: &ReadProcessedDetails,
+        nodes: &mut [&mut &mut HistoryNode],
+        _timestamps_to_return: TimestampsToReturn,
+    ) -> Result<(), StatusCode> {
+        let (start, end) = (to_ms(&details.start_time), to_ms(&details.end_time));
+        let (from, to) = (start.min(end), start.max(end));
+        let aggregates = details.aggregate_type.as_deref().unwrap_or(&[]);
+        if aggregates.len() != nodes.len() {
+            return Err(StatusCode::BadAggregateListMismatch);
+        }
+
+        for (node, aggregate) in nodes.iter_mut().zip(aggregates) {
+            let Some(aggregate) = aggregate_of(aggregate) else {
+                node.set_status(StatusCode::BadAggregateNotSupported);
+                continue;
+            };
+            let Some(historized) = self.tag_of(node.node_id()) else {
+                node.set_status(StatusCode::BadHistoryOperationUnsupported);
+                continue;
+            };
+            let backend = self.backends[&historized.backend].lock().unwrap();
+            let processed = match backend.store.read_processed(&historized.tag, from, to, details.processing_interval as u64, aggregate) {
+                Ok(processed) => processed,
+                Err(e) => {
+                    log::error!("[OPC UA] HistoryRead of {} failed: {}", historized.tag, e);
+                    node.set_status(StatusCode::BadHistoryOperationInvalid);
+                    continue;
+                }
+            };
+
+            // minimum and maximum keep the tag's type, averages are Double and counts UInt32 whatever the tag
+            let ty = historized.ty;
+            let data_values = processed
+                .into_iter()
+                .map(|(ts_ms, value)| match (value, aggregate) {
+                    (Some(average), Aggregate::Average) => history_value(TagType::Float64, average, ts_ms),
+                    (Some(count), Aggregate::Count) => history_value(TagType::UInt32, count, ts_ms),
+                    (Some(value), _) => history_value(ty, value, ts_ms),
+                    (None, _) => DataValue {
+                        status: Some(StatusCode::BadNoData),
+                        source_timestamp: to_date_time(ts_ms),
+                        ..Default::default()
+                    },
+                })
+                .collect();
+            node.set_result(HistoryData { data_values: Some(data_values) });
+        }
+        Ok(())
+    }
+
+    // ReadEvent from the event store, newest first like ReadRaw when there's no start time
+    async fn history_read_events(
+        &self,
+        _context: &RequestContext,
+        details: &ReadEventDetails,
+        nodes: &mut [&mut &mut HistoryNode],
+        _timestamps_to_return: TimestampsToReturn,
+    ) -> Result<(), StatusCode> {
+        let (start, end) = (to_ms(&details.start_time), to_ms(&details.end_time));
+        let newest_first = details.start_time.is_null() || (!details.end_time.is_null() && end < start);
+        let (from, to) = match (details.start_time.is_null(), details.end_time.is_null()) {
+            (true, _) => (0, end),
+            (_, true) => (start, u64::MAX),
+            _ => (start.min(end), start.max(end)),
+        };
+        let query = EventQuery { start_ms: from, end_ms: to, limit: details.num_values_per_node as usize, newest_first, ..Default::default() };
+
+        for node in nodes.iter_mut() {
+            let Some(events) = self.events.as_ref().filter(|_| self.event_history.read().unwrap().contains(node.node_id())) else {
+                node.set_status(StatusCode::BadHistoryOperationUnsupported);
+                continue;
+            };
+            match events.lock().unwrap().query(&query) {
+                Ok(stored) => node.set_result(event_history::history_event(&stored, &details.filter)),
+                Err(e) => {
+                    log::error!("[OPC UA] HistoryReadEvents of {} failed: {}", node.node_id(), e);
+                    node.set_status(StatusCode::BadHistoryOperationInvalid);
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,112 @@
+// Operator actions as methods on Objects/Operator, for SCADA clients that need more than writing tag values:
+//
+// AckAlarm(Alarm: String)                       operator  acknowledge an alarm by its tag name
+// ForceChannel(Channel: String, Value: Double)  engineer  force a DO channel, named like the IO folder does ("EBus/DO0/Ch3")
+// UnforceChannel(Channel: String)               engineer  release that force
+// SetRunMode(Run: Boolean)                      engineer  start/stop the PLC logic, outputs hold while stopped
+// ResetTotals()                                 operator  zero the PLC's counters (Totals folder)
+// SetLogLevel(Module: String, Level: String)    engineer  log a module ("plc::ctrl_loop", "" for all) at "debug" etc. from
+//                                                         now on, Level "" for what [logging] says. No restart needed
+// ReloadConfig()                                engineer  the PLC reads gipop.toml again, applying what it can without
+//                                                         stopping the bus and refusing the rest (plc/src/reload.rs)
+//
+// All of them go to the PLC as commands (see gipop_shm::ring). Good means the PLC got the command, it checks e.g.
+// force targets itself and logs what it applied or ignored. The tags in the System folder show the outcome.
+use opcua::server::address_space::{AddressSpace, MethodBuilder, ObjectBuilder};
+use opcua::types::{Argument, DataTypeId, NodeId, ObjectId, StatusCode, Variant};
+use gipop_shm::{Action, IoAddress, RingItem, Subscriber};
+
+use crate::alarms::AlarmAcks;
+use crate::audit;
+use crate::node_manager::GipopNodeManager;
+
+pub fn add_operator_methods(ns: u16, manager: &GipopNodeManager, table: &Subscriber, acks: AlarmAcks) {
+    let folder = NodeId::new(ns, "operator");
+    let address_space = manager.address_space();
+    let mut address_space = address_space.write();
+    ObjectBuilder::new(&folder, "Operator", "Operator")
+        .organized_by(ObjectId::ObjectsFolder)
+        .insert(&mut address_space);
+    let callbacks = manager.inner();
+
+    let id = add_method(&mut address_space, ns, &folder, "AckAlarm", &[("Alarm", DataTypeId::String)]);
+    callbacks.require(id.clone(), Action::AckAlarm);
+    callbacks.add_method_callback(id, move |args| {
+        let Some(Variant::String(name)) = args.first() else {
+            return Err(StatusCode::BadInvalidArgument);
+        };
+        acks.acknowledge(name.as_ref()).map(|_| Vec::new())
+    });
+
+    let id = add_method(&mut address_space, ns, &folder, "ForceChannel", &[("Channel", DataTypeId::String), ("Value", DataTypeId::Double)]);
+    callbacks.require(id.clone(), Action::ForceIo);
+    let force_table = table.clone();
+    callbacks.add_method_callback(id, move |args| {
+        let Some(Variant::Double(value)) = args.get(1) else {
+            return Err(StatusCode::BadInvalidArgument);
+        };
+        send(&force_table, RingItem::force(channel_arg(args)?, *value))
+    });
+
+    let id = add_method(&mut address_space, ns, &folder, "UnforceChannel", &[("Channel", DataTypeId::String)]);
+    callbacks.require(id.clone(), Action::ForceIo);
+    let unforce_table = table.clone();
+    callbacks.add_method_callback(id, move |args| send(&unforce_table, RingItem::unforce(channel_arg(args)?)));
+
+    let id = add_method(&mut address_space, ns, &folder, "SetRunMode", &[("Run", DataTypeId::Boolean)]);
+    callbacks.require(id.clone(), Action::RunMode);
+    let run_mode_table = table.clone();
+    callbacks.add_method_callback(id, move |args| {
+        let Some(Variant::Boolean(run)) = args.first() else {
+            return Err(StatusCode::BadInvalidArgument);
+        };
+        send(&run_mode_table, RingItem::run_mode(*run))
+    });
+
+    let id = add_method(&mut address_space, ns, &folder, "ResetTotals", &[]);
+    callbacks.require(id.clone(), Action::ResetTotals);
+    let reset_table = table.clone();
+    callbacks.add_method_callback(id, move |_| send(&reset_table, RingItem::reset_totals()));
+
+    let id = add_method(&mut address_space, ns, &folder, "SetLogLevel", &[("Module", DataTypeId::String), ("Level", DataTypeId::String)]);
+    callbacks.require(id.clone(), Action::LogLevel);
+    let log_level_table = table.clone();
+    callbacks.add_method_callback(id, move |args| {
+        let (Some(Variant::String(module)), Some(Variant::String(level))) = (args.first(), args.get(1)) else {
+            return Err(StatusCode::BadInvalidArgument);
+        };
+        let level = gipop_shm::logging::parse_level(level.as_ref()).map_err(|_| StatusCode::BadOutOfRange)?;
+        let item = RingItem::log_level(module.as_ref(), level).map_err(|_| StatusCode::BadOutOfRange)?;
+        send(&log_level_table, item)
+    });
+
+    let id = add_method(&mut address_space, ns, &folder, "ReloadConfig", &[]);
+    callbacks.require(id.clone(), Action::ReloadConfig);
+    let reload_table = table.clone();
+    callbacks.add_method_callback(id, move |_| send(&reload_table, RingItem::reload_config()));
+
+    log::info!("Added operator methods");
+}
+
+// Method node "operator/<name>" with its InputArguments property
+fn add_method(address_space: &mut AddressSpace, ns: u16, folder: &NodeId, name: &str, args: &[(&str, DataTypeId)]) -> NodeId {
+    let id = NodeId::new(ns, format!("operator/{}", name));
+    let mut method = MethodBuilder::new(&id, name, name).component_of(folder.clone());
+    if !args.is_empty() {
+        let args: Vec<Argument> = args.iter().map(|&arg| arg.into()).collect();
+        method = method.input_args(address_space, &NodeId::new(ns, format!("operator/{}/InputArguments", name)), &args);
+    }
+    method.insert(address_space);
+    id
+}
+
+fn channel_arg(args: &[Variant]) -> Result<IoAddress, StatusCode> {
+    match args.first() {
+        Some(Variant::String(path)) => IoAddress::parse(path.as_ref()).ok_or(StatusCode::BadInvalidArgument),
+        _ => Err(StatusCode::BadInvalidArgument),
+    }
+}
+
+fn send(table: &Subscriber, item: RingItem) -> Result<Vec<Variant>, StatusCode> {
+    audit::command(table, item).map(|_| Vec::new())
+}
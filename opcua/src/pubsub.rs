@@ -0,0 +1,247 @@
+// OPC UA PubSub publisher (Part 14), for subscribers that want values without keeping a client session: every
+// [[pubsub.datasets]] entry publishes its tags every interval_ms as one DataSetMessage (key frame, every field) in a
+// NetworkMessage of its own.
+//
+// opc.udp://239.0.0.1:4840   UADP over UDP, usually multicast. Fields are DataValues so each carries its tag's
+//                            status and source timestamp, see uadp() for the exact header layout
+// mqtt://broker:1883         JSON over MQTT to `topic`, the Part 14 JSON NetworkMessage:
+//                            {"MessageId":"..","MessageType":"ua-data","PublisherId":"1","Messages":[{"DataSetWriterId":1,
+//                             "SequenceNumber":7,"Timestamp":"..","Payload":{"temperature":{"Value":21.5,...}}}]}
+//
+// No security and no discovery (DataSetMetaData) messages, subscribers are configured with the same tag list.
+use std::time::Duration;
+
+use gipop_shm::{Quality, Subscriber, TagSample, TagValue};
+use rumqttc::{AsyncClient, MqttOptions, QoS};
+use serde_json::{json, Map, Value};
+use tokio::net::UdpSocket;
+
+use crate::config::{DataSetConfig, PubSubConfig};
+
+// UADP header flags used here
+const UADP_VERSION: u8 = 1;
+const UADP_PUBLISHER_ID: u8 = 0x10;
+const UADP_GROUP_HEADER: u8 = 0x20;
+const UADP_PAYLOAD_HEADER: u8 = 0x40;
+const UADP_EXTENDED_FLAGS1: u8 = 0x80;
+const EXT1_PUBLISHER_ID_UINT16: u8 = 0x01;
+const EXT1_TIMESTAMP: u8 = 0x20;
+const GROUP_WRITER_GROUP_ID: u8 = 0x01;
+const GROUP_SEQUENCE_NUMBER: u8 = 0x08;
+const DSM_VALID: u8 = 0x01;
+const DSM_DATA_VALUE_FIELDS: u8 = 0x04;
+const DSM_SEQUENCE_NUMBER: u8 = 0x08;
+const DSM_STATUS: u8 = 0x10;
+const DSM_FLAGS2: u8 = 0x80;
+const DSM2_TIMESTAMP: u8 = 0x10; // message type 0, key frame
+const DATA_VALUE_VALUE: u8 = 0x01;
+const DATA_VALUE_STATUS: u8 = 0x02;
+const DATA_VALUE_SOURCE_TIMESTAMP: u8 = 0x04;
+
+const UNIX_EPOCH_TICKS: i64 = 116_444_736_000_000_000; // 1601-01-01 to 1970-01-01 in 100 ns ticks
+
+enum Transport {
+    Udp(UdpSocket, String),
+    Mqtt(AsyncClient, String),
+}
+
+/// Starts a publishing task per dataset
+pub fn start(config: &PubSubConfig, table: &Subscriber) {
+    for dataset in &config.datasets {
+        let fields: Option<Vec<(usize, String)>> = dataset.tags.iter()
+            .map(|name| table.index_of(name).map(|idx| (idx, name.clone())))
+            .collect();
+        let Some(fields) = fields else {
+            log::error!("[PubSub] Dataset '{}' names a tag the PLC doesn't have, not publishing it", dataset.name);
+            continue;
+        };
+        let (dataset, table, publisher_id) = (dataset.clone(), table.clone(), config.publisher_id);
+        tokio::spawn(async move {
+            let transport = match connect(&dataset, publisher_id).await {
+                Ok(transport) => transport,
+                Err(e) => {
+                    log::error!("[PubSub] Dataset '{}': {}", dataset.name, e);
+                    return;
+                }
+            };
+            log::info!("[PubSub] Publishing '{}' ({} tags) to {} every {} ms", dataset.name, fields.len(), dataset.url, dataset.interval_ms);
+            publish(dataset, publisher_id, fields, table, transport).await;
+        });
+    }
+}
+
+async fn connect(dataset: &DataSetConfig, publisher_id: u16) -> Result<Transport, String> {
+    if let Some(address) = dataset.url.strip_prefix("opc.udp://") {
+        let socket = UdpSocket::bind("0.0.0.0:0").await.map_err(|e| format!("UDP socket: {}", e))?;
+        socket.set_multicast_ttl_v4(dataset.ttl).map_err(|e| format!("multicast TTL: {}", e))?;
+        return Ok(Transport::Udp(socket, address.trim_end_matches('/').to_owned()));
+    }
+    if let Some(address) = dataset.url.strip_prefix("mqtt://") {
+        let (host, port) = match address.trim_end_matches('/').rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| format!("bad port in {}", dataset.url))?),
+            None => (address.trim_end_matches('/'), 1883),
+        };
+        let mut options = MqttOptions::new(format!("gipop-{}-{}", publisher_id, dataset.writer_id), host, port);
+        options.set_keep_alive(Duration::from_secs(30));
+        let (client, mut event_loop) = AsyncClient::new(options, 10);
+        // the event loop does the actual network IO and reconnects, it has to be polled for anything to go out
+        let url = dataset.url.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = event_loop.poll().await {
+                    log::warn!("[PubSub] {}: {}", url, e);
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                }
+            }
+        });
+        let topic = if dataset.topic.is_empty() { format!("gipop/{}", dataset.name) } else { dataset.topic.clone() };
+        return Ok(Transport::Mqtt(client, topic));
+    }
+    Err(format!("{} isn't an opc.udp:// or mqtt:// URL", dataset.url))
+}
+
+async fn publish(dataset: DataSetConfig, publisher_id: u16, fields: Vec<(usize, String)>, table: Subscriber, transport: Transport) {
+    let mut interval = tokio::time::interval(Duration::from_millis(dataset.interval_ms.max(1)));
+    let mut sequence: u16 = 0;
+    loop {
+        interval.tick().await;
+        sequence = sequence.wrapping_add(1);
+        let samples: Vec<TagSample> = fields.iter().map(|(idx, _)| table.read_sample(*idx)).collect();
+        let now_ms = gipop_shm::tags::now_ms();
+        let sent = match &transport {
+            Transport::Udp(socket, address) => {
+                let message = uadp(publisher_id, &dataset, sequence, now_ms, &samples);
+                socket.send_to(&message, address.as_str()).await.map(|_| ()).map_err(|e| e.to_string())
+            }
+            Transport::Mqtt(client, topic) => {
+                let names = fields.iter().map(|(_, name)| name.as_str());
+                let message = json_message(publisher_id, &dataset, sequence, now_ms, names.zip(&samples));
+                client.publish(topic.as_str(), QoS::AtMostOnce, false, message.to_string()).await.map_err(|e| e.to_string())
+            }
+        };
+        if let Err(e) = sent {
+            log::warn!("[PubSub] Dataset '{}' not sent: {}", dataset.name, e);
+        }
+    }
+}
+
+/// UADP NetworkMessage with one key frame DataSetMessage:
+/// flags, extended flags 1, PublisherId (UInt16), group header (WriterGroupId, SequenceNumber), payload header
+/// (1 DataSetWriterId), Timestamp, then the DataSetMessage: flags 1/2, SequenceNumber, Timestamp, Status, field count
+/// and the fields as DataValues
+fn uadp(publisher_id: u16, dataset: &DataSetConfig, sequence: u16, now_ms: u64, samples: &[TagSample]) -> Vec<u8> {
+    let mut message = vec![
+        UADP_VERSION | UADP_PUBLISHER_ID | UADP_GROUP_HEADER | UADP_PAYLOAD_HEADER | UADP_EXTENDED_FLAGS1,
+        EXT1_PUBLISHER_ID_UINT16 | EXT1_TIMESTAMP,
+    ];
+    message.extend(publisher_id.to_le_bytes());
+    message.push(GROUP_WRITER_GROUP_ID | GROUP_SEQUENCE_NUMBER);
+    message.extend(dataset.writer_group_id.to_le_bytes());
+    message.extend(sequence.to_le_bytes());
+    message.push(1); // DataSetMessage count
+    message.extend(dataset.writer_id.to_le_bytes());
+    message.extend(ticks(now_ms).to_le_bytes());
+
+    message.push(DSM_VALID | DSM_DATA_VALUE_FIELDS | DSM_SEQUENCE_NUMBER | DSM_STATUS | DSM_FLAGS2);
+    message.push(DSM2_TIMESTAMP);
+    message.extend(sequence.to_le_bytes());
+    message.extend(ticks(now_ms).to_le_bytes());
+    let worst = samples.iter().map(|sample| status_code(sample.quality)).max_by_key(|status| status >> 30).unwrap_or(0);
+    message.extend(((worst >> 16) as u16).to_le_bytes()); // the DataSetMessage status is a StatusCode's upper half
+    message.extend((samples.len() as u16).to_le_bytes());
+    for sample in samples {
+        let mut mask = DATA_VALUE_VALUE | DATA_VALUE_STATUS;
+        if sample.ts_ms != 0 {
+            mask |= DATA_VALUE_SOURCE_TIMESTAMP;
+        }
+        message.push(mask);
+        encode_variant(&mut message, sample.value);
+        message.extend(status_code(sample.quality).to_le_bytes());
+        if sample.ts_ms != 0 {
+            message.extend(ticks(sample.ts_ms).to_le_bytes());
+        }
+    }
+    message
+}
+
+// Variant binary encoding, built-in type id then the value
+fn encode_variant(buffer: &mut Vec<u8>, value: TagValue) {
+    match value {
+        TagValue::Bool(b) => buffer.extend([1, b as u8]),
+        TagValue::Int32(n) => {
+            buffer.push(6);
+            buffer.extend(n.to_le_bytes());
+        }
+        TagValue::UInt32(n) => {
+            buffer.push(7);
+            buffer.extend(n.to_le_bytes());
+        }
+        TagValue::Float32(f) => {
+            buffer.push(10);
+            buffer.extend(f.to_le_bytes());
+        }
+        TagValue::Float64(f) => {
+            buffer.push(11);
+            buffer.extend(f.to_le_bytes());
+        }
+    }
+}
+
+fn json_message<'a>(
+    publisher_id: u16,
+    dataset: &DataSetConfig,
+    sequence: u16,
+    now_ms: u64,
+    fields: impl Iterator<Item = (&'a str, &'a TagSample)>,
+) -> Value {
+    let payload: Map<String, Value> = fields.map(|(name, sample)| {
+        let mut field = json!({ "Value": json_value(sample.value) });
+        if !sample.quality.is_good() {
+            field["StatusCode"] = json!({ "Code": status_code(sample.quality) });
+        }
+        if sample.ts_ms != 0 {
+            field["SourceTimestamp"] = json!(timestamp(sample.ts_ms));
+        }
+        (name.to_owned(), field)
+    }).collect();
+    json!({
+        "MessageId": format!("{}-{}-{}", publisher_id, dataset.writer_id, now_ms),
+        "MessageType": "ua-data",
+        "PublisherId": publisher_id.to_string(),
+        "Messages": [{
+            "DataSetWriterId": dataset.writer_id,
+            "SequenceNumber": sequence,
+            "Timestamp": timestamp(now_ms),
+            "Payload": payload,
+        }],
+    })
+}
+
+fn json_value(value: TagValue) -> Value {
+    match value {
+        TagValue::Bool(b) => json!(b),
+        TagValue::Int32(n) => json!(n),
+        TagValue::UInt32(n) => json!(n),
+        TagValue::Float32(f) => json!(f),
+        TagValue::Float64(f) => json!(f),
+    }
+}
+
+// Same codes the tag variables carry, see quality_to_status in main.rs
+fn status_code(quality: Quality) -> u32 {
+    match quality {
+        Quality::Good => 0,
+        Quality::Uncertain => 0x4090_0000,             // UncertainLastUsableValue
+        Quality::Bad => 0x8000_0000,                   // Bad
+        Quality::BadNotConnected => 0x808A_0000,       // BadNotConnected
+        Quality::WaitingForInitialData => 0x8032_0000, // BadWaitingForInitialData
+    }
+}
+
+fn ticks(ts_ms: u64) -> i64 {
+    ts_ms as i64 * 10_000 + UNIX_EPOCH_TICKS
+}
+
+fn timestamp(ts_ms: u64) -> String {
+    chrono::DateTime::from_timestamp_millis(ts_ms as i64).map_or_else(String::new, |time| time.to_rfc3339_opts(chrono::SecondsFormat::Millis, true))
+}
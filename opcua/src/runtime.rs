@@ -0,0 +1,139 @@
+// The PLC as a whole, for clients that want to start/stop it or check what's running:
+//
+// Objects/PlcRuntime
+//   RunMode      String    "RUN" or "STOP", engineers write either to start/stop the logic
+//   Run(), Stop()          same as writing RunMode, engineers only
+//   scan time              the PLC tag of that name (ms), organized here too
+//   Version, Commit, Profile, StartTime   the PLC's build and start, see gipop_shm::PlcInfo
+//   Project, ProjectVersion               the project bundle it runs, empty without one (gipop_shm::project)
+//
+// RUN/STOP go through the command handshake: the write or call only returns Good once the PLC acked the command,
+// BadTimeout if it didn't within COMMAND_TIMEOUT. RunMode itself follows the PLC's "running" tag, not the request.
+use std::time::{Duration, Instant};
+
+use opcua::server::address_space::{AccessLevel, AddressSpace, MethodBuilder, ObjectBuilder, VariableBuilder};
+use opcua::server::SubscriptionCache;
+use opcua::types::{DataTypeId, DataValue, DateTime, NodeId, NumericRange, ObjectId, ReferenceTypeId, StatusCode, Variant, VariableTypeId};
+use gipop_shm::{Action, PlcInfo, RingItem, Subscriber, TagValue};
+
+use crate::audit;
+use crate::layout::TagLayout;
+use crate::node_manager::GipopNodeManager;
+
+const RUNNING_TAG: &str = "running"; // see plc/src/tags.rs
+const SCAN_TIME_TAG: &str = "scan time";
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(1); // the PLC acks on its next sync cycle, every 100 ms
+
+pub struct RuntimeNodes {
+    ns: u16,
+    running_idx: Option<usize>,
+    running: Option<bool>,
+    info: Option<PlcInfo>,
+}
+
+impl RuntimeNodes {
+    pub fn new(layout: &TagLayout, manager: &GipopNodeManager, table: &Subscriber) -> Self {
+        let ns = layout.ns;
+        let folder = NodeId::new(ns, "runtime");
+        let run_mode = NodeId::new(ns, "runtime/RunMode");
+        let address_space = manager.address_space();
+        let mut address_space = address_space.write();
+        ObjectBuilder::new(&folder, "PlcRuntime", "PlcRuntime")
+            .organized_by(ObjectId::ObjectsFolder)
+            .insert(&mut address_space);
+
+        VariableBuilder::new(&run_mode, "RunMode", "RunMode")
+            .data_type(DataTypeId::String)
+            .value(Variant::Empty)
+            .access_level(AccessLevel::CURRENT_READ | AccessLevel::CURRENT_WRITE)
+            .user_access_level(AccessLevel::CURRENT_READ | AccessLevel::CURRENT_WRITE)
+            .component_of(folder.clone())
+            .has_type_definition(VariableTypeId::BaseDataVariableType)
+            .insert(&mut address_space);
+        for name in ["Version", "Commit", "Profile", "StartTime", "Project", "ProjectVersion"] {
+            let data_type = if name == "StartTime" { DataTypeId::DateTime } else { DataTypeId::String };
+            VariableBuilder::new(&NodeId::new(ns, format!("runtime/{}", name)), name, name)
+                .property_of(folder.clone())
+                .has_type_definition(VariableTypeId::PropertyType)
+                .data_type(data_type)
+                .value(Variant::Empty)
+                .insert(&mut address_space);
+        }
+        if let Some(idx) = table.index_of(SCAN_TIME_TAG) {
+            address_space.insert_reference(&folder, &layout.node(&table.tags()[idx]), ReferenceTypeId::Organizes);
+        }
+
+        let callbacks = manager.inner();
+        callbacks.require(run_mode.clone(), Action::RunMode);
+        let write_table = table.clone();
+        callbacks.add_command_write(run_mode, move |value: DataValue, _: &NumericRange| {
+            match value.value {
+                Some(Variant::String(mode)) if mode.as_ref() == "RUN" => command(&write_table, RingItem::run_mode(true)),
+                Some(Variant::String(mode)) if mode.as_ref() == "STOP" => command(&write_table, RingItem::run_mode(false)),
+                Some(Variant::String(_)) => Err(StatusCode::BadOutOfRange),
+                _ => Err(StatusCode::BadTypeMismatch),
+            }
+        });
+        for (name, run) in [("Run", true), ("Stop", false)] {
+            let id = add_method(&mut address_space, ns, &folder, name);
+            callbacks.require(id.clone(), Action::RunMode);
+            let method_table = table.clone();
+            callbacks.add_method_callback(id, move |_| command(&method_table, RingItem::run_mode(run)).map(|_| Vec::new()));
+        }
+
+        Self { ns, running_idx: table.index_of(RUNNING_TAG), running: None, info: None }
+    }
+
+    pub fn update(&mut self, manager: &GipopNodeManager, subscriptions: &SubscriptionCache, table: &Subscriber) {
+        let mut values = Vec::new();
+        if let Some(idx) = self.running_idx
+            && let TagValue::Bool(running) = table.read(idx)
+            && self.running != Some(running) {
+            self.running = Some(running);
+            values.push(("RunMode", Variant::from(if running { "RUN" } else { "STOP" })));
+        }
+        if let Some(info) = table.read_plc_info()
+            && self.info != Some(info) {
+            self.info = Some(info);
+            let started = chrono::DateTime::from_timestamp_millis(info.started_ms as i64).map_or(DateTime::null(), DateTime::from);
+            log::info!("[OPC UA sync] PLC {} ({}, {})", info.version(), info.commit(), info.profile());
+            values.extend([
+                ("Version", Variant::from(info.version())),
+                ("Commit", Variant::from(info.commit())),
+                ("Profile", Variant::from(info.profile())),
+                ("StartTime", Variant::from(started)),
+                ("Project", Variant::from(info.project())),
+                ("ProjectVersion", Variant::from(info.project_version())),
+            ]);
+        }
+        if !values.is_empty() {
+            let ids: Vec<NodeId> = values.iter().map(|(name, _)| NodeId::new(self.ns, format!("runtime/{}", name))).collect();
+            let _ = manager.set_values(
+                subscriptions,
+                ids.iter().zip(values).map(|(id, (_, value))| (id, None, DataValue::new_now(value))),
+            );
+        }
+    }
+}
+
+fn add_method(address_space: &mut AddressSpace, ns: u16, folder: &NodeId, name: &str) -> NodeId {
+    let id = NodeId::new(ns, format!("runtime/{}", name));
+    MethodBuilder::new(&id, name, name)
+        .component_of(folder.clone())
+        .insert(address_space);
+    id
+}
+
+// Pushes the command and waits for the PLC to ack it. Blocks the calling task, only for the rare RUN/STOP.
+fn command(table: &Subscriber, item: RingItem) -> Result<u32, StatusCode> {
+    let seq = audit::command(table, item)?;
+    let sent = Instant::now();
+    while !table.is_command_acked(seq) {
+        if sent.elapsed() > COMMAND_TIMEOUT {
+            log::error!("PLC didn't ack run mode command {} within {:?}", seq, COMMAND_TIMEOUT);
+            return Err(StatusCode::BadTimeout);
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    Ok(seq)
+}
@@ -0,0 +1,64 @@
+// What clients asked for when they subscribed to a tag: how often they want to hear about it (sampling interval)
+// and how much it has to change to count (deadband, absolute or percent of its EURange). The node manager collects
+// the requests per tag, the sync task only notifies a tag's monitored items when the strictest of them is met, so a
+// noisy analog input doesn't flood every subscription with 10 updates a second.
+//
+// The address space itself is always current, reads see every value regardless.
+use std::time::{Duration, Instant};
+
+use opcua::types::{DataChangeFilter, DeadbandType, StatusCode};
+use gipop_shm::{TagSample, TagType};
+
+/// One monitored item's request, deadband already absolute
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sampling {
+    pub interval: Duration,
+    pub deadband: f64,
+}
+
+impl Sampling {
+    /// From a monitored item's sampling interval (ms, negative for the publishing interval, which we treat as
+    /// "as fast as we get it") and data change filter. `eu_span` is EURange high - low of analog tags.
+    pub fn requested(interval_ms: f64, filter: Option<&DataChangeFilter>, eu_span: Option<f64>) -> Result<Self, StatusCode> {
+        let deadband = match filter {
+            None => 0.0,
+            Some(filter) if filter.deadband_value < 0.0 => return Err(StatusCode::BadDeadbandFilterInvalid),
+            Some(filter) if filter.deadband_type == DeadbandType::None as u32 => 0.0,
+            Some(filter) if filter.deadband_type == DeadbandType::Absolute as u32 => filter.deadband_value,
+            Some(filter) if filter.deadband_type == DeadbandType::Percent as u32 => match eu_span {
+                Some(span) if filter.deadband_value <= 100.0 => filter.deadband_value / 100.0 * span,
+                Some(_) => return Err(StatusCode::BadDeadbandFilterInvalid),
+                None => return Err(StatusCode::BadMonitoredItemFilterUnsupported), // percent of what?
+            },
+            Some(_) => return Err(StatusCode::BadDeadbandFilterInvalid),
+        };
+        Ok(Self { interval: Duration::from_secs_f64(interval_ms.max(0.0) / 1000.0), deadband })
+    }
+
+    /// What every one of `requests` gets: the shortest interval and the smallest deadband
+    pub fn strictest(requests: impl Iterator<Item = Sampling>) -> Option<Self> {
+        requests.reduce(|a, b| Self { interval: a.interval.min(b.interval), deadband: a.deadband.min(b.deadband) })
+    }
+}
+
+pub enum Decision {
+    Report,
+    Wait, // changed enough, but too soon after the last report
+    Drop, // within the deadband
+}
+
+/// Whether monitored items hear about `sample`, given the last sample they heard about and when
+pub fn decide(sampling: Sampling, ty: TagType, sample: &TagSample, reported: Option<&(TagSample, Instant)>) -> Decision {
+    let Some((last, at)) = reported else {
+        return Decision::Report;
+    };
+    // quality changes always count, deadbands are for numbers (a Bool flip is always a change)
+    let changed = last.quality != sample.quality
+        || ty == TagType::Bool && last.value != sample.value
+        || (sample.value.as_f64() - last.value.as_f64()).abs() > sampling.deadband;
+    match changed {
+        false => Decision::Drop,
+        true if at.elapsed() < sampling.interval => Decision::Wait,
+        true => Decision::Report,
+    }
+}
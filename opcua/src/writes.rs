@@ -0,0 +1,59 @@
+// Client writes to PLC tags. Every tag the PLC marks writable (TAG_WRITABLE) takes writes from operators, checked
+// against the tag's metadata before anything goes to the PLC:
+//
+// index range      BadIndexRangeNoData, tags are scalars
+// wrong type       BadTypeMismatch, the tag's data type exactly (Float for Float32 tags...)
+// NaN/infinite     BadOutOfRange
+// out of range     BadOutOfRange, outside the instrument range (or EU range, see TagDef::write_range)
+// PLC unreachable  BadNoCommunication, the socket to the PLC is gone
+// ring full        BadResourceUnavailable, the PLC isn't consuming commands
+//
+// A Good write means the command is queued, the PLC applies it and mirrors the tag value back like any other.
+use opcua::types::{DataValue, NumericRange, StatusCode, Variant};
+use gipop_shm::{Action, RingItem, Subscriber, TagDef, TagType, TagValue};
+
+use crate::audit;
+use crate::layout::TagLayout;
+use crate::node_manager::GipopNodeManager;
+
+/// Routes writes to every writable tag in `tags` (the PLC's directory, in order) to the PLC
+pub fn add_tag_writes(layout: &TagLayout, manager: &GipopNodeManager, tags: &[TagDef], table: &Subscriber) {
+    let mut count = 0;
+    for (idx, tag) in tags.iter().enumerate().filter(|(_, tag)| tag.writable()) {
+        let node = layout.node(tag);
+        manager.inner().require(node.clone(), Action::WriteTag);
+        let (tag, table) = (tag.clone(), table.clone());
+        manager.inner().add_command_write(node, move |value: DataValue, range: &NumericRange| write_tag(&table, idx, &tag, value, range));
+        count += 1;
+    }
+    log::info!("{} writable tags", count);
+}
+
+fn write_tag(table: &Subscriber, idx: usize, tag: &TagDef, value: DataValue, range: &NumericRange) -> Result<u32, StatusCode> {
+    if !matches!(range, NumericRange::None) {
+        return Err(StatusCode::BadIndexRangeNoData);
+    }
+    let Some(value) = value.value.as_ref().and_then(|variant| variant_to_tag(tag.ty, variant)) else {
+        log::warn!("Write to '{}' rejected, not a {:?}: {:?}", tag.name, tag.ty, value.value);
+        return Err(StatusCode::BadTypeMismatch);
+    };
+    if !tag.accepts(value) {
+        log::warn!("Write to '{}' rejected, {} is out of range {:?}", tag.name, value.as_f64(), tag.write_range());
+        return Err(StatusCode::BadOutOfRange);
+    }
+    if !table.is_connected() {
+        return Err(StatusCode::BadNoCommunication);
+    }
+    audit::command(table, RingItem::tag_write(idx, value))
+}
+
+fn variant_to_tag(ty: TagType, variant: &Variant) -> Option<TagValue> {
+    match (ty, variant) {
+        (TagType::Bool, Variant::Boolean(b)) => Some(TagValue::Bool(*b)),
+        (TagType::UInt32, Variant::UInt32(n)) => Some(TagValue::UInt32(*n)),
+        (TagType::Int32, Variant::Int32(n)) => Some(TagValue::Int32(*n)),
+        (TagType::Float32, Variant::Float(f)) => Some(TagValue::Float32(*f)),
+        (TagType::Float64, Variant::Double(f)) => Some(TagValue::Float64(*f)),
+        _ => None,
+    }
+}
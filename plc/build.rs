@@ -0,0 +1,29 @@
+fn main() {
+    // commit the PLC reports to consumers (gipop_shm::PlcInfo), empty when not built from a git checkout
+    let commit = std::process::Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_owned())
+        .unwrap_or_default();
+    println!("cargo:rustc-env=GIPOP_COMMIT={}", commit);
+
+    // gRPC stubs are only needed with the `grpc` feature, don't make everyone else pay for protoc
+    #[cfg(feature = "grpc")]
+    compile_protos();
+}
+
+#[cfg(feature = "grpc")]
+fn compile_protos() {
+    // this script also builds the root `gipop` package, which sits one directory up
+    let proto_dir = if std::path::Path::new("proto/tags.proto").exists() { "proto" } else { "plc/proto" };
+    println!("cargo:rerun-if-changed={}/tags.proto", proto_dir);
+
+    // SAFETY: build scripts are single threaded
+    unsafe { std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path().expect("vendored protoc")) };
+    tonic_build::configure()
+        .build_client(false)
+        .compile_protos(&[format!("{}/tags.proto", proto_dir)], &[proto_dir])
+        .expect("compile tags.proto");
+}
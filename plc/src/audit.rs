@@ -0,0 +1,193 @@
+// Audit log: which user did what to the plant through a consumer, one line per command, appended to [audit] path.
+//
+// Every consumer follows a command up with an ITEM_AUDIT naming the user and session it sent the command for. A
+// command from the command ring or socket waits for its audit (Unaudited) and only goes through if the user's role in
+// [users] allows it, see gipop_shm::users. The entry is written once both are in (unix epoch ms first, like every
+// timestamp in the shm region), rejected commands get one too:
+// 1792142564512 seq=41 user=alice session=3 write 'area 1 lights hmi cmd' = 2
+// 1792142564730 seq=44 user=bob session=0 rejected force ebus/do/1/3 = 1: bob (Operator) may not ForceIo, needs Engineer
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use gipop_shm::ring::{ITEM_ALARM_ACK, ITEM_AUDIT, ITEM_FORCE, ITEM_LOG_LEVEL, ITEM_RELOAD_CONFIG, ITEM_RESET_TOTALS, ITEM_RESTORE_TOTAL, ITEM_RUN_MODE, ITEM_TAG_WRITE, ITEM_UNFORCE, RING_CAPACITY};
+use gipop_shm::tags::now_ms;
+use gipop_shm::{Action, RingItem, TagDef, TagValue, Users};
+
+const AUDIT_TIMEOUT: Duration = Duration::from_secs(1); // consumers send the audit right after the command
+
+static LOG: OnceLock<Mutex<File>> = OnceLock::new();
+
+/// Appends to `path` from now on, "" leaves the audit log off
+pub fn open(path: &str) {
+    if path.is_empty() {
+        return;
+    }
+    match OpenOptions::new().create(true).append(true).open(path) {
+        Ok(file) => {
+            log::info!("Audit log: {}", path);
+            let _ = LOG.set(Mutex::new(file));
+        }
+        Err(e) => log::error!("Can't open audit log {}: {}", path, e),
+    }
+}
+
+/// Commands applied lately, for the ITEM_AUDIT that follows each. A ring's worth, an audit item can't be further
+/// behind its command than that.
+#[derive(Default)]
+pub struct RecentCommands(VecDeque<RingItem>);
+
+impl RecentCommands {
+    pub fn push(&mut self, item: RingItem) {
+        if self.0.len() == RING_CAPACITY as usize {
+            self.0.pop_front();
+        }
+        self.0.push_back(item);
+    }
+
+    /// Writes the entry for the command an ITEM_AUDIT is about
+    pub fn audit(&self, audit: &RingItem, tags: &[TagDef]) {
+        let (seq, session, user) = audit.audited();
+        let Some(command) = self.0.iter().find(|item| item.seq == seq) else {
+            log::warn!("Audit for unknown command {} by {}", seq, user);
+            return;
+        };
+        let action = describe(command, tags);
+        log::info!("Audit: {} by {} (session {})", action, user, session);
+
+        append(seq, &user, session, &action);
+    }
+}
+
+/// Commands from the command ring or socket waiting for the ITEM_AUDIT that says who sent them
+#[derive(Default)]
+pub struct Unaudited(VecDeque<(RingItem, Instant)>);
+
+impl Unaudited {
+    /// Of `items` as they came in and the commands held from before, what goes through now, each command followed
+    /// by its audit: those whose user's role allows them. Commands whose audit didn't come within AUDIT_TIMEOUT are
+    /// rejected as anonymous, the rest are held for the next call
+    pub fn admit(&mut self, items: Vec<RingItem>, users: &Users, tags: &[TagDef]) -> Vec<RingItem> {
+        let mut admitted = Vec::new();
+        for item in items {
+            if item.kind != ITEM_AUDIT {
+                self.0.push_back((item, Instant::now()));
+                continue;
+            }
+            let (seq, session, user) = item.audited();
+            let Some(pos) = self.0.iter().position(|(command, _)| command.seq == seq) else {
+                log::warn!("Audit for unknown command {} by {}", seq, user);
+                continue;
+            };
+            let (command, _) = self.0.remove(pos).expect("position in range");
+            // kinds users don't send go through to be ignored and logged
+            match Action::of_command(command.kind).map(|action| users.authorize(Some(&user), action)) {
+                Some(Err(e)) => reject(&command, &user, session, &e, tags),
+                _ => admitted.extend([command, item]),
+            }
+        }
+        while let Some(&(command, since)) = self.0.front() && since.elapsed() >= AUDIT_TIMEOUT {
+            self.0.pop_front();
+            reject(&command, "anonymous", 0, "no audit said who sent it", tags);
+        }
+        admitted
+    }
+
+    /// The oldest command still waiting for its audit, acks mustn't cover it yet
+    pub fn oldest_seq(&self) -> Option<u32> {
+        self.0.front().map(|(command, _)| command.seq)
+    }
+}
+
+fn reject(command: &RingItem, user: &str, session: u32, why: &str, tags: &[TagDef]) {
+    let action = format!("rejected {}: {}", describe(command, tags), why);
+    log::warn!("Audit: {} by {} (session {})", action, user, session);
+    append(command.seq, user, session, &action);
+}
+
+fn append(seq: u32, user: &str, session: u32, action: &str) {
+    let Some(file) = LOG.get() else {
+        return;
+    };
+    if let Err(e) = writeln!(file.lock().unwrap(), "{} seq={} user={} session={} {}", now_ms(), seq, user, session, action) {
+        log::error!("Audit log write failed: {}", e);
+    }
+}
+
+/// What a command does, in words
+pub fn describe(item: &RingItem, tags: &[TagDef]) -> String {
+    let tag = tags.get(item.tag as usize);
+    let tag_name = tag.map_or("?", |tag| tag.name.as_str());
+    match item.kind {
+        ITEM_TAG_WRITE => match tag {
+            Some(tag) => format!("write '{}' = {}", tag.name, TagValue::from_raw(tag.ty, item.value).as_f64()),
+            None => format!("write tag {}", item.tag),
+        },
+        ITEM_ALARM_ACK => format!("acknowledge '{}'", tag_name),
+        ITEM_FORCE => format!("force {} = {}", item.io_address().path(), f64::from_bits(item.value)),
+        ITEM_UNFORCE => format!("unforce {}", item.io_address().path()),
+        ITEM_RUN_MODE => (if item.value != 0 { "run" } else { "stop" }).to_string(),
+        ITEM_RESET_TOTALS => "reset totals".to_string(),
+        ITEM_LOG_LEVEL => match item.logged_level() {
+            (module, Some(level)) => format!("log '{}' at {}", module, level.as_str().to_lowercase()),
+            (module, None) => format!("log '{}' as configured", module),
+        },
+        ITEM_RELOAD_CONFIG => "reload config".to_string(),
+        ITEM_RESTORE_TOTAL => match tag {
+            Some(tag) => format!("restore '{}' = {}", tag.name, TagValue::from_raw(tag.ty, item.value).as_f64()),
+            None => format!("restore tag {}", item.tag),
+        },
+        kind => format!("command kind {}", kind),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gipop_shm::IoAddress;
+    use gipop_shm::io_mirror::IO_DO;
+
+    fn users() -> Users {
+        let path = std::env::temp_dir().join(format!("gipop-audit-users-{}.toml", std::process::id()));
+        std::fs::write(&path, "[users.alice]\nrole = \"operator\"\n[users.carol]\nrole = \"engineer\"\n").unwrap();
+        let users = Users::load(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        users
+    }
+
+    fn sent(mut item: RingItem, seq: u32) -> RingItem {
+        item.seq = seq;
+        item
+    }
+
+    #[test]
+    fn commands_go_through_once_audited_by_a_user_allowed_to_send_them() {
+        let users = users();
+        let force = |seq| sent(RingItem::force(IoAddress { bus: 0, kind: IO_DO, terminal: 1, channel: 3 }, 1.0), seq);
+        let mut unaudited = Unaudited::default();
+
+        // the command alone is held, and holds back the ack
+        assert!(unaudited.admit(vec![force(1)], &users, &[]).is_empty());
+        assert_eq!(unaudited.oldest_seq(), Some(1));
+
+        // an engineer may force, the audit follows the command through
+        let admitted = unaudited.admit(vec![RingItem::audit(1, 3, "carol")], &users, &[]);
+        assert_eq!(admitted.iter().map(|item| (item.kind, item.seq)).collect::<Vec<_>>(), [(ITEM_FORCE, 1), (ITEM_AUDIT, 0)]);
+        assert_eq!(unaudited.oldest_seq(), None);
+
+        // an operator may not, nor may whoever isn't in [users]
+        for user in ["alice", "mallory"] {
+            assert!(unaudited.admit(vec![force(2), RingItem::audit(2, 0, user)], &users, &[]).is_empty(), "{}", user);
+            assert_eq!(unaudited.oldest_seq(), None);
+        }
+
+        // audits name their command, not whichever came last
+        let write = sent(RingItem::run_mode(true), 4);
+        let admitted = unaudited.admit(vec![force(3), write, RingItem::audit(4, 0, "carol")], &users, &[]);
+        assert_eq!(admitted.iter().map(|item| item.seq).collect::<Vec<_>>(), [4, 0]);
+        assert_eq!(unaudited.oldest_seq(), Some(3));
+        assert!(unaudited.admit(vec![RingItem::audit(9, 0, "carol")], &users, &[]).is_empty());
+    }
+}
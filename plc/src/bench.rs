@@ -0,0 +1,173 @@
+// gipop_plc bench <interface> [<seconds>] [<period_us>], whether a host and NIC keep up with the bus before anything
+// is commissioned on them. Brings the bus to OP like a PLC start (ctrl_loop::configure_pre_op), then exchanges process
+// data every `period_us` (1000 by default) for `seconds` (10) with no logic, no IPC and all outputs zero, and prints:
+//
+// - the cycle period, start to start, as min/avg/percentiles/max and a histogram around the target
+// - the tx_rx latency, from sending the frame to having the inputs back
+// - missed deadlines: cycles whose tx_rx was still running when the next one was due. A late cycle doesn't try to
+//   catch up, the next one starts a whole period after it
+// - working counter errors, like the bus diagnostics count them
+//
+// Numbers in µs. Run it on an otherwise idle host with the same kernel and CPU settings the PLC will get, the spread
+// is what the PLC's scan will see on top of its logic. The bus goes back to INIT at the end.
+//
+// gipop_plc bench-handlers [<terminals>] [<cycles>] times what the cycle does between two tx_rx instead, with no bus:
+// `terminals` (2000 by default) 16 channel EL1889s, EL2889s, KL1889s and KL2889s in turn, each at its own bits of one
+// input and one output image, refreshed from and into them `cycles` (10000) times like the input and output handlers
+// do. Prints the time a cycle's handlers take as min/avg/percentiles/max, in ns.
+use anyhow::Result;
+use async_io::Timer;
+use ethercrab::std::ethercat_now;
+use bitvec::prelude::*;
+use hal::diagnostics::{self, CycleStats};
+use hal::io_defs::TermStates;
+use hal::pdi::PdiRange;
+use hal::term_cfg::{DITerm, DOTerm, KBusTerm, KBusTerminalGender};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use crate::ctrl_loop::{self, MAX_SUBDEVICES, PDI_LEN};
+use crate::virtual_bus::{KL1889, KL2889};
+
+pub const DEFAULT_SECONDS: u64 = 10;
+pub const DEFAULT_PERIOD_US: u64 = 1000;
+pub const DEFAULT_HANDLER_TERMINALS: u64 = 2000;
+pub const DEFAULT_HANDLER_CYCLES: u64 = 10000;
+const HANDLER_CHANNELS: usize = 16;
+const HISTOGRAM_BUCKETS: u64 = 20; // across 0 to twice the period, anything longer lands in one more
+const BAR_WIDTH: usize = 50;
+
+pub async fn bench(network_interface: &str, seconds: u64, period: Duration) -> Result<()> {
+    let maindevice = ctrl_loop::start_maindevice(network_interface);
+    let group = maindevice.init_single_group::<MAX_SUBDEVICES, PDI_LEN>(ethercat_now).await?;
+    ctrl_loop::configure_pre_op(&group, &maindevice, &mut TermStates::new()).await?;
+    let group = group.into_op(&maindevice).await?;
+    let expected_wkc = diagnostics::expected_wkc(group.iter(&maindevice).map(|sd| {
+        let io = sd.io_raw();
+        (io.inputs().len(), io.outputs().len())
+    }));
+    println!("{} SubDevices in OP on {}, {} s at {} µs", group.len(), network_interface, seconds, period.as_micros());
+
+    let mut stats = CycleStats::default();
+    let (mut periods, mut latencies) = (Vec::new(), Vec::new());
+    let (mut missed, mut longest_overrun) = (0u64, Duration::ZERO);
+    let end = Instant::now() + Duration::from_secs(seconds);
+    let mut deadline = Instant::now();
+    let mut last_start = None;
+    while deadline < end {
+        Timer::at(deadline).await;
+        let start = Instant::now();
+        let response = group.tx_rx(&maindevice).await?;
+        let done = Instant::now();
+
+        if let Some(last_start) = last_start {
+            let cycle = start - last_start;
+            stats.record(cycle, response.working_counter, expected_wkc);
+            periods.push(cycle.as_micros() as u64);
+        }
+        last_start = Some(start);
+        latencies.push((done - start).as_micros() as u64);
+
+        deadline += period;
+        if done > deadline {
+            missed += 1;
+            longest_overrun = longest_overrun.max(done - deadline);
+            deadline = done + period;
+        }
+    }
+
+    let group = group.into_safe_op(&maindevice).await?;
+    let group = group.into_pre_op(&maindevice).await?;
+    let _group = group.into_init(&maindevice).await?;
+
+    let cycles = latencies.len() as u64;
+    println!("{} cycles, {} working counter errors, jitter avg {} max {}", cycles, stats.wkc_errors, stats.jitter_avg.as_micros(), stats.jitter_max.as_micros());
+    println!("\n{:<16}{:>8}{:>8}{:>8}{:>8}{:>8}{:>8}", "µs", "min", "avg", "p50", "p99", "p99.9", "max");
+    println!("{}", summary("cycle period", &mut periods));
+    println!("{}", summary("tx_rx latency", &mut latencies));
+    println!(
+        "\nMissed deadlines: {} of {} ({:.3}%), longest overrun {} µs",
+        missed, cycles, 100.0 * missed as f64 / cycles.max(1) as f64, longest_overrun.as_micros(),
+    );
+    println!("\nCycle period histogram:");
+    print!("{}", histogram(&periods, period.as_micros() as u64));
+    Ok(())
+}
+
+pub fn bench_handlers(terminals: usize, cycles: u64) {
+    let (mut di, mut dout, mut kbus_in, mut kbus_out) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    for idx in 0..terminals {
+        let range = PdiRange::new(HANDLER_CHANNELS * idx, HANDLER_CHANNELS);
+        match idx % 4 {
+            0 => di.push((DITerm::new(HANDLER_CHANNELS as u8), range)),
+            1 => dout.push((DOTerm::new(HANDLER_CHANNELS as u8), range)),
+            2 => kbus_in.push((KBusTerm::new(KL1889.code(), false, HANDLER_CHANNELS as u8, KBusTerminalGender::Input, (0, 0)), range)),
+            _ => kbus_out.push((KBusTerm::new(KL2889.code(), false, HANDLER_CHANNELS as u8, KBusTerminalGender::Output, (0, 0)), range)),
+        }
+    }
+    let image_bytes = (HANDLER_CHANNELS * terminals).div_ceil(8);
+    let inputs: Vec<u8> = (0..image_bytes).map(|byte| byte as u8 ^ 0x5a).collect();
+    let mut outputs = vec![0u8; image_bytes];
+    println!("{} terminals, {} bytes per image, {} cycles", terminals, image_bytes, cycles);
+
+    let mut samples = Vec::with_capacity(cycles as usize);
+    for _ in 0..cycles {
+        let start = Instant::now();
+        let input_bits = black_box(&inputs[..]).view_bits::<Lsb0>();
+        for (term, range) in &mut di {
+            term.refresh(range.view(input_bits));
+        }
+        for (term, range) in &mut kbus_in {
+            term.refresh_ctrlr(Some(range.view(input_bits)), None);
+        }
+        let output_bits = outputs.view_bits_mut::<Lsb0>();
+        for (term, range) in &dout {
+            term.refresh(range.view_mut(output_bits));
+        }
+        for (term, range) in &kbus_out {
+            term.refresh_term(range.view_mut(output_bits));
+        }
+        black_box(&mut outputs);
+        samples.push(start.elapsed().as_nanos() as u64);
+    }
+
+    println!("\n{:<16}{:>8}{:>8}{:>8}{:>8}{:>8}{:>8}", "ns", "min", "avg", "p50", "p99", "p99.9", "max");
+    println!("{}", summary("handlers", &mut samples));
+}
+
+// One row of the table, sorts `samples`
+fn summary(name: &str, samples: &mut [u64]) -> String {
+    if samples.is_empty() {
+        return format!("{:<16}no samples", name);
+    }
+    samples.sort_unstable();
+    let percentile = |p: f64| samples[((samples.len() - 1) as f64 * p).round() as usize];
+    let avg = samples.iter().sum::<u64>() / samples.len() as u64;
+    format!(
+        "{:<16}{:>8}{:>8}{:>8}{:>8}{:>8}{:>8}",
+        name, samples[0], avg, percentile(0.5), percentile(0.99), percentile(0.999), samples[samples.len() - 1],
+    )
+}
+
+// Counts in buckets of a tenth of the period from 0 to twice the period, one more for anything longer. Only the
+// buckets from the first to the last one with anything in them are printed
+fn histogram(samples: &[u64], period_us: u64) -> String {
+    let width = (2 * period_us / HISTOGRAM_BUCKETS).max(1);
+    let mut counts = vec![0u64; HISTOGRAM_BUCKETS as usize + 1];
+    for &sample in samples {
+        counts[((sample / width) as usize).min(HISTOGRAM_BUCKETS as usize)] += 1;
+    }
+    let (Some(first), Some(last)) = (counts.iter().position(|&n| n > 0), counts.iter().rposition(|&n| n > 0)) else {
+        return "  no samples\n".to_owned();
+    };
+    let most = *counts.iter().max().unwrap();
+
+    let mut out = String::new();
+    for (bucket, &count) in counts.iter().enumerate().take(last + 1).skip(first) {
+        let from = bucket as u64 * width;
+        let range = if bucket == HISTOGRAM_BUCKETS as usize { format!(">= {}", from) } else { format!("{}-{}", from, from + width - 1) };
+        let bar = if count == 0 { 0 } else { ((count * BAR_WIDTH as u64).div_ceil(most)) as usize };
+        out.push_str(&format!("  {:>13} {:<width$} {}\n", range, "#".repeat(bar), count, width = BAR_WIDTH));
+    }
+    out
+}
@@ -0,0 +1,228 @@
+// Black box ([blackbox] in gipop.toml): the last `cycles` cycles and `events` events kept in memory, written to
+// <dir>/blackbox-<unix ms>.txt when the PLC panics (any thread) or the bus fails under it, so a field failure comes
+// with what the plant looked like right before instead of only the log.
+//
+// A cycle is its number, when it came back, how long it took, the working counter, whether the logic ran, E-bus and
+// K-bus health, and the raw process image: every SubDevice's inputs as just received and outputs as just sent, in
+// hex. Events are the key tags' changes (`tags`, or every tag but the analog ones), alarms raised and cleared and the
+// commands the PLC got. Everything is recorded whatever the log level, the buffers are allocated once at start.
+//
+// # Gipop black box: TX/RX failed: Timeout(Pdu)
+// # 1792142564512, PLC 0.1.0 (3f2a9c1)
+// # cycle ts_ms cycle_us wkc run ebus kbus EK1100 EL1889 EL2889 EL3024 BK1120 (inputs/outputs)
+// 81234 1792142564511 1012 9 1 1 1 / 0580/ /a55a 0000.../ 0000.../0000...
+// # ts_ms event
+// 1792142564400 'area 1 lights' = 1
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Mutex, TryLockError};
+
+use gipop_shm::tags::now_ms;
+use gipop_shm::{TagDef, TagValue};
+
+use crate::config::BlackBoxConfig;
+use crate::ctrl_loop::PDI_LEN;
+
+static RECORDER: Mutex<Option<Recorder>> = Mutex::new(None);
+
+/// What the control loop knows about a cycle, besides its process image
+#[derive(Clone, Copy, Default)]
+pub struct CycleState {
+    pub cycle: u64,
+    pub cycle_us: u32,
+    pub wkc: u16,
+    pub running: bool,
+    pub ebus_ok: bool,
+    pub kbus_ok: bool,
+}
+
+#[derive(Clone, Copy)]
+struct Cycle {
+    state: CycleState,
+    ts_ms: u64,
+    image: [u8; PDI_LEN], // every SubDevice's inputs then outputs, in bus order, as the layout splits it
+    len: usize,
+}
+
+struct Recorder {
+    config: BlackBoxConfig,
+    layout: Vec<(String, usize, usize)>, // SubDevice name, input and output bytes
+    cycles: VecDeque<Cycle>,
+    events: VecDeque<(u64, String)>,
+    tags: HashMap<usize, TagValue>, // key tags as last recorded
+    dumped: bool, // a dump was written, the panic that follows a bus failure doesn't need another
+}
+
+/// Starts recording, "" for dir leaves the black box off. Installs the panic hook that dumps it, after which the
+/// previous hook (the message on stderr) runs as before
+pub fn start(config: &BlackBoxConfig) {
+    if config.dir.is_empty() {
+        return;
+    }
+    if config.cycles == 0 {
+        log::error!("Black box off, [blackbox] cycles is 0");
+        return;
+    }
+    if let Err(e) = fs::create_dir_all(&config.dir) {
+        log::error!("Black box off, can't create {}: {}", config.dir, e);
+        return;
+    }
+    *RECORDER.lock().unwrap() = Some(Recorder {
+        config: config.clone(),
+        layout: Vec::new(),
+        cycles: VecDeque::with_capacity(config.cycles),
+        events: VecDeque::with_capacity(config.events),
+        tags: HashMap::new(),
+        dumped: false,
+    });
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let recorder = match RECORDER.try_lock() {
+            Ok(recorder) => Some(recorder),
+            Err(TryLockError::Poisoned(poisoned)) => Some(poisoned.into_inner()),
+            Err(TryLockError::WouldBlock) => None, // the panic came from inside the black box itself
+        };
+        if let Some(mut recorder) = recorder
+            && let Some(recorder) = recorder.as_mut()
+            && !recorder.dumped {
+            recorder.dump(&format!("panic: {}", info));
+        }
+        previous(info);
+    }));
+    log::info!("Black box: last {} cycles and {} events, dumped to {} on a crash", config.cycles, config.events, config.dir);
+}
+
+/// The SubDevices in bus order with their input and output bytes, how the recorded images split. Once in OP
+pub fn set_layout(layout: Vec<(String, usize, usize)>) {
+    if let Some(recorder) = RECORDER.lock().unwrap().as_mut() {
+        recorder.layout = layout;
+    }
+}
+
+/// The key tags from now on (every tag but the analog ones if empty), for a config reload
+pub fn set_tags(tags: Vec<String>) {
+    if let Some(recorder) = RECORDER.lock().unwrap().as_mut() {
+        recorder.config.tags = tags;
+    }
+}
+
+/// One cycle, `fill` pushes every SubDevice's inputs and outputs in bus order
+pub fn record_cycle(state: CycleState, fill: impl FnOnce(&mut CycleImage)) {
+    let mut recorder = RECORDER.lock().unwrap();
+    let Some(recorder) = recorder.as_mut() else {
+        return;
+    };
+    let mut cycle = if recorder.cycles.len() == recorder.config.cycles {
+        recorder.cycles.pop_front().unwrap()
+    }
+    else {
+        Cycle { state, ts_ms: 0, image: [0; PDI_LEN], len: 0 }
+    };
+    cycle.state = state;
+    cycle.ts_ms = now_ms();
+    cycle.len = 0;
+    fill(&mut CycleImage(&mut cycle));
+    recorder.cycles.push_back(cycle);
+}
+
+/// A cycle's process image being recorded
+pub struct CycleImage<'a>(&'a mut Cycle);
+
+impl CycleImage<'_> {
+    /// Appends `bytes`, what doesn't fit in PDI_LEN is cut
+    pub fn push(&mut self, bytes: &[u8]) {
+        let cycle = &mut self.0;
+        let len = bytes.len().min(PDI_LEN - cycle.len);
+        cycle.image[cycle.len..cycle.len + len].copy_from_slice(&bytes[..len]);
+        cycle.len += len;
+    }
+}
+
+/// Something that happened, `what` is only made with the black box on
+pub fn event(what: impl FnOnce() -> String) {
+    if let Some(recorder) = RECORDER.lock().unwrap().as_mut() {
+        recorder.event(what());
+    }
+}
+
+/// The key tags among freshly published `values` that changed since they were last recorded
+pub fn record_tags(tags: &[TagDef], values: &[(usize, TagValue)]) {
+    let mut recorder = RECORDER.lock().unwrap();
+    let Some(recorder) = recorder.as_mut() else {
+        return;
+    };
+    for &(idx, value) in values {
+        let Some(tag) = tags.get(idx) else { continue };
+        let key = if recorder.config.tags.is_empty() {
+            !matches!(value, TagValue::Float32(_) | TagValue::Float64(_))
+        }
+        else {
+            recorder.config.tags.contains(&tag.name)
+        };
+        if key && recorder.tags.insert(idx, value) != Some(value) {
+            recorder.event(format!("'{}' = {}", tag.name, value.as_f64()));
+        }
+    }
+}
+
+/// Writes the black box out now, for failures that don't panic (or before they do)
+pub fn dump(reason: &str) {
+    if let Some(recorder) = RECORDER.lock().unwrap().as_mut() {
+        recorder.dump(reason);
+    }
+}
+
+impl Recorder {
+    fn event(&mut self, what: String) {
+        if self.config.events == 0 {
+            return;
+        }
+        if self.events.len() == self.config.events {
+            self.events.pop_front();
+        }
+        self.events.push_back((now_ms(), what));
+    }
+
+    fn dump(&mut self, reason: &str) {
+        self.dumped = true;
+        let ts_ms = now_ms();
+        let path = Path::new(&self.config.dir).join(format!("blackbox-{}.txt", ts_ms));
+        let written = fs::File::create(&path).and_then(|mut file| {
+            let mut text = format!("# Gipop black box: {}\n", reason.replace('\n', " "));
+            text += &format!("# {}, PLC {} ({})\n", ts_ms, env!("CARGO_PKG_VERSION"), env!("GIPOP_COMMIT"));
+            let names: Vec<&str> = self.layout.iter().map(|(name, _, _)| name.as_str()).collect();
+            text += &format!("# cycle ts_ms cycle_us wkc run ebus kbus {} (inputs/outputs)\n", names.join(" "));
+            for cycle in &self.cycles {
+                let state = cycle.state;
+                text += &format!(
+                    "{} {} {} {} {} {} {}",
+                    state.cycle, cycle.ts_ms, state.cycle_us, state.wkc, state.running as u8, state.ebus_ok as u8, state.kbus_ok as u8,
+                );
+                let mut image = &cycle.image[..cycle.len];
+                for &(_, inputs, outputs) in &self.layout {
+                    let (ins, rest) = image.split_at(inputs.min(image.len()));
+                    let (outs, rest) = rest.split_at(outputs.min(rest.len()));
+                    text += &format!(" {}/{}", hex(ins), hex(outs));
+                    image = rest;
+                }
+                text.push('\n');
+            }
+            text += "# ts_ms event\n";
+            for (ts_ms, what) in &self.events {
+                text += &format!("{} {}\n", ts_ms, what);
+            }
+            file.write_all(text.as_bytes())?;
+            file.sync_all()
+        });
+        match written {
+            Ok(()) => log::error!("Black box ({}) written to {}", reason, path.display()),
+            Err(e) => log::error!("Black box ({}) not written to {}: {}", reason, path.display(), e),
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
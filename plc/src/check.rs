@@ -0,0 +1,108 @@
+// gipop_plc check-config [<interface>], before deploying. Reads gipop.toml (--config or --project's) and the tag list
+// the way a PLC start does and prints everything wrong with them, one problem per line, where a start stops at the first one or
+// doesn't notice at all. Exits 1 if there was anything.
+//
+// Config: every section the PLC reads parses ([ipc], [logging], [telemetry], [users] and the ones in config.rs), the
+// [modbus] register map names existing tags of a type that fits the table without overlapping (modbus::check).
+// [[networks]] have names and interfaces of their own (networks::check).
+// Tags (tags.rs and the networks'): names are unique, they're the OPC UA node ids and what every mapping goes by, fit the shm tag
+// directory, and ranges run from low to high. A project has to be made for this tag list, whose fingerprint (what
+// a project's manifest pins, gipop_shm::project) is printed either way. Terminal channels bound to tags in hal's terminals! (io_defs.rs) name
+// tags that exist, on channels the terminal has.
+// Hardware is only known from the bus. With a network interface the bus is brought up to PRE-OP like for `scan`,
+// compared with [hardware] if that lists the SubDevices, and the K-bus terminals' slot ranges are checked against
+// their channels, each other and the coupler's images (hal::term_cfg::kbus_layout_problems). Without one that part
+// is skipped. Only the main bus is checked, the other networks are compared with their config when they start.
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+use gipop_shm::logging::LoggingConfig;
+use gipop_shm::region::check_entry;
+use gipop_shm::telemetry::TelemetryConfig;
+use gipop_shm::project::fingerprint;
+use gipop_shm::{IpcConfig, Project, TagDef, Users};
+use hal::io_defs::TAG_BINDINGS;
+
+use crate::config::PlcConfig;
+use crate::{ctrl_loop, networks, scan, tags};
+#[cfg(feature = "modbus")]
+use crate::modbus;
+
+/// Prints what's wrong, false if anything is
+pub async fn check_config(config: &str, project: Option<&Project>, network_interface: Option<&str>) -> bool {
+    let mut problems = Vec::new();
+    let sections = [
+        IpcConfig::load(config).err(),
+        LoggingConfig::load(config).err(),
+        TelemetryConfig::load(config).err(),
+        Users::load(config).err(),
+    ];
+    problems.extend(sections.into_iter().flatten());
+
+    let cfg = PlcConfig::load(config).unwrap_or_else(|e| {
+        problems.push(e);
+        PlcConfig::default()
+    });
+    problems.extend(networks::check(&cfg.networks));
+    let tags = [tags::plc_tags(), networks::tags(&cfg.networks)].concat();
+    problems.extend(tag_problems(&tags));
+    problems.extend(binding_problems(&tags));
+    problems.extend(project.and_then(|project| project.check_tags(&tags).err()));
+    #[cfg(feature = "modbus")]
+    problems.extend(modbus::check(&cfg.modbus, &tags));
+
+    if let Some(network_interface) = network_interface {
+        match scan::bring_up(&ctrl_loop::start_maindevice(network_interface)).await {
+            Ok(bus) => {
+                problems.extend(bus.hardware_problems(&cfg.hardware));
+                problems.extend(bus.kbus_problems());
+            }
+            Err(e) => problems.push(format!("Can't bring up the bus on {}: {}", network_interface, e)),
+        }
+    }
+
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    println!("Tag list fingerprint {}", fingerprint(&tags));
+    match problems.len() {
+        0 if network_interface.is_none() => println!("{} and {} tags OK, hardware not checked (no network interface given)", config, tags.len()),
+        0 => println!("{}, {} tags and the bus OK", config, tags.len()),
+        count => println!("{} problem{}", count, if count == 1 { "" } else { "s" }),
+    }
+    problems.is_empty()
+}
+
+fn tag_problems(tags: &[TagDef]) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut names = HashSet::new();
+    for tag in tags {
+        if tag.name.is_empty() {
+            problems.push(format!("A tag in folder '{}' has no name", tag.folder));
+        } else if !names.insert(tag.name.as_str()) {
+            problems.push(format!("Tag '{}' is defined more than once, names have to be unique", tag.name));
+        }
+        if let Err(e) = check_entry(tag) {
+            problems.push(e);
+        }
+        for (range, kind) in [(tag.eu_range, "EU"), (tag.instrument_range, "instrument")] {
+            if let Some((low, high)) = range && low.partial_cmp(&high) != Some(Ordering::Less) {
+                problems.push(format!("Tag '{}' has {} range {} to {}, low has to be below high", tag.name, kind, low, high));
+            }
+        }
+    }
+    problems
+}
+
+fn binding_problems(tags: &[TagDef]) -> Vec<String> {
+    let mut problems = Vec::new();
+    for binding in TAG_BINDINGS {
+        if !tags.iter().any(|tag| tag.name == binding.tag) {
+            problems.push(format!("{} {:?} is bound to tag '{}', which isn't defined", binding.terminal, binding.channel, binding.tag));
+        }
+        if binding.channel as u8 > binding.channels {
+            problems.push(format!("{} {:?} is bound to tag '{}', but the {} only has {} channels", binding.terminal, binding.channel, binding.tag, binding.terminal, binding.channels));
+        }
+    }
+    problems
+}
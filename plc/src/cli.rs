@@ -0,0 +1,81 @@
+// gipop_plc's command line:
+//
+// gipop_plc <interface>                     run the PLC on the EtherCAT bus behind that NIC, e.g. eth0. More buses
+//                                           on other NICs are [[networks]] in gipop.toml (networks.rs)
+// gipop_plc --simulate                      run it on a virtual reference rack instead (virtual_bus.rs), no NIC needed
+// gipop_plc scan <interface>                list the bus and its process image (scan.rs)
+// gipop_plc check-config [<interface>]      check gipop.toml, the tags and with an interface the K-bus layout (check.rs)
+// gipop_plc bench <interface> [<seconds>] [<period_us>]   time the bus with no logic (bench.rs)
+// gipop_plc bench-handlers [<terminals>] [<cycles>]       time the I/O handlers on a made-up rack, no bus (bench.rs)
+//
+// Options, --config, --project and --log-level for every command, the rest only when running the PLC:
+// --config <path>      gipop.toml to read, ./gipop.toml by default
+// --project <dir>      a project bundle instead (gipop_shm::project), its gipop.toml. GIPOP_PROJECT if not given
+// --log-level <spec>   RUST_LOG syntax ("debug", "info,plc::ctrl_loop=debug"), over [logging] level and RUST_LOG
+// --cycle-us <us>      start a cycle at most this often, 0 (default) as often as the bus answers
+// --dry-run            load the config and tags, bring the bus up and list it like scan, then stop before OP
+//
+// Bad usage exits with 2 and says what's wrong, without starting anything.
+use clap::{Parser, Subcommand};
+
+use crate::bench;
+use crate::config::CONFIG_PATH;
+
+#[derive(Debug, Parser)]
+#[command(name = "gipop_plc", version, about = "Gipop's soft PLC on an EtherCAT bus")]
+#[command(subcommand_negates_reqs = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Network interface the main EtherCAT bus is on, the one the logic runs on
+    #[arg(required_unless_present = "simulate", conflicts_with = "simulate")]
+    pub interface: Option<String>,
+
+    /// gipop.toml to read
+    #[arg(long, global = true, value_name = "PATH", default_value = CONFIG_PATH)]
+    pub config: String,
+
+    /// Project bundle to run, its manifest and gipop.toml instead of --config
+    #[arg(long, global = true, value_name = "DIR", env = gipop_shm::project::PROJECT_ENV, conflicts_with = "config")]
+    pub project: Option<String>,
+
+    /// Log filter in RUST_LOG syntax, over [logging] level and RUST_LOG
+    #[arg(long, global = true, value_name = "SPEC")]
+    pub log_level: Option<String>,
+
+    /// Start a cycle at most this often, 0 for as often as the bus answers
+    #[arg(long, value_name = "US", default_value_t = 0)]
+    pub cycle_us: u64,
+
+    /// Run on a virtual reference rack instead of a network interface
+    #[arg(long)]
+    pub simulate: bool,
+
+    /// Stop after bringing the bus up and listing it, before OP
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// List the bus and its process image without running the PLC
+    Scan { interface: String },
+    /// Check gipop.toml, the tags and, with an interface, the bus
+    CheckConfig { interface: Option<String> },
+    /// Time the bus with no logic
+    Bench {
+        interface: String,
+        #[arg(default_value_t = bench::DEFAULT_SECONDS, value_parser = clap::value_parser!(u64).range(1..))]
+        seconds: u64,
+        #[arg(default_value_t = bench::DEFAULT_PERIOD_US, value_parser = clap::value_parser!(u64).range(1..))]
+        period_us: u64,
+    },
+    /// Time the I/O handlers on a made-up rack, without a bus
+    BenchHandlers {
+        #[arg(default_value_t = bench::DEFAULT_HANDLER_TERMINALS, value_parser = clap::value_parser!(u64).range(1..))]
+        terminals: u64,
+        #[arg(default_value_t = bench::DEFAULT_HANDLER_CYCLES, value_parser = clap::value_parser!(u64).range(1..))]
+        cycles: u64,
+    },
+}
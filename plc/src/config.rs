@@ -0,0 +1,223 @@
+// PLC specific sections of gipop.toml. `[ipc]` is shared with the consumers and read by gipop_shm::IpcConfig.
+//
+// [grpc]
+// enabled = false
+// listen = "127.0.0.1:50051"
+//
+// [modbus]
+// enabled = false
+// listen = "0.0.0.0:502"
+// unit_id = 1
+//
+// [[modbus.registers]]
+// tag = "temperature"
+// table = "input"      # coil, discrete, holding or input
+// address = 0          # 0 based, as on the wire
+// format = "int16"     # registers only: int16, uint16, int32, uint32, float32. Defaults by tag type
+// scale = 10.0         # register = value * scale, 21.5 °C reads as 215
+//
+// [watchdog]
+// hmi_timeout_ms = 10000
+//
+// [audit]
+// path = "gipop_audit.log"
+//
+// [blackbox]                # see blackbox.rs
+// dir = "blackbox"          # dumps land here on a crash, empty disables
+// cycles = 1000             # cycles kept, each with the whole process image
+// events = 500              # key tag changes, alarms and commands kept
+// tags = ["running", "area 1 lights"] # key tags, every tag but the analog ones if empty
+//
+// [time_sync]
+// enabled = true
+// source = "kernel"    # kernel or chrony, see gipop_shm::time_sync
+// interval_ms = 10000
+// max_offset_ms = 10.0
+//
+// [[hardware.subdevices]]   # the bus as it should be, in order. `gipop_plc check-config <interface>` compares
+// name = "BK1120"           # what the SubDevice reports
+// label = "Term 4 (BK1120)" # what the project calls it, for the messages
+// kbus = ["KL6581", "KL1889", "KL2889"] # BK couplers: the K-bus terminals behind it, in order
+//
+// [[networks]]              # more EtherCAT networks, each on a NIC of its own, see networks.rs
+// name = "line2"            # its tags are "line2.ok", "line2.EL1889 1 ch3"...
+// interface = "eth1"
+// cycle_us = 1000
+// subdevices = [{name = "EK1100"}, {name = "EL1889", label = "press 2 in"}, {name = "EL2889"}]
+use gipop_shm::time_sync::TimeSource;
+use serde::Deserialize;
+use std::time::Duration;
+use std::path::Path;
+
+pub const CONFIG_PATH: &str = "gipop.toml";
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct PlcConfig {
+    pub grpc: GrpcConfig,
+    pub modbus: ModbusConfig,
+    pub watchdog: WatchdogConfig,
+    pub audit: AuditConfig,
+    pub blackbox: BlackBoxConfig,
+    pub time_sync: TimeSyncConfig,
+    pub hardware: HardwareConfig,
+    pub networks: Vec<NetworkConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct GrpcConfig {
+    pub enabled: bool,  // needs the `grpc` cargo feature
+    pub listen: String, // use 0.0.0.0 to accept other hosts
+}
+
+impl Default for GrpcConfig {
+    fn default() -> Self {
+        Self { enabled: false, listen: "127.0.0.1:50051".to_string() }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ModbusConfig {
+    pub enabled: bool,
+    pub listen: String,
+    pub unit_id: u8, // requests for other units go unanswered, 0 and 255 are always accepted
+    pub registers: Vec<RegisterConfig>,
+}
+
+impl Default for ModbusConfig {
+    fn default() -> Self {
+        Self { enabled: false, listen: "0.0.0.0:502".to_string(), unit_id: 1, registers: Vec::new() }
+    }
+}
+
+/// Where one tag lives in the Modbus address space
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct RegisterConfig {
+    pub tag: String,
+    pub table: ModbusTable,
+    pub address: u16,
+    #[serde(default)]
+    pub format: Option<RegisterFormat>,
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModbusTable {
+    Coil,     // read/write bits
+    Discrete, // read only bits
+    Holding,  // read/write registers
+    Input,    // read only registers
+}
+
+/// How a value is packed into 16 bit registers, 32 bit formats take two with the high word first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RegisterFormat {
+    Int16,
+    Uint16,
+    Int32,
+    Uint32,
+    Float32,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct WatchdogConfig {
+    // HMI-commanded outputs go back to local control once the HMI heartbeat has been stale this long, 0 disables
+    pub hmi_timeout_ms: u64,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self { hmi_timeout_ms: 10_000 }
+    }
+}
+
+impl WatchdogConfig {
+    pub fn hmi_timeout(&self) -> Option<Duration> {
+        (self.hmi_timeout_ms != 0).then(|| Duration::from_millis(self.hmi_timeout_ms))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct AuditConfig {
+    pub path: String, // audit log of user commands, see audit.rs. Empty disables
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self { path: "gipop_audit.log".to_string() }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct BlackBoxConfig {
+    pub dir: String,
+    pub cycles: usize,
+    pub events: usize,
+    pub tags: Vec<String>,
+}
+
+impl Default for BlackBoxConfig {
+    fn default() -> Self {
+        Self { dir: "blackbox".to_string(), cycles: 1000, events: 500, tags: Vec::new() }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct TimeSyncConfig {
+    pub enabled: bool,
+    pub source: TimeSource,
+    pub interval_ms: u64,
+    pub max_offset_ms: f64, // further off than this counts as unsynchronized, however synchronized the daemon says it is
+}
+
+impl Default for TimeSyncConfig {
+    fn default() -> Self {
+        Self { enabled: true, source: TimeSource::Kernel, interval_ms: 10_000, max_offset_ms: 10.0 }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct HardwareConfig {
+    pub subdevices: Vec<SubDeviceConfig>, // empty: whatever is on the bus, nothing to compare with
+}
+
+/// One SubDevice of the expected bus, see `gipop import` for generating them from a TwinCAT project
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SubDeviceConfig {
+    pub name: String,
+    #[serde(default)]
+    pub label: String,
+    #[serde(default)]
+    pub kbus: Vec<String>,
+}
+
+/// An EtherCAT network besides the main bus, its tags are made from `subdevices`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct NetworkConfig {
+    pub name: String,
+    pub interface: String,
+    #[serde(default)]
+    pub cycle_us: u64, // a cycle at most this often, 0 for as often as the bus answers
+    #[serde(default)]
+    pub subdevices: Vec<SubDeviceConfig>,
+}
+
+impl PlcConfig {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        gipop_shm::config::load(path)
+    }
+}
@@ -1,476 +1,1130 @@
 use ethercrab::{
-    std::ethercat_now, MainDevice, MainDeviceConfig, PduStorage, RetryBehaviour, SubDeviceGroup, SubDeviceRef, Timeouts
+    std::ethercat_now, subdevice_group::{Op, PreOp}, MainDevice, MainDeviceConfig, PduLoop, PduStorage, RetryBehaviour, SubDeviceGroup, SubDeviceRef, Timeouts
 };
 use async_io::Timer;
 use memmap2::{Mmap, MmapMut};
 use std::{
-    fs::OpenOptions, ops::Deref, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex, RwLock}, time::Duration
+    ops::Deref, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex, RwLock}, time::{Duration, Instant}
 };
 use bitvec::prelude::*;
-use anyhow::Result;
+use anyhow::{Context, Result};
+use bytemuck::Zeroable;
 use enum_iterator::all;
 
 // For getting read/write locks to terminal objects in PLC memory
 use hal::io_defs::*;
 use hal::term_cfg::*;
+use hal::diagnostics::{self, CycleStats};
+use hal::pdi::PdiRange;
 use crate::logic::*; // Business logic execution; Calls to methods to accomplish business logic
-use crate::shared::{SharedData, SHM_PATH, map_shared_memory, read_data, write_data};
-
-const MAX_SUBDEVICES: usize = 16; /// Max no. of SubDevices that can be stored. This must be a power of 2 greater than 1.
-const MAX_PDU_DATA: usize = PduStorage::element_size(1100); /// Max PDU data payload size - set this to the max PDI size or higher.
-const MAX_FRAMES: usize = 16; /// Max no. of EtherCAT frames that can be in flight at any one time.
-const PDI_LEN: usize = 64; /// Max total PDI length.
-static PDU_STORAGE: PduStorage<MAX_FRAMES, MAX_PDU_DATA> = PduStorage::new();
-
-pub async fn entry_loop(network_interface: &String) -> Result<(), anyhow::Error> {
-
-    let network_interface = network_interface.to_string();
-    
-    let (tx, rx, pdu_loop) = PDU_STORAGE.try_split().expect("can only split once");
+use crate::blackbox::{self, CycleState};
+use gipop_shm::{BusDiagnostics, ImageField, IoAddress, IoChannel, Liveness, ProcessImage, Publisher, RingItem, SubDeviceDiagnostics, TagSample, TagValue, blob::BLOB_TOPOLOGY};
+use gipop_shm::tags::now_ms;
+use gipop_shm::telemetry::span_if;
+use tracing::Instrument;
+use gipop_shm::ring::{ITEM_ALARM_ACK, ITEM_AUDIT, ITEM_FORCE, ITEM_LOG_LEVEL, ITEM_RELOAD_CONFIG, ITEM_RESET_TOTALS, ITEM_RESTORE_TOTAL, ITEM_RUN_MODE, ITEM_TAG_WRITE, ITEM_UNFORCE};
+use gipop_shm::io_mirror::{IO_AI, IO_BUS_EBUS, IO_BUS_KBUS, IO_DI, IO_DO, IO_SMART, IO_STATUS_BUS_DOWN, IO_STATUS_FORCED};
+use crate::tags;
+
+pub const MAX_SUBDEVICES: usize = 16; /// Max no. of SubDevices that can be stored. This must be a power of 2 greater than 1.
+pub const MAX_PDU_DATA: usize = PduStorage::element_size(1100); /// Max PDU data payload size - set this to the max PDI size or higher.
+pub const MAX_FRAMES: usize = 16; /// Max no. of EtherCAT frames that can be in flight at any one time.
+pub const PDI_LEN: usize = 64; /// Max total PDI length.
+const BUS_DIAG_INTERVAL: Duration = Duration::from_secs(1); // register reads for the bus diagnostics cost bus time
+
+/// The main bus in OP with the terminals on it, what the logic cycles on
+pub struct Bus {
+    group: SubDeviceGroup<MAX_SUBDEVICES, PDI_LEN, Op>,
+    term_states: Arc<TermStates>, // as it was when the bus came up, only its terminals change
+    io: Vec<SubDeviceIo>, // in bus order
+    expected_wkc: u16,
+}
 
-    let maindevice = Arc::new(MainDevice::new(
-        pdu_loop,
-        Timeouts { // BK coupler is a bit sluggish
-            state_transition: Duration::from_millis(20_000), // Other values that seem to work: 5000, 15_000
-            pdu: Duration::from_micros(30_000), // Can try 50_000
-            eeprom: Duration::from_millis(10), // Can try 100
-            wait_loop_delay: Duration::from_millis(2),
-            mailbox_echo: Duration::from_millis(600), // Set to 100 in TwinCAT
-            mailbox_response: Duration::from_millis(6000), // Set to 6000 in TwinCAT. Can try 25_000
-        },
-        MainDeviceConfig {retry_behaviour: RetryBehaviour::Count(10), ..Default::default()}
-    ));
+// What a cycle does with a SubDevice's images, resolved in bring_up: which terminal objects its handlers fill or drain
+// (indices into TermStates) and where their bits are, so a cycle only takes views of the images
+enum SubDeviceIo {
+    El1889 { term: usize, inputs: PdiRange },
+    El2889 { term: usize, outputs: PdiRange },
+    El3024 { term: usize, inputs: PdiRange },
+    Bk1120 {
+        kl6581: Option<PdiRange>, // the same bits in both images
+        inputs: Vec<(usize, PdiRange)>, // simple K-bus input terminals
+        outputs: Vec<(usize, PdiRange)>, // simple K-bus output terminals
+    },
+    Other,
+}
 
-    std::thread::Builder::new()
-    .name("EthercatTxRxThread".to_owned())
-    .spawn(move || {
-        let runtime = smol::LocalExecutor::new();
-        let _ = smol::block_on(runtime.run(async {
-            ethercrab::std::tx_rx_task(&network_interface, tx, rx)
-                .expect("spawn TX/RX task")
-                .await
-        }));
-    })
-    .expect("build TX/RX thread");
+// The PLC on a MainDevice that's up: bus to OP, IPC thread, cycles until `shutdown`, bus back to INIT. main.rs brings
+// the parts up one by one (gipop_shm::startup), the tests run them in one go on a virtual bus (virtual_bus.rs)
+pub async fn run(maindevice: &MainDevice<'_>, publisher: Publisher, consumers: Liveness, cycle_every: u64, cycle_time: Duration, shutdown: Arc<AtomicBool>) -> Result<(), anyhow::Error> {
+    let bus = bring_up(maindevice).await?;
+    let (bus, _publisher) = cycle(maindevice, bus, publisher, consumers, cycle_every, cycle_time, shutdown).await;
+    step_down(maindevice, bus).await
+}
 
+// `maindevice` is on a NIC (start_maindevice) or with --simulate on a virtual bus (virtual_bus.rs). Its SubDevices
+// through PRE-OP setup to OP, with their terminals in a fresh TermStates. Err leaves the bus wherever it got, another
+// try starts over from INIT
+pub async fn bring_up(maindevice: &MainDevice<'_>) -> Result<Bus> {
     let group = maindevice
     .init_single_group::<MAX_SUBDEVICES, PDI_LEN>(ethercat_now)
     .await
-    .expect("Init");
+    .context("Init")?;
 
     log::info!("Discovered {} SubDevices", group.len());
 
-    // initialize terminal states
-    let term_states = init_term_states();
-
-    for sd in group.iter(&maindevice) {
-        if matches!(sd.name(), "EL3004" | "EL3024") {
-            log::info!("Found EL30{}4. Configuring...", sd.name().chars().nth(4).unwrap());
+    // Bus topology for consumers, in bus order. SubDevice names are plain ASCII so no escaping needed
+    let topology: Vec<String> = group.iter(maindevice)
+        .map(|sd| format!("{{\"name\":\"{}\",\"address\":{}}}", sd.name(), sd.configured_address()))
+        .collect();
+    queue_blob(BLOB_TOPOLOGY, format!("{{\"subdevices\":[{}]}}", topology.join(",")).into_bytes());
 
-            sd.sdo_write(0x1c12, 0, 0u8).await?;
-            sd
-                .sdo_write_array(0x1c13, &[0x1a00u16, 0x1a02, 0x1a04, 0x1a06])
-                .await?;
-            sd.sdo_write(0x1c13, 0, 0x4u8).await?;
-        }
+    // initialize terminal states
+    let mut term_states = TermStates::new();
 
-        // Configure K-bus terminals
-        if sd.name() == "BK1120" {
-            let num_of_terms: u8 = sd.sdo_read(0x4012, 0).await?;
-            log::info!("Number of K-bus terminals detected: {}", num_of_terms-1);
+    configure_pre_op(&group, maindevice, &mut term_states).await?;
 
-            for term in 1..num_of_terms+1 {
-                let term_name: u16 = sd.sdo_read(0x4012, term).await?;
-                let ts = term_states.clone();
-                parse_term(term_name, ts);
+    // Move from PRE-OP -> SAFE-OP -> OP
+    let group = group.into_op(maindevice).await.context("PRE-OP -> OP")?;
+
+    let mut io = Vec::new();
+    for subdevice in group.iter(maindevice) {
+        let images = subdevice.io_raw();
+        let (inputs, outputs) = (images.inputs().len(), images.outputs().len());
+        io.push(match subdevice.name() {
+            "EL2889" => {
+                term_states.ebus_do_terms.push(Arc::new(RwLock::new(DOTerm::new(8 * (inputs + outputs) as u8))));
+                SubDeviceIo::El2889 { term: term_states.ebus_do_terms.len() - 1, outputs: PdiRange::image(outputs) }
             }
-            let ts = term_states.clone();
-            set_slot_idx_range(ts);
-        }
+            "EL1889" => {
+                term_states.ebus_di_terms.push(Arc::new(RwLock::new(DITerm::new(8 * (inputs + outputs) as u8))));
+                SubDeviceIo::El1889 { term: term_states.ebus_di_terms.len() - 1, inputs: PdiRange::image(inputs) }
+            }
+            "EL3024" => {
+                let size = (inputs + outputs) / 4;
+                log::warn!(subdevice = subdevice.name(), address = subdevice.configured_address(); "size of EL3024: {}", size);
+                term_states.ebus_ai_terms.push(Arc::new(RwLock::new(AITerm::new(size as u8))));
+                SubDeviceIo::El3024 { term: term_states.ebus_ai_terms.len() - 1, inputs: PdiRange::image(inputs) }
+            }
+            "BK1120" => kbus_io(subdevice.name(), &term_states, (8 * inputs, 8 * outputs)),
+            _ => SubDeviceIo::Other,
+        });
+    }
+
+    {
+        let peek_num_of_channels = term_states.ebus_di_terms[0].read()
+        .expect("get EL1889 from dyn heap read lock");
 
+        log::info!("EL1889 in dyn heap: {}", peek_num_of_channels.num_of_channels);
     }
 
-    // Move from PRE-OP -> SAFE-OP -> OP
-    let group = group.into_op(&maindevice).await.expect("PRE-OP -> OP"); // Should probably handle errors better
-
-    for subdevice in group.iter(&maindevice) {
-        // TODO: all of these if blocks contain repetitive code, should be abstracted away in a helper function
-        if subdevice.name() == "EL2889" {
-            let io = subdevice.io_raw();
-            let size = 8*(io.inputs().len() + io.outputs().len());
-            let guard = term_states.clone();
-            let mut guard = guard.write().expect("get term_states write guard");
-
-            guard.ebus_do_terms
-            .push(
-                Arc::new(
-                    RwLock::new(
-                        DOTerm::new(size as u8))));
-        }
+    {
+        let peek_num_of_channels = term_states.ebus_do_terms[0].read()
+        .expect("get EL2889 from dyn heap read lock");
 
-        if subdevice.name() == "EL1889" {
-            let io = subdevice.io_raw();
-            let size = 8*(io.inputs().len() + io.outputs().len());
-            let guard = term_states.clone();
-            let mut guard = guard.write().expect("get term_states write guard");
-           
-            guard.ebus_di_terms
-            .push(
-                Arc::new(
-                    RwLock::new(
-                        DITerm::new(size as u8))));
-        }
+        log::info!("EL2889 in dyn heap: {}", peek_num_of_channels.num_of_channels);
+    }
 
-        if subdevice.name() == "EL3024" {
-            let io = subdevice.io_raw();
-            let size = (io.inputs().len() + io.outputs().len()) / 4;
-            let guard = term_states.clone();
-            let mut guard = guard.write().expect("get term_states write guard");
-            log::warn!("size of EL3024: {}", size);
-           
-            guard.ebus_ai_terms
-            .push(
-                Arc::new(
-                    RwLock::new(
-                        AITerm::new(size as u8))));
-        }
+    // Raw images for diagnostics (gipop image), laid out once now that every terminal is known
+    match image_layout(&group, maindevice, &term_states) {
+        Ok(image) => LOCAL_PLC_DATA.lock().unwrap().process_image = Some(Box::new(image)),
+        Err(e) => log::warn!("Not publishing the process image: {}", e),
     }
 
-    let shutdown = Arc::new(AtomicBool::new(false)); // Handling Ctrl+C
-    signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(&shutdown)).expect("Register hook");    
+    // Fieldbus health for consumers, see hal::diagnostics and gipop_shm::bus_diag
+    let expected_wkc = diagnostics::expected_wkc(group.iter(maindevice).map(|sd| {
+        let io = sd.io_raw();
+        (io.inputs().len(), io.outputs().len())
+    }));
+    blackbox::set_layout(group.iter(maindevice).map(|sd| {
+        let io = sd.io_raw();
+        (sd.name().to_owned(), io.inputs().len(), io.outputs().len())
+    }).collect());
+
+    // From here on the terminal list is only read, so nothing locks it. A thread holds the lock of at most one terminal
+    // at a time, taken after LOCAL_PLC_DATA if it needs both, so no two threads can wait on each other
+    Ok(Bus { group, term_states: Arc::new(term_states), io, expected_wkc })
+}
 
+// The logic on `bus`: IPC thread, cycles until `shutdown`. Hands the bus back still in OP, and the publisher once the
+// IPC thread has stopped too, so nothing takes commands or publishes tags while the bus goes back to INIT
+pub async fn cycle(maindevice: &MainDevice<'_>, bus: Bus, mut publisher: Publisher, mut consumers: Liveness, cycle_every: u64, cycle_time: Duration, shutdown: Arc<AtomicBool>) -> (Bus, Publisher) {
+    let Bus { group, term_states, io, expected_wkc } = bus;
     let shm_ts_ref = term_states.clone();
+    let shm_shutdown = shutdown.clone();
 
-    std::thread::Builder::new()
+    let shm_thread = std::thread::Builder::new()
     .name("PlcOpcUaServerShmThread".to_owned())
     .spawn(move || {
         let runtime = smol::LocalExecutor::new();
-        smol::block_on(runtime.run(async move {
-            loop {
+        smol::block_on(runtime.run(async {
+            while !shm_shutdown.load(Ordering::Relaxed) {
                 {
-                    opcua_shm(shm_ts_ref.clone());
+                    opcua_shm(&mut publisher, &mut consumers, &shm_ts_ref);
                 }
 
                 Timer::after(Duration::from_millis(100)).await;
             }
         }));
+        publisher
     })
     .expect("build shared mem thread");
 
-    {
-        let peek_num_of_channels 
-        = term_states.read()
-        .expect("get term_states read guard");
-        
-        let peek_num_of_channels = peek_num_of_channels.ebus_di_terms[0].read()
-        .expect("get EL1889 from dyn heap read lock");
-
-        log::info!("EL1889 in dyn heap: {}", peek_num_of_channels.num_of_channels);
-    }
-
-    {
-        let peek_num_of_channels 
-        = term_states.read()
-        .expect("get term_states read guard");
-        
-        let peek_num_of_channels = peek_num_of_channels.ebus_do_terms[0].read()
-        .expect("get EL2889 from dyn heap read lock");
-
-        log::info!("EL2889 in dyn heap: {}", peek_num_of_channels.num_of_channels);
-    }
+    let mut cycle_stats = CycleStats::default();
+    let mut coupler_status = u32::MAX; // no BK1120 seen yet
+    let mut last_cycle = Instant::now();
+    let mut last_bus_diag = Instant::now();
+    let mut next_cycle = Instant::now();
 
     // Enter the primary loop
     loop {
         if shutdown.load(Ordering::Relaxed) {
-            log::info!("Shutting down...");
+            log::info!(cycle = cycle_stats.cycles; "Shutting down...");
             break;
         }
-
-        group.tx_rx(&maindevice).await.expect("TX/RX");
-
-        // PLC logic entry point. Cycle time watchdog should be here (TODO)
-        plc_execute_logic(term_states.clone()).await;
-
-        {
-            let peek_num_of_channels 
-            = term_states.read()
-            .expect("get term_states read guard");
-
-            let peek_num_of_channels = peek_num_of_channels.ebus_di_terms[0].read()
-            .expect("get EL1889 from dyn heap read lock");
-
-            // log::info!("EL1889 in dyn heap value: {:b}", peek_num_of_channels.values);
+        if !cycle_time.is_zero() {
+            Timer::at(next_cycle).await;
+            // a late cycle doesn't make the next ones come early to catch up
+            next_cycle = (next_cycle + cycle_time).max(Instant::now());
         }
 
-        {
-            let peek_num_of_channels = term_states.read().expect("get term_states read guard");
-
-            let peek_num_of_channels = peek_num_of_channels.ebus_ai_terms[0].read()
-            .expect("get EL1889 from dyn heap read lock");
+        // One cycle in cycle_every is traced ([telemetry]), with its steps as child spans
+        let traced = cycle_every > 0 && cycle_stats.cycles % cycle_every == 0;
+        let _cycle = span_if(traced, || tracing::info_span!("cycle", cycle = cycle_stats.cycles)).entered();
 
-            let ch1_reading = peek_num_of_channels.read(Some(ChannelInput::Channel(TermChannel::Ch2))).unwrap();
-            let current = ch1_reading.pick_current().unwrap();
-            let humd = ((current * 493.0)/1000.0 + 1.022) * 5.0; // offset can be calculated delta / 5.0
+        let response = match group.tx_rx(maindevice).instrument(span_if(traced, || tracing::info_span!("tx_rx"))).await {
+            Ok(response) => response,
+            Err(e) => {
+                blackbox::dump(&format!("TX/RX failed: {:?}", e));
+                panic!("TX/RX: {:?}", e);
+            }
+        };
+        let now = Instant::now();
+        cycle_stats.record(now - last_cycle, response.working_counter, expected_wkc);
+        last_cycle = now;
+        if last_bus_diag.elapsed() >= BUS_DIAG_INTERVAL {
+            last_bus_diag = now;
+            let diag = bus_diagnostics(&group, maindevice, &cycle_stats, coupler_status).await;
+            LOCAL_PLC_DATA.lock().unwrap().bus_diag = Some(diag);
+        }
 
-            log::info!("EL3024 in dyn heap value: {}", humd);
+        // PLC logic entry point. Cycle time watchdog should be here (TODO)
+        let running = {
+            let mut plc_data = LOCAL_PLC_DATA.lock().unwrap();
+            plc_data.scan_time = cycle_stats.last;
+            plc_data.ebus_ok = response.working_counter == expected_wkc;
+            plc_data.kbus_ok = coupler_status == 0; // of the previous cycle, it's read with the inputs below
+            plc_data.running
+        };
+        // into the black box as exchanged: inputs just in, outputs just sent
+        let state = CycleState {
+            cycle: cycle_stats.cycles,
+            cycle_us: cycle_stats.last.as_micros() as u32,
+            wkc: response.working_counter,
+            running,
+            ebus_ok: response.working_counter == expected_wkc,
+            kbus_ok: coupler_status == 0,
+        };
+        blackbox::record_cycle(state, |image| {
+            for subdevice in group.iter(maindevice) {
+                let io = subdevice.io_raw();
+                image.push(io.inputs());
+                image.push(io.outputs());
+            }
+        });
+        if running {
+            plc_execute_logic(&term_states).instrument(span_if(traced, || tracing::info_span!("logic"))).await;
         }
+        apply_forces(&term_states);
 
         // Physical Input Terminal --> Program Code Input Terminal Object
-        for subdevice in group.iter(&maindevice) {
+        for (subdevice, io) in group.iter(maindevice).zip(&io) {
+            let _handler = span_if(traced, || tracing::info_span!("input_handler", subdevice = subdevice.name())).entered();
             let input = subdevice.inputs_raw();
             let input_bits = input.view_bits::<Lsb0>();
-        
-            if subdevice.name() == "EL1889" {
-                el1889_handler(&*TERM_EL1889, input_bits); // TODO purge static allocation
-
-                {
-                    let guard =
-                    term_states.read().expect("get term_states read guard");
 
-                    let mut guard = guard.ebus_di_terms[0].write()
-                    .expect("get EL1889 from dyn heap read lock");
+            match io {
+                SubDeviceIo::El1889 { term, inputs } => {
+                    el1889_handler(&*TERM_EL1889, inputs.view(input_bits)); // TODO purge static allocation
 
-                    guard.refresh(input_bits);
+                    term_states.ebus_di_terms[*term].write().expect("get EL1889 from dyn heap write lock").refresh(inputs.view(input_bits));
                 }
-            }
+                SubDeviceIo::El3024 { term, inputs } => {
+                    for channel in all::<TermChannel>() {
+                        if channel as u8 > EL3024_NUM_CHANNELS { break; }
+                        el3024_handler(&*TERM_EL3024, inputs.view(input_bits), channel);
+                    }
 
-            if subdevice.name() == "EL3024" {
-                for channel in all::<TermChannel>() {
-                    if channel as u8 > EL3024_NUM_CHANNELS { break; }
-                    el3024_handler(&*TERM_EL3024, input_bits, channel);
+                    term_states.ebus_ai_terms[*term].write().expect("get EL3024 from dyn heap write lock").refresh(inputs.view(input_bits));
                 }
+                SubDeviceIo::Bk1120 { kl6581, inputs, .. } => {
+                    // Bytes 0-1 are the K-bus status word the coupler maps in front of the terminals, 0 while the K-bus is fine
+                    coupler_status = input_bits[..KBUS_CTRL_BITS].load_le::<u16>() as u32;
 
-                {
-                    let guard =
-                    term_states.read().expect("get term_states read guard");
-
-                    let mut guard = guard.ebus_ai_terms[0].write()
-                    .expect("get EL1889 from dyn heap read lock");
-
-                    guard.refresh(input_bits);
-                }
-            }
-
-            if subdevice.name() == "BK1120" {
-                // View only KL6581 portion of the input process image (bytes 2-13)
-                // indexing is by bit in here, not by byte
-                kl6581_input_handler(&*TERM_KL6581, &input_bits[16..112]);
-                // kl1889_handler(&*TERM_KL1889, &input_bits[112..128]);
-
-                {
-                    let guard =
-                    term_states.read().expect("get term_states read guard");
+                    if let Some(kl6581) = kl6581 {
+                        kl6581_input_handler(&*TERM_KL6581, kl6581.view(input_bits));
+                    }
 
                     // kbus_terms are indexed based on physical location from BK coupler
-                    let mut guard = guard.kbus_terms[0].write()
-                    .expect("get BK1120/KL1889 from dyn heap read lock");
-
-                    guard.refresh_ctrlr(Some(input_bits), None);
+                    for (term, range) in inputs {
+                        term_states.kbus_terms[*term].write().expect("get K-bus term from dyn heap write lock").refresh_ctrlr(Some(range.view(input_bits)), None);
+                    }
                 }
+                SubDeviceIo::El2889 { .. } | SubDeviceIo::Other => {}
             }
         }
 
         // Program Code Output Terminal Object --> Physical Output Terminal
-        for subdevice in group.iter(&maindevice) {
+        for (subdevice, io) in group.iter(maindevice).zip(&io) {
+            let _handler = span_if(traced, || tracing::info_span!("output_handler", subdevice = subdevice.name())).entered();
             let mut output = subdevice.outputs_raw_mut();
             let output_bits = output.view_bits_mut::<Lsb0>();
 
-            if subdevice.name() == "EL2889" {
-                el2889_handler(output_bits, &*TERM_EL2889); // TODO purge static allocation
-
-                {
-                    let guard = 
-                    term_states.read().expect("get term_states read guard");
-
-                    // kbus_terms are indexed based on physical location from BK coupler
-                    let guard = guard.ebus_do_terms[0].read()
-                    .expect("get EL2889 from dyn heap read lock");
+            match io {
+                SubDeviceIo::El2889 { term, outputs } => {
+                    el2889_handler(outputs.view_mut(output_bits), &*TERM_EL2889); // TODO purge static allocation
 
-                    guard.refresh(output_bits);
+                    term_states.ebus_do_terms[*term].read().expect("get EL2889 from dyn heap read lock").refresh(outputs.view_mut(output_bits));
                 }
-            }
-            if subdevice.name() == "BK1120" {
-                // View only KL6581 portion of the output process image (bytes 2-13)
-                // indexing is by bit in here, not by byte.
-                kl6581_output_handler(&mut output_bits[16..112], &*TERM_KL6581);
-                // kl2889_handler(&mut output_bits[112..128], &*TERM_KL2889);
-
-                {
-                    let guard = 
-                    term_states.read().expect("get term_states read guard");
-
-                    let guard = guard.kbus_terms[1].read()
-                    .expect("get BK1120/KL2889 from dyn heap read lock");
-
-                    guard.refresh_term(output_bits);
+                SubDeviceIo::Bk1120 { kl6581, outputs, .. } => {
+                    if let Some(kl6581) = kl6581 {
+                        kl6581_output_handler(kl6581.view_mut(output_bits), &*TERM_KL6581);
+                    }
+
+                    for (term, range) in outputs {
+                        term_states.kbus_terms[*term].read().expect("get K-bus term from dyn heap read lock").refresh_term(range.view_mut(output_bits));
+                    }
                 }
+                SubDeviceIo::El1889 { .. } | SubDeviceIo::El3024 { .. } | SubDeviceIo::Other => {}
             }
         }
 
         {
-            let peek = term_states.read().expect("get term_states read guard");
-            let peek = peek.kbus_terms[0].read().expect("get KL1889 from dyn heap read lock");
-
-            let ch6_reading = peek.read(Some(ChannelInput::Channel(TermChannel::Ch6))).unwrap();
-            let res = ch6_reading.pick_simple().unwrap();
-            log::info!("KL1889 Channel 6 from dyn heap: {}", res)
+            let mut peek = term_states.kbus_terms[1].write().expect("get KL1889 from dyn heap read lock");
+            _ = peek.write(true, ChannelInput::Channel(TermChannel::Ch12));
         }
 
-        {
-            let peek = term_states.read().expect("get term_states read guard");
-            let mut peek = peek.kbus_terms[1].write().expect("get KL1889 from dyn heap read lock");
-            _ = peek.write(true, ChannelInput::Channel(TermChannel::Ch12));
+        // Raw images for `gipop image`, instead of peeking at bytes in here. Outputs as they go out next cycle
+        if let Some(image) = LOCAL_PLC_DATA.lock().unwrap().process_image.as_mut() {
+            image.cycle = cycle_stats.cycles;
+            for (idx, subdevice) in group.iter(maindevice).enumerate() {
+                let io = subdevice.io_raw();
+                image.set_data(idx, io.inputs(), io.outputs());
+            }
         }
 
     }
 
-    let group = group.into_safe_op(&maindevice).await.expect("OP -> SAFE-OP");
+    let publisher = shm_thread.join().expect("join shared mem thread"); // a panic in there stops the logic too
+    (Bus { group, term_states, io, expected_wkc }, publisher)
+}
+
+// `bus` from OP back to INIT, at shutdown
+pub async fn step_down(maindevice: &MainDevice<'_>, bus: Bus) -> Result<()> {
+    let group = bus.group.into_safe_op(maindevice).await.context("OP -> SAFE-OP")?;
     log::info!("Commence shutdown: OP -> SAFE-OP");
 
-    let group = group.into_pre_op(&maindevice).await.expect("SAFE-OP -> PRE-OP");
+    let group = group.into_pre_op(maindevice).await.context("SAFE-OP -> PRE-OP")?;
     log::info!("SAFE-OP -> PRE-OP");
 
-    let _group = group.into_init(&maindevice).await.expect("PRE-OP -> INIT");
+    let _group = group.into_init(maindevice).await.context("PRE-OP -> INIT")?;
     log::info!("PRE-OP -> INIT, shutdown complete");
 
     Ok(())
 }
 
-fn opcua_shm(term_states: Arc<RwLock<TermStates>>) {
-    let file = OpenOptions::new().read(true).write(true).open(SHM_PATH).unwrap();
+// MainDevice on `network_interface` with its TX/RX thread running. Every call gets a PDU storage of its own, kept
+// for the rest of the process like the MainDevice on it, so each network (networks.rs) has its own
+pub fn start_maindevice(network_interface: &str) -> Arc<MainDevice<'static>> {
+    let network_interface = network_interface.to_owned();
+
+    let storage: &'static PduStorage<MAX_FRAMES, MAX_PDU_DATA> = Box::leak(Box::new(PduStorage::new()));
+    let (tx, rx, pdu_loop) = storage.try_split().expect("split fresh PDU storage");
+    let maindevice = Arc::new(new_maindevice(pdu_loop));
+
+    std::thread::Builder::new()
+    .name(format!("EthercatTxRxThread {}", network_interface))
+    .spawn(move || {
+        let runtime = smol::LocalExecutor::new();
+        let _ = smol::block_on(runtime.run(async {
+            ethercrab::std::tx_rx_task(&network_interface, tx, rx)
+                .expect("spawn TX/RX task")
+                .await
+        }));
+    })
+    .expect("build TX/RX thread");
+
+    maindevice
+}
+
+// MainDevice with the timeouts and retries the rack needs, on whatever moves `pdu_loop`'s frames
+pub fn new_maindevice(pdu_loop: PduLoop<'static>) -> MainDevice<'static> {
+    MainDevice::new(
+        pdu_loop,
+        Timeouts { // BK coupler is a bit sluggish
+            state_transition: Duration::from_millis(20_000), // Other values that seem to work: 5000, 15_000
+            pdu: Duration::from_micros(30_000), // Can try 50_000
+            eeprom: Duration::from_millis(10), // Can try 100
+            wait_loop_delay: Duration::from_millis(2),
+            mailbox_echo: Duration::from_millis(600), // Set to 100 in TwinCAT
+            mailbox_response: Duration::from_millis(6000), // Set to 6000 in TwinCAT. Can try 25_000
+        },
+        MainDeviceConfig {retry_behaviour: RetryBehaviour::Count(10), ..Default::default()}
+    )
+}
+
+// Where the BK1120's K-bus terminals are in its images (`image_bits`, inputs and outputs): the KL6581 TERM_KL6581
+// stands for, the first one, and every simple terminal. One that doesn't fit is left out and logged,
+// kbus_layout_problems says why at PRE-OP
+fn kbus_io(subdevice: &str, term_states: &TermStates, image_bits: (usize, usize)) -> SubDeviceIo {
+    let (mut kl6581, mut inputs, mut outputs) = (None, Vec::new(), Vec::new());
+    for (idx, term) in term_states.kbus_terms.iter().enumerate() {
+        let term = term.read().expect("get K-bus term read guard");
+        let range = term.pdi_range();
+        let fits = (!term.in_image(false) || range.fits(image_bits.0)) && (!term.in_image(true) || range.fits(image_bits.1));
+        if !fits || range.len != term.image_bits() {
+            log::error!(subdevice; "Not exchanging {}, its slot range {:?} doesn't fit the coupler's images", term.label(), term.slot_idx_range);
+            continue;
+        }
+        match term.gender {
+            KBusTerminalGender::Enby if term.name == 6581 && kl6581.is_none() => kl6581 = Some(range),
+            KBusTerminalGender::Enby => log::warn!(subdevice; "Not exchanging {}, only one KL6581 is handled", term.label()),
+            KBusTerminalGender::Input => inputs.push((idx, range)),
+            KBusTerminalGender::Output => outputs.push((idx, range)),
+        }
+    }
+    SubDeviceIo::Bk1120 { kl6581, inputs, outputs }
+}
+
+// Mailbox setup while the group is in PRE-OP: EL30x4 PDO assignment, and the K-bus terminals behind a BK1120 read
+// from 0x4012 into `term_states` with their slot ranges set
+pub async fn configure_pre_op(group: &SubDeviceGroup<MAX_SUBDEVICES, PDI_LEN, PreOp>, maindevice: &MainDevice<'_>, term_states: &mut TermStates) -> Result<()> {
+    for sd in group.iter(maindevice) {
+        if matches!(sd.name(), "EL3004" | "EL3024") {
+            log::info!(subdevice = sd.name(), address = sd.configured_address(); "Found EL30{}4. Configuring...", sd.name().chars().nth(4).unwrap());
+
+            sd.sdo_write(0x1c12, 0, 0u8).await?;
+            sd
+                .sdo_write_array(0x1c13, &[0x1a00u16, 0x1a02, 0x1a04, 0x1a06])
+                .await?;
+            sd.sdo_write(0x1c13, 0, 0x4u8).await?;
+        }
+
+        // Configure K-bus terminals
+        if sd.name() == "BK1120" {
+            let num_of_terms: u8 = sd.sdo_read(0x4012, 0).await?;
+            log::info!(subdevice = sd.name(), address = sd.configured_address(); "Number of K-bus terminals detected: {}", num_of_terms-1);
 
-    let mut mmap = map_shared_memory(&file);
-    let mut data = read_data(&mmap);
+            for term in 1..num_of_terms+1 {
+                let term_name: u16 = sd.sdo_read(0x4012, term).await?;
+                parse_term(sd.name(), term, term_name, term_states);
+            }
+            set_slot_idx_range(term_states);
+
+            let terms: Vec<_> = term_states.kbus_terms.iter().map(|term| term.read().expect("get K-bus term read guard")).collect();
+            for problem in kbus_layout_problems(&terms.iter().map(|term| &**term).collect::<Vec<_>>(), None) {
+                log::error!(subdevice = sd.name(), address = sd.configured_address(); "{}", problem);
+            }
+        }
 
+    }
+    Ok(())
+}
+
+fn opcua_shm(table: &mut Publisher, consumers: &mut Liveness, term_states: &TermStates) {
     // the reason for making a duplicate is so that the logic loop can fetch from LOCAL_PLC_DATA
-    // instead of opening the shared mem file, which is dedicated for IPC between the ctrl_loop and the OPC UA server
+    // instead of going through the IPC transport, which is dedicated to the ctrl_loop <-> OPC UA server link
     let mut plc_data = LOCAL_PLC_DATA.lock().unwrap();
 
+    // Everything published here carries the same stamp, consumers' spans join this one's trace by it
+    let publish_ts = now_ms();
+    let _sync = tracing::info_span!("ipc_sync", publish_ts).entered();
+
+    // every channel of every terminal, consumers pick what they need from it
+    let mut io_channels = io_mirror(term_states, plc_data.ebus_ok, plc_data.kbus_ok);
+    for channel in io_channels.iter_mut().filter(|channel| plc_data.forces.contains_key(&channel.address())) {
+        channel.status |= IO_STATUS_FORCED;
+    }
+    table.write_io(&io_channels);
+
     {   
-        let rd_guard = term_states.read().expect("Acquire TERM_EL3024 read guard"); // calling read() twice in this scope will cause a freeze
-        let guard = rd_guard.ebus_ai_terms[0].read().unwrap();
+        let guard = term_states.ebus_ai_terms[0].read().expect("get EL3024 read guard");
         let ch2_reading = guard.read(Some(ChannelInput::Channel(TermChannel::Ch2))).unwrap();
         let current = ch2_reading.pick_current().unwrap();
         let temp = ((current * 493.0)/1000.0 + 1.044) * 5.0; // offset can be calculated delta / 5.0
         plc_data.temperature = temp;
-        data.temperature = temp;
 
         let ch1_reading = guard.read(Some(ChannelInput::Channel(TermChannel::Ch1))).unwrap();
         let current = ch1_reading.pick_current().unwrap();
         let rh = ((current * 493.0)/1000.0 + 1.018) * 10.0; // offset can be calculated delta / 10.0
         plc_data.humidity = rh;
-        data.humidity = rh;
     }
 
-    let ts_status = term_states.clone();
-    let rd_guard = ts_status.read().expect("get term_states read guard");
-    let rd_guard = rd_guard.kbus_terms[0].read().expect("get KL1889 read guard");
-    data.status = rd_guard.read(Some(ChannelInput::Channel(TermChannel::Ch6))).unwrap().pick_simple().unwrap() as u32;
+    {
+        let rd_guard = term_states.kbus_terms[0].read().expect("get KL1889 read guard");
+        plc_data.status = rd_guard.read(Some(ChannelInput::Channel(TermChannel::Ch6))).unwrap().pick_simple().unwrap() as u32;
+    }
+
+    plc_data.area_1_lights = read_area_1_lights(term_states) as u32;
+    plc_data.area_2_lights = read_area_2_lights(term_states) as u32;
+
+    // Heartbeats: ours out, the consumers' in
+    table.heartbeat();
+    match consumers.update(table.consumer_heartbeat()) {
+        Some(true) => log::info!("HMI heartbeat detected"),
+        Some(false) => log::warn!("HMI heartbeat went stale"),
+        None => {}
+    }
+    plc_data.hmi_alive = consumers.is_alive();
+    if plc_data.hmi_alive {
+        plc_data.hmi_last_alive = Some(std::time::Instant::now());
+    }
+
+    // published as one update, consumers never see a mix of this cycle's and the last cycle's values
+    let idx = |name: &str| table.index_of(name).expect("tag missing from shm directory");
+    let hmi_cmd_idx = idx(tags::AREA_1_LIGHTS_HMI_CMD);
+    let mut values = vec![
+        (idx(tags::TEMPERATURE), TagValue::Float32(plc_data.temperature)),
+        (idx(tags::HUMIDITY), TagValue::Float32(plc_data.humidity)),
+        (idx(tags::STATUS), TagValue::UInt32(plc_data.status)),
+        (idx(tags::AREA_1_LIGHTS), TagValue::UInt32(plc_data.area_1_lights)),
+        (idx(tags::AREA_2_LIGHTS), TagValue::UInt32(plc_data.area_2_lights)),
+        (idx(tags::HMI_ALIVE), TagValue::Bool(plc_data.hmi_alive)),
+        (idx(tags::RUNNING), TagValue::Bool(plc_data.running)),
+        (idx(tags::SCAN_TIME), TagValue::Float32(plc_data.scan_time.as_secs_f32() * 1000.0)),
+        (idx(tags::FORCED_CHANNELS), TagValue::UInt32(plc_data.forces.len() as u32)),
+        (idx(tags::ENOCEAN_TELEGRAMS), TagValue::UInt32(plc_data.enocean_telegrams)),
+        (idx(tags::KL6581_FAULT), TagValue::Bool(plc_data.kl6581_fault)),
+        (idx(tags::HMI_WATCHDOG_TRIPPED), TagValue::Bool(plc_data.hmi_watchdog_tripped)),
+        (idx(tags::CLOCK_UNSYNCED), TagValue::Bool(plc_data.clock_unsynced)),
+    ];
+    values.extend(crate::networks::values(|name| table.index_of(name)));
+    // left waiting for initial data while the clock isn't monitored, consumers don't flag anything then
+    if let Some(clock) = plc_data.clock {
+        values.extend([
+            (idx(tags::CLOCK_SYNCED), TagValue::Bool(!plc_data.clock_unsynced)),
+            (idx(tags::CLOCK_OFFSET), TagValue::Float32(clock.offset_ms as f32)),
+            (idx(tags::CLOCK_MAX_ERROR), TagValue::Float32(clock.max_error_ms as f32)),
+        ]);
+    }
+    let alarms = [
+        (tags::KL6581_FAULT, plc_data.kl6581_fault),
+        (tags::HMI_WATCHDOG_TRIPPED, plc_data.hmi_watchdog_tripped),
+        (tags::CLOCK_UNSYNCED, plc_data.clock_unsynced),
+    ].map(|(name, active)| (name, idx(name), active));
+    let samples: Vec<(usize, TagSample)> = values.iter().map(|&(idx, value)| (idx, TagSample::good_at(value, publish_ts))).collect();
+    tracing::info_span!("publish", tags = samples.len()).in_scope(|| table.write_samples(&samples)).expect("publish PLC tags");
+    blackbox::record_tags(table.tags(), &values);
+
+    // Alarm transitions also go out as events, for consumers that log rather than watch tags
+    for (name, alarm_idx, active) in alarms {
+        if plc_data.published_alarms.insert(name, active).unwrap_or(false) != active {
+            log::warn!(tag = name; "Alarm {}: {}", if active { "raised" } else { "cleared" }, name);
+            blackbox::event(|| format!("alarm {}: {}", if active { "raised" } else { "cleared" }, name));
+            plc_data.events.push_back(RingItem::alarm(alarm_idx, active));
+        }
+    }
+    #[cfg(feature = "modbus")]
+    crate::modbus::publish(&values);
+    #[cfg(feature = "grpc")]
+    crate::grpc::publish(&values);
+
+    // Incoming to PLC: HMI commands from the command ring/socket (and gRPC/Modbus clients) to local PLC state. Those
+    // from the ring/socket once their audit says who sent them and [users] lets them, gRPC and Modbus check their own
+    let received: Vec<RingItem> = std::iter::from_fn(|| table.pop_command()).collect();
+    let plc_data = &mut *plc_data;
+    #[allow(unused_mut)] // in a build without gRPC and Modbus
+    let mut commands = plc_data.unaudited.admit(received, &plc_data.users, table.tags());
+    #[cfg(feature = "modbus")]
+    commands.extend(std::iter::from_fn(crate::modbus::pop_command));
+    #[cfg(feature = "grpc")]
+    commands.extend(std::iter::from_fn(crate::grpc::pop_command));
+    for item in commands {
+        let _command = tracing::info_span!("command", kind = item.kind, tag = item.tag, seq = item.seq).entered();
+        if item.kind != ITEM_AUDIT {
+            blackbox::event(|| format!("command seq={} {}", item.seq, crate::audit::describe(&item, table.tags())));
+        }
+        if item.seq != 0 {
+            plc_data.last_cmd_seq = item.seq;
+            if item.kind != ITEM_AUDIT {
+                plc_data.recent_commands.push(item);
+            }
+        }
+        match item.kind {
+            ITEM_TAG_WRITE if item.tag as usize == hmi_cmd_idx => {
+                let cmd = item.value as u32;
+                plc_data.pending_hmi_cmds.push_back((item.seq, cmd));
+                _ = table.write_samples(&[(hmi_cmd_idx, TagSample::good_at(TagValue::UInt32(cmd), publish_ts))]); // mirror the last command received for read-back
+                #[cfg(feature = "modbus")]
+                crate::modbus::publish(&[(hmi_cmd_idx, TagValue::UInt32(cmd))]);
+                #[cfg(feature = "grpc")]
+                crate::grpc::publish(&[(hmi_cmd_idx, TagValue::UInt32(cmd))]);
+            }
+            // External values from other OPC UA servers (gipop_opcua_client), published as received
+            ITEM_TAG_WRITE if table.tags().get(item.tag as usize).is_some_and(|tag| tag.is_external()) => {
+                let tag = table.tags()[item.tag as usize].clone();
+                let value = TagValue::from_raw(tag.ty, item.value);
+                _ = table.write_samples(&[(item.tag as usize, TagSample::good_at(value, publish_ts))]);
+                plc_data.external.insert(tag.name, value);
+            }
+            // Any other writable tag (setpoints, other networks' outputs): validated, published as received and left
+            // for the logic, or for the network whose output it is
+            ITEM_TAG_WRITE if table.tags().get(item.tag as usize).is_some_and(|tag| tag.writable()) => {
+                let tag = table.tags()[item.tag as usize].clone();
+                let value = TagValue::from_raw(tag.ty, item.value);
+                if !tag.accepts(value) {
+                    log::warn!(tag = tag.name.as_str(); "Ignoring write of {} to '{}', out of range {:?}", value.as_f64(), tag.name, tag.write_range());
+                    continue;
+                }
+                _ = table.write_samples(&[(item.tag as usize, TagSample::good_at(value, publish_ts))]);
+                #[cfg(feature = "modbus")]
+                crate::modbus::publish(&[(item.tag as usize, value)]);
+                #[cfg(feature = "grpc")]
+                crate::grpc::publish(&[(item.tag as usize, value)]);
+                if !crate::networks::write(&tag.name, value) {
+                    plc_data.setpoints.insert(tag.name, value);
+                }
+            }
+            // Operator actions, applied right here. Logged, they change what the plant does outside of the logic
+            ITEM_ALARM_ACK => match table.tags().get(item.tag as usize).filter(|tag| tag.is_alarm()) {
+                Some(tag) => {
+                    log::info!(tag = tag.name.as_str(); "Alarm acknowledged: {}", tag.name);
+                    plc_data.events.push_back(item); // so every consumer learns, not only the one that acknowledged
+                }
+                None => log::warn!(tag = item.tag; "Ignoring acknowledge for tag {}, not an alarm", item.tag),
+            },
+            ITEM_FORCE | ITEM_UNFORCE => {
+                let address = item.io_address();
+                let known = io_channels.iter().any(|channel| channel.address() == address);
+                if address.kind != IO_DO || !known {
+                    log::warn!(channel = address.path(); "Ignoring force on {}, only existing DO channels can be forced", address.path());
+                }
+                else if item.kind == ITEM_FORCE {
+                    let value = f64::from_bits(item.value) != 0.0;
+                    log::warn!(channel = address.path(); "Forcing {} to {}", address.path(), value);
+                    plc_data.forces.insert(address, value);
+                }
+                else if plc_data.forces.remove(&address).is_some() {
+                    log::warn!(channel = address.path(); "Released force on {}", address.path());
+                }
+            }
+            ITEM_RUN_MODE => {
+                let running = item.value != 0;
+                if running != plc_data.running {
+                    log::warn!("Logic {} by operator", if running { "started" } else { "stopped" });
+                    plc_data.running = running;
+                }
+            }
+            ITEM_RESET_TOTALS => {
+                log::info!("Totals reset by operator");
+                plc_data.enocean_telegrams = 0;
+            }
+            ITEM_RESTORE_TOTAL => match table.tags().get(item.tag as usize).map(|tag| tag.name.as_str()) {
+                Some(tags::ENOCEAN_TELEGRAMS) => {
+                    plc_data.enocean_telegrams = item.value as u32;
+                    log::info!("Total '{}' restored to {}", tags::ENOCEAN_TELEGRAMS, plc_data.enocean_telegrams);
+                }
+                _ => log::warn!(tag = item.tag; "Ignoring restore of tag {}, not a total", item.tag),
+            },
+            ITEM_LOG_LEVEL => {
+                let (module, level) = item.logged_level();
+                match gipop_shm::logging::set_level(&module, level) {
+                    Ok(()) => log::info!("Log levels set by operator: {}", gipop_shm::logging::levels()),
+                    Err(e) => log::warn!("Ignoring log level for '{}': {}", module, e),
+                }
+            }
+            // reads files and takes LOCAL_PLC_DATA itself, off this thread
+            ITEM_RELOAD_CONFIG => {
+                log::info!("Config reload requested by operator");
+                std::thread::spawn(crate::reload::on_command);
+            }
+            ITEM_AUDIT => plc_data.recent_commands.audit(&item, table.tags()),
+            _ => log::warn!(tag = item.tag; "Ignoring command kind {} for tag {}", item.kind, item.tag),
+        }
+    }
+
+    // Acks are cumulative: with nothing pending everything received is done (ignored commands included),
+    // otherwise only up to what the logic has consumed
+    if plc_data.pending_hmi_cmds.is_empty() {
+        plc_data.acked_cmd_seq = plc_data.last_cmd_seq;
+    }
+    // and never past a command still waiting for its audit
+    let mut acked = plc_data.acked_cmd_seq;
+    if let Some(held) = plc_data.unaudited.oldest_seq() && (acked.wrapping_sub(held) as i32) >= 0 {
+        acked = held.wrapping_sub(1);
+    }
+    table.ack_command(acked);
+
+    // Outgoing from PLC: events, payloads queued by the logic, the latest bus diagnostics and process image
+    if let Some(diag) = plc_data.bus_diag.take() {
+        table.write_bus_diag(&diag);
+    }
+    if let Some(image) = &plc_data.process_image {
+        table.write_process_image(image);
+    }
+    while let Some(item) = plc_data.events.pop_front() {
+        #[cfg(feature = "grpc")]
+        crate::grpc::event(&item);
+        table.push_event(item);
+    }
+    while let Some((kind, payload)) = plc_data.blobs.pop_front() {
+        if let Err(e) = table.push_blob(kind, &payload) {
+            log::warn!("Dropping payload of kind {}: {}", kind, e);
+        }
+    }
+}
+
+// One diagnostics pass: the cycle stats so far plus each SubDevice's AL state and error counters read from its ESC.
+// A SubDevice that doesn't answer shows up with zeroed registers (state UNKNOWN).
+async fn bus_diagnostics(
+    group: &SubDeviceGroup<MAX_SUBDEVICES, PDI_LEN, Op>,
+    maindevice: &MainDevice<'_>,
+    stats: &CycleStats,
+    coupler_status: u32,
+) -> BusDiagnostics {
+    let micros = |d: Duration| d.as_micros().min(u32::MAX as u128) as u32;
+    let mut diag = BusDiagnostics {
+        cycles: stats.cycles,
+        wkc_errors: stats.wkc_errors,
+        cycle_us_last: micros(stats.last),
+        cycle_us_min: micros(stats.min),
+        cycle_us_max: micros(stats.max),
+        cycle_us_avg: micros(stats.avg),
+        jitter_us_max: micros(stats.jitter_max),
+        jitter_us_avg: micros(stats.jitter_avg),
+        coupler_status,
+        subdevice_count: 0,
+        subdevices: [SubDeviceDiagnostics::default(); gipop_shm::bus_diag::MAX_BUS_SUBDEVICES],
+    };
+
+    for (entry, sd) in diag.subdevices.iter_mut().zip(group.iter(maindevice)) {
+        *entry = SubDeviceDiagnostics::new(sd.name(), sd.configured_address());
+        entry.al_state = sd.register_read::<u16>(diagnostics::REG_AL_STATUS).await.unwrap_or(0);
+        entry.al_status_code = sd.register_read::<u16>(diagnostics::REG_AL_STATUS_CODE).await.unwrap_or(0);
+        entry.port_errors = diagnostics::port_errors(sd.register_read::<[u8; 8]>(diagnostics::REG_RX_ERROR_COUNTERS).await.unwrap_or_default());
+        entry.lost_links = sd.register_read::<[u8; 4]>(diagnostics::REG_LOST_LINK_COUNTERS).await
+            .map(|ports| ports.iter().map(|&n| n as u32).sum())
+            .unwrap_or(0);
+        diag.subdevice_count += 1;
+    }
+    diag
+}
+
+// Where every SubDevice, terminal channel and K-bus status/control word is in the process image the PLC publishes,
+// see gipop_shm::process_image. Terminals are numbered like io_mirror does, channel fields carry their mirror address.
+fn image_layout(group: &SubDeviceGroup<MAX_SUBDEVICES, PDI_LEN, Op>, maindevice: &MainDevice<'_>, term_states: &TermStates) -> Result<ProcessImage, String> {
+    let sizes: Vec<_> = group.iter(maindevice)
+        .map(|sd| {
+            let io = sd.io_raw();
+            (sd.name().to_owned(), sd.configured_address(), io.inputs().len(), io.outputs().len())
+        })
+        .collect();
+    let mut image = ProcessImage::zeroed();
+    image.inputs_len = sizes.iter().map(|(_, _, inputs, _)| inputs).sum::<usize>() as u32;
+    image.outputs_len = sizes.iter().map(|(_, _, _, outputs)| outputs).sum::<usize>() as u32;
+
+    let address = |bus, kind, terminal: usize, channel: usize| IoAddress { bus, kind, terminal: terminal as u16, channel: channel as u16 };
+    let (mut in_offset, mut out_offset) = (0, image.inputs_len as usize);
+    let (mut ebus_di, mut ebus_do, mut ebus_ai) = (0, 0, 0);
+    for (name, sd_address, inputs, outputs) in sizes {
+        let sd = image.push_subdevice(&name, sd_address, (in_offset, inputs), (out_offset, outputs))?;
+        let (in_bit, out_bit) = (in_offset * 8, out_offset * 8);
+        match name.as_str() {
+            "EL1889" => {
+                for ch in 0..inputs * 8 {
+                    image.push_field(ImageField::new(&name, sd, in_bit + ch, 1).channel(address(IO_BUS_EBUS, IO_DI, ebus_di, ch + 1)))?;
+                }
+                ebus_di += 1;
+            }
+            "EL2889" => {
+                for ch in 0..outputs * 8 {
+                    image.push_field(ImageField::new(&name, sd, out_bit + ch, 1).channel(address(IO_BUS_EBUS, IO_DO, ebus_do, ch + 1)))?;
+                }
+                ebus_do += 1;
+            }
+            "EL3004" | "EL3024" => {
+                // status word then value per channel, the PDO assignment configure_pre_op sets up
+                for ch in 0..inputs / 4 {
+                    let channel = address(IO_BUS_EBUS, IO_AI, ebus_ai, ch + 1);
+                    image.push_field(ImageField::new(&format!("{} status", name), sd, in_bit + ch * 32, 16).channel(channel))?;
+                    image.push_field(ImageField::new(&format!("{} value", name), sd, in_bit + ch * 32 + 16, 16).channel(channel))?;
+                }
+                ebus_ai += 1;
+            }
+            "BK1120" => {
+                image.push_field(ImageField::new("K-bus status", sd, in_bit, KBUS_CTRL_BITS))?;
+                image.push_field(ImageField::new("K-bus control", sd, out_bit, KBUS_CTRL_BITS))?;
+                let (mut kbus_di, mut kbus_do, mut kbus_smart) = (0, 0, 0);
+                for term in &term_states.kbus_terms {
+                    let term = term.read().expect("get K-bus term read guard");
+                    let begin = term.slot_idx_range.0 as usize;
+                    let label = if term.intelligent { term.label() } else { "K-bus digital".to_owned() };
+                    match term.gender {
+                        KBusTerminalGender::Input => {
+                            for ch in 0..term.image_bits() {
+                                image.push_field(ImageField::new(&label, sd, in_bit + begin + ch, 1).channel(address(IO_BUS_KBUS, IO_DI, kbus_di, ch + 1)))?;
+                            }
+                            kbus_di += 1;
+                        }
+                        KBusTerminalGender::Output => {
+                            for ch in 0..term.image_bits() {
+                                image.push_field(ImageField::new(&label, sd, out_bit + begin + ch, 1).channel(address(IO_BUS_KBUS, IO_DO, kbus_do, ch + 1)))?;
+                            }
+                            kbus_do += 1;
+                        }
+                        KBusTerminalGender::Enby => {
+                            for bit in [in_bit, out_bit] {
+                                image.push_field(ImageField::new(&label, sd, bit + begin, term.image_bits()).channel(address(IO_BUS_KBUS, IO_SMART, kbus_smart, 0)))?;
+                            }
+                            kbus_smart += 1;
+                        }
+                    }
+                }
+            }
+            _ => {} // couplers and anything the PLC doesn't know, the dump shows their bytes raw
+        }
+        in_offset += inputs;
+        out_offset += outputs;
+    }
+    Ok(image)
+}
+
+// Flattens the terminal states into the shm I/O mirror, see gipop_shm::io_mirror. Channels on a bus that's down are
+// flagged IO_STATUS_BUS_DOWN, their values are whatever the bus delivered last.
+fn io_mirror(term_states: &TermStates, ebus_ok: bool, kbus_ok: bool) -> Vec<IoChannel> {
+    let mut channels = Vec::new();
+    let entry = |bus, kind, terminal: usize, channel: usize| IoChannel { bus, kind, terminal: terminal as u16, channel: channel as u16, ..Default::default() };
+    let bits = |bits: &BitSlice<u8, Lsb0>| bits.iter().map(|bit| *bit as u8 as f64).collect::<Vec<_>>();
+
+    let (mut kbus_di, mut kbus_do, mut kbus_smart) = (0, 0, 0);
+    for term in &term_states.kbus_terms {
+        let term = term.read().expect("get K-bus term read guard");
+        match term.gender {
+            KBusTerminalGender::Input | KBusTerminalGender::Output => {
+                let (kind, terminal, image) = if term.gender == KBusTerminalGender::Input {
+                    kbus_di += 1;
+                    (IO_DI, kbus_di - 1, term.tx_data.as_deref())
+                }
+                else {
+                    kbus_do += 1;
+                    (IO_DO, kbus_do - 1, term.rx_data.as_deref())
+                };
+                for (ch, value) in image.map(bits).unwrap_or_default().into_iter().enumerate() {
+                    channels.push(IoChannel { value, ..entry(IO_BUS_KBUS, kind, terminal, ch + 1) });
+                }
+            }
+            KBusTerminalGender::Enby => {
+                let status = term.tx_data.as_deref().map(|bits| bits[..8.min(bits.len())].load_le::<u8>()).unwrap_or(0);
+                channels.push(IoChannel { status: status as u32, ..entry(IO_BUS_KBUS, IO_SMART, kbus_smart, 0) });
+                kbus_smart += 1;
+            }
+        }
+    }
 
-    let ts_1 = term_states.clone();
-    let ts_2 = ts_1.clone();
+    for (terminal, term) in term_states.ebus_di_terms.iter().enumerate() {
+        let term = term.read().expect("get DI term read guard");
+        for (ch, value) in bits(&term.values).into_iter().enumerate() {
+            channels.push(IoChannel { value, ..entry(IO_BUS_EBUS, IO_DI, terminal, ch + 1) });
+        }
+    }
 
-    plc_data.area_1_lights = read_area_1_lights(ts_1) as u32;
-    data.area_1_lights = plc_data.area_1_lights;
+    for (terminal, term) in term_states.ebus_do_terms.iter().enumerate() {
+        let term = term.read().expect("get DO term read guard");
+        for (ch, value) in bits(&term.values).into_iter().enumerate() {
+            channels.push(IoChannel { value, ..entry(IO_BUS_EBUS, IO_DO, terminal, ch + 1) });
+        }
+    }
 
-    plc_data.area_2_lights = read_area_2_lights(ts_2) as u32;
-    data.area_2_lights = plc_data.area_2_lights;
+    for (terminal, term) in term_states.ebus_ai_terms.iter().enumerate() {
+        let term = term.read().expect("get AI term read guard");
+        for ch in 0..term.num_of_channels {
+            let value = term.read(Some(ChannelInput::Index(ch))).ok().and_then(|reading| reading.pick_current()).unwrap_or(f32::NAN);
+            let status = term.check(Some(ChannelInput::Index(ch))).and_then(Result::ok).map(|bits| bits.load_le::<u16>()).unwrap_or(0);
+            channels.push(IoChannel { value: value as f64, status: status as u32, ..entry(IO_BUS_EBUS, IO_AI, terminal, ch as usize + 1) });
+        }
+    }
 
-    // Incoming to PLC: HMI command from shmem to local PLC state
-    plc_data.area_1_lights_hmi_cmd = data.area_1_lights_hmi_cmd;
-    write_data(&mut mmap, data);
+    for channel in &mut channels {
+        let bus_ok = if channel.bus == IO_BUS_EBUS { ebus_ok } else { kbus_ok };
+        if !bus_ok {
+            channel.status |= IO_STATUS_BUS_DOWN;
+        }
+    }
+    channels
 }
 
-/// Parses K-bus terminals and pushes them into the heap, but with `slot_idx_range` initialized to (0, 0)
-fn parse_term(term_name: u16, term_states: Arc<RwLock<TermStates>>) {
-    let guard = term_states.clone();
-    let mut guard = guard.write().expect("get term_states write guard");
+/// Parses K-bus terminals and pushes them into the heap, but with `slot_idx_range` initialized to (0, 0).
+/// `subdevice` is the coupler and `slot` the terminal's position behind it, for the log.
+fn parse_term(subdevice: &str, slot: u8, term_name: u16, term_states: &mut TermStates) {
+    log::warn!(subdevice, slot, terminal = term_name; "K-bus term name: {}", term_name);
+    if term_name & 0x8000 != 0 {
+        log::warn!(subdevice, slot, terminal = term_name; "K-bus term size in bits: {}", simple_term_bits(term_name));
+    }
 
-    log::warn!("K-bus term name: {}", term_name);
+    if let Some(term) = decode_term(term_name) {
+        term_states.kbus_terms.push(Arc::new(RwLock::new(term)));
+    }
 
+    log::warn!(subdevice; "Total K-bus terminals parsed: {}", term_states.kbus_terms.len());
+
+}
+
+/// The K-bus terminal a 0x4012 entry names, with `slot_idx_range` (0, 0). None for the coupler itself and for what
+/// the PLC doesn't handle: intelligent terminals other than the KL6581, simple ones with both inputs and outputs or
+/// no bits.
+fn decode_term(term_name: u16) -> Option<KBusTerm> {
     // KL6581 is guaranteed Intelligent
     if term_name == 6581 {
-        guard.kbus_terms
-        .push(
-            Arc::new(
-                RwLock::new(
-                    KBusTerm::new(
-                        term_name,
-                        true,
-                        192,
-                        KBusTerminalGender::Enby,
-                        (0, 0)
-                ))));
-    }
-
-    let term_name_bits: BitVec<u16, Lsb0> = BitVec::from_element(term_name as u16);
-
-    // If Simple Terminal
-    if term_name_bits[15] {
-        let size_in_bits: u8 = term_name_bits[7..15].load_le();
-        log::warn!("K-bus term size in bits: {}", size_in_bits);
-
-        // If Input Terminal
-        if term_name_bits[0] && !term_name_bits[1] { 
-            guard.kbus_terms
-            .push(
-                Arc::new(
-                    RwLock::new(
-                        KBusTerm::new(
-                            term_name,
-                            false,
-                            size_in_bits / 2,
-                            KBusTerminalGender::Input,
-                            (0, 0)
-                ))));
+        return Some(KBusTerm::new(term_name, true, 192, KBusTerminalGender::Enby, (0, 0)));
+    }
+    // Simple terminals have bit 15 set and say whether they're inputs (bit 0) or outputs (bit 1)
+    let gender = match (term_name & 0x8000 != 0, term_name & 0b11) {
+        (true, 0b01) => KBusTerminalGender::Input,
+        (true, 0b10) => KBusTerminalGender::Output,
+        _ => return None,
+    };
+    let size_in_bits = simple_term_bits(term_name);
+    (size_in_bits > 0).then(|| KBusTerm::new(term_name, false, size_in_bits, gender, (0, 0)))
+}
+
+/// Bits a simple terminal takes in the coupler's image, from bits 8-14 of its 0x4012 entry
+pub fn simple_term_bits(term_name: u16) -> u8 {
+    ((term_name >> 8) & 0x7f) as u8
+}
+
+// Determine and set the `slot_idx_range` of each K-bus terminal in the BK coupler's images, the way the coupler maps
+// them: after its status/control word every intelligent terminal in rack order, at the same bits in both images, then
+// the simple terminals' bits packed in rack order, inputs in the input image and outputs in the output image.
+// A terminal past the last bit a slot range can address (254) keeps (0, 0), kbus_layout_problems flags it.
+fn set_slot_idx_range(term_states: &TermStates) {
+    let (intelligent, simple): (Vec<_>, Vec<_>) = term_states.kbus_terms.iter()
+        .partition(|term| term.read().expect("get K-bus term read guard").intelligent);
+    let mut next = [KBUS_CTRL_BITS; 2]; // first free bit of the input and of the output image
+    for term in intelligent.into_iter().chain(simple) {
+        let mut term_lock = term.write().expect("get K-bus term write guard");
+        let images: &[usize] = match term_lock.gender {
+            KBusTerminalGender::Input => &[0],
+            KBusTerminalGender::Output => &[1],
+            KBusTerminalGender::Enby => &[0, 1],
+        };
+        let begin = images.iter().map(|&image| next[image]).max().unwrap_or(KBUS_CTRL_BITS);
+        let end = begin + term_lock.image_bits() - 1;
+        for &image in images {
+            next[image] = end + 1;
+        }
+        match (u8::try_from(begin), u8::try_from(end)) {
+            (Ok(begin), Ok(end)) if end < u8::MAX => term_lock.slot_idx_range = (begin, end),
+            _ => log::error!("{} at bit {} doesn't fit the coupler's images, slot ranges end at bit 254", term_lock.label(), begin),
         }
+    }
+}
 
-        // If Output Terminal
-        if !term_name_bits[0] && term_name_bits[1] { 
-            guard.kbus_terms
-            .push(
-                Arc::new(
-                    RwLock::new(
-                        KBusTerm::new(
-                            term_name,
-                            false,
-                            size_in_bits / 2,
-                            KBusTerminalGender::Output,
-                            (0, 0)
-                ))));
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtual_bus::{self, KBusTerminal, VirtualBus, KL1889, KL2889};
+    use gipop_shm::{IpcConfig, Subscriber, Transport};
+    use proptest::prelude::*;
+
+    // positions in virtual_bus::reference_rack()
+    const EL1889: usize = 1;
+    const EL2889: usize = 2;
+    const EL3024: usize = 3;
+    const BK1120: usize = 4;
+    const RUN_FOR: Duration = Duration::from_secs(3);
+
+    #[test]
+    fn reference_rack_through_pre_op_setup_and_cyclic_exchange() {
+        let bus = VirtualBus::new(virtual_bus::reference_rack());
+        let maindevice = bus.start_maindevice();
+        smol::block_on(async {
+            let group = maindevice.init_single_group::<MAX_SUBDEVICES, PDI_LEN>(ethercat_now).await.expect("Init");
+            let names: Vec<_> = group.iter(&maindevice).map(|sd| sd.name().to_owned()).collect();
+            assert_eq!(names, ["EK1100", "EL1889", "EL2889", "EL3024", "BK1120"]);
+
+            let mut term_states = TermStates::new();
+            configure_pre_op(&group, &maindevice, &mut term_states).await.expect("PRE-OP setup");
+            assert_eq!(bus.object(EL3024, 0x1c12, 0), Some(vec![0]));
+            assert_eq!(bus.object(EL3024, 0x1c13, 0), Some(vec![4]));
+            assert_eq!(bus.object(EL3024, 0x1c13, 2), Some(0x1a02u16.to_le_bytes().to_vec()));
+            {
+                let terms: Vec<_> = term_states.kbus_terms.iter().map(|term| term.read().expect("get K-bus term read guard")).collect();
+                let layout: Vec<_> = terms.iter().map(|term| (term.name, term.slot_idx_range)).collect();
+                assert_eq!(layout, [(KL1889.code(), (112, 127)), (KL2889.code(), (112, 127)), (6581, (16, 111))]);
+                let terms: Vec<&KBusTerm> = terms.iter().map(|term| &**term).collect();
+                assert_eq!(kbus_layout_problems(&terms, Some((128, 128))), Vec::<String>::new());
+            }
+
+            let group = group.into_op(&maindevice).await.expect("PRE-OP -> OP");
+            let sizes: Vec<_> = group.iter(&maindevice)
+                .map(|sd| {
+                    let io = sd.io_raw();
+                    (io.inputs().len(), io.outputs().len())
+                })
+                .collect();
+            assert_eq!(sizes, [(0, 0), (2, 0), (0, 2), (16, 0), (16, 16)]);
+            assert!((0..sizes.len()).all(|position| bus.al_state(position) == 8));
+
+            bus.set_inputs(EL1889, &[0x05, 0x80]);
+            group.iter(&maindevice).nth(EL2889).unwrap().outputs_raw_mut().copy_from_slice(&[0xa5, 0x5a]);
+            let response = group.tx_rx(&maindevice).await.expect("TX/RX");
+            assert_eq!(response.working_counter, diagnostics::expected_wkc(sizes.iter().copied()));
+            assert_eq!(&group.iter(&maindevice).nth(EL1889).unwrap().inputs_raw()[..], [0x05, 0x80]);
+            assert_eq!(bus.outputs(EL2889), [0xa5, 0x5a]);
+
+            let group = group.into_safe_op(&maindevice).await.expect("OP -> SAFE-OP");
+            let group = group.into_pre_op(&maindevice).await.expect("SAFE-OP -> PRE-OP");
+            let _group = group.into_init(&maindevice).await.expect("PRE-OP -> INIT");
+            assert!((0..sizes.len()).all(|position| bus.al_state(position) == 1));
+        });
+    }
+
+    // The reference rack's terminals get the ranges they had before slots were allocated from the rack: the KL6581
+    // right after the status/control word, the KL1889's and the KL2889's bits after it in their own images
+    #[test]
+    fn reference_rack_slot_ranges() {
+        let term_states = init_term_states();
+        for (slot, term_name) in [1120, KL1889.code(), KL2889.code(), 6581].into_iter().enumerate() {
+            parse_term("BK1120", slot as u8 + 1, term_name, term_states.clone());
         }
+        set_slot_idx_range(term_states.clone());
+        let guard = term_states.read().unwrap();
+        let layout: Vec<_> = guard.kbus_terms.iter().map(|term| term.read().map(|term| (term.name, term.slot_idx_range)).unwrap()).collect();
+        assert_eq!(layout, [(KL1889.code(), (112, 127)), (KL2889.code(), (112, 127)), (6581, (16, 111))]);
     }
 
-    log::warn!("Total K-bus terminals parsed: {}", guard.kbus_terms.len());
+    #[test]
+    fn run_on_reference_rack() {
+        let ipc = IpcConfig {
+            transport: Transport::Shm,
+            shm_path: format!("/dev/shm/gipop_ctrl_loop_test_{}", std::process::id()),
+            ..Default::default()
+        };
+        let publisher = Publisher::create(&ipc, &tags::plc_tags()).expect("create shm region");
+        let subscriber = Subscriber::connect(&ipc).expect("open shm region");
+        let bus = VirtualBus::new(virtual_bus::reference_rack());
+        bus.set_inputs(EL1889, &[0x05, 0x80]);
+        let maindevice = bus.start_maindevice();
+
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let stop = shutdown.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(RUN_FOR);
+            stop.store(true, Ordering::Relaxed);
+        });
+        smol::block_on(run(&maindevice, publisher, Liveness::new(Duration::from_secs(2)), 0, Duration::ZERO, shutdown)).expect("run");
+        assert!((0..5).all(|position| bus.al_state(position) == 1));
+
+        // field inputs in the I/O mirror
+        let di: Vec<_> = subscriber.read_io().iter()
+            .filter(|channel| channel.bus == IO_BUS_EBUS && channel.kind == IO_DI)
+            .map(|channel| channel.value)
+            .collect();
+        assert_eq!(di.len(), 16);
+        assert_eq!((di[0], di[1], di[2], di[15]), (1.0, 0.0, 1.0, 1.0));
+
+        // KL2889 Ch12, set by the loop every cycle, out on the K-bus: bit 112 + 11 of the BK1120's outputs
+        assert_eq!(bus.outputs(BK1120)[15] & 0x08, 0x08);
+
+        let image = subscriber.read_process_image().expect("process image published");
+        assert_eq!(image.subdevices().iter().map(|sd| sd.name()).collect::<Vec<_>>(), ["EK1100", "EL1889", "EL2889", "EL3024", "BK1120"]);
+        assert_eq!(image.data[..2], [0x05, 0x80]);
+        _ = std::fs::remove_file(&ipc.shm_path);
+    }
 
-}
+    // Any terminal a 0x4012 entry can describe
+    fn terminal() -> impl Strategy<Value = KBusTerminal> {
+        prop_oneof![
+            (1u8..=127).prop_map(KBusTerminal::Input),
+            (1u8..=127).prop_map(KBusTerminal::Output),
+            Just(KBusTerminal::Kl6581),
+        ]
+    }
 
-// Determine and set the correct `slot_idx_range` occupied by each K-bus terminal in the BK coupler input/output images
-fn set_slot_idx_range(term_states: Arc<RwLock<TermStates>>) {
-    let guard = term_states.clone();
-    let guard = guard.write().expect("get term_states write guard");
-    let terms = &guard.kbus_terms;
-
-    // This implementation is incomplete. It does not cover the following cases:
-    // - Multiple instances of the same terminal
-    // - Non-contiguous terminal layout (from mixed Simple and Terminal physical layout -> cluster Simple/Terminal separately in memory).
-    // TODO: KBusTerm (any terminal instance, really) should have a UID
-    for (_pos, term) in terms.iter().enumerate() {
-        let mut term_lock = term.write().expect("get K-bus term write guard");
+    // Racks whose images fit the bits slot ranges address
+    fn rack() -> impl Strategy<Value = Vec<KBusTerminal>> {
+        let terminal = prop_oneof![
+            4 => (1u8..=32).prop_map(KBusTerminal::Input),
+            4 => (1u8..=32).prop_map(KBusTerminal::Output),
+            1 => Just(KBusTerminal::Kl6581),
+        ];
+        prop::collection::vec(terminal, 0..8).prop_filter("images past bit 254", |rack| {
+            [false, true].iter().all(|&output| virtual_bus::bk1120_image_len(rack, output) * 8 <= u8::MAX as usize)
+        })
+    }
 
-        // setting slot index ranges should be conditioned on UID instead of non-unique attributes like name and gender
-        if term_lock.name == 6581 {
-            assert!(term_lock.intelligent && term_lock.name == 6581); // Panic if KL6581 is for some reason not Intelligent
-            term_lock.slot_idx_range = (16, 15+(12*8));
+    // The rack through parse_term and set_slot_idx_range like configure_pre_op does, coupler entry first
+    fn parse_rack(rack: &[KBusTerminal]) -> TermStates {
+        let mut term_states = TermStates::new();
+        for (slot, term_name) in std::iter::once(1120).chain(rack.iter().map(|terminal| terminal.code())).enumerate() {
+            parse_term("BK1120", slot as u8 + 1, term_name, &mut term_states);
         }
+        set_slot_idx_range(&term_states);
+        term_states
+    }
 
-        if term_lock.gender == KBusTerminalGender::Input {
-            term_lock.slot_idx_range = (112, 112+15);
+    proptest! {
+        #[test]
+        fn decode_term_round_trips(terminal in terminal()) {
+            let term = decode_term(terminal.code()).expect("a terminal the PLC handles");
+            prop_assert_eq!(term.name, terminal.code());
+            prop_assert_eq!(term.slot_idx_range, (0, 0));
+            match terminal {
+                KBusTerminal::Input(bits) | KBusTerminal::Output(bits) => {
+                    let gender = if matches!(terminal, KBusTerminal::Input(_)) { KBusTerminalGender::Input } else { KBusTerminalGender::Output };
+                    prop_assert!(term.gender == gender && !term.intelligent);
+                    prop_assert_eq!(simple_term_bits(terminal.code()), bits);
+                    prop_assert_eq!(term.image_bits(), bits as usize);
+                }
+                KBusTerminal::Kl6581 => {
+                    prop_assert!(term.gender == KBusTerminalGender::Enby && term.intelligent);
+                    prop_assert_eq!(term.image_bits(), 96);
+                }
+            }
         }
 
-        if term_lock.gender == KBusTerminalGender::Output {
-            term_lock.slot_idx_range = (112, 112+15);
+        // Simple terminals with one direction and the KL6581 are taken, any other entry is skipped, none panics
+        #[test]
+        fn parse_term_takes_only_known_entries(term_name in any::<u16>()) {
+            let mut term_states = TermStates::new();
+            parse_term("BK1120", 1, term_name, &mut term_states);
+            let known = term_name == 6581 || (term_name & 0x8000 != 0 && matches!(term_name & 0b11, 0b01 | 0b10) && simple_term_bits(term_name) > 0);
+            prop_assert_eq!(term_states.kbus_terms.len(), known as usize);
         }
 
+        // Every terminal lands where the coupler maps it: nothing kbus_layout_problems objects to against the virtual
+        // BK1120's images, and the simple terminals' bits read and write back packed in rack order after the
+        // intelligent terminals
+        #[test]
+        fn slot_ranges_match_the_couplers_images(rack in rack(), image in prop::collection::vec(any::<u8>(), 32)) {
+            let term_states = parse_rack(&rack);
+            let mut terms: Vec<_> = term_states.kbus_terms.iter().map(|term| term.write().unwrap()).collect();
+            prop_assert_eq!(terms.len(), rack.len());
+
+            let image_bits = (virtual_bus::bk1120_image_len(&rack, false) * 8, virtual_bus::bk1120_image_len(&rack, true) * 8);
+            let refs: Vec<&KBusTerm> = terms.iter().map(|term| &**term).collect();
+            prop_assert_eq!(kbus_layout_problems(&refs, Some(image_bits)), Vec::<String>::new());
+
+            let simple_from = KBUS_CTRL_BITS + rack.iter().filter(|&&terminal| terminal == KBusTerminal::Kl6581).count() * 96;
+            let image = image.view_bits::<Lsb0>();
+            let mut inputs = BitVec::<u8, Lsb0>::new();
+            for term in terms.iter_mut().filter(|term| term.gender == KBusTerminalGender::Input) {
+                let range = term.pdi_range();
+                term.refresh_ctrlr(Some(range.view(image)), None);
+                inputs.extend_from_bitslice(term.tx_data.as_deref().unwrap());
+            }
+            prop_assert_eq!(&inputs[..], &image[simple_from..simple_from + inputs.len()]);
+
+            let mut outputs = BitVec::<u8, Lsb0>::new();
+            let mut output_image = bitvec![u8, Lsb0; 0; image_bits.1];
+            for (n, term) in terms.iter_mut().filter(|term| term.gender == KBusTerminalGender::Output).enumerate() {
+                let pattern = &image[n * 8..n * 8 + term.image_bits()];
+                term.rx_data.as_mut().unwrap().copy_from_bitslice(pattern);
+                term.refresh_term(term.pdi_range().view_mut(&mut output_image));
+                outputs.extend_from_bitslice(pattern);
+            }
+            prop_assert!(output_image[..simple_from].not_any());
+            prop_assert_eq!(&outputs[..], &output_image[simple_from..simple_from + outputs.len()]);
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,194 @@
+// EnOcean over the KL6581 master on the K-bus (`enocean` feature, on by default): telegrams go to consumers as
+// ITEM_ENOCEAN_TELEGRAM events and are counted in the "enocean telegrams" total, rocker B switches the area 1 lights
+// (taking them back from the HMI), rocker A the area 2 lights, and errors of the KL6581 raise its alarm. Without the
+// feature the KL6581's image is still exchanged, nothing reads it, and its tags stay at 0.
+use bitvec::prelude::*;
+use hal::io_defs::*;
+use hal::term_cfg::*;
+use gipop_shm::RingItem;
+
+use crate::logic::{queue_event, report_diagnostic, write_all_channel_el2889, write_all_channel_kl2889, LOCAL_PLC_DATA};
+
+/// Runs once per scan, before the rest of the logic
+pub fn enocean_sm(term_states: &TermStates) {
+
+    LOCAL_PLC_DATA.lock().unwrap().kl6581_fault = (3..=6).any(check_sb_bit);
+
+    if check_sb_bit(6) { // Error reported
+        let message = CnodeErrors::cnode_err_to_string(read_cnode());
+        log::error!("{}", message);
+        report_diagnostic(&format!("KL6581: {}", message));
+    }
+    else if check_sb_bit(5) {
+        log::error!("Config missmatch!");
+        report_diagnostic("KL6581: Config mismatch");
+    }
+    else if check_sb_bit(4) {
+        log::error!("AddrConflict - Address of a KL6583 doubly assigned!");
+        report_diagnostic("KL6581: Address of a KL6583 doubly assigned");
+    }
+    else if check_sb_bit(3) {
+        log::error!("Communication Error - No KL6583 ready for op found. Check cabling and addresses");
+        report_diagnostic("KL6581: No KL6583 ready for op found. Check cabling and addresses");
+    }
+    else { // No errors
+        if read_cb1() != check_sb_bit(1) {
+            queue_event(RingItem::enocean_telegram(&read_telegram()));
+            LOCAL_PLC_DATA.lock().unwrap().enocean_telegrams += 1;
+
+            if (read_db3() & 0b11110000) == 0b01010000 {
+                log::info!("Rocker B, I pos. pressed");
+                write_all_channel_kl2889(term_states, true);
+                take_local_control(true);
+            }
+
+            if (read_db3() & 0b11110000) == 0b01110000 {
+                log::info!("Rocker B, O pos. pressed");
+                write_all_channel_kl2889(term_states, false);
+                take_local_control(false);
+            }
+
+            if (read_db3() & 0b11110000) == 0b00010000 {
+                log::info!("Rocker A, I pos. pressed");
+                write_all_channel_el2889(true, term_states);
+            }
+
+            if (read_db3() & 0b11110000) == 0b00110000 {
+                log::info!("Rocker A, 0 pos. pressed");
+                write_all_channel_el2889(false, term_states);
+            }
+            // log::info!("sb1 through check: {}", check_sb1());
+            write_cb1(!check_sb_bit(1)); // Very important. Tells KL6581 we've fetched the packet.
+        }
+        else {
+            // log::info!("CB.1 == SB.1");
+            if buffer_full() {
+                log::info!("Buffer full");
+                write_cb1(!check_sb_bit(1)); // Very important. Tells KL6581 we've fetched the packet.
+            }
+        }
+    }
+}
+
+// A local press overrides whatever the HMI commanded last
+fn take_local_control(area_1_lights: bool) {
+    let mut plc_data = LOCAL_PLC_DATA.lock().unwrap();
+    plc_data.area_1_lights_local = area_1_lights;
+    plc_data.area_1_lights_hmi_owned = false;
+}
+
+fn read_cnode() -> u8 {
+    let rd_guard = &*TERM_KL6581.read().expect("Acquire TERM_KL6581 read guard");
+    let reading = rd_guard.read(None).unwrap();
+    let value: SmartReading = reading.pick_smart().unwrap(); // 192 bits = 24 bytes
+    return value.bits(8..16).load_le();
+}
+
+#[repr(u8)]
+enum CnodeErrors { // variant names follow the KL6581 manual from Beckhoff, with the exception of the obvious 'KL6853` typo
+    WatchdogError     = 0x10,
+    NoComWithKL6581   = 0x11,
+    idx_number_not_OK = 0x12,
+    Switch_to_Stopp   = 0x13,
+    not_ready         = 0x14,
+    No_KL6583_Found   = 0x15,
+    TransmissionError = 0x16,
+}
+
+impl CnodeErrors {
+    fn cnode_err_from_u8(value: u8) -> Result<Self, String> {
+        match value {
+            0x10 => Ok(CnodeErrors::WatchdogError),
+            0x11 => Ok(CnodeErrors::NoComWithKL6581),
+            0x12 => Ok(CnodeErrors::idx_number_not_OK),
+            0x13 => Ok(CnodeErrors::Switch_to_Stopp),
+            0x14 => Ok(CnodeErrors::not_ready),
+            0x15 => Ok(CnodeErrors::No_KL6583_Found),
+            0x16 => Ok(CnodeErrors::TransmissionError),
+            _ => Err("Invalid CNODE byte value".into()),
+        }
+    }
+
+    // To be used with read_cnode()
+    fn cnode_err_to_string(cnode: u8) -> String {
+        let err_message = match CnodeErrors::cnode_err_from_u8(cnode) {
+            Ok(CnodeErrors::WatchdogError)     => "The KL6581 does not answer anymore. Check the mapping and communication.",
+            Ok(CnodeErrors::NoComWithKL6581)   => "The KL6581 does not answer. Check the mapping and communication.",
+            Ok(CnodeErrors::idx_number_not_OK) => "nIdx is not correct. nIdx may have a value from 0 to 64.",
+            Ok(CnodeErrors::Switch_to_Stopp)   => "bInit is FALSE. Set bInit back to TRUE.",
+            Ok(CnodeErrors::not_ready)         => "The terminal is not in data exchange. Check the mapping and communication.",
+            Ok(CnodeErrors::No_KL6583_Found)   => "There is no KL6583 connected. Check the wiring to the KL6583.",
+            Ok(CnodeErrors::TransmissionError) => "The KL6581 does not answer anymore. Check the mapping and communication.",
+            _ => "Invalid CNODE byte value",
+        };
+        return err_message.to_string()
+    }
+}
+
+// Raw KL6581 input image (SB, CNODE, telegram bytes), 12 bytes
+fn read_telegram() -> Vec<u8> {
+    let rd_guard = &*TERM_KL6581.read().expect("Acquire TERM_KL6581 read guard");
+    let reading = rd_guard.read(None).unwrap();
+    let value: SmartReading = reading.pick_smart().unwrap(); // 192 bits = 24 bytes
+    value.bits(0..96).chunks(8).map(|byte| byte.load_le::<u8>()).collect()
+}
+
+fn read_cb1() -> bool {
+    let rd_guard = &*TERM_KL6581.read().expect("Acquire TERM_KL6581 read guard");
+    let reading = rd_guard.read(None).unwrap();
+    let value: SmartReading = reading.pick_smart().unwrap(); // 192 bits = 24 bytes
+    return value.bit(1);
+}
+
+fn read_cb1_dyn(term_states: &TermStates) -> bool {
+    let rd_guard = term_states.kbus_terms[2].write().expect("get KL6581 write guard");
+    let reading = rd_guard.read(None).unwrap();
+    let value: SmartReading = reading.pick_smart().unwrap(); // 192 bits = 24 bytes
+    return value.bit(1);
+}
+
+pub fn read_db3() -> u8 {
+    let rd_guard = &*TERM_KL6581.read().expect("Acquire TERM_KL6581 read guard");
+    let reading = rd_guard.read(None).unwrap();
+    let value: SmartReading = reading.pick_smart().unwrap(); // 192 bits = 24 bytes
+    return value.bits(6*8..56).load::<u8>();
+}
+
+pub fn read_db3_dyn(term_states: &TermStates) -> u8 {
+    let rd_guard = term_states.kbus_terms[2].write().expect("get KL6581 write guard");
+    let reading = rd_guard.read(None).unwrap();
+    let value: SmartReading = reading.pick_smart().unwrap(); // 192 bits = 24 bytes
+    return value.bits(6*8..56).load::<u8>();
+}
+
+fn buffer_full() -> bool {
+    let rd_guard = &*TERM_KL6581.read().expect("Acquire TERM_KL6581 read guard");
+    let reading = rd_guard.read(None).unwrap();
+    let value: SmartReading = reading.pick_smart().unwrap(); // 192 bits = 24 bytes
+    return value.bit((12*8)+2); // SB.2
+}
+
+fn buffer_full_dyn(term_states: &TermStates) -> bool {
+    let rd_guard = term_states.kbus_terms[2].write().expect("get KL6581 write guard");
+    let reading = rd_guard.read(None).unwrap();
+    let value: SmartReading = reading.pick_smart().unwrap(); // 192 bits = 24 bytes
+    return value.bit((12*8)+2); // SB.2
+}
+
+// use fn write() implemented by Setter trait
+fn write_cb1(val: bool) {
+    let wr_guard = &mut *TERM_KL6581.write().expect("acquire KL6581 write lock");
+    wr_guard.write(val, ChannelInput::Index(1)).unwrap(); // CB.1
+}
+
+fn write_cb1_dyn(term_states: &TermStates, val: bool) {
+    let mut wr_guard = term_states.kbus_terms[2].write().expect("get KL6581 write guard");
+    wr_guard.write(val, ChannelInput::Index(1)).unwrap(); // CB.1
+}
+
+fn check_sb_bit(bit: usize) -> bool {
+    let rd_guard = &*TERM_KL6581.read().expect("Acquire TERM_KL6581 read guard");
+    let reading: StatusBits = rd_guard.check(None).unwrap().expect("call check");
+    return reading[bit];
+}
+
@@ -0,0 +1,277 @@
+// Optional gRPC tag service (`grpc` feature, `[grpc]` in gipop.toml), see proto/tags.proto.
+// Runs on its own thread with a tokio runtime. The shm thread feeds it every publish and drains its commands
+// right next to the ones coming in over the IPC transport, so gRPC clients and the OPC UA server see the same PLC.
+// It's also passed the PLC's events, that's how alarm acknowledgements from any consumer show up in GetAlarms.
+// Writes and acknowledgements are checked against [users] like OPC UA writes are, callers identify with their api_key
+// in the x-api-key metadata. Reading, subscribing and listing alarms is open to everyone who can reach the port.
+use gipop_shm::ring::{ITEM_ALARM, ITEM_ALARM_ACK};
+use gipop_shm::{Action, RingItem, TagDef, TagType, TagValue, Users};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+#[allow(clippy::enum_variant_names)] // generated, oneof variants all end in `Value`
+mod pb {
+    tonic::include_proto!("gipop.tags.v1");
+}
+use pb::tag_service_server::{TagService, TagServiceServer};
+use pb::tag_value::Value;
+
+const UPDATE_BACKLOG: usize = 64; // publishes a slow subscriber may fall behind before it gets resynced
+const MAX_QUEUED_COMMANDS: usize = 64; // same bound as the shm command ring
+const API_KEY_METADATA: &str = "x-api-key";
+
+struct Hub {
+    tags: Vec<TagDef>,
+    by_name: HashMap<String, usize>,
+    values: RwLock<Vec<TagValue>>,
+    acked: Mutex<Vec<bool>>, // per tag, only meaningful for alarms
+    updates: broadcast::Sender<Arc<Vec<(usize, TagValue)>>>,
+    commands: Mutex<VecDeque<RingItem>>,
+}
+
+static HUB: OnceLock<Arc<Hub>> = OnceLock::new();
+
+/// Start serving `tags` on `listen` to `users`, returns once the thread is up
+pub fn serve(listen: &str, tags: &[TagDef], users: Users) -> Result<(), String> {
+    let addr = listen.parse().map_err(|e| format!("Bad gRPC listen address '{}': {}", listen, e))?;
+
+    let hub = Arc::new(Hub {
+        tags: tags.to_vec(),
+        by_name: tags.iter().enumerate().map(|(idx, def)| (def.name.clone(), idx)).collect(),
+        values: RwLock::new(tags.iter().map(|def| def.ty.default_value()).collect()),
+        acked: Mutex::new(vec![true; tags.len()]),
+        updates: broadcast::channel(UPDATE_BACKLOG).0,
+        commands: Mutex::new(VecDeque::new()),
+    });
+    HUB.set(hub.clone()).map_err(|_| "gRPC tag service already started".to_string())?;
+
+    std::thread::Builder::new()
+    .name("PlcGrpcThread".to_owned())
+    .spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("build gRPC runtime");
+        runtime.block_on(async move {
+            log::info!("gRPC tag service listening on {}", addr);
+            let served = tonic::transport::Server::builder()
+                .add_service(TagServiceServer::new(GrpcTagService { hub, users }))
+                .serve(addr)
+                .await;
+            if let Err(e) = served {
+                log::error!("gRPC tag service stopped: {}", e);
+            }
+        });
+    })
+    .map_err(|e| format!("Failed to spawn gRPC thread: {}", e))?;
+
+    Ok(())
+}
+
+/// Call after every publish, subscribers only get what changed. No-op if the service isn't running.
+pub fn publish(values: &[(usize, TagValue)]) {
+    let Some(hub) = HUB.get() else { return };
+
+    let mut changed = Vec::new();
+    {
+        let mut current = hub.values.write().unwrap();
+        for &(idx, value) in values {
+            if current[idx] != value {
+                current[idx] = value;
+                changed.push((idx, value));
+            }
+        }
+    }
+
+    if !changed.is_empty() {
+        _ = hub.updates.send(Arc::new(changed)); // Err only means nobody is subscribed
+    }
+}
+
+/// Call with every event the PLC pushes, keeps track of which alarms are acknowledged. No-op if the service isn't running.
+pub fn event(item: &RingItem) {
+    let Some(hub) = HUB.get() else { return };
+    let mut acked = hub.acked.lock().unwrap();
+    match item.kind {
+        ITEM_ALARM if item.value != 0 => acked[item.tag as usize] = false,
+        ITEM_ALARM_ACK => acked[item.tag as usize] = true,
+        _ => {}
+    }
+}
+
+/// Commands from WriteTags and AckAlarm, in the same form as the ones from the command ring
+pub fn pop_command() -> Option<RingItem> {
+    HUB.get()?.commands.lock().unwrap().pop_front()
+}
+
+impl Hub {
+    fn index_of(&self, name: &str) -> Result<usize, String> {
+        self.by_name.get(name).copied().ok_or_else(|| format!("No tag named '{}'", name))
+    }
+
+    // empty means every tag
+    fn resolve(&self, names: &[String]) -> Result<Vec<usize>, String> {
+        if names.is_empty() {
+            return Ok((0..self.tags.len()).collect());
+        }
+        names.iter().map(|name| self.index_of(name)).collect()
+    }
+
+    fn tag(&self, idx: usize, value: TagValue) -> pb::Tag {
+        pb::Tag { name: self.tags[idx].name.clone(), value: Some(to_pb(value)), writable: self.tags[idx].writable() }
+    }
+
+    fn alarms(&self) -> Vec<pb::Alarm> {
+        let (values, acked) = (self.values.read().unwrap(), self.acked.lock().unwrap());
+        self.tags.iter().enumerate()
+            .filter(|(_, def)| def.is_alarm())
+            .map(|(idx, def)| pb::Alarm {
+                name: def.name.clone(),
+                active: values[idx].as_f64() != 0.0,
+                acked: acked[idx],
+                severity: def.severity as u32,
+            })
+            .collect()
+    }
+
+    fn queue(&self, items: Vec<RingItem>) -> Result<(), Status> {
+        let mut commands = self.commands.lock().unwrap();
+        if commands.len() + items.len() > MAX_QUEUED_COMMANDS {
+            return Err(Status::resource_exhausted("PLC isn't consuming commands"));
+        }
+        commands.extend(items);
+        Ok(())
+    }
+
+    fn snapshot(&self, idxs: &[usize]) -> pb::TagUpdate {
+        let values = self.values.read().unwrap();
+        pb::TagUpdate { tags: idxs.iter().map(|&idx| self.tag(idx, values[idx])).collect() }
+    }
+}
+
+fn to_pb(value: TagValue) -> pb::TagValue {
+    let value = match value {
+        TagValue::Bool(b) => Value::BoolValue(b),
+        TagValue::UInt32(n) => Value::Uint32Value(n),
+        TagValue::Int32(n) => Value::Int32Value(n),
+        TagValue::Float32(f) => Value::FloatValue(f),
+        TagValue::Float64(f) => Value::DoubleValue(f),
+    };
+    pb::TagValue { value: Some(value) }
+}
+
+fn from_pb(ty: TagType, value: &pb::TagValue) -> Option<TagValue> {
+    match (ty, value.value?) {
+        (TagType::Bool, Value::BoolValue(b)) => Some(TagValue::Bool(b)),
+        (TagType::UInt32, Value::Uint32Value(n)) => Some(TagValue::UInt32(n)),
+        (TagType::Int32, Value::Int32Value(n)) => Some(TagValue::Int32(n)),
+        (TagType::Float32, Value::FloatValue(f)) => Some(TagValue::Float32(f)),
+        (TagType::Float64, Value::DoubleValue(f)) => Some(TagValue::Float64(f)),
+        _ => None,
+    }
+}
+
+struct GrpcTagService {
+    hub: Arc<Hub>,
+    users: Users,
+}
+
+impl GrpcTagService {
+    // The user behind the request's api key, None without one
+    fn user<T>(&self, request: &Request<T>) -> Result<Option<String>, Status> {
+        let Some(key) = request.metadata().get(API_KEY_METADATA) else {
+            return Ok(None);
+        };
+        let key = key.to_str().map_err(|_| Status::unauthenticated("Malformed api key"))?;
+        self.users.by_api_key(key).map(|user| Some(user.to_owned())).ok_or_else(|| Status::unauthenticated("Unknown api key"))
+    }
+
+    fn authorize<T>(&self, request: &Request<T>, action: Action) -> Result<(), Status> {
+        let user = self.user(request)?;
+        self.users.authorize(user.as_deref(), action).map(|_| ()).map_err(|e| {
+            log::warn!("gRPC: {}", e);
+            Status::permission_denied(e)
+        })
+    }
+}
+
+#[tonic::async_trait]
+impl TagService for GrpcTagService {
+    async fn read_tags(&self, request: Request<pb::ReadTagsRequest>) -> Result<Response<pb::ReadTagsResponse>, Status> {
+        let idxs = self.hub.resolve(&request.get_ref().names).map_err(Status::not_found)?;
+        Ok(Response::new(pb::ReadTagsResponse { tags: self.hub.snapshot(&idxs).tags }))
+    }
+
+    async fn write_tags(&self, request: Request<pb::WriteTagsRequest>) -> Result<Response<pb::WriteTagsResponse>, Status> {
+        self.authorize(&request, Action::WriteTag)?;
+        // validate the whole batch first so a bad entry doesn't leave half of it queued
+        let mut items = Vec::new();
+        for tag in &request.get_ref().tags {
+            let idx = self.hub.index_of(&tag.name).map_err(Status::not_found)?;
+            let def = &self.hub.tags[idx];
+            if !def.writable() {
+                return Err(Status::permission_denied(format!("Tag '{}' is read only", def.name)));
+            }
+            let value = tag.value.as_ref()
+                .and_then(|value| from_pb(def.ty, value))
+                .ok_or_else(|| Status::invalid_argument(format!("Tag '{}' takes a {:?} value", def.name, def.ty)))?;
+            items.push(RingItem::tag_write(idx, value));
+        }
+
+        self.hub.queue(items)?;
+        Ok(Response::new(pb::WriteTagsResponse {}))
+    }
+
+    async fn get_alarms(&self, _request: Request<pb::GetAlarmsRequest>) -> Result<Response<pb::GetAlarmsResponse>, Status> {
+        Ok(Response::new(pb::GetAlarmsResponse { alarms: self.hub.alarms() }))
+    }
+
+    async fn ack_alarm(&self, request: Request<pb::AckAlarmRequest>) -> Result<Response<pb::AckAlarmResponse>, Status> {
+        self.authorize(&request, Action::AckAlarm)?;
+        let name = &request.get_ref().name;
+        let idx = self.hub.index_of(name).map_err(Status::not_found)?;
+        if !self.hub.tags[idx].is_alarm() {
+            return Err(Status::invalid_argument(format!("Tag '{}' isn't an alarm", name)));
+        }
+        self.hub.queue(vec![RingItem::alarm_ack(idx)])?;
+        Ok(Response::new(pb::AckAlarmResponse {}))
+    }
+
+    type SubscribeStream = ReceiverStream<Result<pb::TagUpdate, Status>>;
+
+    async fn subscribe(&self, request: Request<pb::SubscribeRequest>) -> Result<Response<Self::SubscribeStream>, Status> {
+        let idxs = self.hub.resolve(&request.get_ref().names).map_err(Status::not_found)?;
+        let mut wanted = vec![false; self.hub.tags.len()];
+        for &idx in &idxs {
+            wanted[idx] = true;
+        }
+
+        let mut updates = self.hub.updates.subscribe(); // before the snapshot, so no change falls in between
+        let (tx, rx) = mpsc::channel(UPDATE_BACKLOG);
+        let hub = self.hub.clone();
+
+        tokio::spawn(async move {
+            let mut next = hub.snapshot(&idxs);
+            loop {
+                if !next.tags.is_empty() && tx.send(Ok(next)).await.is_err() {
+                    break; // client went away
+                }
+                next = match updates.recv().await {
+                    Ok(changed) => pb::TagUpdate {
+                        tags: changed.iter().filter(|(idx, _)| wanted[*idx]).map(|&(idx, value)| hub.tag(idx, value)).collect(),
+                    },
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        log::warn!("gRPC subscriber missed {} updates, resending a snapshot", missed);
+                        hub.snapshot(&idxs)
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
@@ -1,11 +1,14 @@
-use bitvec::prelude::*;
 // For getting read/write locks to terminal objects in PLC memory
 use hal::io_defs::*;
 use hal::term_cfg::*;
-use std::sync::{Arc, RwLock, LazyLock, Mutex};
-use std::fs::OpenOptions;
-use std::time::Duration;
-use crate::shared::{SharedData, SHM_PATH, map_shared_memory, read_data, write_data};
+use std::sync::{LazyLock, Mutex};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use gipop_shm::{BusDiagnostics, IoAddress, ProcessImage, RingItem, TagValue, Users};
+use gipop_shm::blob::BLOB_DIAGNOSTIC;
+use gipop_shm::io_mirror::{IO_BUS_EBUS, IO_BUS_KBUS};
+use gipop_shm::time_sync::ClockSync;
+use crate::audit::{RecentCommands, Unaudited};
 
 // PLC (business logic) program is defined here via methods that read/write to/from terminal objects in PLC memory
 
@@ -16,6 +19,35 @@ pub struct LocalPlcData {
     pub area_1_lights: u32,
     pub area_2_lights: u32,
     pub area_1_lights_hmi_cmd: u32, // incoming to PLC
+    pub pending_hmi_cmds: VecDeque<(u32, u32)>, // (seq, cmd) drained from the command ring, consumed one per scan
+    pub last_cmd_seq: u32,  // newest command received, see RingItem::seq
+    pub acked_cmd_seq: u32, // newest command the logic has consumed
+    pub events: VecDeque<RingItem>, // outgoing to the shm event ring
+    pub blobs: VecDeque<(u16, Vec<u8>)>, // outgoing to the shm payload area, (kind, payload)
+    pub last_diagnostic: Option<String>, // so a condition that persists across scans is only reported once
+    pub hmi_alive: bool, // consumer heartbeat, updated by the shm sync thread
+    pub hmi_last_alive: Option<Instant>, // None if no HMI ever attached
+    pub hmi_timeout: Option<Duration>, // [watchdog] hmi_timeout_ms, None disables the watchdog
+    pub area_1_lights_local: bool, // last state commanded locally (EnOcean rocker B), what the watchdog falls back to
+    pub area_1_lights_hmi_owned: bool, // area 1 lights were last set by the HMI
+    pub kl6581_fault: bool, // alarm, KL6581 status byte has an error bit set
+    pub hmi_watchdog_tripped: bool, // alarm, from the watchdog handing area 1 lights back until the HMI is back
+    pub published_alarms: HashMap<&'static str, bool>, // alarm tag -> state consumers last got an event for
+    pub running: bool, // false: logic stopped by an operator (SetRunMode), outputs hold their last state
+    pub scan_time: Duration, // last full pass of the control loop
+    pub forces: HashMap<IoAddress, bool>, // DO channels forced by an operator, win over the logic every scan
+    pub enocean_telegrams: u32, // total telegrams received, until an operator resets the totals
+    pub bus_diag: Option<BusDiagnostics>, // fresh from the control loop, not yet published
+    pub process_image: Option<Box<ProcessImage>>, // raw images of the last cycle, None until the bus is in OP
+    pub ebus_ok: bool, // last cycle came back with the expected working counter
+    pub kbus_ok: bool, // the BK1120 reported a healthy K-bus on the last cycle
+    pub external: HashMap<String, TagValue>, // latest values of external tags (see tags.rs) by name, missing until the first one arrived
+    pub setpoints: HashMap<String, TagValue>, // latest writes to writable tags other than the HMI command, by name
+    pub recent_commands: RecentCommands, // for the audit log
+    pub unaudited: Unaudited, // commands from the ring or socket until their audit is in
+    pub users: Users, // [users], who may send which commands
+    pub clock: Option<ClockSync>, // last reading of the host clock's sync state, None while it isn't monitored
+    pub clock_unsynced: bool, // alarm, the clock isn't synchronized within [time_sync] max_offset_ms
 }
 
 impl LocalPlcData {
@@ -26,224 +58,149 @@ impl LocalPlcData {
             status: 0,
             area_1_lights: 0,
             area_2_lights: 0,
-            area_1_lights_hmi_cmd: 0
+            area_1_lights_hmi_cmd: 0,
+            pending_hmi_cmds: VecDeque::new(),
+            last_cmd_seq: 0,
+            acked_cmd_seq: 0,
+            events: VecDeque::new(),
+            blobs: VecDeque::new(),
+            last_diagnostic: None,
+            hmi_alive: false,
+            hmi_last_alive: None,
+            hmi_timeout: None,
+            area_1_lights_local: false,
+            area_1_lights_hmi_owned: false,
+            kl6581_fault: false,
+            hmi_watchdog_tripped: false,
+            published_alarms: HashMap::new(),
+            running: true,
+            scan_time: Duration::ZERO,
+            forces: HashMap::new(),
+            enocean_telegrams: 0,
+            bus_diag: None,
+            process_image: None,
+            ebus_ok: false,
+            kbus_ok: false,
+            external: HashMap::new(),
+            setpoints: HashMap::new(),
+            recent_commands: RecentCommands::default(),
+            unaudited: Unaudited::default(),
+            users: Users::default(),
+            clock: None,
+            clock_unsynced: false,
         }
     }
 }
 
 pub static LOCAL_PLC_DATA: LazyLock<Mutex<LocalPlcData>> = LazyLock::new(|| Mutex::new(LocalPlcData::new()));
 
-pub async fn plc_execute_logic(term_states: Arc<RwLock<TermStates>>) {
-    let ts_enocean = term_states.clone();
-    enocean_sm(ts_enocean);
+pub async fn plc_execute_logic(term_states: &TermStates) {
+    #[cfg(feature = "enocean")]
+    crate::enocean::enocean_sm(term_states);
+    std::thread::sleep(Duration::from_millis(10)); // We're not controlling servos :)
 
-    let cmd = LOCAL_PLC_DATA.lock().unwrap();
+    let mut cmd = LOCAL_PLC_DATA.lock().unwrap();
+
+    // one command per scan, so back-to-back HMI writes are all applied in order instead of only the last one
+    if let Some((seq, next)) = cmd.pending_hmi_cmds.pop_front() {
+        cmd.area_1_lights_hmi_cmd = next;
+        if seq != 0 {
+            cmd.acked_cmd_seq = seq; // applied below in this same scan
+        }
+    }
 
     if cmd.area_1_lights_hmi_cmd == 2 {
         // log::info!("Area 1 Lights Command On");
-        let ts_wr_all_kl2889_true = term_states.clone();
-        write_all_channel_kl2889(ts_wr_all_kl2889_true, true);
-        reset_hmi_cmd(); // Must be reset to avoid conflict with EnOcean
+        write_all_channel_kl2889(term_states, true);
+        reset_hmi_cmd(&mut cmd); // Must be reset to avoid conflict with EnOcean
+        cmd.area_1_lights_hmi_owned = true;
     }
 
     if cmd.area_1_lights_hmi_cmd == 1 {
         // log::info!("Area 1 Lights Command Off");
-        let ts_wr_all_kl2889_false = term_states.clone();
-        write_all_channel_kl2889(ts_wr_all_kl2889_false, false);
-        reset_hmi_cmd(); // Must be reset to avoid conflict with EnOcean
+        write_all_channel_kl2889(term_states, false);
+        reset_hmi_cmd(&mut cmd); // Must be reset to avoid conflict with EnOcean
+        cmd.area_1_lights_hmi_owned = true;
     }
-}
 
-fn enocean_sm(term_states: Arc<RwLock<TermStates>>) {
-    let ts_a = Arc::clone(&term_states);
-    let ts_b = ts_a.clone();
-    let ts_c = ts_a.clone();
-    let ts_d = ts_a.clone();
-
-    if check_sb_bit(6) { // Error reported
-        log::error!("{}", CnodeErrors::cnode_err_to_string(read_cnode()));
-    }
-    else if check_sb_bit(5) {
-        log::error!("Config missmatch!");
-    }
-    else if check_sb_bit(4) {
-        log::error!("AddrConflict - Address of a KL6583 doubly assigned!");
+    // HMI watchdog: a remote command must not latch forever once the HMI is gone, hand the outputs back to local control
+    if cmd.area_1_lights_hmi_owned && hmi_stale(&cmd) {
+        log::warn!("HMI heartbeat stale for over {:?}, area 1 lights back to local control", cmd.hmi_timeout.unwrap_or_default());
+        write_all_channel_kl2889(term_states, cmd.area_1_lights_local);
+        cmd.area_1_lights_hmi_owned = false;
+        cmd.pending_hmi_cmds.clear(); // queued before the HMI went away, don't replay them
+        cmd.hmi_watchdog_tripped = true;
     }
-    else if check_sb_bit(3) {
-        log::error!("Communication Error - No KL6583 ready for op found. Check cabling and addresses");
+    if cmd.hmi_alive {
+        cmd.hmi_watchdog_tripped = false;
     }
-    else { // No errors
-        if read_cb1() != check_sb_bit(1) {
-
-            if (read_db3() & 0b11110000) == 0b01010000 {
-                log::info!("Rocker B, I pos. pressed");
-                write_all_channel_kl2889(ts_c, true);
-            }
-
-            if (read_db3() & 0b11110000) == 0b01110000 {
-                log::info!("Rocker B, O pos. pressed");
-                write_all_channel_kl2889(ts_d, false);
-            }
-
-            if (read_db3() & 0b11110000) == 0b00010000 {
-                log::info!("Rocker A, I pos. pressed");
-                write_all_channel_el2889(true, ts_a);
-            }
-
-            if (read_db3() & 0b11110000) == 0b00110000 {
-                log::info!("Rocker A, 0 pos. pressed");
-                write_all_channel_el2889(false, ts_b);
-            }
-            // log::info!("sb1 through check: {}", check_sb1());
-            write_cb1(!check_sb_bit(1)); // Very important. Tells KL6581 we've fetched the packet.
-        }
-        else {
-            // log::info!("CB.1 == SB.1");
-            if buffer_full() {
-                log::info!("Buffer full");
-                write_cb1(!check_sb_bit(1)); // Very important. Tells KL6581 we've fetched the packet.
-            }
-        }
-    }
-
-    std::thread::sleep(Duration::from_millis(10)); // We're not controlling servos :)
-}
-
-fn read_cnode() -> BitVec<u8, Lsb0> {
-    let rd_guard = &*TERM_KL6581.read().expect("Acquire TERM_KL6581 read guard");
-    let reading = rd_guard.read(None).unwrap();
-    let value: BitVec<u8, Lsb0> = reading.pick_smart().unwrap(); // 192 bits = 24 bytes
-    let bits: &BitSlice<u8, Lsb0> = value.as_bitslice();
-    return BitVec::from_bitslice(&bits[8..16]);
 }
 
-#[repr(u8)]
-enum CnodeErrors { // variant names follow the KL6581 manual from Beckhoff, with the exception of the obvious 'KL6853` typo
-    WatchdogError     = 0x10,
-    NoComWithKL6581   = 0x11,
-    idx_number_not_OK = 0x12,
-    Switch_to_Stopp   = 0x13,
-    not_ready         = 0x14,
-    No_KL6583_Found   = 0x15,
-    TransmissionError = 0x16,
-}
-
-impl CnodeErrors {
-    fn cnode_err_from_u8(value: u8) -> Result<Self, String> {
-        match value {
-            0x10 => Ok(CnodeErrors::WatchdogError),
-            0x11 => Ok(CnodeErrors::NoComWithKL6581),
-            0x12 => Ok(CnodeErrors::idx_number_not_OK),
-            0x13 => Ok(CnodeErrors::Switch_to_Stopp),
-            0x14 => Ok(CnodeErrors::not_ready),
-            0x15 => Ok(CnodeErrors::No_KL6583_Found),
-            0x16 => Ok(CnodeErrors::TransmissionError),
-            _ => Err("Invalid CNODE byte value".into()),
-        }
+/// Forced outputs override whatever the logic wrote this scan. Runs in stop too, forcing is how outputs get moved
+/// by hand while the logic is stopped. Channels were checked against the I/O mirror when the force came in.
+pub fn apply_forces(term_states: &TermStates) {
+    let plc_data = LOCAL_PLC_DATA.lock().unwrap();
+    if plc_data.forces.is_empty() {
+        return;
     }
 
-    // To be used with read_cnode()
-    fn cnode_err_to_string(cnode: BitVec<u8, Lsb0>) -> String {
-        let cnode: u8 = cnode.load_le();
-    
-        let err_message = match CnodeErrors::cnode_err_from_u8(cnode) {
-            Ok(CnodeErrors::WatchdogError)     => "The KL6581 does not answer anymore. Check the mapping and communication.",
-            Ok(CnodeErrors::NoComWithKL6581)   => "The KL6581 does not answer. Check the mapping and communication.",
-            Ok(CnodeErrors::idx_number_not_OK) => "nIdx is not correct. nIdx may have a value from 0 to 64.",
-            Ok(CnodeErrors::Switch_to_Stopp)   => "bInit is FALSE. Set bInit back to TRUE.",
-            Ok(CnodeErrors::not_ready)         => "The terminal is not in data exchange. Check the mapping and communication.",
-            Ok(CnodeErrors::No_KL6583_Found)   => "There is no KL6583 connected. Check the wiring to the KL6583.",
-            Ok(CnodeErrors::TransmissionError) => "The KL6581 does not answer anymore. Check the mapping and communication.",
-            _ => "Invalid CNODE byte value",
+    // K-bus DO terminals are numbered among the K-bus output terminals, like in the I/O mirror
+    let kbus_outputs: Vec<_> = term_states.kbus_terms.iter()
+        .filter(|term| term.read().expect("get K-bus term read guard").gender == KBusTerminalGender::Output)
+        .collect();
+
+    for (address, &value) in &plc_data.forces {
+        let channel = ChannelInput::Index(address.channel.saturating_sub(1) as u8); // channels are 1-based
+        let written = match address.bus {
+            IO_BUS_KBUS => kbus_outputs.get(address.terminal as usize)
+                .map(|term| term.write().expect("get K-bus term write guard").write(value, channel)),
+            IO_BUS_EBUS => term_states.ebus_do_terms.get(address.terminal as usize)
+                .map(|term| term.write().expect("get DO term write guard").write(value, channel)),
+            _ => None,
         };
-        return err_message.to_string()
+        if let Some(Err(e)) = written {
+            log::error!("Force on {}: {}", address.path(), e);
+        }
     }
 }
 
-fn read_cb1() -> bool {
-    let rd_guard = &*TERM_KL6581.read().expect("Acquire TERM_KL6581 read guard");
-    let reading = rd_guard.read(None).unwrap();
-    let value: BitVec<u8, Lsb0> = reading.pick_smart().unwrap(); // 192 bits = 24 bytes
-    let bits: &BitSlice<u8, Lsb0> = value.as_bitslice();
-    return bits[1];
-}
-
-fn read_cb1_dyn(term_states: Arc<RwLock<TermStates>>) -> bool {
-    let rd_guard = term_states.write().expect("get term_states write guard");
-    let rd_guard = rd_guard.kbus_terms[2].write().expect("get KL6581 write guard");
-    let reading = rd_guard.read(None).unwrap();
-    let value: BitVec<u8, Lsb0> = reading.pick_smart().unwrap(); // 192 bits = 24 bytes
-    let bits: &BitSlice<u8, Lsb0> = value.as_bitslice();
-    return bits[1];
-}
-
-pub fn read_db3() -> u8 {
-    let rd_guard = &*TERM_KL6581.read().expect("Acquire TERM_KL6581 read guard");
-    let reading = rd_guard.read(None).unwrap();
-    let value: BitVec<u8, Lsb0> = reading.pick_smart().unwrap(); // 192 bits = 24 bytes
-    let bits: &BitSlice<u8, Lsb0> = value.as_bitslice();
-    return bits[6*8..56].load::<u8>();
-}
-
-pub fn read_db3_dyn(term_states: Arc<RwLock<TermStates>>) -> u8 {
-    let rd_guard = term_states.write().expect("get term_states write guard");
-    let rd_guard = rd_guard.kbus_terms[2].write().expect("get KL6581 write guard");
-    let reading = rd_guard.read(None).unwrap();
-    let value: BitVec<u8, Lsb0> = reading.pick_smart().unwrap(); // 192 bits = 24 bytes
-    let bits: &BitSlice<u8, Lsb0> = value.as_bitslice();
-    return bits[6*8..56].load::<u8>();
-}
-
-fn buffer_full() -> bool {
-    let rd_guard = &*TERM_KL6581.read().expect("Acquire TERM_KL6581 read guard");
-    let reading = rd_guard.read(None).unwrap();
-    let value: BitVec<u8, Lsb0> = reading.pick_smart().unwrap(); // 192 bits = 24 bytes
-    let bits: &BitSlice<u8, Lsb0> = value.as_bitslice();
-    return bits[(12*8)+2]; // SB.2
-}
-
-fn buffer_full_dyn(term_states: Arc<RwLock<TermStates>>) -> bool {
-    let rd_guard = term_states.write().expect("get term_states write guard");
-    let rd_guard = rd_guard.kbus_terms[2].write().expect("get KL6581 write guard");
-    let reading = rd_guard.read(None).unwrap();
-    let value: BitVec<u8, Lsb0> = reading.pick_smart().unwrap(); // 192 bits = 24 bytes
-    let bits: &BitSlice<u8, Lsb0> = value.as_bitslice();
-    return bits[(12*8)+2]; // SB.2
+// Only trips after the HMI was alive at least once, setups without an HMI (e.g. gRPC only) never hand over
+fn hmi_stale(plc_data: &LocalPlcData) -> bool {
+    match (plc_data.hmi_timeout, plc_data.hmi_last_alive) {
+        (Some(timeout), Some(last_alive)) => !plc_data.hmi_alive && last_alive.elapsed() >= timeout,
+        _ => false,
+    }
 }
 
-// use fn write() implemented by Setter trait
-fn write_cb1(val: bool) {
-    let wr_guard = &mut *TERM_KL6581.write().expect("acquire KL6581 write lock");
-    wr_guard.write(val, ChannelInput::Index(1)).unwrap(); // CB.1
+/// Queue an event for the shm event ring, picked up by the shm sync thread
+pub fn queue_event(item: RingItem) {
+    LOCAL_PLC_DATA.lock().unwrap().events.push_back(item);
 }
 
-fn write_cb1_dyn(term_states: Arc<RwLock<TermStates>>, val: bool) {
-    let wr_guard = term_states.write().expect("get term_states write guard");
-    let mut wr_guard = wr_guard.kbus_terms[2].write().expect("get KL6581 write guard");
-    wr_guard.write(val, ChannelInput::Index(1)).unwrap(); // CB.1
+/// Queue a variable-length payload (see gipop_shm::blob) for the shm sync thread
+pub fn queue_blob(kind: u16, payload: Vec<u8>) {
+    LOCAL_PLC_DATA.lock().unwrap().blobs.push_back((kind, payload));
 }
 
-fn check_sb_bit(bit: usize) -> bool {
-    let rd_guard = &*TERM_KL6581.read().expect("Acquire TERM_KL6581 read guard");
-    let reading: BitVec<u8, Lsb0> = rd_guard.check(None).unwrap().expect("call check");
-    return reading.as_bitslice()[bit];
+/// Diagnostic text for consumers, only when it changed since the last report
+pub fn report_diagnostic(message: &str) {
+    let mut plc_data = LOCAL_PLC_DATA.lock().unwrap();
+    if plc_data.last_diagnostic.as_deref() != Some(message) {
+        plc_data.last_diagnostic = Some(message.to_string());
+        plc_data.blobs.push_back((BLOB_DIAGNOSTIC, message.as_bytes().to_vec()));
+    }
 }
 
-pub fn read_area_1_lights(term_states: Arc<RwLock<TermStates>>) -> u8 {
-    let rd_guard = term_states.read().expect("get term_states read guard");
-    let rd_guard = rd_guard.kbus_terms[1].write().expect("acquire KL2889 dyn heap write lock");
+pub fn read_area_1_lights(term_states: &TermStates) -> u8 {
+    let rd_guard = term_states.kbus_terms[1].write().expect("acquire KL2889 dyn heap write lock");
 
     let reading = rd_guard.read(Some(ChannelInput::Channel(TermChannel::Ch1))).unwrap();
     return reading.pick_simple().unwrap()
 }
 
-pub fn read_area_2_lights(term_states: Arc<RwLock<TermStates>>) -> u8 {
-    let rd_guard =
-    term_states.read()
-    .expect("get term_states read guard");
-
+pub fn read_area_2_lights(term_states: &TermStates) -> u8 {
     let rd_guard =
-    rd_guard.ebus_do_terms[0]
+    term_states.ebus_do_terms[0]
     .write()
     .expect("acquire EL2889 dyn heap write lock");
 
@@ -251,22 +208,17 @@ pub fn read_area_2_lights(term_states: Arc<RwLock<TermStates>>) -> u8 {
     return reading.pick_simple().unwrap()
 }
 
-fn write_all_channel_kl2889(term_states: Arc<RwLock<TermStates>>, val: bool) {
-    let wr_guard = term_states.write().expect("get term_states write guard");
-    let mut wr_guard = wr_guard.kbus_terms[1].write().expect("get KL2889 write guard");
+pub fn write_all_channel_kl2889(term_states: &TermStates, val: bool) {
+    let mut wr_guard = term_states.kbus_terms[1].write().expect("get KL2889 write guard");
 
     for idx in 0..wr_guard.size_in_bits { // All 16 bits of KL2889
         wr_guard.write(val, ChannelInput::Index(idx)).unwrap();
     }
 }
 
-fn write_all_channel_el2889(val: bool, term_states: Arc<RwLock<TermStates>>) {
-    let wr_guard =
-    term_states.read()
-    .expect("get term_states read guard");
-
+pub fn write_all_channel_el2889(val: bool, term_states: &TermStates) {
     let mut wr_guard =
-    wr_guard.ebus_do_terms[0]
+    term_states.ebus_do_terms[0]
     .write()
     .expect("acquire EL2889 dyn heap write lock");
 
@@ -275,12 +227,8 @@ fn write_all_channel_el2889(val: bool, term_states: Arc<RwLock<TermStates>>) {
     }
 }
 
-// Very important. Resets hmi cmd in shared mem so that the old value doesn't create conflict with
-// later EnOcean commands
-fn reset_hmi_cmd() {
-    let file = OpenOptions::new().read(true).write(true).open(SHM_PATH).unwrap();
-    let mut mmap = map_shared_memory(&file);
-    let mut data = read_data(&mmap);
-    data.area_1_lights_hmi_cmd = 0;
-    write_data(&mut mmap, data);
+// Very important. Resets the hmi cmd so that the old value doesn't create conflict with
+// later EnOcean commands. Commands arrive as discrete items through the shm command ring, nothing to clear there
+fn reset_hmi_cmd(plc_data: &mut LocalPlcData) {
+    plc_data.area_1_lights_hmi_cmd = 0;
 }
\ No newline at end of file
@@ -1,45 +1,242 @@
-use env_logger::Env;
+pub mod audit;
+pub mod bench;
+pub mod blackbox;
+pub mod check;
+pub mod cli;
 pub mod ctrl_loop;
-mod shared;
+#[cfg(feature = "enocean")]
+pub mod enocean;
 pub mod logic;
-use shared::{SharedData, SHM_PATH};
-use std::{env, fs::OpenOptions, path::Path,};
+pub mod tags;
+pub mod config;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(feature = "modbus")]
+pub mod modbus;
+pub mod networks;
+pub mod reload;
+pub mod scan;
+pub mod time_sync;
+mod virtual_bus;
+use clap::Parser;
+use gipop_shm::telemetry::{self, TelemetryConfig};
+use gipop_shm::{IpcConfig, Liveness, PlcInfo, Project, Publisher, Startup, Step, TagDef, Transport, Users};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use cli::{Cli, Command};
+use config::PlcConfig;
+
+const IPC_RETRY: Duration = Duration::from_millis(500);
+const IPC_TIMEOUT: Duration = Duration::from_secs(10);
+const BUS_RETRY: Duration = Duration::from_secs(2); // on top of the state transition timeouts a try can take
+const BUS_TIMEOUT: Duration = Duration::from_secs(60);
+const SERVER_RETRY: Duration = Duration::from_secs(1); // a port the last instance still holds
+const SERVER_TIMEOUT: Duration = Duration::from_secs(10);
 
 fn main() { // opcua setup + config + shutdown should be done here
-    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+    let args = Cli::parse(); // exits with 2 and the usage on bad arguments
+    // a project is read as a whole or not at all, before anything starts
+    let project = args.project.as_ref().map(|dir| Project::open(dir, env!("CARGO_PKG_VERSION")).unwrap_or_else(|e| {
+        eprintln!("gipop_plc: {}", e);
+        std::process::exit(1);
+    }));
+    let config = project.as_ref().map_or(args.config.clone(), |project| project.config_path().to_string_lossy().into_owned());
+    let config = config.as_str();
+    if let Some(spec) = &args.log_level {
+        gipop_shm::logging::set_spec(spec);
+    }
+    gipop_shm::logging::init(config, "gipop_plc");
+    gipop_shm::logging::reload_on_sighup();
+    if let Some(project) = &project {
+        log::info!("Running {}", project);
+    }
+
+    // scan, check-config, bench and bench-handlers exit without starting the PLC, see cli.rs
+    match args.command {
+        Some(Command::Scan { interface }) => {
+            if let Err(e) = smol::block_on(scan::scan(&ctrl_loop::start_maindevice(&interface), &interface)) {
+                log::error!("Bus scan failed: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::CheckConfig { interface }) => {
+            let ok = smol::block_on(check::check_config(config, project.as_ref(), interface.as_deref()));
+            std::process::exit(if ok { 0 } else { 1 });
+        }
+        Some(Command::Bench { interface, seconds, period_us }) => {
+            if let Err(e) = smol::block_on(bench::bench(&interface, seconds, Duration::from_micros(period_us))) {
+                log::error!("Bench failed: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::BenchHandlers { terminals, cycles }) => {
+            bench::bench_handlers(terminals as usize, cycles);
+            return;
+        }
+        None => {}
+    }
+    let cfg = PlcConfig::load(config).expect("load PLC config");
+    let problems = networks::check(&cfg.networks);
+    for problem in &problems {
+        log::error!("{}", problem);
+    }
+    if !problems.is_empty() {
+        std::process::exit(1);
+    }
+    // the logic's tags, then those of the other networks
+    let plc_tags = [tags::plc_tags(), networks::tags(&cfg.networks)].concat();
+    if let Some(project) = &project && let Err(e) = project.check_tags(&plc_tags) {
+        log::error!("{}", e);
+        std::process::exit(1);
+    }
 
-    log::info!("Initializing shared memory");
-    let init = init_shared_memory(); // shared memory between PLC and OPC UA server
-    match init {
-        Ok(_file) => {
+    let ipc = IpcConfig::load(config).expect("load IPC config");
+    let trace = TelemetryConfig::load(config).expect("load telemetry config");
+    telemetry::init(&trace, "gipop_plc");
+
+    // The bus on the NIC or the virtual reference rack, the NIC's TX/RX thread starts here
+    let simulate = args.simulate || args.interface.is_none();
+    let (maindevice, bus) = match &args.interface {
+        Some(interface) if !simulate => (ctrl_loop::start_maindevice(interface), interface.clone()),
+        _ => {
+            log::warn!("Simulating: running on a virtual reference rack, not on a bus");
+            (virtual_bus::VirtualBus::new(virtual_bus::reference_rack()).start_maindevice(), "the virtual rack".to_owned())
         }
-        Err(error) => {
-            log::error!("Error opening the file: {}", error);
+    };
+
+    if args.dry_run {
+        log::info!("Dry run: {} and {} tags loaded, stopping after the bus scan", config, plc_tags.len());
+        if let Err(e) = smol::block_on(scan::scan(&maindevice, &bus)) {
+            log::error!("Bus scan failed: {}", e);
+            std::process::exit(1);
         }
+        return;
+    }
+
+    // Brought up in this order and taken down in reverse: the IPC region or socket, the main bus to OP, the other
+    // networks, the logic, then the servers. Ctrl+C stops the logic and the networks, then the rest comes down
+    let shutdown = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(&shutdown)).expect("Register hook");
+    let mut startup = Startup::new("gipop_plc");
+
+    log::info!("Initializing {:?} IPC", ipc.transport);
+    // IPC between PLC and OPC UA server. Held until exit, on Windows the shm region is gone once its last handle closes.
+    // Retried, a socket can still be held by an instance that's exiting
+    let target = if ipc.transport == Transport::Shm { &ipc.shm_path } else { &ipc.socket_path };
+    let mut publisher = startup.up(Step::new("ipc").retry(IPC_RETRY, IPC_TIMEOUT), || init_ipc(&ipc, &plc_tags).map_err(|e| format!("{}: {}", target, e)))
+        .unwrap_or_else(|e| startup.fail(&e));
+
+    publisher.write_plc_info(&plc_info(project.as_ref()));
+
+    {
+        let mut plc_data = logic::LOCAL_PLC_DATA.lock().unwrap();
+        plc_data.hmi_timeout = cfg.watchdog.hmi_timeout();
+        plc_data.users = Users::load(config).expect("load [users]"); // checked against every command's audit
+    }
+    audit::open(&cfg.audit.path);
+    blackbox::start(&cfg.blackbox);
+    if cfg.time_sync.enabled {
+        time_sync::start(cfg.time_sync.clone());
     }
 
-    let args: Vec<String> = env::args().collect();
+    // Retried while the rack is still booting. Kept here between the steps, the logic has it while it runs
+    let bus = startup.up(Step::new("bus").needs(&["ipc"]).retry(BUS_RETRY, BUS_TIMEOUT), || {
+        smol::block_on(ctrl_loop::bring_up(&maindevice)).map_err(|e| format!("{}: {:#}", bus, e))
+    }).unwrap_or_else(|e| startup.fail(&e));
+    let parked = Arc::new(Mutex::new(Some(bus)));
+    startup.on_down({
+        let (maindevice, parked) = (maindevice.clone(), parked.clone());
+        move || {
+            if let Some(bus) = parked.lock().unwrap().take() && let Err(e) = smol::block_on(ctrl_loop::step_down(&maindevice, bus)) {
+                log::error!("Taking the bus down: {:#}", e);
+            }
+        }
+    });
+
+    let networks = startup.up(Step::new("networks").needs(&["ipc"]), || Ok::<_, String>(networks::start(&cfg.networks, simulate, &shutdown)))
+        .unwrap_or_else(|e| startup.fail(&e));
+    startup.on_down({
+        let shutdown = shutdown.clone();
+        move || {
+            shutdown.store(true, Ordering::Relaxed);
+            for network in networks {
+                _ = network.join(); // back in INIT, or failed and logged
+            }
+        }
+    });
 
-    if args.len() != 2 {
-        log::error!("Provide only 1 argument: The network interface name!");
+    // Joined below, before anything else comes down
+    let consumers = Liveness::new(ipc.heartbeat_timeout());
+    let cycle_time = Duration::from_micros(args.cycle_us);
+    let mut handover = Some((publisher, consumers));
+    let logic = startup.up(Step::new("logic").needs(&["ipc", "bus"]), || {
+        let (publisher, consumers) = handover.take().expect("logic started once");
+        let (maindevice, parked, shutdown) = (maindevice.clone(), parked.clone(), shutdown.clone());
+        let (cycle_every, cycle_time) = (trace.cycle_every, cycle_time);
+        let bus = parked.lock().unwrap().take().expect("bus up");
+        std::thread::Builder::new()
+        .name("PlcLogicThread".to_owned())
+        .spawn(move || {
+            let (bus, publisher) = smol::block_on(ctrl_loop::cycle(&maindevice, bus, publisher, consumers, cycle_every, cycle_time, shutdown));
+            *parked.lock().unwrap() = Some(bus);
+            publisher
+        })
+    }).unwrap_or_else(|e| startup.fail(&e));
+
+    // Without them the PLC still runs, so they're only logged when they don't come up
+    if cfg.modbus.enabled && let Err(e) = startup.up(Step::new("modbus").needs(&["logic"]).retry(SERVER_RETRY, SERVER_TIMEOUT), || start_modbus(config, &cfg, &plc_tags)) {
+        log::error!("{}", e);
+    }
+    if cfg.grpc.enabled && let Err(e) = startup.up(Step::new("grpc").needs(&["logic"]), || start_grpc(config, &cfg, &plc_tags)) {
+        log::error!("{}", e);
     }
+    reload::start(config, &cfg);
 
-    let network_interface = &args[1];
-    
-    smol::block_on(ctrl_loop::entry_loop(network_interface)).expect("Entry loop task");
+    match logic.join() {
+        Ok(publisher) => {
+            startup.down();
+            drop(publisher); // the IPC came up first, it goes last
+        }
+        Err(_) => startup.fail("The logic stopped on a panic"),
+    }
     log::info!("Program terminated.");
 }
 
-fn init_shared_memory() -> std::io::Result<std::fs::File> {
-    let path = Path::new(SHM_PATH);
+// Lays out the shm region (header, tag directory, zeroed values) or binds the socket from the PLC's tag list
+fn init_ipc(ipc: &IpcConfig, plc_tags: &[TagDef]) -> std::io::Result<Publisher> {
+    Publisher::create(ipc, plc_tags)
+}
 
-    let file = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)    // create if it doesn't exist
-        .truncate(true)  // resize to correct length
-        .open(path)?;
+// What consumers show as the PLC's firmware and application
+fn plc_info(project: Option<&Project>) -> PlcInfo {
+    let profile = if cfg!(debug_assertions) { "debug" } else { "release" };
+    let info = PlcInfo::new(env!("CARGO_PKG_VERSION"), env!("GIPOP_COMMIT"), profile);
+    match project {
+        Some(project) => info.with_project(&project.manifest.name, &project.manifest.version),
+        None => info,
+    }
+}
+
+#[cfg(feature = "grpc")]
+fn start_grpc(config: &str, cfg: &PlcConfig, plc_tags: &[TagDef]) -> Result<(), String> {
+    // writes are checked against the same [users] as OPC UA's
+    gipop_shm::Users::load(config).and_then(|users| grpc::serve(&cfg.grpc.listen, plc_tags, users))
+}
+
+#[cfg(not(feature = "grpc"))]
+fn start_grpc(config: &str, _cfg: &PlcConfig, _plc_tags: &[TagDef]) -> Result<(), String> {
+    Err(format!("gRPC is enabled in {} but this build doesn't have the `grpc` feature", config))
+}
+
+#[cfg(feature = "modbus")]
+fn start_modbus(_config: &str, cfg: &PlcConfig, plc_tags: &[TagDef]) -> Result<(), String> {
+    modbus::serve(&cfg.modbus, plc_tags)
+}
 
-    file.set_len(std::mem::size_of::<SharedData>() as u64)?;
-    Ok(file)
+#[cfg(not(feature = "modbus"))]
+fn start_modbus(config: &str, _cfg: &PlcConfig, _plc_tags: &[TagDef]) -> Result<(), String> {
+    Err(format!("Modbus is enabled in {} but this build doesn't have the `modbus` feature", config))
 }
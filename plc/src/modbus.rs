@@ -0,0 +1,380 @@
+// Modbus TCP server (`[modbus]` in gipop.toml) for HMIs and SCADA that only speak Modbus. Same arrangement as the
+// gRPC tag service: the shm thread feeds it every publish and drains its writes right next to the command ring's.
+// Tags sit where the register map puts them, unmapped addresses read as 0 and can't be written.
+//
+// Modbus has no notion of users, so writes are open to whoever reaches the port. Put only what they may command
+// in the coil/holding tables (writable tags only), everything else in discrete/input, and mind `listen`.
+//
+// Function codes: 1 read coils, 2 read discrete inputs, 3 read holding registers, 4 read input registers,
+// 5/15 write single/multiple coils, 6/16 write single/multiple holding registers.
+use crate::config::{ModbusConfig, ModbusTable, RegisterConfig, RegisterFormat};
+use gipop_shm::{RingItem, TagDef, TagType, TagValue};
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+
+const MAX_QUEUED_COMMANDS: usize = 64; // same bound as the shm command ring
+const ADDRESS_SPACE: u32 = 0x10000;
+
+// Per request, what fits in one PDU
+const MAX_READ_BITS: u16 = 2000;
+const MAX_READ_REGISTERS: u16 = 125;
+const MAX_WRITE_BITS: u16 = 1968;
+const MAX_WRITE_REGISTERS: u16 = 123;
+
+// Exception codes
+const ILLEGAL_FUNCTION: u8 = 1;
+const ILLEGAL_DATA_ADDRESS: u8 = 2;
+const ILLEGAL_DATA_VALUE: u8 = 3;
+const SERVER_DEVICE_BUSY: u8 = 6;
+
+struct Mapping {
+    tag: usize,
+    table: ModbusTable,
+    address: u16,
+    format: RegisterFormat,
+    scale: f64,
+}
+
+struct Hub {
+    tags: Vec<TagDef>,
+    unit_id: u8,
+    mappings: RwLock<Vec<Mapping>>, // swapped by remap() on a config reload
+    values: RwLock<Vec<TagValue>>,
+    commands: Mutex<VecDeque<RingItem>>,
+}
+
+static HUB: OnceLock<Arc<Hub>> = OnceLock::new();
+
+/// Start serving `tags` as mapped by `config`, returns once the listener is bound
+pub fn serve(config: &ModbusConfig, tags: &[TagDef]) -> Result<(), String> {
+    let mappings = config.registers.iter().map(|register| mapping(register, tags)).collect::<Result<Vec<_>, _>>()?;
+    if let Some(overlap) = overlaps(&mappings, tags).into_iter().next() {
+        return Err(overlap);
+    }
+
+    let listener = TcpListener::bind(&config.listen).map_err(|e| format!("Failed to listen for Modbus on {}: {}", config.listen, e))?;
+    let hub = Arc::new(Hub {
+        tags: tags.to_vec(),
+        unit_id: config.unit_id,
+        mappings: RwLock::new(mappings),
+        values: RwLock::new(tags.iter().map(|def| def.ty.default_value()).collect()),
+        commands: Mutex::new(VecDeque::new()),
+    });
+    HUB.set(hub.clone()).map_err(|_| "Modbus server already started".to_string())?;
+
+    let listen = config.listen.clone();
+    std::thread::Builder::new()
+    .name("PlcModbusThread".to_owned())
+    .spawn(move || {
+        log::info!("Modbus TCP server listening on {}, {} tags mapped", listen, hub.mappings.read().unwrap().len());
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log::warn!("Modbus accept failed: {}", e);
+                    continue;
+                }
+            };
+            let peer = stream.peer_addr().map(|addr| addr.to_string()).unwrap_or_default();
+            let hub = hub.clone();
+            let spawned = std::thread::Builder::new()
+                .name("PlcModbusClient".to_owned())
+                .spawn(move || {
+                    log::info!("Modbus client {} connected", peer);
+                    match hub.client(stream) {
+                        Ok(()) => log::info!("Modbus client {} disconnected", peer),
+                        Err(e) => log::warn!("Modbus client {} dropped: {}", peer, e),
+                    }
+                });
+            if let Err(e) = spawned {
+                log::warn!("Failed to spawn Modbus client thread: {}", e);
+            }
+        }
+    })
+    .map_err(|e| format!("Failed to spawn Modbus thread: {}", e))?;
+
+    Ok(())
+}
+
+/// Call after every publish. No-op if the server isn't running.
+pub fn publish(values: &[(usize, TagValue)]) {
+    let Some(hub) = HUB.get() else { return };
+    let mut current = hub.values.write().unwrap();
+    for &(idx, value) in values {
+        current[idx] = value;
+    }
+}
+
+/// Writes from Modbus clients, in the same form as the ones from the command ring
+pub fn pop_command() -> Option<RingItem> {
+    HUB.get()?.commands.lock().unwrap().pop_front()
+}
+
+/// Serves `config`'s register map from now on (addresses, formats, scaling), for a config reload. Nothing changes if
+/// it doesn't check out, no-op if the server isn't running
+pub fn remap(config: &ModbusConfig) -> Result<(), String> {
+    let Some(hub) = HUB.get() else { return Ok(()) };
+    let mappings = config.registers.iter().map(|register| mapping(register, &hub.tags)).collect::<Result<Vec<_>, _>>()?;
+    if let Some(overlap) = overlaps(&mappings, &hub.tags).into_iter().next() {
+        return Err(overlap);
+    }
+    *hub.mappings.write().unwrap() = mappings;
+    Ok(())
+}
+
+/// Everything wrong with the register map, where serve stops at the first. Empty if serve would take it
+pub fn check(config: &ModbusConfig, tags: &[TagDef]) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mappings: Vec<Mapping> = config.registers.iter()
+        .filter_map(|register| mapping(register, tags).map_err(|e| problems.push(e)).ok())
+        .collect();
+    problems.extend(overlaps(&mappings, tags));
+    problems
+}
+
+fn overlaps(mappings: &[Mapping], tags: &[TagDef]) -> Vec<String> {
+    let mut overlaps = Vec::new();
+    for (i, a) in mappings.iter().enumerate() {
+        for b in mappings[..i].iter().filter(|b| b.table == a.table && (b.address as u32) < a.end() && (a.address as u32) < b.end()) {
+            overlaps.push(format!("Modbus addresses of '{}' and '{}' overlap", tags[b.tag].name, tags[a.tag].name));
+        }
+    }
+    overlaps
+}
+
+fn mapping(register: &RegisterConfig, tags: &[TagDef]) -> Result<Mapping, String> {
+    let tag = tags.iter().position(|def| def.name == register.tag)
+        .ok_or_else(|| format!("Modbus register map: no tag named '{}'", register.tag))?;
+    let ty = tags[tag].ty;
+    let bits = matches!(register.table, ModbusTable::Coil | ModbusTable::Discrete);
+    if bits && (ty != TagType::Bool || register.format.is_some()) {
+        return Err(format!("Modbus register map: '{}' can't be a {:?}, only Bool tags without a format fit in bits", register.tag, register.table));
+    }
+    if register.scale == 0.0 || !register.scale.is_finite() {
+        return Err(format!("Modbus register map: '{}' has scale {}", register.tag, register.scale));
+    }
+    let format = register.format.unwrap_or(match ty {
+        TagType::Bool => RegisterFormat::Uint16,
+        TagType::UInt32 => RegisterFormat::Uint32,
+        TagType::Int32 => RegisterFormat::Int32,
+        TagType::Float32 | TagType::Float64 => RegisterFormat::Float32,
+    });
+
+    let mapping = Mapping { tag, table: register.table, address: register.address, format, scale: register.scale };
+    if mapping.end() > ADDRESS_SPACE {
+        return Err(format!("Modbus register map: '{}' runs past address 65535", register.tag));
+    }
+    Ok(mapping)
+}
+
+impl Mapping {
+    fn is_bit(&self) -> bool {
+        matches!(self.table, ModbusTable::Coil | ModbusTable::Discrete)
+    }
+
+    // bits or registers taken
+    fn len(&self) -> usize {
+        match self.format {
+            _ if self.is_bit() => 1,
+            RegisterFormat::Int16 | RegisterFormat::Uint16 => 1,
+            RegisterFormat::Int32 | RegisterFormat::Uint32 | RegisterFormat::Float32 => 2,
+        }
+    }
+
+    fn end(&self) -> u32 {
+        self.address as u32 + self.len() as u32
+    }
+
+    // High word first, only the first len() are used. Out of range values saturate (`as` does that for floats)
+    fn encode(&self, value: TagValue) -> [u16; 2] {
+        let split = |n: u32| [(n >> 16) as u16, n as u16];
+        let scaled = value.as_f64() * self.scale;
+        match self.format {
+            _ if self.is_bit() => [(value.as_f64() != 0.0) as u16, 0],
+            RegisterFormat::Int16 => [scaled.round() as i16 as u16, 0],
+            RegisterFormat::Uint16 => [scaled.round() as u16, 0],
+            RegisterFormat::Int32 => split(scaled.round() as i32 as u32),
+            RegisterFormat::Uint32 => split(scaled.round() as u32),
+            RegisterFormat::Float32 => split((scaled as f32).to_bits()),
+        }
+    }
+
+    // None when the written number doesn't fit the tag's type
+    fn decode(&self, ty: TagType, words: &[u16]) -> Option<TagValue> {
+        if self.is_bit() {
+            return Some(TagValue::Bool(words[0] != 0));
+        }
+        let joined = || (words[0] as u32) << 16 | words[1] as u32;
+        let number = match self.format {
+            RegisterFormat::Int16 => words[0] as i16 as f64,
+            RegisterFormat::Uint16 => words[0] as f64,
+            RegisterFormat::Int32 => joined() as i32 as f64,
+            RegisterFormat::Uint32 => joined() as f64,
+            RegisterFormat::Float32 => f32::from_bits(joined()) as f64,
+        } / self.scale;
+
+        let whole = number.round();
+        match ty {
+            _ if !number.is_finite() => None,
+            TagType::Bool => Some(TagValue::Bool(number != 0.0)),
+            TagType::UInt32 => (0.0..=u32::MAX as f64).contains(&whole).then_some(TagValue::UInt32(whole as u32)),
+            TagType::Int32 => (i32::MIN as f64..=i32::MAX as f64).contains(&whole).then_some(TagValue::Int32(whole as i32)),
+            TagType::Float32 => Some(TagValue::Float32(number as f32)),
+            TagType::Float64 => Some(TagValue::Float64(number)),
+        }
+    }
+}
+
+impl Hub {
+    // Serves one connection until the client hangs up or sends garbage
+    fn client(&self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+        let mut header = [0u8; 7]; // transaction id, protocol id (0), length of unit id + PDU, unit id
+        loop {
+            match stream.read_exact(&mut header) {
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                read => read?,
+            }
+            let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+            if header[2..4] != [0, 0] || !(2..=254).contains(&length) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "not a Modbus TCP frame"));
+            }
+            let mut pdu = vec![0u8; length - 1];
+            stream.read_exact(&mut pdu)?;
+
+            let unit = header[6];
+            if unit != self.unit_id && unit != 0 && unit != 255 {
+                continue; // someone else's, a gateway would forward it
+            }
+            let response = self.handle(&pdu).unwrap_or_else(|exception| vec![pdu[0] | 0x80, exception]);
+
+            let mut frame = Vec::with_capacity(header.len() + response.len());
+            frame.extend_from_slice(&header[..4]);
+            frame.extend_from_slice(&(response.len() as u16 + 1).to_be_bytes());
+            frame.push(unit);
+            frame.extend_from_slice(&response);
+            stream.write_all(&frame)?;
+        }
+    }
+
+    // The response PDU, Err with the exception code
+    fn handle(&self, pdu: &[u8]) -> Result<Vec<u8>, u8> {
+        let word = |at: usize| pdu.get(at..at + 2).map(|b| u16::from_be_bytes([b[0], b[1]])).ok_or(ILLEGAL_DATA_VALUE);
+        let function = pdu[0];
+        match function {
+            1..=4 => {
+                let (start, count) = (word(1)?, word(3)?);
+                let table = [ModbusTable::Coil, ModbusTable::Discrete, ModbusTable::Holding, ModbusTable::Input][function as usize - 1];
+                let bits = function <= 2;
+                if count == 0 || count > if bits { MAX_READ_BITS } else { MAX_READ_REGISTERS } {
+                    return Err(ILLEGAL_DATA_VALUE);
+                }
+                if start as u32 + count as u32 > ADDRESS_SPACE {
+                    return Err(ILLEGAL_DATA_ADDRESS);
+                }
+
+                let words = self.read(table, start, count);
+                let mut response = vec![function];
+                if bits {
+                    let mut packed = vec![0u8; count.div_ceil(8) as usize];
+                    for (i, _) in words.iter().enumerate().filter(|(_, bit)| **bit != 0) {
+                        packed[i / 8] |= 1 << (i % 8);
+                    }
+                    response.push(packed.len() as u8);
+                    response.extend(packed);
+                } else {
+                    response.push((words.len() * 2) as u8);
+                    response.extend(words.iter().flat_map(|word| word.to_be_bytes()));
+                }
+                Ok(response)
+            }
+            5 => {
+                let bit = match word(3)? {
+                    0xFF00 => 1,
+                    0x0000 => 0,
+                    _ => return Err(ILLEGAL_DATA_VALUE),
+                };
+                self.write(ModbusTable::Coil, word(1)?, &[bit])?;
+                Ok(pdu[..5].to_vec()) // echo
+            }
+            6 => {
+                self.write(ModbusTable::Holding, word(1)?, &[word(3)?])?;
+                Ok(pdu[..5].to_vec()) // echo
+            }
+            15 | 16 => {
+                let (start, count) = (word(1)?, word(3)?);
+                let coils = function == 15;
+                if count == 0 || count > if coils { MAX_WRITE_BITS } else { MAX_WRITE_REGISTERS } {
+                    return Err(ILLEGAL_DATA_VALUE);
+                }
+                let expected = (if coils { count.div_ceil(8) } else { count * 2 }) as usize;
+                let data = pdu.get(6..6 + expected).filter(|_| pdu.get(5) == Some(&(expected as u8))).ok_or(ILLEGAL_DATA_VALUE)?;
+
+                let words: Vec<u16> = if coils {
+                    (0..count as usize).map(|i| (data[i / 8] >> (i % 8) & 1) as u16).collect()
+                } else {
+                    data.chunks(2).map(|b| u16::from_be_bytes([b[0], b[1]])).collect()
+                };
+                let table = if coils { ModbusTable::Coil } else { ModbusTable::Holding };
+                self.write(table, start, &words)?;
+                Ok(pdu[..5].to_vec()) // function, start, count
+            }
+            _ => Err(ILLEGAL_FUNCTION),
+        }
+    }
+
+    // One word per address from `start`, bits as 0/1
+    fn read(&self, table: ModbusTable, start: u16, count: u16) -> Vec<u16> {
+        let mut words = vec![0; count as usize];
+        let values = self.values.read().unwrap();
+        for mapping in self.mappings.read().unwrap().iter().filter(|mapping| mapping.table == table) {
+            let encoded = mapping.encode(values[mapping.tag]);
+            for (offset, word) in encoded[..mapping.len()].iter().enumerate() {
+                let slot = (mapping.address as usize + offset).checked_sub(start as usize).and_then(|i| words.get_mut(i));
+                if let Some(slot) = slot {
+                    *slot = *word;
+                }
+            }
+        }
+        words
+    }
+
+    // All or nothing: every address written must belong to a writable tag, and 32 bit values must be written whole
+    fn write(&self, table: ModbusTable, start: u16, words: &[u16]) -> Result<(), u8> {
+        let (start, end) = (start as u32, start as u32 + words.len() as u32);
+        let mut items = Vec::new();
+        let mut covered = 0;
+        for mapping in self.mappings.read().unwrap().iter().filter(|mapping| mapping.table == table && (mapping.address as u32) < end && mapping.end() > start) {
+            let def = &self.tags[mapping.tag];
+            if (mapping.address as u32) < start || mapping.end() > end {
+                log::warn!("Modbus: partial write to '{}'", def.name);
+                return Err(ILLEGAL_DATA_ADDRESS);
+            }
+            if !def.writable() {
+                log::warn!("Modbus: '{}' is read only", def.name);
+                return Err(ILLEGAL_DATA_ADDRESS);
+            }
+            let offset = (mapping.address as u32 - start) as usize;
+            let value = mapping.decode(def.ty, &words[offset..offset + mapping.len()])
+                .filter(|value| def.accepts(*value))
+                .ok_or_else(|| {
+                    log::warn!("Modbus: value out of range for '{}' {:?}", def.name, def.write_range());
+                    ILLEGAL_DATA_VALUE
+                })?;
+            items.push(RingItem::tag_write(mapping.tag, value));
+            covered += mapping.len();
+        }
+        if covered != words.len() {
+            return Err(ILLEGAL_DATA_ADDRESS); // some address isn't mapped
+        }
+
+        let mut commands = self.commands.lock().unwrap();
+        if commands.len() + items.len() > MAX_QUEUED_COMMANDS {
+            return Err(SERVER_DEVICE_BUSY);
+        }
+        commands.extend(items);
+        Ok(())
+    }
+}
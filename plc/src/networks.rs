@@ -0,0 +1,340 @@
+// More EtherCAT networks ([[networks]] in gipop.toml, see config.rs), each on a NIC of its own next to the main bus
+// (the interface on the command line, the one the logic runs on). Every network has its own MainDevice and PDU
+// storage (ctrl_loop::start_maindevice), group, terminal set and cycle, in a thread of its own, so a slow or failed
+// network doesn't hold up the others.
+//
+// What's on a network is published as tags named after it, in the folder Networks/<name>:
+//
+// line2.ok                  the network is in OP and the last cycle's working counter was right
+// line2.cycle time          ms, the last cycle
+// line2.wkc errors          cycles with a wrong working counter since the start
+// line2.EL1889 1 ch3        a channel: the SubDevice's label, or its name and how many of that name came before plus
+//                           one, then the channel. EL1889 inputs, EL2889 outputs (writable), EL3004/EL3024 inputs in mA
+//
+// Tags are there from the start, made from the network's `subdevices`, which the bus has to match in order for the
+// network to go to OP. Other SubDevices (couplers...) are exchanged but have no tags, K-bus terminals behind a BK1120
+// are only driven on the main bus. Bus diagnostics, the process image, the I/O mirror, forces and the black box are
+// the main bus's too. With --simulate every network runs on a virtual rack of its `subdevices` (virtual_bus.rs).
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use async_io::Timer;
+use bitvec::prelude::*;
+use ethercrab::std::ethercat_now;
+use ethercrab::MainDevice;
+use gipop_shm::{TagDef, TagType, TagValue, TAG_WRITABLE};
+use hal::diagnostics::{self, CycleStats};
+use hal::pdi::PdiRange;
+use hal::io_defs::{TermStates, EL1889_IMG_LEN_BITS, EL2889_IMG_LEN_BITS, EL3024_NUM_CHANNELS};
+use hal::term_cfg::{AITerm, ChannelInput, DITerm, DOTerm, Getter};
+
+use crate::config::NetworkConfig;
+use crate::ctrl_loop::{self, MAX_SUBDEVICES, PDI_LEN};
+use crate::virtual_bus::{VirtualBus, VirtualSubDevice};
+
+pub const MAX_NETWORKS: usize = 4; // besides the main bus, each takes a NIC, a TX/RX thread and a cycle thread
+
+static NETWORKS: RwLock<Vec<Arc<Network>>> = RwLock::new(Vec::new());
+
+struct Network {
+    config: NetworkConfig,
+    tags: Vec<(String, Source)>,
+    term_states: OnceLock<TermStates>, // set once the network is in OP
+    status: Mutex<Status>,
+}
+
+#[derive(Default)]
+struct Status {
+    ok: bool,
+    cycle: Duration,
+    wkc_errors: u64,
+}
+
+// What a tag of a network is, terminals counted per kind in bus order like the I/O mirror does
+#[derive(Clone, Copy)]
+enum Source {
+    Ok,
+    CycleTime,
+    WkcErrors,
+    Di(usize, usize), // terminal, channel index
+    Do(usize, usize),
+    Ai(usize, usize),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Kind {
+    Di,
+    Do,
+    Ai,
+}
+
+// The SubDevices networks publish channels of, and how many
+fn kind(subdevice: &str) -> Option<(Kind, usize)> {
+    match subdevice {
+        "EL1889" => Some((Kind::Di, EL1889_IMG_LEN_BITS as usize)),
+        "EL2889" => Some((Kind::Do, EL2889_IMG_LEN_BITS as usize)),
+        "EL3004" | "EL3024" => Some((Kind::Ai, EL3024_NUM_CHANNELS as usize)),
+        _ => None,
+    }
+}
+
+/// Every network's tags, to publish with the PLC's own (tags::plc_tags)
+pub fn tags(networks: &[NetworkConfig]) -> Vec<TagDef> {
+    networks.iter().flat_map(layout).map(|(tag, _)| tag).collect()
+}
+
+// A network's tags and what each is
+fn layout(network: &NetworkConfig) -> Vec<(TagDef, Source)> {
+    let folder = format!("Networks/{}", network.name);
+    let name = |what: &str| format!("{}.{}", network.name, what);
+    let mut tags = vec![
+        (TagDef::new(&name("ok"), TagType::Bool, 0), Source::Ok),
+        (TagDef::new(&name("cycle time"), TagType::Float32, 0).analog("ms", 0.0, 10.0), Source::CycleTime),
+        (TagDef::new(&name("wkc errors"), TagType::UInt32, 0), Source::WkcErrors),
+    ];
+    let mut terminals = HashMap::new(); // of each kind so far
+    let mut named = HashMap::new(); // of each SubDevice name so far
+    for subdevice in &network.subdevices {
+        let count = named.entry(subdevice.name.as_str()).or_insert(0);
+        *count += 1;
+        let Some((kind, channels)) = kind(&subdevice.name) else { continue };
+        let label = if subdevice.label.is_empty() { format!("{} {}", subdevice.name, count) } else { subdevice.label.clone() };
+        let terminal = terminals.entry(kind).or_insert(0);
+        for ch in 0..channels {
+            let channel = name(&format!("{} ch{}", label, ch + 1));
+            tags.push(match kind {
+                Kind::Di => (TagDef::new(&channel, TagType::Bool, 0), Source::Di(*terminal, ch)),
+                Kind::Do => (TagDef::new(&channel, TagType::Bool, TAG_WRITABLE), Source::Do(*terminal, ch)),
+                Kind::Ai => (TagDef::new(&channel, TagType::Float32, 0).analog("mA", 4.0, 20.0), Source::Ai(*terminal, ch)),
+            });
+        }
+        *terminal += 1;
+    }
+    tags.into_iter().map(|(tag, source)| (tag.in_folder(&folder), source)).collect()
+}
+
+/// What's wrong with [[networks]], for check-config
+pub fn check(networks: &[NetworkConfig]) -> Vec<String> {
+    let mut problems = Vec::new();
+    if networks.len() > MAX_NETWORKS {
+        problems.push(format!("[[networks]] lists {} networks, the PLC drives at most {} besides the main bus", networks.len(), MAX_NETWORKS));
+    }
+    let (mut names, mut interfaces) = (HashSet::new(), HashSet::new());
+    for network in networks {
+        if network.name.is_empty() || network.name.contains('.') {
+            problems.push(format!("Network '{}' needs a name without dots, it's the prefix of its tags", network.name));
+        } else if !names.insert(&network.name) {
+            problems.push(format!("Network '{}' is listed more than once", network.name));
+        }
+        if network.interface.is_empty() {
+            problems.push(format!("Network '{}' has no interface", network.name));
+        } else if !interfaces.insert(&network.interface) {
+            problems.push(format!("Network '{}' is on {}, which another network is on already", network.name, network.interface));
+        }
+        for subdevice in network.subdevices.iter().filter(|subdevice| !subdevice.kbus.is_empty()) {
+            problems.push(format!("Network '{}' lists K-bus terminals behind {}, only the main bus drives those", network.name, subdevice.name));
+        }
+    }
+    problems
+}
+
+/// Starts every network (the first MAX_NETWORKS, check() says so) in a thread of its own, `simulate` on virtual
+/// racks. The threads end once they took their network back to INIT after `shutdown` is set
+pub fn start(networks: &[NetworkConfig], simulate: bool, shutdown: &Arc<AtomicBool>) -> Vec<JoinHandle<()>> {
+    let mut threads = Vec::new();
+    for config in networks.iter().take(MAX_NETWORKS) {
+        let network = Arc::new(Network {
+            config: config.clone(),
+            tags: layout(config).into_iter().map(|(tag, source)| (tag.name, source)).collect(),
+            term_states: OnceLock::new(),
+            status: Mutex::new(Status::default()),
+        });
+        NETWORKS.write().unwrap().push(network.clone());
+
+        let shutdown = shutdown.clone();
+        let thread = std::thread::Builder::new()
+        .name(format!("Network {}", config.name))
+        .spawn(move || {
+            let maindevice = if simulate {
+                VirtualBus::new(virtual_rack(&network.config)).start_maindevice()
+            } else {
+                ctrl_loop::start_maindevice(&network.config.interface)
+            };
+            let name = network.config.name.clone();
+            if let Err(e) = smol::block_on(run(&maindevice, &network, shutdown)) {
+                log::error!(network = name.as_str(); "Network {} stopped: {}", name, e);
+            }
+            network.status.lock().unwrap().ok = false;
+        })
+        .expect("build network thread");
+        threads.push(thread);
+    }
+    threads
+}
+
+// One network: to OP once it matches its config, cycles until `shutdown`, back to INIT
+async fn run(maindevice: &MainDevice<'_>, network: &Network, shutdown: Arc<AtomicBool>) -> Result<(), String> {
+    let config = &network.config;
+    let group = maindevice.init_single_group::<MAX_SUBDEVICES, PDI_LEN>(ethercat_now).await
+        .map_err(|e| format!("init on {} failed: {:?}", config.interface, e))?;
+    let found: Vec<String> = group.iter(maindevice).map(|sd| sd.name().to_owned()).collect();
+    let expected: Vec<&str> = config.subdevices.iter().map(|sd| sd.name.as_str()).collect();
+    if !expected.is_empty() && found != expected {
+        return Err(format!("{} has {:?}, [[networks]] lists {:?}", config.interface, found, expected));
+    }
+    let mut term_states = TermStates::new();
+    ctrl_loop::configure_pre_op(&group, maindevice, &mut term_states).await.map_err(|e| e.to_string())?;
+    let group = group.into_op(maindevice).await.map_err(|e| format!("PRE-OP -> OP failed: {:?}", e))?;
+
+    // the terminals its tags read and write, in bus order like layout() counts them, and where their bits are
+    let io: Vec<Option<(Kind, usize, PdiRange)>> = group.iter(maindevice).map(|sd| {
+        let io = sd.io_raw();
+        let (inputs, outputs) = (io.inputs().len(), io.outputs().len());
+        match kind(sd.name()) {
+            Some((Kind::Di, _)) => {
+                term_states.ebus_di_terms.push(Arc::new(RwLock::new(DITerm::new(8 * inputs as u8))));
+                Some((Kind::Di, term_states.ebus_di_terms.len() - 1, PdiRange::image(inputs)))
+            }
+            Some((Kind::Do, _)) => {
+                term_states.ebus_do_terms.push(Arc::new(RwLock::new(DOTerm::new(8 * outputs as u8))));
+                Some((Kind::Do, term_states.ebus_do_terms.len() - 1, PdiRange::image(outputs)))
+            }
+            Some((Kind::Ai, _)) => {
+                term_states.ebus_ai_terms.push(Arc::new(RwLock::new(AITerm::new((inputs / 4) as u8))));
+                Some((Kind::Ai, term_states.ebus_ai_terms.len() - 1, PdiRange::image(inputs)))
+            }
+            None => None,
+        }
+    }).collect();
+    // shared as it is from here on, see TermStates
+    let term_states = network.term_states.get_or_init(|| term_states);
+    let expected_wkc = diagnostics::expected_wkc(group.iter(maindevice).map(|sd| {
+        let io = sd.io_raw();
+        (io.inputs().len(), io.outputs().len())
+    }));
+    log::info!(network = config.name.as_str(); "Network {} on {}: {} SubDevices in OP", config.name, config.interface, group.len());
+
+    let cycle_time = Duration::from_micros(config.cycle_us);
+    let mut cycle_stats = CycleStats::default();
+    let (mut last_cycle, mut next_cycle) = (Instant::now(), Instant::now());
+    let mut failing = false;
+    while !shutdown.load(Ordering::Relaxed) {
+        if !cycle_time.is_zero() {
+            Timer::at(next_cycle).await;
+            next_cycle = (next_cycle + cycle_time).max(Instant::now());
+        }
+        // unlike the main bus's, a network failing doesn't stop the PLC: its tags say so and it keeps trying
+        let response = match group.tx_rx(maindevice).await {
+            Ok(response) => response,
+            Err(e) => {
+                if !failing {
+                    log::error!(network = config.name.as_str(); "Network {} TX/RX failed: {:?}", config.name, e);
+                }
+                failing = true;
+                network.status.lock().unwrap().ok = false;
+                Timer::after(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        if failing {
+            log::info!(network = config.name.as_str(); "Network {} is back", config.name);
+            failing = false;
+        }
+        let now = Instant::now();
+        cycle_stats.record(now - last_cycle, response.working_counter, expected_wkc);
+        last_cycle = now;
+
+        for (sd, io) in group.iter(maindevice).zip(&io) {
+            match io {
+                Some((Kind::Di, term, range)) => term_states.ebus_di_terms[*term].write().expect("get DI term write guard")
+                    .refresh(range.view(sd.inputs_raw().view_bits::<Lsb0>())),
+                Some((Kind::Do, term, range)) => term_states.ebus_do_terms[*term].read().expect("get DO term read guard")
+                    .refresh(range.view_mut(sd.outputs_raw_mut().view_bits_mut::<Lsb0>())),
+                Some((Kind::Ai, term, range)) => term_states.ebus_ai_terms[*term].write().expect("get AI term write guard")
+                    .refresh(range.view(sd.inputs_raw().view_bits::<Lsb0>())),
+                None => {}
+            }
+        }
+        *network.status.lock().unwrap() = Status {
+            ok: response.working_counter == expected_wkc,
+            cycle: cycle_stats.last,
+            wkc_errors: cycle_stats.wkc_errors,
+        };
+    }
+
+    network.status.lock().unwrap().ok = false;
+    let group = group.into_safe_op(maindevice).await.map_err(|e| format!("OP -> SAFE-OP failed: {:?}", e))?;
+    let group = group.into_pre_op(maindevice).await.map_err(|e| format!("SAFE-OP -> PRE-OP failed: {:?}", e))?;
+    let _group = group.into_init(maindevice).await.map_err(|e| format!("PRE-OP -> INIT failed: {:?}", e))?;
+    log::info!(network = config.name.as_str(); "Network {} back in INIT", config.name);
+    Ok(())
+}
+
+/// The networks' tags as they are now, `index` finds a tag in the PLC's list. Channels of a network that hasn't
+/// reached OP are left out
+pub fn values(index: impl Fn(&str) -> Option<usize>) -> Vec<(usize, TagValue)> {
+    let mut values = Vec::new();
+    for network in NETWORKS.read().unwrap().iter() {
+        let (ok, cycle, wkc_errors) = {
+            let status = network.status.lock().unwrap();
+            (status.ok, status.cycle, status.wkc_errors)
+        };
+        let none = TermStates::new();
+        let term_states = network.term_states.get().unwrap_or(&none);
+        for (name, source) in &network.tags {
+            let value = match *source {
+                Source::Ok => Some(TagValue::Bool(ok)),
+                Source::CycleTime => Some(TagValue::Float32(cycle.as_secs_f32() * 1000.0)),
+                Source::WkcErrors => Some(TagValue::UInt32(wkc_errors.min(u32::MAX as u64) as u32)),
+                Source::Di(terminal, ch) => term_states.ebus_di_terms.get(terminal)
+                    .and_then(|term| term.read().expect("get DI term read guard").values.get(ch).map(|bit| TagValue::Bool(*bit))),
+                Source::Do(terminal, ch) => term_states.ebus_do_terms.get(terminal)
+                    .and_then(|term| term.read().expect("get DO term read guard").values.get(ch).map(|bit| TagValue::Bool(*bit))),
+                Source::Ai(terminal, ch) => term_states.ebus_ai_terms.get(terminal)
+                    .and_then(|term| term.read().expect("get AI term read guard").read(Some(ChannelInput::Index(ch as u8))).ok()?.pick_current())
+                    .map(TagValue::Float32),
+            };
+            if let Some(value) = value && let Some(idx) = index(name) {
+                values.push((idx, value));
+            }
+        }
+    }
+    values
+}
+
+/// A write to tag `name`: true if it's a network's output, which goes out on its next cycle
+pub fn write(name: &str, value: TagValue) -> bool {
+    for network in NETWORKS.read().unwrap().iter() {
+        let Some(&(_, Source::Do(terminal, ch))) = network.tags.iter().find(|(tag, _)| tag == name) else { continue };
+        match network.term_states.get().and_then(|term_states| term_states.ebus_do_terms.get(terminal)) {
+            Some(term) => {
+                let mut term = term.write().expect("get DO term write guard");
+                if ch < term.values.len() {
+                    term.values.set(ch, value.as_f64() != 0.0);
+                }
+            }
+            None => log::warn!(network = network.config.name.as_str(); "Ignoring write to '{}', network {} isn't in OP", name, network.config.name),
+        }
+        return true;
+    }
+    false
+}
+
+// The SubDevices of `network` the virtual bus has, for --simulate
+fn virtual_rack(network: &NetworkConfig) -> Vec<VirtualSubDevice> {
+    network.subdevices.iter()
+        .filter_map(|subdevice| match subdevice.name.as_str() {
+            "EK1100" => Some(VirtualSubDevice::ek1100()),
+            "EL1889" => Some(VirtualSubDevice::el1889()),
+            "EL2889" => Some(VirtualSubDevice::el2889()),
+            "EL3024" => Some(VirtualSubDevice::el3024()),
+            name => {
+                log::warn!(network = network.name.as_str(); "Network {} simulated without its {}, the virtual bus has none", network.name, name);
+                None
+            }
+        })
+        .collect()
+}
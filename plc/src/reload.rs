@@ -0,0 +1,136 @@
+// Config reload: on SIGHUP or an ITEM_RELOAD_CONFIG command (the gateway's POST /api/config/reload, the OPC UA
+// server's ReloadConfig) gipop.toml is read again and what can change under a running bus is applied, the bus stays
+// in OP:
+//
+// [modbus] registers                  the register map, scaling and formats included
+// [watchdog] hmi_timeout_ms
+// [time_sync] interval_ms, max_offset_ms   the limit of the clock alarm
+// [blackbox] tags                     the key tags recorded
+// [logging] level                     see gipop_shm::logging
+// [users]                             who may send which commands, see gipop_shm::users
+//
+// A reload that changes anything else is rejected whole, nothing of it applied, naming what changed: [hardware] and
+// [[networks]] would have a bus brought up again, the listeners, files and buffers ([grpc], [modbus] listen and
+// unit_id, [audit], the rest of [blackbox] and [time_sync]) are only set up at start. Restart gipop_plc for those. Tag
+// scaling and alarm limits are part of the tag list (tags.rs) and compiled in. Either way the outcome is logged and
+// sent to consumers as a PLC diagnostic.
+use std::sync::Mutex;
+
+use gipop_shm::blob::BLOB_DIAGNOSTIC;
+use gipop_shm::Users;
+
+use crate::config::PlcConfig;
+use crate::logic::{self, LOCAL_PLC_DATA};
+use crate::{blackbox, time_sync};
+#[cfg(feature = "modbus")]
+use crate::modbus;
+
+static RUNNING: Mutex<Option<(String, PlcConfig)>> = Mutex::new(None); // path, what's applied
+
+/// Remembers what the PLC started with and reloads on SIGHUP from now on
+pub fn start(path: &str, config: &PlcConfig) {
+    *RUNNING.lock().unwrap() = Some((path.to_owned(), config.clone()));
+    gipop_shm::logging::on_sighup(|| report(reload()));
+}
+
+/// An ITEM_RELOAD_CONFIG, the same as a SIGHUP: the log levels, then the rest
+pub fn on_command() {
+    if let Err(e) = gipop_shm::logging::reload() {
+        log::error!("Log levels not reloaded: {}", e);
+    }
+    report(reload());
+}
+
+/// Reads the config again and applies it, with what changed. Err with why if any of it can't be applied live
+pub fn reload() -> Result<Vec<String>, String> {
+    let mut running = RUNNING.lock().unwrap();
+    let Some((path, current)) = running.as_mut() else {
+        return Err("No config to reload, the PLC isn't running".to_owned());
+    };
+    let config = PlcConfig::load(&*path)?;
+    let users = Users::load(&*path)?;
+    let restart = needs_restart(current, &config);
+    if !restart.is_empty() {
+        return Err(format!("{} not reloaded, {}", path, restart.join("; ")));
+    }
+
+    let mut applied = Vec::new();
+    // first, the only part that can still be refused
+    #[cfg(feature = "modbus")]
+    if config.modbus.registers != current.modbus.registers {
+        modbus::remap(&config.modbus).map_err(|e| format!("{} not reloaded, {}", path, e))?;
+        applied.push(format!("[modbus] {} registers", config.modbus.registers.len()));
+    }
+    if config.watchdog != current.watchdog {
+        LOCAL_PLC_DATA.lock().unwrap().hmi_timeout = config.watchdog.hmi_timeout();
+        applied.push(format!("[watchdog] hmi_timeout_ms = {}", config.watchdog.hmi_timeout_ms));
+    }
+    if config.time_sync != current.time_sync {
+        time_sync::set_limits(&config.time_sync);
+        applied.push(format!("[time_sync] interval_ms = {}, max_offset_ms = {}", config.time_sync.interval_ms, config.time_sync.max_offset_ms));
+    }
+    if config.blackbox != current.blackbox {
+        blackbox::set_tags(config.blackbox.tags.clone());
+        applied.push(format!("[blackbox] tags = {:?}", config.blackbox.tags));
+    }
+    {
+        let mut plc_data = LOCAL_PLC_DATA.lock().unwrap();
+        if users != plc_data.users {
+            plc_data.users = users;
+            applied.push("[users]".to_owned());
+        }
+    }
+    *current = config;
+    Ok(applied)
+}
+
+/// Logs how a reload went and tells the consumers
+pub fn report(reloaded: Result<Vec<String>, String>) {
+    let message = match reloaded {
+        Ok(applied) if applied.is_empty() => {
+            log::info!("Config reloaded, nothing changed");
+            return;
+        }
+        Ok(applied) => {
+            log::info!("Config reloaded: {}", applied.join(", "));
+            format!("Config reloaded: {}", applied.join(", "))
+        }
+        Err(e) => {
+            log::error!("{}", e);
+            e
+        }
+    };
+    logic::queue_blob(BLOB_DIAGNOSTIC, message.into_bytes());
+}
+
+// What changed that only a restart (or bringing the bus up again) takes
+fn needs_restart(current: &PlcConfig, config: &PlcConfig) -> Vec<String> {
+    let mut restart = Vec::new();
+    if config.hardware != current.hardware {
+        restart.push("[hardware] changed, the bus would have to be brought up again".to_owned());
+    }
+    if config.networks != current.networks {
+        restart.push("[[networks]] changed".to_owned());
+    }
+    if config.grpc != current.grpc {
+        restart.push("[grpc] changed".to_owned());
+    }
+    let server = |config: &PlcConfig| (config.modbus.enabled, config.modbus.listen.clone(), config.modbus.unit_id);
+    if server(config) != server(current) {
+        restart.push("[modbus] enabled, listen or unit_id changed".to_owned());
+    }
+    if config.audit != current.audit {
+        restart.push("[audit] changed".to_owned());
+    }
+    let buffers = |config: &PlcConfig| (config.blackbox.dir.clone(), config.blackbox.cycles, config.blackbox.events);
+    if buffers(config) != buffers(current) {
+        restart.push("[blackbox] dir, cycles or events changed".to_owned());
+    }
+    if (config.time_sync.enabled, config.time_sync.source) != (current.time_sync.enabled, current.time_sync.source) {
+        restart.push("[time_sync] enabled or source changed".to_owned());
+    }
+    if !restart.is_empty() {
+        restart.push("restart gipop_plc for those".to_owned());
+    }
+    restart
+}
@@ -0,0 +1,181 @@
+// gipop_plc scan <interface> (and --dry-run), what's on the bus without running the PLC: no IPC, no logic, no OPC UA. Brings the bus
+// to PRE-OP, configures it like a PLC start does (ctrl_loop::configure_pre_op) and has ethercrab map the PDI, then
+// prints every SubDevice with its address and PDI sizes, the K-bus terminals each BK1120 lists in 0x4012, and where
+// all of it lands in the process image.
+//
+// ethercrab lays the PDI out as every SubDevice's inputs in bus order followed by every SubDevice's outputs, offsets
+// print as byte.bit into it. K-bus terminals show at the slot ranges ctrl_loop::set_slot_idx_range gives them, i.e.
+// where the PLC will look for them, followed by whatever hal::term_cfg::kbus_layout_problems finds wrong with them.
+// The SubDevices are left in PRE-OP, the next PLC start takes them from there.
+use anyhow::Result;
+use ethercrab::std::ethercat_now;
+use ethercrab::MainDevice;
+use hal::io_defs::TermStates;
+use hal::term_cfg::{kbus_layout_problems, KBUS_CTRL_BITS};
+
+use crate::config::HardwareConfig;
+use crate::ctrl_loop::{self, MAX_SUBDEVICES, PDI_LEN};
+
+/// The bus brought up to PRE-OP with its PDI mapped, what scan prints and check-config checks
+pub struct Bus {
+    pub subdevices: Vec<Mapped>,
+    pub couplers: Vec<(u16, Vec<u16>)>, // address, its 0x4012 entries
+    pub term_states: TermStates,
+}
+
+pub struct Mapped {
+    pub name: String,
+    pub address: u16,
+    pub inputs: usize, // bytes
+    pub outputs: usize,
+}
+
+pub async fn bring_up(maindevice: &MainDevice<'_>) -> Result<Bus> {
+    let group = maindevice.init_single_group::<MAX_SUBDEVICES, PDI_LEN>(ethercat_now).await?;
+
+    // 0x4012 as each coupler lists it, configure_pre_op only keeps the terminals it recognizes
+    let mut couplers = Vec::new();
+    for sd in group.iter(maindevice) {
+        if sd.name() == "BK1120" {
+            let count: u8 = sd.sdo_read(0x4012, 0).await?;
+            let mut entries = Vec::new();
+            for idx in 1..count+1 {
+                entries.push(sd.sdo_read::<u16>(0x4012, idx).await?);
+            }
+            couplers.push((sd.configured_address(), entries));
+        }
+    }
+
+    let mut term_states = TermStates::new();
+    ctrl_loop::configure_pre_op(&group, maindevice, &mut term_states).await?;
+    let group = group.into_pre_op_pdi(maindevice).await?; // PDI mapped, SubDevices still in PRE-OP
+
+    let subdevices = group.iter(maindevice)
+        .map(|sd| {
+            let io = sd.io_raw();
+            Mapped { name: sd.name().to_owned(), address: sd.configured_address(), inputs: io.inputs().len(), outputs: io.outputs().len() }
+        })
+        .collect();
+    Ok(Bus { subdevices, couplers, term_states })
+}
+
+impl Bus {
+    /// kbus_layout_problems of the K-bus terminals behind the (one) BK1120, against its images
+    pub fn kbus_problems(&self) -> Vec<String> {
+        let Some(coupler) = self.subdevices.iter().find(|sd| sd.name == "BK1120") else { return Vec::new() };
+        let terms: Vec<_> = self.term_states.kbus_terms.iter().map(|term| term.read().expect("get K-bus term read guard")).collect();
+        kbus_layout_problems(&terms.iter().map(|term| &**term).collect::<Vec<_>>(), Some((coupler.inputs * 8, coupler.outputs * 8)))
+    }
+
+    /// Where the bus differs from [hardware], nothing if that doesn't list any SubDevices
+    pub fn hardware_problems(&self, expected: &HardwareConfig) -> Vec<String> {
+        let mut problems = Vec::new();
+        if expected.subdevices.is_empty() {
+            return problems;
+        }
+        if expected.subdevices.len() != self.subdevices.len() {
+            problems.push(format!("[hardware] lists {} SubDevices, the bus has {}", expected.subdevices.len(), self.subdevices.len()));
+        }
+        for (pos, (want, found)) in expected.subdevices.iter().zip(&self.subdevices).enumerate() {
+            let what = if want.label.is_empty() { format!("SubDevice {}", pos + 1) } else { format!("SubDevice {} ({})", pos + 1, want.label) };
+            if want.name != found.name {
+                problems.push(format!("{} should be a {}, the bus has a {} there (0x{:04x})", what, want.name, found.name, found.address));
+                continue;
+            }
+            let Some((_, entries)) = self.couplers.iter().find(|(address, _)| *address == found.address) else {
+                if !want.kbus.is_empty() {
+                    problems.push(format!("{} has K-bus terminals in [hardware], but only a BK1120 can have them", what));
+                }
+                continue;
+            };
+            // the coupler lists itself first
+            let own_number = found.name.trim_start_matches(|c: char| c.is_ascii_alphabetic()).parse::<u16>().ok();
+            let terms = if entries.first().copied() == own_number { &entries[1..] } else { &entries[..] };
+            if want.kbus.len() != terms.len() {
+                problems.push(format!("{} should have {} K-bus terminals, it has {}", what, want.kbus.len(), terms.len()));
+            }
+            for (slot, (want, word)) in want.kbus.iter().zip(terms).enumerate() {
+                if !kbus_matches(want, *word) {
+                    problems.push(format!("{} K-bus terminal {} should be a {}, the coupler reports {}", what, slot + 1, want, kbus_entry(*word)));
+                }
+            }
+        }
+        problems
+    }
+}
+
+/// Lists the bus on `maindevice`, `on` says where that is ("eth0")
+pub async fn scan(maindevice: &MainDevice<'_>, on: &str) -> Result<()> {
+    let bus = bring_up(maindevice).await?;
+    println!("{} SubDevices on {}, PRE-OP", bus.subdevices.len(), on);
+
+    println!("\nADDR    NAME              IN   OUT (bytes)");
+    for sd in &bus.subdevices {
+        println!("{:<8}{:<18}{:<5}{}", format!("0x{:04x}", sd.address), sd.name, sd.inputs, sd.outputs);
+        for (idx, word) in bus.couplers.iter().filter(|(address, _)| *address == sd.address).flat_map(|(_, entries)| entries.iter().enumerate()) {
+            println!("          0x4012:{:<3}0x{:04x}  {}", idx + 1, word, kbus_entry(*word));
+        }
+    }
+
+    let (total_in, total_out) = bus.subdevices.iter().fold((0, 0), |(i, o), sd| (i + sd.inputs, o + sd.outputs));
+    println!("\nProcess image, {} of {} bytes: inputs {}, outputs {}", total_in + total_out, PDI_LEN, total_in, total_out);
+    {
+        let kbus_terms: Vec<_> = bus.term_states.kbus_terms.iter().map(|term| term.read().expect("get K-bus term read guard")).collect();
+        for (output, mut offset) in [(false, 0), (true, total_in)] {
+            for sd in &bus.subdevices {
+                let len = if output { sd.outputs } else { sd.inputs };
+                if len == 0 {
+                    continue;
+                }
+                println!("{:<4}{:<14}{} (0x{:04x})", if output { "OUT" } else { "IN" }, bits(offset * 8, len * 8), sd.name, sd.address);
+                if sd.name == "BK1120" {
+                    println!("      {:<14}K-bus {} word", bits(offset * 8, KBUS_CTRL_BITS), if output { "control" } else { "status" });
+                    for term in kbus_terms.iter().filter(|term| term.in_image(output)) {
+                        let (begin, end) = term.slot_idx_range;
+                        println!("      {:<14}{}", bits(offset * 8 + begin as usize, end.saturating_sub(begin) as usize + 1), term.label());
+                    }
+                }
+                offset += len;
+            }
+        }
+    }
+
+    let problems = bus.kbus_problems();
+    if !problems.is_empty() {
+        println!("\nK-bus layout problems, the PLC would read or write the wrong bits:");
+        for problem in problems {
+            println!("  {}", problem);
+        }
+    }
+    Ok(())
+}
+
+// A 0x4012 entry: an intelligent terminal (or the coupler) by its number, a simple one by the size and direction
+// encoded in it, see ctrl_loop::parse_term
+fn kbus_entry(word: u16) -> String {
+    if word & 0x8000 == 0 {
+        return format!("{}, intelligent", word);
+    }
+    let direction = match word & 0b11 {
+        0b01 => "input",
+        0b10 => "output",
+        _ => "input/output",
+    };
+    format!("simple {}, {} bits", direction, ctrl_loop::simple_term_bits(word))
+}
+
+// A 0x4012 entry against the terminal type expected there. Intelligent terminals report their number, simple ones
+// only their direction: KL1xxx are inputs, KL2xxx outputs
+fn kbus_matches(expected: &str, word: u16) -> bool {
+    let number = expected.trim_start_matches(|c: char| c.is_ascii_alphabetic());
+    if word & 0x8000 == 0 {
+        return number.parse::<u16>().ok() == Some(word);
+    }
+    matches!((number.chars().next(), word & 0b11), (Some('1'), 0b01) | (Some('2'), 0b10))
+}
+
+// `len` bits from bit `start` of the PDI as byte.bit-byte.bit
+fn bits(start: usize, len: usize) -> String {
+    let end = start + len - 1;
+    format!("{}.{}-{}.{}", start / 8, start % 8, end / 8, end % 8)
+}
@@ -0,0 +1,70 @@
+// Tags the PLC publishes through the shm region. This is the only list to touch when adding a tag,
+// consumers (OPC UA server etc.) discover them from the region's tag directory. The OPC UA server and gipop_sim also
+// compile this file in to simulate the PLC when there's none (gipop_shm::sim), so it may only depend on gipop_shm.
+// Names double as OPC UA node ids, so renaming one breaks HMI bindings. Folders only decide where the node shows up
+// in the address space and can be reorganized freely, as can the display names and descriptions (TagDef::text), which
+// OPC UA clients get in their own language when it's there. English first, it's the default.
+use gipop_shm::{TagDef, TagType, TAG_WRITABLE, TOTALS_FOLDER};
+
+pub const TEMPERATURE: &str = "temperature";
+pub const HUMIDITY: &str = "humidity";
+pub const STATUS: &str = "status";
+pub const AREA_1_LIGHTS: &str = "area 1 lights";
+pub const AREA_2_LIGHTS: &str = "area 2 lights";
+pub const AREA_1_LIGHTS_HMI_CMD: &str = "area 1 lights hmi cmd"; // incoming to PLC
+pub const HMI_ALIVE: &str = "hmi alive"; // some consumer's heartbeat is moving, see gipop_shm::heartbeat
+pub const RUNNING: &str = "running"; // logic running, false after an operator stopped it
+pub const SCAN_TIME: &str = "scan time"; // ms, last pass of the control loop
+pub const FORCED_CHANNELS: &str = "forced channels"; // how many outputs are forced right now
+pub const ENOCEAN_TELEGRAMS: &str = "enocean telegrams"; // total, reset by operators
+pub const CLOCK_SYNCED: &str = gipop_shm::time_sync::SYNCED_TAG; // host clock in step with NTP/PTP, see [time_sync]
+pub const CLOCK_OFFSET: &str = "clock offset"; // ms from the time reference
+pub const CLOCK_MAX_ERROR: &str = "clock max error"; // ms, how far off the clock can be at worst
+
+// External, written by gipop_opcua_client from other OPC UA servers ([opcua_client] in gipop.toml)
+pub const CHILLER_SUPPLY_TEMP: &str = "chiller supply temp";
+
+// Alarms, true while active. The name is what operators see as the alarm message.
+pub const KL6581_FAULT: &str = "EnOcean master KL6581 reports an error";
+pub const HMI_WATCHDOG_TRIPPED: &str = "HMI lost, area 1 lights back to local control";
+pub const CLOCK_UNSYNCED: &str = "Clock not synchronized, timestamps can't be trusted";
+
+pub fn plc_tags() -> Vec<TagDef> {
+    vec![
+        // 0-10 V transmitters on the EL3024, spanning 0-50 °C and 0-100 %RH
+        TagDef::new(TEMPERATURE, TagType::Float32, 0).analog("°C", 10.0, 35.0).instrument_range(0.0, 50.0).in_folder("Environment")
+            .text("en", "Temperature", "Room air temperature")
+            .text("ms", "Suhu", "Suhu udara bilik"),
+        TagDef::new(HUMIDITY, TagType::Float32, 0).analog("%RH", 0.0, 100.0).instrument_range(0.0, 100.0).in_folder("Environment")
+            .text("en", "Humidity", "Relative humidity of the room air")
+            .text("ms", "Kelembapan", "Kelembapan relatif udara bilik"),
+        TagDef::new(STATUS, TagType::UInt32, 0).in_folder("System"),
+        TagDef::new(AREA_1_LIGHTS, TagType::UInt32, 0).in_folder("Area 1/Lights")
+            .text("en", "Area 1 lights", "Lights in area 1, on or off")
+            .text("ms", "Lampu kawasan 1", "Lampu di kawasan 1, hidup atau mati"),
+        TagDef::new(AREA_2_LIGHTS, TagType::UInt32, 0).in_folder("Area 2/Lights")
+            .text("en", "Area 2 lights", "Lights in area 2, on or off")
+            .text("ms", "Lampu kawasan 2", "Lampu di kawasan 2, hidup atau mati"),
+        TagDef::new(AREA_1_LIGHTS_HMI_CMD, TagType::UInt32, TAG_WRITABLE).in_folder("Area 1/Lights")
+            .text("en", "Area 1 lights command", "Switches the area 1 lights from the HMI")
+            .text("ms", "Arahan lampu kawasan 1", "Menghidupkan atau mematikan lampu kawasan 1 dari HMI"),
+        TagDef::new(HMI_ALIVE, TagType::Bool, 0).in_folder("System"),
+        TagDef::new(RUNNING, TagType::Bool, 0).in_folder("System"),
+        TagDef::new(SCAN_TIME, TagType::Float32, 0).analog("ms", 0.0, 10.0).in_folder("System"),
+        TagDef::new(FORCED_CHANNELS, TagType::UInt32, 0).in_folder("System"),
+        TagDef::new(CLOCK_SYNCED, TagType::Bool, 0).in_folder("System/Clock"),
+        TagDef::new(CLOCK_OFFSET, TagType::Float32, 0).analog("ms", -10.0, 10.0).in_folder("System/Clock"),
+        TagDef::new(CLOCK_MAX_ERROR, TagType::Float32, 0).analog("ms", 0.0, 100.0).in_folder("System/Clock"),
+        TagDef::new(ENOCEAN_TELEGRAMS, TagType::UInt32, 0).in_folder(TOTALS_FOLDER),
+        TagDef::external(CHILLER_SUPPLY_TEMP, TagType::Float32).analog("°C", 0.0, 20.0).in_folder("External/Chiller"),
+        TagDef::alarm(KL6581_FAULT, 700).in_folder("Alarms")
+            .text("en", "EnOcean master fault", "The KL6581 EnOcean master reports an error")
+            .text("ms", "Kerosakan induk EnOcean", "Induk EnOcean KL6581 melaporkan ralat"),
+        TagDef::alarm(HMI_WATCHDOG_TRIPPED, 500).in_folder("Alarms")
+            .text("en", "HMI lost", "HMI lost, area 1 lights back to local control")
+            .text("ms", "HMI terputus", "HMI terputus, lampu kawasan 1 kembali ke kawalan tempatan"),
+        TagDef::alarm(CLOCK_UNSYNCED, 500).in_folder("Alarms")
+            .text("en", "Clock not synchronized", "The host clock isn't synchronized (NTP/PTP), timestamps can't be trusted")
+            .text("ms", "Jam tidak disegerakkan", "Jam hos tidak disegerakkan (NTP/PTP), cap masa tidak boleh dipercayai"),
+    ]
+}
@@ -0,0 +1,51 @@
+// Host clock monitoring ([time_sync] in gipop.toml). Every interval_ms the sync state is read (see
+// gipop_shm::time_sync) and left in LOCAL_PLC_DATA for the control loop to publish as the System/Clock tags, along
+// with an alarm while the clock isn't synchronized within max_offset_ms. Consumers that store timestamps flag what
+// they record meanwhile, and the alarm's transitions mark the stretch in every event history.
+//
+// On its own thread, chronyc can take its time and the control loop mustn't wait for it. interval_ms and max_offset_ms
+// change with a config reload (set_limits), the source takes a restart.
+use std::sync::RwLock;
+use std::time::Duration;
+
+use gipop_shm::time_sync;
+
+use crate::config::TimeSyncConfig;
+use crate::logic::LOCAL_PLC_DATA;
+
+static LIMITS: RwLock<(u64, f64)> = RwLock::new((10_000, 10.0)); // interval_ms, max_offset_ms
+
+pub fn start(config: TimeSyncConfig) {
+    set_limits(&config);
+    let spawned = std::thread::Builder::new().name("time_sync".into()).spawn(move || {
+        let mut last: Option<Result<bool, String>> = None; // for logging changes only
+        loop {
+            let (interval_ms, max_offset_ms) = *LIMITS.read().unwrap();
+            let reading = time_sync::query(config.source);
+            let state = reading.as_ref().map(|clock| clock.within(max_offset_ms)).map_err(Clone::clone);
+            if last.as_ref() != Some(&state) {
+                match &reading {
+                    Ok(clock) if state == Ok(true) => log::info!("Clock synchronized, {:.3} ms off, max error {:.3} ms", clock.offset_ms, clock.max_error_ms),
+                    Ok(clock) if clock.synced => log::warn!("Clock {:.3} ms off, more than the {} ms allowed", clock.offset_ms, max_offset_ms),
+                    Ok(_) => log::warn!("Clock not synchronized, timestamps can't be trusted"),
+                    Err(e) => log::warn!("Can't tell whether the clock is synchronized: {}", e),
+                }
+                last = Some(state.clone());
+            }
+            {
+                let mut plc_data = LOCAL_PLC_DATA.lock().unwrap();
+                plc_data.clock = reading.ok();
+                plc_data.clock_unsynced = state != Ok(true);
+            }
+            std::thread::sleep(Duration::from_millis(interval_ms.max(1)));
+        }
+    });
+    if let Err(e) = spawned {
+        log::error!("Can't start the time sync monitor: {}", e);
+    }
+}
+
+/// How often the clock is checked and how far off it may be from now on
+pub fn set_limits(config: &TimeSyncConfig) {
+    *LIMITS.write().unwrap() = (config.interval_ms, config.max_offset_ms);
+}
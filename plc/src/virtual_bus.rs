@@ -0,0 +1,783 @@
+// Stand-in for the EtherCAT bus: a chain of emulated SubDevices answering the frames ethercrab sends, so ctrl_loop's
+// init, PRE-OP mailbox setup and cyclic exchange run in CI with no NIC and no rack. See the tests at the end of
+// ctrl_loop.rs, reference_rack() is the rack ctrl_loop is written for and what `gipop_plc --simulate` runs on, for
+// developing logic and consumers away from the hardware (its inputs stay 0 there).
+//
+// The emulation is at the ESC level, the way a real chain handles a frame: every SubDevice has its registers and
+// process RAM, SII (EEPROM) contents describing it, a CoE object dictionary behind its mailbox and FMMUs mapping the
+// logical process image onto its RAM. What ethercrab needs and little more: position, configured, broadcast and
+// logical addressing with working counters, AL state requests (granted at once), SII reads, expedited and normal SDO
+// upload/download including complete access. Not there: DC (the ESCs report no support), EoE/FoE, bit aligned FMMUs,
+// SM watchdogs and error counters (they read 0).
+//
+// Inputs are what the field presents to a SubDevice (VirtualBus::set_inputs), outputs what the PLC last sent it in OP
+// (VirtualBus::outputs), both by bus position. Mailbox SubDevices size their process data from their PDO assignment
+// when asked for SAFE-OP, like the real ones, so writes to 0x1C12/0x1C13 in PRE-OP change the image.
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use ethercrab::{MainDevice, PduStorage};
+use hal::diagnostics::{REG_AL_STATUS, REG_AL_STATUS_CODE};
+
+use crate::ctrl_loop::{self, MAX_FRAMES, MAX_PDU_DATA};
+
+const ETHERCAT_ETHERTYPE: u16 = 0x88a4;
+const POLL_INTERVAL: Duration = Duration::from_micros(50); // how often the bus thread looks for frames to answer
+
+const ESC_MEMORY: usize = 0x2000; // registers up to 0x0fff, process RAM after
+const REG_STATION_ADDRESS: usize = 0x0010;
+const REG_DL_STATUS: usize = 0x0110;
+const REG_AL_CONTROL: usize = 0x0120;
+const REG_SII_CONTROL: usize = 0x0502; // low byte config (8 byte reads), high byte command and busy bits
+const REG_SII_ADDRESS: usize = 0x0504; // in words
+const REG_SII_DATA: usize = 0x0508;
+const REG_FMMU: usize = 0x0600; // 16 bytes each
+const REG_SM: usize = 0x0800; // 8 bytes each
+const FMMUS: usize = 8;
+const SM_STATUS_MAILBOX_FULL: u8 = 0x08;
+
+// Where the SII puts each SyncManager, mailbox SubDevices have both mailboxes and then the process data
+const MAILBOX_OUT: u16 = 0x1000; // PLC to SubDevice
+const MAILBOX_IN: u16 = 0x1080;
+const MAILBOX_LEN: u16 = 128;
+const MAILBOX_PD_OUTPUTS: u16 = 0x1100;
+const MAILBOX_PD_INPUTS: u16 = 0x1180;
+const SIMPLE_PD_OUTPUTS: u16 = 0x0f00; // terminals without a mailbox, like the EL1889 and EL2889
+const SIMPLE_PD_INPUTS: u16 = 0x1000;
+
+const AL_INIT: u8 = 1;
+const AL_PRE_OP: u8 = 2;
+const AL_SAFE_OP: u8 = 4;
+const AL_OP: u8 = 8;
+
+const MAILBOX_COE: u8 = 3;
+const COE_SDO_REQUEST: u16 = 2;
+const COE_SDO_RESPONSE: u16 = 3;
+const SDO_ABORT_NO_OBJECT: u32 = 0x0602_0000;
+const SDO_ABORT_NO_SUBINDEX: u32 = 0x0609_0011;
+const SDO_ABORT_LENGTH: u32 = 0x0607_0010;
+const SDO_ABORT_COMMAND: u32 = 0x0504_0001;
+
+const APRD: u8 = 1;
+const APWR: u8 = 2;
+const APRW: u8 = 3;
+const FPRD: u8 = 4;
+const FPWR: u8 = 5;
+const FPRW: u8 = 6;
+const BRD: u8 = 7;
+const BWR: u8 = 8;
+const BRW: u8 = 9;
+const LRD: u8 = 10;
+const LWR: u8 = 11;
+const LRW: u8 = 12;
+const ARMW: u8 = 13;
+const FRMW: u8 = 14;
+
+const BECKHOFF: u32 = 0x0000_0002;
+const KL6581_IMAGE_LEN: usize = 12; // bytes in each direction
+const KBUS_CTRL_LEN: usize = 2; // the BK1120's status word in front of its inputs, control word in front of its outputs
+
+/// A terminal behind a virtual BK1120, in the order they sit on the K-bus
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KBusTerminal {
+    Input(u8), // simple digital input terminal with that many channels, e.g. a KL1889 is Input(16)
+    Output(u8),
+    Kl6581,
+}
+
+pub const KL1889: KBusTerminal = KBusTerminal::Input(16);
+pub const KL2889: KBusTerminal = KBusTerminal::Output(16);
+
+impl KBusTerminal {
+    /// What the BK1120 reports for the terminal in 0x4012: the KL number of intelligent ones, 0x8000 + size in bits
+    /// from bit 8 + direction in bits 0-1 for simple ones
+    pub fn code(self) -> u16 {
+        match self {
+            KBusTerminal::Input(bits) => 0x8000 | (bits as u16) << 8 | 0x01,
+            KBusTerminal::Output(bits) => 0x8000 | (bits as u16) << 8 | 0x02,
+            KBusTerminal::Kl6581 => 6581,
+        }
+    }
+}
+
+/// One emulated ESC and what's behind it
+pub struct VirtualSubDevice {
+    memory: Vec<u8>,
+    eeprom: Vec<u8>,
+    objects: BTreeMap<(u16, u8), Vec<u8>>, // CoE object dictionary, empty without a mailbox
+    mailbox: bool,
+    pd_inputs: (u16, usize), // process data SyncManager address and length in bytes
+    pd_outputs: (u16, usize),
+    inputs: Vec<u8>,
+    outputs: Vec<u8>,
+}
+
+impl VirtualSubDevice {
+    pub fn ek1100() -> Self {
+        Self::simple("EK1100", 0x044c_2c52, 0, 0)
+    }
+
+    /// 16 digital inputs, 2 bytes of inputs
+    pub fn el1889() -> Self {
+        Self::simple("EL1889", 0x0761_3052, 2, 0)
+    }
+
+    /// 16 digital outputs, 2 bytes of outputs
+    pub fn el2889() -> Self {
+        Self::simple("EL2889", 0x0b49_3052, 0, 2)
+    }
+
+    /// 4 analog inputs, each a 16 bit status word and a 16 bit value (0x1A00, 0x1A02...) or only the value in the
+    /// compact PDOs (0x1A01, 0x1A03...). Assigned the standard ones to start with
+    pub fn el3024() -> Self {
+        let mut objects = BTreeMap::new();
+        sm_types(&mut objects);
+        assignment(&mut objects, 0x1c12, &[]);
+        assignment(&mut objects, 0x1c13, &[0x1a00, 0x1a02, 0x1a04, 0x1a06]);
+        for channel in 0..4 {
+            let entry = 0x6000 + 0x10 * channel;
+            mapping(&mut objects, 0x1a00 + 2 * channel, &[(entry, 0x01, 16), (entry, 0x11, 16)]);
+            mapping(&mut objects, 0x1a01 + 2 * channel, &[(entry, 0x11, 16)]);
+        }
+        Self::with_mailbox("EL3024", 0x0bd0_3052, objects)
+    }
+
+    /// A BK1120 with `terminals` on its K-bus, images as bk1120_image_len() lays them out
+    pub fn bk1120(terminals: &[KBusTerminal]) -> Self {
+        let mut objects = BTreeMap::new();
+        sm_types(&mut objects);
+        // entry 1 is the coupler itself
+        object(&mut objects, 0x4012, 0, &[terminals.len() as u8 + 1]);
+        object(&mut objects, 0x4012, 1, &1120u16.to_le_bytes());
+        for (slot, terminal) in terminals.iter().enumerate() {
+            object(&mut objects, 0x4012, slot as u8 + 2, &terminal.code().to_le_bytes());
+        }
+
+        for (output, assign, pdo, entry) in [(true, 0x1c12, 0x1600, 0x7000), (false, 0x1c13, 0x1a00, 0x6000)] {
+            assignment(&mut objects, assign, &[pdo]);
+            let words: Vec<_> = (0..bk1120_image_len(terminals, output).div_ceil(2)).map(|word| (entry, word as u8 + 1, 16)).collect();
+            mapping(&mut objects, pdo, &words);
+        }
+        Self::with_mailbox("BK1120", 0x0460_2c22, objects)
+    }
+
+    // A terminal without a mailbox, its process data described in the SII
+    fn simple(name: &str, product: u32, inputs: usize, outputs: usize) -> Self {
+        let mut sms = Vec::new();
+        if outputs > 0 {
+            sms.push((SIMPLE_PD_OUTPUTS, outputs, 0x44, 3));
+        }
+        if inputs > 0 {
+            sms.push((SIMPLE_PD_INPUTS, inputs, 0x00, 4));
+        }
+        let fmmus: Vec<u8> = [(outputs, 1), (inputs, 2)].iter().filter(|(len, _)| *len > 0).map(|&(_, usage)| usage).collect();
+        let pdos = |len: usize, sm: u8, pdo: u16, entry: u16| (len > 0).then_some((pdo, sm, (1..=len * 8).map(|bit| (entry, bit as u8, 1)).collect::<Vec<_>>()));
+        let rx_pdo = pdos(outputs, 0, 0x1600, 0x7000);
+        let tx_pdo = pdos(inputs, (outputs > 0) as u8, 0x1a00, 0x6000);
+        let eeprom = sii(name, product, false, &sms, &fmmus, tx_pdo.as_slice(), rx_pdo.as_slice());
+        Self::new(eeprom, BTreeMap::new(), false, (SIMPLE_PD_INPUTS, inputs), (SIMPLE_PD_OUTPUTS, outputs))
+    }
+
+    // A SubDevice with a CoE mailbox, its process data sized from the PDO assignment in `objects`
+    fn with_mailbox(name: &str, product: u32, objects: BTreeMap<(u16, u8), Vec<u8>>) -> Self {
+        let sms = [
+            (MAILBOX_OUT, MAILBOX_LEN as usize, 0x26, 1),
+            (MAILBOX_IN, MAILBOX_LEN as usize, 0x22, 2),
+            (MAILBOX_PD_OUTPUTS, 0, 0x64, 3),
+            (MAILBOX_PD_INPUTS, 0, 0x20, 4),
+        ];
+        let eeprom = sii(name, product, true, &sms, &[1, 2, 3], &[], &[]);
+        let mut sd = Self::new(eeprom, objects, true, (MAILBOX_PD_INPUTS, 0), (MAILBOX_PD_OUTPUTS, 0));
+        sd.map_process_data();
+        sd
+    }
+
+    fn new(eeprom: Vec<u8>, objects: BTreeMap<(u16, u8), Vec<u8>>, mailbox: bool, pd_inputs: (u16, usize), pd_outputs: (u16, usize)) -> Self {
+        let mut memory = vec![0; ESC_MEMORY];
+        memory[0x0000] = 0x11; // ET1100
+        memory[0x0001] = 0x02;
+        memory[0x0004] = FMMUS as u8;
+        memory[0x0005] = 8; // SyncManagers
+        memory[0x0006] = 8; // KiB of process RAM
+        memory[0x0007] = 0x0f;
+        memory[REG_AL_STATUS as usize] = AL_INIT;
+        memory[REG_SII_CONTROL] = 0x40; // reads 8 bytes at a time
+        Self {
+            memory,
+            eeprom,
+            objects,
+            mailbox,
+            pd_inputs,
+            pd_outputs,
+            inputs: vec![0; pd_inputs.1],
+            outputs: vec![0; pd_outputs.1],
+        }
+    }
+
+    fn station_address(&self) -> u16 {
+        u16::from_le_bytes([self.memory[REG_STATION_ADDRESS], self.memory[REG_STATION_ADDRESS + 1]])
+    }
+
+    fn al_state(&self) -> u8 {
+        self.memory[REG_AL_STATUS as usize] & 0x0f
+    }
+
+    // Process data lengths from the PDO assignment, what the SubDevice checks going to SAFE-OP
+    fn map_process_data(&mut self) {
+        if !self.mailbox {
+            return;
+        }
+        let bytes = |assign: u16| {
+            let count = self.objects.get(&(assign, 0)).map_or(0, |count| count[0]);
+            let bits: usize = (1..=count)
+                .filter_map(|sub| self.objects.get(&(assign, sub)))
+                .map(|pdo| u16::from_le_bytes([pdo[0], pdo[1]]))
+                .map(|pdo| {
+                    let entries = self.objects.get(&(pdo, 0)).map_or(0, |count| count[0]);
+                    (1..=entries).filter_map(|sub| self.objects.get(&(pdo, sub))).map(|entry| entry[0] as usize).sum::<usize>()
+                })
+                .sum();
+            bits.div_ceil(8)
+        };
+        self.pd_outputs.1 = bytes(0x1c12);
+        self.pd_inputs.1 = bytes(0x1c13);
+        self.outputs.resize(self.pd_outputs.1, 0);
+    }
+
+    // The field's inputs into process RAM, from SAFE-OP on
+    fn latch_inputs(&mut self) {
+        if matches!(self.al_state(), AL_SAFE_OP | AL_OP) {
+            let (address, len) = (self.pd_inputs.0 as usize, self.pd_inputs.1.min(self.inputs.len()));
+            self.memory[address..address + len].copy_from_slice(&self.inputs[..len]);
+        }
+    }
+
+    // Process RAM out to the field, in OP only
+    fn latch_outputs(&mut self) {
+        if self.al_state() == AL_OP {
+            let (address, len) = (self.pd_outputs.0 as usize, self.pd_outputs.1);
+            self.outputs.copy_from_slice(&self.memory[address..address + len]);
+        }
+    }
+
+    // A physical read, write or both of `data.len()` bytes from `address`, the working counter it adds
+    fn access(&mut self, address: u16, data: &mut [u8], access: Access) -> u16 {
+        let range = address as usize..address as usize + data.len();
+        if range.end > ESC_MEMORY {
+            return 0;
+        }
+        match access {
+            Access::Read => {
+                data.copy_from_slice(&self.memory[range.clone()]);
+                self.after_read(range);
+                1
+            }
+            Access::OrRead => {
+                for (data, memory) in data.iter_mut().zip(&self.memory[range.clone()]) {
+                    *data |= memory;
+                }
+                self.after_read(range);
+                1
+            }
+            Access::Write => {
+                self.write(range.start, data);
+                1
+            }
+            Access::ReadWrite => {
+                let read = self.memory[range.clone()].to_vec();
+                self.write(range.start, data);
+                data.copy_from_slice(&read);
+                self.after_read(range);
+                3
+            }
+        }
+    }
+
+    // A logical read, write or both through the FMMUs, the working counter it adds: 1 for reading, 2 for writing in
+    // an LRW, 1 for either on its own
+    fn logical(&mut self, address: u32, data: &mut [u8], command: u8) -> u16 {
+        let (mut read, mut written) = (false, false);
+        let end = address + data.len() as u32;
+        for fmmu in 0..FMMUS {
+            let reg = &self.memory[REG_FMMU + 16 * fmmu..REG_FMMU + 16 * (fmmu + 1)];
+            if reg[12] & 1 == 0 {
+                continue;
+            }
+            let start = u32::from_le_bytes([reg[0], reg[1], reg[2], reg[3]]);
+            let len = u16::from_le_bytes([reg[4], reg[5]]) as u32;
+            let physical = u16::from_le_bytes([reg[8], reg[9]]) as usize;
+            let kind = reg[11];
+            for logical in start.max(address)..(start + len).min(end) {
+                let (frame_at, memory_at) = ((logical - address) as usize, physical + (logical - start) as usize);
+                if memory_at >= ESC_MEMORY {
+                    continue;
+                }
+                if kind & 0x01 != 0 && command != LWR {
+                    data[frame_at] = self.memory[memory_at];
+                    read = true;
+                }
+                if kind & 0x02 != 0 && command != LRD {
+                    self.memory[memory_at] = data[frame_at];
+                    written = true;
+                }
+            }
+        }
+        match command {
+            LRD => read as u16,
+            LWR => written as u16,
+            _ => read as u16 + 2 * written as u16,
+        }
+    }
+
+    fn write(&mut self, start: usize, data: &[u8]) {
+        let range = start..start + data.len();
+        for (address, &byte) in range.clone().zip(data) {
+            if !read_only(address) {
+                self.memory[address] = byte;
+            }
+        }
+        if range.contains(&REG_AL_CONTROL) {
+            self.request_state();
+        }
+        if range.contains(&(REG_SII_CONTROL + 1)) {
+            self.sii_command();
+        }
+        if let Some((address, len)) = self.mailbox_sm(0) && range.contains(&(address + len - 1)) {
+            self.mailbox_request(address, len);
+        }
+    }
+
+    // Reading the last byte of the in mailbox empties it
+    fn after_read(&mut self, range: std::ops::Range<usize>) {
+        if let Some((address, len)) = self.mailbox_sm(1) && range.contains(&(address + len - 1)) {
+            self.memory[REG_SM + 8 + 5] &= !SM_STATUS_MAILBOX_FULL;
+        }
+    }
+
+    // AL control: any of INIT, PRE-OP, SAFE-OP and OP is granted at once, with the error flag cleared
+    fn request_state(&mut self) {
+        let requested = self.memory[REG_AL_CONTROL] & 0x0f;
+        if !matches!(requested, AL_INIT | AL_PRE_OP | AL_SAFE_OP | AL_OP) {
+            return;
+        }
+        if requested == AL_SAFE_OP && self.al_state() < AL_SAFE_OP {
+            self.map_process_data();
+        }
+        self.memory[REG_AL_STATUS as usize] = requested;
+        self.memory[REG_AL_STATUS as usize + 1] = 0;
+        self.memory[REG_AL_STATUS_CODE as usize..REG_AL_STATUS_CODE as usize + 2].fill(0);
+    }
+
+    // SII commands complete at once: a read puts 8 bytes from the word address into the data register. Never busy,
+    // never an error
+    fn sii_command(&mut self) {
+        if self.memory[REG_SII_CONTROL + 1] & 0x01 != 0 {
+            let word = u32::from_le_bytes(self.memory[REG_SII_ADDRESS..REG_SII_ADDRESS + 4].try_into().unwrap()) as usize;
+            for byte in 0..8 {
+                self.memory[REG_SII_DATA + byte] = self.eeprom.get(2 * word + byte).copied().unwrap_or(0xff);
+            }
+        }
+        self.memory[REG_SII_CONTROL + 1] &= !0x87;
+    }
+
+    // Address and length of SyncManager `sm` if it's configured as a mailbox
+    fn mailbox_sm(&self, sm: usize) -> Option<(usize, usize)> {
+        let reg = &self.memory[REG_SM + 8 * sm..REG_SM + 8 * (sm + 1)];
+        let (address, len) = (u16::from_le_bytes([reg[0], reg[1]]) as usize, u16::from_le_bytes([reg[2], reg[3]]) as usize);
+        (self.mailbox && reg[4] & 0x03 == 0x02 && reg[6] & 0x01 != 0 && len > 0 && address + len <= ESC_MEMORY).then_some((address, len))
+    }
+
+    // A complete request in the out mailbox: CoE SDO requests are answered in the in mailbox, anything else dropped
+    fn mailbox_request(&mut self, address: usize, len: usize) {
+        let Some((in_address, in_len)) = self.mailbox_sm(1) else { return };
+        let request = self.memory[address..address + len].to_vec();
+        let length = u16::from_le_bytes([request[0], request[1]]) as usize;
+        let counter = request[5] & 0x70;
+        if request[5] & 0x0f != MAILBOX_COE || length < 10 || 6 + length > len {
+            return;
+        }
+        if u16::from_le_bytes([request[6], request[7]]) >> 12 != COE_SDO_REQUEST {
+            return;
+        }
+        let sdo = self.sdo(&request[8..6 + length]);
+        if 8 + sdo.len() > in_len {
+            return;
+        }
+
+        let mut response = vec![0; in_len];
+        response[0..2].copy_from_slice(&(2 + sdo.len() as u16).to_le_bytes());
+        response[5] = MAILBOX_COE | counter;
+        response[6..8].copy_from_slice(&(COE_SDO_RESPONSE << 12).to_le_bytes());
+        response[8..8 + sdo.len()].copy_from_slice(&sdo);
+        self.memory[in_address..in_address + in_len].copy_from_slice(&response);
+        self.memory[REG_SM + 8 + 5] |= SM_STATUS_MAILBOX_FULL;
+    }
+
+    // The SDO response to an SDO request: command byte, index, subindex, then data
+    fn sdo(&mut self, request: &[u8]) -> Vec<u8> {
+        let (command, index, sub) = (request[0], u16::from_le_bytes([request[1], request[2]]), request[3]);
+        let complete = command & 0x10;
+        let abort = |code: u32| [&[0x80, request[1], request[2], sub][..], &code.to_le_bytes()].concat();
+        match command >> 5 {
+            1 => {
+                let data = if command & 0x02 != 0 {
+                    let size = if command & 0x01 != 0 { 4 - (command >> 2 & 0x03) as usize } else { 4 };
+                    &request[4..4 + size]
+                }
+                else {
+                    let size = u32::from_le_bytes(request[4..8].try_into().unwrap()) as usize;
+                    &request[8..(8 + size).min(request.len())]
+                };
+                match self.download(index, sub, complete != 0, data) {
+                    Ok(()) => vec![0x60 | complete, request[1], request[2], sub, 0, 0, 0, 0],
+                    Err(code) => abort(code),
+                }
+            }
+            2 => match self.upload(index, sub, complete != 0) {
+                Ok(data) if (1..=4).contains(&data.len()) => {
+                    let mut response = vec![0x43 | complete | ((4 - data.len()) as u8) << 2, request[1], request[2], sub, 0, 0, 0, 0];
+                    response[4..4 + data.len()].copy_from_slice(&data);
+                    response
+                }
+                Ok(data) => [&[0x41 | complete, request[1], request[2], sub][..], &(data.len() as u32).to_le_bytes(), &data].concat(),
+                Err(code) => abort(code),
+            },
+            _ => abort(SDO_ABORT_COMMAND),
+        }
+    }
+
+    // Complete access starts at `sub` and carries every entry after it, subindex 0 takes 16 bits
+    fn upload(&self, index: u16, sub: u8, complete: bool) -> Result<Vec<u8>, u32> {
+        if !self.objects.keys().any(|&(object, _)| object == index) {
+            return Err(SDO_ABORT_NO_OBJECT);
+        }
+        if !complete {
+            return self.objects.get(&(index, sub)).cloned().ok_or(SDO_ABORT_NO_SUBINDEX);
+        }
+        let count = self.objects.get(&(index, 0)).map_or(0, |count| count[0]);
+        let mut data = Vec::new();
+        for sub in sub..=count {
+            let value = self.objects.get(&(index, sub)).ok_or(SDO_ABORT_NO_SUBINDEX)?;
+            data.extend_from_slice(value);
+            if sub == 0 {
+                data.push(0);
+            }
+        }
+        Ok(data)
+    }
+
+    fn download(&mut self, index: u16, sub: u8, complete: bool, data: &[u8]) -> Result<(), u32> {
+        if !self.objects.keys().any(|&(object, _)| object == index) {
+            return Err(SDO_ABORT_NO_OBJECT);
+        }
+        if !complete {
+            let value = self.objects.get_mut(&(index, sub)).ok_or(SDO_ABORT_NO_SUBINDEX)?;
+            if value.len() != data.len() {
+                return Err(SDO_ABORT_LENGTH);
+            }
+            value.copy_from_slice(data);
+            return Ok(());
+        }
+        let (mut sub, mut data) = (sub, data);
+        if sub == 0 {
+            let (count, rest) = (data.first().copied().ok_or(SDO_ABORT_LENGTH)?, data.get(2..).unwrap_or_default());
+            self.objects.insert((index, 0), vec![count]);
+            (sub, data) = (1, rest);
+        }
+        let size = self.objects.get(&(index, sub)).map_or(2, Vec::len);
+        for chunk in data.chunks(size) {
+            let value = self.objects.get_mut(&(index, sub)).ok_or(SDO_ABORT_NO_SUBINDEX)?;
+            if chunk.len() != value.len() {
+                return Err(SDO_ABORT_LENGTH);
+            }
+            value.copy_from_slice(chunk);
+            sub += 1;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Access {
+    Read,
+    OrRead, // BRD, every SubDevice ORs its bytes into the frame
+    Write,
+    ReadWrite,
+}
+
+// ESC information, DL status and AL status registers, the PLC can't write them
+fn read_only(address: usize) -> bool {
+    matches!(address, 0x0000..=0x000f | 0x0110..=0x0111 | 0x0130..=0x0135) || address == REG_SII_CONTROL
+}
+
+/// The virtual chain in bus order
+pub struct VirtualChain {
+    subdevices: Vec<VirtualSubDevice>,
+}
+
+impl VirtualChain {
+    pub fn new(mut subdevices: Vec<VirtualSubDevice>) -> Self {
+        // Links on port 0 towards the PLC and port 1 towards the next SubDevice, port 1 closed on the last one
+        let last = subdevices.len().saturating_sub(1);
+        for (position, sd) in subdevices.iter_mut().enumerate() {
+            let dl_status: u16 = if position == last { 0x5611 } else { 0x5a31 };
+            sd.memory[REG_DL_STATUS..REG_DL_STATUS + 2].copy_from_slice(&dl_status.to_le_bytes());
+        }
+        Self { subdevices }
+    }
+
+    /// The chain's answer to an Ethernet frame, None for anything that isn't EtherCAT
+    pub fn exchange(&mut self, frame: &[u8]) -> Option<Vec<u8>> {
+        if frame.len() < 16 || u16::from_be_bytes([frame[12], frame[13]]) != ETHERCAT_ETHERTYPE {
+            return None;
+        }
+        let mut frame = frame.to_vec();
+        frame[6] |= 0x02; // the ESCs mark the source address as having been round the chain
+        let header = u16::from_le_bytes([frame[14], frame[15]]);
+        if header >> 12 != 1 {
+            return Some(frame); // not PDUs, passed through untouched
+        }
+        let end = (16 + (header & 0x07ff) as usize).min(frame.len());
+
+        for sd in &mut self.subdevices {
+            sd.latch_inputs();
+        }
+        let mut at = 16;
+        while at + 12 <= end {
+            let len = (u16::from_le_bytes([frame[at + 6], frame[at + 7]]) & 0x07ff) as usize;
+            let more = frame[at + 7] & 0x80 != 0;
+            if at + 12 + len > end {
+                break;
+            }
+            self.datagram(&mut frame[at..at + 12 + len]);
+            at += 12 + len;
+            if !more {
+                break;
+            }
+        }
+        for sd in &mut self.subdevices {
+            sd.latch_outputs();
+        }
+        Some(frame)
+    }
+
+    // One datagram through every SubDevice in turn: header, data, working counter
+    fn datagram(&mut self, datagram: &mut [u8]) {
+        let (header, rest) = datagram.split_at_mut(10);
+        let (data, wkc) = rest.split_at_mut(rest.len() - 2);
+        let command = header[0];
+        let mut counter = u16::from_le_bytes([wkc[0], wkc[1]]);
+
+        for sd in &mut self.subdevices {
+            let adp = u16::from_le_bytes([header[2], header[3]]);
+            let ado = u16::from_le_bytes([header[4], header[5]]);
+            // position and broadcast addressing count up at every SubDevice, the one that sees 0 is addressed
+            if matches!(command, APRD | APWR | APRW | ARMW | BRD | BWR | BRW) {
+                header[2..4].copy_from_slice(&adp.wrapping_add(1).to_le_bytes());
+            }
+            let configured = adp == sd.station_address();
+            counter = counter.wrapping_add(match command {
+                APRD if adp == 0 => sd.access(ado, data, Access::Read),
+                APWR if adp == 0 => sd.access(ado, data, Access::Write),
+                APRW if adp == 0 => sd.access(ado, data, Access::ReadWrite),
+                FPRD if configured => sd.access(ado, data, Access::Read),
+                FPWR if configured => sd.access(ado, data, Access::Write),
+                FPRW if configured => sd.access(ado, data, Access::ReadWrite),
+                BRD => sd.access(ado, data, Access::OrRead),
+                BWR => sd.access(ado, data, Access::Write),
+                BRW => sd.access(ado, data, Access::ReadWrite),
+                LRD | LWR | LRW => sd.logical(u32::from_le_bytes([header[2], header[3], header[4], header[5]]), data, command),
+                // read by the addressed one, written to everyone else
+                ARMW => sd.access(ado, data, if adp == 0 { Access::Read } else { Access::Write }),
+                FRMW => sd.access(ado, data, if configured { Access::Read } else { Access::Write }),
+                _ => 0,
+            });
+        }
+        wkc.copy_from_slice(&counter.to_le_bytes());
+    }
+}
+
+/// Bytes in a BK1120's input or output image with `terminals` behind it: the status/control word, then the
+/// intelligent terminals', then the simple terminals' bits packed together
+pub fn bk1120_image_len(terminals: &[KBusTerminal], output: bool) -> usize {
+    let intelligent = terminals.iter().filter(|&&terminal| terminal == KBusTerminal::Kl6581).count() * KL6581_IMAGE_LEN;
+    let simple_bits: usize = terminals.iter()
+        .map(|terminal| match *terminal {
+            KBusTerminal::Input(bits) if !output => bits as usize,
+            KBusTerminal::Output(bits) if output => bits as usize,
+            _ => 0,
+        })
+        .sum();
+    KBUS_CTRL_LEN + intelligent + simple_bits.div_ceil(8)
+}
+
+/// The rack ctrl_loop is written for: EK1100, EL1889, EL2889, EL3024 and a BK1120 with a KL1889, a KL2889 and a
+/// KL6581 behind it
+pub fn reference_rack() -> Vec<VirtualSubDevice> {
+    vec![
+        VirtualSubDevice::ek1100(),
+        VirtualSubDevice::el1889(),
+        VirtualSubDevice::el2889(),
+        VirtualSubDevice::el3024(),
+        VirtualSubDevice::bk1120(&[KL1889, KL2889, KBusTerminal::Kl6581]),
+    ]
+}
+
+/// Handle on a virtual chain, shared with the thread answering the MainDevice's frames
+#[derive(Clone)]
+pub struct VirtualBus {
+    chain: Arc<Mutex<VirtualChain>>,
+}
+
+impl VirtualBus {
+    pub fn new(subdevices: Vec<VirtualSubDevice>) -> Self {
+        Self { chain: Arc::new(Mutex::new(VirtualChain::new(subdevices))) }
+    }
+
+    /// What the field presents to the SubDevice at `position` from now on, cut or zero padded to its image
+    #[cfg(test)]
+    pub fn set_inputs(&self, position: usize, inputs: &[u8]) {
+        let mut chain = self.chain.lock().unwrap();
+        let sd = &mut chain.subdevices[position];
+        sd.inputs = inputs.to_vec();
+        let len = sd.pd_inputs.1.max(sd.inputs.len());
+        sd.inputs.resize(len, 0);
+    }
+
+    /// The outputs the SubDevice at `position` last got in OP
+    #[cfg(test)]
+    pub fn outputs(&self, position: usize) -> Vec<u8> {
+        self.chain.lock().unwrap().subdevices[position].outputs.clone()
+    }
+
+    #[cfg(test)]
+    pub fn al_state(&self, position: usize) -> u8 {
+        self.chain.lock().unwrap().subdevices[position].al_state()
+    }
+
+    /// An entry of the SubDevice's object dictionary as it is now, None without it
+    #[cfg(test)]
+    pub fn object(&self, position: usize, index: u16, sub: u8) -> Option<Vec<u8>> {
+        self.chain.lock().unwrap().subdevices[position].objects.get(&(index, sub)).cloned()
+    }
+
+    /// A MainDevice on the chain, with a thread in place of ethercrab's TX/RX task. Every call gets its own PDU
+    /// storage, so each test in the process can have a bus
+    pub fn start_maindevice(&self) -> Arc<MainDevice<'static>> {
+        let storage: &'static PduStorage<MAX_FRAMES, MAX_PDU_DATA> = Box::leak(Box::new(PduStorage::new()));
+        let (mut tx, mut rx, pdu_loop) = storage.try_split().expect("split fresh PDU storage");
+        let chain = self.chain.clone();
+
+        std::thread::Builder::new()
+        .name("VirtualBusThread".to_owned())
+        .spawn(move || loop {
+            while let Some(frame) = tx.next_sendable_frame() {
+                let mut reply = None;
+                let sent = frame.send_blocking(|frame| {
+                    reply = chain.lock().unwrap().exchange(frame);
+                    Ok(frame.len())
+                });
+                if sent.is_ok() && let Some(reply) = reply && let Err(e) = rx.receive_frame(&reply) {
+                    log::warn!("Virtual bus reply not taken: {:?}", e);
+                }
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        })
+        .expect("build virtual bus thread");
+
+        Arc::new(ctrl_loop::new_maindevice(pdu_loop))
+    }
+}
+
+fn object(objects: &mut BTreeMap<(u16, u8), Vec<u8>>, index: u16, sub: u8, value: &[u8]) {
+    objects.insert((index, sub), value.to_vec());
+}
+
+// 0x1C00, the communication type of each SyncManager: mailbox out and in, outputs, inputs
+fn sm_types(objects: &mut BTreeMap<(u16, u8), Vec<u8>>) {
+    object(objects, 0x1c00, 0, &[4]);
+    for sm in 1..=4 {
+        object(objects, 0x1c00, sm, &[sm]);
+    }
+}
+
+// A PDO assignment (0x1C12, 0x1C13) with room for 8 PDOs
+fn assignment(objects: &mut BTreeMap<(u16, u8), Vec<u8>>, index: u16, pdos: &[u16]) {
+    object(objects, index, 0, &[pdos.len() as u8]);
+    for sub in 1..=8 {
+        object(objects, index, sub, &pdos.get(sub as usize - 1).copied().unwrap_or(0).to_le_bytes());
+    }
+}
+
+// A PDO mapping, entries as (index, subindex, bits)
+fn mapping(objects: &mut BTreeMap<(u16, u8), Vec<u8>>, index: u16, entries: &[(u16, u8, u8)]) {
+    object(objects, index, 0, &[entries.len() as u8]);
+    for (sub, &(entry, entry_sub, bits)) in entries.iter().enumerate() {
+        let value = (entry as u32) << 16 | (entry_sub as u32) << 8 | bits as u32;
+        object(objects, index, sub as u8 + 1, &value.to_le_bytes());
+    }
+}
+
+// SII contents: identity and mailbox words, then the strings, general, FMMU, SyncManager and PDO categories.
+// `sms` are (address, length, control, type), PDOs (index, SyncManager, entries as in mapping())
+type SiiPdo = (u16, u8, Vec<(u16, u8, u8)>);
+
+fn sii(name: &str, product: u32, mailbox: bool, sms: &[(u16, usize, u8, u8)], fmmus: &[u8], tx_pdos: &[SiiPdo], rx_pdos: &[SiiPdo]) -> Vec<u8> {
+    let mut eeprom = vec![0u8; 0x80];
+    let mut put = |word: usize, value: &[u8]| eeprom[2 * word..2 * word + value.len()].copy_from_slice(value);
+    put(0x08, &BECKHOFF.to_le_bytes());
+    put(0x0a, &product.to_le_bytes());
+    put(0x0c, &0x0010_0000u32.to_le_bytes()); // revision
+    if mailbox {
+        put(0x18, &MAILBOX_OUT.to_le_bytes());
+        put(0x19, &MAILBOX_LEN.to_le_bytes());
+        put(0x1a, &MAILBOX_IN.to_le_bytes());
+        put(0x1b, &MAILBOX_LEN.to_le_bytes());
+        put(0x1c, &0x0004u16.to_le_bytes()); // CoE
+    }
+    put(0x3e, &0x0007u16.to_le_bytes()); // 1 KiB EEPROM
+    put(0x3f, &0x0001u16.to_le_bytes());
+
+    let mut category = |kind: u16, mut data: Vec<u8>| {
+        if !data.len().is_multiple_of(2) {
+            data.push(0);
+        }
+        eeprom.extend_from_slice(&kind.to_le_bytes());
+        eeprom.extend_from_slice(&(data.len() as u16 / 2).to_le_bytes());
+        eeprom.extend_from_slice(&data);
+    };
+
+    // strings 1 and 2, name as the order number and the device name
+    let mut strings = vec![2];
+    for _ in 0..2 {
+        strings.push(name.len() as u8);
+        strings.extend_from_slice(name.as_bytes());
+    }
+    category(10, strings);
+
+    let mut general = vec![0u8; 32];
+    general[2] = 1; // order number
+    general[3] = 2; // name
+    general[5] = if mailbox { 0x2d } else { 0 }; // CoE: SDO, PDO assignment and configuration, complete access
+    category(30, general);
+
+    category(40, fmmus.to_vec());
+    category(41, sms.iter().flat_map(|&(address, len, control, kind)| {
+        [&address.to_le_bytes()[..], &(len as u16).to_le_bytes(), &[control, 0, 1, kind]].concat()
+    }).collect());
+    for (kind, pdos) in [(50, tx_pdos), (51, rx_pdos)] {
+        if pdos.is_empty() {
+            continue;
+        }
+        category(kind, pdos.iter().flat_map(|(index, sm, entries)| {
+            let mut data = [&index.to_le_bytes()[..], &[entries.len() as u8, *sm, 0, 0, 0, 0]].concat();
+            for &(entry, sub, bits) in entries {
+                data.extend_from_slice(&[&entry.to_le_bytes()[..], &[sub, 0, 0, bits, 0, 0]].concat());
+            }
+            data
+        }).collect());
+    }
+    category(0xffff, Vec::new());
+    eeprom
+}
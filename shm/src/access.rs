@@ -0,0 +1,129 @@
+// Who may attach to the PLC. The region and the socket used to be created with the default umask, so any local
+// user could flip HMI commands by writing the file.
+//
+// Unix: the shm file and the socket are owner only (0600), or owner + `[ipc] group` (0660) when a group is
+// configured. Socket peers are additionally checked by their credentials: the PLC's own user, root or a member
+// of the group, whose commands the PLC takes as that member's (see users.rs). Windows: "Local\\" mapping objects are already limited to the creating session, `group` isn't
+// supported there.
+use std::io;
+#[cfg(unix)]
+use std::fs::{self, File, Permissions};
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Access {
+    pub gid: Option<u32>, // group that shares access with the owner
+}
+
+impl Access {
+    /// `group` is a group name or a numeric gid, None means owner only
+    pub fn from_group(group: Option<&str>) -> io::Result<Self> {
+        match group {
+            None => Ok(Self::default()),
+            #[cfg(unix)]
+            Some(group) => resolve_group(group).map(|gid| Self { gid: Some(gid) }),
+            #[cfg(not(unix))]
+            Some(_) => Err(io::Error::other("[ipc] group is only supported on unix")),
+        }
+    }
+
+    pub fn mode(&self) -> u32 {
+        if self.gid.is_some() { 0o660 } else { 0o600 }
+    }
+
+    /// Chown to the group and chmod, also fixes up a file left behind by a previous run with other permissions
+    #[cfg(unix)]
+    pub(crate) fn apply(&self, file: &File) -> io::Result<()> {
+        if self.gid.is_some() {
+            std::os::unix::fs::fchown(file, None, self.gid)?;
+        }
+        file.set_permissions(Permissions::from_mode(self.mode()))
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn apply_to_path(&self, path: &str) -> io::Result<()> {
+        if self.gid.is_some() {
+            std::os::unix::fs::chown(path, None, self.gid)?;
+        }
+        fs::set_permissions(path, Permissions::from_mode(self.mode()))
+    }
+
+    /// Socket peers: the same user, root, or a member of the group
+    #[cfg(unix)]
+    pub(crate) fn peer(&self, stream: &UnixStream) -> io::Result<Peer> {
+        let peer = peer_credentials(stream)?;
+        if peer.uid == 0 || peer.uid == unsafe { libc::geteuid() } {
+            return Ok(Peer::Service);
+        }
+        if !self.gid.is_some_and(|gid| peer.gids.contains(&gid)) {
+            return Ok(Peer::Denied);
+        }
+        // a uid without a login is nobody in [users], a viewer
+        Ok(Peer::User(crate::users::Users::login_of(peer.uid).unwrap_or_else(|| peer.uid.to_string())))
+    }
+}
+
+/// Who's on the other end of a socket
+#[cfg(unix)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Peer {
+    Denied,
+    Service,      // the PLC's own user or root, names the users it acts for in its audits
+    User(String), // a member of the group, by login: commands are theirs whatever their audits say
+}
+
+#[cfg(unix)]
+fn resolve_group(group: &str) -> io::Result<u32> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    // name:password:gid:members
+    let groups = fs::read_to_string("/etc/group")?;
+    groups.lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .find(|fields| fields.first() == Some(&group))
+        .and_then(|fields| fields.get(2)?.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("No group named '{}'", group)))
+}
+
+#[cfg(unix)]
+struct PeerCredentials {
+    uid: u32,
+    gids: Vec<u32>, // primary first, then supplementary where the OS tells us
+}
+
+#[cfg(target_os = "linux")]
+fn peer_credentials(stream: &UnixStream) -> io::Result<PeerCredentials> {
+    use std::os::fd::AsRawFd;
+
+    let mut cred: libc::ucred = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let rc = unsafe {
+        libc::getsockopt(stream.as_raw_fd(), libc::SOL_SOCKET, libc::SO_PEERCRED, &mut cred as *mut _ as *mut libc::c_void, &mut len)
+    };
+    if rc != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // SO_PEERCRED only has the primary group, the rest is in /proc while the peer is around
+    let mut gids = vec![cred.gid];
+    if let Ok(status) = fs::read_to_string(format!("/proc/{}/status", cred.pid))
+        && let Some(groups) = status.lines().find_map(|line| line.strip_prefix("Groups:")) {
+        gids.extend(groups.split_whitespace().filter_map(|gid| gid.parse::<u32>().ok()));
+    }
+    Ok(PeerCredentials { uid: cred.uid, gids })
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn peer_credentials(stream: &UnixStream) -> io::Result<PeerCredentials> {
+    use std::os::fd::AsRawFd;
+
+    let (mut uid, mut gid) = (0, 0);
+    if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(PeerCredentials { uid, gids: vec![gid] })
+}
@@ -0,0 +1,77 @@
+// gipop_sim [<config>], a stand-in for the PLC process: creates the region (or binds the socket) from [ipc] in
+// gipop.toml with the PLC's tag list and publishes simulated values into it, so the OPC UA server, the gateway and
+// HMIs run against it exactly like against the real PLC, with no EtherCAT hardware or PLC build around.
+//
+// Values come from gipop_shm::sim (sines, ramps and toggles), "hmi alive" follows the consumers' heartbeat like on
+// the PLC. Writes to writable tags stick, alarm acks come back as events, every command is acknowledged. Forces, run
+// mode and the rest are accepted and ignored, there's no I/O, bus diagnostics or process image. Publishes every
+// SYNC_INTERVAL like the PLC's IPC thread, runs until killed.
+#[path = "../../../plc/src/tags.rs"]
+#[allow(dead_code)] // only the tag list and HMI_ALIVE are used here
+mod plc_tags;
+
+use std::time::Duration;
+
+use gipop_shm::sim::Simulator;
+use gipop_shm::{IpcConfig, Liveness, Publisher, TagSample, TagValue, Transport};
+
+const CONFIG_PATH: &str = "gipop.toml";
+const SYNC_INTERVAL: Duration = Duration::from_millis(100);
+
+fn main() {
+    let config = std::env::args().nth(1).map_or_else(|| gipop_shm::project::config_path(CONFIG_PATH, env!("CARGO_PKG_VERSION")), Ok)
+        .unwrap_or_else(|e| {
+            eprintln!("gipop_sim: {}", e);
+            std::process::exit(1);
+        });
+    gipop_shm::logging::init(&config, "gipop_sim");
+    gipop_shm::logging::reload_on_sighup();
+
+    let ipc = match IpcConfig::load(&config) {
+        Ok(ipc) => ipc,
+        Err(e) => {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let tags = plc_tags::plc_tags();
+    let target = if ipc.transport == Transport::Shm { &ipc.shm_path } else { &ipc.socket_path };
+    let mut publisher = match Publisher::create(&ipc, &tags) {
+        Ok(publisher) => publisher,
+        Err(e) => {
+            log::error!("Error opening {}: {}", target, e);
+            std::process::exit(1);
+        }
+    };
+    log::info!("Simulating the PLC's {} tags on {}", tags.len(), target);
+
+    let sim = Simulator::new(&tags);
+    publisher.write_plc_info(&sim.read_plc_info());
+    let hmi_alive = sim.index_of(plc_tags::HMI_ALIVE);
+    let mut consumers = Liveness::new(ipc.heartbeat_timeout());
+
+    loop {
+        while let Some(item) = publisher.pop_command() {
+            _ = sim.push_command(item);
+            publisher.ack_command(item.seq);
+        }
+        while let Some(event) = sim.pop_event() {
+            publisher.push_event(event);
+        }
+
+        match consumers.update(publisher.consumer_heartbeat()) {
+            Some(true) => log::info!("Consumer heartbeat detected"),
+            Some(false) => log::warn!("Consumer heartbeat went stale"),
+            None => {}
+        }
+        let mut samples: Vec<(usize, TagSample)> = sim.read_all_samples().into_iter().enumerate().collect();
+        if let Some(idx) = hmi_alive {
+            samples[idx].1.value = TagValue::Bool(consumers.is_alive());
+        }
+        if let Err(e) = publisher.write_samples(&samples) {
+            log::warn!("Publishing simulated values failed: {}", e);
+        }
+        publisher.heartbeat();
+        std::thread::sleep(SYNC_INTERVAL);
+    }
+}
@@ -0,0 +1,157 @@
+// Variable-length payload area in the shm region, for what doesn't fit a fixed-size RingItem: raw EnOcean
+// telegrams, diagnostic strings, topology JSON...
+//
+// A byte ring the PLC appends records to, every consumer follows it with its own BlobCursor, same broadcast model
+// as the event ring. Each record is | BlobRecord | payload | padded to 8 bytes, and may wrap around the end of the area.
+//
+// `head` and `reserve` are free-running byte positions. The PLC bumps `reserve` before overwriting anything and
+// `head` once the record is complete, so a reader can tell after copying a record whether the PLC got to it in the
+// meantime. Record boundaries can't be recovered after that, a reader that falls behind by more than the area
+// skips ahead to `head` and counts a gap.
+use bytemuck::{Pod, Zeroable};
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{AtomicU64, Ordering, fence};
+
+pub const BLOB_AREA_LEN: usize = 64 * 1024; // bytes of payload area, must be a multiple of 8
+pub const MAX_BLOB_LEN: usize = 4 * 1024;   // a single payload, keeps one record from wiping out everything else
+
+// Payload kinds
+pub const BLOB_ENOCEAN_TELEGRAM: u16 = 1; // raw KL6581 process image
+pub const BLOB_DIAGNOSTIC: u16 = 2;       // utf-8 text
+pub const BLOB_TOPOLOGY: u16 = 3;         // utf-8 JSON describing the discovered bus
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct BlobAreaHeader {
+    pub head: u64,    // end of the last complete record
+    pub reserve: u64, // end of the record being written, == head while idle
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct BlobRecord {
+    pub len: u32, // payload bytes, without padding
+    pub kind: u16,
+    pub _pad: u16,
+    pub _reserved: u64,
+}
+
+/// One payload read from the area (or received over the socket)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Blob {
+    pub kind: u16,
+    pub data: Vec<u8>,
+}
+
+impl Blob {
+    /// Lossy, for BLOB_DIAGNOSTIC and BLOB_TOPOLOGY
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.data).into_owned()
+    }
+}
+
+/// One consumer's position in the payload area
+#[derive(Debug, Clone, Copy)]
+pub struct BlobCursor {
+    pos: u64,
+    pub lost: u64, // times this consumer fell behind and skipped ahead
+}
+
+const AREA_HEADER_LEN: usize = mem::size_of::<BlobAreaHeader>();
+const RECORD_LEN: usize = mem::size_of::<BlobRecord>();
+
+pub(crate) const fn area_len() -> usize {
+    AREA_HEADER_LEN + BLOB_AREA_LEN
+}
+
+fn record_len(payload_len: usize) -> u64 {
+    (RECORD_LEN + payload_len.div_ceil(8) * 8) as u64
+}
+
+/// View over the payload area at `base` (start of its BlobAreaHeader) inside a live mapping
+pub(crate) struct BlobRef {
+    pub base: *mut u8,
+}
+
+impl BlobRef {
+    fn counter(&self, offset: usize) -> &AtomicU64 {
+        // SAFETY: `base` is 8-byte aligned inside the mapping (checked by the region layout)
+        unsafe { AtomicU64::from_ptr(self.base.add(offset) as *mut u64) }
+    }
+
+    fn head(&self) -> &AtomicU64 { self.counter(mem::offset_of!(BlobAreaHeader, head)) }
+    fn reserve(&self) -> &AtomicU64 { self.counter(mem::offset_of!(BlobAreaHeader, reserve)) }
+
+    // both copies split at the end of the area
+    fn copy_in(&self, pos: u64, bytes: &[u8]) {
+        let at = (pos % BLOB_AREA_LEN as u64) as usize;
+        let first = bytes.len().min(BLOB_AREA_LEN - at);
+        unsafe {
+            let area = self.base.add(AREA_HEADER_LEN);
+            ptr::copy_nonoverlapping(bytes.as_ptr(), area.add(at), first);
+            ptr::copy_nonoverlapping(bytes[first..].as_ptr(), area, bytes.len() - first);
+        }
+    }
+
+    fn copy_out(&self, pos: u64, out: &mut [u8]) {
+        let at = (pos % BLOB_AREA_LEN as u64) as usize;
+        let first = out.len().min(BLOB_AREA_LEN - at);
+        unsafe {
+            let area = self.base.add(AREA_HEADER_LEN);
+            ptr::copy_nonoverlapping(area.add(at), out.as_mut_ptr(), first);
+            ptr::copy_nonoverlapping(area, out[first..].as_mut_ptr(), out.len() - first);
+        }
+    }
+
+    /// PLC only. Never waits, the oldest records get overwritten.
+    pub fn push(&self, kind: u16, payload: &[u8]) -> Result<(), String> {
+        if payload.len() > MAX_BLOB_LEN {
+            return Err(format!("Payload of {} bytes exceeds the {} byte limit", payload.len(), MAX_BLOB_LEN));
+        }
+        let head = self.head().load(Ordering::Relaxed);
+        let end = head + record_len(payload.len());
+
+        self.reserve().store(end, Ordering::Relaxed);
+        fence(Ordering::Release);
+        let record = BlobRecord { len: payload.len() as u32, kind, ..BlobRecord::zeroed() };
+        self.copy_in(head, bytemuck::bytes_of(&record));
+        self.copy_in(head + RECORD_LEN as u64, payload);
+        self.head().store(end, Ordering::Release);
+        Ok(())
+    }
+
+    /// A cursor that sees every record pushed from now on
+    pub fn cursor(&self) -> BlobCursor {
+        BlobCursor { pos: self.head().load(Ordering::Acquire), lost: 0 }
+    }
+
+    /// Any consumer with its own cursor
+    pub fn next(&self, cursor: &mut BlobCursor) -> Option<Blob> {
+        loop {
+            let head = self.head().load(Ordering::Acquire);
+            if cursor.pos == head {
+                return None;
+            }
+
+            let mut record = [0u8; RECORD_LEN];
+            self.copy_out(cursor.pos, &mut record);
+            let record: BlobRecord = bytemuck::pod_read_unaligned(&record);
+            let len = (record.len as usize).min(MAX_BLOB_LEN); // garbage if overwritten, checked below
+            let mut data = vec![0u8; len];
+            self.copy_out(cursor.pos + RECORD_LEN as u64, &mut data);
+
+            fence(Ordering::Acquire);
+            let reserve = self.reserve().load(Ordering::Relaxed);
+            let overwritten = reserve.wrapping_sub(cursor.pos) > BLOB_AREA_LEN as u64; // the PLC wrapped onto this record
+            if overwritten || record.len as usize > MAX_BLOB_LEN {
+                cursor.lost += 1;
+                cursor.pos = head;
+                continue;
+            }
+
+            cursor.pos += record_len(len);
+            return Some(Blob { kind: record.kind, data });
+        }
+    }
+}
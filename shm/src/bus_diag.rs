@@ -0,0 +1,73 @@
+// Fieldbus health in the shm region: EtherCAT cycle statistics, the K-bus coupler's status and per-SubDevice state
+// and error counters. The PLC rewrites the whole snapshot about once a second (register reads cost bus time),
+// consumers read it under the same sequence lock as the I/O mirror.
+use bytemuck::{Pod, Zeroable};
+
+pub const MAX_BUS_SUBDEVICES: usize = 16; // same as the PLC's MainDevice
+pub const SUBDEVICE_NAME_LEN: usize = 16;
+
+/// The whole bus as of the PLC's last diagnostics pass
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Pod, Zeroable)]
+pub struct BusDiagnostics {
+    pub cycles: u64,     // process data cycles since the PLC started
+    pub wkc_errors: u64, // cycles whose working counter wasn't the expected one
+    pub cycle_us_last: u32,
+    pub cycle_us_min: u32,
+    pub cycle_us_max: u32,
+    pub cycle_us_avg: u32,
+    pub jitter_us_max: u32, // difference between consecutive cycle times
+    pub jitter_us_avg: u32,
+    pub coupler_status: u32, // K-bus state reported by the BK1120, 0 = OK, u32::MAX = no coupler on the bus
+    pub subdevice_count: u32,
+    pub subdevices: [SubDeviceDiagnostics; MAX_BUS_SUBDEVICES],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Pod, Zeroable)]
+pub struct SubDeviceDiagnostics {
+    pub name: [u8; SUBDEVICE_NAME_LEN], // ascii, zero padded, e.g. "EL2889"
+    pub address: u16,        // configured station address
+    pub al_state: u16,       // AL status register (0x0130): state in the low nibble, 0x10 = error indication
+    pub al_status_code: u16, // AL status code register (0x0134), why the SubDevice refused a transition
+    pub _pad: u16,
+    pub port_errors: [u32; 4], // per port: invalid frames + RX errors (0x0300..0x0307)
+    pub lost_links: u32,       // all ports (0x0310..0x0313)
+    pub _reserved: u32,
+}
+
+impl BusDiagnostics {
+    pub fn subdevices(&self) -> &[SubDeviceDiagnostics] {
+        &self.subdevices[..(self.subdevice_count as usize).min(MAX_BUS_SUBDEVICES)]
+    }
+}
+
+impl SubDeviceDiagnostics {
+    pub fn new(name: &str, address: u16) -> Self {
+        let mut diag = Self { address, ..Self::zeroed() };
+        let len = name.len().min(SUBDEVICE_NAME_LEN);
+        diag.name[..len].copy_from_slice(&name.as_bytes()[..len]);
+        diag
+    }
+
+    pub fn name(&self) -> String {
+        let len = self.name.iter().position(|&b| b == 0).unwrap_or(SUBDEVICE_NAME_LEN);
+        String::from_utf8_lossy(&self.name[..len]).into_owned()
+    }
+
+    pub fn has_error(&self) -> bool {
+        self.al_state & 0x10 != 0
+    }
+
+    /// INIT, PRE-OP, BOOT, SAFE-OP or OP, ignoring the error indication
+    pub fn state_name(&self) -> &'static str {
+        match self.al_state & 0x0f {
+            0x1 => "INIT",
+            0x2 => "PRE-OP",
+            0x3 => "BOOT",
+            0x4 => "SAFE-OP",
+            0x8 => "OP",
+            _ => "UNKNOWN",
+        }
+    }
+}
@@ -0,0 +1,167 @@
+// `[ipc]` section of gipop.toml, read by the PLC and every consumer so they agree on how to reach each other.
+//
+// [ipc]
+// transport = "shm"                  # or "uds"
+// shm_path = "/dev/shm/shared_plc_data"
+// socket_path = "/tmp/gipop_plc.sock"
+// heartbeat_timeout_ms = 2000        # peer counts as gone after this long without a heartbeat
+// connect_timeout_ms = 30000         # consumers started before the PLC wait this long for its region or socket
+// group = "gipop"                    # unix only, shares the region and socket with this group (name or gid), see access.rs
+use crate::access::Access;
+use crate::startup::Step;
+use serde::de::{DeserializeOwned, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, Visitor};
+use serde::Deserialize;
+use std::marker::PhantomData;
+use std::time::Duration;
+use std::{fmt, fs, io, path::Path};
+
+const CONNECT_RETRY: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    #[default]
+    Shm, // mmap region, see region.rs
+    Uds, // unix domain socket, see uds.rs
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct IpcConfig {
+    pub transport: Transport,
+    pub shm_path: String,
+    pub socket_path: String,
+    pub heartbeat_timeout_ms: u64,
+    pub connect_timeout_ms: u64,
+    pub group: Option<String>, // None: only the PLC's own user (and root) gets in
+}
+
+impl Default for IpcConfig {
+    fn default() -> Self {
+        Self {
+            transport: Transport::default(),
+            shm_path: crate::SHM_PATH.to_string(),
+            socket_path: crate::SOCKET_PATH.to_string(),
+            heartbeat_timeout_ms: 2000,
+            connect_timeout_ms: 30_000,
+            group: None,
+        }
+    }
+}
+
+impl IpcConfig {
+    pub fn heartbeat_timeout(&self) -> Duration {
+        Duration::from_millis(self.heartbeat_timeout_ms)
+    }
+
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_millis(self.connect_timeout_ms)
+    }
+
+    /// A consumer attaching to the PLC's region or socket, tried until connect_timeout so it can start first
+    pub fn connect_step(&self) -> Step {
+        Step::new("plc").retry(CONNECT_RETRY, self.connect_timeout())
+    }
+
+    /// Resolves `group`, fails if it names a group that doesn't exist
+    pub fn access(&self) -> io::Result<Access> {
+        Access::from_group(self.group.as_deref())
+    }
+
+    /// Reads the `[ipc]` section of `path`. A missing file or section means defaults.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        load_section(path, "ipc")
+    }
+}
+
+/// Reads all of `path` as `T`, the way every binary reads its own part of gipop.toml. A missing file means defaults,
+/// sections `T` doesn't name are left to whoever owns them.
+pub fn load<T: DeserializeOwned + Default>(path: impl AsRef<Path>) -> Result<T, String> {
+    read(path.as_ref(), |text| toml::from_str(text))
+}
+
+/// Reads the `[section]` table of `path` as `T`, for config shared between binaries. A missing file or section means
+/// defaults, parse errors still point at the line in `path`.
+pub fn load_section<T: DeserializeOwned + Default>(path: impl AsRef<Path>, section: &str) -> Result<T, String> {
+    read(path.as_ref(), |text| Section { name: section, section: PhantomData }.deserialize(toml::Deserializer::new(text)))
+}
+
+fn read<T: Default>(path: &Path, parse: impl FnOnce(&str) -> Result<T, toml::de::Error>) -> Result<T, String> {
+    match fs::read_to_string(path) {
+        Ok(text) => parse(&text).map_err(|e| format!("Failed to parse {}: {}", path.display(), e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+    }
+}
+
+// Deserializes the one top-level table called `name` and skips the rest of the document
+struct Section<'a, T> {
+    name: &'a str,
+    section: PhantomData<T>,
+}
+
+impl<'de, T: Deserialize<'de> + Default> DeserializeSeed<'de> for Section<'_, T> {
+    type Value = T;
+
+    fn deserialize<D: Deserializer<'de>>(self, document: D) -> Result<T, D::Error> {
+        document.deserialize_map(self)
+    }
+}
+
+impl<'de, T: Deserialize<'de> + Default> Visitor<'de> for Section<'_, T> {
+    type Value = T;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a TOML document")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut tables: A) -> Result<T, A::Error> {
+        let mut section = None;
+        while let Some(key) = tables.next_key::<String>()? {
+            if key == self.name {
+                section = Some(tables.next_value()?);
+            } else {
+                tables.next_value::<IgnoredAny>()?;
+            }
+        }
+        Ok(section.unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Default, PartialEq, Deserialize)]
+    #[serde(default)]
+    struct Mine {
+        rate: u64,
+        name: String,
+    }
+
+    #[test]
+    fn section_loading() {
+        type Case = (&'static str, Result<Mine, &'static str>);
+        let cases: &[Case] = &[
+            ("", Ok(Mine::default())),
+            ("[other]\nrate = \"ignored\"\n", Ok(Mine::default())),
+            ("[other]\nx = [1, 2]\n\n[mine]\nrate = 5\n\n[mine.nested]\nx = 1\n", Ok(Mine { rate: 5, name: String::new() })),
+            ("[mine]\nrate = 5\n[after]\nname = 1\n", Ok(Mine { rate: 5, name: String::new() })),
+            ("[before.deep]\nx = 1\n[mine]\nname = \"a\"\n", Ok(Mine { rate: 0, name: "a".into() })),
+            ("[mine]\nrate = \"fast\"\n", Err("line 2")),
+            ("[mine\n", Err("line 1")),
+        ];
+        let path = std::env::temp_dir().join(format!("gipop_config_test_{}.toml", std::process::id()));
+        for (text, expected) in cases {
+            fs::write(&path, text).unwrap();
+            let loaded = load_section::<Mine>(&path, "mine");
+            match expected {
+                Ok(section) => assert_eq!(loaded.as_ref(), Ok(section), "{:?}", text),
+                Err(hint) => assert!(loaded.as_ref().is_err_and(|e| e.contains(hint)), "{:?}: {:?}", text, loaded),
+            }
+        }
+        fs::remove_file(&path).unwrap();
+        assert_eq!(load_section::<Mine>(&path, "mine"), Ok(Mine::default()));
+        assert_eq!(load::<Mine>(&path), Ok(Mine::default()));
+    }
+}
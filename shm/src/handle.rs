@@ -0,0 +1,247 @@
+// Long-lived handles onto the shm region. Map once, clone freely: clones share one mapping, so callbacks and
+// threads don't reopen and remap the region on every access.
+//
+// ShmWriter is the PLC end (tag values, command consumer, event producer), ShmReader the consumer end.
+// Any number of processes may hold an ShmReader. Each ShmReader (and its clones) follows the event ring with its
+// own cursor, open another one for an independent event stream.
+use crate::access::Access;
+use crate::region::TagTable;
+use crate::blob::{Blob, BlobCursor};
+use crate::bus_diag::BusDiagnostics;
+use crate::plc_info::PlcInfo;
+use crate::process_image::ProcessImage;
+use crate::io_mirror::IoChannel;
+use crate::ring::{EventCursor, RingItem};
+use crate::tags::{TagDef, TagPrimitive, TagSample, TagValue};
+use std::io;
+use std::sync::{Arc, Mutex};
+
+struct ReaderInner {
+    table: TagTable,
+    events: Mutex<EventCursor>,
+    blobs: Mutex<BlobCursor>,
+}
+
+#[derive(Clone)]
+pub struct ShmReader {
+    inner: Arc<ReaderInner>,
+}
+
+impl ShmReader {
+    /// Attach to a region created by the PLC, see TagTable::open
+    pub fn open(name: &str) -> Result<Self, String> {
+        let table = TagTable::open(name)?;
+        let events = Mutex::new(table.event_cursor());
+        let blobs = Mutex::new(table.blob_cursor());
+        Ok(Self { inner: Arc::new(ReaderInner { table, events, blobs }) })
+    }
+
+    pub fn tags(&self) -> &[TagDef] {
+        self.inner.table.tags()
+    }
+
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.inner.table.index_of(name)
+    }
+
+    pub fn read(&self, idx: usize) -> TagValue {
+        self.inner.table.read(idx)
+    }
+
+    pub fn read_by_name(&self, name: &str) -> Option<TagValue> {
+        self.inner.table.read_by_name(name)
+    }
+
+    /// Value with the PLC's timestamp and quality
+    pub fn read_sample(&self, idx: usize) -> TagSample {
+        self.inner.table.read_sample(idx)
+    }
+
+    pub fn read_all(&self) -> Vec<TagValue> {
+        self.inner.table.read_all()
+    }
+
+    pub fn read_all_samples(&self) -> Vec<TagSample> {
+        self.inner.table.read_all_samples()
+    }
+
+    /// None if there's no such tag or it isn't a `T`
+    pub fn get<T: TagPrimitive>(&self, idx: usize) -> Option<T> {
+        if self.tags().get(idx)?.ty != T::TYPE {
+            return None;
+        }
+        T::from_value(self.read(idx))
+    }
+
+    pub fn get_by_name<T: TagPrimitive>(&self, name: &str) -> Option<T> {
+        self.get(self.index_of(name)?)
+    }
+
+    /// Returns the command's sequence number
+    pub fn push_command(&self, item: RingItem) -> Result<u32, RingItem> {
+        self.inner.table.push_command(item)
+    }
+
+    /// Whether the PLC has consumed the command with sequence number `seq`
+    pub fn is_command_acked(&self, seq: u32) -> bool {
+        self.inner.table.is_command_acked(seq)
+    }
+
+    /// Tell the PLC this consumer is alive, call it periodically
+    pub fn heartbeat(&self) {
+        self.inner.table.beat_consumer()
+    }
+
+    pub fn plc_heartbeat(&self) -> u32 {
+        self.inner.table.plc_heartbeat()
+    }
+
+    /// Next event for this reader (shared between its clones)
+    pub fn pop_event(&self) -> Option<RingItem> {
+        let mut cursor = self.inner.events.lock().unwrap();
+        let lost = cursor.lost;
+        let item = self.inner.table.next_event(&mut cursor);
+        if cursor.lost != lost {
+            log::warn!("Fell behind on the shm event ring, {} events lost so far", cursor.lost);
+        }
+        item
+    }
+
+    /// Every terminal channel as of the PLC's last sync cycle
+    pub fn read_io(&self) -> Vec<IoChannel> {
+        self.inner.table.read_io()
+    }
+
+    /// Fieldbus health as of the PLC's last diagnostics pass, None before the first one
+    pub fn read_bus_diag(&self) -> Option<BusDiagnostics> {
+        self.inner.table.read_bus_diag()
+    }
+
+    /// The PLC's build and start time, None before it wrote them
+    pub fn read_plc_info(&self) -> Option<PlcInfo> {
+        self.inner.table.read_plc_info()
+    }
+
+    /// Raw process image as of the PLC's last sync cycle, None before its bus went to OP
+    pub fn read_process_image(&self) -> Option<ProcessImage> {
+        self.inner.table.read_process_image()
+    }
+
+    /// Next variable-length payload for this reader (shared between its clones)
+    pub fn pop_blob(&self) -> Option<Blob> {
+        let mut cursor = self.inner.blobs.lock().unwrap();
+        let lost = cursor.lost;
+        let blob = self.inner.table.next_blob(&mut cursor);
+        if cursor.lost != lost {
+            log::warn!("Fell behind on the shm payload area and skipped ahead ({} times so far)", cursor.lost);
+        }
+        blob
+    }
+}
+
+struct WriterInner {
+    table: TagTable,
+    writer: Mutex<()>, // one publish at a time, also guards the command consumer and event producer ends
+}
+
+#[derive(Clone)]
+pub struct ShmWriter {
+    inner: Arc<WriterInner>,
+}
+
+impl ShmWriter {
+    /// Create the region, see TagTable::create. Only the PLC should call this.
+    pub fn create(name: &str, defs: &[TagDef], access: &Access) -> io::Result<Self> {
+        let table = TagTable::create(name, defs, access)?;
+        Ok(Self { inner: Arc::new(WriterInner { table, writer: Mutex::new(()) }) })
+    }
+
+    pub fn tags(&self) -> &[TagDef] {
+        self.inner.table.tags()
+    }
+
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.inner.table.index_of(name)
+    }
+
+    pub fn read(&self, idx: usize) -> TagValue {
+        self.inner.table.read(idx)
+    }
+
+    pub fn read_all(&self) -> Vec<TagValue> {
+        self.inner.table.read_all()
+    }
+
+    /// Atomic update, see TagTable::write_many
+    pub fn write_many(&self, values: &[(usize, TagValue)]) -> Result<(), String> {
+        let _writer = self.inner.writer.lock().unwrap();
+        self.inner.table.publish(values)
+    }
+
+    /// Like write_many but with explicit timestamps and quality
+    pub fn write_samples(&self, samples: &[(usize, TagSample)]) -> Result<(), String> {
+        let _writer = self.inner.writer.lock().unwrap();
+        self.inner.table.publish_samples(samples)
+    }
+
+    pub fn write(&self, idx: usize, value: TagValue) -> Result<(), String> {
+        self.write_many(&[(idx, value)])
+    }
+
+    pub fn set<T: TagPrimitive>(&self, idx: usize, value: T) -> Result<(), String> {
+        self.write(idx, value.into_value())
+    }
+
+    pub fn set_by_name<T: TagPrimitive>(&self, name: &str, value: T) -> Result<(), String> {
+        let idx = self.index_of(name).ok_or_else(|| format!("No tag named '{}'", name))?;
+        self.set(idx, value)
+    }
+
+    pub fn pop_command(&self) -> Option<RingItem> {
+        let _writer = self.inner.writer.lock().unwrap();
+        self.inner.table.pop_command()
+    }
+
+    pub fn ack_command(&self, seq: u32) {
+        self.inner.table.ack_command(seq)
+    }
+
+    pub fn push_event(&self, item: RingItem) {
+        let _writer = self.inner.writer.lock().unwrap();
+        self.inner.table.push_event(item)
+    }
+
+    pub fn write_io(&self, channels: &[IoChannel]) {
+        let _writer = self.inner.writer.lock().unwrap();
+        self.inner.table.write_io(channels)
+    }
+
+    pub fn write_bus_diag(&self, diag: &BusDiagnostics) {
+        let _writer = self.inner.writer.lock().unwrap();
+        self.inner.table.write_bus_diag(diag)
+    }
+
+    pub fn write_plc_info(&self, info: &PlcInfo) {
+        let _writer = self.inner.writer.lock().unwrap();
+        self.inner.table.write_plc_info(info)
+    }
+
+    pub fn write_process_image(&self, image: &ProcessImage) {
+        let _writer = self.inner.writer.lock().unwrap();
+        self.inner.table.write_process_image(image)
+    }
+
+    pub fn push_blob(&self, kind: u16, payload: &[u8]) -> Result<(), String> {
+        let _writer = self.inner.writer.lock().unwrap();
+        self.inner.table.push_blob(kind, payload)
+    }
+
+    /// Tell consumers the PLC is alive, once per sync cycle
+    pub fn heartbeat(&self) {
+        self.inner.table.beat_plc()
+    }
+
+    pub fn consumer_heartbeat(&self) -> u32 {
+        self.inner.table.consumer_heartbeat()
+    }
+}
@@ -0,0 +1,46 @@
+// Liveness between the PLC and its consumers.
+//
+// Each side bumps its own heartbeat counter periodically (a region header field for shm, a frame for uds) and
+// watches the other side's. A peer is alive while its counter keeps changing, and stale once it stood still for
+// longer than `[ipc] heartbeat_timeout_ms`. The counter's value means nothing, only that it moves.
+use std::time::{Duration, Instant};
+
+/// Watches one peer's heartbeat counter
+#[derive(Debug, Clone)]
+pub struct Liveness {
+    timeout: Duration,
+    last: Option<u32>,
+    changed_at: Option<Instant>, // None until the counter moved at least once
+    alive: bool,
+}
+
+impl Liveness {
+    /// Starts out stale, a counter left behind by a previous run doesn't count until it moves
+    pub fn new(timeout: Duration) -> Self {
+        Self { timeout, last: None, changed_at: None, alive: false }
+    }
+
+    /// Feed the peer's current counter. Returns the new state when it changed, so callers can log transitions.
+    pub fn update(&mut self, counter: u32) -> Option<bool> {
+        if self.last.is_some_and(|last| last != counter) {
+            self.changed_at = Some(Instant::now());
+        }
+        self.last = Some(counter);
+
+        let alive = self.changed_at.is_some_and(|at| at.elapsed() < self.timeout);
+        if alive != self.alive {
+            self.alive = alive;
+            return Some(alive);
+        }
+        None
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.alive
+    }
+
+    /// How long the counter has stood still, None if it never moved
+    pub fn silent_for(&self) -> Option<Duration> {
+        self.changed_at.map(|at| at.elapsed())
+    }
+}
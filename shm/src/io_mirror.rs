@@ -0,0 +1,177 @@
+// Mirror of every terminal channel in the shm region, so consumers can expose all I/O without a tag per signal.
+//
+// The PLC rewrites the whole array every sync cycle. Channels are identified by (bus, kind, terminal, channel),
+// `terminal` counting terminals of the same bus and kind in bus order. Guarded by a sequence lock: `seq` is odd
+// while the PLC is writing, readers retry until they copied the array with the same even `seq` on both sides.
+use crate::region::backoff;
+use bytemuck::{Pod, Zeroable};
+use std::marker::PhantomData;
+use std::mem;
+use std::sync::atomic::{AtomicU32, Ordering, fence};
+
+pub const MAX_IO_CHANNELS: usize = 256;
+
+// IoChannel::bus
+pub const IO_BUS_KBUS: u8 = 1; // behind the BK1120 coupler
+pub const IO_BUS_EBUS: u8 = 2; // EtherCAT terminals
+
+// IoChannel::kind
+pub const IO_DI: u8 = 1;    // value 0/1
+pub const IO_DO: u8 = 2;    // value 0/1
+pub const IO_AI: u8 = 3;    // value in the terminal's unit (mA for current inputs), status = terminal status word
+pub const IO_SMART: u8 = 4; // intelligent terminal (KL6581...), no value, status = status byte
+
+// IoChannel::status of IO_AI channels, the EL30xx status bits as hal::term_cfg's Checker packs them
+pub const AI_STATUS_TXPDO_TOGGLE: u32 = 1 << 0;
+pub const AI_STATUS_TXPDO_STATE: u32 = 1 << 1; // the terminal says its input data is invalid
+pub const AI_STATUS_ERROR: u32 = 1 << 2;
+pub const AI_STATUS_OVERRANGE: u32 = 1 << 7;
+pub const AI_STATUS_UNDERRANGE: u32 = 1 << 8; // below 4 mA on 4..20 mA inputs, a broken wire
+// IoChannel::status of any channel, set by the PLC rather than the terminal
+pub const IO_STATUS_BUS_DOWN: u32 = 1 << 31; // the channel's bus failed on the last cycle, the value is the last one read
+pub const IO_STATUS_FORCED: u32 = 1 << 30;   // DO channel forced by an operator, the value is the forced one
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Pod, Zeroable)]
+pub struct IoChannel {
+    pub bus: u8,
+    pub kind: u8,
+    pub terminal: u16,
+    pub channel: u16, // 1-based like TermChannel, 0 for terminal-wide entries
+    pub _pad: u16,
+    pub status: u32,
+    pub _reserved: u32,
+    pub value: f64,
+}
+
+impl IoChannel {
+    pub fn address(&self) -> IoAddress {
+        IoAddress { bus: self.bus, kind: self.kind, terminal: self.terminal, channel: self.channel }
+    }
+
+    /// See IoAddress::path
+    pub fn path(&self) -> String {
+        self.address().path()
+    }
+}
+
+/// Which channel an IoChannel (or a force command, see RingItem::force) is about
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct IoAddress {
+    pub bus: u8,
+    pub kind: u8,
+    pub terminal: u16,
+    pub channel: u16,
+}
+
+const BUS_NAMES: [(u8, &str); 2] = [(IO_BUS_KBUS, "KBus"), (IO_BUS_EBUS, "EBus")];
+const KIND_NAMES: [(u8, &str); 4] = [(IO_DI, "DI"), (IO_DO, "DO"), (IO_AI, "AI"), (IO_SMART, "Smart")];
+
+impl IoAddress {
+    /// Stable name for the channel, e.g. "EBus/AI0/Ch2", used as OPC UA node id by the server
+    pub fn path(&self) -> String {
+        let bus = BUS_NAMES.iter().find(|(bus, _)| *bus == self.bus).map_or("Bus", |(_, name)| name);
+        let kind = KIND_NAMES.iter().find(|(kind, _)| *kind == self.kind).map_or("IO", |(_, name)| name);
+        if self.channel == 0 {
+            format!("{}/{}{}", bus, kind, self.terminal)
+        }
+        else {
+            format!("{}/{}{}/Ch{}", bus, kind, self.terminal, self.channel)
+        }
+    }
+
+    /// Inverse of `path`, for channel names typed in by operators. Case insensitive.
+    pub fn parse(path: &str) -> Option<Self> {
+        let mut parts = path.split('/');
+        let (bus, terminal, channel) = (parts.next()?, parts.next()?, parts.next());
+        if parts.next().is_some() {
+            return None;
+        }
+
+        let bus = BUS_NAMES.iter().find(|(_, name)| name.eq_ignore_ascii_case(bus))?.0;
+        let (kind, terminal) = KIND_NAMES.iter().find_map(|(kind, name)| {
+            let (prefix, number) = terminal.split_at_checked(name.len())?;
+            prefix.eq_ignore_ascii_case(name).then_some((*kind, number))
+        })?;
+        let channel = match channel {
+            Some(channel) => {
+                let (prefix, number) = channel.split_at_checked(2)?;
+                if !prefix.eq_ignore_ascii_case("ch") {
+                    return None;
+                }
+                number.parse().ok()?
+            }
+            None => 0,
+        };
+        Some(Self { bus, kind, terminal: terminal.parse().ok()?, channel })
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct IoMirrorHeader {
+    pub seq: u32,
+    pub count: u32,
+}
+
+const MIRROR_HEADER_LEN: usize = mem::size_of::<IoMirrorHeader>();
+
+/// Bytes taken by a mirror of up to `capacity` items
+pub(crate) const fn mirror_len<T>(capacity: usize) -> usize {
+    MIRROR_HEADER_LEN + capacity * mem::size_of::<T>()
+}
+
+/// View over a mirror of up to `capacity` items at `base` (start of its IoMirrorHeader) inside a live mapping.
+/// The I/O mirror is one of IoChannel, the bus diagnostics (see bus_diag.rs) reuse it with a single item.
+pub(crate) struct MirrorRef<T> {
+    base: *mut u8,
+    capacity: usize,
+    _item: PhantomData<T>,
+}
+
+impl<T: Pod> MirrorRef<T> {
+    pub fn new(base: *mut u8, capacity: usize) -> Self {
+        Self { base, capacity, _item: PhantomData }
+    }
+
+    fn counter(&self, offset: usize) -> &AtomicU32 {
+        // SAFETY: `base` is 8-byte aligned inside the mapping (checked by the region layout)
+        unsafe { AtomicU32::from_ptr(self.base.add(offset) as *mut u32) }
+    }
+
+    fn seq(&self) -> &AtomicU32 { self.counter(mem::offset_of!(IoMirrorHeader, seq)) }
+    fn count(&self) -> &AtomicU32 { self.counter(mem::offset_of!(IoMirrorHeader, count)) }
+
+    fn item(&self, idx: usize) -> *mut T {
+        unsafe { self.base.add(MIRROR_HEADER_LEN + idx * mem::size_of::<T>()) as *mut T }
+    }
+
+    /// PLC only. Anything past `capacity` is cut off.
+    pub fn write(&self, items: &[T]) {
+        let count = items.len().min(self.capacity);
+        let seq = self.seq().load(Ordering::Relaxed);
+        self.seq().store(seq.wrapping_add(1), Ordering::Relaxed); // odd: being written
+        fence(Ordering::Release);
+        for (idx, item) in items[..count].iter().enumerate() {
+            unsafe { self.item(idx).write_volatile(*item) };
+        }
+        self.count().store(count as u32, Ordering::Relaxed);
+        self.seq().store(seq.wrapping_add(2), Ordering::Release);
+    }
+
+    pub fn read(&self) -> Vec<T> {
+        let mut spins: u32 = 0;
+        loop {
+            let before = self.seq().load(Ordering::Acquire);
+            if before.is_multiple_of(2) {
+                let count = (self.count().load(Ordering::Relaxed) as usize).min(self.capacity);
+                let items: Vec<T> = (0..count).map(|idx| unsafe { self.item(idx).read_volatile() }).collect();
+                fence(Ordering::Acquire);
+                if self.seq().load(Ordering::Relaxed) == before {
+                    return items;
+                }
+            }
+            backoff(&mut spins);
+        }
+    }
+}
@@ -0,0 +1,53 @@
+// Single source of truth for the PLC <-> OPC UA server IPC: the shared memory region and the socket alternative.
+// Both binaries depend on this crate, so the layout can't drift between them anymore. It also holds what every
+// interface has to agree on besides the IPC, who may do what (users.rs), how they log
+// and trace (logging.rs, telemetry.rs), whether the clock their timestamps come from can be trusted (time_sync.rs),
+// which project they run (project.rs) and how their parts come up in order (startup.rs).
+pub mod tags;
+pub mod region;
+pub mod ring;
+pub mod blob;
+pub mod io_mirror;
+pub mod bus_diag;
+pub mod plc_info;
+pub mod process_image;
+pub mod platform;
+pub mod config;
+pub mod transport;
+pub mod handle;
+pub mod heartbeat;
+pub mod access;
+pub mod users;
+pub mod sim;
+pub mod logging;
+pub mod telemetry;
+pub mod time_sync;
+pub mod project;
+pub mod startup;
+#[cfg(unix)]
+pub mod uds;
+
+pub use tags::{Quality, TagDef, TagPrimitive, TagSample, TagText, TagType, TagValue, TAG_ALARM, TAG_EXTERNAL, TAG_WRITABLE, TOTALS_FOLDER};
+pub use region::{TagTable, SCHEMA_VERSION, MAGIC};
+pub use ring::RingItem;
+pub use blob::Blob;
+pub use io_mirror::{IoAddress, IoChannel};
+pub use bus_diag::{BusDiagnostics, SubDeviceDiagnostics};
+pub use plc_info::PlcInfo;
+pub use process_image::{ImageField, ImageSubDevice, ProcessImage};
+pub use config::{IpcConfig, Transport};
+pub use transport::{Publisher, Subscriber};
+pub use handle::{ShmReader, ShmWriter};
+pub use heartbeat::Liveness;
+pub use access::Access;
+pub use users::{Action, Role, Users};
+pub use project::Project;
+pub use startup::{Startup, Step};
+
+// Name of the region, a tmpfs file on unix and a named file mapping object on Windows
+#[cfg(unix)]
+pub const SHM_PATH: &str = "/dev/shm/shared_plc_data";
+#[cfg(windows)]
+pub const SHM_PATH: &str = "Local\\shared_plc_data";
+
+pub const SOCKET_PATH: &str = "/tmp/gipop_plc.sock";
@@ -0,0 +1,416 @@
+// The `[logging]` section of gipop.toml and the logger every binary installs in place of env_logger. Filtering stays
+// env_logger's (`level` in the config, RUST_LOG overrides it: "info,plc::ctrl_loop=debug"), the output goes to stderr,
+// journald or syslog.
+//
+// [logging]
+// target = "stderr"         # stderr, journald or syslog
+// level = "info"
+// syslog = "/dev/log"       # syslog only: a unix socket path, or host:port of a collector (UDP)
+//
+// Log statements carry what they're about as key-value fields, log::warn!(subdevice = "BK1120", slot = 3; "..."), so
+// logs can be searched by field instead of by wording. journald gets them as fields of their own (SUBDEVICE=BK1120,
+// `journalctl -t gipop_plc SUBDEVICE=BK1120`), syslog as RFC 5424 structured data ([gipop@32473 subdevice="BK1120"]),
+// stderr appended as subdevice=BK1120. Mind that where journald owns /dev/log it only understands the older syslog
+// format, use target = "journald" there. If the journal or syslog can't be reached a record goes to stderr instead.
+//
+// Levels can change while the process runs, the PLC's bus stays in OP: set_level() from a command (ITEM_LOG_LEVEL,
+// sent by the gateway's PUT /api/log-level and the OPC UA server's SetLogLevel) adds a level for one module or all of
+// them on top of `level`, and with reload_on_sighup() a SIGHUP reads `level` again and drops what was set that way.
+// The target stays what the process started with. Whatever else a binary reloads on SIGHUP it hangs on on_sighup().
+use serde::Deserialize;
+use std::io::{self, Write};
+use std::sync::{Mutex, RwLock};
+use std::{fs, path::Path, path::PathBuf};
+
+use log::kv::{self, Key, Value, VisitSource};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::tags::{iso_time, now_ms};
+
+#[cfg(unix)]
+pub const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+const SD_ID: &str = "gipop@32473"; // 32473 is the private enterprise number set aside for examples (RFC 5612)
+const FACILITY_DAEMON: u8 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogTarget {
+    #[default]
+    Stderr,
+    Journald,
+    Syslog,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    pub target: LogTarget,
+    pub level: String,  // env_logger syntax, RUST_LOG wins when set
+    pub syslog: String, // starting with / a unix datagram socket, otherwise host:port for UDP
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self { target: LogTarget::default(), level: "info".to_string(), syslog: "/dev/log".to_string() }
+    }
+}
+
+impl LoggingConfig {
+    /// Reads the `[logging]` section of `path`. A missing file or section means defaults.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        crate::config::load_section(path, "logging")
+    }
+}
+
+// What the installed logger lets through: `level` (or RUST_LOG) with the levels set at runtime on top, last one wins
+static FILTER: RwLock<Option<Filtering>> = RwLock::new(None);
+static CONFIG_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+static SPEC: Mutex<Option<String>> = Mutex::new(None); // from the command line, over RUST_LOG and the config
+static SIGHUP_HOOKS: Mutex<Vec<Box<dyn Fn() + Send>>> = Mutex::new(Vec::new());
+
+struct Filtering {
+    spec: String,                        // RUST_LOG, or `level` from the config
+    runtime: Vec<(String, LevelFilter)>, // module ("" for all of them) and its level, from set_level()
+    filter: env_filter::Filter,
+}
+
+impl Filtering {
+    fn new(spec: String, runtime: Vec<(String, LevelFilter)>) -> Self {
+        let mut builder = env_filter::Builder::new();
+        builder.parse(&spec);
+        for (module, level) in &runtime {
+            if module.is_empty() {
+                builder.filter_level(*level);
+            }
+            else {
+                builder.filter_module(module, *level);
+            }
+        }
+        let filter = builder.build();
+        log::set_max_level(filter.filter());
+        Self { spec, runtime, filter }
+    }
+}
+
+/// Installs the logger configured in `path`'s `[logging]`, `identifier` names the process in journald and syslog
+/// ("gipop_plc"). Never fails: with a broken config or an unreachable target it logs to stderr and says why.
+pub fn init(path: impl AsRef<Path>, identifier: &str) {
+    let path = path.as_ref();
+    let (config, config_error) = match LoggingConfig::load(path) {
+        Ok(config) => (config, None),
+        Err(e) => (LoggingConfig::default(), Some(e)),
+    };
+    let (sink, sink_error) = match Sink::open(&config) {
+        Ok(sink) => (sink, None),
+        Err(e) => (Sink::Stderr, Some(e)),
+    };
+    let logger = Logger { sink, identifier: identifier.to_string(), hostname: hostname() };
+    if log::set_boxed_logger(Box::new(logger)).is_err() {
+        return; // one is installed already
+    }
+    *FILTER.write().unwrap() = Some(Filtering::new(base_spec(&config), Vec::new()));
+    *CONFIG_PATH.lock().unwrap() = Some(path.to_path_buf());
+    for error in [config_error, sink_error].into_iter().flatten() {
+        log::error!("{}, logging to stderr", error);
+    }
+}
+
+fn base_spec(config: &LoggingConfig) -> String {
+    if let Some(spec) = SPEC.lock().unwrap().clone() {
+        return spec;
+    }
+    std::env::var("RUST_LOG").unwrap_or_else(|_| config.level.clone())
+}
+
+/// Filters by `spec` (RUST_LOG syntax) instead of `level` and RUST_LOG for the rest of the process, reloads included.
+/// For a binary's --log-level, levels set with set_level() still go on top
+pub fn set_spec(spec: &str) {
+    *SPEC.lock().unwrap() = Some(spec.to_string());
+    let mut guard = FILTER.write().unwrap();
+    if let Some(filtering) = guard.take() {
+        *guard = Some(Filtering::new(spec.to_string(), filtering.runtime));
+    }
+}
+
+/// Logs `module` (a module path or its start, "plc::ctrl_loop", "" for every module) at `level` from now on, over
+/// what `level` in the config says. None drops the level set for `module` this way, for "" every one of them.
+pub fn set_level(module: &str, level: Option<LevelFilter>) -> Result<(), String> {
+    if module.contains(|c: char| c.is_whitespace() || c == ',' || c == '=' || c == '/') {
+        return Err(format!("'{}' isn't a module path", module));
+    }
+    let mut guard = FILTER.write().unwrap();
+    let Some(filtering) = guard.take() else {
+        return Err("No logger installed".to_string());
+    };
+    let mut runtime = filtering.runtime;
+    if module.is_empty() && level.is_none() {
+        runtime.clear();
+    }
+    else {
+        runtime.retain(|(set, _)| set != module);
+    }
+    if let Some(level) = level {
+        runtime.push((module.to_string(), level));
+    }
+    *guard = Some(Filtering::new(filtering.spec, runtime));
+    Ok(())
+}
+
+/// A level as commands name it: "off", "error" to "trace" (any case), "" for what the config says
+pub fn parse_level(level: &str) -> Result<Option<LevelFilter>, String> {
+    if level.is_empty() {
+        return Ok(None);
+    }
+    level.parse().map(Some).map_err(|_| format!("'{}' isn't a log level: off, error, warn, info, debug or trace", level))
+}
+
+/// The levels in effect, in RUST_LOG syntax: "info,plc::ctrl_loop=debug"
+pub fn levels() -> String {
+    let guard = FILTER.read().unwrap();
+    let Some(filtering) = guard.as_ref() else {
+        return String::new();
+    };
+    let runtime = filtering.runtime.iter().map(|(module, level)| {
+        let level = level.as_str().to_lowercase();
+        if module.is_empty() { level } else { format!("{}={}", module, level) }
+    });
+    std::iter::once(filtering.spec.clone()).chain(runtime).filter(|part| !part.is_empty()).collect::<Vec<_>>().join(",")
+}
+
+/// Reads `level` from the config again (RUST_LOG and set_spec() still win) and drops the levels set with set_level()
+pub fn reload() -> Result<(), String> {
+    let Some(path) = CONFIG_PATH.lock().unwrap().clone() else {
+        return Err("No logger installed".to_string());
+    };
+    let config = LoggingConfig::load(&path)?;
+    *FILTER.write().unwrap() = Some(Filtering::new(base_spec(&config), Vec::new()));
+    log::info!("Log levels reloaded from {}: {}", path.display(), levels());
+    Ok(())
+}
+
+/// Runs `reload` on every SIGHUP after the log levels, for the rest of a binary's config. Needs reload_on_sighup()
+pub fn on_sighup(reload: impl Fn() + Send + 'static) {
+    SIGHUP_HOOKS.lock().unwrap().push(Box::new(reload));
+}
+
+/// reload() on every SIGHUP, from a thread of its own. For the long running binaries, SIGHUP no longer ends them
+#[cfg(unix)]
+pub fn reload_on_sighup() {
+    let mut signals = match signal_hook::iterator::Signals::new([signal_hook::consts::SIGHUP]) {
+        Ok(signals) => signals,
+        Err(e) => {
+            log::error!("Can't handle SIGHUP, log levels only change through commands: {}", e);
+            return;
+        }
+    };
+    std::thread::spawn(move || {
+        for _ in signals.forever() {
+            if let Err(e) = reload() {
+                log::error!("Log levels not reloaded: {}", e);
+            }
+            for hook in SIGHUP_HOOKS.lock().unwrap().iter() {
+                hook();
+            }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub fn reload_on_sighup() {}
+
+enum Sink {
+    Stderr,
+    #[cfg(unix)]
+    Journald(std::os::unix::net::UnixDatagram),
+    #[cfg(unix)]
+    SyslogUnix(std::os::unix::net::UnixDatagram),
+    SyslogUdp(std::net::UdpSocket),
+}
+
+impl Sink {
+    fn open(config: &LoggingConfig) -> Result<Self, String> {
+        match config.target {
+            LogTarget::Stderr => Ok(Sink::Stderr),
+            #[cfg(unix)]
+            LogTarget::Journald => {
+                let socket = std::os::unix::net::UnixDatagram::unbound().map_err(|e| e.to_string())?;
+                socket.connect(JOURNAL_SOCKET).map_err(|e| format!("Failed to reach journald at {}: {}", JOURNAL_SOCKET, e))?;
+                Ok(Sink::Journald(socket))
+            }
+            #[cfg(not(unix))]
+            LogTarget::Journald => Err("journald is only there on Linux".to_string()),
+            #[cfg(unix)]
+            LogTarget::Syslog if config.syslog.starts_with('/') => {
+                let socket = std::os::unix::net::UnixDatagram::unbound().map_err(|e| e.to_string())?;
+                socket.connect(&config.syslog).map_err(|e| format!("Failed to reach syslog at {}: {}", config.syslog, e))?;
+                Ok(Sink::SyslogUnix(socket))
+            }
+            LogTarget::Syslog => {
+                let socket = std::net::UdpSocket::bind("0.0.0.0:0").map_err(|e| e.to_string())?;
+                socket.connect(&config.syslog).map_err(|e| format!("Failed to reach syslog at {}: {}", config.syslog, e))?;
+                Ok(Sink::SyslogUdp(socket))
+            }
+        }
+    }
+}
+
+struct Logger {
+    sink: Sink,
+    identifier: String,
+    hostname: String,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        FILTER.read().unwrap().as_ref().is_some_and(|filtering| filtering.filter.enabled(metadata))
+    }
+
+    fn log(&self, record: &Record) {
+        if !FILTER.read().unwrap().as_ref().is_some_and(|filtering| filtering.filter.matches(record)) {
+            return;
+        }
+        let mut fields = Fields::default();
+        _ = record.key_values().visit(&mut fields);
+        let sent = match &self.sink {
+            Sink::Stderr => Ok(()),
+            #[cfg(unix)]
+            Sink::Journald(socket) => socket.send(&self.journal_entry(record, &fields.0)).map(drop),
+            #[cfg(unix)]
+            Sink::SyslogUnix(socket) => socket.send(self.syslog_message(record, &fields.0).as_bytes()).map(drop),
+            Sink::SyslogUdp(socket) => socket.send(self.syslog_message(record, &fields.0).as_bytes()).map(drop),
+        };
+        // stderr is the target, or the last resort (journald refuses entries too big for one datagram, for one)
+        if matches!(self.sink, Sink::Stderr) || sent.is_err() {
+            _ = writeln!(io::stderr().lock(), "{}", stderr_line(record, &fields.0));
+        }
+    }
+
+    fn flush(&self) {
+        _ = io::stderr().flush();
+    }
+}
+
+impl Logger {
+    // journald's native protocol: one datagram of FIELD=value lines, values with line breaks length-prefixed
+    fn journal_entry(&self, record: &Record, fields: &[(String, String)]) -> Vec<u8> {
+        let mut entry = Vec::new();
+        let mut add = |name: &str, value: &str| {
+            if value.contains('\n') {
+                entry.extend_from_slice(name.as_bytes());
+                entry.push(b'\n');
+                entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+            }
+            else {
+                entry.extend_from_slice(name.as_bytes());
+                entry.push(b'=');
+            }
+            entry.extend_from_slice(value.as_bytes());
+            entry.push(b'\n');
+        };
+        add("MESSAGE", &record.args().to_string());
+        add("PRIORITY", &severity(record.level()).to_string());
+        add("SYSLOG_IDENTIFIER", &self.identifier);
+        add("TARGET", record.target());
+        if let Some(file) = record.file() {
+            add("CODE_FILE", file);
+        }
+        if let Some(line) = record.line() {
+            add("CODE_LINE", &line.to_string());
+        }
+        for (key, value) in fields {
+            add(&journal_field(key), value);
+        }
+        entry
+    }
+
+    // RFC 5424: <PRI>1 TIMESTAMP HOSTNAME APP-NAME PROCID MSGID [SD-ID key="value"...] MSG
+    fn syslog_message(&self, record: &Record, fields: &[(String, String)]) -> String {
+        let structured = if fields.is_empty() {
+            "-".to_string()
+        }
+        else {
+            let params: Vec<String> = fields.iter()
+                .map(|(key, value)| format!(" {}=\"{}\"", sd_name(key), sd_escape(value)))
+                .collect();
+            format!("[{}{}]", SD_ID, params.concat())
+        };
+        format!(
+            "<{}>1 {} {} {} {} - {} {}",
+            FACILITY_DAEMON * 8 + severity(record.level()),
+            iso_time(now_ms()),
+            self.hostname,
+            self.identifier,
+            std::process::id(),
+            structured,
+            record.args(),
+        )
+    }
+}
+
+// The record's key-values, in the order they were written
+#[derive(Default)]
+struct Fields(Vec<(String, String)>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.push((key.to_string(), value.to_string()));
+        Ok(())
+    }
+}
+
+// [2024-05-01T12:00:00.000Z WARN  plc::ctrl_loop] K-bus terminal subdevice=BK1120 slot=3 terminal=6581
+fn stderr_line(record: &Record, fields: &[(String, String)]) -> String {
+    let mut line = format!("[{} {:<5} {}] {}", iso_time(now_ms()), record.level(), record.target(), record.args());
+    for (key, value) in fields {
+        if value.is_empty() || value.contains([' ', '"', '=']) {
+            line += &format!(" {}={:?}", key, value);
+        }
+        else {
+            line += &format!(" {}={}", key, value);
+        }
+    }
+    line
+}
+
+// syslog severity, also journald's PRIORITY
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+// journald field names are upper case letters, digits and underscores, not starting with an underscore or a digit
+fn journal_field(key: &str) -> String {
+    let name: String = key.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect();
+    let name = name.trim_start_matches(|c: char| c == '_' || c.is_ascii_digit());
+    if name.is_empty() { "FIELD".to_string() } else { name.to_string() }
+}
+
+// SD-NAMEs are at most 32 printable ASCII characters without '=', ' ', ']' and '"'
+fn sd_name(key: &str) -> String {
+    key.chars().filter(|c| c.is_ascii_graphic() && !matches!(c, '=' | ']' | '"')).take(32).collect()
+}
+
+fn sd_escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace(']', "\\]")
+}
+
+#[cfg(unix)]
+fn hostname() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .or_else(|_| fs::read_to_string("/etc/hostname"))
+        .map(|name| name.trim().to_string())
+        .ok()
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+#[cfg(not(unix))]
+fn hostname() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_else(|_| "-".to_string())
+}
@@ -0,0 +1,161 @@
+// OS specific backing of the shm region. Everything above this module only sees a mapped byte slice.
+//
+// Unix: a regular file (normally under /dev/shm, i.e. tmpfs) mapped with memmap2, mode and group from `Access`.
+// Windows: a pagefile-backed named file mapping. `name` is the kernel object name, e.g. "Local\\shared_plc_data".
+// Windows destroys the mapping once the last handle to it closes, so the creator has to keep its Mapping alive.
+pub use imp::Mapping;
+
+#[cfg(unix)]
+mod imp {
+    use crate::access::Access;
+    use memmap2::MmapMut;
+    use std::fs::OpenOptions;
+    use std::io;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::ops::{Deref, DerefMut};
+
+    pub struct Mapping {
+        mmap: MmapMut,
+    }
+
+    impl Mapping {
+        /// Create (or truncate and recreate) a zeroed region of `len` bytes
+        pub fn create(name: &str, len: usize, access: &Access) -> io::Result<Self> {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)    // create if it doesn't exist
+                .truncate(true)  // zero everything left over from a previous run
+                .mode(access.mode()) // never world accessible, not even briefly
+                .open(name)?;
+            access.apply(&file)?; // mode() only applies to new files, umask aside
+            file.set_len(len as u64)?;
+            Ok(Self { mmap: unsafe { MmapMut::map_mut(&file)? } }) // unsafe because of potential UB if file is modified
+        }
+
+        pub fn open(name: &str) -> io::Result<Self> {
+            let file = OpenOptions::new().read(true).write(true).open(name)?;
+            Ok(Self { mmap: unsafe { MmapMut::map_mut(&file)? } })
+        }
+
+        pub fn flush(&self) -> io::Result<()> {
+            self.mmap.flush()
+        }
+    }
+
+    impl Deref for Mapping {
+        type Target = [u8];
+        fn deref(&self) -> &[u8] { &self.mmap }
+    }
+
+    impl DerefMut for Mapping {
+        fn deref_mut(&mut self) -> &mut [u8] { &mut self.mmap }
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use crate::access::Access;
+    use std::ops::{Deref, DerefMut};
+    use std::{io, mem, ptr, slice};
+    use windows_sys::Win32::Foundation::{CloseHandle, GetLastError, ERROR_ALREADY_EXISTS, HANDLE, INVALID_HANDLE_VALUE};
+    use windows_sys::Win32::System::Memory::{
+        CreateFileMappingW, FlushViewOfFile, MapViewOfFile, OpenFileMappingW, UnmapViewOfFile, VirtualQuery,
+        FILE_MAP_ALL_ACCESS, MEMORY_BASIC_INFORMATION, MEMORY_MAPPED_VIEW_ADDRESS, PAGE_READWRITE,
+    };
+
+    pub struct Mapping {
+        handle: HANDLE,
+        ptr: *mut u8,
+        len: usize,
+    }
+
+    // The view is plain shared memory, synchronization is done by the region on top of it
+    unsafe impl Send for Mapping {}
+    unsafe impl Sync for Mapping {}
+
+    fn wide(name: &str) -> Vec<u16> {
+        name.encode_utf16().chain(Some(0)).collect()
+    }
+
+    impl Mapping {
+        /// Create a zeroed region of `len` bytes, or take over and zero an existing one that's big enough
+        /// `access` has nothing to do here, "Local\\" objects already stay within the creating session
+        pub fn create(name: &str, len: usize, _access: &Access) -> io::Result<Self> {
+            let name = wide(name);
+            let handle = unsafe {
+                CreateFileMappingW(
+                    INVALID_HANDLE_VALUE, // backed by the pagefile, not a file on disk
+                    ptr::null(),
+                    PAGE_READWRITE,
+                    (len as u64 >> 32) as u32,
+                    len as u32,
+                    name.as_ptr(),
+                )
+            };
+            if handle.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            let existed = unsafe { GetLastError() } == ERROR_ALREADY_EXISTS;
+
+            let mut mapping = Self::map(handle)?;
+            if mapping.len < len {
+                // someone else (an older PLC instance?) holds a smaller mapping under this name, its size can't change
+                return Err(io::Error::other(format!("Existing mapping is {} bytes, need {}", mapping.len, len)));
+            }
+            if existed {
+                mapping.fill(0); // fresh mappings are zeroed by the OS, leftovers are not
+            }
+            Ok(mapping)
+        }
+
+        pub fn open(name: &str) -> io::Result<Self> {
+            let name = wide(name);
+            let handle = unsafe { OpenFileMappingW(FILE_MAP_ALL_ACCESS, 0, name.as_ptr()) };
+            if handle.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            Self::map(handle)
+        }
+
+        fn map(handle: HANDLE) -> io::Result<Self> {
+            let view = unsafe { MapViewOfFile(handle, FILE_MAP_ALL_ACCESS, 0, 0, 0) };
+            if view.Value.is_null() {
+                let err = io::Error::last_os_error();
+                unsafe { CloseHandle(handle) };
+                return Err(err);
+            }
+
+            // the mapping size isn't exposed directly, the view's region size is it rounded up to a page
+            let mut info: MEMORY_BASIC_INFORMATION = unsafe { mem::zeroed() };
+            unsafe { VirtualQuery(view.Value, &mut info, mem::size_of::<MEMORY_BASIC_INFORMATION>()) };
+
+            Ok(Self { handle, ptr: view.Value as *mut u8, len: info.RegionSize })
+        }
+
+        pub fn flush(&self) -> io::Result<()> {
+            if unsafe { FlushViewOfFile(self.ptr as *const _, self.len) } == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+    }
+
+    impl Drop for Mapping {
+        fn drop(&mut self) {
+            unsafe {
+                UnmapViewOfFile(MEMORY_MAPPED_VIEW_ADDRESS { Value: self.ptr as *mut _ });
+                CloseHandle(self.handle);
+            }
+        }
+    }
+
+    impl Deref for Mapping {
+        type Target = [u8];
+        fn deref(&self) -> &[u8] { unsafe { slice::from_raw_parts(self.ptr, self.len) } }
+    }
+
+    impl DerefMut for Mapping {
+        fn deref_mut(&mut self) -> &mut [u8] { unsafe { slice::from_raw_parts_mut(self.ptr, self.len) } }
+    }
+}
@@ -0,0 +1,61 @@
+// Who the PLC is: its build, the project it runs (project.rs) and when it started. Written once by the PLC at startup, consumers show it next to the
+// run state. Lives in the region like the bus diagnostics, over the socket it's sent to every consumer on connect.
+use bytemuck::{Pod, Zeroable};
+
+use crate::tags::now_ms;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Pod, Zeroable)]
+pub struct PlcInfo {
+    pub version: [u8; 16], // ascii, zero padded, the plc crate's version
+    pub commit: [u8; 16],  // git commit the PLC was built from, empty if it wasn't built from a checkout
+    pub profile: [u8; 8],  // "release" or "debug"
+    pub started_ms: u64,   // ms since the unix epoch
+    pub project: [u8; 32], // project name, empty when the PLC runs without one
+    pub project_version: [u8; 16],
+}
+
+impl PlcInfo {
+    /// Stamped with the current time as the start
+    pub fn new(version: &str, commit: &str, profile: &str) -> Self {
+        Self { version: padded(version), commit: padded(commit), profile: padded(profile), started_ms: now_ms(), ..Self::zeroed() }
+    }
+
+    pub fn with_project(mut self, name: &str, version: &str) -> Self {
+        self.project = padded(name);
+        self.project_version = padded(version);
+        self
+    }
+
+    pub fn version(&self) -> String {
+        unpadded(&self.version)
+    }
+
+    pub fn commit(&self) -> String {
+        unpadded(&self.commit)
+    }
+
+    pub fn profile(&self) -> String {
+        unpadded(&self.profile)
+    }
+
+    pub fn project(&self) -> String {
+        unpadded(&self.project)
+    }
+
+    pub fn project_version(&self) -> String {
+        unpadded(&self.project_version)
+    }
+}
+
+pub(crate) fn padded<const N: usize>(text: &str) -> [u8; N] {
+    let mut bytes = [0; N];
+    let len = text.len().min(N);
+    bytes[..len].copy_from_slice(&text.as_bytes()[..len]);
+    bytes
+}
+
+pub(crate) fn unpadded(bytes: &[u8]) -> String {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..len]).into_owned()
+}
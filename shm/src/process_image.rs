@@ -0,0 +1,149 @@
+// The raw EtherCAT process image in the shm region, for diagnostics that need the bits rather than the values in the
+// I/O mirror (gipop image). Every SubDevice's inputs and outputs as of the PLC's last cycle, plus where each terminal
+// channel, status and control word sits in them so a dump can say what every bit is.
+//
+// `data` is laid out like ethercrab's PDI: every SubDevice's inputs in bus order, then every SubDevice's outputs.
+// The outputs are what the PLC sends on the next cycle. The layout (SubDevices and fields) is fixed once the bus is
+// in OP, the data is rewritten every sync cycle under the same sequence lock as the I/O mirror.
+use bytemuck::{Pod, Zeroable};
+
+use crate::bus_diag::{MAX_BUS_SUBDEVICES, SUBDEVICE_NAME_LEN};
+use crate::io_mirror::IoAddress;
+use crate::plc_info::{padded, unpadded};
+
+pub const PROCESS_IMAGE_LEN: usize = 64; // bytes, inputs and outputs together, same as the PLC's PDI_LEN
+pub const MAX_IMAGE_FIELDS: usize = 256;
+pub const FIELD_LABEL_LEN: usize = 16;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Pod, Zeroable)]
+pub struct ProcessImage {
+    pub cycle: u64,       // process data cycle the data is from
+    pub inputs_len: u32,  // bytes at the start of `data` that are inputs, the outputs follow
+    pub outputs_len: u32,
+    pub data: [u8; PROCESS_IMAGE_LEN],
+    pub subdevice_count: u32,
+    pub field_count: u32,
+    pub subdevices: [ImageSubDevice; MAX_BUS_SUBDEVICES],
+    pub fields: [ImageField; MAX_IMAGE_FIELDS],
+}
+
+/// Where one SubDevice's inputs and outputs are in ProcessImage::data, in bytes
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Pod, Zeroable)]
+pub struct ImageSubDevice {
+    pub name: [u8; SUBDEVICE_NAME_LEN], // ascii, zero padded, e.g. "BK1120"
+    pub address: u16,
+    pub inputs_offset: u16,
+    pub inputs_len: u16,
+    pub outputs_offset: u16,
+    pub outputs_len: u16,
+    pub _pad: u16,
+}
+
+/// A run of bits in ProcessImage::data with a meaning: a terminal channel, or a status or control word
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Pod, Zeroable)]
+pub struct ImageField {
+    pub bit: u16, // first bit, from the start of `data`
+    pub bits: u16,
+    pub subdevice: u8, // index into ProcessImage::subdevices
+    pub bus: u8,       // IoChannel::bus and so on of the channel, kind 0 for fields that aren't one
+    pub kind: u8,
+    pub _pad: u8,
+    pub terminal: u16,
+    pub channel: u16,
+    pub label: [u8; FIELD_LABEL_LEN], // ascii, zero padded, e.g. "EL3024 value", "K-bus status"
+}
+
+impl ProcessImage {
+    pub fn subdevices(&self) -> &[ImageSubDevice] {
+        &self.subdevices[..(self.subdevice_count as usize).min(MAX_BUS_SUBDEVICES)]
+    }
+
+    pub fn fields(&self) -> &[ImageField] {
+        &self.fields[..(self.field_count as usize).min(MAX_IMAGE_FIELDS)]
+    }
+
+    /// Adds a SubDevice after the ones already there, its inputs and outputs at the given byte offsets. Err when
+    /// there are MAX_BUS_SUBDEVICES already or they don't fit `data`
+    pub fn push_subdevice(&mut self, name: &str, address: u16, inputs: (usize, usize), outputs: (usize, usize)) -> Result<u8, String> {
+        let idx = self.subdevice_count as usize;
+        if idx >= MAX_BUS_SUBDEVICES {
+            return Err(format!("More than {} SubDevices in the process image", MAX_BUS_SUBDEVICES));
+        }
+        if inputs.0 + inputs.1 > PROCESS_IMAGE_LEN || outputs.0 + outputs.1 > PROCESS_IMAGE_LEN {
+            return Err(format!("{} (0x{:04x}) doesn't fit the {} byte process image", name, address, PROCESS_IMAGE_LEN));
+        }
+        self.subdevices[idx] = ImageSubDevice {
+            name: padded(name),
+            address,
+            inputs_offset: inputs.0 as u16,
+            inputs_len: inputs.1 as u16,
+            outputs_offset: outputs.0 as u16,
+            outputs_len: outputs.1 as u16,
+            _pad: 0,
+        };
+        self.subdevice_count += 1;
+        Ok(idx as u8)
+    }
+
+    /// Adds a field, Err when there are MAX_IMAGE_FIELDS already or it runs past `data`
+    pub fn push_field(&mut self, field: ImageField) -> Result<(), String> {
+        let idx = self.field_count as usize;
+        if idx >= MAX_IMAGE_FIELDS {
+            return Err(format!("More than {} fields in the process image", MAX_IMAGE_FIELDS));
+        }
+        if field.bit as usize + field.bits as usize > PROCESS_IMAGE_LEN * 8 {
+            return Err(format!("{} at bit {} runs past the process image", field.label(), field.bit));
+        }
+        self.fields[idx] = field;
+        self.field_count += 1;
+        Ok(())
+    }
+
+    /// Copies SubDevice `idx`'s raw inputs and outputs to where push_subdevice put them
+    pub fn set_data(&mut self, idx: usize, inputs: &[u8], outputs: &[u8]) {
+        let Some(sd) = self.subdevices().get(idx).copied() else { return };
+        for (offset, len, src) in [(sd.inputs_offset, sd.inputs_len, inputs), (sd.outputs_offset, sd.outputs_len, outputs)] {
+            let len = (len as usize).min(src.len());
+            self.data[offset as usize..offset as usize + len].copy_from_slice(&src[..len]);
+        }
+    }
+
+    /// Bit `bit` of `data`, least significant bit of each byte first like the EtherCAT PDI
+    pub fn bit(&self, bit: usize) -> bool {
+        self.data[bit / 8] >> (bit % 8) & 1 != 0
+    }
+
+    /// `bits` (up to 64) from bit `bit` of `data` as a little endian number
+    pub fn read_bits(&self, bit: usize, bits: usize) -> u64 {
+        (0..bits.min(64)).fold(0, |value, n| value | (self.bit(bit + n) as u64) << n)
+    }
+}
+
+impl ImageSubDevice {
+    pub fn name(&self) -> String {
+        unpadded(&self.name)
+    }
+}
+
+impl ImageField {
+    pub fn new(label: &str, subdevice: u8, bit: usize, bits: usize) -> Self {
+        Self { label: padded(label), subdevice, bit: bit as u16, bits: bits as u16, ..Self::zeroed() }
+    }
+
+    /// The same field as the I/O mirror channel at `address`
+    pub fn channel(self, address: IoAddress) -> Self {
+        Self { bus: address.bus, kind: address.kind, terminal: address.terminal, channel: address.channel, ..self }
+    }
+
+    pub fn label(&self) -> String {
+        unpadded(&self.label)
+    }
+
+    /// The I/O mirror channel the field is, None for status and control words
+    pub fn address(&self) -> Option<IoAddress> {
+        (self.kind != 0).then_some(IoAddress { bus: self.bus, kind: self.kind, terminal: self.terminal, channel: self.channel })
+    }
+}
@@ -0,0 +1,137 @@
+// Project bundles: one directory with everything that makes an application on top of the runtime, its config
+// ([hardware], [users], register maps, alarm recipients...) under a manifest with a name and a version, so a complete
+// application can be kept in version control and copied from one controller to the next as a unit.
+//
+// plant-1/
+//   gipop-project.toml     the manifest
+//   gipop.toml             the config every binary reads from it, as it would from its working directory
+//
+// [project]
+// name = "plant-1"
+// version = "1.4.0"
+// description = "Building 1 lighting and HVAC"
+// runtime = "0.1"          # the Gipop release it's made for, runtimes of another major (minor before 1.0) refuse it
+// tags = "9c0e1f3a"        # the tag list it's made for, see fingerprint(). Empty runs with any
+// config = "gipop.toml"    # relative to the directory
+//
+// gipop_plc takes it with --project <dir>, every binary (gipopd, and through it its children) with GIPOP_PROJECT set
+// to the directory. The PLC publishes the name and version with its build (PlcInfo).
+//
+// Tags and logic are compiled into gipop_plc (plc/src/tags.rs, logic.rs), a project doesn't bring its own. The tag
+// fingerprint ties it to the builds with the tag list its register maps, bindings and recipients were written for,
+// `gipop_plc check-config` prints it. There's no scheduler to take schedules yet.
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::{fmt, fs};
+
+use crate::TagDef;
+
+pub const MANIFEST: &str = "gipop-project.toml";
+pub const PROJECT_ENV: &str = "GIPOP_PROJECT";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Manifest {
+    pub name: String,
+    pub version: String,
+    #[serde(default)]
+    pub description: String,
+    pub runtime: String,
+    #[serde(default)]
+    pub tags: String,
+    #[serde(default = "default_config")]
+    pub config: String,
+}
+
+fn default_config() -> String {
+    "gipop.toml".to_string()
+}
+
+#[derive(Deserialize)]
+struct ManifestFile {
+    project: Manifest,
+}
+
+#[derive(Debug, Clone)]
+pub struct Project {
+    pub dir: PathBuf,
+    pub manifest: Manifest,
+}
+
+impl Project {
+    /// The project in `dir`, Err if its manifest doesn't parse, it's made for another `runtime` (the binary's
+    /// version) or its config isn't there
+    pub fn open(dir: impl AsRef<Path>, runtime: &str) -> Result<Self, String> {
+        let dir = dir.as_ref();
+        let path = dir.join(MANIFEST);
+        let text = fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let manifest = toml::from_str::<ManifestFile>(&text).map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?.project;
+        if manifest.name.is_empty() || manifest.version.is_empty() {
+            return Err(format!("{} needs a name and a version", path.display()));
+        }
+        if !compatible(runtime, &manifest.runtime) {
+            return Err(format!("Project {} {} is made for Gipop {}, this is {}", manifest.name, manifest.version, manifest.runtime, runtime));
+        }
+        let project = Self { dir: dir.to_owned(), manifest };
+        if !project.config_path().is_file() {
+            return Err(format!("Project {} has no {}", project.manifest.name, project.config_path().display()));
+        }
+        Ok(project)
+    }
+
+    /// GIPOP_PROJECT's project, None when it isn't set
+    pub fn from_env(runtime: &str) -> Result<Option<Self>, String> {
+        match std::env::var_os(PROJECT_ENV).filter(|dir| !dir.is_empty()) {
+            Some(dir) => Self::open(dir, runtime).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn config_path(&self) -> PathBuf {
+        self.dir.join(&self.manifest.config)
+    }
+
+    /// Err if the project is pinned to another tag list than `tags`
+    pub fn check_tags(&self, tags: &[TagDef]) -> Result<(), String> {
+        let fingerprint = fingerprint(tags);
+        if !self.manifest.tags.is_empty() && !self.manifest.tags.eq_ignore_ascii_case(&fingerprint) {
+            return Err(format!("Project {} {} is made for tag list {}, this build's is {}", self.manifest.name, self.manifest.version, self.manifest.tags, fingerprint));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Project {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "project {} {} ({})", self.manifest.name, self.manifest.version, self.dir.display())
+    }
+}
+
+/// The gipop.toml a binary of version `runtime` reads: GIPOP_PROJECT's if that's set, `default` otherwise
+pub fn config_path(default: &str, runtime: &str) -> Result<String, String> {
+    Ok(match Project::from_env(runtime)? {
+        Some(project) => project.config_path().to_string_lossy().into_owned(),
+        None => default.to_owned(),
+    })
+}
+
+/// What a project's tags pin: every tag's name, type and flags in order. Folders, units, ranges and texts are left
+/// out, they can change without breaking anything that goes by tag
+pub fn fingerprint(tags: &[TagDef]) -> String {
+    let mut hasher = crc32fast::Hasher::new();
+    for tag in tags {
+        hasher.update(tag.name.as_bytes());
+        hasher.update(&[0, tag.ty as u8, tag.flags]);
+    }
+    format!("{:08x}", hasher.finalize())
+}
+
+// Same major, before 1.0 the same minor: runtime "0.1.4" runs projects made for "0.1"
+fn compatible(runtime: &str, wanted: &str) -> bool {
+    let key = |version: &str| {
+        let mut parts = version.trim().split('.').map(|part| part.parse::<u64>().ok());
+        let major = parts.next().flatten()?;
+        let minor = parts.next().flatten().unwrap_or(0);
+        Some((major, if major == 0 { minor } else { 0 }))
+    };
+    key(runtime).is_some() && key(runtime) == key(wanted)
+}
@@ -0,0 +1,674 @@
+// Self-describing shm layout:
+//
+// | RegionHeader | TagEntry * tag_count (directory) | value buffer 0 | value buffer 1 | command ring | event ring | payload area |
+// | I/O mirror | bus diagnostics | PLC info | process image |
+//
+// Each value buffer holds one ValueSlot per tag. The PLC (the only writer of tag values) fills the back buffer
+// and then flips `generation`, whose lowest bit says which buffer is the published one.
+//
+// The PLC creates the region from its tag list, consumers discover the tags by reading the directory,
+// so adding a tag only means adding it on the PLC side.
+//
+// The header starts with MAGIC and carries a CRC over its static part and the directory, so a stale or foreign
+// file at SHM_PATH is rejected on open instead of being reinterpreted by bytemuck.
+use crate::tags::*;
+use crate::ring::{self, EventCursor, RingItem, RingRef, Stall, RING_CAPACITY};
+use crate::blob::{self, Blob, BlobCursor, BlobRef, BLOB_AREA_LEN};
+use crate::io_mirror::{self, IoChannel, MirrorRef, MAX_IO_CHANNELS};
+use crate::bus_diag::BusDiagnostics;
+use crate::plc_info::PlcInfo;
+use crate::process_image::ProcessImage;
+use bytemuck::{Pod, Zeroable};
+use crate::access::Access;
+use crate::platform::Mapping;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering, fence};
+use std::{hint, io, mem};
+
+/// Bump this whenever anything in the region changes layout
+pub const SCHEMA_VERSION: u32 = 20;
+
+/// First bytes of every region, anything else at SHM_PATH isn't ours
+pub const MAGIC: [u8; 8] = *b"GIPOPSHM";
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct RegionHeader {
+    pub magic: [u8; 8],
+    pub schema_version: u32,
+    pub tag_count: u32,
+    pub dir_offset: u32,    // byte offset of the first TagEntry
+    pub values_offset: u32, // byte offset of value buffer 0, buffer 1 follows right after
+    pub region_len: u32,    // total size of the file as laid out by the creator
+    pub generation: u32,    // bumped on every publish, `generation & 1` is the buffer readers should use. Only ever touched atomically
+    pub cmd_ring_offset: u32,   // consumers -> PLC
+    pub event_ring_offset: u32, // PLC -> consumers
+    pub ring_capacity: u32,
+    pub static_crc: u32, // CRC32 of the header (with `generation`, `static_crc` and the heartbeats zeroed) followed by the tag directory
+    pub plc_heartbeat: u32,      // bumped by the PLC every sync cycle, see heartbeat.rs. Only ever touched atomically
+    pub consumer_heartbeat: u32, // bumped by every attached consumer
+    pub blob_offset: u32,   // variable-length payloads, see blob.rs
+    pub blob_area_len: u32,
+    pub io_offset: u32, // terminal channel mirror, see io_mirror.rs
+    pub io_capacity: u32,
+    pub bus_diag_offset: u32, // fieldbus health, see bus_diag.rs
+    pub bus_diag_len: u32,
+    pub plc_info_offset: u32, // see plc_info.rs
+    pub plc_info_len: u32,
+    pub process_image_offset: u32, // raw EtherCAT process image, see process_image.rs
+    pub process_image_len: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct ValueSlot {
+    pub raw: u64,   // see `TagValue::to_raw`
+    pub ts_ms: u64, // source timestamp, ms since the unix epoch
+    pub quality: u32, // see `Quality`
+    pub _pad: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct TagEntry {
+    pub name: [u8; TAG_NAME_LEN], // utf-8, zero padded
+    pub tag_type: u8,
+    pub flags: u8,
+    pub severity: u16, // see TagDef::severity
+    pub offset: u32, // byte offset of the value slot, relative to the start of a value buffer
+    pub folder: [u8; TAG_FOLDER_LEN], // utf-8, zero padded, see TagDef::folder
+    pub eu_range: [f64; 2],         // low, high, NaN when the tag has none
+    pub instrument_range: [f64; 2], // same
+    pub unit: [u8; TAG_UNIT_LEN],   // utf-8, zero padded, see TagDef::unit
+    pub texts: [u8; TAG_TEXT_LEN],  // utf-8, zero padded, see TagDef::texts_to_raw
+}
+
+const HEADER_LEN: usize = mem::size_of::<RegionHeader>();
+const ENTRY_LEN: usize = mem::size_of::<TagEntry>();
+const SLOT_LEN: usize = mem::size_of::<ValueSlot>();
+const GENERATION_OFFSET: usize = mem::offset_of!(RegionHeader, generation);
+const BUS_DIAG_LEN: usize = io_mirror::mirror_len::<BusDiagnostics>(1);
+const PLC_INFO_LEN: usize = io_mirror::mirror_len::<PlcInfo>(1);
+const PROCESS_IMAGE_LEN: usize = io_mirror::mirror_len::<ProcessImage>(1);
+
+fn align8(n: usize) -> usize {
+    (n + 7) & !7
+}
+
+struct Layout {
+    dir_offset: usize,
+    values_offset: usize,
+    cmd_ring_offset: usize,
+    event_ring_offset: usize,
+    blob_offset: usize,
+    io_offset: usize,
+    bus_diag_offset: usize,
+    plc_info_offset: usize,
+    process_image_offset: usize,
+    region_len: usize,
+}
+
+/// Where everything goes in a region holding `tag_count` tags
+fn layout(tag_count: usize) -> Layout {
+    let dir_offset = HEADER_LEN;
+    let values_offset = align8(dir_offset + tag_count * ENTRY_LEN); // slots must be 8-byte aligned for AtomicU64
+    let cmd_ring_offset = values_offset + 2 * tag_count * SLOT_LEN;
+    let event_ring_offset = cmd_ring_offset + ring::ring_len();
+    let blob_offset = event_ring_offset + ring::ring_len();
+    let io_offset = blob_offset + blob::area_len();
+    let bus_diag_offset = align8(io_offset + io_mirror::mirror_len::<IoChannel>(MAX_IO_CHANNELS));
+    let plc_info_offset = align8(bus_diag_offset + BUS_DIAG_LEN);
+    let process_image_offset = align8(plc_info_offset + PLC_INFO_LEN);
+    let region_len = process_image_offset + PROCESS_IMAGE_LEN;
+    Layout { dir_offset, values_offset, cmd_ring_offset, event_ring_offset, blob_offset, io_offset, bus_diag_offset, plc_info_offset, process_image_offset, region_len }
+}
+
+// Ranges in the directory, NaN for none
+pub(crate) fn range_to_raw(range: Option<(f64, f64)>) -> [f64; 2] {
+    range.map_or([f64::NAN; 2], |(low, high)| [low, high])
+}
+
+pub(crate) fn range_from_raw([low, high]: [f64; 2]) -> Option<(f64, f64)> {
+    (!low.is_nan() && !high.is_nan()).then_some((low, high))
+}
+
+fn static_crc(header: &RegionHeader, directory: &[u8]) -> u32 {
+    let mut header = *header;
+    header.generation = 0; // changes on every publish
+    header.static_crc = 0;
+    header.plc_heartbeat = 0;
+    header.consumer_heartbeat = 0;
+
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(bytemuck::bytes_of(&header));
+    hasher.update(directory);
+    hasher.finalize()
+}
+
+/// Handle to the tag region. Holds its own mapping.
+pub struct TagTable {
+    mmap: Mapping,
+    tags: Vec<TagDef>,
+    offsets: Vec<usize>, // byte offset of each tag's slot within a value buffer
+    buffers: [usize; 2], // absolute byte offset of each value buffer
+    by_name: HashMap<String, usize>,
+    cmd_ring_offset: usize,
+    event_ring_offset: usize,
+    blob_offset: usize,
+    io_offset: usize,
+    bus_diag_offset: usize,
+    plc_info_offset: usize,
+    process_image_offset: usize,
+    cmd_stall: Mutex<Option<Stall>>, // PLC side, see ring::STALE_SLOT_TIMEOUT
+}
+
+/// Err if `def` doesn't fit its tag directory entry, TagTable::create refuses the whole table then
+pub fn check_entry(def: &TagDef) -> Result<(), String> {
+    if def.name.len() > TAG_NAME_LEN {
+        return Err(format!("Tag name '{}' longer than {} bytes", def.name, TAG_NAME_LEN));
+    }
+    if def.folder.len() > TAG_FOLDER_LEN {
+        return Err(format!("Folder of tag '{}' longer than {} bytes", def.name, TAG_FOLDER_LEN));
+    }
+    if def.unit.len() > TAG_UNIT_LEN {
+        return Err(format!("Unit of tag '{}' longer than {} bytes", def.name, TAG_UNIT_LEN));
+    }
+    if def.texts_to_raw().len() > TAG_TEXT_LEN {
+        return Err(format!("Localized texts of tag '{}' longer than {} bytes", def.name, TAG_TEXT_LEN));
+    }
+    Ok(())
+}
+
+impl TagTable {
+    /// Create (or truncate and recreate) the region `name` (see SHM_PATH) from `defs`. Only the PLC should call this.
+    pub fn create(name: &str, defs: &[TagDef], access: &Access) -> io::Result<Self> {
+        let Layout { dir_offset, values_offset, cmd_ring_offset, event_ring_offset, blob_offset, io_offset, bus_diag_offset, plc_info_offset, process_image_offset, region_len } = layout(defs.len());
+
+        let mut mmap = Mapping::create(name, region_len, access)?;
+
+        let mut header = RegionHeader {
+            magic: MAGIC,
+            schema_version: SCHEMA_VERSION,
+            tag_count: defs.len() as u32,
+            dir_offset: dir_offset as u32,
+            values_offset: values_offset as u32,
+            region_len: region_len as u32,
+            generation: 0,
+            cmd_ring_offset: cmd_ring_offset as u32,
+            event_ring_offset: event_ring_offset as u32,
+            ring_capacity: RING_CAPACITY,
+            static_crc: 0,
+            plc_heartbeat: 0,
+            consumer_heartbeat: 0,
+            blob_offset: blob_offset as u32,
+            blob_area_len: BLOB_AREA_LEN as u32,
+            io_offset: io_offset as u32,
+            io_capacity: MAX_IO_CHANNELS as u32,
+            bus_diag_offset: bus_diag_offset as u32,
+            bus_diag_len: BUS_DIAG_LEN as u32,
+            plc_info_offset: plc_info_offset as u32,
+            plc_info_len: PLC_INFO_LEN as u32,
+            process_image_offset: process_image_offset as u32,
+            process_image_len: PROCESS_IMAGE_LEN as u32,
+        };
+
+        for (idx, def) in defs.iter().enumerate() {
+            check_entry(def).map_err(io::Error::other)?;
+            let texts = def.texts_to_raw();
+            let mut entry = TagEntry::zeroed();
+            entry.name[..def.name.len()].copy_from_slice(def.name.as_bytes());
+            entry.folder[..def.folder.len()].copy_from_slice(def.folder.as_bytes());
+            entry.unit[..def.unit.len()].copy_from_slice(def.unit.as_bytes());
+            entry.texts[..texts.len()].copy_from_slice(texts.as_bytes());
+            entry.eu_range = range_to_raw(def.eu_range);
+            entry.instrument_range = range_to_raw(def.instrument_range);
+            entry.tag_type = def.ty as u8;
+            entry.flags = def.flags;
+            entry.severity = def.severity;
+            entry.offset = (idx * SLOT_LEN) as u32;
+
+            let at = dir_offset + idx * ENTRY_LEN;
+            mmap[at..at + ENTRY_LEN].copy_from_slice(bytemuck::bytes_of(&entry));
+        }
+
+        // before the header goes in, nobody attaches until the header is there
+        RingRef { base: unsafe { mmap.as_mut_ptr().add(cmd_ring_offset) } }.init_queue();
+
+        header.static_crc = static_crc(&header, &mmap[dir_offset..values_offset]);
+        mmap[..HEADER_LEN].copy_from_slice(bytemuck::bytes_of(&header));
+
+        mmap.flush()?;
+        Self::from_mmap(mmap).map_err(io::Error::other)
+    }
+
+    /// Attach to a region created by the PLC. Fails if it was laid out by an incompatible build.
+    pub fn open(name: &str) -> Result<Self, String> {
+        let mmap = Mapping::open(name).map_err(|e| format!("Failed to map {}: {}", name, e))?;
+        Self::from_mmap(mmap)
+    }
+
+    fn from_mmap(mmap: Mapping) -> Result<Self, String> {
+        if mmap.len() < HEADER_LEN {
+            return Err(format!("Shared memory region is {} bytes, too small for a header", mmap.len()));
+        }
+
+        let header: RegionHeader = bytemuck::pod_read_unaligned(&mmap[..HEADER_LEN]);
+
+        if header.magic != MAGIC {
+            return Err("Shared memory region has no Gipop magic bytes, it's stale or belongs to something else. Restart the PLC to recreate it".into());
+        }
+
+        if header.schema_version != SCHEMA_VERSION {
+            return Err(format!(
+                "Shared memory schema version mismatch: region has v{}, this binary expects v{}. Rebuild/restart both PLC and OPC UA server",
+                header.schema_version, SCHEMA_VERSION
+            ));
+        }
+
+        let Layout { dir_offset, values_offset, cmd_ring_offset, event_ring_offset, blob_offset, io_offset, bus_diag_offset, plc_info_offset, process_image_offset, region_len } = layout(header.tag_count as usize);
+        if header.dir_offset as usize != dir_offset
+            || header.values_offset as usize != values_offset
+            || header.cmd_ring_offset as usize != cmd_ring_offset
+            || header.event_ring_offset as usize != event_ring_offset
+            || header.ring_capacity != RING_CAPACITY
+            || header.blob_offset as usize != blob_offset
+            || header.blob_area_len as usize != BLOB_AREA_LEN
+            || header.io_offset as usize != io_offset
+            || header.io_capacity as usize != MAX_IO_CHANNELS
+            || header.bus_diag_offset as usize != bus_diag_offset
+            || header.bus_diag_len as usize != BUS_DIAG_LEN
+            || header.plc_info_offset as usize != plc_info_offset
+            || header.plc_info_len as usize != PLC_INFO_LEN
+            || header.process_image_offset as usize != process_image_offset
+            || header.process_image_len as usize != PROCESS_IMAGE_LEN
+            || header.region_len as usize != region_len
+            || mmap.len() < region_len {
+            return Err(format!("Shared memory layout is inconsistent ({} bytes mapped, header: {:?})", mmap.len(), header));
+        }
+
+        let crc = static_crc(&header, &mmap[dir_offset..values_offset]);
+        if crc != header.static_crc {
+            return Err(format!("Shared memory header CRC mismatch (stored {:08x}, computed {:08x}), region is corrupt", header.static_crc, crc));
+        }
+
+        let mut tags = Vec::with_capacity(header.tag_count as usize);
+        let mut offsets = Vec::with_capacity(header.tag_count as usize);
+        let mut by_name = HashMap::new();
+
+        for idx in 0..header.tag_count as usize {
+            let at = dir_offset + idx * ENTRY_LEN;
+            let entry: TagEntry = bytemuck::pod_read_unaligned(&mmap[at..at + ENTRY_LEN]);
+
+            let name_len = entry.name.iter().position(|&b| b == 0).unwrap_or(TAG_NAME_LEN);
+            let name = std::str::from_utf8(&entry.name[..name_len])
+                .map_err(|_| format!("Tag {} has a non utf-8 name", idx))?
+                .to_string();
+            let folder_len = entry.folder.iter().position(|&b| b == 0).unwrap_or(TAG_FOLDER_LEN);
+            let folder = std::str::from_utf8(&entry.folder[..folder_len])
+                .map_err(|_| format!("Tag '{}' has a non utf-8 folder", name))?
+                .to_string();
+            let unit_len = entry.unit.iter().position(|&b| b == 0).unwrap_or(TAG_UNIT_LEN);
+            let unit = std::str::from_utf8(&entry.unit[..unit_len])
+                .map_err(|_| format!("Tag '{}' has a non utf-8 unit", name))?
+                .to_string();
+            let texts_len = entry.texts.iter().position(|&b| b == 0).unwrap_or(TAG_TEXT_LEN);
+            let texts = std::str::from_utf8(&entry.texts[..texts_len])
+                .map_err(|_| format!("Tag '{}' has non utf-8 localized texts", name))?;
+            let ty = TagType::from_u8(entry.tag_type)?;
+
+            let offset = entry.offset as usize;
+            if !offset.is_multiple_of(SLOT_LEN) || offset + SLOT_LEN > header.tag_count as usize * SLOT_LEN {
+                return Err(format!("Tag '{}' has an invalid value offset {}", name, entry.offset));
+            }
+
+            by_name.insert(name.clone(), idx);
+            tags.push(TagDef {
+                name,
+                ty,
+                flags: entry.flags,
+                folder,
+                severity: entry.severity,
+                unit,
+                eu_range: range_from_raw(entry.eu_range),
+                instrument_range: range_from_raw(entry.instrument_range),
+                texts: TagDef::texts_from_raw(texts),
+            });
+            offsets.push(offset);
+        }
+
+        let buffers = [values_offset, values_offset + header.tag_count as usize * SLOT_LEN];
+
+        Ok(Self { mmap, tags, offsets, buffers, by_name, cmd_ring_offset, event_ring_offset, blob_offset, io_offset, bus_diag_offset, plc_info_offset, process_image_offset, cmd_stall: Mutex::new(None) })
+    }
+
+    pub fn tags(&self) -> &[TagDef] {
+        &self.tags
+    }
+
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.by_name.get(name).copied()
+    }
+
+    pub fn read(&self, idx: usize) -> TagValue {
+        self.read_sample(idx).value
+    }
+
+    pub fn read_sample(&self, idx: usize) -> TagSample {
+        let slot = self.read_consistent(|buf| self.load_slot(buf, idx));
+        self.sample_of(idx, slot)
+    }
+
+    pub fn read_by_name(&self, name: &str) -> Option<TagValue> {
+        self.index_of(name).map(|idx| self.read(idx))
+    }
+
+    /// Consistent snapshot of every tag, in directory order
+    pub fn read_all(&self) -> Vec<TagValue> {
+        self.read_all_samples().into_iter().map(|sample| sample.value).collect()
+    }
+
+    pub fn read_all_samples(&self) -> Vec<TagSample> {
+        let slots: Vec<ValueSlot> = self.read_consistent(|buf| {
+            (0..self.tags.len()).map(|idx| self.load_slot(buf, idx)).collect()
+        });
+        slots.into_iter().enumerate().map(|(idx, slot)| self.sample_of(idx, slot)).collect()
+    }
+
+    fn sample_of(&self, idx: usize, slot: ValueSlot) -> TagSample {
+        TagSample {
+            value: TagValue::from_raw(self.tags[idx].ty, slot.raw),
+            ts_ms: slot.ts_ms,
+            quality: Quality::from_u32(slot.quality),
+        }
+    }
+
+    pub fn write(&mut self, idx: usize, value: TagValue) -> Result<(), String> {
+        self.write_many(&[(idx, value)])
+    }
+
+    pub fn write_by_name(&mut self, name: &str, value: TagValue) -> Result<(), String> {
+        let idx = self.index_of(name).ok_or_else(|| format!("No tag named '{}'", name))?;
+        self.write(idx, value)
+    }
+
+    /// Write several tags as one atomic update, readers see either all of them or none.
+    /// Only one process (the PLC) may write tag values, everybody else goes through the command ring.
+    /// Values are stamped Good with the current time, see write_samples() to set those explicitly.
+    pub fn write_many(&mut self, values: &[(usize, TagValue)]) -> Result<(), String> {
+        self.publish(values)
+    }
+
+    pub fn write_samples(&mut self, samples: &[(usize, TagSample)]) -> Result<(), String> {
+        self.publish_samples(samples)
+    }
+
+    // write_many() without the &mut, callers must make sure there's only one publisher at a time (see ShmWriter)
+    pub(crate) fn publish(&self, values: &[(usize, TagValue)]) -> Result<(), String> {
+        let samples: Vec<(usize, TagSample)> = values.iter().map(|&(idx, value)| (idx, TagSample::good(value))).collect();
+        self.publish_samples(&samples)
+    }
+
+    pub(crate) fn publish_samples(&self, samples: &[(usize, TagSample)]) -> Result<(), String> {
+        for (idx, sample) in samples {
+            let def = self.tags.get(*idx).ok_or_else(|| format!("Tag index {} out of bounds", idx))?;
+            if def.ty != sample.value.ty() {
+                return Err(format!("Tag '{}' is {:?}, got {:?}", def.name, def.ty, sample.value.ty()));
+            }
+        }
+
+        // back buffer starts as a copy of the published one, so tags not in `values` keep their value
+        let generation = self.generation().load(Ordering::Relaxed); // we're the only writer, no need to sync with ourselves
+        let front = (generation & 1) as usize;
+        let back = front ^ 1;
+        // The back buffer is the one readers of the last generation but one may still be copying. Keeps the writes
+        // below after that publish's `generation` store, so a reader that sees any of them also sees it moved on
+        fence(Ordering::Release);
+        for idx in 0..self.tags.len() {
+            let slot = self.load_slot(front, idx);
+            self.store_slot(back, idx, slot);
+        }
+        for (idx, sample) in samples {
+            let slot = ValueSlot { raw: sample.value.to_raw(), ts_ms: sample.ts_ms, quality: sample.quality as u32, _pad: 0 };
+            self.store_slot(back, *idx, slot);
+        }
+        self.generation().store(generation.wrapping_add(1), Ordering::Release); // publish
+        Ok(())
+    }
+
+    /// Consumer -> PLC. Any number of consumers may push commands, only the PLC pops them.
+    /// Returns the command's sequence number, see `is_command_acked`.
+    pub fn push_command(&self, item: RingItem) -> Result<u32, RingItem> {
+        self.ring(self.cmd_ring_offset).push(item)
+    }
+
+    pub fn pop_command(&self) -> Option<RingItem> {
+        self.ring(self.cmd_ring_offset).pop(&mut self.cmd_stall.lock().unwrap())
+    }
+
+    /// PLC only: every command up to `seq` has been consumed by the logic
+    pub fn ack_command(&self, seq: u32) {
+        self.ring(self.cmd_ring_offset).ack(seq)
+    }
+
+    pub fn is_command_acked(&self, seq: u32) -> bool {
+        self.ring(self.cmd_ring_offset).is_acked(seq)
+    }
+
+    /// PLC -> every consumer. Only the PLC pushes events. Consumers that fall more than RING_CAPACITY behind
+    /// lose the oldest ones, the PLC never waits.
+    pub fn push_event(&self, item: RingItem) {
+        self.ring(self.event_ring_offset).broadcast(item)
+    }
+
+    /// Start following the event ring, from the next event on. One cursor per consumer.
+    pub fn event_cursor(&self) -> EventCursor {
+        self.ring(self.event_ring_offset).cursor()
+    }
+
+    pub fn next_event(&self, cursor: &mut EventCursor) -> Option<RingItem> {
+        self.ring(self.event_ring_offset).next(cursor)
+    }
+
+    /// PLC -> every consumer, for payloads that don't fit a RingItem. Only the PLC pushes, and never waits:
+    /// consumers that fall behind by more than BLOB_AREA_LEN skip ahead (see BlobCursor::lost).
+    pub fn push_blob(&self, kind: u16, payload: &[u8]) -> Result<(), String> {
+        self.blob_area().push(kind, payload)
+    }
+
+    /// Start following the payload area, from the next payload on. One cursor per consumer.
+    pub fn blob_cursor(&self) -> BlobCursor {
+        self.blob_area().cursor()
+    }
+
+    pub fn next_blob(&self, cursor: &mut BlobCursor) -> Option<Blob> {
+        self.blob_area().next(cursor)
+    }
+
+    /// PLC only, the whole mirror at once every sync cycle
+    pub fn write_io(&self, channels: &[IoChannel]) {
+        self.io_mirror().write(channels)
+    }
+
+    /// Consistent copy of the mirror
+    pub fn read_io(&self) -> Vec<IoChannel> {
+        self.io_mirror().read()
+    }
+
+    /// PLC only, whenever it has fresh diagnostics
+    pub fn write_bus_diag(&self, diag: &BusDiagnostics) {
+        self.bus_diag().write(std::slice::from_ref(diag))
+    }
+
+    /// None until the PLC published its first diagnostics
+    pub fn read_bus_diag(&self) -> Option<BusDiagnostics> {
+        self.bus_diag().read().pop()
+    }
+
+    /// PLC only, once at startup
+    pub fn write_plc_info(&self, info: &PlcInfo) {
+        self.plc_info().write(std::slice::from_ref(info))
+    }
+
+    /// None until the PLC wrote it
+    pub fn read_plc_info(&self) -> Option<PlcInfo> {
+        self.plc_info().read().pop()
+    }
+
+    /// PLC only, every sync cycle once the bus is in OP
+    pub fn write_process_image(&self, image: &ProcessImage) {
+        self.process_image().write(std::slice::from_ref(image))
+    }
+
+    /// None until the PLC's bus went to OP
+    pub fn read_process_image(&self) -> Option<ProcessImage> {
+        self.process_image().read().pop()
+    }
+
+    /// PLC only, once per sync cycle
+    pub fn beat_plc(&self) {
+        self.header_counter(mem::offset_of!(RegionHeader, plc_heartbeat)).fetch_add(1, Ordering::Release);
+    }
+
+    /// Consumers, periodically. All consumers share one counter, so it says "somebody is attached and alive".
+    pub fn beat_consumer(&self) {
+        self.header_counter(mem::offset_of!(RegionHeader, consumer_heartbeat)).fetch_add(1, Ordering::Release);
+    }
+
+    pub fn plc_heartbeat(&self) -> u32 {
+        self.header_counter(mem::offset_of!(RegionHeader, plc_heartbeat)).load(Ordering::Acquire)
+    }
+
+    pub fn consumer_heartbeat(&self) -> u32 {
+        self.header_counter(mem::offset_of!(RegionHeader, consumer_heartbeat)).load(Ordering::Acquire)
+    }
+
+    /// (queued commands, commands dropped)
+    pub fn ring_stats(&self) -> (u32, u32) {
+        let cmd = self.ring(self.cmd_ring_offset);
+        (cmd.len(), cmd.dropped_count())
+    }
+
+    fn bus_diag(&self) -> MirrorRef<BusDiagnostics> {
+        MirrorRef::new(unsafe { self.mmap.as_ptr().add(self.bus_diag_offset) as *mut u8 }, 1)
+    }
+
+    fn plc_info(&self) -> MirrorRef<PlcInfo> {
+        MirrorRef::new(unsafe { self.mmap.as_ptr().add(self.plc_info_offset) as *mut u8 }, 1)
+    }
+
+    fn process_image(&self) -> MirrorRef<ProcessImage> {
+        MirrorRef::new(unsafe { self.mmap.as_ptr().add(self.process_image_offset) as *mut u8 }, 1)
+    }
+
+    fn io_mirror(&self) -> MirrorRef<IoChannel> {
+        MirrorRef::new(unsafe { self.mmap.as_ptr().add(self.io_offset) as *mut u8 }, MAX_IO_CHANNELS)
+    }
+
+    fn blob_area(&self) -> BlobRef {
+        BlobRef { base: unsafe { self.mmap.as_ptr().add(self.blob_offset) as *mut u8 } }
+    }
+
+    fn ring(&self, offset: usize) -> RingRef {
+        RingRef { base: unsafe { self.mmap.as_ptr().add(offset) as *mut u8 } }
+    }
+
+    // Double buffering: readers load `generation`, copy from buffer `generation & 1` and check `generation` again.
+    // While it hasn't moved the writer can only be filling the *other* buffer, so what was read is consistent.
+    // Once it moves the writer may already be refilling ours, so retry. Publishes happen once per sync cycle,
+    // so a retry is rare and the writer never waits on readers.
+
+    fn generation(&self) -> &AtomicU32 {
+        self.header_counter(GENERATION_OFFSET)
+    }
+
+    fn header_counter(&self, offset: usize) -> &AtomicU32 {
+        // SAFETY: the mapping is page aligned and `offset` is a 4-byte aligned u32 field of RegionHeader, the mapping outlives the borrow
+        unsafe { AtomicU32::from_ptr(self.mmap.as_ptr().add(offset) as *mut u32) }
+    }
+
+    fn slot_ptr(&self, buf: usize, idx: usize, field_offset: usize) -> *mut u8 {
+        // SAFETY: offsets were validated to be in bounds and slot aligned in from_mmap()
+        unsafe { self.mmap.as_ptr().add(self.buffers[buf] + self.offsets[idx] + field_offset) as *mut u8 }
+    }
+
+    fn word(&self, buf: usize, idx: usize, field_offset: usize) -> &AtomicU64 {
+        // SAFETY: slots are 8-byte aligned and the u64 fields of ValueSlot are too
+        unsafe { AtomicU64::from_ptr(self.slot_ptr(buf, idx, field_offset) as *mut u64) }
+    }
+
+    fn quality(&self, buf: usize, idx: usize) -> &AtomicU32 {
+        unsafe { AtomicU32::from_ptr(self.slot_ptr(buf, idx, mem::offset_of!(ValueSlot, quality)) as *mut u32) }
+    }
+
+    // field by field, consistency comes from read_consistent()/the generation flip, not from the accesses themselves
+    fn load_slot(&self, buf: usize, idx: usize) -> ValueSlot {
+        ValueSlot {
+            raw: self.word(buf, idx, mem::offset_of!(ValueSlot, raw)).load(Ordering::Relaxed),
+            ts_ms: self.word(buf, idx, mem::offset_of!(ValueSlot, ts_ms)).load(Ordering::Relaxed),
+            quality: self.quality(buf, idx).load(Ordering::Relaxed),
+            _pad: 0,
+        }
+    }
+
+    fn store_slot(&self, buf: usize, idx: usize, slot: ValueSlot) {
+        self.word(buf, idx, mem::offset_of!(ValueSlot, raw)).store(slot.raw, Ordering::Relaxed);
+        self.word(buf, idx, mem::offset_of!(ValueSlot, ts_ms)).store(slot.ts_ms, Ordering::Relaxed);
+        self.quality(buf, idx).store(slot.quality, Ordering::Relaxed);
+    }
+
+    fn read_consistent<T>(&self, mut read: impl FnMut(usize) -> T) -> T {
+        let generation = self.generation();
+        let mut spins: u32 = 0;
+        loop {
+            let before = generation.load(Ordering::Acquire);
+            let data = read((before & 1) as usize);
+            fence(Ordering::Acquire);
+            if generation.load(Ordering::Relaxed) == before {
+                return data;
+            }
+            backoff(&mut spins);
+        }
+    }
+}
+
+pub(crate) fn backoff(spins: &mut u32) {
+    *spins += 1;
+    if *spins < 64 { hint::spin_loop(); }
+    else { std::thread::yield_now(); }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every publish writes the same count to all tags, a reader must never see two different ones in a snapshot
+    #[test]
+    fn snapshots_are_consistent_while_publishing() {
+        let path = std::env::temp_dir().join(format!("gipop_region_test_{}.shm", std::process::id()));
+        let path = path.to_str().unwrap().to_owned();
+        let defs: Vec<TagDef> = (0..64).map(|idx| TagDef::new(&format!("t{}", idx), TagType::UInt32, 0)).collect();
+        let writer = TagTable::create(&path, &defs, &Access::default()).unwrap();
+
+        let reader = std::thread::spawn({
+            let path = path.clone();
+            move || {
+                let table = TagTable::open(&path).unwrap();
+                let mut last = 0;
+                while last < 20_000 {
+                    let values = table.read_all();
+                    let TagValue::UInt32(first) = values[0] else { panic!("not a UInt32: {:?}", values[0]) };
+                    assert!(values.iter().all(|value| *value == TagValue::UInt32(first)), "torn snapshot: {:?}", values);
+                    assert!(first >= last, "went back from {} to {}", last, first);
+                    last = first;
+                }
+            }
+        });
+        for count in 1..=20_000u32 {
+            let values: Vec<(usize, TagValue)> = (0..defs.len()).map(|idx| (idx, TagValue::UInt32(count))).collect();
+            writer.publish(&values).unwrap();
+        }
+        reader.join().unwrap();
+        _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,487 @@
+// Rings living in the shm region.
+//
+// Commands (consumers -> PLC) and events (PLC -> consumers) are discrete items that must not be collapsed into
+// "latest value" like tags are, e.g. two HMI writes between PLC polls are two commands.
+//
+// Any number of consumers (OPC UA server, web HMI, logger...) may be attached at once, so:
+// - the command ring is multi producer, single consumer (the PLC). Producers reserve a slot by CAS on `head`
+//   and mark it ready through the slot's `seq` once the item is in (bounded MPMC queue a la Vyukov).
+// - the event ring is a broadcast ring. The PLC never waits for anybody and overwrites the oldest event,
+//   every consumer reads at its own pace through its own EventCursor and finds out when it fell behind.
+//
+// `head`/`tail`/`seq` are free-running u32 counters so full/empty can be told apart without wasting a slot.
+//
+// Every command gets a sequence number (its position in the ring + 1) when pushed. The PLC acknowledges a command
+// once its logic has actually consumed it by storing that number in `acked`, so a producer can tell whether a
+// write went through or is still queued. Commands are consumed in order, so `acked` also covers all earlier ones.
+//
+// Producers are other processes and can be killed at any point, also between reserving a slot and marking it ready.
+// The PLC would wait on that slot forever, so one that stays reserved for STALE_SLOT_TIMEOUT is skipped and counted
+// as dropped. Filling a slot takes microseconds, a producer that's only slow doesn't get anywhere near that, but one
+// that's stopped (SIGSTOP, a debugger) can come back after the skip. So marking ready is a CAS from `head` the skip
+// makes fail: the skip leaves `head + 2` in `seq`, which no lap ever expects, and the producer that finds it there
+// hands the slot on to the next lap and gets its item back as not pushed. A slot whose producer never comes back is
+// taken back by the PLC when it gets around to it again, after another STALE_SLOT_TIMEOUT.
+use crate::io_mirror::IoAddress;
+use crate::tags::TagValue;
+use bytemuck::{Pod, Zeroable};
+use log::LevelFilter;
+use std::mem;
+use std::sync::atomic::{AtomicU32, Ordering, fence};
+use std::time::{Duration, Instant};
+
+pub const RING_CAPACITY: u32 = 64; // slots per ring, must be a power of 2 above 2 (see `skipped`)
+
+// Item kinds
+pub const ITEM_TAG_WRITE: u16 = 1;        // command: write `value` to tag `tag`
+pub const ITEM_ENOCEAN_TELEGRAM: u16 = 2; // event: raw KL6581 process image in `data`
+pub const ITEM_ALARM: u16 = 3;            // event: tag `tag` alarm went active (`value` = 1) or cleared (0)
+pub const ITEM_ALARM_ACK: u16 = 4;        // command: operator acknowledged alarm tag `tag`. Echoed as event by the PLC
+pub const ITEM_FORCE: u16 = 5;            // command: force the I/O channel in `data` to `value` (f64 bits)
+pub const ITEM_UNFORCE: u16 = 6;          // command: release the force on the I/O channel in `data`
+pub const ITEM_RUN_MODE: u16 = 7;         // command: run the logic (`value` = 1) or stop it (0)
+pub const ITEM_RESET_TOTALS: u16 = 8;     // command: zero the PLC's counters
+pub const ITEM_AUDIT: u16 = 9;            // command: who sent an earlier command, for the PLC's audit log. See RingItem::audit
+pub const ITEM_LOG_LEVEL: u16 = 10;       // command: log the module in `data` at level `value`. See RingItem::log_level
+pub const ITEM_RELOAD_CONFIG: u16 = 11;   // command: read gipop.toml again and apply what can change live (plc/src/reload.rs)
+pub const ITEM_RESTORE_TOTAL: u16 = 12;   // command: set counter tag `tag` to `value`, from a backup (gipop restore)
+
+pub const STALE_SLOT_TIMEOUT: Duration = Duration::from_secs(2); // reserved and not filled, its producer is gone
+pub const ITEM_DATA_LEN: usize = 16;
+const LOG_LEVEL_CONFIGURED: u64 = u64::MAX; // ITEM_LOG_LEVEL `value` with no level, LevelFilter's are 0 (off) to 5 (trace)
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct RingHeader {
+    pub head: u32,    // next slot a producer writes
+    pub tail: u32,    // next slot the PLC reads (command ring only, event consumers keep their own cursor)
+    pub dropped: u32, // pushes rejected because the ring was full and slots skipped as stale, for diagnostics
+    pub acked: u32,   // command ring: `seq` of the last command the PLC consumed
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct RingItem {
+    pub kind: u16,
+    pub tag: u16,  // tag index for tag related items
+    pub len: u16,  // bytes used in `data`
+    pub _pad: u16,
+    pub seq: u32,  // commands: assigned by push_command (the socket: as received), 0 for commands that didn't come through either
+    pub _reserved: u32,
+    pub value: u64, // raw tag value, see `TagValue::to_raw`
+    pub data: [u8; ITEM_DATA_LEN],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct RingSlot {
+    pub seq: u32, // command ring: Vyukov sequence. Event ring: 2 * index + 1 while being written, 2 * index + 2 once done
+    pub _pad: u32,
+    pub item: RingItem,
+}
+
+impl RingItem {
+    pub fn tag_write(tag: usize, value: TagValue) -> Self {
+        Self { kind: ITEM_TAG_WRITE, tag: tag as u16, value: value.to_raw(), ..Self::zeroed() }
+    }
+
+    pub fn enocean_telegram(bytes: &[u8]) -> Self {
+        let len = bytes.len().min(ITEM_DATA_LEN);
+        let mut item = Self { kind: ITEM_ENOCEAN_TELEGRAM, len: len as u16, ..Self::zeroed() };
+        item.data[..len].copy_from_slice(&bytes[..len]);
+        item
+    }
+
+    pub fn alarm(tag: usize, active: bool) -> Self {
+        Self { kind: ITEM_ALARM, tag: tag as u16, value: active as u64, ..Self::zeroed() }
+    }
+
+    pub fn alarm_ack(tag: usize) -> Self {
+        Self { kind: ITEM_ALARM_ACK, tag: tag as u16, ..Self::zeroed() }
+    }
+
+    pub fn force(address: IoAddress, value: f64) -> Self {
+        Self { kind: ITEM_FORCE, value: value.to_bits(), ..Self::with_io_address(address) }
+    }
+
+    pub fn unforce(address: IoAddress) -> Self {
+        Self { kind: ITEM_UNFORCE, ..Self::with_io_address(address) }
+    }
+
+    pub fn run_mode(run: bool) -> Self {
+        Self { kind: ITEM_RUN_MODE, value: run as u64, ..Self::zeroed() }
+    }
+
+    pub fn reset_totals() -> Self {
+        Self { kind: ITEM_RESET_TOTALS, ..Self::zeroed() }
+    }
+
+    pub fn reload_config() -> Self {
+        Self { kind: ITEM_RELOAD_CONFIG, ..Self::zeroed() }
+    }
+
+    /// Counter `tag` (a Totals one) back to `value`, what it was when a backup was taken
+    pub fn restore_total(tag: usize, value: TagValue) -> Self {
+        Self { kind: ITEM_RESTORE_TOTAL, tag: tag as u16, value: value.to_raw(), ..Self::zeroed() }
+    }
+
+    /// Log `module` ("" for every module, at most ITEM_DATA_LEN bytes: "plc::ctrl_loop") at `level`, None for what
+    /// [logging] says again. See logging::set_level
+    pub fn log_level(module: &str, level: Option<LevelFilter>) -> Result<Self, String> {
+        if module.len() > ITEM_DATA_LEN {
+            return Err(format!("Module '{}' is longer than {} bytes, name it by its start", module, ITEM_DATA_LEN));
+        }
+        let value = level.map_or(LOG_LEVEL_CONFIGURED, |level| level as u64);
+        let mut item = Self { kind: ITEM_LOG_LEVEL, len: module.len() as u16, value, ..Self::zeroed() };
+        item.data[..module.len()].copy_from_slice(module.as_bytes());
+        Ok(item)
+    }
+
+    /// (module, level) of an ITEM_LOG_LEVEL command
+    pub fn logged_level(&self) -> (String, Option<LevelFilter>) {
+        let level = LevelFilter::iter().find(|level| *level as u64 == self.value);
+        (String::from_utf8_lossy(self.payload()).into_owned(), level)
+    }
+
+    /// Command `seq` was sent on behalf of `user` (cut to ITEM_DATA_LEN bytes) over OPC UA session `session`
+    pub fn audit(seq: u32, session: u32, user: &str) -> Self {
+        let mut len = user.len().min(ITEM_DATA_LEN);
+        while !user.is_char_boundary(len) {
+            len -= 1;
+        }
+        let mut item = Self { kind: ITEM_AUDIT, len: len as u16, value: (session as u64) << 32 | seq as u64, ..Self::zeroed() };
+        item.data[..len].copy_from_slice(&user.as_bytes()[..len]);
+        item
+    }
+
+    /// (seq, session, user) of an ITEM_AUDIT command
+    pub fn audited(&self) -> (u32, u32, String) {
+        (self.value as u32, (self.value >> 32) as u32, String::from_utf8_lossy(self.payload()).into_owned())
+    }
+
+    // bus, kind, terminal (LE), channel (LE)
+    fn with_io_address(address: IoAddress) -> Self {
+        let mut item = Self { len: 6, ..Self::zeroed() };
+        item.data[0] = address.bus;
+        item.data[1] = address.kind;
+        item.data[2..4].copy_from_slice(&address.terminal.to_le_bytes());
+        item.data[4..6].copy_from_slice(&address.channel.to_le_bytes());
+        item
+    }
+
+    /// The channel of an ITEM_FORCE/ITEM_UNFORCE command
+    pub fn io_address(&self) -> IoAddress {
+        IoAddress {
+            bus: self.data[0],
+            kind: self.data[1],
+            terminal: u16::from_le_bytes([self.data[2], self.data[3]]),
+            channel: u16::from_le_bytes([self.data[4], self.data[5]]),
+        }
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.data[..(self.len as usize).min(ITEM_DATA_LEN)]
+    }
+}
+
+/// One consumer's position in the event ring
+#[derive(Debug, Clone, Copy)]
+pub struct EventCursor {
+    next: u32,     // index of the next event to read
+    pub lost: u64, // events overwritten before this consumer got to them
+}
+
+/// The command slot pop() found reserved but not filled, and since when. The PLC's own, kept between pops
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Stall {
+    idx: u32,
+    since: Instant,
+}
+
+pub(crate) const RING_HEADER_LEN: usize = mem::size_of::<RingHeader>();
+pub(crate) const SLOT_LEN: usize = mem::size_of::<RingSlot>();
+const MASK: u32 = RING_CAPACITY - 1;
+
+pub(crate) const fn ring_len() -> usize {
+    RING_HEADER_LEN + RING_CAPACITY as usize * SLOT_LEN
+}
+
+// Command slot `seq` of index `idx` skipped by pop(). Every lap expects `idx` (free) or `idx + 1` (ready) modulo
+// RING_CAPACITY, and the next lap's producer takes it for a full ring instead of spinning on it
+const fn skipped(idx: u32) -> u32 {
+    idx.wrapping_add(2)
+}
+
+// Whether the PLC has been waiting on command slot `idx` for STALE_SLOT_TIMEOUT, starts the wait if it hasn't yet
+fn waited(stall: &mut Option<Stall>, idx: u32) -> bool {
+    match stall {
+        Some(waiting) if waiting.idx == idx => {
+            let done = waiting.since.elapsed() >= STALE_SLOT_TIMEOUT;
+            if done {
+                *stall = None;
+            }
+            done
+        }
+        _ => {
+            *stall = Some(Stall { idx, since: Instant::now() });
+            false
+        }
+    }
+}
+
+/// View over one ring at `base` (start of its RingHeader) inside a live mapping
+pub(crate) struct RingRef {
+    pub base: *mut u8,
+}
+
+impl RingRef {
+    fn counter(&self, offset: usize) -> &AtomicU32 {
+        // SAFETY: `base` is 8-byte aligned inside the mapping (checked by the region layout)
+        unsafe { AtomicU32::from_ptr(self.base.add(offset) as *mut u32) }
+    }
+
+    fn head(&self) -> &AtomicU32 { self.counter(mem::offset_of!(RingHeader, head)) }
+    fn tail(&self) -> &AtomicU32 { self.counter(mem::offset_of!(RingHeader, tail)) }
+    fn dropped(&self) -> &AtomicU32 { self.counter(mem::offset_of!(RingHeader, dropped)) }
+    fn acked(&self) -> &AtomicU32 { self.counter(mem::offset_of!(RingHeader, acked)) }
+
+    fn slot_offset(idx: u32) -> usize {
+        RING_HEADER_LEN + (idx & MASK) as usize * SLOT_LEN
+    }
+
+    fn seq(&self, idx: u32) -> &AtomicU32 {
+        self.counter(Self::slot_offset(idx) + mem::offset_of!(RingSlot, seq))
+    }
+
+    fn item(&self, idx: u32) -> *mut RingItem {
+        unsafe { self.base.add(Self::slot_offset(idx) + mem::offset_of!(RingSlot, item)) as *mut RingItem }
+    }
+
+    /// Command ring only, slot i starts out expecting the producer of index i
+    pub fn init_queue(&self) {
+        for idx in 0..RING_CAPACITY {
+            self.seq(idx).store(idx, Ordering::Relaxed);
+        }
+    }
+
+    /// Command ring, any process. Returns the command's sequence number, hands the item back if the ring is full or
+    /// the PLC skipped the slot before it was filled.
+    pub fn push(&self, item: RingItem) -> Result<u32, RingItem> {
+        match self.reserve() {
+            Some(head) => self.fill(head, item),
+            None => Err(item),
+        }
+    }
+
+    // The index of the slot this producer got, None if the ring is full
+    fn reserve(&self) -> Option<u32> {
+        let mut head = self.head().load(Ordering::Relaxed);
+        loop {
+            let seq = self.seq(head).load(Ordering::Acquire);
+            let diff = seq.wrapping_sub(head) as i32;
+            if diff == 0 {
+                // slot is free for index `head`, race the other producers for it
+                match self.head().compare_exchange_weak(head, head.wrapping_add(1), Ordering::Relaxed, Ordering::Relaxed) {
+                    Ok(_) => return Some(head),
+                    Err(current) => head = current,
+                }
+            }
+            else if diff < 0 {
+                // slot still holds an item from the last lap the PLC hasn't taken, or was skipped and not handed on
+                self.dropped().fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+            else {
+                head = self.head().load(Ordering::Relaxed); // another producer got it first
+            }
+        }
+    }
+
+    fn fill(&self, head: u32, mut item: RingItem) -> Result<u32, RingItem> {
+        item.seq = head.wrapping_add(1);
+        unsafe { self.item(head).write_volatile(item) };
+        let ready = self.seq(head).compare_exchange(head, head.wrapping_add(1), Ordering::Release, Ordering::Relaxed);
+        if ready.is_err() {
+            // pop() gave up on us. Nobody reserved the slot since, so what was just written can't have hurt anybody
+            _ = self.seq(head).compare_exchange(skipped(head), head.wrapping_add(RING_CAPACITY), Ordering::Release, Ordering::Relaxed);
+            return Err(item);
+        }
+        Ok(item.seq)
+    }
+
+    /// Command ring, PLC only. `stall` is where the last call left off, see STALE_SLOT_TIMEOUT
+    pub fn pop(&self, stall: &mut Option<Stall>) -> Option<RingItem> {
+        loop {
+            let tail = self.tail().load(Ordering::Relaxed);
+            let seq = self.seq(tail).load(Ordering::Acquire);
+            if seq == tail.wrapping_add(1) {
+                *stall = None;
+                let item = unsafe { self.item(tail).read_volatile() };
+                self.tail().store(tail.wrapping_add(1), Ordering::Relaxed);
+                self.seq(tail).store(tail.wrapping_add(RING_CAPACITY), Ordering::Release); // free for the next lap
+                return Some(item);
+            }
+            if self.head().load(Ordering::Acquire) == tail {
+                if seq == tail {
+                    *stall = None;
+                }
+                else if waited(stall, tail) {
+                    // skipped a lap ago and its producer never came back to hand it on, nobody can reserve it until
+                    // it expects index `tail` again
+                    _ = self.seq(tail).compare_exchange(seq, tail, Ordering::Release, Ordering::Relaxed);
+                }
+                return None; // empty
+            }
+
+            // a producer reserved the slot and is still writing it, or died doing so
+            if !waited(stall, tail) {
+                return None;
+            }
+            if self.seq(tail).compare_exchange(tail, skipped(tail), Ordering::Relaxed, Ordering::Relaxed).is_err() {
+                continue; // filled just now after all
+            }
+            log::warn!("Command slot {} reserved for {} ms and never filled, skipping it", tail & MASK, STALE_SLOT_TIMEOUT.as_millis());
+            self.dropped().fetch_add(1, Ordering::Relaxed);
+            self.tail().store(tail.wrapping_add(1), Ordering::Relaxed);
+        }
+    }
+
+    /// Command ring, PLC only. Everything up to and including `seq` has been consumed.
+    pub fn ack(&self, seq: u32) {
+        self.acked().store(seq, Ordering::Release);
+    }
+
+    pub fn is_acked(&self, seq: u32) -> bool {
+        self.acked().load(Ordering::Acquire).wrapping_sub(seq) as i32 >= 0
+    }
+
+    /// Command ring: items waiting for the PLC
+    pub fn len(&self) -> u32 {
+        self.head().load(Ordering::Acquire).wrapping_sub(self.tail().load(Ordering::Acquire))
+    }
+
+    pub fn dropped_count(&self) -> u32 {
+        self.dropped().load(Ordering::Relaxed)
+    }
+
+    /// Event ring, PLC only. Never fails, the oldest event gets overwritten.
+    pub fn broadcast(&self, item: RingItem) {
+        let head = self.head().load(Ordering::Relaxed);
+        let seq = self.seq(head);
+        seq.store(head.wrapping_mul(2).wrapping_add(1), Ordering::Relaxed); // odd: being written
+        fence(Ordering::Release);
+        unsafe { self.item(head).write_volatile(item) };
+        seq.store(head.wrapping_mul(2).wrapping_add(2), Ordering::Release);
+        self.head().store(head.wrapping_add(1), Ordering::Release);
+    }
+
+    /// Event ring: a cursor that sees every event broadcast from now on
+    pub fn cursor(&self) -> EventCursor {
+        EventCursor { next: self.head().load(Ordering::Acquire), lost: 0 }
+    }
+
+    /// Event ring, any consumer with its own cursor
+    pub fn next(&self, cursor: &mut EventCursor) -> Option<RingItem> {
+        loop {
+            let head = self.head().load(Ordering::Acquire);
+            let behind = head.wrapping_sub(cursor.next);
+            if behind == 0 {
+                return None;
+            }
+            if behind > RING_CAPACITY {
+                // lapped, skip to the oldest event that can still be there
+                cursor.lost += (behind - RING_CAPACITY) as u64;
+                cursor.next = head.wrapping_sub(RING_CAPACITY);
+                continue;
+            }
+
+            let expected = cursor.next.wrapping_mul(2).wrapping_add(2);
+            let seq = self.seq(cursor.next);
+            let before = seq.load(Ordering::Acquire);
+            let item = unsafe { self.item(cursor.next).read_volatile() };
+            fence(Ordering::Acquire);
+            if before == expected && seq.load(Ordering::Relaxed) == expected {
+                cursor.next = cursor.next.wrapping_add(1);
+                return Some(item);
+            }
+            // the PLC is overwriting this slot right now
+            cursor.lost += 1;
+            cursor.next = cursor.next.wrapping_add(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A command ring on the heap instead of in a region
+    fn command_ring() -> (Vec<u64>, RingRef) {
+        let mut memory = vec![0u64; ring_len().div_ceil(8)];
+        let ring = RingRef { base: memory.as_mut_ptr() as *mut u8 };
+        ring.init_queue();
+        (memory, ring)
+    }
+
+    fn expired(stall: Option<Stall>) -> Option<Stall> {
+        stall.map(|stall| Stall { since: stall.since - STALE_SLOT_TIMEOUT, ..stall })
+    }
+
+    // A producer killed between reserving a slot and filling it: the PLC waits for it, then skips it for what's behind
+    #[test]
+    fn half_written_slot_is_skipped() {
+        let (_memory, ring) = command_ring();
+        let mut stall = None;
+        ring.reserve().unwrap(); // slot 0, never filled
+        ring.push(RingItem::run_mode(true)).unwrap();
+        ring.push(RingItem::reset_totals()).unwrap();
+
+        assert!(ring.pop(&mut stall).is_none());
+        assert!(ring.pop(&mut stall).is_none(), "skipped before STALE_SLOT_TIMEOUT");
+        stall = expired(stall);
+
+        assert_eq!(ring.pop(&mut stall).map(|item| (item.kind, item.seq)), Some((ITEM_RUN_MODE, 2)));
+        assert_eq!(ring.pop(&mut stall).map(|item| (item.kind, item.seq)), Some((ITEM_RESET_TOTALS, 3)));
+        assert!(ring.pop(&mut stall).is_none());
+        assert_eq!(ring.dropped_count(), 1);
+
+        // a lap later nobody handed the slot on. Producers find the ring full there until the PLC has waited on it
+        // once more and takes it back, then the ring keeps going
+        for lap_item in 3..RING_CAPACITY {
+            ring.push(RingItem::reset_totals()).unwrap();
+            assert_eq!(ring.pop(&mut stall).map(|item| item.seq), Some(lap_item + 1));
+        }
+        assert!(ring.push(RingItem::reset_totals()).is_err());
+        assert!(ring.pop(&mut stall).is_none());
+        assert!(ring.push(RingItem::reset_totals()).is_err(), "taken back before STALE_SLOT_TIMEOUT");
+        stall = expired(stall);
+        assert!(ring.pop(&mut stall).is_none());
+        for lap_item in RING_CAPACITY..RING_CAPACITY + 3 {
+            ring.push(RingItem::reset_totals()).unwrap();
+            assert_eq!(ring.pop(&mut stall).map(|item| item.seq), Some(lap_item + 1));
+        }
+        assert_eq!(ring.dropped_count(), 3);
+    }
+
+    // A producer stopped between reserving a slot and filling it that finishes after the skip: the PLC never sees its
+    // item, the producer gets it back and the slot goes on to the next lap
+    #[test]
+    fn late_producer_gets_its_item_back() {
+        let (_memory, ring) = command_ring();
+        let mut stall = None;
+        let late = ring.reserve().unwrap();
+        ring.push(RingItem::run_mode(true)).unwrap();
+
+        assert!(ring.pop(&mut stall).is_none());
+        stall = expired(stall);
+        assert_eq!(ring.pop(&mut stall).map(|item| item.kind), Some(ITEM_RUN_MODE));
+        assert_eq!(ring.fill(late, RingItem::alarm_ack(7)).map_err(|item| (item.kind, item.tag)), Err((ITEM_ALARM_ACK, 7)));
+        assert!(ring.pop(&mut stall).is_none());
+
+        for lap_item in 2..2 * RING_CAPACITY + 2 {
+            assert_eq!(ring.push(RingItem::reset_totals()).ok(), Some(lap_item + 1));
+            assert_eq!(ring.pop(&mut stall).map(|item| (item.kind, item.seq)), Some((ITEM_RESET_TOTALS, lap_item + 1)));
+        }
+        assert_eq!(ring.dropped_count(), 1);
+    }
+}
@@ -0,0 +1,113 @@
+// A PLC that isn't there: serves made up values for a tag list so consumers can run without the PLC, e.g. HMI
+// engineers building screens against the real address space before the hardware exists. Values follow the clock,
+// every tag with its own phase so they don't all move in step:
+//
+// Float32/Float64  sine across the EU range (instrument range, else 0..100), one period a minute
+// UInt32/Int32     ramp from the range's low to its high and back to low, one step a second
+// Bool             toggles every 30 s, alarms included
+//
+// Writes to writable tags stick and stop that tag's simulation, alarm acks come back as events like the PLC echoes
+// them. Everything else (forces, run mode...) is accepted and ignored. No I/O channels or bus diagnostics.
+use crate::plc_info::PlcInfo;
+use crate::ring::{RingItem, ITEM_ALARM_ACK, ITEM_TAG_WRITE};
+use crate::tags::{now_ms, Quality, TagDef, TagSample, TagType, TagValue};
+use std::collections::{HashMap, VecDeque};
+use std::f64::consts::TAU;
+use std::sync::{Mutex, RwLock};
+use std::time::Instant;
+
+const PERIOD_S: f64 = 60.0;
+const TOGGLE_S: u64 = 30;
+
+pub struct Simulator {
+    tags: Vec<TagDef>,
+    by_name: HashMap<String, usize>,
+    started: Instant,
+    written: RwLock<HashMap<usize, TagSample>>, // tags a client wrote, no longer simulated
+    events: Mutex<VecDeque<RingItem>>,
+    info: PlcInfo,
+}
+
+impl Simulator {
+    pub fn new(tags: &[TagDef]) -> Self {
+        Self {
+            tags: tags.to_vec(),
+            by_name: tags.iter().enumerate().map(|(idx, tag)| (tag.name.clone(), idx)).collect(),
+            started: Instant::now(),
+            written: RwLock::new(HashMap::new()),
+            events: Mutex::new(VecDeque::new()),
+            info: PlcInfo::new(env!("CARGO_PKG_VERSION"), "simulated", "sim"),
+        }
+    }
+
+    pub fn tags(&self) -> &[TagDef] {
+        &self.tags
+    }
+
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.by_name.get(name).copied()
+    }
+
+    pub fn read_sample(&self, idx: usize) -> TagSample {
+        if let Some(sample) = self.written.read().unwrap().get(&idx) {
+            return *sample;
+        }
+        TagSample { value: self.simulate(idx), ts_ms: now_ms(), quality: Quality::Good }
+    }
+
+    pub fn read_all_samples(&self) -> Vec<TagSample> {
+        (0..self.tags.len()).map(|idx| self.read_sample(idx)).collect()
+    }
+
+    pub fn push_command(&self, item: RingItem) -> Result<u32, RingItem> {
+        match item.kind {
+            ITEM_TAG_WRITE => {
+                let idx = item.tag as usize;
+                let Some(tag) = self.tags.get(idx).filter(|tag| tag.writable()) else {
+                    return Ok(0);
+                };
+                let value = TagValue::from_raw(tag.ty, item.value);
+                if tag.accepts(value) {
+                    self.written.write().unwrap().insert(idx, TagSample::good(value));
+                }
+            }
+            ITEM_ALARM_ACK => self.events.lock().unwrap().push_back(item),
+            _ => {}
+        }
+        Ok(0)
+    }
+
+    pub fn pop_event(&self) -> Option<RingItem> {
+        self.events.lock().unwrap().pop_front()
+    }
+
+    pub fn read_plc_info(&self) -> PlcInfo {
+        self.info
+    }
+
+    /// Moves like a live PLC's, every 100 ms
+    pub fn plc_heartbeat(&self) -> u32 {
+        (self.started.elapsed().as_millis() / 100) as u32
+    }
+
+    fn simulate(&self, idx: usize) -> TagValue {
+        let tag = &self.tags[idx];
+        let (low, high) = tag.instrument_range.or(tag.eu_range).unwrap_or((0.0, 100.0));
+        let phase = idx as f64 / self.tags.len().max(1) as f64;
+        let elapsed = self.started.elapsed();
+        match tag.ty {
+            TagType::Bool => TagValue::Bool((elapsed.as_secs() + idx as u64 * 7) / TOGGLE_S % 2 == 1),
+            TagType::Float32 | TagType::Float64 => {
+                let wave = ((elapsed.as_secs_f64() / PERIOD_S + phase) * TAU).sin();
+                let value = low + (high - low) * (wave + 1.0) / 2.0;
+                if tag.ty == TagType::Float32 { TagValue::Float32(value as f32) } else { TagValue::Float64(value) }
+            }
+            TagType::UInt32 | TagType::Int32 => {
+                let steps = (high - low).max(1.0) as u64;
+                let step = (elapsed.as_secs() + (phase * steps as f64) as u64) % (2 * steps);
+                let value = low + if step < steps { step } else { 2 * steps - step } as f64;
+                if tag.ty == TagType::UInt32 { TagValue::UInt32(value.max(0.0) as u32) } else { TagValue::Int32(value as i32) }
+            }
+        }
+    }
+}
@@ -0,0 +1,116 @@
+// Startup sequencing. A binary's parts (the IPC region, the bus, the logic, the servers...) come up one at a time in
+// the order they're declared, each naming the parts it needs, which have to be up before it. A part that fails is
+// tried again every `retry` until `timeout` has passed, so one that's only late (a PLC still starting, a port an
+// exiting instance holds, a rack still booting) doesn't fail the binary. What came up goes down in reverse order, at
+// a normal shutdown and when a later part can't come up, through what each part registered with on_down().
+//
+// let mut startup = Startup::new("gipop_plc");
+// let publisher = startup.up(Step::new("ipc").retry(IPC_RETRY, IPC_TIMEOUT), || Publisher::create(&ipc, &tags))
+//     .unwrap_or_else(|e| startup.fail(&e));
+// let bus = startup.up(Step::new("bus").needs(&["ipc"]).retry(BUS_RETRY, BUS_TIMEOUT), || ...)
+//     .unwrap_or_else(|e| startup.fail(&e));
+// startup.on_down(move || ...); // back to INIT
+// ...
+// startup.down();
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+
+type Stop = Box<dyn FnOnce()>;
+
+/// One part of a binary to bring up
+#[derive(Debug, Clone, Copy)]
+pub struct Step {
+    name: &'static str,
+    needs: &'static [&'static str],
+    retry: Duration,
+    timeout: Duration, // Duration::ZERO: tried once
+}
+
+impl Step {
+    /// Tried once, needing nothing
+    pub const fn new(name: &'static str) -> Self {
+        Self { name, needs: &[], retry: Duration::ZERO, timeout: Duration::ZERO }
+    }
+
+    /// Parts that have to be up first
+    pub const fn needs(self, needs: &'static [&'static str]) -> Self {
+        Self { needs, ..self }
+    }
+
+    /// Tried again every `retry` until it's up, no new try starts once `timeout` has passed
+    pub const fn retry(self, retry: Duration, timeout: Duration) -> Self {
+        Self { retry, timeout, ..self }
+    }
+}
+
+/// The parts of a binary that are up, in the order they came up
+pub struct Startup {
+    component: &'static str,
+    up: Vec<(&'static str, Option<Stop>)>, // None: nothing to do to take it down
+}
+
+impl Startup {
+    pub fn new(component: &'static str) -> Self {
+        Self { component, up: Vec::new() }
+    }
+
+    /// Brings `step` up with `start`, retrying per the step. Err if something it needs isn't up or it's still failing
+    /// at its timeout, the last failure's error
+    pub fn up<T, E: Display>(&mut self, step: Step, mut start: impl FnMut() -> Result<T, E>) -> Result<T, String> {
+        if let Some(missing) = step.needs.iter().find(|need| !self.is_up(need)) {
+            return Err(format!("{} needs {}, which isn't up", step.name, missing));
+        }
+        let began = Instant::now();
+        let mut tries = 0;
+        loop {
+            tries += 1;
+            let error = match start() {
+                Ok(value) => {
+                    log::info!(component = self.component, step = step.name; "{} up in {} ms{}", step.name, began.elapsed().as_millis(),
+                        if tries > 1 { format!(", after {} tries", tries) } else { String::new() });
+                    self.up.push((step.name, None));
+                    return Ok(value);
+                }
+                Err(e) => e.to_string(),
+            };
+            if step.timeout.is_zero() || began.elapsed() + step.retry > step.timeout {
+                return Err(format!("{} didn't come up{}: {}", step.name,
+                    if tries > 1 { format!(" in {} tries over {} ms", tries, began.elapsed().as_millis()) } else { String::new() }, error));
+            }
+            if tries == 1 {
+                log::warn!(component = self.component, step = step.name; "{} not up yet, trying again for up to {} ms: {}", step.name, step.timeout.as_millis(), error);
+            } else {
+                log::debug!(component = self.component, step = step.name; "{} not up yet: {}", step.name, error);
+            }
+            std::thread::sleep(step.retry);
+        }
+    }
+
+    /// How to take the part that came up last down again
+    pub fn on_down(&mut self, stop: impl FnOnce() + 'static) {
+        if let Some((_, last)) = self.up.last_mut() {
+            *last = Some(Box::new(stop));
+        }
+    }
+
+    pub fn is_up(&self, name: &str) -> bool {
+        self.up.iter().any(|(up, _)| *up == name)
+    }
+
+    /// Takes every part down, the last one up first
+    pub fn down(&mut self) {
+        while let Some((name, stop)) = self.up.pop() {
+            if let Some(stop) = stop {
+                log::info!(component = self.component, step = name; "Taking {} down", name);
+                stop();
+            }
+        }
+    }
+
+    /// Logs why startup failed, takes down what came up and exits with 1
+    pub fn fail(&mut self, error: &str) -> ! {
+        log::error!(component = self.component; "{}", error);
+        self.down();
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,355 @@
+// Tag types and values as they travel through the shm region.
+// Every value occupies one slot in the value area regardless of type: the 8-byte raw value plus the source
+// timestamp and quality the PLC published it with.
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const TAG_NAME_LEN: usize = 64; // max bytes of a tag name in the directory, zero padded (no terminating NUL needed)
+pub const TAG_FOLDER_LEN: usize = 64; // same for the folder path
+pub const TAG_UNIT_LEN: usize = 16; // same for the engineering unit
+pub const TAG_TEXT_LEN: usize = 256; // same for the localized texts, see TagDef::texts_to_raw
+
+// Directory flags
+pub const TAG_WRITABLE: u8 = 0b0000_0001; // consumers may write this tag (e.g. HMI commands incoming to PLC)
+pub const TAG_ALARM: u8 = 0b0000_0010;    // Bool tag that's true while an alarm condition is active, see TagDef::alarm
+pub const TAG_EXTERNAL: u8 = 0b0000_0100; // value comes from another OPC UA server through gipop_opcua_client, see TagDef::external
+
+pub const TOTALS_FOLDER: &str = "Totals"; // counters operators reset, retained like setpoints, see TagDef::retained
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagType {
+    Bool = 1,
+    UInt32 = 2,
+    Int32 = 3,
+    Float32 = 4,
+    Float64 = 5,
+}
+
+impl TagType {
+    pub fn from_u8(value: u8) -> Result<Self, String> {
+        match value {
+            1 => Ok(TagType::Bool),
+            2 => Ok(TagType::UInt32),
+            3 => Ok(TagType::Int32),
+            4 => Ok(TagType::Float32),
+            5 => Ok(TagType::Float64),
+            _ => Err(format!("Unknown tag type {}", value)),
+        }
+    }
+
+    pub fn default_value(&self) -> TagValue {
+        TagValue::from_raw(*self, 0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TagValue {
+    Bool(bool),
+    UInt32(u32),
+    Int32(i32),
+    Float32(f32),
+    Float64(f64),
+}
+
+impl TagValue {
+    pub fn ty(&self) -> TagType {
+        match self {
+            TagValue::Bool(_) => TagType::Bool,
+            TagValue::UInt32(_) => TagType::UInt32,
+            TagValue::Int32(_) => TagType::Int32,
+            TagValue::Float32(_) => TagType::Float32,
+            TagValue::Float64(_) => TagType::Float64,
+        }
+    }
+
+    /// Bit pattern stored in the value slot
+    pub fn to_raw(&self) -> u64 {
+        match *self {
+            TagValue::Bool(b) => b as u64,
+            TagValue::UInt32(n) => n as u64,
+            TagValue::Int32(n) => n as u32 as u64,
+            TagValue::Float32(f) => f.to_bits() as u64,
+            TagValue::Float64(f) => f.to_bits(),
+        }
+    }
+
+    pub fn from_raw(ty: TagType, raw: u64) -> Self {
+        match ty {
+            TagType::Bool => TagValue::Bool(raw != 0),
+            TagType::UInt32 => TagValue::UInt32(raw as u32),
+            TagType::Int32 => TagValue::Int32(raw as u32 as i32),
+            TagType::Float32 => TagValue::Float32(f32::from_bits(raw as u32)),
+            TagType::Float64 => TagValue::Float64(f64::from_bits(raw)),
+        }
+    }
+
+    /// `number` as a value of type `ty`, None if it doesn't fit (fractions or out of range for integer tags)
+    pub fn from_f64(ty: TagType, number: f64) -> Option<Self> {
+        let whole = number.round();
+        match ty {
+            TagType::Bool => Some(TagValue::Bool(number != 0.0)),
+            TagType::UInt32 => (whole == number && (0.0..=u32::MAX as f64).contains(&number)).then_some(TagValue::UInt32(whole as u32)),
+            TagType::Int32 => (whole == number && (i32::MIN as f64..=i32::MAX as f64).contains(&number)).then_some(TagValue::Int32(whole as i32)),
+            TagType::Float32 => Some(TagValue::Float32(number as f32)),
+            TagType::Float64 => Some(TagValue::Float64(number)),
+        }
+    }
+
+    /// Lossy numeric view, handy for logging/historian/deadbands
+    pub fn as_f64(&self) -> f64 {
+        match *self {
+            TagValue::Bool(b) => b as u8 as f64,
+            TagValue::UInt32(n) => n as f64,
+            TagValue::Int32(n) => n as f64,
+            TagValue::Float32(f) => f as f64,
+            TagValue::Float64(f) => f,
+        }
+    }
+}
+
+/// Set by the PLC next to every value, consumers map it onto their own notion (OPC UA StatusCode etc.)
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Quality {
+    #[default]
+    WaitingForInitialData = 0, // slot was never published, what a freshly created region reads as
+    Good = 1,
+    Uncertain = 2,       // e.g. a last known value the PLC couldn't refresh
+    Bad = 3,
+    BadNotConnected = 4, // the terminal/bus behind the tag isn't there
+}
+
+impl Quality {
+    pub fn from_u32(value: u32) -> Self {
+        match value {
+            0 => Quality::WaitingForInitialData,
+            1 => Quality::Good,
+            2 => Quality::Uncertain,
+            4 => Quality::BadNotConnected,
+            _ => Quality::Bad,
+        }
+    }
+
+    pub fn is_good(&self) -> bool {
+        *self == Quality::Good
+    }
+}
+
+/// A value with the PLC's timestamp (ms since the unix epoch) and quality
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TagSample {
+    pub value: TagValue,
+    pub ts_ms: u64,
+    pub quality: Quality,
+}
+
+impl TagSample {
+    /// Good, stamped now
+    pub fn good(value: TagValue) -> Self {
+        Self { value, ts_ms: now_ms(), quality: Quality::Good }
+    }
+
+    /// Good, stamped `ts_ms`, for values published together
+    pub fn good_at(value: TagValue, ts_ms: u64) -> Self {
+        Self { value, ts_ms, quality: Quality::Good }
+    }
+
+    /// What a tag reads as before the PLC published it the first time
+    pub fn initial(ty: TagType) -> Self {
+        Self { value: ty.default_value(), ts_ms: 0, quality: Quality::WaitingForInitialData }
+    }
+}
+
+pub fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+/// `ts_ms` as "2024-05-01T12:00:00.000Z"
+pub fn iso_time(ts_ms: u64) -> String {
+    let (days, ms) = ((ts_ms / 86_400_000) as i64, ts_ms % 86_400_000);
+    // days since the epoch to a civil date, H. Hinnant's algorithm
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year, month, day, ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, ms % 1000,
+    )
+}
+
+/// Rust types that map 1:1 onto a TagType, for the typed getters/setters on the shm handles
+pub trait TagPrimitive: Sized {
+    const TYPE: TagType;
+    fn from_value(value: TagValue) -> Option<Self>;
+    fn into_value(self) -> TagValue;
+}
+
+macro_rules! tag_primitive {
+    ($ty:ty, $variant:ident) => {
+        impl TagPrimitive for $ty {
+            const TYPE: TagType = TagType::$variant;
+
+            fn from_value(value: TagValue) -> Option<Self> {
+                match value {
+                    TagValue::$variant(v) => Some(v),
+                    _ => None,
+                }
+            }
+
+            fn into_value(self) -> TagValue {
+                TagValue::$variant(self)
+            }
+        }
+    };
+}
+
+tag_primitive!(bool, Bool);
+tag_primitive!(u32, UInt32);
+tag_primitive!(i32, Int32);
+tag_primitive!(f32, Float32);
+tag_primitive!(f64, Float64);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TagDef {
+    pub name: String,
+    pub ty: TagType,
+    pub flags: u8,
+    pub folder: String, // where consumers file the tag, '/' separated e.g. "Area 1/Lights". Empty is the top level
+    pub severity: u16,  // alarms only, 1 (info) to 1000 (critical) as in OPC UA
+    pub unit: String,   // engineering unit symbol, e.g. "°C". Empty for unitless tags
+    pub eu_range: Option<(f64, f64)>,         // low, high the value normally stays within, what HMIs scale gauges to
+    pub instrument_range: Option<(f64, f64)>, // low, high the sensor/transmitter can report at all
+    pub texts: Vec<TagText>, // display name and description per locale, the first one is the default
+}
+
+/// What a tag is called and described as in one locale
+#[derive(Debug, Clone, PartialEq)]
+pub struct TagText {
+    pub locale: String, // e.g. "en", "ms", "en-US"
+    pub display_name: String,
+    pub description: String,
+}
+
+impl TagDef {
+    pub fn new(name: &str, ty: TagType, flags: u8) -> Self {
+        Self {
+            name: name.to_string(),
+            ty,
+            flags,
+            folder: String::new(),
+            severity: 0,
+            unit: String::new(),
+            eu_range: None,
+            instrument_range: None,
+            texts: Vec::new(),
+        }
+    }
+
+    /// Alarm condition, the tag's name doubles as the alarm's message
+    pub fn alarm(name: &str, severity: u16) -> Self {
+        Self { severity: severity.clamp(1, 1000), ..Self::new(name, TagType::Bool, TAG_ALARM) }
+    }
+
+    pub fn in_folder(mut self, folder: &str) -> Self {
+        self.folder = folder.to_string();
+        self
+    }
+
+    /// Fed from another OPC UA server by gipop_opcua_client, which writes it like a command. Read-only for everyone else.
+    pub fn external(name: &str, ty: TagType) -> Self {
+        Self::new(name, ty, TAG_EXTERNAL)
+    }
+
+    /// Analog measurement in `unit`, normally between `low` and `high`
+    pub fn analog(mut self, unit: &str, low: f64, high: f64) -> Self {
+        self.unit = unit.to_string();
+        self.eu_range = Some((low, high));
+        self
+    }
+
+    pub fn instrument_range(mut self, low: f64, high: f64) -> Self {
+        self.instrument_range = Some((low, high));
+        self
+    }
+
+    /// Display name and description in `locale`, add the default locale first
+    pub fn text(mut self, locale: &str, display_name: &str, description: &str) -> Self {
+        self.texts.push(TagText { locale: locale.to_string(), display_name: display_name.to_string(), description: description.to_string() });
+        self
+    }
+
+    /// The texts as they're stored in the directory, "locale\tdisplay name\tdescription" lines. Tabs and newlines
+    /// inside a text would break that and are replaced with spaces.
+    pub fn texts_to_raw(&self) -> String {
+        let clean = |text: &str| text.replace(['\t', '\n'], " ");
+        self.texts.iter()
+            .map(|text| format!("{}\t{}\t{}", clean(&text.locale), clean(&text.display_name), clean(&text.description)))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub fn texts_from_raw(raw: &str) -> Vec<TagText> {
+        raw.lines().filter_map(|line| {
+            let mut fields = line.splitn(3, '\t');
+            Some(TagText {
+                locale: fields.next()?.to_string(),
+                display_name: fields.next()?.to_string(),
+                description: fields.next().unwrap_or_default().to_string(),
+            })
+        }).collect()
+    }
+
+    /// Folder path split into its non-empty components
+    pub fn folder_path(&self) -> impl Iterator<Item = &str> {
+        self.folder.split('/').filter(|part| !part.is_empty())
+    }
+
+    pub fn writable(&self) -> bool {
+        self.flags & TAG_WRITABLE != 0
+    }
+
+    /// What writes have to stay within: the instrument range, or else the EU range. None for unbounded tags
+    pub fn write_range(&self) -> Option<(f64, f64)> {
+        self.instrument_range.or(self.eu_range)
+    }
+
+    /// Whether `value` can be written to the tag: its type, and a number within write_range
+    pub fn accepts(&self, value: TagValue) -> bool {
+        let number = value.as_f64();
+        value.ty() == self.ty
+            && number.is_finite()
+            && self.write_range().is_none_or(|(low, high)| (low..=high).contains(&number))
+    }
+
+    pub fn is_external(&self) -> bool {
+        self.flags & TAG_EXTERNAL != 0
+    }
+
+    pub fn is_alarm(&self) -> bool {
+        self.flags & TAG_ALARM != 0 && self.ty == TagType::Bool
+    }
+
+    /// What the PLC only keeps in memory and a new one would start without, what `gipop backup` saves and the standby of
+    /// a redundant pair is kept up to date with: "retained" for writable tags (setpoints and HMI commands) other than
+    /// external ones, "total" for the counters in TOTALS_FOLDER. None for the rest, the bus and the logic make those
+    pub fn retained(&self) -> Option<&'static str> {
+        if self.writable() && !self.is_external() {
+            Some("retained")
+        } else if self.folder == TOTALS_FOLDER && !self.is_alarm() {
+            Some("total")
+        } else {
+            None
+        }
+    }
+
+    /// Numeric tag with a unit or range, what OPC UA calls an analog item
+    pub fn is_analog(&self) -> bool {
+        self.ty != TagType::Bool && (!self.unit.is_empty() || self.eu_range.is_some() || self.instrument_range.is_some())
+    }
+}
@@ -0,0 +1,379 @@
+// The `[telemetry]` section of gipop.toml and the `tracing` subscriber that ships spans to an OpenTelemetry collector
+// over OTLP/HTTP (JSON), `otlp` cargo feature. The binaries open spans around their cycle, handlers, IPC and OPC UA
+// callbacks; without an endpoint (or the feature) no subscriber is installed and those spans cost next to nothing.
+//
+// [telemetry]
+// otlp_endpoint = "http://localhost:4318"   # the collector, spans go to <endpoint>/v1/traces. Empty: no tracing
+// batch_ms = 1000                            # how often finished spans are sent
+// cycle_every = 1000                         # PLC only, trace one EtherCAT cycle in this many
+// [telemetry.headers]                        # sent along, e.g. the API key of a hosted collector
+// authorization = "Bearer ..."
+//
+// Processes don't share a trace context over the IPC, the region has no room for one. What they do share is the
+// timestamp of each publish (TagSample::ts_ms): the PLC's span of a publish carries it as `publish_ts`, which makes it
+// the root of a trace whose ids are derived from that timestamp, and a consumer's span carrying `from_publish_ts`
+// joins that trace as its child. So PLC write -> shm -> OPC UA notify shows up as one trace in the collector.
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TelemetryConfig {
+    pub otlp_endpoint: String,
+    pub headers: BTreeMap<String, String>,
+    pub batch_ms: u64,
+    pub cycle_every: u64,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self { otlp_endpoint: String::new(), headers: BTreeMap::new(), batch_ms: 1000, cycle_every: 1000 }
+    }
+}
+
+impl TelemetryConfig {
+    /// Reads the `[telemetry]` section of `path`. A missing file or section means defaults.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        crate::config::load_section(path, "telemetry")
+    }
+}
+
+/// Starts exporting spans to `otlp_endpoint` as `service` ("gipop_plc"), nothing without an endpoint
+#[cfg(feature = "otlp")]
+pub fn init(config: &TelemetryConfig, service: &str) {
+    if config.otlp_endpoint.is_empty() {
+        return;
+    }
+    match otlp::Tracer::start(config, service) {
+        Ok(tracer) => {
+            if tracing::subscriber::set_global_default(tracer).is_err() {
+                log::warn!("[Telemetry] A tracing subscriber is installed already");
+                return;
+            }
+            log::info!("[Telemetry] Exporting spans to {}", config.otlp_endpoint);
+        }
+        Err(e) => log::error!("[Telemetry] {}", e),
+    }
+}
+
+#[cfg(not(feature = "otlp"))]
+pub fn init(config: &TelemetryConfig, _service: &str) {
+    if !config.otlp_endpoint.is_empty() {
+        log::warn!("[Telemetry] otlp_endpoint is set but this build doesn't have the `otlp` feature");
+    }
+}
+
+/// `make()` if `traced`, else a span that isn't recorded. For hot loops that only trace some of their rounds: a span
+/// opened inside an untraced round would start a trace of its own.
+pub fn span_if(traced: bool, make: impl FnOnce() -> tracing::Span) -> tracing::Span {
+    if traced { make() } else { tracing::Span::none() }
+}
+
+#[cfg(feature = "otlp")]
+mod otlp {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::fmt;
+    use std::num::NonZeroU64;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use serde_json::{json, Value};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    use super::TelemetryConfig;
+
+    const MAX_QUEUE: usize = 10_000; // finished spans waiting for the exporter, the newest are dropped beyond this
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    thread_local! {
+        // entered spans of this thread, innermost last
+        static STACK: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+    }
+
+    #[derive(Clone)]
+    struct SpanData {
+        trace_id: u128,
+        span_id: u64,
+        parent_id: Option<u64>,
+        name: &'static str,
+        start_ns: u64,
+        end_ns: u64,
+        attributes: Vec<(&'static str, Value)>,
+        refs: usize,
+    }
+
+    pub struct Tracer {
+        spans: Mutex<HashMap<u64, SpanData>>, // open spans by span id, which is also their tracing Id
+        finished: Arc<Mutex<Vec<SpanData>>>,
+        dropped: Arc<AtomicU64>,
+        random: AtomicU64,
+    }
+
+    impl Tracer {
+        pub fn start(config: &TelemetryConfig, service: &str) -> Result<Self, String> {
+            let url = format!("{}/v1/traces", config.otlp_endpoint.trim_end_matches('/'));
+            let finished = Arc::new(Mutex::new(Vec::new()));
+            let dropped = Arc::new(AtomicU64::new(0));
+            let exporter = Exporter {
+                url,
+                headers: config.headers.clone(),
+                service: service.to_string(),
+                agent: ureq::AgentBuilder::new().timeout(TIMEOUT).build(),
+                finished: finished.clone(),
+                dropped: dropped.clone(),
+                failing: false,
+            };
+            let interval = Duration::from_millis(config.batch_ms.max(100));
+            std::thread::Builder::new()
+                .name("OtlpExporter".to_owned())
+                .spawn(move || exporter.run(interval))
+                .map_err(|e| format!("Failed to start the span exporter: {}", e))?;
+            let seed = now_ns() ^ (std::process::id() as u64).rotate_left(32);
+            Ok(Self { spans: Mutex::new(HashMap::new()), finished, dropped, random: AtomicU64::new(seed) })
+        }
+
+        fn next_random(&self) -> u64 {
+            loop {
+                let n = mix(self.random.fetch_add(0x9E37_79B9_7F4A_7C15, Ordering::Relaxed));
+                if n != 0 {
+                    return n;
+                }
+            }
+        }
+
+        fn current(&self) -> Option<u64> {
+            STACK.with(|stack| stack.borrow().last().copied())
+        }
+    }
+
+    impl Subscriber for Tracer {
+        fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+            metadata.is_span() // events are log's business
+        }
+
+        fn new_span(&self, attributes: &Attributes<'_>) -> Id {
+            let mut fields = Fields::default();
+            attributes.record(&mut fields);
+            let parent = if attributes.is_root() {
+                None
+            }
+            else if let Some(parent) = attributes.parent() {
+                Some(parent.into_u64())
+            }
+            else {
+                self.current()
+            };
+            let mut spans = self.spans.lock().unwrap();
+            let parent = parent.and_then(|id| spans.get(&id)).map(|parent| (parent.trace_id, parent.span_id));
+            let (trace_id, span_id, parent_id) = match (fields.publish_ts, fields.from_publish_ts, parent) {
+                (Some(ts), _, _) => (publish_trace(ts), publish_span(ts), None),
+                (None, Some(ts), _) => (publish_trace(ts), self.next_random(), Some(publish_span(ts))),
+                (None, None, Some((trace_id, parent_id))) => (trace_id, self.next_random(), Some(parent_id)),
+                (None, None, None) => ((self.next_random() as u128) << 64 | self.next_random() as u128, self.next_random(), None),
+            };
+            // a span id derived from a publish could already be open, another publish in the same millisecond
+            let span_id = if spans.contains_key(&span_id) { self.next_random() } else { span_id };
+            spans.insert(span_id, SpanData {
+                trace_id,
+                span_id,
+                parent_id,
+                name: attributes.metadata().name(),
+                start_ns: now_ns(),
+                end_ns: 0,
+                attributes: fields.attributes,
+                refs: 1,
+            });
+            Id::from_non_zero_u64(NonZeroU64::new(span_id).expect("span ids aren't 0"))
+        }
+
+        fn record(&self, span: &Id, values: &Record<'_>) {
+            let mut fields = Fields::default();
+            values.record(&mut fields);
+            if let Some(data) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+                data.attributes.extend(fields.attributes);
+            }
+        }
+
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+        fn event(&self, _event: &Event<'_>) {}
+
+        fn enter(&self, span: &Id) {
+            STACK.with(|stack| stack.borrow_mut().push(span.into_u64()));
+        }
+
+        fn exit(&self, span: &Id) {
+            STACK.with(|stack| {
+                let mut stack = stack.borrow_mut();
+                if let Some(at) = stack.iter().rposition(|&id| id == span.into_u64()) {
+                    stack.remove(at);
+                }
+            });
+        }
+
+        fn clone_span(&self, span: &Id) -> Id {
+            if let Some(data) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+                data.refs += 1;
+            }
+            span.clone()
+        }
+
+        fn try_close(&self, span: Id) -> bool {
+            let mut spans = self.spans.lock().unwrap();
+            let Some(data) = spans.get_mut(&span.into_u64()) else {
+                return false;
+            };
+            data.refs -= 1;
+            if data.refs > 0 {
+                return false;
+            }
+            let mut data = spans.remove(&span.into_u64()).expect("just looked at");
+            drop(spans);
+            data.end_ns = now_ns();
+            let mut finished = self.finished.lock().unwrap();
+            if finished.len() < MAX_QUEUE {
+                finished.push(data);
+            }
+            else {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            true
+        }
+    }
+
+    // Span fields as OTLP attributes, publish_ts and from_publish_ts are picked out for the trace ids
+    #[derive(Default)]
+    struct Fields {
+        attributes: Vec<(&'static str, Value)>,
+        publish_ts: Option<u64>,
+        from_publish_ts: Option<u64>,
+    }
+
+    impl Visit for Fields {
+        fn record_u64(&mut self, field: &Field, value: u64) {
+            match field.name() {
+                "publish_ts" => self.publish_ts = Some(value),
+                "from_publish_ts" => self.from_publish_ts = Some(value),
+                _ => {}
+            }
+            self.attributes.push((field.name(), json!({ "intValue": value.to_string() })));
+        }
+
+        fn record_i64(&mut self, field: &Field, value: i64) {
+            self.attributes.push((field.name(), json!({ "intValue": value.to_string() })));
+        }
+
+        fn record_f64(&mut self, field: &Field, value: f64) {
+            self.attributes.push((field.name(), json!({ "doubleValue": value })));
+        }
+
+        fn record_bool(&mut self, field: &Field, value: bool) {
+            self.attributes.push((field.name(), json!({ "boolValue": value })));
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.attributes.push((field.name(), json!({ "stringValue": value })));
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.attributes.push((field.name(), json!({ "stringValue": format!("{:?}", value) })));
+        }
+    }
+
+    struct Exporter {
+        url: String,
+        headers: std::collections::BTreeMap<String, String>,
+        service: String,
+        agent: ureq::Agent,
+        finished: Arc<Mutex<Vec<SpanData>>>,
+        dropped: Arc<AtomicU64>,
+        failing: bool, // logged once per outage, not for every batch
+    }
+
+    impl Exporter {
+        fn run(mut self, interval: Duration) {
+            loop {
+                std::thread::sleep(interval);
+                let batch = std::mem::take(&mut *self.finished.lock().unwrap());
+                let dropped = self.dropped.swap(0, Ordering::Relaxed);
+                if dropped > 0 {
+                    log::warn!("[Telemetry] {} span(s) dropped, the exporter can't keep up", dropped);
+                }
+                if !batch.is_empty() {
+                    self.send(&batch);
+                }
+            }
+        }
+
+        fn send(&mut self, batch: &[SpanData]) {
+            let mut request = self.agent.post(&self.url).set("Content-Type", "application/json");
+            for (name, value) in &self.headers {
+                request = request.set(name, value);
+            }
+            match request.send_string(&self.body(batch).to_string()) {
+                Ok(_) if self.failing => {
+                    log::info!("[Telemetry] Exporting to {} again", self.url);
+                    self.failing = false;
+                }
+                Ok(_) => {}
+                Err(e) if !self.failing => {
+                    log::warn!("[Telemetry] Failed to export {} span(s): {}", batch.len(), e);
+                    self.failing = true;
+                }
+                Err(_) => {}
+            }
+        }
+
+        // ExportTraceServiceRequest in OTLP's JSON encoding, ids as hex and 64 bit integers as strings
+        fn body(&self, batch: &[SpanData]) -> Value {
+            let spans: Vec<Value> = batch.iter()
+                .map(|span| {
+                    let mut value = json!({
+                        "traceId": format!("{:032x}", span.trace_id),
+                        "spanId": format!("{:016x}", span.span_id),
+                        "name": span.name,
+                        "kind": 1, // internal
+                        "startTimeUnixNano": span.start_ns.to_string(),
+                        "endTimeUnixNano": span.end_ns.to_string(),
+                        "attributes": span.attributes.iter().map(|(key, value)| json!({ "key": key, "value": value })).collect::<Vec<_>>(),
+                    });
+                    if let Some(parent) = span.parent_id {
+                        value["parentSpanId"] = json!(format!("{:016x}", parent));
+                    }
+                    value
+                })
+                .collect();
+            json!({
+                "resourceSpans": [{
+                    "resource": { "attributes": [{ "key": "service.name", "value": { "stringValue": self.service } }] },
+                    "scopeSpans": [{ "scope": { "name": "gipop", "version": env!("CARGO_PKG_VERSION") }, "spans": spans }],
+                }]
+            })
+        }
+    }
+
+    fn now_ns() -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0)
+    }
+
+    // splitmix64's finalizer, spreads neighbouring inputs over the whole range
+    fn mix(mut z: u64) -> u64 {
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // The ids every process derives from a publish's timestamp, see the top of this file
+    fn publish_trace(ts_ms: u64) -> u128 {
+        (mix(ts_ms ^ 0x6769_706F_7074_7261) as u128) << 64 | mix(ts_ms ^ 0x6365_6964_0000_0001) as u128
+    }
+
+    fn publish_span(ts_ms: u64) -> u64 {
+        mix(ts_ms ^ 0x7075_626C_6973_6800).max(1)
+    }
+}
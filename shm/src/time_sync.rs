@@ -0,0 +1,87 @@
+// Host clock synchronization (NTP, PTP). Every timestamp the PLC hands out comes from the host clock, a sequence of
+// events recorded off an unsynchronized clock can't be lined up with anything else in the plant.
+//
+// Two ways to ask, `[time_sync] source` in gipop.toml:
+// kernel  the kernel's clock discipline (adjtimex). chronyd, ntpd, systemd-timesyncd and phc2sys (PTP) all clear its
+//         unsynchronized flag while they keep the clock in step, but only ntpd leaves an offset in it, the others
+//         steer the clock themselves and the offset reads 0. The error bounds are what the daemon set
+// chrony  `chronyc -c tracking`, chronyd's own offset and root distance. Covers PTP too with a PHC refclock
+//
+// The PLC publishes what it got as System tags, consumers recording timestamps look at SYNCED_TAG to flag what they
+// record while the clock isn't synchronized.
+use serde::Deserialize;
+
+/// Tag the PLC publishes the verdict in, true while the clock is synchronized within `[time_sync] max_offset_ms`.
+/// Never published (still waiting for initial data) while the clock isn't monitored
+pub const SYNCED_TAG: &str = "clock synced";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeSource {
+    #[default]
+    Kernel,
+    Chrony,
+}
+
+/// One reading of the clock's synchronization state
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ClockSync {
+    pub synced: bool,      // the daemon considers the clock synchronized to its reference
+    pub offset_ms: f64,    // of the host clock from the reference, positive when it's ahead
+    pub max_error_ms: f64, // how far off the clock can be at worst
+}
+
+impl ClockSync {
+    /// Synchronized and closer to the reference than `max_offset_ms`, i.e. its timestamps can be trusted
+    pub fn within(&self, max_offset_ms: f64) -> bool {
+        self.synced && self.offset_ms.abs() <= max_offset_ms
+    }
+}
+
+/// Asks `source`, Err if it can't tell (no chronyc, a platform without adjtimex...)
+pub fn query(source: TimeSource) -> Result<ClockSync, String> {
+    match source {
+        TimeSource::Kernel => kernel(),
+        TimeSource::Chrony => {
+            let output = std::process::Command::new("chronyc").args(["-c", "tracking"]).output()
+                .map_err(|e| format!("Can't run chronyc: {}", e))?;
+            if !output.status.success() {
+                return Err(format!("chronyc: {}", String::from_utf8_lossy(&output.stderr).trim()));
+            }
+            parse_chrony(&String::from_utf8_lossy(&output.stdout))
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn kernel() -> Result<ClockSync, String> {
+    let mut timex: libc::timex = unsafe { std::mem::zeroed() }; // modes 0, reads without adjusting anything
+    let state = unsafe { libc::adjtimex(&mut timex) };
+    if state == -1 {
+        return Err(format!("adjtimex: {}", std::io::Error::last_os_error()));
+    }
+    let unit = if timex.status & libc::STA_NANO != 0 { 1e6 } else { 1e3 }; // offset in ns or µs
+    Ok(ClockSync {
+        synced: state != libc::TIME_ERROR && timex.status & libc::STA_UNSYNC == 0,
+        offset_ms: timex.offset as f64 / unit,
+        max_error_ms: timex.maxerror as f64 / 1e3, // always µs
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn kernel() -> Result<ClockSync, String> {
+    Err("The kernel's clock discipline can only be read on Linux, use source = \"chrony\"".to_owned())
+}
+
+// `chronyc -c tracking`: ref id, ref name, stratum, ref time, system time (s), last offset, RMS offset, frequency,
+// residual frequency, skew, root delay (s), root dispersion (s), update interval, leap status
+fn parse_chrony(csv: &str) -> Result<ClockSync, String> {
+    let fields: Vec<&str> = csv.trim().split(',').collect();
+    let seconds = |idx: usize| fields.get(idx).and_then(|field| field.parse::<f64>().ok()).ok_or_else(|| format!("Unexpected chronyc output {:?}", csv.trim()));
+    let (system_time, root_delay, root_dispersion) = (seconds(4)?, seconds(10)?, seconds(11)?);
+    Ok(ClockSync {
+        synced: fields.get(13).is_some_and(|leap| *leap != "Not synchronised"),
+        offset_ms: -system_time * 1e3, // chrony's is the correction still to apply, the other way round
+        max_error_ms: (root_delay / 2.0 + root_dispersion) * 1e3, // root distance
+    })
+}
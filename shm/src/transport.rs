@@ -0,0 +1,365 @@
+// One API over the configured transport, so the PLC and consumers don't care whether they talk through the mmap
+// region or a socket. `Publisher` is the PLC end, `Subscriber` the consumer end, which can also be a simulated PLC
+// (sim.rs) for running consumers without one.
+use crate::blob::Blob;
+use crate::config::{IpcConfig, Transport};
+use crate::bus_diag::BusDiagnostics;
+use crate::plc_info::PlcInfo;
+use crate::process_image::ProcessImage;
+use crate::io_mirror::IoChannel;
+use crate::handle::{ShmReader, ShmWriter};
+use crate::ring::RingItem;
+use crate::sim::Simulator;
+use crate::tags::{TagDef, TagPrimitive, TagSample, TagValue};
+#[cfg(unix)]
+use crate::uds::{UdsClient, UdsServer};
+use std::io;
+use std::sync::Arc;
+
+pub enum Publisher {
+    Shm(ShmWriter),
+    #[cfg(unix)]
+    Uds(Box<UdsServer>),
+}
+
+impl Publisher {
+    pub fn create(config: &IpcConfig, defs: &[TagDef]) -> io::Result<Self> {
+        let access = config.access()?;
+        match config.transport {
+            Transport::Shm => ShmWriter::create(&config.shm_path, defs, &access).map(Publisher::Shm),
+            #[cfg(unix)]
+            Transport::Uds => UdsServer::bind(&config.socket_path, defs, access).map(|server| Publisher::Uds(Box::new(server))),
+            #[cfg(not(unix))]
+            Transport::Uds => Err(io::Error::other("The uds transport is only available on unix")),
+        }
+    }
+
+    pub fn tags(&self) -> &[TagDef] {
+        match self {
+            Publisher::Shm(writer) => writer.tags(),
+            #[cfg(unix)]
+            Publisher::Uds(server) => server.tags(),
+        }
+    }
+
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        match self {
+            Publisher::Shm(writer) => writer.index_of(name),
+            #[cfg(unix)]
+            Publisher::Uds(server) => server.index_of(name),
+        }
+    }
+
+    pub fn write_many(&mut self, values: &[(usize, TagValue)]) -> Result<(), String> {
+        match self {
+            Publisher::Shm(writer) => writer.write_many(values),
+            #[cfg(unix)]
+            Publisher::Uds(server) => server.write_many(values),
+        }
+    }
+
+    /// For values the PLC couldn't refresh or doesn't trust, write_many() stamps everything Good
+    pub fn write_samples(&mut self, samples: &[(usize, TagSample)]) -> Result<(), String> {
+        match self {
+            Publisher::Shm(writer) => writer.write_samples(samples),
+            #[cfg(unix)]
+            Publisher::Uds(server) => server.write_samples(samples),
+        }
+    }
+
+    pub fn write(&mut self, idx: usize, value: TagValue) -> Result<(), String> {
+        self.write_many(&[(idx, value)])
+    }
+
+    pub fn set<T: TagPrimitive>(&mut self, idx: usize, value: T) -> Result<(), String> {
+        self.write(idx, value.into_value())
+    }
+
+    pub fn pop_command(&mut self) -> Option<RingItem> {
+        match self {
+            Publisher::Shm(writer) => writer.pop_command(),
+            #[cfg(unix)]
+            Publisher::Uds(server) => server.pop_command(),
+        }
+    }
+
+    /// Tell consumers every command up to `seq` has been consumed. Only the shm ring tracks acks, the socket is
+    /// ordered and lossless so a command that went out will be applied.
+    pub fn ack_command(&mut self, seq: u32) {
+        match self {
+            Publisher::Shm(writer) => writer.ack_command(seq),
+            #[cfg(unix)]
+            Publisher::Uds(_) => {}
+        }
+    }
+
+    pub fn push_event(&mut self, item: RingItem) {
+        match self {
+            Publisher::Shm(writer) => writer.push_event(item),
+            #[cfg(unix)]
+            Publisher::Uds(server) => server.push_event(item),
+        }
+    }
+
+    /// Terminal channel mirror, the whole of it every sync cycle
+    pub fn write_io(&mut self, channels: &[IoChannel]) {
+        match self {
+            Publisher::Shm(writer) => writer.write_io(channels),
+            #[cfg(unix)]
+            Publisher::Uds(server) => server.write_io(channels),
+        }
+    }
+
+    /// Fieldbus health, whenever the PLC has fresh numbers
+    pub fn write_bus_diag(&mut self, diag: &BusDiagnostics) {
+        match self {
+            Publisher::Shm(writer) => writer.write_bus_diag(diag),
+            #[cfg(unix)]
+            Publisher::Uds(server) => server.write_bus_diag(diag),
+        }
+    }
+
+    /// The PLC's build and start time, once at startup
+    pub fn write_plc_info(&mut self, info: &PlcInfo) {
+        match self {
+            Publisher::Shm(writer) => writer.write_plc_info(info),
+            #[cfg(unix)]
+            Publisher::Uds(server) => server.write_plc_info(info),
+        }
+    }
+
+    /// Raw process image with its layout, every sync cycle once the bus is in OP
+    pub fn write_process_image(&mut self, image: &ProcessImage) {
+        match self {
+            Publisher::Shm(writer) => writer.write_process_image(image),
+            #[cfg(unix)]
+            Publisher::Uds(server) => server.write_process_image(image),
+        }
+    }
+
+    /// Variable-length payload for every consumer, see blob.rs for `kind`
+    pub fn push_blob(&mut self, kind: u16, payload: &[u8]) -> Result<(), String> {
+        match self {
+            Publisher::Shm(writer) => writer.push_blob(kind, payload),
+            #[cfg(unix)]
+            Publisher::Uds(server) => server.push_blob(kind, payload),
+        }
+    }
+
+    /// Once per sync cycle, see heartbeat.rs
+    pub fn heartbeat(&mut self) {
+        match self {
+            Publisher::Shm(writer) => writer.heartbeat(),
+            #[cfg(unix)]
+            Publisher::Uds(server) => server.heartbeat(),
+        }
+    }
+
+    /// Moves while at least one consumer is alive, feed it to a Liveness
+    pub fn consumer_heartbeat(&mut self) -> u32 {
+        match self {
+            Publisher::Shm(writer) => writer.consumer_heartbeat(),
+            #[cfg(unix)]
+            Publisher::Uds(server) => server.consumer_heartbeat(),
+        }
+    }
+}
+
+/// Cheap to clone, clones share the connection
+#[derive(Clone)]
+pub enum Subscriber {
+    Shm(ShmReader),
+    #[cfg(unix)]
+    Uds(Arc<UdsClient>),
+    Sim(Arc<Simulator>),
+}
+
+impl Subscriber {
+    /// Attach to a running PLC
+    pub fn connect(config: &IpcConfig) -> Result<Self, String> {
+        match config.transport {
+            Transport::Shm => ShmReader::open(&config.shm_path).map(Subscriber::Shm),
+            #[cfg(unix)]
+            Transport::Uds => UdsClient::connect(&config.socket_path)
+                .map(|client| Subscriber::Uds(Arc::new(client)))
+                .map_err(|e| format!("Failed to connect to {}: {}", config.socket_path, e)),
+            #[cfg(not(unix))]
+            Transport::Uds => Err("The uds transport is only available on unix".into()),
+        }
+    }
+
+    /// No PLC, simulated values for `tags` instead, see sim.rs
+    pub fn simulate(tags: &[TagDef]) -> Self {
+        Subscriber::Sim(Arc::new(Simulator::new(tags)))
+    }
+
+    pub fn tags(&self) -> &[TagDef] {
+        match self {
+            Subscriber::Shm(reader) => reader.tags(),
+            #[cfg(unix)]
+            Subscriber::Uds(client) => client.tags(),
+            Subscriber::Sim(sim) => sim.tags(),
+        }
+    }
+
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        match self {
+            Subscriber::Shm(reader) => reader.index_of(name),
+            #[cfg(unix)]
+            Subscriber::Uds(client) => client.index_of(name),
+            Subscriber::Sim(sim) => sim.index_of(name),
+        }
+    }
+
+    pub fn read(&self, idx: usize) -> TagValue {
+        match self {
+            Subscriber::Shm(reader) => reader.read(idx),
+            #[cfg(unix)]
+            Subscriber::Uds(client) => client.read(idx),
+            Subscriber::Sim(sim) => sim.read_sample(idx).value,
+        }
+    }
+
+    /// Value with the timestamp and quality the PLC published it with
+    pub fn read_sample(&self, idx: usize) -> TagSample {
+        match self {
+            Subscriber::Shm(reader) => reader.read_sample(idx),
+            #[cfg(unix)]
+            Subscriber::Uds(client) => client.read_sample(idx),
+            Subscriber::Sim(sim) => sim.read_sample(idx),
+        }
+    }
+
+    pub fn read_all(&self) -> Vec<TagValue> {
+        match self {
+            Subscriber::Shm(reader) => reader.read_all(),
+            #[cfg(unix)]
+            Subscriber::Uds(client) => client.read_all(),
+            Subscriber::Sim(sim) => sim.read_all_samples().iter().map(|sample| sample.value).collect(),
+        }
+    }
+
+    pub fn read_all_samples(&self) -> Vec<TagSample> {
+        match self {
+            Subscriber::Shm(reader) => reader.read_all_samples(),
+            #[cfg(unix)]
+            Subscriber::Uds(client) => client.read_all_samples(),
+            Subscriber::Sim(sim) => sim.read_all_samples(),
+        }
+    }
+
+    /// None if there's no such tag or it isn't a `T`
+    pub fn get<T: TagPrimitive>(&self, idx: usize) -> Option<T> {
+        if self.tags().get(idx)?.ty != T::TYPE {
+            return None;
+        }
+        T::from_value(self.read(idx))
+    }
+
+    pub fn get_by_name<T: TagPrimitive>(&self, name: &str) -> Option<T> {
+        self.get(self.index_of(name)?)
+    }
+
+    /// Returns the command's sequence number, 0 if the transport doesn't track acks
+    pub fn push_command(&self, item: RingItem) -> Result<u32, RingItem> {
+        match self {
+            Subscriber::Shm(reader) => reader.push_command(item),
+            #[cfg(unix)]
+            Subscriber::Uds(client) => client.push_command(item).map(|_| 0),
+            Subscriber::Sim(sim) => sim.push_command(item),
+        }
+    }
+
+    pub fn is_command_acked(&self, seq: u32) -> bool {
+        match self {
+            Subscriber::Shm(reader) => seq == 0 || reader.is_command_acked(seq),
+            #[cfg(unix)]
+            Subscriber::Uds(_) => true,
+            Subscriber::Sim(_) => true,
+        }
+    }
+
+    pub fn pop_event(&self) -> Option<RingItem> {
+        match self {
+            Subscriber::Shm(reader) => reader.pop_event(),
+            #[cfg(unix)]
+            Subscriber::Uds(client) => client.pop_event(),
+            Subscriber::Sim(sim) => sim.pop_event(),
+        }
+    }
+
+    pub fn read_io(&self) -> Vec<IoChannel> {
+        match self {
+            Subscriber::Shm(reader) => reader.read_io(),
+            #[cfg(unix)]
+            Subscriber::Uds(client) => client.read_io(),
+            Subscriber::Sim(_) => Vec::new(),
+        }
+    }
+
+    pub fn read_bus_diag(&self) -> Option<BusDiagnostics> {
+        match self {
+            Subscriber::Shm(reader) => reader.read_bus_diag(),
+            #[cfg(unix)]
+            Subscriber::Uds(client) => client.read_bus_diag(),
+            Subscriber::Sim(_) => None,
+        }
+    }
+
+    pub fn read_plc_info(&self) -> Option<PlcInfo> {
+        match self {
+            Subscriber::Shm(reader) => reader.read_plc_info(),
+            #[cfg(unix)]
+            Subscriber::Uds(client) => client.read_plc_info(),
+            Subscriber::Sim(sim) => Some(sim.read_plc_info()),
+        }
+    }
+
+    pub fn read_process_image(&self) -> Option<ProcessImage> {
+        match self {
+            Subscriber::Shm(reader) => reader.read_process_image(),
+            #[cfg(unix)]
+            Subscriber::Uds(client) => client.read_process_image(),
+            Subscriber::Sim(_) => None,
+        }
+    }
+
+    pub fn pop_blob(&self) -> Option<Blob> {
+        match self {
+            Subscriber::Shm(reader) => reader.pop_blob(),
+            #[cfg(unix)]
+            Subscriber::Uds(client) => client.pop_blob(),
+            Subscriber::Sim(_) => None,
+        }
+    }
+
+    /// Call periodically so the PLC sees this consumer as alive
+    pub fn heartbeat(&self) {
+        match self {
+            Subscriber::Shm(reader) => reader.heartbeat(),
+            #[cfg(unix)]
+            Subscriber::Uds(client) => client.heartbeat(),
+            Subscriber::Sim(_) => {}
+        }
+    }
+
+    /// Moves while the PLC is alive, feed it to a Liveness
+    pub fn plc_heartbeat(&self) -> u32 {
+        match self {
+            Subscriber::Shm(reader) => reader.plc_heartbeat(),
+            #[cfg(unix)]
+            Subscriber::Uds(client) => client.plc_heartbeat(),
+            Subscriber::Sim(sim) => sim.plc_heartbeat(),
+        }
+    }
+
+    /// The shm region outlives the PLC, so only a socket can tell that it went away
+    pub fn is_connected(&self) -> bool {
+        match self {
+            Subscriber::Shm(_) => true,
+            #[cfg(unix)]
+            Subscriber::Uds(client) => client.is_connected(),
+            Subscriber::Sim(_) => true,
+        }
+    }
+}
@@ -0,0 +1,652 @@
+// Unix domain socket alternative to the mmap region, for deployments where the PLC and its consumers can't share
+// /dev/shm (e.g. containers with a private one). Same model as the region: the PLC owns the tag values and
+// publishes whole updates, consumers send commands back, the PLC pushes events.
+//
+// Every frame is | len: u32 LE (bytes after this field) | kind: u8 | payload |
+//
+// FRAME_DIRECTORY  PLC -> consumer, first frame on connect. tag_count: u32, then per tag ty: u8, flags: u8, name_len: u16, name,
+//                  folder_len: u16, folder, severity: u16, unit_len: u16, unit, eu_range: 2 * f64,
+//                  instrument_range: 2 * f64 (NaN when the tag has none), texts_len: u16, texts (see TagDef::texts_to_raw)
+// FRAME_VALUES     PLC -> consumer, one per publish. count: u32, then per tag idx: u32, raw: u64 (see `TagValue::to_raw`),
+//                  ts_ms: u64, quality: u32 (see `TagSample`)
+// FRAME_EVENT      PLC -> consumer, a RingItem
+// FRAME_COMMAND    consumer -> PLC, a RingItem
+// FRAME_HEARTBEAT  both ways, counter: u32 (see heartbeat.rs)
+// FRAME_BLOB       PLC -> consumer, kind: u16 then the payload (see blob.rs)
+// FRAME_IO         PLC -> consumer, one per sync cycle. IoChannel * n (see io_mirror.rs)
+// FRAME_BUS_DIAG   PLC -> consumer, a BusDiagnostics (see bus_diag.rs)
+// FRAME_PLC_INFO   PLC -> consumer, a PlcInfo (see plc_info.rs), also sent on connect once the PLC wrote it
+// FRAME_IMAGE      PLC -> consumer, one per sync cycle once the bus is in OP. A ProcessImage (see process_image.rs)
+//
+// Everything is little endian. A consumer that can't keep up (socket buffer full for WRITE_TIMEOUT) gets dropped
+// and has to reconnect, the PLC never waits on it for longer than that. Connections from users that `Access`
+// doesn't allow are closed before they see anything. Commands are numbered here as they come in like the ring numbers
+// them, and an ITEM_AUDIT is about the latest command from the same consumer, naming its login if it isn't one of the
+// PLC's own services (see users.rs).
+use crate::access::{Access, Peer};
+use crate::blob::{Blob, MAX_BLOB_LEN};
+use crate::bus_diag::BusDiagnostics;
+use crate::io_mirror::{IoChannel, MAX_IO_CHANNELS};
+use crate::plc_info::PlcInfo;
+use crate::process_image::ProcessImage;
+use crate::region::{range_from_raw, range_to_raw};
+use crate::ring::{RingItem, ITEM_AUDIT, RING_CAPACITY};
+use crate::tags::*;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use std::{fs, mem, thread};
+
+const FRAME_DIRECTORY: u8 = 1;
+const FRAME_VALUES: u8 = 2;
+const FRAME_EVENT: u8 = 3;
+const FRAME_COMMAND: u8 = 4;
+const FRAME_HEARTBEAT: u8 = 5;
+const FRAME_BLOB: u8 = 6;
+const FRAME_IO: u8 = 7;
+const FRAME_BUS_DIAG: u8 = 8;
+const FRAME_PLC_INFO: u8 = 9;
+const FRAME_IMAGE: u8 = 10;
+
+const MAX_FRAME_LEN: usize = 1 << 20; // anything bigger is a broken peer, not a real frame
+const WRITE_TIMEOUT: Duration = Duration::from_millis(50);
+const ITEM_LEN: usize = mem::size_of::<RingItem>();
+const VALUE_ENTRY_LEN: usize = 4 + 8 + 8 + 4;
+
+fn frame(kind: u8, payload: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(5 + payload.len());
+    buf.extend_from_slice(&(payload.len() as u32 + 1).to_le_bytes());
+    buf.push(kind);
+    buf.extend_from_slice(payload);
+    buf
+}
+
+fn directory_frame(tags: &[TagDef]) -> Vec<u8> {
+    let mut payload = (tags.len() as u32).to_le_bytes().to_vec();
+    for tag in tags {
+        payload.push(tag.ty as u8);
+        payload.push(tag.flags);
+        payload.extend_from_slice(&(tag.name.len() as u16).to_le_bytes());
+        payload.extend_from_slice(tag.name.as_bytes());
+        payload.extend_from_slice(&(tag.folder.len() as u16).to_le_bytes());
+        payload.extend_from_slice(tag.folder.as_bytes());
+        payload.extend_from_slice(&tag.severity.to_le_bytes());
+        payload.extend_from_slice(&(tag.unit.len() as u16).to_le_bytes());
+        payload.extend_from_slice(tag.unit.as_bytes());
+        for limit in range_to_raw(tag.eu_range).into_iter().chain(range_to_raw(tag.instrument_range)) {
+            payload.extend_from_slice(&limit.to_le_bytes());
+        }
+        let texts = tag.texts_to_raw();
+        payload.extend_from_slice(&(texts.len() as u16).to_le_bytes());
+        payload.extend_from_slice(texts.as_bytes());
+    }
+    frame(FRAME_DIRECTORY, &payload)
+}
+
+fn values_frame(samples: &[(usize, TagSample)]) -> Vec<u8> {
+    let mut payload = (samples.len() as u32).to_le_bytes().to_vec();
+    for (idx, sample) in samples {
+        payload.extend_from_slice(&(*idx as u32).to_le_bytes());
+        payload.extend_from_slice(&sample.value.to_raw().to_le_bytes());
+        payload.extend_from_slice(&sample.ts_ms.to_le_bytes());
+        payload.extend_from_slice(&(sample.quality as u32).to_le_bytes());
+    }
+    frame(FRAME_VALUES, &payload)
+}
+
+/// Blocking read of one whole frame
+fn read_frame(stream: &mut impl Read) -> io::Result<(u8, Vec<u8>)> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len == 0 || len > MAX_FRAME_LEN {
+        return Err(io::Error::new(ErrorKind::InvalidData, format!("Bad frame length {}", len)));
+    }
+    let mut buf = vec![0u8; len];
+    stream.read_exact(&mut buf)?;
+    let payload = buf.split_off(1);
+    Ok((buf[0], payload))
+}
+
+/// Pops the first complete frame off `buf`, if there is one
+fn take_frame(buf: &mut Vec<u8>) -> io::Result<Option<(u8, Vec<u8>)>> {
+    if buf.len() < 4 {
+        return Ok(None);
+    }
+    let len = u32::from_le_bytes(buf[..4].try_into().unwrap()) as usize;
+    if len == 0 || len > MAX_FRAME_LEN {
+        return Err(io::Error::new(ErrorKind::InvalidData, format!("Bad frame length {}", len)));
+    }
+    if buf.len() < 4 + len {
+        return Ok(None);
+    }
+    let rest = buf.split_off(4 + len);
+    let frame = mem::replace(buf, rest);
+    Ok(Some((frame[4], frame[5..].to_vec())))
+}
+
+fn parse_heartbeat(payload: &[u8]) -> io::Result<u32> {
+    payload.try_into()
+        .map(u32::from_le_bytes)
+        .map_err(|_| io::Error::new(ErrorKind::InvalidData, format!("Heartbeat frame is {} bytes, expected 4", payload.len())))
+}
+
+fn parse_blob(payload: &[u8]) -> io::Result<Blob> {
+    if payload.len() < 2 || payload.len() - 2 > MAX_BLOB_LEN {
+        return Err(io::Error::new(ErrorKind::InvalidData, format!("Bad blob frame of {} bytes", payload.len())));
+    }
+    Ok(Blob { kind: u16::from_le_bytes([payload[0], payload[1]]), data: payload[2..].to_vec() })
+}
+
+fn parse_io(payload: &[u8]) -> io::Result<Vec<IoChannel>> {
+    let len = mem::size_of::<IoChannel>();
+    if !payload.len().is_multiple_of(len) || payload.len() / len > MAX_IO_CHANNELS {
+        return Err(io::Error::new(ErrorKind::InvalidData, format!("Bad I/O frame of {} bytes", payload.len())));
+    }
+    Ok(payload.chunks_exact(len).map(bytemuck::pod_read_unaligned).collect())
+}
+
+fn parse_bus_diag(payload: &[u8]) -> io::Result<BusDiagnostics> {
+    if payload.len() != mem::size_of::<BusDiagnostics>() {
+        return Err(io::Error::new(ErrorKind::InvalidData, format!("Bad bus diagnostics frame of {} bytes", payload.len())));
+    }
+    Ok(bytemuck::pod_read_unaligned(payload))
+}
+
+fn parse_plc_info(payload: &[u8]) -> io::Result<PlcInfo> {
+    if payload.len() != mem::size_of::<PlcInfo>() {
+        return Err(io::Error::new(ErrorKind::InvalidData, format!("Bad PLC info frame of {} bytes", payload.len())));
+    }
+    Ok(bytemuck::pod_read_unaligned(payload))
+}
+
+fn parse_process_image(payload: &[u8]) -> io::Result<ProcessImage> {
+    if payload.len() != mem::size_of::<ProcessImage>() {
+        return Err(io::Error::new(ErrorKind::InvalidData, format!("Bad process image frame of {} bytes", payload.len())));
+    }
+    Ok(bytemuck::pod_read_unaligned(payload))
+}
+
+fn parse_item(payload: &[u8]) -> io::Result<RingItem> {
+    if payload.len() != ITEM_LEN {
+        return Err(io::Error::new(ErrorKind::InvalidData, format!("Item frame is {} bytes, expected {}", payload.len(), ITEM_LEN)));
+    }
+    Ok(bytemuck::pod_read_unaligned(payload))
+}
+
+fn parse_directory(payload: &[u8]) -> io::Result<Vec<TagDef>> {
+    let bad = |what: &str| io::Error::new(ErrorKind::InvalidData, format!("Malformed directory frame: {}", what));
+    let count = u32::from_le_bytes(payload.get(..4).ok_or_else(|| bad("no tag count"))?.try_into().unwrap());
+    let mut at = 4;
+    let mut tags = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let head = payload.get(at..at + 4).ok_or_else(|| bad("truncated tag"))?;
+        let ty = TagType::from_u8(head[0]).map_err(|e| bad(&e))?;
+        let flags = head[1];
+        let name_len = u16::from_le_bytes([head[2], head[3]]) as usize;
+        at += 4;
+        let name = payload.get(at..at + name_len).ok_or_else(|| bad("truncated name"))?;
+        let name = std::str::from_utf8(name).map_err(|_| bad("non utf-8 name"))?;
+        at += name_len;
+        let folder_len = payload.get(at..at + 2).ok_or_else(|| bad("truncated tag"))?;
+        let folder_len = u16::from_le_bytes([folder_len[0], folder_len[1]]) as usize;
+        at += 2;
+        let folder = payload.get(at..at + folder_len).ok_or_else(|| bad("truncated folder"))?;
+        let folder = std::str::from_utf8(folder).map_err(|_| bad("non utf-8 folder"))?;
+        at += folder_len;
+        let severity = payload.get(at..at + 2).ok_or_else(|| bad("truncated tag"))?;
+        let severity = u16::from_le_bytes([severity[0], severity[1]]);
+        at += 2;
+        let unit_len = payload.get(at..at + 2).ok_or_else(|| bad("truncated tag"))?;
+        let unit_len = u16::from_le_bytes([unit_len[0], unit_len[1]]) as usize;
+        at += 2;
+        let unit = payload.get(at..at + unit_len).ok_or_else(|| bad("truncated unit"))?;
+        let unit = std::str::from_utf8(unit).map_err(|_| bad("non utf-8 unit"))?;
+        at += unit_len;
+        let limits = payload.get(at..at + 32).ok_or_else(|| bad("truncated ranges"))?;
+        let limit = |i: usize| f64::from_le_bytes(limits[8 * i..8 * i + 8].try_into().unwrap());
+        at += 32;
+        let texts_len = payload.get(at..at + 2).ok_or_else(|| bad("truncated tag"))?;
+        let texts_len = u16::from_le_bytes([texts_len[0], texts_len[1]]) as usize;
+        at += 2;
+        let texts = payload.get(at..at + texts_len).ok_or_else(|| bad("truncated texts"))?;
+        let texts = std::str::from_utf8(texts).map_err(|_| bad("non utf-8 texts"))?;
+        at += texts_len;
+        tags.push(TagDef {
+            severity,
+            unit: unit.to_string(),
+            eu_range: range_from_raw([limit(0), limit(1)]),
+            instrument_range: range_from_raw([limit(2), limit(3)]),
+            texts: TagDef::texts_from_raw(texts),
+            ..TagDef::new(name, ty, flags).in_folder(folder)
+        });
+    }
+    Ok(tags)
+}
+
+fn parse_values(payload: &[u8], tags: &[TagDef]) -> io::Result<Vec<(usize, TagSample)>> {
+    let bad = |what: &str| io::Error::new(ErrorKind::InvalidData, format!("Malformed values frame: {}", what));
+    let count = u32::from_le_bytes(payload.get(..4).ok_or_else(|| bad("no count"))?.try_into().unwrap()) as usize;
+    if payload.len() != 4 + count * VALUE_ENTRY_LEN {
+        return Err(bad("length doesn't match count"));
+    }
+    payload[4..].chunks_exact(VALUE_ENTRY_LEN).map(|chunk| {
+        let idx = u32::from_le_bytes(chunk[..4].try_into().unwrap()) as usize;
+        let raw = u64::from_le_bytes(chunk[4..12].try_into().unwrap());
+        let ts_ms = u64::from_le_bytes(chunk[12..20].try_into().unwrap());
+        let quality = Quality::from_u32(u32::from_le_bytes(chunk[20..].try_into().unwrap()));
+        let def = tags.get(idx).ok_or_else(|| bad("tag index out of bounds"))?;
+        Ok((idx, TagSample { value: TagValue::from_raw(def.ty, raw), ts_ms, quality }))
+    }).collect()
+}
+
+struct Client {
+    stream: UnixStream,
+    rx: Vec<u8>, // partial command frames
+    login: Option<String>, // None for the PLC's own services, see Peer
+    last_seq: u32, // of its latest command, what its next ITEM_AUDIT is about
+}
+
+/// PLC side. Not thread safe, poll it from the thread that publishes.
+pub struct UdsServer {
+    listener: UnixListener,
+    path: String,
+    access: Access,
+    tags: Vec<TagDef>,
+    by_name: HashMap<String, usize>,
+    values: Vec<TagSample>,
+    clients: Vec<Client>,
+    commands: VecDeque<RingItem>,
+    next_seq: u32, // commands are numbered here like the ring numbers them, so audits find theirs
+    heartbeat: u32,
+    consumer_heartbeat: u32, // latest from any consumer
+    plc_info: Option<Vec<u8>>, // FRAME_PLC_INFO, for consumers that connect later
+}
+
+impl UdsServer {
+    /// Bind `path`, replacing a socket left behind by a previous run
+    pub fn bind(path: &str, defs: &[TagDef], access: Access) -> io::Result<Self> {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        let listener = UnixListener::bind(path)?;
+        access.apply_to_path(path)?; // connecting needs write permission on the socket file
+        listener.set_nonblocking(true)?;
+
+        Ok(Self {
+            listener,
+            path: path.to_string(),
+            access,
+            tags: defs.to_vec(),
+            by_name: defs.iter().enumerate().map(|(idx, def)| (def.name.clone(), idx)).collect(),
+            values: defs.iter().map(|def| TagSample::initial(def.ty)).collect(),
+            clients: Vec::new(),
+            commands: VecDeque::new(),
+            next_seq: 1,
+            heartbeat: 0,
+            consumer_heartbeat: 0,
+            plc_info: None,
+        })
+    }
+
+    pub fn tags(&self) -> &[TagDef] {
+        &self.tags
+    }
+
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.by_name.get(name).copied()
+    }
+
+    /// Same contract as `TagTable::write_many`, the update goes out to every consumer as one frame
+    pub fn write_many(&mut self, values: &[(usize, TagValue)]) -> Result<(), String> {
+        let samples: Vec<(usize, TagSample)> = values.iter().map(|&(idx, value)| (idx, TagSample::good(value))).collect();
+        self.write_samples(&samples)
+    }
+
+    pub fn write_samples(&mut self, samples: &[(usize, TagSample)]) -> Result<(), String> {
+        for (idx, sample) in samples {
+            let def = self.tags.get(*idx).ok_or_else(|| format!("Tag index {} out of bounds", idx))?;
+            if def.ty != sample.value.ty() {
+                return Err(format!("Tag '{}' is {:?}, got {:?}", def.name, def.ty, sample.value.ty()));
+            }
+        }
+        for (idx, sample) in samples {
+            self.values[*idx] = *sample;
+        }
+
+        self.accept();
+        self.broadcast(&values_frame(samples));
+        Ok(())
+    }
+
+    pub fn write(&mut self, idx: usize, value: TagValue) -> Result<(), String> {
+        self.write_many(&[(idx, value)])
+    }
+
+    pub fn pop_command(&mut self) -> Option<RingItem> {
+        if self.commands.is_empty() {
+            self.accept();
+            self.receive();
+        }
+        self.commands.pop_front()
+    }
+
+    /// Events are sent straight away, consumers that aren't connected miss them
+    pub fn push_event(&mut self, item: RingItem) {
+        self.broadcast(&frame(FRAME_EVENT, bytemuck::bytes_of(&item)));
+    }
+
+    /// Same as TagTable::write_io, late joiners get the next cycle's mirror
+    pub fn write_io(&mut self, channels: &[IoChannel]) {
+        let count = channels.len().min(MAX_IO_CHANNELS);
+        self.broadcast(&frame(FRAME_IO, bytemuck::cast_slice(&channels[..count])));
+    }
+
+    pub fn write_bus_diag(&mut self, diag: &BusDiagnostics) {
+        self.broadcast(&frame(FRAME_BUS_DIAG, bytemuck::bytes_of(diag)));
+    }
+
+    pub fn write_plc_info(&mut self, info: &PlcInfo) {
+        let info = frame(FRAME_PLC_INFO, bytemuck::bytes_of(info));
+        self.broadcast(&info);
+        self.plc_info = Some(info);
+    }
+
+    pub fn write_process_image(&mut self, image: &ProcessImage) {
+        self.broadcast(&frame(FRAME_IMAGE, bytemuck::bytes_of(image)));
+    }
+
+    /// Same limits as TagTable::push_blob
+    pub fn push_blob(&mut self, kind: u16, payload: &[u8]) -> Result<(), String> {
+        if payload.len() > MAX_BLOB_LEN {
+            return Err(format!("Payload of {} bytes exceeds the {} byte limit", payload.len(), MAX_BLOB_LEN));
+        }
+        let mut body = kind.to_le_bytes().to_vec();
+        body.extend_from_slice(payload);
+        self.broadcast(&frame(FRAME_BLOB, &body));
+        Ok(())
+    }
+
+    pub fn heartbeat(&mut self) {
+        self.heartbeat = self.heartbeat.wrapping_add(1);
+        self.accept();
+        self.broadcast(&frame(FRAME_HEARTBEAT, &self.heartbeat.to_le_bytes()));
+    }
+
+    pub fn consumer_heartbeat(&mut self) -> u32 {
+        self.accept();
+        self.receive();
+        self.consumer_heartbeat
+    }
+
+    fn accept(&mut self) {
+        // until WouldBlock, nobody else is waiting
+        while let Ok((mut stream, _)) = self.listener.accept() {
+            let login = match self.access.peer(&stream) {
+                Ok(Peer::Service) => None,
+                Ok(Peer::User(login)) => Some(login),
+                Ok(Peer::Denied) => {
+                    log::warn!("Rejected a consumer from a user outside the configured group");
+                    continue;
+                }
+                Err(e) => {
+                    log::warn!("Rejected a consumer, couldn't get its credentials: {}", e);
+                    continue;
+                }
+            };
+            // new consumers get the directory and a full snapshot before anything else
+            let snapshot: Vec<(usize, TagSample)> = self.values.iter().copied().enumerate().collect();
+            let sent = stream.set_write_timeout(Some(WRITE_TIMEOUT))
+                .and_then(|_| stream.write_all(&directory_frame(&self.tags)))
+                .and_then(|_| stream.write_all(&values_frame(&snapshot)))
+                .and_then(|_| stream.write_all(self.plc_info.as_deref().unwrap_or_default()));
+            if sent.is_ok() {
+                self.clients.push(Client { stream, rx: Vec::new(), login, last_seq: 0 });
+            }
+        }
+    }
+
+    fn broadcast(&mut self, frame: &[u8]) {
+        self.clients.retain_mut(|client| client.stream.write_all(frame).is_ok());
+    }
+
+    // drain whatever commands the consumers have sent without blocking
+    fn receive(&mut self) {
+        let commands = &mut self.commands;
+        let next_seq = &mut self.next_seq;
+        let consumer_heartbeat = &mut self.consumer_heartbeat;
+        self.clients.retain_mut(|client| {
+            if client.stream.set_nonblocking(true).is_err() {
+                return false;
+            }
+            let mut chunk = [0u8; 1024];
+            let alive = loop {
+                match client.stream.read(&mut chunk) {
+                    Ok(0) => break false, // consumer hung up
+                    Ok(n) => client.rx.extend_from_slice(&chunk[..n]),
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => break true,
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(_) => break false,
+                }
+            };
+            let alive = alive && loop {
+                match take_frame(&mut client.rx) {
+                    Ok(Some((FRAME_COMMAND, payload))) => match parse_item(&payload) {
+                        Ok(item) => commands.push_back(client.numbered(item, next_seq)),
+                        Err(_) => break false,
+                    },
+                    // consumers each count from their own start, so the server keeps its own count of beats received
+                    Ok(Some((FRAME_HEARTBEAT, payload))) => match parse_heartbeat(&payload) {
+                        Ok(_) => *consumer_heartbeat = consumer_heartbeat.wrapping_add(1),
+                        Err(_) => break false,
+                    },
+                    Ok(Some(_)) => break false, // consumers only send commands and heartbeats
+                    Ok(None) => break true,
+                    Err(_) => break false,
+                }
+            };
+            alive && client.stream.set_nonblocking(false).is_ok()
+        });
+    }
+}
+
+impl Client {
+    // `item` as the PLC takes it: a command with the next seq, an audit about this consumer's latest command and,
+    // from someone else than the PLC's own services, naming their login
+    fn numbered(&mut self, mut item: RingItem, next_seq: &mut u32) -> RingItem {
+        if item.kind == ITEM_AUDIT {
+            let (_, session, user) = item.audited();
+            return RingItem::audit(self.last_seq, session, self.login.as_deref().unwrap_or(&user));
+        }
+        item.seq = *next_seq;
+        self.last_seq = item.seq;
+        *next_seq = next_seq.wrapping_add(1).max(1); // 0 is for commands that didn't come through the ring or socket
+        item
+    }
+}
+
+impl Drop for UdsServer {
+    fn drop(&mut self) {
+        _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Consumer side. A background thread keeps the latest values and queues events, so reads never touch the socket.
+pub struct UdsClient {
+    tags: Vec<TagDef>,
+    by_name: HashMap<String, usize>,
+    values: Arc<RwLock<Vec<TagSample>>>,
+    events: Arc<Mutex<VecDeque<RingItem>>>,
+    blobs: Arc<Mutex<VecDeque<Blob>>>,
+    io: Arc<RwLock<Vec<IoChannel>>>,
+    bus_diag: Arc<RwLock<Option<BusDiagnostics>>>,
+    plc_info: Arc<RwLock<Option<PlcInfo>>>,
+    process_image: Arc<RwLock<Option<ProcessImage>>>,
+    connected: Arc<AtomicBool>,
+    plc_heartbeat: Arc<AtomicU32>,
+    heartbeat: AtomicU32,
+    writer: Mutex<UnixStream>,
+}
+
+impl UdsClient {
+    /// Blocks until the PLC's next poll accepts the connection and sends the directory
+    pub fn connect(path: &str) -> io::Result<Self> {
+        let mut stream = UnixStream::connect(path)?;
+
+        let tags = match read_frame(&mut stream)? {
+            (FRAME_DIRECTORY, payload) => parse_directory(&payload)?,
+            (kind, _) => return Err(io::Error::new(ErrorKind::InvalidData, format!("Expected directory frame, got kind {}", kind))),
+        };
+
+        let values = Arc::new(RwLock::new(tags.iter().map(|def| TagSample::initial(def.ty)).collect::<Vec<_>>()));
+        let events = Arc::new(Mutex::new(VecDeque::new()));
+        let blobs = Arc::new(Mutex::new(VecDeque::new()));
+        let io = Arc::new(RwLock::new(Vec::new()));
+        let bus_diag = Arc::new(RwLock::new(None));
+        let plc_info = Arc::new(RwLock::new(None));
+        let process_image = Arc::new(RwLock::new(None));
+        let connected = Arc::new(AtomicBool::new(true));
+        let plc_heartbeat = Arc::new(AtomicU32::new(0));
+
+        let mut reader = stream.try_clone()?;
+        let (rd_tags, rd_values, rd_events, rd_connected) = (tags.clone(), values.clone(), events.clone(), connected.clone());
+        let rd_heartbeat = plc_heartbeat.clone();
+        let rd_blobs = blobs.clone();
+        let rd_io = io.clone();
+        let rd_bus_diag = bus_diag.clone();
+        let rd_plc_info = plc_info.clone();
+        let rd_process_image = process_image.clone();
+        thread::Builder::new()
+            .name("UdsClientReader".to_owned())
+            .spawn(move || {
+                loop {
+                    let applied = read_frame(&mut reader).and_then(|(kind, payload)| match kind {
+                        FRAME_VALUES => {
+                            let update = parse_values(&payload, &rd_tags)?;
+                            let mut values = rd_values.write().unwrap();
+                            for (idx, sample) in update {
+                                values[idx] = sample;
+                            }
+                            Ok(())
+                        }
+                        FRAME_EVENT => {
+                            let item = parse_item(&payload)?;
+                            let mut events = rd_events.lock().unwrap();
+                            if events.len() < RING_CAPACITY as usize { // same bound as the shm event ring
+                                events.push_back(item);
+                            }
+                            Ok(())
+                        }
+                        FRAME_IO => {
+                            *rd_io.write().unwrap() = parse_io(&payload)?;
+                            Ok(())
+                        }
+                        FRAME_BUS_DIAG => {
+                            *rd_bus_diag.write().unwrap() = Some(parse_bus_diag(&payload)?);
+                            Ok(())
+                        }
+                        FRAME_PLC_INFO => {
+                            *rd_plc_info.write().unwrap() = Some(parse_plc_info(&payload)?);
+                            Ok(())
+                        }
+                        FRAME_IMAGE => {
+                            *rd_process_image.write().unwrap() = Some(parse_process_image(&payload)?);
+                            Ok(())
+                        }
+                        FRAME_BLOB => {
+                            let blob = parse_blob(&payload)?;
+                            let mut blobs = rd_blobs.lock().unwrap();
+                            if blobs.len() < RING_CAPACITY as usize {
+                                blobs.push_back(blob);
+                            }
+                            Ok(())
+                        }
+                        FRAME_HEARTBEAT => {
+                            rd_heartbeat.store(parse_heartbeat(&payload)?, Ordering::Release);
+                            Ok(())
+                        }
+                        other => Err(io::Error::new(ErrorKind::InvalidData, format!("Unexpected frame kind {}", other))),
+                    });
+                    if applied.is_err() {
+                        rd_connected.store(false, Ordering::Release);
+                        break;
+                    }
+                }
+            })?;
+
+        let by_name = tags.iter().enumerate().map(|(idx, def)| (def.name.clone(), idx)).collect();
+        Ok(Self { tags, by_name, values, events, blobs, io, bus_diag, plc_info, process_image, connected, plc_heartbeat, heartbeat: AtomicU32::new(0), writer: Mutex::new(stream) })
+    }
+
+    pub fn tags(&self) -> &[TagDef] {
+        &self.tags
+    }
+
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.by_name.get(name).copied()
+    }
+
+    /// Last value received, stale once `is_connected()` turns false
+    pub fn read(&self, idx: usize) -> TagValue {
+        self.read_sample(idx).value
+    }
+
+    pub fn read_sample(&self, idx: usize) -> TagSample {
+        self.values.read().unwrap()[idx]
+    }
+
+    pub fn read_all(&self) -> Vec<TagValue> {
+        self.values.read().unwrap().iter().map(|sample| sample.value).collect()
+    }
+
+    pub fn read_all_samples(&self) -> Vec<TagSample> {
+        self.values.read().unwrap().clone()
+    }
+
+    pub fn push_command(&self, item: RingItem) -> Result<(), RingItem> {
+        let mut writer = self.writer.lock().unwrap();
+        writer.write_all(&frame(FRAME_COMMAND, bytemuck::bytes_of(&item))).map_err(|_| item)
+    }
+
+    pub fn pop_event(&self) -> Option<RingItem> {
+        self.events.lock().unwrap().pop_front()
+    }
+
+    pub fn read_io(&self) -> Vec<IoChannel> {
+        self.io.read().unwrap().clone()
+    }
+
+    pub fn read_bus_diag(&self) -> Option<BusDiagnostics> {
+        *self.bus_diag.read().unwrap()
+    }
+
+    pub fn read_plc_info(&self) -> Option<PlcInfo> {
+        *self.plc_info.read().unwrap()
+    }
+
+    pub fn read_process_image(&self) -> Option<ProcessImage> {
+        *self.process_image.read().unwrap()
+    }
+
+    pub fn pop_blob(&self) -> Option<Blob> {
+        self.blobs.lock().unwrap().pop_front()
+    }
+
+    pub fn heartbeat(&self) {
+        let counter = self.heartbeat.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
+        let mut writer = self.writer.lock().unwrap();
+        _ = writer.write_all(&frame(FRAME_HEARTBEAT, &counter.to_le_bytes())); // a dead socket shows up in is_connected()
+    }
+
+    pub fn plc_heartbeat(&self) -> u32 {
+        self.plc_heartbeat.load(Ordering::Acquire)
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Acquire)
+    }
+}
@@ -0,0 +1,151 @@
+// `[users]` section of gipop.toml, who may do what. Read by every interface that takes commands (OPC UA server, gRPC
+// tag service, gateway, command line tools) so a user has the same rights whichever way they come in.
+//
+// [users.alice]
+// role = "operator"
+// api_key = "5f0c..."   # gRPC (x-api-key metadata) and HTTP (X-Api-Key header)
+//
+// [users.commissioning]
+// role = "engineer"
+//
+// How each interface knows who's asking:
+// OPC UA   the user token id in server.conf (username/password or certificate) is the user name
+// gRPC     the api_key sent with the call
+// HTTP     the api_key in the X-Api-Key header, or the sub of a JWT in Authorization: Bearer
+// MQTT     the `user` configured for the broker connection
+// CLI      the unix login name running the tool
+//
+// Whoever isn't listed, or doesn't identify at all, is a viewer.
+//
+// The PLC checks again: every command from the command ring or socket waits for the ITEM_AUDIT naming who sent it,
+// and only goes through if that user's role allows it (plc/src/audit.rs). One that isn't audited within a second is
+// anonymous and rejected. An audit carries ITEM_DATA_LEN (16) bytes of the name, keep the names of users that send
+// commands shorter than that. On the socket the PLC knows who's on the other end: a consumer running as another user
+// than the PLC's own (or root) is named by its unix login whatever its audit says, only the PLC's own services name
+// the users they act for. The shm region has no such proof, whoever [ipc] group lets open it names themselves.
+use crate::ring::{
+    ITEM_ALARM_ACK, ITEM_FORCE, ITEM_LOG_LEVEL, ITEM_RELOAD_CONFIG, ITEM_RESET_TOTALS, ITEM_RESTORE_TOTAL, ITEM_RUN_MODE, ITEM_TAG_WRITE,
+    ITEM_UNFORCE,
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::{fs, path::Path};
+
+/// What a user may do, each role can do everything the ones before it can
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    Viewer,   // read, browse, subscribe, history
+    Operator, // write command tags, acknowledge alarms, reset totals
+    Engineer, // force I/O, start/stop the logic, change log levels, reload the config, restore a backup
+}
+
+/// Everything an interface lets users do, with the role it takes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Read,
+    WriteTag,
+    AckAlarm,
+    ResetTotals,
+    ForceIo,
+    RunMode,
+    LogLevel,
+    ReloadConfig,
+    RestoreState,
+}
+
+impl Action {
+    pub fn role(&self) -> Role {
+        match self {
+            Action::Read => Role::Viewer,
+            Action::WriteTag | Action::AckAlarm | Action::ResetTotals => Role::Operator,
+            Action::ForceIo | Action::RunMode | Action::LogLevel | Action::ReloadConfig | Action::RestoreState => Role::Engineer,
+        }
+    }
+
+    /// What a command of `kind` does, None for kinds that aren't commands users send
+    pub fn of_command(kind: u16) -> Option<Action> {
+        match kind {
+            ITEM_TAG_WRITE => Some(Action::WriteTag),
+            ITEM_ALARM_ACK => Some(Action::AckAlarm),
+            ITEM_RESET_TOTALS => Some(Action::ResetTotals),
+            ITEM_FORCE | ITEM_UNFORCE => Some(Action::ForceIo),
+            ITEM_RUN_MODE => Some(Action::RunMode),
+            ITEM_LOG_LEVEL => Some(Action::LogLevel),
+            ITEM_RELOAD_CONFIG => Some(Action::ReloadConfig),
+            ITEM_RESTORE_TOTAL => Some(Action::RestoreState),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct User {
+    #[serde(default)]
+    pub role: Role,
+    #[serde(default)]
+    pub api_key: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Users {
+    users: HashMap<String, User>,
+}
+
+impl Users {
+    /// Reads the `[users]` section of `path`. A missing file or section means everyone is a viewer.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let users: HashMap<String, User> = crate::config::load_section(path, "users")?;
+        let mut keys = HashMap::new();
+        for (name, user) in &users {
+            if let Some(key) = &user.api_key && keys.insert(key, name).is_some() {
+                return Err(format!("Users '{}' and '{}' share an api_key", name, keys[key]));
+            }
+        }
+        Ok(Self { users })
+    }
+
+    /// None for anonymous
+    pub fn role_of(&self, user: Option<&str>) -> Role {
+        user.and_then(|name| self.users.get(name)).map(|user| user.role).unwrap_or_default()
+    }
+
+    /// Who `api_key` belongs to
+    pub fn by_api_key(&self, api_key: &str) -> Option<&str> {
+        self.users.iter().find(|(_, user)| user.api_key.as_deref() == Some(api_key)).map(|(name, _)| name.as_str())
+    }
+
+    /// Ok with the user's role if they may do `action`, the error says why not
+    pub fn authorize(&self, user: Option<&str>, action: Action) -> Result<Role, String> {
+        let role = self.role_of(user);
+        if role < action.role() {
+            return Err(format!("{} ({:?}) may not {:?}, needs {:?}", user.unwrap_or("anonymous"), role, action, action.role()));
+        }
+        Ok(role)
+    }
+
+    /// For command line tools: the login name of the user running them. From the real uid rather than $USER,
+    /// which anyone can set.
+    #[cfg(unix)]
+    pub fn local_user() -> Option<String> {
+        Self::login_of(unsafe { libc::getuid() })
+    }
+
+    /// The login name of `uid`, None if it has none
+    #[cfg(unix)]
+    pub fn login_of(uid: u32) -> Option<String> {
+        let uid = uid.to_string();
+        // name:password:uid:gid:...
+        fs::read_to_string("/etc/passwd").ok()?
+            .lines()
+            .map(|line| line.split(':').collect::<Vec<_>>())
+            .find(|fields| fields.get(2) == Some(&uid.as_str()))
+            .and_then(|fields| fields.first().map(|name| name.to_string()))
+    }
+
+    #[cfg(not(unix))]
+    pub fn local_user() -> Option<String> {
+        std::env::var("USERNAME").ok().filter(|name| !name.is_empty())
+    }
+}
@@ -0,0 +1,122 @@
+// One supervised process: started from its [supervisor.*] command, watched for exiting, restarted after a backoff and
+// stopped with SIGINT (what the PLC, the OPC UA server and the gateway all shut down cleanly on), SIGKILL if that
+// doesn't do it.
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, ExitStatus};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::{ChildConfig, SupervisorConfig};
+
+const STOP_POLL: Duration = Duration::from_millis(50);
+
+pub struct Supervised {
+    pub name: &'static str,
+    config: ChildConfig,
+    process: Option<Child>,
+    started_at: Option<Instant>,
+    crashes: u32,             // in a row, reset once the child stayed up stable_ms
+    restart_at: Option<Instant>, // after a crash, None: may start right away
+}
+
+impl Supervised {
+    pub fn new(name: &'static str, config: ChildConfig) -> Self {
+        Self { name, config, process: None, started_at: None, crashes: 0, restart_at: None }
+    }
+
+    /// Not configured, never started
+    pub fn disabled(&self) -> bool {
+        self.config.command.is_empty()
+    }
+
+    pub fn running(&self) -> bool {
+        self.process.is_some()
+    }
+
+    /// Not running, configured and past its backoff
+    pub fn due(&self) -> bool {
+        !self.disabled() && !self.running() && self.restart_at.is_none_or(|at| Instant::now() >= at)
+    }
+
+    /// Starts the child in its own process group, so a Ctrl-C on gipopd's terminal only reaches gipopd, which stops
+    /// the children in order. A child that can't be started counts as crashed.
+    pub fn start(&mut self, cfg: &SupervisorConfig) {
+        let (program, args) = self.config.command.split_first().expect("start() on a disabled child");
+        let mut command = Command::new(program);
+        command.args(args).process_group(0);
+        if !self.config.dir.is_empty() {
+            command.current_dir(&self.config.dir);
+        }
+        match command.spawn() {
+            Ok(process) => {
+                log::info!(child = self.name, pid = process.id(); "Started {} ({})", self.name, self.config.command.join(" "));
+                self.process = Some(process);
+                self.started_at = Some(Instant::now());
+                self.restart_at = None;
+            }
+            Err(e) => {
+                let program = program.clone();
+                self.crashed(cfg);
+                log::error!(child = self.name; "Can't start {} ({}) in '{}': {}, retrying in {:?}", self.name, program, self.config.dir, e, self.restart_in());
+            }
+        }
+    }
+
+    /// The exit status once the child is gone, it then counts as crashed and waits out its backoff
+    pub fn exited(&mut self, cfg: &SupervisorConfig) -> Option<ExitStatus> {
+        let process = self.process.as_mut()?;
+        let status = match process.try_wait() {
+            Ok(Some(status)) => status,
+            Ok(None) => return None,
+            Err(e) => {
+                log::error!(child = self.name; "Can't tell whether {} is still running: {}", self.name, e);
+                return None;
+            }
+        };
+        self.process = None;
+        self.crashed(cfg);
+        log::warn!(child = self.name; "{} exited ({}), restarting in {:?}", self.name, status, self.restart_in());
+        Some(status)
+    }
+
+    /// Restarts a child that's up but not doing its job, like one that crashed
+    pub fn restart(&mut self, cfg: &SupervisorConfig) {
+        self.stop(cfg);
+        self.crashed(cfg);
+    }
+
+    /// SIGINT, SIGKILL after stop_timeout_ms. Not a crash, the child may start again right away
+    pub fn stop(&mut self, cfg: &SupervisorConfig) {
+        let Some(mut process) = self.process.take() else { return };
+        log::info!(child = self.name; "Stopping {}", self.name);
+        // SAFETY: kill() has no memory safety requirements, the pid is our own child's, which hasn't been waited for
+        unsafe { libc::kill(process.id() as libc::pid_t, libc::SIGINT) };
+
+        let deadline = Instant::now() + Duration::from_millis(cfg.stop_timeout_ms);
+        while Instant::now() < deadline {
+            match process.try_wait() {
+                Ok(Some(status)) => {
+                    log::info!(child = self.name; "{} stopped ({})", self.name, status);
+                    return;
+                }
+                Ok(None) => thread::sleep(STOP_POLL),
+                Err(_) => break,
+            }
+        }
+        log::warn!(child = self.name; "{} didn't stop within {} ms, killing it", self.name, cfg.stop_timeout_ms);
+        _ = process.kill();
+        _ = process.wait();
+    }
+
+    fn crashed(&mut self, cfg: &SupervisorConfig) {
+        if self.started_at.take().is_some_and(|at| at.elapsed() >= Duration::from_millis(cfg.stable_ms)) {
+            self.crashes = 0;
+        }
+        self.crashes += 1;
+        self.restart_at = Some(Instant::now() + cfg.backoff(self.crashes));
+    }
+
+    fn restart_in(&self) -> Duration {
+        self.restart_at.map(|at| at.saturating_duration_since(Instant::now())).unwrap_or_default()
+    }
+}
@@ -0,0 +1,127 @@
+// gipopd's section of gipop.toml. `[ipc]` is shared with the PLC and read by gipop_shm::IpcConfig, gipopd watches the
+// PLC's heartbeat there to know when it's up.
+//
+// [supervisor]
+// ready_timeout_ms = 30000     # the PLC has to publish this soon after starting (bus in OP), or it's restarted
+// backoff_ms = 1000            # first restart delay, doubles with every crash in a row
+// max_backoff_ms = 60000
+// stable_ms = 60000            # a child up this long starts over at backoff_ms when it crashes next
+// stop_timeout_ms = 10000      # SIGINT on stop, SIGKILL if the child is still there after this long
+//
+// [supervisor.plc]
+// command = ["./target/release/gipop_plc", "eth0"]
+// dir = "."                    # working directory, every binary looks for gipop.toml relative to it
+//
+// [supervisor.opcua]
+// command = ["./target/release/opcua"]
+// dir = "opcua"
+//
+// [supervisor.gateway]         # an empty command isn't started, that goes for all three
+// command = ["../target/release/gipop_gateway"]
+// dir = "gateway"
+//
+// [supervisor.redundancy]      # active/standby pair, see redundancy.rs
+// enabled = true
+// listen = "0.0.0.0:47810"     # UDP, this controller's end of the sync link
+// peer = "10.10.0.2:47810"     # the other controller's end
+// preferred = true             # active when both start together, set it on one of the pair only
+// heartbeat_ms = 200
+// sync_ms = 1000               # the active's state to the standby
+// peer_timeout_ms = 1000       # the standby takes over after this long without hearing the active
+// takeover_timeout_ms = 20000  # the PLC has to publish this soon after a takeover (bus up again from INIT)
+use serde::Deserialize;
+use std::{path::Path, time::Duration};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SupervisorConfig {
+    pub ready_timeout_ms: u64,
+    pub backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    pub stable_ms: u64,
+    pub stop_timeout_ms: u64,
+    pub plc: ChildConfig,
+    pub opcua: ChildConfig,
+    pub gateway: ChildConfig,
+    pub redundancy: RedundancyConfig,
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        Self {
+            ready_timeout_ms: 30_000,
+            backoff_ms: 1000,
+            max_backoff_ms: 60_000,
+            stable_ms: 60_000,
+            stop_timeout_ms: 10_000,
+            plc: ChildConfig { command: vec!["./target/release/gipop_plc".to_string()], dir: ".".to_string() },
+            opcua: ChildConfig { command: vec!["./target/release/opcua".to_string()], dir: "opcua".to_string() },
+            gateway: ChildConfig { command: Vec::new(), dir: "gateway".to_string() },
+            redundancy: RedundancyConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ChildConfig {
+    pub command: Vec<String>, // program and arguments, empty: not started
+    pub dir: String,          // working directory, empty: gipopd's
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RedundancyConfig {
+    pub enabled: bool,
+    pub listen: String,
+    pub peer: String,
+    pub preferred: bool,
+    pub heartbeat_ms: u64,
+    pub sync_ms: u64,
+    pub peer_timeout_ms: u64,
+    pub takeover_timeout_ms: u64,
+}
+
+impl Default for RedundancyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: "0.0.0.0:47810".to_string(),
+            peer: String::new(),
+            preferred: false,
+            heartbeat_ms: 200,
+            sync_ms: 1000,
+            peer_timeout_ms: 1000,
+            takeover_timeout_ms: 20_000,
+        }
+    }
+}
+
+impl SupervisorConfig {
+    /// Reads the `[supervisor]` section of `path` and checks it's something gipopd can run
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let cfg: Self = gipop_shm::config::load_section(path, "supervisor")?;
+        if cfg.plc.command.is_empty() {
+            return Err(format!("[supervisor.plc] in {} has no command, there's nothing to supervise without the PLC", path.display()));
+        }
+        if cfg.backoff_ms == 0 || cfg.max_backoff_ms < cfg.backoff_ms {
+            return Err(format!("[supervisor] in {}: backoff_ms has to be above 0 and at most max_backoff_ms", path.display()));
+        }
+        let redundancy = &cfg.redundancy;
+        if redundancy.enabled && redundancy.peer.is_empty() {
+            return Err(format!("[supervisor.redundancy] in {} has no peer", path.display()));
+        }
+        // a couple of heartbeats lost on the way mustn't look like a dead active
+        if redundancy.enabled && (redundancy.heartbeat_ms == 0 || redundancy.peer_timeout_ms < 3 * redundancy.heartbeat_ms) {
+            return Err(format!("[supervisor.redundancy] in {}: heartbeat_ms has to be above 0 and peer_timeout_ms at least three of them", path.display()));
+        }
+        Ok(cfg)
+    }
+
+    /// How long to wait before restarting a child that crashed `crashes` times in a row
+    pub fn backoff(&self, crashes: u32) -> Duration {
+        let factor = 1u64.checked_shl(crashes.saturating_sub(1)).unwrap_or(u64::MAX);
+        Duration::from_millis(self.backoff_ms.saturating_mul(factor).min(self.max_backoff_ms))
+    }
+}
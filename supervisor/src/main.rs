@@ -0,0 +1,164 @@
+// gipopd [<config>], runs the PLC and what runs against it, so they come up in the order they need to and come back
+// when they die, see config.rs for [supervisor] in gipop.toml. Unix only, like the PLC.
+//
+// The OPC UA server and the gateway attach to the PLC's shm region (or socket) when they start and give up when it
+// isn't there within [ipc] connect_timeout_ms, so the PLC starts first and they only start once it publishes: its heartbeat moving in [ipc]'s
+// region, within ready_timeout_ms of the start. A child that exits is started again after a backoff that doubles with
+// every crash in a row (backoff_ms up to max_backoff_ms), one that stayed up stable_ms starts over at backoff_ms.
+// When the PLC exits the others are stopped with it and started again once it publishes again, a PLC that doesn't
+// publish in time is restarted like one that crashed.
+//
+// SIGINT or SIGTERM stops everything in reverse order: the gateway, the OPC UA server, then the PLC.
+//
+// One of a redundant pair ([supervisor.redundancy]), gipopd runs them only while its controller is the active one and
+// takes over when the active fails, see redundancy.rs.
+mod child;
+mod config;
+mod redundancy;
+
+use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use gipop_shm::{IpcConfig, Liveness, Subscriber, Transport};
+
+use child::Supervised;
+use config::SupervisorConfig;
+use redundancy::{Change, Link};
+
+const CONFIG_PATH: &str = "gipop.toml";
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+enum PlcState {
+    Down, // not running, or waiting out its backoff
+    Starting { since: Instant, started: SystemTime, liveness: Liveness, table: Option<Subscriber> },
+    Publishing { table: Subscriber },
+}
+
+fn main() {
+    let config = std::env::args().nth(1).map_or_else(|| gipop_shm::project::config_path(CONFIG_PATH, env!("CARGO_PKG_VERSION")), Ok)
+        .unwrap_or_else(|e| {
+            eprintln!("gipopd: {}", e);
+            std::process::exit(1);
+        });
+    gipop_shm::logging::init(&config, "gipopd");
+    gipop_shm::logging::reload_on_sighup();
+
+    let ipc = match IpcConfig::load(&config) {
+        Ok(ipc) => ipc,
+        Err(e) => {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let cfg = match SupervisorConfig::load(&config) {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let target = if ipc.transport == Transport::Shm { &ipc.shm_path } else { &ipc.socket_path };
+
+    let shutdown = Arc::new(AtomicBool::new(false));
+    for signal in [signal_hook::consts::SIGINT, signal_hook::consts::SIGTERM] {
+        signal_hook::flag::register(signal, Arc::clone(&shutdown)).expect("Register hook");
+    }
+
+    let mut plc = Supervised::new("PLC", cfg.plc.clone());
+    // started in this order once the PLC publishes, stopped in reverse before it
+    let mut dependents = [Supervised::new("OPC UA server", cfg.opcua.clone()), Supervised::new("gateway", cfg.gateway.clone())];
+    let mut state = PlcState::Down;
+    let mut link = cfg.redundancy.enabled.then(|| Link::open(&cfg.redundancy).unwrap_or_else(|e| {
+        log::error!("{}", e);
+        std::process::exit(1);
+    }));
+    let mut restore = false; // took over, the state synced from the peer goes to the PLC once it publishes
+
+    while !shutdown.load(Ordering::Relaxed) {
+        if plc.exited(&cfg).is_some() {
+            stop_all(&mut dependents, &cfg);
+            state = PlcState::Down;
+        }
+
+        if let Some(link) = &mut link {
+            let table = match &state {
+                PlcState::Publishing { table } => Some(table),
+                _ => None,
+            };
+            match link.poll(table) {
+                Some(Change::TakeOver) => restore = true,
+                Some(Change::StandDown) => {
+                    stop_all(&mut dependents, &cfg);
+                    plc.stop(&cfg);
+                    state = PlcState::Down;
+                    restore = false;
+                }
+                None => {}
+            }
+        }
+        let active = link.as_ref().is_none_or(Link::is_active);
+
+        match &mut state {
+            PlcState::Down => {
+                if active && plc.due() {
+                    let started = SystemTime::now();
+                    plc.start(&cfg);
+                    if plc.running() {
+                        state = PlcState::Starting { since: Instant::now(), started, liveness: Liveness::new(ipc.heartbeat_timeout()), table: None };
+                    }
+                }
+            }
+            PlcState::Starting { since, started, liveness, table } => {
+                if table.is_none() && region_recreated(&ipc, *started) {
+                    *table = Subscriber::connect(&ipc).ok();
+                }
+                if let Some(table) = table {
+                    liveness.update(table.plc_heartbeat());
+                }
+                // a takeover has a timeout of its own, the bus has to come up from INIT again within it
+                let ready_timeout_ms = if restore { cfg.redundancy.takeover_timeout_ms } else { cfg.ready_timeout_ms };
+                if liveness.is_alive() && let Some(table) = table.take() {
+                    log::info!("PLC publishing on {} after {:?}, starting the rest", target, since.elapsed());
+                    if restore && let Some(link) = &link {
+                        link.restore(&table);
+                        restore = false;
+                    }
+                    state = PlcState::Publishing { table };
+                } else if since.elapsed() >= Duration::from_millis(ready_timeout_ms) {
+                    log::error!("PLC not publishing on {} {} ms after starting, restarting it", target, ready_timeout_ms);
+                    plc.restart(&cfg);
+                    state = PlcState::Down;
+                }
+            }
+            PlcState::Publishing { .. } => {
+                for child in &mut dependents {
+                    child.exited(&cfg);
+                    if child.due() {
+                        child.start(&cfg);
+                    }
+                }
+            }
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    log::info!("Shutting down");
+    stop_all(&mut dependents, &cfg);
+    plc.stop(&cfg);
+}
+
+// Last started first
+fn stop_all(children: &mut [Supervised], cfg: &SupervisorConfig) {
+    for child in children.iter_mut().rev() {
+        child.stop(cfg);
+    }
+}
+
+// The PLC truncates and recreates a region left behind by its last run when it starts, mapping that one before could
+// have us reading past its end. Sockets are bound anew, nothing to wait for there
+fn region_recreated(ipc: &IpcConfig, started: SystemTime) -> bool {
+    ipc.transport != Transport::Shm || fs::metadata(&ipc.shm_path).and_then(|meta| meta.modified()).is_ok_and(|modified| modified >= started)
+}
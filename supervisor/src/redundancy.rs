@@ -0,0 +1,609 @@
+// Active/standby controller pair ([supervisor.redundancy], see config.rs). Both controllers have gipopd, the PLC and
+// what runs against it installed and are cabled to the same EtherCAT network (the standby's NIC at the far end of the
+// line or on a ring port), and to each other by a link of their own. The active one runs everything like a gipopd
+// without redundancy. The standby runs gipopd only and never opens its NIC, a bus has one MainDevice at a time.
+//
+// Over the link each gipopd sends a heartbeat every heartbeat_ms, the active also its state every sync_ms: the run
+// state, the retained tags and totals (TagDef::retained, what `gipop backup` saves) and the external tags' values,
+// what a PLC starting on the standby wouldn't have otherwise. The standby takes over once it hasn't heard an active
+// peer for peer_timeout_ms: it starts the PLC, which brings the bus up again from INIT, puts the state back through
+// the command ring once that publishes (audited as gipopd, which needs the engineer role in [users] or the PLC drops
+// it), then starts the OPC UA server and the gateway like after any PLC start. A PLC that doesn't publish within
+// takeover_timeout_ms is restarted, so a takeover is done within peer_timeout_ms + takeover_timeout_ms unless the
+// standby's PLC can't come up either.
+//
+// Who's active:
+// - both start as standby. Hearing no active peer for peer_timeout_ms, the one with precedence takes over: the
+//   preferred one, then the lower id. Either one does when the peer is silent too
+// - an active whose PLC hasn't published for takeover_timeout_ms hands over to a standby it hears: it stops its
+//   children and gives up its precedence until the peer has been active, so it doesn't take back a bus it can't run
+// - two actives (the link was down, each took the other for dead) is settled once they hear each other again: the
+//   one that became active last stands down
+//
+// Datagrams are text, tab separated like the rest of gipop's output, every one starts with the header:
+//
+// gipop-sync	1	active	1760607164512	2	9c0e1f3a2b4d5e6f   role, active since (unix ms, 0 as standby), precedence, id
+// tags	9c0e1f3a                                    the tag list, gipop_shm::project::fingerprint, in every state datagram
+// running	true
+// retained	area 1 lights hmi cmd	1	3              kind, tag, type (TagType as u8), raw value in hex
+// total	enocean telegrams	1	4737
+// external	chiller supply temp	3	4046000000000000
+//
+// State lines count only after a tags line, and a tag list other than the last drops what was synced from that one.
+// Only datagrams from the peer's address are taken. The link has no authentication, keep it a cable between the two.
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use gipop_shm::project::fingerprint;
+use gipop_shm::tags::now_ms;
+use gipop_shm::{RingItem, Subscriber, TagValue};
+
+use crate::config::RedundancyConfig;
+
+const MAGIC: &str = "gipop-sync";
+const FORMAT: u32 = 1;
+const DATAGRAM_LEN: usize = 1200; // the state goes in datagrams up to this long, below any link's MTU
+const RUNNING_TAG: &str = "running"; // see plc/src/tags.rs
+const USER: &str = "gipopd"; // who the PLC's audit log says restored the state
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Active,
+    Standby,
+}
+
+/// What gipopd has to do after a poll()
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    TakeOver,  // start the PLC, restore() once it publishes
+    StandDown, // stop everything, the peer runs the bus
+}
+
+// The peer as its last header said
+struct Peer {
+    heard: Instant,
+    role: Role,
+    since_ms: u64,
+    precedence: u8,
+    id: u64,
+}
+
+// One tag of the active's state
+struct Synced {
+    kind: String, // "retained", "total" or "external"
+    ty: u8,
+    raw: u64,
+}
+
+pub struct Link {
+    cfg: RedundancyConfig,
+    socket: UdpSocket,
+    peer_addr: SocketAddr,
+    id: u64,
+    role: Role,
+    since_ms: u64,
+    handed_over: bool, // since the peer was last active, no precedence until it has been
+    started: Instant,
+    peer: Option<Peer>,
+    active_heard: Option<Instant>, // the peer, as active
+    heartbeat_sent: Option<Instant>,
+    state_sent: Option<Instant>,
+    not_publishing: Option<Instant>, // active with the PLC down since
+    // the active's state, as a standby
+    tags: String,
+    running: Option<bool>,
+    state: HashMap<String, Synced>,
+    synced: Option<Instant>,
+}
+
+impl Link {
+    /// Binds the link's end, as standby
+    pub fn open(cfg: &RedundancyConfig) -> Result<Self, String> {
+        let socket = UdpSocket::bind(&cfg.listen).map_err(|e| format!("Failed to bind the sync link on {}: {}", cfg.listen, e))?;
+        socket.set_nonblocking(true).map_err(|e| format!("Sync link on {}: {}", cfg.listen, e))?;
+        let peer_addr = cfg.peer.to_socket_addrs().ok()
+            .and_then(|mut addrs| addrs.next())
+            .ok_or_else(|| format!("Peer {} in [supervisor.redundancy] doesn't resolve", cfg.peer))?;
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().subsec_nanos();
+        log::info!("Sync link on {} to {}, standby until the peer is heard", cfg.listen, peer_addr);
+        Ok(Self {
+            cfg: cfg.clone(),
+            socket,
+            peer_addr,
+            id: ((std::process::id() as u64) << 32) | nanos as u64,
+            role: Role::Standby,
+            since_ms: 0,
+            handed_over: false,
+            started: Instant::now(),
+            peer: None,
+            active_heard: None,
+            heartbeat_sent: None,
+            state_sent: None,
+            not_publishing: None,
+            tags: String::new(),
+            running: None,
+            state: HashMap::new(),
+            synced: None,
+        })
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.role == Role::Active
+    }
+
+    /// Takes in what the peer sent, sends what's due and says whether to take over or stand down. `table` is the
+    /// local PLC's while it publishes
+    pub fn poll(&mut self, table: Option<&Subscriber>) -> Option<Change> {
+        self.receive();
+        let now = Instant::now();
+        if self.heartbeat_sent.is_none_or(|at| now - at >= Duration::from_millis(self.cfg.heartbeat_ms)) {
+            self.send(&self.header());
+            self.heartbeat_sent = Some(now);
+        }
+        if self.role == Role::Active && let Some(table) = table
+            && self.state_sent.is_none_or(|at| now - at >= Duration::from_millis(self.cfg.sync_ms)) {
+            self.send_state(table);
+            self.state_sent = Some(now);
+        }
+        self.decide(table.is_some())
+    }
+
+    /// Puts the state last synced from the active into the local PLC, publishing after a takeover
+    pub fn restore(&self, table: &Subscriber) {
+        if let Some(heard) = self.active_heard {
+            log::warn!("Took over {} ms after last hearing the active", heard.elapsed().as_millis());
+        }
+        let Some(synced) = self.synced else {
+            if self.active_heard.is_some() {
+                log::warn!("Nothing synced from the peer, the PLC runs on its own state");
+            }
+            return;
+        };
+        if self.tags != fingerprint(table.tags()) {
+            log::warn!("State synced from tag list {}, the PLC runs {}, restoring what still matches", self.tags, fingerprint(table.tags()));
+        }
+        let mut items = Vec::new();
+        for (name, synced) in &self.state {
+            let Some(idx) = table.index_of(name) else {
+                log::warn!(tag = name.as_str(); "Not restoring '{}', the PLC has no tag of that name", name);
+                continue;
+            };
+            let tag = &table.tags()[idx];
+            let kind = if tag.is_external() { Some("external") } else { tag.retained() };
+            let value = TagValue::from_raw(tag.ty, synced.raw);
+            if kind != Some(synced.kind.as_str()) || tag.ty as u8 != synced.ty || (synced.kind == "retained" && !tag.accepts(value)) {
+                log::warn!(tag = name.as_str(); "Not restoring '{}', it's no longer a {} tag of that type or the value is out of range", name, synced.kind);
+                continue;
+            }
+            items.push(if synced.kind == "total" { RingItem::restore_total(idx, value) } else { RingItem::tag_write(idx, value) });
+        }
+        // the logic started (or stopped) on the state last
+        items.extend(self.running.map(RingItem::run_mode));
+
+        let count = items.len();
+        for item in items {
+            match table.push_command(item) {
+                Ok(seq) => {
+                    _ = table.push_command(RingItem::audit(seq, 0, USER));
+                }
+                Err(_) => {
+                    log::error!("PLC isn't consuming commands, the synced state isn't restored fully");
+                    return;
+                }
+            }
+        }
+        log::info!("Restored {} synced item(s), as of {} ms ago", count, synced.elapsed().as_millis());
+    }
+
+    fn decide(&mut self, publishing: bool) -> Option<Change> {
+        let now = Instant::now();
+        let timeout = Duration::from_millis(self.cfg.peer_timeout_ms);
+        let peer = self.peer.as_ref().filter(|peer| now - peer.heard < timeout);
+        match self.role {
+            Role::Standby => {
+                if now - self.active_heard.unwrap_or(self.started) < timeout {
+                    return None;
+                }
+                if let Some(peer) = peer && (peer.precedence, Reverse(peer.id)) > (self.precedence(), Reverse(self.id)) {
+                    return None; // a standby too, it takes over
+                }
+                match (self.active_heard, peer) {
+                    (None, _) => log::info!("No active peer, becoming active"),
+                    (Some(_), Some(_)) => log::warn!("Peer no longer active, taking over"),
+                    (Some(_), None) => log::warn!("Peer not heard for {} ms, taking over", self.cfg.peer_timeout_ms),
+                }
+                self.role = Role::Active;
+                self.since_ms = now_ms();
+                self.not_publishing = None;
+                Some(Change::TakeOver)
+            }
+            Role::Active => {
+                if let Some(peer) = peer && peer.role == Role::Active
+                    && (self.since_ms, Reverse(self.precedence()), self.id) > (peer.since_ms, Reverse(peer.precedence), peer.id) {
+                    log::error!("Both controllers active, standing down: the peer since {}, this one since {}", peer.since_ms, self.since_ms);
+                    return Some(self.stand_down());
+                }
+                if publishing {
+                    self.not_publishing = None;
+                    return None;
+                }
+                let since = *self.not_publishing.get_or_insert(now);
+                if now - since >= Duration::from_millis(self.cfg.takeover_timeout_ms) && peer.is_some_and(|peer| peer.role == Role::Standby) {
+                    log::error!("PLC not publishing for {} ms, handing over to the standby", self.cfg.takeover_timeout_ms);
+                    self.handed_over = true;
+                    return Some(self.stand_down());
+                }
+                None
+            }
+        }
+    }
+
+    fn stand_down(&mut self) -> Change {
+        self.role = Role::Standby;
+        self.since_ms = 0;
+        self.not_publishing = None;
+        self.send(&self.header()); // so the peer doesn't wait out a heartbeat to learn
+        Change::StandDown
+    }
+
+    // 2 preferred, 1 not, 0 handed over
+    fn precedence(&self) -> u8 {
+        if self.handed_over { 0 } else if self.cfg.preferred { 2 } else { 1 }
+    }
+
+    fn header(&self) -> String {
+        let role = if self.role == Role::Active { "active" } else { "standby" };
+        format!("{}\t{}\t{}\t{}\t{}\t{:016x}\n", MAGIC, FORMAT, role, self.since_ms, self.precedence(), self.id)
+    }
+
+    fn send(&self, datagram: &str) {
+        // a peer that isn't there is what the pair is for, nothing to report
+        if let Err(e) = self.socket.send_to(datagram.as_bytes(), self.peer_addr) {
+            log::debug!("Sync link to {}: {}", self.peer_addr, e);
+        }
+    }
+
+    // What a PLC on the peer would start without, in as many datagrams as it takes
+    fn send_state(&self, table: &Subscriber) {
+        let mut lines = Vec::new();
+        if let Some(idx) = table.index_of(RUNNING_TAG) {
+            lines.push(format!("running\t{}", table.read_sample(idx).value.as_f64() != 0.0));
+        }
+        for (idx, tag) in table.tags().iter().enumerate() {
+            let Some(kind) = (if tag.is_external() { Some("external") } else { tag.retained() }) else { continue };
+            lines.push(format!("{}\t{}\t{}\t{:x}", kind, tag.name, tag.ty as u8, table.read_sample(idx).value.to_raw()));
+        }
+
+        let header = format!("{}tags\t{}\n", self.header(), fingerprint(table.tags()));
+        let mut datagram = header.clone();
+        for line in lines {
+            if datagram.len() + line.len() + 1 > DATAGRAM_LEN && datagram.len() > header.len() {
+                self.send(&datagram);
+                datagram = header.clone();
+            }
+            datagram += &line;
+            datagram.push('\n');
+        }
+        self.send(&datagram);
+    }
+
+    fn receive(&mut self) {
+        let mut buf = vec![0; u16::MAX as usize];
+        loop {
+            match self.socket.recv_from(&mut buf) {
+                Ok((len, from)) if from.ip() == self.peer_addr.ip() => self.take(&buf[..len]),
+                Ok((_, from)) => log::debug!("Ignoring a datagram from {}, not the peer", from),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    log::debug!("Sync link on {}: {}", self.cfg.listen, e);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn take(&mut self, datagram: &[u8]) {
+        let text = String::from_utf8_lossy(datagram);
+        let mut lines = text.lines().map(|line| line.split('\t').collect::<Vec<_>>());
+        let peer = match lines.next().as_deref() {
+            Some([MAGIC, format, role, since_ms, precedence, id]) if format.parse() == Ok(FORMAT) => {
+                let role = match *role {
+                    "active" => Role::Active,
+                    "standby" => Role::Standby,
+                    _ => return,
+                };
+                let (Ok(since_ms), Ok(precedence), Ok(id)) = (since_ms.parse(), precedence.parse(), u64::from_str_radix(id, 16)) else { return };
+                Peer { heard: Instant::now(), role, since_ms, precedence, id }
+            }
+            _ => {
+                log::debug!("Ignoring a datagram from the peer, not gipop-sync version {}", FORMAT);
+                return;
+            }
+        };
+        if peer.role == Role::Active {
+            if self.active_heard.is_none() || self.peer.as_ref().is_some_and(|last| last.role != Role::Active) {
+                log::info!("Peer is active (since {})", peer.since_ms);
+            }
+            self.active_heard = Some(peer.heard);
+            self.handed_over = false;
+        }
+        let active = peer.role == Role::Active;
+        self.peer = Some(peer);
+        if !active || self.role == Role::Active {
+            return;
+        }
+
+        let mut tagged = false; // the state lines are of a tag list
+        for fields in lines {
+            match fields.as_slice() {
+                ["tags", tags] => {
+                    if *tags != self.tags {
+                        if !self.state.is_empty() {
+                            log::info!("Peer's tag list is now {}, dropping the state synced from {}", tags, self.tags);
+                        }
+                        self.tags = tags.to_string();
+                        self.state.clear();
+                    }
+                    tagged = true;
+                }
+                ["running", running @ ("true" | "false")] => self.running = Some(*running == "true"),
+                [kind @ ("retained" | "total" | "external"), name, ty, raw] if tagged => {
+                    let (Ok(ty), Ok(raw)) = (ty.parse(), u64::from_str_radix(raw, 16)) else { continue };
+                    self.state.insert(name.to_string(), Synced { kind: kind.to_string(), ty, raw });
+                }
+                _ => continue, // malformed, or lines of later versions
+            }
+            self.synced = Some(Instant::now());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: u64 = 0x50;
+
+    // A standby started `started_ms` ago, its peer a port nobody listens on
+    fn standby(preferred: bool, started_ms: u64) -> Link {
+        let cfg = RedundancyConfig {
+            enabled: true,
+            listen: "127.0.0.1:0".to_string(),
+            peer: "127.0.0.1:9".to_string(),
+            preferred,
+            peer_timeout_ms: 1000,
+            takeover_timeout_ms: 2000,
+            ..Default::default()
+        };
+        let mut link = Link::open(&cfg).unwrap();
+        link.id = ID;
+        link.started = ago(started_ms);
+        link
+    }
+
+    fn ago(ms: u64) -> Instant {
+        Instant::now().checked_sub(Duration::from_millis(ms)).unwrap()
+    }
+
+    fn header(role: &str, since_ms: u64, precedence: u8, id: u64) -> String {
+        format!("{}\t{}\t{}\t{}\t{}\t{:016x}\n", MAGIC, FORMAT, role, since_ms, precedence, id)
+    }
+
+    // The peer's header, heard `heard_ms` ago
+    fn hear(link: &mut Link, header: &str, heard_ms: u64) {
+        link.take(header.as_bytes());
+        link.peer.as_mut().expect("header taken").heard = ago(heard_ms);
+        if link.peer.as_ref().is_some_and(|peer| peer.role == Role::Active) {
+            link.active_heard = Some(ago(heard_ms));
+        }
+    }
+
+    #[test]
+    fn standby_takes_over_without_an_active_peer() {
+        // nothing heard yet, waiting a peer timeout for one
+        let mut link = standby(false, 0);
+        assert_eq!(link.decide(false), None);
+        // the peer is silent too
+        link.started = ago(1000);
+        assert_eq!(link.decide(false), Some(Change::TakeOver));
+        assert!(link.is_active() && link.since_ms > 0);
+        assert!(link.header().starts_with("gipop-sync\t1\tactive\t"));
+
+        // an active peer keeps it standby, until it's not heard for a peer timeout
+        let mut link = standby(false, 60_000);
+        hear(&mut link, &header("active", 1, 1, 7), 0);
+        assert_eq!(link.decide(false), None);
+        hear(&mut link, &header("active", 1, 1, 7), 999);
+        assert_eq!(link.decide(false), None);
+        hear(&mut link, &header("active", 1, 1, 7), 1000);
+        assert_eq!(link.decide(false), Some(Change::TakeOver));
+
+        // or says it's standby now
+        let mut link = standby(false, 60_000);
+        hear(&mut link, &header("active", 1, 1, 7), 1500);
+        hear(&mut link, &header("standby", 0, 0, 7), 0);
+        assert_eq!(link.decide(false), Some(Change::TakeOver));
+    }
+
+    #[test]
+    fn precedence_picks_the_standby_to_take_over() {
+        type Case = (bool, u8, u64, Option<Change>);
+        // preferred, the peer's precedence and id, what decide() says
+        let cases: [Case; 7] = [
+            (false, 2, ID + 1, None),                   // the preferred peer does
+            (true, 1, ID - 1, Some(Change::TakeOver)),  // this one is preferred
+            (true, 2, ID - 1, None),                    // both, the lower id does
+            (true, 2, ID + 1, Some(Change::TakeOver)),
+            (false, 1, ID - 1, None),
+            (false, 1, ID + 1, Some(Change::TakeOver)),
+            (false, 0, ID - 1, Some(Change::TakeOver)), // the peer handed over
+        ];
+        for (preferred, precedence, id, change) in cases {
+            let mut link = standby(preferred, 60_000);
+            hear(&mut link, &header("standby", 0, precedence, id), 0);
+            assert_eq!(link.decide(false), change, "preferred {}, peer {} {:x}", preferred, precedence, id);
+        }
+
+        // a peer with precedence that's gone silent doesn't hold the takeover up
+        let mut link = standby(false, 60_000);
+        hear(&mut link, &header("standby", 0, 2, 1), 1000);
+        assert_eq!(link.decide(false), Some(Change::TakeOver));
+    }
+
+    #[test]
+    fn active_hands_over_when_its_plc_does_not_publish() {
+        let mut link = standby(true, 60_000);
+        assert_eq!(link.decide(true), Some(Change::TakeOver));
+        hear(&mut link, &header("standby", 0, 1, 7), 0);
+        assert_eq!(link.decide(true), None);
+
+        // not publishing, the standby gets the bus after takeover_timeout_ms
+        assert_eq!(link.decide(false), None);
+        link.not_publishing = Some(ago(1999));
+        assert_eq!(link.decide(false), None);
+        link.not_publishing = Some(ago(2000));
+        hear(&mut link, &header("standby", 0, 1, 7), 0);
+        assert_eq!(link.decide(false), Some(Change::StandDown));
+        assert!(!link.is_active());
+        assert_eq!((link.since_ms, link.precedence()), (0, 0));
+        assert!(link.header().starts_with("gipop-sync\t1\tstandby\t0\t0\t"));
+
+        // no precedence while the peer hasn't been active, even preferred and with the lower id
+        link.active_heard = None;
+        link.started = ago(60_000);
+        hear(&mut link, &header("standby", 0, 1, 7), 0);
+        assert_eq!(link.decide(false), None);
+        hear(&mut link, &header("active", now_ms(), 1, 7), 0);
+        assert_eq!(link.precedence(), 2);
+
+        // a PLC publishing again in time keeps the bus, no standby keeps it too
+        let mut link = standby(false, 60_000);
+        assert_eq!(link.decide(false), Some(Change::TakeOver));
+        hear(&mut link, &header("standby", 0, 1, 7), 0);
+        link.not_publishing = Some(ago(1500));
+        assert_eq!(link.decide(true), None);
+        assert_eq!(link.not_publishing, None);
+        link.not_publishing = Some(ago(5000));
+        link.peer = None;
+        assert_eq!(link.decide(false), None);
+        assert!(link.is_active());
+    }
+
+    #[test]
+    fn of_two_actives_the_later_one_stands_down() {
+        type Case = (u64, bool, u64, u8, u64, Option<Change>);
+        // active since, preferred, the peer's since, precedence and id, what decide() says
+        let cases: [Case; 7] = [
+            (2000, false, 1000, 1, ID + 1, Some(Change::StandDown)),
+            (1000, false, 2000, 1, ID - 1, None),
+            // the same ms: the one with precedence stays, then the lower id
+            (1000, true, 1000, 1, ID - 1, None),
+            (1000, false, 1000, 2, ID + 1, Some(Change::StandDown)),
+            (1000, false, 1000, 1, ID + 1, None),
+            (1000, false, 1000, 1, ID - 1, Some(Change::StandDown)),
+            (2000, true, 1000, 1, ID + 1, Some(Change::StandDown)), // precedence doesn't beat being first
+        ];
+        for (since_ms, preferred, peer_since_ms, precedence, id, change) in cases {
+            let mut link = standby(preferred, 60_000);
+            assert_eq!(link.decide(true), Some(Change::TakeOver));
+            link.since_ms = since_ms;
+            hear(&mut link, &header("active", peer_since_ms, precedence, id), 0);
+            assert_eq!(link.decide(true), change, "since {} preferred {}, peer {} {} {:x}", since_ms, preferred, peer_since_ms, precedence, id);
+            // standing down for this keeps the precedence
+            assert_eq!((link.is_active(), link.precedence()), (change.is_none(), if preferred { 2 } else { 1 }));
+        }
+
+        // one not heard for a peer timeout doesn't count
+        let mut link = standby(false, 60_000);
+        assert_eq!(link.decide(true), Some(Change::TakeOver));
+        link.since_ms = 2000;
+        hear(&mut link, &header("active", 1000, 2, 1), 1000);
+        assert_eq!(link.decide(true), None);
+    }
+
+    #[test]
+    fn malformed_headers_are_ignored() {
+        let cases = [
+            "".to_string(),
+            "hello\n".to_string(),
+            header("active", 1, 1, 7).replace(MAGIC, "gipop-sink"),
+            header("active", 1, 1, 7).replace("\t1\tactive", "\t2\tactive"),
+            header("Active", 1, 1, 7),
+            header("active", 1, 1, 7).replace("\t1\t1\t", "\t-1\t1\t"),
+            header("active", 1, 1, 7).replace("\t1\t1\t", "\t1\t256\t"),
+            header("active", 1, 1, 7).replace("0000000000000007", "xyz"),
+            header("active", 1, 1, 7).replace("0000000000000007", "10000000000000007"),
+            header("active", 1, 1, 7).replace("\t0000000000000007", ""),
+            header("active", 1, 1, 7).replace('\n', "\textra\n"),
+            header("active", 1, 1, 7).replace('\t', " "),
+        ];
+        for datagram in cases {
+            let mut link = standby(false, 0);
+            link.take(datagram.as_bytes());
+            assert!(link.peer.is_none() && link.active_heard.is_none(), "{:?}", datagram);
+        }
+        let mut link = standby(false, 0);
+        link.take(&[0xFF, 0xFE, b'\n']);
+        link.take(header("standby", 0, 1, 7).as_bytes());
+        assert!(link.peer.as_ref().is_some_and(|peer| (peer.role, peer.since_ms, peer.precedence, peer.id) == (Role::Standby, 0, 1, 7)));
+    }
+
+    #[test]
+    fn state_is_taken_from_an_active_peer_and_one_tag_list() {
+        let synced = |link: &Link| {
+            let mut state: Vec<_> = link.state.iter().map(|(name, s)| (name.clone(), s.kind.clone(), s.ty, s.raw)).collect();
+            state.sort();
+            state
+        };
+        let entry = |name: &str, kind: &str, ty: u8, raw: u64| (name.to_string(), kind.to_string(), ty, raw);
+        let active = header("active", 1000, 1, 7);
+
+        let mut link = standby(false, 0);
+        let datagram = active.clone()
+            + "retained\tbefore the tag list\t1\t1\n"
+            + "tags\t9c0e1f3a\n"
+            + "running\ttrue\n"
+            + "retained\tarea 1 lights hmi cmd\t1\t3\n"
+            + "total\tenocean telegrams\t1\t4737\n"
+            + "external\tchiller supply temp\t3\t4046000000000000\n"
+            + "retained\tbad type\t256\t1\n"
+            + "retained\tbad raw\t1\tzz\n"
+            + "retained\ttoo short\t1\n"
+            + "retained\ttoo\tlong\t1\t1\n"
+            + "setpoint\tnot a kind\t1\t1\n"
+            + "running\tmaybe\n";
+        link.take(datagram.as_bytes());
+        assert_eq!(link.tags, "9c0e1f3a");
+        assert_eq!(link.running, Some(true));
+        assert_eq!(synced(&link), [
+            entry("area 1 lights hmi cmd", "retained", 1, 3),
+            entry("chiller supply temp", "external", 3, 0x4046000000000000),
+            entry("enocean telegrams", "total", 1, 0x4737),
+        ]);
+        assert!(link.synced.is_some());
+
+        // the next datagram of the same state adds to it
+        link.take((active.clone() + "tags\t9c0e1f3a\nretained\tsetpoint\t3\t0\n").as_bytes());
+        assert_eq!(synced(&link).len(), 4);
+        // lines without a tag list don't
+        link.take((active.clone() + "retained\tstray\t1\t1\n").as_bytes());
+        assert_eq!(synced(&link).len(), 4);
+
+        // another tag list replaces the state
+        link.take((active.clone() + "tags\t11111111\nrunning\tfalse\nretained\tsetpoint\t3\t1\n").as_bytes());
+        assert_eq!(link.tags, "11111111");
+        assert_eq!(link.running, Some(false));
+        assert_eq!(synced(&link), [entry("setpoint", "retained", 3, 1)]);
+
+        // a standby's state isn't taken, nor any while active
+        let standby_state = "tags\t22222222\nretained\tstandby\t1\t1\n";
+        link.take((header("standby", 0, 1, 7) + standby_state).as_bytes());
+        assert_eq!((link.tags.as_str(), synced(&link).len()), ("11111111", 1));
+        link.role = Role::Active;
+        link.take((active + standby_state).as_bytes());
+        assert_eq!((link.tags.as_str(), synced(&link).len()), ("11111111", 1));
+    }
+}